@@ -0,0 +1,119 @@
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use webml::ast::{Desugar, Rename, SymbolTable, Typer, UntypedCore, VarToConstructor};
+use webml::id::Id;
+use webml::{parse, Config, Pass, TypeError};
+
+const PRELUDE: &str = include_str!("../ml_src/prelude.sml");
+
+/// `n` functions and values, each using the previous one
+fn many_bindings(n: usize) -> String {
+    let mut source = PRELUDE.to_string();
+    source.push_str("fun f0 x = x + 1\nval v0 = f0 0\n");
+    for i in 1..n {
+        source.push_str(&format!(
+            "fun f{i} x = if x > {i} then f{prev} (x - 1) else x * 2\nval v{i} = f{i} v{prev}\n",
+            i = i,
+            prev = i - 1
+        ));
+    }
+    source
+}
+
+/// a value whose expression nests `depth` lets
+fn nested_lets(depth: usize) -> String {
+    let mut source = PRELUDE.to_string();
+    source.push_str("val x =\n");
+    for i in 0..depth {
+        source.push_str(&format!(
+            "let val a{i} = {i} fun g{i} y = y + a{i} in\n",
+            i = i
+        ));
+    }
+    source.push_str("a0");
+    for i in 1..depth {
+        source.push_str(&format!(" + g{i} a{i}", i = i));
+    }
+    for _ in 0..depth {
+        source.push_str("\nend");
+    }
+    source.push('\n');
+    source
+}
+
+/// the programs to benchmark, large enough that each takes a while. so the groups take
+/// fewer samples than the default
+fn programs() -> Vec<(String, String)> {
+    let mut programs = vec![];
+    for &n in &[1000, 5000] {
+        programs.push((format!("bindings/{}", n), many_bindings(n)));
+    }
+    for &depth in &[100, 300] {
+        programs.push((format!("nested_lets/{}", depth), nested_lets(depth)));
+    }
+    programs
+}
+
+fn desugar(source: &str, id: &Id) -> UntypedCore {
+    let ast = parse(source).expect("parse error");
+    let core: Result<_, TypeError> = Desugar::new(id.clone()).trans(ast, &Config::default());
+    core.unwrap()
+}
+
+fn rename(core: UntypedCore, id: &Id) -> (SymbolTable, UntypedCore) {
+    let config = Config::default();
+    let renamed: Result<_, ()> = Rename::new(id.clone()).trans(core, &config);
+    let renamed: Result<_, ()> = VarToConstructor::new(id.clone()).trans(renamed.unwrap(), &config);
+    renamed.unwrap()
+}
+
+fn bench_parse(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse");
+    group.sample_size(10);
+    for (name, source) in programs() {
+        group.throughput(Throughput::Bytes(source.len() as u64));
+        group.bench_function(name, |b| b.iter(|| parse(black_box(&source)).unwrap()));
+    }
+    group.finish();
+}
+
+fn bench_rename(c: &mut Criterion) {
+    let mut group = c.benchmark_group("rename");
+    group.sample_size(10);
+    for (name, source) in programs() {
+        let id = Id::new();
+        let core = desugar(&source, &id);
+        group.bench_function(name, |b| {
+            b.iter_batched(
+                || core.clone(),
+                |core| rename(core, &id),
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+fn bench_typing(c: &mut Criterion) {
+    let mut group = c.benchmark_group("typing");
+    group.sample_size(10);
+    let config = Config::default();
+    for (name, source) in programs() {
+        let id = Id::new();
+        let renamed = rename(desugar(&source, &id), &id);
+        group.bench_function(name, |b| {
+            b.iter_batched(
+                || renamed.clone(),
+                |renamed| {
+                    let typed: Result<_, TypeError> =
+                        Typer::new(id.clone()).trans(renamed, &config);
+                    typed.unwrap()
+                },
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_parse, bench_rename, bench_typing);
+criterion_main!(benches);
@@ -0,0 +1,86 @@
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use webml::hir::{Expr, FlatLet, HTy, SymbolTable, Val, HIR};
+use webml::prim::*;
+use webml::{Config, Pass};
+
+fn sym(id: u64) -> Expr {
+    Expr::Sym {
+        ty: HTy::Int,
+        name: Symbol("x".into(), id),
+    }
+}
+
+fn binds(id: u64, expr: Expr, ret: Expr) -> Expr {
+    Expr::Binds {
+        ty: HTy::Int,
+        binds: vec![Val {
+            ty: HTy::Int,
+            rec: false,
+            name: Symbol("x".into(), id),
+            expr,
+        }],
+        ret: Box::new(ret),
+    }
+}
+
+/// `let val x1 = 0 in let val x2 = x1 in ... x{depth} end end`
+fn let_chain(depth: u64) -> Expr {
+    let mut expr = sym(depth);
+    for id in (1..=depth).rev() {
+        let init = if id == 1 {
+            Expr::Lit {
+                ty: HTy::Int,
+                value: Literal::Int(0),
+            }
+        } else {
+            sym(id - 1)
+        };
+        expr = binds(id, init, expr);
+    }
+    expr
+}
+
+/// `let val x{depth} = let val x{depth - 1} = ... in x{depth - 1} end in x{depth} end`
+fn nested_vals(depth: u64) -> Expr {
+    let mut expr = Expr::Lit {
+        ty: HTy::Int,
+        value: Literal::Int(0),
+    };
+    for id in 1..=depth {
+        expr = binds(id, expr, sym(id));
+    }
+    expr
+}
+
+fn flat_let(hir: HIR) -> HIR {
+    let symbol_table = SymbolTable {
+        types: Default::default(),
+        exports: Vec::new(),
+    };
+    let result: Result<_, ()> = FlatLet::new().trans((symbol_table, hir), &Config::default());
+    result.unwrap().1
+}
+
+fn bench_flat_let(c: &mut Criterion) {
+    let mut group = c.benchmark_group("flat_let");
+    group.sample_size(10);
+    let programs = vec![
+        ("let_chain/10000", let_chain(10_000)),
+        ("nested_vals/10000", nested_vals(10_000)),
+    ];
+    for (name, expr) in programs {
+        let val = Val {
+            ty: HTy::Int,
+            rec: false,
+            name: Symbol("main".into(), 0),
+            expr,
+        };
+        group.bench_function(name, |b| {
+            b.iter_batched(|| HIR(vec![val.clone()]), flat_let, BatchSize::LargeInput)
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_flat_let);
+criterion_main!(benches);
@@ -0,0 +1,28 @@
+//! parses the input, and checks that the formatted input parses to the same program.
+//! run with `cargo fuzz run parse`
+
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use webml::{fmt, parse, FormatConfig};
+
+fuzz_target!(|data: &[u8]| {
+    let source = match std::str::from_utf8(data) {
+        Ok(source) => source,
+        Err(_) => return,
+    };
+    let ast = match parse(source) {
+        Ok(ast) => ast,
+        Err(_) => return,
+    };
+    let formatted = match fmt(source, &FormatConfig::default()) {
+        Ok(formatted) => formatted,
+        Err(e) => panic!("failed to format the parsed program: {}", e),
+    };
+    match parse(&formatted) {
+        Ok(reparsed) => assert_eq!(ast, reparsed, "formatted as:\n{}", formatted),
+        Err(e) => panic!(
+            "failed to parse the formatted program: {:?}\n{}",
+            e, formatted
+        ),
+    }
+});
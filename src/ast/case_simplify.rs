@@ -3,6 +3,8 @@ use crate::ast::*;
 use crate::config::Config;
 use crate::id::Id;
 use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+use tracing::debug_span;
 
 #[derive(Debug)]
 pub struct CaseSimplify {
@@ -22,6 +24,70 @@ pub struct WildcardToVariable {
 
 type Stack<T> = Vec<T>;
 
+/// keeps the first occurrence of each key in order.
+/// the order of the arms must not depend on hashing to make the output reproducible.
+fn dedup_keys<K: Hash + Eq + Clone, V>(items: impl IntoIterator<Item = (K, V)>) -> Vec<(K, V)> {
+    let mut seen = HashSet::new();
+    items
+        .into_iter()
+        .filter(|(key, _)| seen.insert(key.clone()))
+        .collect()
+}
+
+fn has_or(pattern: &TypedPattern) -> bool {
+    match &pattern.inner {
+        PatternKind::Or { .. } => true,
+        PatternKind::Constructor { arg, .. } => arg.iter().any(|arg| has_or(arg)),
+        PatternKind::Tuple { tuple } => tuple.iter().any(has_or),
+        _ => false,
+    }
+}
+
+/// the patterns without or-patterns which match the values the pattern does, in order
+fn alternatives(pattern: TypedPattern) -> Vec<TypedPattern> {
+    let ty = pattern.ty;
+    match pattern.inner {
+        PatternKind::Or { patterns } => patterns.into_iter().flat_map(alternatives).collect(),
+        PatternKind::Constructor {
+            name,
+            arg: Some(arg),
+        } => alternatives(*arg)
+            .into_iter()
+            .map(|arg| Pattern {
+                ty: ty.clone(),
+                inner: PatternKind::Constructor {
+                    name,
+                    arg: Some(Box::new(arg)),
+                },
+            })
+            .collect(),
+        PatternKind::Tuple { tuple } => {
+            let mut tuples = vec![Vec::new()];
+            for pat in tuple {
+                let alts = alternatives(pat);
+                tuples = tuples
+                    .into_iter()
+                    .flat_map(|prefix: Vec<TypedPattern>| {
+                        alts.iter().map(move |alt| {
+                            let mut tuple = prefix.clone();
+                            tuple.push(alt.clone());
+                            tuple
+                        })
+                    })
+                    .collect();
+            }
+            tuples
+                .into_iter()
+                .map(|tuple| Pattern {
+                    ty: ty.clone(),
+                    inner: PatternKind::Tuple { tuple },
+                })
+                .collect()
+        }
+        inner => vec![Pattern { ty, inner }],
+    }
+}
+
 impl CaseSimplify {
     pub fn new(id: Id) -> Self {
         Self { id }
@@ -45,31 +111,138 @@ impl CaseSimplifyPass {
 
     fn gensym(&mut self, name: &str) -> Symbol {
         let id = self.id.next();
-        Symbol(format!("#{}", name), id)
+        Symbol(format!("#{}", name).into(), id)
     }
 
     fn wildcard_to_variable(&mut self, ast: TypedCore) -> TypedCore {
         WildcardToVariable::new(self.id.clone()).transform_ast(ast)
     }
 
-    fn rename_pattern(&mut self, pattern: &mut TypedPattern) {
+    /// gives the variables fresh ids, recording the new names to `renamed`
+    fn rename_pattern(
+        &mut self,
+        pattern: &mut TypedPattern,
+        renamed: &mut HashMap<Symbol, Symbol>,
+    ) {
         use PatternKind::*;
         match &mut pattern.inner {
             Constructor { arg, .. } => {
                 if let Some(arg) = arg {
-                    self.rename_pattern(arg)
+                    self.rename_pattern(arg, renamed)
                 }
             }
-            Tuple { tuple, .. } => {
-                for pat in tuple {
-                    self.rename_pattern(pat)
+            Tuple { tuple: patterns } | Or { patterns } => {
+                for pat in patterns {
+                    self.rename_pattern(pat, renamed)
                 }
             }
-            Variable { name, .. } => name.1 = self.id.next(),
+            Variable { name, .. } => {
+                let old = *name;
+                *name = *renamed
+                    .entry(old)
+                    .or_insert_with(|| Symbol(old.0, self.id.next()));
+            }
             _ => (),
         }
     }
 
+    /// `p1 | p2 => e` to `p1 => join (x, ...) | p2 => join (x, ...)` with `join` bound to
+    /// `fn (x, ...) => e` in `joins`, which shares the arm among the alternatives
+    fn expand_or(
+        &mut self,
+        pattern: TypedPattern,
+        arm: TypedCoreExpr,
+        joins: &mut Vec<TypedCoreDeclaration>,
+    ) -> Vec<(Stack<TypedPattern>, TypedCoreExpr)> {
+        if !has_or(&pattern) {
+            return vec![(vec![pattern], arm)];
+        }
+        let ty = arm.ty();
+        let binds = pattern
+            .binds()
+            .into_iter()
+            .map(|(name, ty)| (*name, ty.clone()))
+            .collect::<Vec<_>>();
+        let param_ty = match binds.as_slice() {
+            [(_, ty)] => ty.clone(),
+            binds => Type::Tuple(binds.iter().map(|(_, ty)| ty.clone()).collect()),
+        };
+        let (param, body) = match binds.as_slice() {
+            [] => (self.gensym("_"), arm),
+            [(name, _)] => (*name, arm),
+            binds => {
+                let param = self.gensym("args");
+                let tuple = binds
+                    .iter()
+                    .map(|(name, ty)| Pattern {
+                        ty: ty.clone(),
+                        inner: PatternKind::Variable { name: *name },
+                    })
+                    .collect();
+                let pattern = Pattern {
+                    ty: param_ty.clone(),
+                    inner: PatternKind::Tuple { tuple },
+                };
+                let clauses = vec![(vec![pattern], arm)];
+                let body = self.match_compile(vec![(param_ty.clone(), param)], ty.clone(), clauses);
+                (param, body)
+            }
+        };
+        let join = self.gensym("join");
+        let join_ty = Type::fun(param_ty.clone(), ty.clone());
+        joins.push(Declaration::Val {
+            rec: false,
+            pattern: Pattern {
+                ty: join_ty.clone(),
+                inner: PatternKind::Variable { name: join },
+            },
+            expr: Expr {
+                ty: join_ty.clone(),
+                inner: ExprKind::Fn {
+                    param,
+                    body: body.boxed(),
+                },
+            },
+        });
+
+        alternatives(pattern)
+            .into_iter()
+            .map(|mut pattern| {
+                let mut renamed = HashMap::new();
+                self.rename_pattern(&mut pattern, &mut renamed);
+                let mut args = binds
+                    .iter()
+                    .map(|(name, ty)| Expr {
+                        ty: ty.clone(),
+                        inner: ExprKind::Symbol {
+                            name: renamed[name],
+                        },
+                    })
+                    .collect::<Vec<_>>();
+                let arg = if args.len() == 1 {
+                    args.remove(0)
+                } else {
+                    Expr {
+                        ty: param_ty.clone(),
+                        inner: ExprKind::Tuple { tuple: args },
+                    }
+                };
+                let call = Expr {
+                    ty: ty.clone(),
+                    inner: ExprKind::App {
+                        fun: Expr {
+                            ty: join_ty.clone(),
+                            inner: ExprKind::Symbol { name: join },
+                        }
+                        .boxed(),
+                        arg: arg.boxed(),
+                    },
+                };
+                (vec![pattern], call)
+            })
+            .collect()
+    }
+
     fn match_compile(
         &mut self,
         cond: Stack<(Type, Symbol)>,
@@ -230,16 +403,13 @@ impl CaseSimplifyPass {
                 (head, clause)
             })
             .collect::<Vec<_>>();
-        let constants = clause_with_heads
-            .iter()
-            .filter_map(|(head, _)| match head {
-                Pattern {
-                    ty,
-                    inner: PatternKind::Constant { value },
-                } => Some((*value, ty.clone())),
-                _ => None,
-            })
-            .collect::<HashMap<_, _>>();
+        let constants = dedup_keys(clause_with_heads.iter().filter_map(|(head, _)| match head {
+            Pattern {
+                ty,
+                inner: PatternKind::Constant { value },
+            } => Some((*value, ty.clone())),
+            _ => None,
+        }));
         let mut clauses = constants
             .iter()
             .map(|(value, ty)| {
@@ -299,16 +469,13 @@ impl CaseSimplifyPass {
                 (head, clause)
             })
             .collect::<Vec<_>>();
-        let chars = clause_with_heads
-            .iter()
-            .filter_map(|(head, _)| match head {
-                Pattern {
-                    ty,
-                    inner: PatternKind::Char { value },
-                } => Some((*value, ty.clone())),
-                _ => None,
-            })
-            .collect::<HashMap<_, _>>();
+        let chars = dedup_keys(clause_with_heads.iter().filter_map(|(head, _)| match head {
+            Pattern {
+                ty,
+                inner: PatternKind::Char { value },
+            } => Some((*value, ty.clone())),
+            _ => None,
+        }));
         let mut clauses = chars
             .iter()
             .map(|(value, ty)| {
@@ -373,17 +540,18 @@ impl CaseSimplifyPass {
             .map(|(head, _)| head.ty())
             .next()
             .unwrap();
-        let constructors = clause_with_heads
-            .iter()
-            .filter_map(|(head, _)| match head {
+        let constructors =
+            dedup_keys(clause_with_heads.iter().filter_map(|(head, _)| match head {
                 Pattern {
                     ty,
                     inner: PatternKind::Constructor { name, arg },
                 } => Some((name.clone(), (ty.clone(), arg.clone()))),
                 _ => None,
-            })
-            .collect::<HashMap<_, _>>();
-        let constructor_names = constructors.keys().collect::<HashSet<_>>();
+            }));
+        let constructor_names = constructors
+            .iter()
+            .map(|(name, _)| name)
+            .collect::<HashSet<_>>();
         let mut clauses = constructors
             .iter()
             .map(|(name, (ty, arg))| {
@@ -649,7 +817,9 @@ impl CaseSimplifyPass {
     ) -> bool {
         use Type::*;
         match ty {
-            Real | Variable(_) | Fun(_, _) => panic!("no way to pattern match against this type"),
+            Word | Real | String | Variable(_) | Fun(_, _) => {
+                panic!("no way to pattern match against this type")
+            }
             Char | Int => false,
             Tuple(_) => {
                 // unlikely reachable, but writing incase it reaches.
@@ -705,7 +875,7 @@ impl Transform<Type> for CaseSimplifyPass {
                     inner: PatternKind::Tuple { tuple: tuple_pat },
                 };
                 let mut pattern = self.transform_pattern(pattern);
-                self.rename_pattern(&mut pattern);
+                self.rename_pattern(&mut pattern, &mut HashMap::new());
                 let binds = pattern.binds();
                 let tuple = binds
                     .into_iter()
@@ -736,6 +906,8 @@ impl Transform<Type> for CaseSimplifyPass {
         cond: Box<TypedCoreExpr>,
         clauses: Vec<(TypedPattern, TypedCoreExpr)>,
     ) -> TypedCoreExprKind {
+        let span = debug_span!("match_compile", clauses = clauses.len());
+        let _enter = span.enter();
         let condsym = self.gensym("cond");
         let condty = cond.ty();
         let ty = clauses
@@ -743,21 +915,28 @@ impl Transform<Type> for CaseSimplifyPass {
             .map(|(_, expr)| expr.ty())
             .next()
             .expect("case should have at least 1 clause");
+        // the arms of the or-patterns, shared by their alternatives
+        let mut joins = Vec::new();
         let clauses = clauses
             .into_iter()
-            .map(|(pat, arm)| (vec![pat], self.transform_expr(arm)))
+            .flat_map(|(pat, arm)| {
+                let arm = self.transform_expr(arm);
+                self.expand_or(pat, arm, &mut joins)
+            })
             .collect();
-        ExprKind::Binds {
-            binds: vec![Declaration::Val {
-                pattern: Pattern {
-                    ty: condty.clone(),
-                    inner: PatternKind::Variable {
-                        name: condsym.clone(),
-                    },
+        let mut binds = vec![Declaration::Val {
+            pattern: Pattern {
+                ty: condty.clone(),
+                inner: PatternKind::Variable {
+                    name: condsym.clone(),
                 },
-                rec: false,
-                expr: *cond,
-            }],
+            },
+            rec: false,
+            expr: *cond,
+        }];
+        binds.extend(joins);
+        ExprKind::Binds {
+            binds,
             ret: self
                 .match_compile(vec![(condty, condsym)], ty, clauses)
                 .boxed(),
@@ -772,7 +951,7 @@ impl WildcardToVariable {
 
     fn gensym(&mut self, name: &str) -> Symbol {
         let id = self.id.next();
-        Symbol(format!("#{}", name), id)
+        Symbol(format!("#{}", name).into(), id)
     }
 }
 
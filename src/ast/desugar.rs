@@ -2,21 +2,102 @@ use crate::ast::*;
 use crate::config::Config;
 use crate::id::Id;
 use crate::pass::Pass;
-use crate::prim::Symbol;
+use crate::prim::{Symbol, BIF};
+use std::collections::HashMap;
 
 pub struct Desugar {
     id: Id,
+    /// `Config::pattern_guards`
+    pattern_guards: bool,
+    /// the first error found, as the transformation itself doesn't fail
+    error: Option<TypeError<'static>>,
+    /// the top level and the `let`s in scope, the innermost last
+    scopes: Vec<TypeScope>,
+}
+
+/// the names declared in a scope, which the later declarations are rewritten with
+#[derive(Default)]
+struct TypeScope {
+    /// the datatypes, the abbreviations of `withtype` and the replications
+    types: HashMap<Symbol, TypeBinding>,
+    /// the constructors of the replicated datatypes, to the original ones.
+    /// the other constructors map to themselves, to shadow the outer ones
+    constructors: HashMap<Symbol, Symbol>,
+}
+
+/// what a type name stands for
+struct TypeBinding {
+    /// the type replacing the name, if it's an abbreviation or a replication
+    ty: Option<Type>,
+    /// the constructors if it's a datatype, as they are named in the scope
+    constructors: Option<Vec<Symbol>>,
 }
 
 impl Desugar {
     pub fn new(id: Id) -> Self {
-        Self { id }
+        Self {
+            id,
+            pattern_guards: false,
+            error: None,
+            scopes: vec![TypeScope::default()],
+        }
     }
 
     pub fn gensym(&mut self) -> Symbol {
         let id = self.id.next();
         Symbol("#arg".into(), id)
     }
+
+    fn scope(&mut self) -> &mut TypeScope {
+        self.scopes.last_mut().unwrap()
+    }
+
+    fn bind_type(&mut self, name: Symbol, ty: Option<Type>, constructors: Option<Vec<Symbol>>) {
+        if let Some(constructors) = &constructors {
+            for &cname in constructors {
+                self.bind_constructor(cname, cname);
+            }
+        }
+        self.scope()
+            .types
+            .insert(name, TypeBinding { ty, constructors });
+    }
+
+    fn bind_constructor(&mut self, name: Symbol, original: Symbol) {
+        self.scope().constructors.insert(name, original);
+    }
+
+    fn lookup_type(&self, name: &Symbol) -> Option<&TypeBinding> {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.types.get(name))
+    }
+
+    /// the name, or the constructor it replicates
+    fn constructor(&self, name: Symbol) -> Symbol {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.constructors.get(&name))
+            .cloned()
+            .unwrap_or(name)
+    }
+
+    /// the type with the abbreviations in scope replaced by their definitions
+    fn expand_type(&self, ty: Type) -> Type {
+        match ty {
+            Type::Datatype(name) => match self.lookup_type(&name).and_then(|b| b.ty.as_ref()) {
+                Some(ty) => ty.clone(),
+                None => Type::Datatype(name),
+            },
+            Type::Fun(arg, ret) => Type::fun(self.expand_type(*arg), self.expand_type(*ret)),
+            Type::Tuple(tuple) => {
+                Type::Tuple(tuple.into_iter().map(|ty| self.expand_type(ty)).collect())
+            }
+            ty => ty,
+        }
+    }
 }
 
 impl Desugar {
@@ -32,9 +113,31 @@ impl Desugar {
         use Declaration::*;
         match decl {
             Datatype { name, constructors } => Some(self.transform_datatype(name, constructors)),
+            Exception { name, arg } => {
+                self.bind_constructor(name, name);
+                Some(Exception {
+                    name,
+                    arg: arg.map(|arg| self.expand_type(arg)),
+                })
+            }
             Val { rec, pattern, expr } => Some(self.transform_val(rec, pattern, expr)),
             D(DerivedDeclaration::Fun { name, clauses }) => Some(self.transform_fun(name, clauses)),
             D(DerivedDeclaration::Infix { .. }) => None,
+            D(DerivedDeclaration::Withtype {
+                name,
+                constructors,
+                types,
+            }) => Some(self.transform_withtype(name, constructors, types)),
+            D(DerivedDeclaration::Replication { name, datatype }) => {
+                self.transform_replication(name, datatype);
+                None
+            }
+            D(DerivedDeclaration::Import {
+                module,
+                name,
+                argty,
+                retty,
+            }) => Some(self.transform_import(module, name, argty, retty)),
         }
     }
 
@@ -43,9 +146,83 @@ impl Desugar {
         name: Symbol,
         constructors: Vec<(Symbol, Option<Type>)>,
     ) -> UntypedCoreDeclaration {
+        let cnames = constructors.iter().map(|&(cname, _)| cname).collect();
+        self.bind_type(name, None, Some(cnames));
+        let constructors = constructors
+            .into_iter()
+            .map(|(cname, arg)| (cname, arg.map(|arg| self.expand_type(arg))))
+            .collect();
         Declaration::Datatype { name, constructors }
     }
 
+    // datatype t = A of u withtype u = t * int
+    // ->
+    // datatype t = A of t * int
+    //
+    // and the later `u`s are also replaced by `t * int`.
+    // the abbreviations are defined after the datatype, so they refer to it
+    fn transform_withtype(
+        &mut self,
+        name: Symbol,
+        constructors: Vec<(Symbol, Option<Type>)>,
+        types: Vec<(Symbol, Type)>,
+    ) -> UntypedCoreDeclaration {
+        self.bind_type(name, None, None);
+        let types = types
+            .into_iter()
+            .map(|(tname, ty)| (tname, self.expand_type(ty)))
+            .collect::<Vec<_>>();
+        for (tname, ty) in types {
+            self.bind_type(tname, Some(ty), None);
+        }
+        self.transform_datatype(name, constructors)
+    }
+
+    // datatype t = datatype M.s
+    // ->
+    // (nothing)
+    //
+    // and the later `t`s are replaced by `M.s`, and the constructors of `M.s` under the
+    // qualifier of `t`, e.g. `A` for `M.A`, by the constructors of `M.s`. Both are bound in
+    // the innermost scope, so a replication in `let` is not seen after its `end`
+    fn transform_replication(&mut self, name: Symbol, datatype: Symbol) {
+        let constructors = match self.lookup_type(&datatype) {
+            Some(TypeBinding {
+                constructors: Some(constructors),
+                ..
+            }) => constructors.clone(),
+            _ => {
+                self.error.get_or_insert(TypeError::NotDatatype(datatype));
+                return;
+            }
+        };
+        let ty = self.expand_type(Type::Datatype(datatype));
+        let qualifier = |name: &Symbol| match name.0.rfind('.') {
+            Some(pos) => name.0[..=pos].to_string(),
+            None => String::new(),
+        };
+        let (from, to) = (qualifier(&datatype), qualifier(&name));
+        let replicas = constructors
+            .iter()
+            .map(|cname| {
+                let cname = cname.0.as_str();
+                let base = cname.strip_prefix(from.as_str()).unwrap_or(cname);
+                Symbol::new(format!("{}{}", to, base))
+            })
+            .collect::<Vec<_>>();
+        for (&replica, &cname) in replicas.iter().zip(&constructors) {
+            let original = self.constructor(cname);
+            self.bind_constructor(replica, original);
+        }
+        self.scope().types.insert(
+            name,
+            TypeBinding {
+                ty: Some(ty),
+                constructors: Some(replicas),
+            },
+        );
+    }
+
     fn transform_val(
         &mut self,
         rec: bool,
@@ -72,7 +249,12 @@ impl Desugar {
                 (
                     Pattern {
                         ty: (),
-                        inner: PatternKind::Tuple { tuple: pats },
+                        inner: PatternKind::Tuple {
+                            tuple: pats
+                                .into_iter()
+                                .map(|pat| self.transform_pattern(pat))
+                                .collect(),
+                        },
                     },
                     self.transform_expr(expr),
                 )
@@ -119,6 +301,85 @@ impl Desugar {
         }
     }
 
+    // _import "m" "f" : (t1, t2) -> t3
+    // ->
+    // val f = fn x => case x of (y1, y2) => _externcall ("m"."f": (t1, t2) -> t3) (y1, y2)
+    fn transform_import(
+        &mut self,
+        module: String,
+        name: Symbol,
+        argty: Type,
+        retty: Type,
+    ) -> UntypedCoreDeclaration {
+        let argty = self.expand_type(argty);
+        let retty = self.expand_type(retty);
+        let param = self.gensym();
+        let symbol = |name| Expr {
+            ty: (),
+            inner: ExprKind::Symbol { name },
+        };
+        let fun = name.0.to_string();
+        let body = match argty {
+            Type::Tuple(argty) => {
+                let params = argty.iter().map(|_| self.gensym()).collect::<Vec<_>>();
+                let pattern = Pattern {
+                    ty: (),
+                    inner: PatternKind::Tuple {
+                        tuple: params
+                            .iter()
+                            .cloned()
+                            .map(|name| Pattern {
+                                ty: (),
+                                inner: PatternKind::Variable { name },
+                            })
+                            .collect(),
+                    },
+                };
+                let call = Expr {
+                    ty: (),
+                    inner: ExprKind::ExternCall {
+                        module,
+                        fun,
+                        args: params.into_iter().map(symbol).collect(),
+                        argty,
+                        retty,
+                    },
+                };
+                Expr {
+                    ty: (),
+                    inner: ExprKind::Case {
+                        cond: symbol(param.clone()).boxed(),
+                        clauses: vec![(pattern, call)],
+                    },
+                }
+            }
+            argty => Expr {
+                ty: (),
+                inner: ExprKind::ExternCall {
+                    module,
+                    fun,
+                    args: vec![symbol(param.clone())],
+                    argty: vec![argty],
+                    retty,
+                },
+            },
+        };
+        Declaration::Val {
+            rec: false,
+            pattern: Pattern {
+                ty: (),
+                inner: PatternKind::Variable { name },
+            },
+            expr: Expr {
+                ty: (),
+                inner: ExprKind::Fn {
+                    param,
+                    body: body.boxed(),
+                },
+            },
+        }
+    }
+
     fn transform_expr(&mut self, expr: UntypedExpr) -> UntypedCoreExpr {
         use crate::ast::ExprKind::*;
         let inner = match expr.inner {
@@ -139,6 +400,10 @@ impl Desugar {
             Symbol { name } => self.transform_symbol(name),
             Literal { value } => self.transform_literal(value),
             D(DerivedExprKind::If { cond, then, else_ }) => self.transform_if(cond, then, else_),
+            D(DerivedExprKind::Handle { expr, clauses }) => self.transform_handle(expr, clauses),
+            D(DerivedExprKind::GuardedCase { cond, clauses }) => {
+                self.transform_guarded_case(cond, clauses)
+            }
         };
         UntypedCoreExpr { ty: expr.ty, inner }
     }
@@ -147,13 +412,14 @@ impl Desugar {
         binds: Vec<UntypedDeclaration>,
         ret: Box<UntypedExpr>,
     ) -> UntypedCoreExprKind {
-        ExprKind::Binds {
-            binds: binds
-                .into_iter()
-                .filter_map(|decl| self.transform_statement(decl))
-                .collect(),
-            ret: self.transform_expr(*ret).boxed(),
-        }
+        self.scopes.push(TypeScope::default());
+        let binds = binds
+            .into_iter()
+            .filter_map(|decl| self.transform_statement(decl))
+            .collect();
+        let ret = self.transform_expr(*ret).boxed();
+        self.scopes.pop();
+        ExprKind::Binds { binds, ret }
     }
 
     fn transform_builtincall(&mut self, fun: BIF, args: Vec<UntypedExpr>) -> UntypedCoreExprKind {
@@ -181,8 +447,8 @@ impl Desugar {
                 .into_iter()
                 .map(|arg| self.transform_expr(arg))
                 .collect(),
-            argty,
-            retty,
+            argty: argty.into_iter().map(|ty| self.expand_type(ty)).collect(),
+            retty: self.expand_type(retty),
         }
     }
 
@@ -237,6 +503,66 @@ impl Desugar {
         }
     }
 
+    // e handle clauses
+    // -> _builtincall "handle"(fn _ => e, fn exn => case exn of clauses | _ => raise exn)
+    fn transform_handle(
+        &mut self,
+        expr: Box<UntypedExpr>,
+        clauses: Vec<(UntypedPattern, UntypedExpr)>,
+    ) -> UntypedCoreExprKind {
+        let unit = self.gensym();
+        let exn = self.gensym();
+        let sym = |name: &Symbol| Expr {
+            ty: (),
+            inner: ExprKind::Symbol { name: name.clone() },
+        };
+        let mut clauses = clauses
+            .into_iter()
+            .map(|(p, e)| (self.transform_pattern(p), self.transform_expr(e)))
+            .collect::<Vec<_>>();
+        // unhandled exceptions propagate.
+        // constructors and variables are not distinguished yet, so the clause is always added
+        clauses.push((
+            Pattern {
+                ty: (),
+                inner: PatternKind::Wildcard {},
+            },
+            Expr {
+                ty: (),
+                inner: ExprKind::BuiltinCall {
+                    fun: BIF::Raise,
+                    args: vec![sym(&exn)],
+                },
+            },
+        ));
+        ExprKind::BuiltinCall {
+            fun: BIF::Handle,
+            args: vec![
+                Expr {
+                    ty: (),
+                    inner: ExprKind::Fn {
+                        param: unit,
+                        body: self.transform_expr(*expr).boxed(),
+                    },
+                },
+                Expr {
+                    ty: (),
+                    inner: ExprKind::Fn {
+                        param: exn.clone(),
+                        body: Expr {
+                            ty: (),
+                            inner: ExprKind::Case {
+                                cond: sym(&exn).boxed(),
+                                clauses,
+                            },
+                        }
+                        .boxed(),
+                    },
+                },
+            ],
+        }
+    }
+
     fn transform_case(
         &mut self,
         cond: Box<UntypedExpr>,
@@ -251,6 +577,129 @@ impl Desugar {
         }
     }
 
+    // case e of p1 => e1 | p2 when g => e2 | rest
+    // ->
+    // let val v = e
+    //     val k = fn _ => case v of p1 => raise Match | rest
+    // in case v of p1 => e1 | p2 => if g then e2 else k () | _ => k () end
+    //
+    // the arms before the guard are kept in `k` for the exhaustiveness check, which then
+    // takes the guarded arm as not matching anything
+    fn transform_guarded_case(
+        &mut self,
+        cond: Box<UntypedExpr>,
+        clauses: Vec<(UntypedPattern, Option<UntypedExpr>, UntypedExpr)>,
+    ) -> UntypedCoreExprKind {
+        if !self.pattern_guards {
+            self.error.get_or_insert(TypeError::PatternGuardsDisabled);
+        }
+        let v = self.gensym();
+        let ret = self.guarded_clauses(&v, Vec::new(), clauses);
+        ExprKind::Binds {
+            binds: vec![Declaration::Val {
+                rec: false,
+                pattern: Pattern {
+                    ty: (),
+                    inner: PatternKind::Variable { name: v },
+                },
+                expr: self.transform_expr(*cond),
+            }],
+            ret: ret.boxed(),
+        }
+    }
+
+    /// the case of `cond` taking the first guarded clause, falling back to the rest of the
+    /// clauses. `covered` are the patterns of the clauses before, which are already tried
+    fn guarded_clauses(
+        &mut self,
+        cond: &Symbol,
+        mut covered: Vec<UntypedPattern>,
+        mut clauses: Vec<(UntypedPattern, Option<UntypedExpr>, UntypedExpr)>,
+    ) -> UntypedCoreExpr {
+        let expr = |inner: ExprKind<()>| UntypedExpr { ty: (), inner };
+        let sym = |name: &Symbol| expr(ExprKind::Symbol { name: name.clone() });
+        let unreachable = || {
+            expr(ExprKind::BuiltinCall {
+                fun: BIF::Raise,
+                args: vec![sym(&Symbol::new("Match"))],
+            })
+        };
+        let pos = match clauses.iter().position(|(_, guard, _)| guard.is_some()) {
+            Some(pos) => pos,
+            None => {
+                let clauses = covered
+                    .into_iter()
+                    .map(|pat| (pat, unreachable()))
+                    .chain(clauses.into_iter().map(|(pat, _, arm)| (pat, arm)))
+                    .collect();
+                return self.transform_expr(expr(ExprKind::Case {
+                    cond: sym(cond).boxed(),
+                    clauses,
+                }));
+            }
+        };
+        let rest = clauses.split_off(pos + 1);
+        let (pat, guard, arm) = clauses.pop().unwrap();
+        let guard = guard.unwrap();
+        let clauses = clauses
+            .into_iter()
+            .map(|(pat, _, arm)| (pat, arm))
+            .collect::<Vec<_>>();
+        covered.extend(clauses.iter().map(|(pat, _)| pat.clone()));
+        if covered.is_empty() && rest.is_empty() {
+            self.error.get_or_insert(TypeError::OnlyGuardedClauses);
+            return self.transform_expr(unreachable());
+        }
+
+        let k = self.gensym();
+        let param = self.gensym();
+        let fallback = self.guarded_clauses(cond, covered, rest);
+        let call_k = || {
+            expr(ExprKind::App {
+                fun: sym(&k).boxed(),
+                arg: expr(ExprKind::Tuple { tuple: vec![] }).boxed(),
+            })
+        };
+        let guarded = expr(ExprKind::D(DerivedExprKind::If {
+            cond: guard.boxed(),
+            then: arm.boxed(),
+            else_: call_k().boxed(),
+        }));
+        let mut clauses = clauses;
+        clauses.push((pat, guarded));
+        clauses.push((
+            Pattern {
+                ty: (),
+                inner: PatternKind::Wildcard {},
+            },
+            call_k(),
+        ));
+        let case = self.transform_expr(expr(ExprKind::Case {
+            cond: sym(cond).boxed(),
+            clauses,
+        }));
+        Expr {
+            ty: (),
+            inner: ExprKind::Binds {
+                binds: vec![Declaration::Val {
+                    rec: false,
+                    pattern: Pattern {
+                        ty: (),
+                        inner: PatternKind::Variable { name: k },
+                    },
+                    expr: Expr {
+                        ty: (),
+                        inner: ExprKind::Fn {
+                            param,
+                            body: fallback.boxed(),
+                        },
+                    },
+                }],
+                ret: case.boxed(),
+            },
+        }
+    }
+
     fn transform_tuple(&mut self, tuple: Vec<UntypedExpr>) -> UntypedCoreExprKind {
         ExprKind::Tuple {
             tuple: tuple.into_iter().map(|t| self.transform_expr(t)).collect(),
@@ -264,11 +713,13 @@ impl Desugar {
     ) -> UntypedCoreExprKind {
         ExprKind::Constructor {
             arg: arg.map(|e| self.transform_expr(*e).boxed()),
-            name,
+            name: self.constructor(name),
         }
     }
     fn transform_symbol(&mut self, name: Symbol) -> UntypedCoreExprKind {
-        ExprKind::Symbol { name }
+        ExprKind::Symbol {
+            name: self.constructor(name),
+        }
     }
 
     fn transform_literal(&mut self, value: Literal) -> UntypedCoreExprKind {
@@ -276,15 +727,46 @@ impl Desugar {
     }
 
     fn transform_pattern(&mut self, pattern: UntypedPattern) -> UntypedPattern {
-        pattern
+        use crate::ast::PatternKind::*;
+        let inner = match pattern.inner {
+            Constructor { name, arg } => Constructor {
+                name: self.constructor(name),
+                arg: arg.map(|arg| Box::new(self.transform_pattern(*arg))),
+            },
+            // constructors and variables are not distinguished yet
+            Variable { name } => Variable {
+                name: self.constructor(name),
+            },
+            Tuple { tuple } => Tuple {
+                tuple: tuple
+                    .into_iter()
+                    .map(|pat| self.transform_pattern(pat))
+                    .collect(),
+            },
+            Or { patterns } => Or {
+                patterns: patterns
+                    .into_iter()
+                    .map(|pat| self.transform_pattern(pat))
+                    .collect(),
+            },
+            inner => inner,
+        };
+        Pattern {
+            ty: pattern.ty,
+            inner,
+        }
     }
 }
 
-impl<E> Pass<UntypedAst, E> for Desugar {
+impl<'a> Pass<UntypedAst, TypeError<'a>> for Desugar {
     type Target = UntypedCore;
 
-    fn trans(&mut self, ast: UntypedAst, _: &Config) -> ::std::result::Result<Self::Target, E> {
+    fn trans(&mut self, ast: UntypedAst, config: &Config) -> Result<'a, Self::Target> {
+        self.pattern_guards = config.pattern_guards;
         let core = self.transform_ast(ast);
-        Ok(core)
+        match self.error.take() {
+            Some(e) => Err(e),
+            None => Ok(core),
+        }
     }
 }
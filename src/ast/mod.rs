@@ -5,15 +5,19 @@ mod rename;
 mod typing;
 mod util;
 mod var2constructor;
+mod verify;
 
 pub use self::case_simplify::CaseSimplify;
 pub use self::desugar::Desugar;
 pub use self::rename::Rename;
 pub use self::typing::Typer;
 pub use self::var2constructor::VarToConstructor;
+pub use self::verify::Verify;
 use crate::ast;
 use crate::prim::*;
 use nom;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 pub use std::collections::HashMap;
 use std::error::Error;
 use std::fmt;
@@ -24,6 +28,7 @@ pub type UntypedCore = Core<()>;
 pub type TypedCore = Core<Type>;
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct AST<Ty, DE = DerivedExprKind<Ty>, DS = DerivedDeclaration<Ty>>(
     pub Vec<Declaration<Ty, DE, DS>>,
 );
@@ -34,11 +39,17 @@ pub type UntypedCoreDeclaration = CoreDeclaration<()>;
 pub type TypedCoreDeclaration = CoreDeclaration<Type>;
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Declaration<Ty, DE = DerivedExprKind<Ty>, DS = DerivedDeclaration<Ty>> {
     Datatype {
         name: Symbol,
         constructors: Vec<(Symbol, Option<Type>)>,
     },
+    /// adds a constructor to `exn`
+    Exception {
+        name: Symbol,
+        arg: Option<Type>,
+    },
     Val {
         rec: bool,
         pattern: Pattern<Ty>,
@@ -48,6 +59,7 @@ pub enum Declaration<Ty, DE = DerivedExprKind<Ty>, DS = DerivedDeclaration<Ty>>
 }
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum DerivedDeclaration<Ty> {
     Fun {
         name: Symbol,
@@ -57,9 +69,28 @@ pub enum DerivedDeclaration<Ty> {
         priority: Option<u8>,
         names: Vec<Symbol>,
     },
+    /// `datatype name = constructors withtype ty = ... and ...`, which declares the type
+    /// abbreviations along with the datatype. the constructors may refer to the abbreviations,
+    /// and the abbreviations to the datatype
+    Withtype {
+        name: Symbol,
+        constructors: Vec<(Symbol, Option<Type>)>,
+        types: Vec<(Symbol, Type)>,
+    },
+    /// `datatype name = datatype datatype`, which binds the datatype and its constructors
+    /// under the qualifier of `name`
+    Replication { name: Symbol, datatype: Symbol },
+    /// `_import "module" "name" : argty -> retty` binds the host function to `name`
+    Import {
+        module: String,
+        name: Symbol,
+        argty: Type,
+        retty: Type,
+    },
 }
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Nothing {}
 
 pub type UntypedExpr = Expr<()>;
@@ -71,6 +102,7 @@ pub type TypedCoreExpr = CoreExpr<Type>;
 pub type TypedCoreExprKind = CoreExprKind<Type>;
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Annot<Ty, Inner> {
     pub ty: Ty,
     pub inner: Inner,
@@ -80,6 +112,7 @@ pub type Expr<Ty, DE = DerivedExprKind<Ty>, DS = DerivedDeclaration<Ty>> =
     Annot<Ty, ExprKind<Ty, DE, DS>>;
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum ExprKind<Ty, DE = DerivedExprKind<Ty>, DS = DerivedDeclaration<Ty>> {
     Binds {
         binds: Vec<Declaration<Ty, DE, DS>>,
@@ -125,12 +158,22 @@ pub enum ExprKind<Ty, DE = DerivedExprKind<Ty>, DS = DerivedDeclaration<Ty>> {
 }
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum DerivedExprKind<Ty> {
     If {
         cond: Box<Expr<Ty>>,
         then: Box<Expr<Ty>>,
         else_: Box<Expr<Ty>>,
     },
+    Handle {
+        expr: Box<Expr<Ty>>,
+        clauses: Vec<(Pattern<Ty>, Expr<Ty>)>,
+    },
+    /// `case` with `pat when expr => expr` clauses, whose arms are taken only if the guard holds
+    GuardedCase {
+        cond: Box<Expr<Ty>>,
+        clauses: Vec<(Pattern<Ty>, Option<Expr<Ty>>, Expr<Ty>)>,
+    },
 }
 
 pub type UntypedPattern = Pattern<()>;
@@ -141,6 +184,7 @@ pub type TypedPatternKind = PatternKind<Type>;
 pub type Pattern<Ty> = Annot<Ty, PatternKind<Ty>>;
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum PatternKind<Ty> {
     Constant {
         // same type as Literal::Int
@@ -161,6 +205,10 @@ pub enum PatternKind<Ty> {
         name: Symbol,
     },
     Wildcard {},
+    /// `p1 | p2 | ...`, whose alternatives bind the same variables
+    Or {
+        patterns: Vec<Pattern<Ty>>,
+    },
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -170,11 +218,14 @@ pub struct SymbolTable {
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Type {
     Variable(u64),
     Char,
     Int,
+    Word,
     Real,
+    String,
     Fun(Box<Type>, Box<Type>),
     Tuple(Vec<Type>),
     Datatype(Symbol),
@@ -208,7 +259,7 @@ impl<Ty> CoreDeclaration<Ty> {
         use Declaration::*;
         match self {
             Datatype { name, constructors } => Datatype { name, constructors },
-
+            Exception { name, arg } => Exception { name, arg },
             Val { pattern, expr, rec } => Val {
                 rec,
                 pattern: pattern.map_ty(&mut *f),
@@ -292,6 +343,9 @@ impl<Ty> Pattern<Ty> {
             },
             Variable { name } => Variable { name },
             Wildcard {} => Wildcard {},
+            Or { patterns } => Or {
+                patterns: patterns.into_iter().map(|pat| pat.map_ty(f)).collect(),
+            },
         };
         Pattern { ty, inner }
     }
@@ -303,6 +357,17 @@ impl<Ty> Pattern<Ty> {
             Variable { name } => vec![(name, &self.ty)],
             Tuple { tuple, .. } => tuple.iter().flat_map(|pat| pat.binds()).collect(),
             Constructor { arg, .. } => arg.iter().flat_map(|pat| pat.binds()).collect(),
+            // the variables all the alternatives bind, which the wildcards turned into
+            // variables by the match compilation are not
+            Or { patterns } => patterns[0]
+                .binds()
+                .into_iter()
+                .filter(|(name, _)| {
+                    patterns[1..]
+                        .iter()
+                        .all(|pat| pat.binds().iter().any(|(other, _)| other == name))
+                })
+                .collect(),
         }
     }
 
@@ -371,6 +436,17 @@ impl SymbolTable {
         self.types.insert(name, info);
     }
 
+    /// adds the constructor of an exception to `exn`
+    pub fn register_exception(&mut self, name: Symbol, arg: Option<Type>) {
+        let exn = Symbol::new("exn");
+        self.constructors.insert(name.clone(), exn.clone());
+        self.types
+            .get_mut(&exn)
+            .expect("internal error: exn is not registered")
+            .constructors
+            .push((name, arg));
+    }
+
     pub fn get_type(&self, name: &Symbol) -> Option<&TypeInfo> {
         self.types.get(&name)
     }
@@ -407,11 +483,30 @@ impl SymbolTable {
 
 #[derive(Debug)]
 pub enum TypeError<'a> {
-    MisMatch { expected: Type, actual: Type },
+    MisMatch {
+        expected: Type,
+        actual: Type,
+    },
     CannotInfer,
     FreeVar,
     NotFunction(ast::Expr<Type>),
     ParseError(nom::Err<(&'a str, nom::error::ErrorKind)>),
+    /// the IR breaks an invariant. this is a bug of the compiler
+    InvalidIR(String),
+    /// the name to export is not a top level function
+    UnknownExport(String),
+    /// the alternatives of an or-pattern bind different variables, or at different types
+    OrPatternBinds,
+    /// the values of the type are compared with `=`, which the type does not admit
+    NotEquality(Type),
+    /// the clause has a guard, but `Config::pattern_guards` is off
+    PatternGuardsDisabled,
+    /// every clause of the case has a guard, so no arm matches when they all fail
+    OnlyGuardedClauses,
+    /// the replicated type is not a datatype
+    NotDatatype(Symbol),
+    /// the exported function reads top level values, which `cps_conversion` binds locally
+    LocalExport(String),
 }
 
 impl<'a> fmt::Display for TypeError<'a> {
@@ -429,25 +524,20 @@ impl<'a> Error for TypeError<'a> {
             &FreeVar => "free variable is found",
             &NotFunction(_) => "not a function",
             &ParseError(_) => "parse error",
+            &InvalidIR(_) => "invalid IR",
+            &UnknownExport(_) => "exported function is not defined",
+            &OrPatternBinds => "the alternatives of the or-pattern bind different variables",
+            &NotEquality(_) => "the type does not admit equality",
+            &PatternGuardsDisabled => "pattern guards are not enabled",
+            &OnlyGuardedClauses => "every clause of the case has a guard",
+            &NotDatatype(_) => "the type is not a datatype",
+            &LocalExport(_) => "exported function reads top level values under CPS",
         }
     }
 }
 
 impl<'a> From<nom::Err<(&'a str, nom::error::ErrorKind)>> for TypeError<'a> {
     fn from(e: nom::Err<(&'a str, nom::error::ErrorKind)>) -> Self {
-        // fn conv<'b>(e: nom::Err<&'b [u8]>) -> nom::Err<&'b str> {
-        //     use std::str::from_utf8;
-        //     use nom::Err::*;
-        //     match e {
-        //         Code(e) => Code(e),
-        //         Node(kind, box_err) => Node(kind, Box::new(conv(*box_err))),
-        //         Position(kind, slice) => Position(kind, from_utf8(slice).unwrap()),
-        //         NodePosition(kind, slice, box_err) => {
-        //             NodePosition(kind, from_utf8(slice).unwrap(), Box::new(conv(*box_err)))
-        //         }
-        //     }
-        // }
-
         TypeError::ParseError(e)
     }
 }
@@ -2,13 +2,82 @@ use crate::ast::*;
 use crate::util::PP;
 use std::io;
 
-impl<Ty: PP, DE: PP, DS: PP> PP for (SymbolTable, AST<Ty, DE, DS>) {
+/// how far an expression extends, which decides where it needs parentheses.
+/// the printed programs parse back to the same AST
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Shape {
+    /// extends to the right as far as possible, like `fn x => x`
+    Open,
+    /// an application, like `f x`
+    App,
+    /// delimited on both sides, like `(x, y)`
+    Closed,
+}
+
+pub trait HasShape {
+    fn shape(&self) -> Shape;
+}
+
+/// prints `e`, parenthesized if it extends further than `shape`
+fn pp_as<E: PP + HasShape, W: io::Write>(
+    e: &E,
+    w: &mut W,
+    indent: usize,
+    shape: Shape,
+) -> io::Result<()> {
+    if e.shape() < shape {
+        write!(w, "(")?;
+        e.pp(w, indent)?;
+        write!(w, ")")
+    } else {
+        e.pp(w, indent)
+    }
+}
+
+/// prints the name in the expressions. symbolic names are prefixed with `op`
+/// because they may be infix operators
+fn pp_op<W: io::Write>(name: &Symbol, w: &mut W, indent: usize) -> io::Result<()> {
+    if name.0.starts_with(|c: char| !c.is_alphanumeric()) {
+        write!(w, "op ")?;
+    }
+    name.pp(w, indent)
+}
+
+impl<Ty, DE: HasShape, DS> HasShape for Expr<Ty, DE, DS> {
+    fn shape(&self) -> Shape {
+        use crate::ast::ExprKind::*;
+        match &self.inner {
+            Fn { .. } | Case { .. } => Shape::Open,
+            BuiltinCall {
+                fun: BIF::Raise,
+                args,
+            } if args.len() == 1 => Shape::Open,
+            App { .. } | Constructor { arg: Some(_), .. } => Shape::App,
+            D(d) => d.shape(),
+            _ => Shape::Closed,
+        }
+    }
+}
+
+impl<Ty> HasShape for DerivedExprKind<Ty> {
+    fn shape(&self) -> Shape {
+        Shape::Open
+    }
+}
+
+impl HasShape for Nothing {
+    fn shape(&self) -> Shape {
+        match *self {}
+    }
+}
+
+impl<Ty: PP, DE: PP + HasShape, DS: PP> PP for (SymbolTable, AST<Ty, DE, DS>) {
     fn pp<W: io::Write>(&self, w: &mut W, indent: usize) -> io::Result<()> {
         self.1.pp(w, indent)
     }
 }
 
-impl<Ty: PP, DE: PP, DS: PP> PP for AST<Ty, DE, DS> {
+impl<Ty: PP, DE: PP + HasShape, DS: PP> PP for AST<Ty, DE, DS> {
     fn pp<W: io::Write>(&self, w: &mut W, indent: usize) -> io::Result<()> {
         for bind in &self.0 {
             bind.pp(w, indent)?;
@@ -18,9 +87,10 @@ impl<Ty: PP, DE: PP, DS: PP> PP for AST<Ty, DE, DS> {
     }
 }
 
-impl<Ty: PP, DE: PP, DS: PP> PP for Declaration<Ty, DE, DS> {
+impl<Ty: PP, DE: PP + HasShape, DS: PP> PP for Declaration<Ty, DE, DS> {
     fn pp<W: io::Write>(&self, w: &mut W, indent: usize) -> io::Result<()> {
         use Declaration::*;
+        write!(w, "{}", Self::nspaces(indent))?;
         match self {
             Datatype { name, constructors } => {
                 write!(w, "datatype ")?;
@@ -36,8 +106,16 @@ impl<Ty: PP, DE: PP, DS: PP> PP for Declaration<Ty, DE, DS> {
                 });
                 Ok(())
             }
+            Exception { name, arg } => {
+                write!(w, "exception ")?;
+                name.pp(w, indent)?;
+                if let Some(arg) = arg {
+                    write!(w, " of ")?;
+                    arg.pp(w, indent)?;
+                }
+                Ok(())
+            }
             Val { pattern, expr, rec } => {
-                write!(w, "{}", Self::nspaces(indent))?;
                 write!(w, "val ")?;
                 if *rec {
                     write!(w, "rec ")?;
@@ -59,23 +137,27 @@ impl<Ty: PP> PP for DerivedDeclaration<Ty> {
         use DerivedDeclaration::*;
         match self {
             Fun { name, clauses, .. } => {
-                write!(w, "{}", Self::nspaces(indent))?;
                 write!(w, "fun ")?;
-                inter_iter!(
-                    clauses,
-                    { write!(w, "\n{}  | ", Self::nspaces(indent))? ; name.pp(w, indent)? },
-                    |(params, expr)| => {
-                    name.pp(w, indent)?;
-                    write!(w, " ")?;
+                for (i, (params, expr)) in clauses.iter().enumerate() {
+                    if i != 0 {
+                        write!(w, "\n{}  | ", Self::nspaces(indent))?;
+                    }
+                    pp_op(name, w, indent)?;
                     for param in params {
-                        param.pp(w, indent)?;
                         write!(w, " ")?;
+                        param.pp_atomic(w, indent)?;
                     }
                     // write!(w, ": ")?;
                     // self.ty.pp(w, indent)?;
                     write!(w, " = ")?;
-                    expr.pp(w, indent + 4)?;
-                });
+                    // the following clauses would be taken as the part of the expression
+                    let shape = if i + 1 == clauses.len() {
+                        Shape::Open
+                    } else {
+                        Shape::App
+                    };
+                    pp_as(expr, w, indent + 4, shape)?;
+                }
                 Ok(())
             }
             Infix { priority, names } => {
@@ -89,11 +171,50 @@ impl<Ty: PP> PP for DerivedDeclaration<Ty> {
                 }
                 Ok(())
             }
+            Withtype {
+                name,
+                constructors,
+                types,
+            } => {
+                write!(w, "datatype ")?;
+                name.pp(w, indent)?;
+                write!(w, " =")?;
+                inter_iter!(constructors, write!(w, " |")?, |(name, param)| =>{
+                    write!(w, " ")?;
+                    name.pp(w, indent)?;
+                    if let Some(param) = param {
+                        write!(w, " of ")?;
+                        param.pp(w, indent)?;
+                    }
+                });
+                write!(w, " withtype ")?;
+                inter_iter!(types, write!(w, " and ")?, |(name, ty)| =>{
+                    name.pp(w, indent)?;
+                    write!(w, " = ")?;
+                    ty.pp(w, indent)?;
+                });
+                Ok(())
+            }
+            Replication { name, datatype } => {
+                write!(w, "datatype ")?;
+                name.pp(w, indent)?;
+                write!(w, " = datatype ")?;
+                datatype.pp(w, indent)
+            }
+            Import {
+                module,
+                name,
+                argty,
+                retty,
+            } => {
+                write!(w, "_import \"{}\" \"{}\" : ", module, name.0)?;
+                Type::fun(argty.clone(), retty.clone()).pp(w, indent)
+            }
         }
     }
 }
 
-impl<Ty: PP, DE: PP, DS: PP> PP for Expr<Ty, DE, DS> {
+impl<Ty: PP, DE: PP + HasShape, DS: PP> PP for Expr<Ty, DE, DS> {
     fn pp<W: io::Write>(&self, w: &mut W, indent: usize) -> io::Result<()> {
         use crate::ast::ExprKind::*;
         match &self.inner {
@@ -109,6 +230,13 @@ impl<Ty: PP, DE: PP, DS: PP> PP for Expr<Ty, DE, DS> {
                 ret.pp(w, indent + 4)?;
                 write!(w, "\n{}end", ind)?;
             }
+            BuiltinCall {
+                fun: BIF::Raise,
+                args,
+            } if args.len() == 1 => {
+                write!(w, "raise ")?;
+                args[0].pp(w, indent)?;
+            }
             BuiltinCall { fun, args } => {
                 write!(w, "_builtincall \"")?;
                 fun.pp(w, indent)?;
@@ -129,16 +257,7 @@ impl<Ty: PP, DE: PP, DS: PP> PP for Expr<Ty, DE, DS> {
                 argty,
                 retty,
             } => {
-                write!(w, "_externcall \"{}\".\"{}\"(", module, fun)?;
-
-                inter_iter! {
-                    &args,
-                    write!(w, ", ")?,
-                    |arg| => {
-                        arg.pp(w, indent)?
-                    }
-                };
-                write!(w, "): (")?;
+                write!(w, "_externcall (\"{}\".\"{}\": (", module, fun)?;
                 inter_iter! {
                     &argty,
                     write!(w, ", ")?,
@@ -148,6 +267,15 @@ impl<Ty: PP, DE: PP, DS: PP> PP for Expr<Ty, DE, DS> {
                 };
                 write!(w, ") -> ")?;
                 retty.pp(w, indent)?;
+                write!(w, ") (")?;
+                inter_iter! {
+                    &args,
+                    write!(w, ", ")?,
+                    |arg| => {
+                        arg.pp(w, indent)?
+                    }
+                };
+                write!(w, ")")?;
             }
             Fn { body, param } => {
                 write!(w, "fn ")?;
@@ -156,22 +284,16 @@ impl<Ty: PP, DE: PP, DS: PP> PP for Expr<Ty, DE, DS> {
                 body.pp(w, indent + 4)?;
             }
             App { fun, arg } => {
-                write!(w, "(")?;
-                fun.pp(w, indent)?;
-                write!(w, ") ")?;
-                arg.pp(w, indent + 4)?;
+                pp_as(&**fun, w, indent, Shape::App)?;
+                write!(w, " ")?;
+                pp_as(&**arg, w, indent + 4, Shape::Closed)?;
             }
             Case { cond, clauses } => {
                 let ind = Self::nspaces(indent);
                 write!(w, "case ")?;
                 cond.pp(w, indent + 4)?;
-                write!(w, " of")?;
-                for (pat, arm) in clauses {
-                    write!(w, "\n{}", ind)?;
-                    pat.pp(w, indent + 4)?;
-                    write!(w, " => ")?;
-                    arm.pp(w, indent + 4)?;
-                }
+                write!(w, " of\n{}    ", ind)?;
+                pp_clauses(clauses, w, indent)?;
             }
             Tuple { tuple } => {
                 write!(w, "(")?;
@@ -185,13 +307,13 @@ impl<Ty: PP, DE: PP, DS: PP> PP for Expr<Ty, DE, DS> {
                 write!(w, ")")?;
             }
             Symbol { name } => {
-                name.pp(w, indent)?;
+                pp_op(name, w, indent)?;
             }
             Constructor { name, arg } => {
                 name.pp(w, indent)?;
                 if let Some(arg) = arg {
                     write!(w, " ")?;
-                    arg.pp(w, indent)?;
+                    pp_as(&**arg, w, indent, Shape::Closed)?;
                 }
             }
             Literal { value } => {
@@ -205,6 +327,46 @@ impl<Ty: PP, DE: PP, DS: PP> PP for Expr<Ty, DE, DS> {
     }
 }
 
+/// `pat => expr | pat => expr ...` of `case` and `handle`
+fn pp_clauses<Ty, E: PP + HasShape, W: io::Write>(
+    clauses: &[(Pattern<Ty>, E)],
+    w: &mut W,
+    indent: usize,
+) -> io::Result<()> {
+    let clauses = clauses
+        .iter()
+        .map(|(pat, arm)| (pat, None, arm))
+        .collect::<Vec<_>>();
+    pp_guarded_clauses(&clauses, w, indent)
+}
+
+/// `pat when expr => expr | pat => expr ...` of the guarded `case`
+fn pp_guarded_clauses<Ty, E: PP + HasShape, W: io::Write>(
+    clauses: &[(&Pattern<Ty>, Option<&E>, &E)],
+    w: &mut W,
+    indent: usize,
+) -> io::Result<()> {
+    for (i, &(pat, guard, arm)) in clauses.iter().enumerate() {
+        if i != 0 {
+            write!(w, "\n{}  | ", E::nspaces(indent))?;
+        }
+        pat.pp(w, indent + 4)?;
+        if let Some(guard) = guard {
+            write!(w, " when ")?;
+            pp_as(guard, w, indent + 4, Shape::App)?;
+        }
+        write!(w, " => ")?;
+        // the following clauses would be taken as the part of the arm
+        let shape = if i + 1 == clauses.len() {
+            Shape::Open
+        } else {
+            Shape::App
+        };
+        pp_as(arm, w, indent + 4, shape)?;
+    }
+    Ok(())
+}
+
 impl<Ty: PP> PP for DerivedExprKind<Ty> {
     fn pp<W: io::Write>(&self, w: &mut W, indent: usize) -> io::Result<()> {
         use DerivedExprKind::*;
@@ -220,6 +382,23 @@ impl<Ty: PP> PP for DerivedExprKind<Ty> {
                 write!(w, "\n{}else ", ind)?;
                 else_.pp(w, indent + 4)?;
             }
+            Handle { expr, clauses } => {
+                let ind = Self::nspaces(indent);
+                pp_as(&**expr, w, indent, Shape::App)?;
+                write!(w, "\n{}handle ", ind)?;
+                pp_clauses(clauses, w, indent)?;
+            }
+            GuardedCase { cond, clauses } => {
+                let ind = Self::nspaces(indent);
+                write!(w, "case ")?;
+                cond.pp(w, indent + 4)?;
+                write!(w, " of\n{}    ", ind)?;
+                let clauses = clauses
+                    .iter()
+                    .map(|(pat, guard, arm)| (pat, guard.as_ref(), arm))
+                    .collect::<Vec<_>>();
+                pp_guarded_clauses(&clauses, w, indent)?;
+            }
         }
         Ok(())
     }
@@ -231,18 +410,31 @@ impl PP for Nothing {
     }
 }
 
+impl<Ty> Pattern<Ty> {
+    /// prints the pattern, parenthesized unless it's an atomic pattern
+    fn pp_atomic<W: io::Write>(&self, w: &mut W, indent: usize) -> io::Result<()> {
+        match &self.inner {
+            PatternKind::Constructor { arg: Some(_), .. } => {
+                write!(w, "(")?;
+                self.pp(w, indent)?;
+                write!(w, ")")
+            }
+            _ => self.pp(w, indent),
+        }
+    }
+}
+
 impl<Ty> PP for Pattern<Ty> {
     fn pp<W: io::Write>(&self, w: &mut W, indent: usize) -> io::Result<()> {
         use PatternKind::*;
         match &self.inner {
             Constant { value, .. } => write!(w, "{}", value),
-            Char { value } => write!(w, r##"#"{}""##, value),
+            Char { value } => crate::prim::Literal::Char(*value).pp(w, indent),
             Constructor { name, arg, .. } => {
                 name.pp(w, indent)?;
                 if let Some(arg) = arg {
-                    // TODO: handle cases when its in function args
                     write!(w, " ")?;
-                    arg.pp(w, indent)?;
+                    arg.pp_atomic(w, indent)?;
                 }
 
                 Ok(())
@@ -260,6 +452,36 @@ impl<Ty> PP for Pattern<Ty> {
             }
             Variable { name, .. } => name.pp(w, indent),
             Wildcard { .. } => write!(w, "_"),
+            Or { patterns } => {
+                write!(w, "(")?;
+                inter_iter! {
+                    patterns.iter(),
+                    write!(w, " | ")?,
+                    |pat| => {
+                        pat.pp(w, indent)?
+                    }
+                }
+                write!(w, ")")
+            }
+        }
+    }
+}
+
+impl Type {
+    /// prints the type, parenthesized if it's a function or a tuple type
+    fn pp_atomic<W: io::Write>(&self, w: &mut W, indent: usize) -> io::Result<()> {
+        match self {
+            Type::Fun(..) => {
+                write!(w, "(")?;
+                self.pp(w, indent)?;
+                write!(w, ")")
+            }
+            Type::Tuple(tys) if !tys.is_empty() => {
+                write!(w, "(")?;
+                self.pp(w, indent)?;
+                write!(w, ")")
+            }
+            _ => self.pp(w, indent),
         }
     }
 }
@@ -271,19 +493,26 @@ impl PP for Type {
             Variable(id) => write!(w, "'{}", id)?,
             Char => write!(w, "char")?,
             Int => write!(w, "int")?,
-            Real => write!(w, "float")?,
+            Word => write!(w, "word")?,
+            Real => write!(w, "real")?,
+            String => write!(w, "string")?,
             Fun(t1, t2) => {
-                t1.pp(w, indent)?;
+                match **t1 {
+                    Fun(..) => t1.pp_atomic(w, indent)?,
+                    _ => t1.pp(w, indent)?,
+                }
                 write!(w, " -> ")?;
                 t2.pp(w, indent)?;
             }
+            Tuple(tys) if tys.is_empty() => write!(w, "unit")?,
             Tuple(tys) => {
-                write!(w, "(")?;
-                for ty in tys.iter() {
-                    ty.pp(w, indent)?;
-                    write!(w, ", ")?;
+                inter_iter! {
+                    tys.iter(),
+                    write!(w, " * ")?,
+                    |ty| => {
+                        ty.pp_atomic(w, indent)?
+                    }
                 }
-                write!(w, ")")?;
             }
             Datatype(name) => name.pp(w, indent)?,
         }
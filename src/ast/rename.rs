@@ -6,16 +6,30 @@ use crate::pass::Pass;
 use crate::prim::*;
 use std::collections::HashMap;
 use std::ops::{Deref, DerefMut, Drop};
+use std::sync::OnceLock;
 
 pub struct Rename {
     symbol_table: Option<SymbolTable>,
-    variable_tables: Vec<HashMap<Symbol, u64>>,
-    type_tables: Vec<HashMap<Symbol, u64>>,
-    constructor_tables: Vec<HashMap<Symbol, u64>>,
-    pos: usize,
+    /// the ids of the names in scope, the innermost last.
+    /// the builtins aren't bound, and keep the id 0 the parser gives
+    variables: HashMap<Symbol, Vec<u64>>,
+    types: HashMap<Symbol, Vec<u64>>,
+    constructors: HashMap<Symbol, Vec<u64>>,
+    /// the names bound in each of the scopes entered, to unbind when leaving it
+    bound: Vec<Vec<(Namespace, Symbol)>>,
     id: Id,
 }
 
+#[derive(Clone, Copy)]
+enum Namespace {
+    Variable,
+    Type,
+    Constructor,
+}
+
+/// the constructors of `bool` and the exceptions raised by the generated code, which are builtin
+static BUILTIN_CONSTRUCTORS: &[&str] = &["false", "true", "Overflow", "Div"];
+
 struct Scope<'a>(&'a mut Rename);
 
 impl<'a> Deref for Scope<'a> {
@@ -33,24 +47,20 @@ impl<'a> DerefMut for Scope<'a> {
 
 impl<'a> Drop for Scope<'a> {
     fn drop(&mut self) {
-        self.pos -= 1;
+        let bound = self.bound.pop().expect("internal error: scope");
+        for (namespace, symbol) in bound {
+            let ids = self.table(namespace).get_mut(&symbol).unwrap();
+            ids.pop();
+            if ids.is_empty() {
+                self.table(namespace).remove(&symbol);
+            }
+        }
     }
 }
 
 impl<'a> Scope<'a> {
     fn new(inner: &'a mut Rename) -> Self {
-        let pos = inner.pos;
-        if inner.variable_tables.len() <= pos {
-            inner.variable_tables.push(HashMap::new());
-            inner.type_tables.push(HashMap::new());
-            inner.constructor_tables.push(HashMap::new());
-        } else {
-            inner.variable_tables[pos].clear();
-            inner.type_tables[pos].clear();
-            inner.constructor_tables[pos].clear();
-        }
-
-        inner.pos += 1;
+        inner.bound.push(Vec::new());
         Scope(inner)
     }
 
@@ -58,66 +68,52 @@ impl<'a> Scope<'a> {
         Scope::new(self)
     }
 
-    fn new_variable(&mut self, symbol: &mut Symbol) {
-        let pos = self.pos - 1;
+    fn bind(&mut self, namespace: Namespace, symbol: &mut Symbol) {
         let new_id = self.id.next();
-        self.variable_tables[pos].insert(symbol.clone(), new_id);
+        self.table(namespace)
+            .entry(*symbol)
+            .or_insert_with(Vec::new)
+            .push(new_id);
+        self.bound.last_mut().unwrap().push((namespace, *symbol));
         symbol.1 = new_id;
     }
 
+    fn new_variable(&mut self, symbol: &mut Symbol) {
+        self.bind(Namespace::Variable, symbol)
+    }
+
     fn new_type(&mut self, symbol: &mut Symbol) {
-        let pos = self.pos - 1;
-        let new_id = self.id.next();
-        self.type_tables[pos].insert(symbol.clone(), new_id);
-        symbol.1 = new_id;
+        self.bind(Namespace::Type, symbol)
     }
 
     fn new_constructor(&mut self, symbol: &mut Symbol) {
-        let pos = self.pos - 1;
-        let new_id = self.id.next();
-        self.constructor_tables[pos].insert(symbol.clone(), new_id);
-        symbol.1 = new_id;
+        self.bind(Namespace::Constructor, symbol)
     }
 
     fn is_constructor(&mut self, symbol: &Symbol) -> bool {
-        let pos = self.pos;
-        self.constructor_tables[0..pos]
-            .iter_mut()
-            .rev()
-            .any(|t| t.contains_key(symbol))
+        self.constructors.contains_key(symbol)
+            || (symbol.1 == 0 && BUILTIN_CONSTRUCTORS.contains(&symbol.0.as_str()))
     }
 
-    fn rename(&mut self, symbol: &mut Symbol) {
-        let pos = self.pos;
-        for table in self.variable_tables[0..pos].iter_mut().rev() {
-            match table.get(symbol) {
-                Some(new_id) => {
-                    symbol.1 = *new_id;
-                    return;
-                }
-                None => {}
-            }
+    fn resolve(&mut self, namespace: Namespace, symbol: &mut Symbol) {
+        if let Some(new_id) = self.table(namespace).get(symbol).and_then(|ids| ids.last()) {
+            symbol.1 = *new_id;
         }
     }
 
+    fn rename(&mut self, symbol: &mut Symbol) {
+        self.resolve(Namespace::Variable, symbol)
+    }
+
     fn rename_constructor(&mut self, symbol: &mut Symbol) {
-        let pos = self.pos;
-        for table in self.constructor_tables[0..pos].iter_mut().rev() {
-            match table.get(symbol) {
-                Some(new_id) => {
-                    symbol.1 = *new_id;
-                    return;
-                }
-                None => {}
-            }
-        }
+        self.resolve(Namespace::Constructor, symbol)
     }
 
     fn rename_type(&mut self, ty: &mut Type) {
         use Type::*;
 
         match ty {
-            Variable(_) | Char | Int | Real => {
+            Variable(_) | Char | Int | Word | Real | String => {
                 // noop
                 ()
             }
@@ -130,18 +126,7 @@ impl<'a> Scope<'a> {
                     self.rename_type(t)
                 }
             }
-            Datatype(name) => {
-                let pos = self.pos;
-                for table in self.type_tables[0..pos].iter_mut().rev() {
-                    match table.get(name) {
-                        Some(new_id) => {
-                            name.1 = *new_id;
-                            return;
-                        }
-                        None => {}
-                    }
-                }
-            }
+            Datatype(name) => self.resolve(Namespace::Type, name),
         }
     }
 }
@@ -164,9 +149,15 @@ impl<'a, Ty: Clone> util::Traverse<Ty> for Scope<'a> {
         let constructor_info = TypeInfo {
             constructors: constructors.clone(),
         };
-        scope
-            .symbol_table()
-            .register_type(name.clone(), constructor_info);
+        scope.symbol_table().register_type(*name, constructor_info);
+    }
+
+    fn traverse_exception(&mut self, name: &mut Symbol, arg: &mut Option<Type>) {
+        self.new_constructor(name);
+        if let Some(arg) = arg {
+            self.rename_type(arg);
+        }
+        self.symbol_table().register_exception(*name, arg.clone());
     }
 
     fn traverse_val<'b, 'c>(
@@ -244,6 +235,47 @@ impl<'a, Ty: Clone> util::Traverse<Ty> for Scope<'a> {
             self.traverse_pattern(pat)
         }
     }
+
+    // the alternatives bind the variables of the first one.
+    // the typing rejects the alternatives binding the others
+    fn traverse_pat_or(&mut self, patterns: &mut Vec<Pattern<Ty>>) {
+        let (first, rest) = patterns
+            .split_first_mut()
+            .expect("internal error: or-pattern");
+        self.traverse_pattern(first);
+        let ids = first
+            .binds()
+            .into_iter()
+            .map(|(name, _)| (name.0, name.1))
+            .collect::<HashMap<_, _>>();
+        for pat in rest {
+            self.new_scope().traverse_pattern(pat);
+            rebind_variables(pat, &ids);
+        }
+    }
+}
+
+/// gives the variables of the pattern the ids of the same names
+fn rebind_variables<Ty>(pattern: &mut Pattern<Ty>, ids: &HashMap<Name, u64>) {
+    use PatternKind::*;
+    match &mut pattern.inner {
+        Constant { .. } | Char { .. } | Wildcard {} => (),
+        Constructor { arg, .. } => {
+            if let Some(arg) = arg {
+                rebind_variables(arg, ids)
+            }
+        }
+        Tuple { tuple: patterns } | Or { patterns } => {
+            for pat in patterns {
+                rebind_variables(pat, ids)
+            }
+        }
+        Variable { name } => {
+            if let Some(id) = ids.get(&name.0) {
+                name.1 = *id
+            }
+        }
+    }
 }
 
 static BUILTIN_FUNCTIONS: &[(&str, BIF)] = &[
@@ -259,21 +291,25 @@ static BUILTIN_FUNCTIONS: &[(&str, BIF)] = &[
     (">=", BIF::Ge),
     ("<", BIF::Lt),
     ("<=", BIF::Le),
+    ("hash", BIF::Hash),
 ];
 
-impl Rename {
-    pub fn new(id: Id) -> Self {
-        // leave built in functions as non_renamed
-        let functions = BUILTIN_FUNCTIONS
+/// the builtin functions by their names.
+/// built once and shared by all the compilations, e.g. the snippets of a REPL session
+fn builtin_functions() -> &'static HashMap<Name, BIF> {
+    static TABLE: OnceLock<HashMap<Name, BIF>> = OnceLock::new();
+    TABLE.get_or_init(|| {
+        BUILTIN_FUNCTIONS
             .iter()
-            .map(|(s, _)| (Symbol::new(*s), 0))
-            .collect();
-        let datatypes = ["bool"].iter().map(|s| (Symbol::new(*s), 0)).collect();
-        let constructors = ["false", "true"]
-            .iter()
-            .map(|s| (Symbol::new(*s), 0))
-            .collect();
+            .map(|(s, bif)| (Name::new(s), *bif))
+            .collect()
+    })
+}
 
+/// the symbol table of the builtin types, which each compilation starts from
+fn builtin_symbol_table() -> &'static SymbolTable {
+    static TABLE: OnceLock<SymbolTable> = OnceLock::new();
+    TABLE.get_or_init(|| {
         let mut symbol_table = SymbolTable::new();
         symbol_table.register_type(
             Symbol::new("bool"),
@@ -281,17 +317,38 @@ impl Rename {
                 constructors: vec![(Symbol::new("false"), None), (Symbol::new("true"), None)],
             },
         );
+        // `Overflow` and `Div` are raised by the checked arithmetic.
+        // the others are added by the `exception` declarations
+        symbol_table.register_type(
+            Symbol::new("exn"),
+            TypeInfo {
+                constructors: vec![(Symbol::new("Overflow"), None), (Symbol::new("Div"), None)],
+            },
+        );
+        symbol_table
+    })
+}
 
+impl Rename {
+    pub fn new(id: Id) -> Self {
         Rename {
-            symbol_table: Some(symbol_table),
-            variable_tables: vec![functions],
-            type_tables: vec![datatypes],
-            constructor_tables: vec![constructors],
-            pos: 0,
+            symbol_table: Some(builtin_symbol_table().clone()),
+            variables: HashMap::new(),
+            types: HashMap::new(),
+            constructors: HashMap::new(),
+            bound: Vec::new(),
             id,
         }
     }
 
+    fn table(&mut self, namespace: Namespace) -> &mut HashMap<Symbol, Vec<u64>> {
+        match namespace {
+            Namespace::Variable => &mut self.variables,
+            Namespace::Type => &mut self.types,
+            Namespace::Constructor => &mut self.constructors,
+        }
+    }
+
     fn symbol_table(&mut self) -> &mut SymbolTable {
         self.symbol_table.as_mut().unwrap()
     }
@@ -307,21 +364,18 @@ impl Rename {
 
 // bif -> fn x => _builtincall "bif"(x)
 struct WrapBIF {
-    bif_table: HashMap<String, BIF>,
+    bif_table: &'static HashMap<Name, BIF>,
     id: Id,
 }
 impl WrapBIF {
     fn new(id: Id) -> Self {
         Self {
-            bif_table: BUILTIN_FUNCTIONS
-                .iter()
-                .map(|(s, bif)| (s.to_string(), *bif))
-                .collect(),
+            bif_table: builtin_functions(),
             id,
         }
     }
 
-    fn gensym(&mut self, name: impl Into<String>) -> Symbol {
+    fn gensym(&mut self, name: impl Into<Name>) -> Symbol {
         let id = self.id.next();
         Symbol(name.into(), id)
     }
@@ -339,7 +393,7 @@ impl Transform<()> for WrapBIF {
                         let r = self.gensym("y");
                         // fn tuple => case tuple of (x, y) => _builtincall "op"(x, y)
                         ExprKind::Fn {
-                            param: tuple.clone(),
+                            param: tuple,
                             body: Expr {
                                 ty: (),
                                 inner: ExprKind::Case {
@@ -355,15 +409,11 @@ impl Transform<()> for WrapBIF {
                                                 tuple: vec![
                                                     Pattern {
                                                         ty: (),
-                                                        inner: PatternKind::Variable {
-                                                            name: l.clone(),
-                                                        },
+                                                        inner: PatternKind::Variable { name: l },
                                                     },
                                                     Pattern {
                                                         ty: (),
-                                                        inner: PatternKind::Variable {
-                                                            name: r.clone(),
-                                                        },
+                                                        inner: PatternKind::Variable { name: r },
                                                     },
                                                 ],
                                             },
@@ -390,11 +440,61 @@ impl Transform<()> for WrapBIF {
                             .boxed(),
                         }
                     }
+                    Hash => {
+                        let x = self.gensym("x");
+                        // fn x => _builtincall "hash"(x)
+                        ExprKind::Fn {
+                            param: x,
+                            body: Expr {
+                                ty: (),
+                                inner: ExprKind::BuiltinCall {
+                                    fun: bif,
+                                    args: vec![Expr {
+                                        ty: (),
+                                        inner: ExprKind::Symbol { name: x },
+                                    }],
+                                },
+                            }
+                            .boxed(),
+                        }
+                    }
+                    Raise | Handle => unreachable!("internal error: raise and handle are keywords"),
                 };
             }
         }
         ExprKind::Symbol { name }
     }
+
+    // `x op y` calls the builtin function directly, without the wrapper, so that the
+    // operations are in the functions using them
+    fn transform_app(
+        &mut self,
+        fun: Box<UntypedCoreExpr>,
+        arg: Box<UntypedCoreExpr>,
+    ) -> UntypedCoreExprKind {
+        let bif = match &fun.inner {
+            ExprKind::Symbol { name } if name.1 == 0 => self.bif_table.get(&name.0).cloned(),
+            _ => None,
+        };
+        match (bif, arg.inner) {
+            // `hash` takes the value itself
+            (Some(BIF::Hash), inner) => ExprKind::BuiltinCall {
+                fun: BIF::Hash,
+                args: vec![self.transform_expr(Expr { ty: (), inner })],
+            },
+            (Some(bif), ExprKind::Tuple { tuple }) if tuple.len() == 2 => ExprKind::BuiltinCall {
+                fun: bif,
+                args: tuple
+                    .into_iter()
+                    .map(|arg| self.transform_expr(arg))
+                    .collect(),
+            },
+            (_, inner) => ExprKind::App {
+                fun: self.transform_expr(*fun).boxed(),
+                arg: self.transform_expr(Expr { ty: (), inner }).boxed(),
+            },
+        }
+    }
 }
 
 impl<E> Pass<UntypedCore, E> for Rename {
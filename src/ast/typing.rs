@@ -3,16 +3,32 @@ use crate::config::Config;
 use crate::id::Id;
 use crate::prim::*;
 use crate::unification_pool::{NodeId, UnificationPool};
-use std::collections::HashMap;
+use crate::util::ensure_stack;
+use std::collections::{HashMap, HashSet};
+use tracing::trace;
 
 #[derive(Debug)]
-pub struct Typer;
+pub struct Typer {
+    id: Id,
+}
 
+/// the environment generating the constraints from the program.
+/// the constraints of a top level declaration are solved after it is traversed
 #[derive(Debug)]
 struct TyEnv {
     env: HashMap<Symbol, NodeId>,
     symbol_table: SymbolTable,
     pool: TypePool,
+    constraints: Vec<Constraint>,
+}
+
+/// a constraint on the types, which the solver unifies
+#[derive(Debug)]
+enum Constraint {
+    /// the types are the same
+    Equal(NodeId, NodeId),
+    /// the type admits equality, which is checked after the types are unified
+    Equality(NodeId),
 }
 
 #[derive(Debug)]
@@ -27,12 +43,16 @@ enum Typing {
     Variable(u64),
     Char,
     Int,
+    Word,
     Real,
+    String,
     Fun(NodeId, NodeId),
     Tuple(Vec<NodeId>),
     Datatype(Symbol),
     OverloadedNum,
     OverloadedNumText,
+    /// the types `=` takes, which are checked by `Constraint::Equality`
+    OverloadedEq,
 }
 
 fn resolve(pool: &UnificationPool<Typing>, id: NodeId) -> Type {
@@ -45,7 +65,9 @@ fn conv_ty(pool: &UnificationPool<Typing>, ty: Typing) -> Type {
         Variable(id) => Type::Variable(id),
         Char => Type::Char,
         Int => Type::Int,
+        Word => Type::Word,
         Real => Type::Real,
+        String => Type::String,
         Fun(param, body) => Type::Fun(
             Box::new(resolve(pool, param)),
             Box::new(resolve(pool, body)),
@@ -54,6 +76,7 @@ fn conv_ty(pool: &UnificationPool<Typing>, ty: Typing) -> Type {
         Datatype(type_id) => Type::Datatype(type_id),
         OverloadedNum => Type::Int,
         OverloadedNumText => Type::Int,
+        OverloadedEq => Type::Int,
     }
 }
 
@@ -67,13 +90,17 @@ fn try_unify<'b, 'r>(
         (t1, t2) if t1 == t2 => Ok(t1),
         (Int, OverloadedNum) | (OverloadedNum, Int) => Ok(Int),
         (Int, OverloadedNumText) | (OverloadedNumText, Int) => Ok(Int),
+        (Word, OverloadedNum) | (OverloadedNum, Word) => Ok(Word),
+        (Word, OverloadedNumText) | (OverloadedNumText, Word) => Ok(Word),
         (Char, OverloadedNumText) | (OverloadedNumText, Char) => Ok(Char),
+        (String, OverloadedNumText) | (OverloadedNumText, String) => Ok(String),
         (Real, OverloadedNum) | (OverloadedNum, Real) => Ok(Real),
         (Real, OverloadedNumText) | (OverloadedNumText, Real) => Ok(Real),
         (OverloadedNumText, OverloadedNum) | (OverloadedNum, OverloadedNumText) => {
             Ok(OverloadedNumText)
         }
         (Variable(_), ty) | (ty, Variable(_)) => Ok(ty),
+        (OverloadedEq, ty) | (ty, OverloadedEq) => Ok(ty),
         (Fun(p1, b1), Fun(p2, b2)) => {
             let p = pool.try_unify_with(p1, p2, try_unify)?;
             let b = pool.try_unify_with(b1, b2, try_unify)?;
@@ -102,21 +129,21 @@ fn try_unify<'b, 'r>(
 }
 
 impl Typer {
-    pub fn new() -> Self {
-        Typer
+    pub fn new(id: Id) -> Self {
+        Typer { id }
     }
 
     fn generate_pass(&mut self, symbol_table: SymbolTable) -> TyEnv {
-        TyEnv::new(symbol_table)
+        TyEnv::new(symbol_table, self.id.clone())
     }
 }
 
 impl TypePool {
-    fn new() -> Self {
+    fn new(id: Id) -> Self {
         let mut ret = Self {
             cache: HashMap::new(),
             pool: UnificationPool::new(),
-            id: Id::new(),
+            id,
         };
         ret.init();
         ret
@@ -125,12 +152,14 @@ impl TypePool {
     fn init(&mut self) {
         self.node_new(Typing::Char);
         self.node_new(Typing::Int);
+        self.node_new(Typing::Word);
         self.node_new(Typing::Real);
+        self.node_new(Typing::String);
     }
 
     fn feed_symbol_table(&mut self, symbol_table: &SymbolTable) {
         for typename in symbol_table.types.keys() {
-            self.node_new(Typing::Datatype(typename.clone()));
+            self.node_new(Typing::Datatype(*typename));
         }
     }
 
@@ -139,7 +168,7 @@ impl TypePool {
     }
 
     fn ty(&mut self, ty: Typing) -> NodeId {
-        self.pool.node_new(ty)
+        self.node_new(ty)
     }
 
     fn ty_int(&mut self) -> NodeId {
@@ -150,6 +179,10 @@ impl TypePool {
         *self.cache.get(&Typing::Char).unwrap()
     }
 
+    fn ty_word(&mut self) -> NodeId {
+        *self.cache.get(&Typing::Word).unwrap()
+    }
+
     fn ty_bool(&mut self) -> NodeId {
         *self
             .cache
@@ -157,10 +190,21 @@ impl TypePool {
             .unwrap()
     }
 
+    fn ty_exn(&mut self) -> NodeId {
+        *self
+            .cache
+            .get(&Typing::Datatype(Symbol::new("exn")))
+            .unwrap()
+    }
+
     fn ty_real(&mut self) -> NodeId {
         *self.cache.get(&Typing::Real).unwrap()
     }
 
+    fn ty_string(&mut self) -> NodeId {
+        *self.cache.get(&Typing::String).unwrap()
+    }
+
     fn ty_overloaded_num(&mut self) -> NodeId {
         self.node_new(Typing::OverloadedNum)
     }
@@ -169,15 +213,37 @@ impl TypePool {
         self.node_new(Typing::OverloadedNumText)
     }
 
+    fn ty_overloaded_eq(&mut self) -> NodeId {
+        self.node_new(Typing::OverloadedEq)
+    }
+
+    /// hash-conses the typing so that structurally equal types share one node,
+    /// which makes the unification of them a lookup.
+    /// variables are fresh by construction, and each overloaded literal needs its own node
     fn node_new(&mut self, t: Typing) -> NodeId {
-        let node_id = self.pool.node_new(t.clone());
+        let t = match t {
+            Typing::Fun(param, body) => {
+                Typing::Fun(self.pool.value_id(param), self.pool.value_id(body))
+            }
+            Typing::Tuple(tys) => {
+                Typing::Tuple(tys.into_iter().map(|ty| self.pool.value_id(ty)).collect())
+            }
+            t => t,
+        };
         match t {
-            t @ Typing::Char | t @ Typing::Int | t @ Typing::Real | t @ Typing::Datatype(_) => {
+            Typing::Variable(_)
+            | Typing::OverloadedNum
+            | Typing::OverloadedNumText
+            | Typing::OverloadedEq => self.pool.node_new(t),
+            t => {
+                if let Some(node_id) = self.cache.get(&t) {
+                    return *node_id;
+                }
+                let node_id = self.pool.node_new(t.clone());
                 self.cache.insert(t, node_id);
+                node_id
             }
-            _ => (), // no cache
         }
-        node_id
     }
 
     fn try_unify_with<'r>(
@@ -203,11 +269,12 @@ impl TypePool {
 }
 
 impl TyEnv {
-    pub fn new(symbol_table: SymbolTable) -> Self {
+    pub fn new(symbol_table: SymbolTable, id: Id) -> Self {
         let mut ret = TyEnv {
             env: HashMap::new(),
             symbol_table: symbol_table,
-            pool: TypePool::new(),
+            pool: TypePool::new(id),
+            constraints: Vec::new(),
         };
         ret.init();
 
@@ -227,7 +294,7 @@ impl TyEnv {
                 .symbol_table
                 .get_datatype_of_constructor(&cname)
                 .expect("internal error: typing");
-            let ty = Type::Datatype(ty.clone());
+            let ty = Type::Datatype(*ty);
             let typing = self.convert(ty);
             let node_id = self.pool.ty(typing);
             self.insert(cname, node_id);
@@ -260,7 +327,9 @@ impl TyEnv {
             Type::Variable(v) => Typing::Variable(v),
             Type::Char => Typing::Char,
             Type::Int => Typing::Int,
+            Type::Word => Typing::Word,
             Type::Real => Typing::Real,
+            Type::String => Typing::String,
             Type::Fun(arg, ret) => {
                 let arg_typing = self.convert(*arg);
                 let ret_typing = self.convert(*ret);
@@ -284,6 +353,7 @@ impl TyEnv {
     fn infer_ast<'b, 'r>(&'b mut self, ast: &Core<NodeId>) -> Result<'r, ()> {
         for decl in ast.0.iter() {
             self.infer_statement(&decl)?;
+            self.solve()?;
         }
         Ok(())
     }
@@ -291,20 +361,20 @@ impl TyEnv {
     fn infer_statement<'b, 'r>(&'b mut self, decl: &CoreDeclaration<NodeId>) -> Result<'r, ()> {
         use Declaration::*;
         match decl {
-            Datatype { .. } => Ok(()),
+            Datatype { .. } | Exception { .. } => Ok(()),
             Val { rec, pattern, expr } => {
                 let names = pattern.binds();
                 if *rec {
                     for &(name, ty) in &names {
-                        self.insert(name.clone(), ty.clone());
+                        self.insert(*name, ty.clone());
                     }
                 }
                 self.infer_expr(expr)?;
                 self.infer_pat(pattern)?;
-                self.unify(expr.ty(), pattern.ty())?;
+                self.unify(expr.ty(), pattern.ty());
                 if !rec {
                     for &(name, ty) in &names {
-                        self.insert(name.clone(), ty.clone());
+                        self.insert(*name, ty.clone());
                     }
                 }
                 Ok(())
@@ -314,128 +384,177 @@ impl TyEnv {
     }
 
     fn infer_expr<'b, 'r>(&'b mut self, expr: &CoreExpr<NodeId>) -> Result<'r, ()> {
-        use crate::ast::ExprKind::*;
-        let int = self.pool.ty_int();
-        let real = self.pool.ty_real();
-        let bool = self.pool.ty_bool();
-        let overloaded_num = self.pool.ty_overloaded_num();
-        let overloaded_num_text = self.pool.ty_overloaded_num_text();
-        let ty = &expr.ty;
-        match &expr.inner {
-            Binds { binds, ret } => {
-                for decl in binds {
-                    self.infer_statement(decl)?;
-                }
-                self.unify(ret.ty(), *ty)?;
-                self.infer_expr(ret)?;
-                Ok(())
-            }
-            BuiltinCall { fun, args } => {
-                use BIF::*;
-                match fun {
-                    Add | Sub | Mul => {
-                        assert!(args.len() == 2);
-                        let l = &args[0];
-                        let r = &args[1];
-
-                        self.infer_expr(l)?;
-                        self.infer_expr(r)?;
-                        self.unify(l.ty(), r.ty())?;
-                        self.unify(l.ty(), overloaded_num)?;
-                        self.unify(*ty, l.ty())?;
-                        Ok(())
+        ensure_stack(|| {
+            use crate::ast::ExprKind::*;
+            let int = self.pool.ty_int();
+            let real = self.pool.ty_real();
+            let word = self.pool.ty_word();
+            let bool = self.pool.ty_bool();
+            let overloaded_num = self.pool.ty_overloaded_num();
+            let overloaded_num_text = self.pool.ty_overloaded_num_text();
+            let overloaded_eq = self.pool.ty_overloaded_eq();
+            let ty = &expr.ty;
+            match &expr.inner {
+                Binds { binds, ret } => {
+                    for decl in binds {
+                        self.infer_statement(decl)?;
                     }
-                    Eq | Neq | Gt | Ge | Lt | Le => {
-                        assert!(args.len() == 2);
-                        let l = &args[0];
-                        let r = &args[1];
-
-                        self.infer_expr(l)?;
-                        self.infer_expr(r)?;
-                        self.unify(l.ty(), r.ty())?;
-                        self.unify(l.ty(), overloaded_num_text)?;
-                        self.unify(*ty, bool)?;
-                        Ok(())
-                    }
-                    Div | Mod => {
-                        assert!(args.len() == 2);
-                        let l = &args[0];
-                        let r = &args[1];
-
-                        self.unify(l.ty(), int)?;
-                        self.unify(r.ty(), int)?;
-                        self.unify(*ty, int)?;
-                        self.infer_expr(l)?;
-                        self.infer_expr(r)?;
-                        Ok(())
+                    self.unify(ret.ty(), *ty);
+                    self.infer_expr(ret)?;
+                    Ok(())
+                }
+                BuiltinCall { fun, args } => {
+                    use BIF::*;
+                    match fun {
+                        Add | Sub | Mul => {
+                            assert!(args.len() == 2);
+                            let l = &args[0];
+                            let r = &args[1];
+
+                            self.infer_expr(l)?;
+                            self.infer_expr(r)?;
+                            self.unify(l.ty(), r.ty());
+                            self.unify(l.ty(), overloaded_num);
+                            self.unify(*ty, l.ty());
+                            Ok(())
+                        }
+                        Eq | Neq => {
+                            assert!(args.len() == 2);
+                            let l = &args[0];
+                            let r = &args[1];
+
+                            self.infer_expr(l)?;
+                            self.infer_expr(r)?;
+                            self.unify(l.ty(), r.ty());
+                            self.unify(l.ty(), overloaded_eq);
+                            self.constraints.push(Constraint::Equality(l.ty()));
+                            self.unify(*ty, bool);
+                            Ok(())
+                        }
+                        Gt | Ge | Lt | Le => {
+                            assert!(args.len() == 2);
+                            let l = &args[0];
+                            let r = &args[1];
+
+                            self.infer_expr(l)?;
+                            self.infer_expr(r)?;
+                            self.unify(l.ty(), r.ty());
+                            self.unify(l.ty(), overloaded_num_text);
+                            self.unify(*ty, bool);
+                            Ok(())
+                        }
+                        Div | Mod => {
+                            assert!(args.len() == 2);
+                            let l = &args[0];
+                            let r = &args[1];
+
+                            self.unify(l.ty(), int);
+                            self.unify(r.ty(), int);
+                            self.unify(*ty, int);
+                            self.infer_expr(l)?;
+                            self.infer_expr(r)?;
+                            Ok(())
+                        }
+                        Divf => {
+                            assert!(args.len() == 2);
+                            let l = &args[0];
+                            let r = &args[1];
+
+                            self.unify(l.ty(), real);
+                            self.unify(r.ty(), real);
+                            self.unify(*ty, real);
+                            self.infer_expr(l)?;
+                            self.infer_expr(r)?;
+                            Ok(())
+                        }
+                        Raise => {
+                            assert!(args.len() == 1);
+                            let exn = &args[0];
+
+                            let exn_ty = self.pool.ty_exn();
+                            self.unify(exn.ty(), exn_ty);
+                            self.infer_expr(exn)?;
+                            Ok(())
+                        }
+                        Handle => {
+                            assert!(args.len() == 2);
+                            let body = &args[0];
+                            let handler = &args[1];
+
+                            let unit = self.pool.ty(Typing::Tuple(vec![]));
+                            let exn = self.pool.ty_exn();
+                            self.infer_expr(body)?;
+                            self.infer_expr(handler)?;
+                            self.give(body.ty(), Typing::Fun(unit, *ty));
+                            self.give(handler.ty(), Typing::Fun(exn, *ty));
+                            Ok(())
+                        }
+                        Hash => {
+                            assert!(args.len() == 1);
+                            let arg = &args[0];
+
+                            self.infer_expr(arg)?;
+                            self.unify(arg.ty(), overloaded_eq);
+                            self.constraints.push(Constraint::Equality(arg.ty()));
+                            self.unify(*ty, word);
+                            Ok(())
+                        }
                     }
-                    Divf => {
-                        assert!(args.len() == 2);
-                        let l = &args[0];
-                        let r = &args[1];
-
-                        self.unify(l.ty(), real)?;
-                        self.unify(r.ty(), real)?;
-                        self.unify(*ty, real)?;
-                        self.infer_expr(l)?;
-                        self.infer_expr(r)?;
-                        Ok(())
+                }
+                ExternCall {
+                    args, argty, retty, ..
+                } => {
+                    for (arg, argty) in args.into_iter().zip(argty) {
+                        self.infer_expr(arg)?;
+                        let argty = self.convert(argty.clone());
+                        self.give(arg.ty(), argty);
                     }
+                    let retty = self.convert(retty.clone());
+                    self.give(*ty, retty);
+                    Ok(())
                 }
-            }
-            ExternCall {
-                args, argty, retty, ..
-            } => {
-                for (arg, argty) in args.into_iter().zip(argty) {
+                Fn { param, body } => {
+                    let param_ty = self.pool.tyvar();
+                    self.insert(*param, param_ty);
+                    self.infer_expr(body)?;
+                    self.give(*ty, Typing::Fun(param_ty, body.ty()));
+                    Ok(())
+                }
+                App { fun, arg } => {
+                    self.infer_expr(fun)?;
                     self.infer_expr(arg)?;
-                    let argty = self.convert(argty.clone());
-                    self.give(arg.ty(), argty)?;
+                    self.give(fun.ty(), Typing::Fun(arg.ty(), *ty));
+                    Ok(())
                 }
-                let retty = self.convert(retty.clone());
-                self.give(*ty, retty)?;
-                Ok(())
-            }
-            Fn { param, body } => {
-                let param_ty = self.pool.tyvar();
-                self.insert(param.clone(), param_ty);
-                self.infer_expr(body)?;
-                self.give(*ty, Typing::Fun(param_ty, body.ty()))?;
-                Ok(())
-            }
-            App { fun, arg } => {
-                self.infer_expr(fun)?;
-                self.infer_expr(arg)?;
-                self.give(fun.ty(), Typing::Fun(arg.ty(), *ty))?;
-                Ok(())
-            }
-            Case { cond, clauses } => {
-                self.infer_expr(cond)?;
-                for (pat, branch) in clauses {
-                    self.infer_pat(pat)?;
-                    self.unify(pat.ty(), cond.ty())?;
-                    self.infer_expr(branch)?;
-                    self.unify(branch.ty(), *ty)?;
+                Case { cond, clauses } => {
+                    self.infer_expr(cond)?;
+                    for (pat, branch) in clauses {
+                        self.infer_pat(pat)?;
+                        self.unify(pat.ty(), cond.ty());
+                        self.infer_expr(branch)?;
+                        self.unify(branch.ty(), *ty);
+                    }
+                    Ok(())
                 }
-                Ok(())
-            }
-            Tuple { tuple } => {
-                self.infer_tuple(tuple, *ty)?;
-                Ok(())
-            }
-            Constructor { arg, name } => {
-                self.infer_constructor(name, arg, *ty)?;
-                Ok(())
-            }
-            Symbol { name } => {
-                self.infer_symbol(name, *ty)?;
-                Ok(())
-            }
-            Literal { value } => {
-                self.infer_literal(value, *ty)?;
-                Ok(())
+                Tuple { tuple } => {
+                    self.infer_tuple(tuple, *ty)?;
+                    Ok(())
+                }
+                Constructor { arg, name } => {
+                    self.infer_constructor(name, arg, *ty)?;
+                    Ok(())
+                }
+                Symbol { name } => {
+                    self.infer_symbol(name, *ty)?;
+                    Ok(())
+                }
+                Literal { value } => {
+                    self.infer_literal(value, *ty)?;
+                    Ok(())
+                }
+                D(d) => match *d {},
             }
-            D(d) => match *d {},
-        }
+        })
     }
 
     fn infer_constructor<'b, 'r>(
@@ -446,13 +565,13 @@ impl TyEnv {
     ) -> Result<'r, ()> {
         match self.get(&sym) {
             Some(ty) => {
-                self.unify(ty, given)?;
+                self.unify(ty, given);
                 let arg_ty = self.symbol_table().get_argtype_of_constructor(sym);
                 if let (Some(arg), Some(arg_ty)) = (arg.clone(), arg_ty.cloned()) {
                     self.infer_expr(&arg)?;
                     let arg_typing = self.convert(arg_ty);
                     let arg_ty_id = self.pool.ty(arg_typing);
-                    self.unify(arg.ty(), arg_ty_id)?;
+                    self.unify(arg.ty(), arg_ty_id);
                 }
                 Ok(())
             }
@@ -462,7 +581,10 @@ impl TyEnv {
 
     fn infer_symbol<'b, 'r>(&'b mut self, sym: &Symbol, given: NodeId) -> Result<'r, ()> {
         match self.get(&sym) {
-            Some(t) => self.unify(t, given),
+            Some(t) => {
+                self.unify(t, given);
+                Ok(())
+            }
             None => Err(TypeError::FreeVar),
         }
     }
@@ -473,20 +595,22 @@ impl TyEnv {
             Int(_) => self.pool.ty_int(),
             Real(_) => self.pool.ty_real(),
             Char(_) => self.pool.ty_char(),
+            Word(_) => self.pool.ty_word(),
+            String(_) => self.pool.ty_string(),
         };
-        self.unify(given, ty)?;
+        self.unify(given, ty);
         Ok(())
     }
 
     fn infer_constant<'b, 'r>(&'b mut self, _: &i64, given: NodeId) -> Result<'r, ()> {
         let ty = self.pool.ty_int();
-        self.unify(given, ty)?;
+        self.unify(given, ty);
         Ok(())
     }
 
     fn infer_char<'b, 'r>(&'b mut self, _: &u32, given: NodeId) -> Result<'r, ()> {
         let ty = self.pool.ty_char();
-        self.unify(given, ty)?;
+        self.unify(given, ty);
         Ok(())
     }
 
@@ -501,12 +625,11 @@ impl TyEnv {
                 self.infer_char(value, *ty)?;
             }
             Constructor { arg, name } => {
-                let type_name = self
+                let type_name = *self
                     .symbol_table()
                     .get_datatype_of_constructor(name)
-                    .expect("internal error: typing")
-                    .clone();
-                self.give(*ty, Typing::Datatype(type_name.clone()))?;
+                    .expect("internal error: typing");
+                self.give(*ty, Typing::Datatype(type_name));
                 if let Some(arg) = arg {
                     self.infer_pat(arg)?;
                     let arg_ty = self
@@ -521,7 +644,7 @@ impl TyEnv {
                         .expect("internal error: typing");
                     let arg_typing = self.convert(arg_ty);
                     let arg_ty_id = self.pool.ty(arg_typing);
-                    self.unify(arg.ty(), arg_ty_id)?;
+                    self.unify(arg.ty(), arg_ty_id);
                 }
             }
             Tuple { tuple } => {
@@ -531,12 +654,32 @@ impl TyEnv {
                 let tuple_ty = self
                     .pool
                     .ty(Typing::Tuple(tuple.iter().map(|pat| pat.ty()).collect()));
-                self.unify(*ty, tuple_ty)?;
+                self.unify(*ty, tuple_ty);
+            }
+            Or { patterns } => {
+                for pat in patterns {
+                    self.infer_pat(pat)?;
+                    self.unify(pat.ty(), *ty);
+                }
+                // the alternatives bind the same variables at the same types
+                let binds = patterns[0].binds();
+                for pat in &patterns[1..] {
+                    let other = pat.binds();
+                    if other.len() != binds.len() {
+                        return Err(TypeError::OrPatternBinds);
+                    }
+                    for (name, ty) in other {
+                        match binds.iter().find(|(name1, _)| *name1 == name) {
+                            Some((_, ty1)) => self.unify(**ty1, *ty),
+                            None => return Err(TypeError::OrPatternBinds),
+                        }
+                    }
+                }
             }
             Wildcard { .. } | Variable { .. } => (),
         };
         for (name, ty) in pat.binds() {
-            self.insert(name.clone(), *ty);
+            self.insert(*name, *ty);
         }
         Ok(())
     }
@@ -553,21 +696,70 @@ impl TyEnv {
 
         for (e, t) in tuple.iter().zip(tys.iter()) {
             self.infer_expr(e)?;
-            self.unify(e.ty(), *t)?;
+            self.unify(e.ty(), *t);
         }
         let tuple_ty = self.pool.ty(Typing::Tuple(tys));
-        self.unify(tuple_ty, given)?;
+        self.unify(tuple_ty, given);
         Ok(())
     }
 
-    fn unify<'b, 'r>(&'b mut self, id1: NodeId, id2: NodeId) -> Result<'r, ()> {
-        self.pool.try_unify_with(id1, id2, try_unify).map(|_| ())
+    /// requires the types to be the same, when the constraints are solved
+    fn unify(&mut self, id1: NodeId, id2: NodeId) {
+        self.constraints.push(Constraint::Equal(id1, id2))
     }
 
-    fn give<'b, 'r>(&'b mut self, id1: NodeId, ty: Typing) -> Result<'r, ()> {
+    fn give(&mut self, id1: NodeId, ty: Typing) {
         let id2 = self.pool.node_new(ty);
         self.unify(id1, id2)
     }
+
+    /// unifies the types of the constraints generated so far, in the order generated,
+    /// and then checks the equalities
+    fn solve<'r>(&mut self) -> Result<'r, ()> {
+        trace!(constraints = self.constraints.len(), "solve");
+        let mut equalities = Vec::new();
+        for constraint in std::mem::take(&mut self.constraints) {
+            match constraint {
+                Constraint::Equal(id1, id2) => {
+                    trace!(?id1, ?id2, "unify");
+                    self.pool.try_unify_with(id1, id2, try_unify)?;
+                }
+                Constraint::Equality(id) => equalities.push(id),
+            }
+        }
+        for id in equalities {
+            let ty = resolve(&self.pool.pool, id);
+            if !self.admits_equality(&ty, &mut HashSet::new()) {
+                return Err(TypeError::NotEquality(ty));
+            }
+        }
+        Ok(())
+    }
+
+    /// whether `=` compares the values of the type. the functions and the exceptions are not
+    /// compared, nor the datatypes holding them. `visited` are the datatypes being checked
+    fn admits_equality(&self, ty: &Type, visited: &mut HashSet<Symbol>) -> bool {
+        match ty {
+            Type::Variable(_) | Type::Char | Type::Int | Type::Word | Type::Real | Type::String => {
+                true
+            }
+            Type::Fun(..) => false,
+            Type::Tuple(tys) => tys.iter().all(|ty| self.admits_equality(ty, visited)),
+            Type::Datatype(name) if *name == Symbol::new("exn") => false,
+            Type::Datatype(name) => {
+                if !visited.insert(*name) {
+                    return true;
+                }
+                self.symbol_table()
+                    .get_type(name)
+                    .expect("internal error: typing")
+                    .constructors
+                    .iter()
+                    .filter_map(|(_, arg)| arg.as_ref())
+                    .all(|arg| self.admits_equality(arg, visited))
+            }
+        }
+    }
 }
 
 use crate::pass::Pass;
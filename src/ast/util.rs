@@ -1,4 +1,5 @@
 use crate::ast::*;
+use crate::util::ensure_stack;
 
 pub trait Traverse<Ty> {
     fn traverse_ast(&mut self, ast: &mut Core<Ty>) {
@@ -11,6 +12,7 @@ pub trait Traverse<Ty> {
         use Declaration::*;
         match decl {
             Datatype { name, constructors } => self.traverse_datatype(name, constructors),
+            Exception { name, arg } => self.traverse_exception(name, arg),
             Val { rec, pattern, expr } => self.traverse_val(rec, pattern, expr),
             D(_) => (),
         }
@@ -23,6 +25,8 @@ pub trait Traverse<Ty> {
     ) {
     }
 
+    fn traverse_exception(&mut self, _name: &mut Symbol, _arg: &mut Option<Type>) {}
+
     fn traverse_val(
         &mut self,
         _rec: &mut bool,
@@ -34,26 +38,28 @@ pub trait Traverse<Ty> {
     }
 
     fn traverse_expr(&mut self, expr: &mut CoreExpr<Ty>) {
-        use crate::ast::ExprKind::*;
-        match &mut expr.inner {
-            Binds { binds, ret } => self.traverse_binds(binds, ret),
-            BuiltinCall { fun, args } => self.traverse_builtincall(fun, args),
-            ExternCall {
-                module,
-                fun,
-                args,
-                argty,
-                retty,
-            } => self.traverse_externcall(module, fun, args, argty, retty),
-            Fn { param, body } => self.traverse_fn(param, body),
-            App { fun, arg } => self.traverse_app(fun, arg),
-            Case { cond, clauses } => self.traverse_case(cond, clauses),
-            Tuple { tuple } => self.traverse_tuple(tuple),
-            Constructor { arg, name } => self.traverse_constructor(arg, name),
-            Symbol { name } => self.traverse_sym(name),
-            Literal { value } => self.traverse_lit(value),
-            D(_) => (),
-        }
+        ensure_stack(|| {
+            use crate::ast::ExprKind::*;
+            match &mut expr.inner {
+                Binds { binds, ret } => self.traverse_binds(binds, ret),
+                BuiltinCall { fun, args } => self.traverse_builtincall(fun, args),
+                ExternCall {
+                    module,
+                    fun,
+                    args,
+                    argty,
+                    retty,
+                } => self.traverse_externcall(module, fun, args, argty, retty),
+                Fn { param, body } => self.traverse_fn(param, body),
+                App { fun, arg } => self.traverse_app(fun, arg),
+                Case { cond, clauses } => self.traverse_case(cond, clauses),
+                Tuple { tuple } => self.traverse_tuple(tuple),
+                Constructor { arg, name } => self.traverse_constructor(arg, name),
+                Symbol { name } => self.traverse_sym(name),
+                Literal { value } => self.traverse_lit(value),
+                D(_) => (),
+            }
+        })
     }
     fn traverse_binds(
         &mut self,
@@ -130,6 +136,7 @@ pub trait Traverse<Ty> {
             Tuple { tuple } => self.traverse_pat_tuple(tuple),
             Variable { name } => self.traverse_pat_variable(name),
             Wildcard {} => self.traverse_pat_wildcard(),
+            Or { patterns } => self.traverse_pat_or(patterns),
         }
     }
 
@@ -144,6 +151,7 @@ pub trait Traverse<Ty> {
     fn traverse_pat_tuple(&mut self, _tuple: &mut Vec<Pattern<Ty>>) {}
     fn traverse_pat_variable(&mut self, _value: &mut Symbol) {}
     fn traverse_pat_wildcard(&mut self) {}
+    fn traverse_pat_or(&mut self, _patterns: &mut Vec<Pattern<Ty>>) {}
 }
 
 pub trait Transform<Ty> {
@@ -159,6 +167,7 @@ pub trait Transform<Ty> {
         use Declaration::*;
         match decl {
             Datatype { name, constructors } => self.transform_datatype(name, constructors),
+            Exception { name, arg } => self.transform_exception(name, arg),
             Val { rec, pattern, expr } => self.transform_val(rec, pattern, expr),
             D(d) => match d {},
         }
@@ -172,6 +181,10 @@ pub trait Transform<Ty> {
         Declaration::Datatype { name, constructors }
     }
 
+    fn transform_exception(&mut self, name: Symbol, arg: Option<Type>) -> CoreDeclaration<Ty> {
+        Declaration::Exception { name, arg }
+    }
+
     fn transform_val(
         &mut self,
         rec: bool,
@@ -186,27 +199,29 @@ pub trait Transform<Ty> {
     }
 
     fn transform_expr(&mut self, mut expr: CoreExpr<Ty>) -> CoreExpr<Ty> {
-        use crate::ast::ExprKind::*;
-        expr.inner = match expr.inner {
-            Binds { binds, ret } => self.transform_binds(binds, ret),
-            BuiltinCall { fun, args } => self.transform_builtincall(fun, args),
-            ExternCall {
-                module,
-                fun,
-                args,
-                argty,
-                retty,
-            } => self.transform_externcall(module, fun, args, argty, retty),
-            Fn { param, body } => self.transform_fn(param, body),
-            App { fun, arg } => self.transform_app(fun, arg),
-            Case { cond, clauses } => self.transform_case(cond, clauses),
-            Tuple { tuple } => self.transform_tuple(tuple),
-            Constructor { arg, name } => self.transform_constructor(arg, name),
-            Symbol { name } => self.transform_symbol(name),
-            Literal { value } => self.transform_literal(value),
-            D(d) => match d {},
-        };
-        expr
+        ensure_stack(|| {
+            use crate::ast::ExprKind::*;
+            expr.inner = match expr.inner {
+                Binds { binds, ret } => self.transform_binds(binds, ret),
+                BuiltinCall { fun, args } => self.transform_builtincall(fun, args),
+                ExternCall {
+                    module,
+                    fun,
+                    args,
+                    argty,
+                    retty,
+                } => self.transform_externcall(module, fun, args, argty, retty),
+                Fn { param, body } => self.transform_fn(param, body),
+                App { fun, arg } => self.transform_app(fun, arg),
+                Case { cond, clauses } => self.transform_case(cond, clauses),
+                Tuple { tuple } => self.transform_tuple(tuple),
+                Constructor { arg, name } => self.transform_constructor(arg, name),
+                Symbol { name } => self.transform_symbol(name),
+                Literal { value } => self.transform_literal(value),
+                D(d) => match d {},
+            };
+            expr
+        })
     }
     fn transform_binds(
         &mut self,
@@ -317,6 +332,7 @@ pub trait Transform<Ty> {
             Tuple { tuple } => self.transform_pat_tuple(tuple),
             Variable { name } => self.transform_pat_variable(name),
             Wildcard {} => self.transform_pat_wildcard(),
+            Or { patterns } => self.transform_pat_or(patterns),
         };
         pattern
     }
@@ -356,4 +372,13 @@ pub trait Transform<Ty> {
     fn transform_pat_wildcard(&mut self) -> PatternKind<Ty> {
         PatternKind::Wildcard {}
     }
+
+    fn transform_pat_or(&mut self, patterns: Vec<Pattern<Ty>>) -> PatternKind<Ty> {
+        PatternKind::Or {
+            patterns: patterns
+                .into_iter()
+                .map(|pat| self.transform_pattern(pat))
+                .collect(),
+        }
+    }
 }
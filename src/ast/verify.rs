@@ -0,0 +1,45 @@
+use crate::ast::*;
+use crate::config::Config;
+use crate::pass::Pass;
+
+/// Checks that the typing resolved all the types.
+/// Type variables left after the typing are not supported by the later passes.
+pub struct Verify;
+
+fn has_variable(ty: &Type) -> bool {
+    use self::Type::*;
+    match ty {
+        Variable(_) => true,
+        Char | Int | Word | Real | String | Datatype(_) => false,
+        Fun(param, ret) => has_variable(param) || has_variable(ret),
+        Tuple(tys) => tys.iter().any(has_variable),
+    }
+}
+
+impl Verify {
+    pub fn new() -> Self {
+        Verify
+    }
+}
+
+impl<'a> Pass<(SymbolTable, TypedCore), TypeError<'a>> for Verify {
+    type Target = (SymbolTable, TypedCore);
+
+    fn trans(
+        &mut self,
+        (symbol_table, ast): (SymbolTable, TypedCore),
+        _: &Config,
+    ) -> ::std::result::Result<Self::Target, TypeError<'a>> {
+        let mut unresolved = None;
+        let ast = ast.map_ty(&mut |ty| {
+            if unresolved.is_none() && has_variable(&ty) {
+                unresolved = Some(ty.clone());
+            }
+            ty
+        });
+        match unresolved {
+            Some(ty) => Err(TypeError::InvalidIR(format!("unresolved type {:?}", ty))),
+            None => Ok((symbol_table, ast)),
+        }
+    }
+}
@@ -0,0 +1,562 @@
+//! generator of the JavaScript loader of the module.
+//! it instantiates the runtime and the module, wires up the host functions
+//! and wraps the exported functions so that they take and return JS values.
+
+use crate::backend::wasm::lty_to_valuetype_opt;
+use crate::backend::Interface;
+use crate::lir::{HostTy, HostTypes};
+use std::fmt::Write;
+use wasm::ValueType;
+
+/// where the runtime is built to, relative to the repository root
+pub const RUNTIME_URL: &str = "webml-rt/target/wasm32-unknown-unknown/release/webml_rt.wasm";
+
+fn value_type(ty: &ValueType) -> &'static str {
+    match ty {
+        ValueType::I32 => "\"i32\"",
+        ValueType::I64 => "\"i64\"",
+        ValueType::F32 => "\"f32\"",
+        ValueType::F64 => "\"f64\"",
+    }
+}
+
+fn value_types(tys: &[ValueType]) -> String {
+    let tys = tys.iter().map(value_type).collect::<Vec<_>>();
+    format!("[{}]", tys.join(", "))
+}
+
+/// the strings are `"string"`, and the callbacks the pairs of the types of
+/// their parameters and result
+fn host_type(ty: &HostTy) -> String {
+    match ty {
+        HostTy::String => "\"string\"".to_string(),
+        HostTy::Callback(param, ret) => {
+            // the parameter is passed even if it is unit, as the closures take it
+            let param = lty_to_valuetype_opt(param).unwrap_or(ValueType::I32);
+            let ret = lty_to_valuetype_opt(ret);
+            format!(
+                "[[{}], {}]",
+                value_type(&param),
+                ret.as_ref().map(value_type).unwrap_or("null")
+            )
+        }
+    }
+}
+
+/// the types of the parameters of an import, converted as `host_types` tells
+fn param_types(tys: &[ValueType], host_types: &HostTypes) -> String {
+    let tys = tys
+        .iter()
+        .enumerate()
+        .map(|(i, ty)| {
+            let host_ty = host_types
+                .params
+                .iter()
+                .find(|(index, _)| *index == i as u32);
+            match host_ty {
+                Some((_, host_ty)) => host_type(host_ty),
+                None => value_type(ty).to_string(),
+            }
+        })
+        .collect::<Vec<_>>();
+    format!("[{}]", tys.join(", "))
+}
+
+// the names are ascii identifiers in practice, for which the Debug format is a valid JS literal
+fn string(s: &str) -> String {
+    format!("{:?}", s)
+}
+
+const LOADER: &str = r#"
+// writes the output of the program to the console. console.log ends the line, so the text is
+// printed by the line, and the rest of the line once the program returns to the host
+function consoleOutput() {
+    let line = "";
+    const write = (text) => {
+        const lines = (line + text).split("\n");
+        line = lines.pop();
+        lines.forEach((l) => console.log(l));
+    };
+    const flush = () => {
+        if (line !== "") {
+            console.log(line);
+            line = "";
+        }
+    };
+    return {write, flush};
+}
+
+// the host functions of the prelude, for the runtime instance `rt`, printing to `output`
+function defaultImports(rt, output) {
+    const print = (x) => output.write(`${x}\n`);
+    const printString = (string) => output.write(string);
+    const math = {sin: Math.sin, cos: Math.cos, exp: Math.exp, ln: Math.log, pow: Math.pow};
+    // the seconds of the realtime clock for 0 and of the monotonic clock for 1, as WASI
+    const clock = (id) => (id === 0 ? Date.now() : performance.now()) / 1000;
+    const random_seed = () => crypto.getRandomValues(new Int32Array(1))[0];
+    return {"js-ffi": {print, printString, clock, random_seed, ...math}};
+}
+
+async function load(source) {
+    if (typeof source === "string" || source instanceof URL) {
+        const response = await fetch(source);
+        return response.arrayBuffer();
+    }
+    return source;
+}
+
+// converts a JS value to the wasm value of the type.
+// `char` takes a one-character string, `bool` a boolean and `unit` nothing
+function toWasm(ty, value) {
+    if (typeof value === "string") {
+        value = value.codePointAt(0);
+    }
+    switch (ty) {
+    case null:
+        return undefined;
+    case "i32":
+        return Number(value) | 0;
+    case "i64":
+        return BigInt(value || 0);
+    default:
+        return Number(value);
+    }
+}
+
+// the runtime instance of the exports `instantiate` returned
+const RUNTIMES = new WeakMap();
+
+// releases the slots of the closures whose callbacks the host has dropped
+const CALLBACK_SLOTS = new FinalizationRegistry(({rt, slot}) => rt.callback_release(slot));
+
+// strings are the length in bytes followed by the UTF-8 bytes in the memory of the runtime.
+// copies the JS string into a new string of the runtime of `exports` and returns its address
+export function encodeString(exports, string) {
+    return writeString(RUNTIMES.get(exports), string);
+}
+
+// decodes the string at the address in the runtime of `exports`
+export function decodeString(exports, address) {
+    return readString(RUNTIMES.get(exports), address);
+}
+
+function writeString(rt, string) {
+    const bytes = new TextEncoder().encode(String(string));
+    const address = rt.string_alloc(bytes.length);
+    new Uint8Array(rt.memory.buffer, address + 4, bytes.length).set(bytes);
+    return address;
+}
+
+function readString(rt, address) {
+    const length = new DataView(rt.memory.buffer).getUint32(address, true);
+    return new TextDecoder().decode(new Uint8Array(rt.memory.buffer, address + 4, length));
+}
+
+// the readers of the LEB128 numbers and the names of the custom section, or null without it
+function sectionReader(module, name) {
+    const [section] = WebAssembly.Module.customSections(module, name);
+    if (!section) {
+        return null;
+    }
+    const bytes = new Uint8Array(section);
+    let offset = 0;
+    const u32 = () => {
+        let n = 0;
+        for (let shift = 0; ; shift += 7) {
+            const b = bytes[offset++];
+            n |= (b & 0x7f) << shift;
+            if ((b & 0x80) === 0) {
+                return n >>> 0;
+            }
+        }
+    };
+    const string = () => {
+        const length = u32();
+        offset += length;
+        return new TextDecoder().decode(bytes.subarray(offset - length, offset));
+    };
+    return {u32, name: string};
+}
+
+// the kind of the error and the function of each site in the `webml.errors` section
+function errorSites(module) {
+    const reader = sectionReader(module, "webml.errors");
+    if (!reader) {
+        return [];
+    }
+    const {u32, name} = reader;
+    return Array.from({length: u32()}, () => [name(), name()]);
+}
+
+// the types of the exported functions in the `webml.types` section, by the name.
+// a type is the name of a scalar type, `["tuple", types]` or `["datatype", index]`,
+// and a datatype the list of the names and the argument types of its constructors
+function exportTypes(module) {
+    const types = new Map();
+    const reader = sectionReader(module, "webml.types");
+    if (!reader) {
+        return types;
+    }
+    const {u32, name} = reader;
+    const SCALARS = ["int", "word", "real", "char", "string", "bool"];
+    const type = () => {
+        const tag = u32();
+        if (tag < SCALARS.length) {
+            return SCALARS[tag];
+        }
+        switch (tag) {
+        case 6:
+            return ["tuple", Array.from({length: u32()}, type)];
+        case 7:
+            return ["datatype", u32()];
+        default:
+            return "fun";
+        }
+    };
+    const constructor = () => [name(), u32() === 0 ? null : type()];
+    const datatype = () => [name(), Array.from({length: u32()}, constructor)];
+    for (let i = u32(); i > 0; i--) {
+        const fun = name();
+        const datatypes = Array.from({length: u32()}, datatype);
+        types.set(fun, {datatypes, param: type(), ret: type()});
+    }
+    return types;
+}
+
+// the values of the types take a word of 8 bytes in the tuples and the datatypes.
+// unit takes none, and the others but real are 32-bit
+const isUnit = (ty) => Array.isArray(ty) && ty[0] === "tuple" && ty[1].length === 0;
+const isPointer = (ty) =>
+    !isUnit(ty) && (Array.isArray(ty) || ["string", "bool", "fun"].includes(ty));
+
+function loadField(rt, ty, address) {
+    const view = new DataView(rt.memory.buffer);
+    if (isUnit(ty)) {
+        return undefined;
+    }
+    return ty === "real" ? view.getFloat64(address, true) : view.getUint32(address, true);
+}
+
+function storeField(rt, ty, address, value) {
+    const view = new DataView(rt.memory.buffer);
+    if (ty === "real") {
+        view.setFloat64(address, value, true);
+    } else if (!isUnit(ty)) {
+        view.setUint32(address, value, true);
+    }
+}
+
+// decodes the wasm value of the type to a JS value: the tuples are arrays, and the values
+// of the datatypes `{tag}` or `{tag, value}` objects of the constructor and its argument
+function fromSml(rt, datatypes, ty, value) {
+    if (isUnit(ty)) {
+        return undefined;
+    }
+    if (Array.isArray(ty) && ty[0] === "tuple") {
+        const field = (ty, i) => fromSml(rt, datatypes, ty, loadField(rt, ty, value + i * 8));
+        return ty[1].map(field);
+    }
+    if (Array.isArray(ty)) {
+        const [, constructors] = datatypes[ty[1]];
+        const [tag, arg] = constructors[new DataView(rt.memory.buffer).getInt32(value, true)];
+        if (arg === null) {
+            return {tag};
+        }
+        // the reals are boxed in the datatypes
+        const field = arg === "real"
+            ? loadField(rt, arg, loadField(rt, "int", value + 8))
+            : loadField(rt, arg, value + 8);
+        return {tag, value: fromSml(rt, datatypes, arg, field)};
+    }
+    switch (ty) {
+    case "int":
+        return value | 0;
+    case "word":
+        return value >>> 0;
+    case "char":
+        return String.fromCodePoint(value);
+    case "string":
+        return readString(rt, value);
+    case "bool":
+        return new DataView(rt.memory.buffer).getInt32(value, true) !== 0;
+    default:
+        // reals, and the addresses of the closures
+        return value;
+    }
+}
+
+// encodes the JS value to the wasm value of the type, allocating the objects in the runtime.
+// the objects are rooted in the shadow stack until the caller leaves the frame
+function toSml(rt, datatypes, ty, value) {
+    const root = (address) => {
+        new DataView(rt.memory.buffer).setUint32(rt.enter(1), address, true);
+        return address;
+    };
+    // an object of the words of the types and the values, the fields encoded before it
+    const object = (tys, values) => {
+        const fields = tys.map((ty, i) => toSml(rt, datatypes, ty, values[i]));
+        let pointers = 0;
+        let ambiguous = 0;
+        tys.forEach((ty, i) => {
+            if (!isPointer(ty)) {
+                return;
+            } else if (i * 2 < 31) {
+                pointers |= 1 << (i * 2);
+            } else {
+                // scan the words after the first 31
+                ambiguous |= 1 << 31;
+            }
+        });
+        const address = root(rt.alloc(tys.length * 8, pointers, ambiguous));
+        tys.forEach((ty, i) => storeField(rt, ty, address + i * 8, fields[i]));
+        return address;
+    };
+    if (isUnit(ty)) {
+        return 0;
+    }
+    if (Array.isArray(ty) && ty[0] === "tuple") {
+        return object(ty[1], Array.from(value));
+    }
+    if (Array.isArray(ty)) {
+        const [name, constructors] = datatypes[ty[1]];
+        const index = constructors.findIndex(([tag]) => tag === value.tag);
+        if (index < 0) {
+            throw new TypeError(`${value.tag} is not a constructor of ${name}`);
+        }
+        const arg = constructors[index][1];
+        // the nullary constructors hold 0 in place of the argument, and the reals are boxed
+        if (arg === null) {
+            return object(["int", "int"], [index, 0]);
+        } else if (arg === "real") {
+            return object(["int", ["tuple", ["real"]]], [index, [value.value]]);
+        }
+        return object(["int", arg], [index, value.value]);
+    }
+    switch (ty) {
+    case "char":
+        return typeof value === "string" ? value.codePointAt(0) : Number(value);
+    case "string":
+        return root(writeString(rt, value));
+    case "bool":
+        return object(["int", "int"], [value ? 1 : 0, 0]);
+    case "real":
+        return Number(value);
+    default:
+        return Number(value) | 0;
+    }
+}
+
+// instantiates the module.
+// `host` provides the imported functions as `{module: {name: function}}`.
+// `runtime` and `program` are URLs or bytes of the wasm modules.
+// returns the exported functions
+export async function instantiate(host = {}, {runtime = RUNTIME_URL, program = PROGRAM_URL} = {}) {
+    const rt = await WebAssembly.instantiate(await load(runtime), {
+        imports: {print: (x) => console.log(x)},
+    });
+    const imports = {"webml-rt": rt.instance.exports};
+    const output = consoleOutput();
+    const defaults = defaultImports(rt.instance.exports, output);
+    let instance;
+    // wraps the closure at the address into a JS function taking the values of the types.
+    // the function calls it through the table of the program, with its environment past the
+    // function index. the runtime keeps the closure in a slot until the function is dropped
+    const callback = (closure, [params, ret]) => {
+        const exports = rt.instance.exports;
+        const slot = exports.callback_register(closure);
+        const fun = (...args) => {
+            if (!instance) {
+                throw new Error("the callback is called before the program is instantiated");
+            }
+            // the collector may have moved the closure
+            const closure = exports.callback_closure(slot);
+            const index = new DataView(exports.memory.buffer).getUint32(closure, true);
+            const values = params.map((ty, i) => toWasm(ty, args[i]));
+            let result;
+            try {
+                result = instance.exports.table.get(index)(closure + 4, ...values);
+            } catch (error) {
+                throw trapped(error);
+            } finally {
+                output.flush();
+            }
+            raised();
+            return ret === null ? undefined : result;
+        };
+        CALLBACK_SLOTS.register(fun, {rt: exports, slot});
+        return fun;
+    };
+    // the argument of an import as the JS value the host function takes
+    const hostValue = (ty, value) => {
+        if (ty === "string") {
+            return readString(rt.instance.exports, value);
+        }
+        return Array.isArray(ty) ? callback(value, ty) : value;
+    };
+    // the async imports resume the program once it is instantiated, as the callbacks do
+    let ready;
+    const instantiated = new Promise((resolve) => (ready = resolve));
+    for (const [module, name, params, ret, suspends] of IMPORTS) {
+        const fun = (host[module] || {})[name] || (defaults[module] || {})[name];
+        if (typeof fun !== "function") {
+            throw new Error(`missing import ${module}.${name}`);
+        }
+        imports[module] = imports[module] || {};
+        // the strings are passed to and returned from `fun` as JS strings,
+        // copied out of and into the memory of the runtime, and the closures as JS functions
+        imports[module][name] = (...args) => {
+            const values = args.map((arg, i) => hostValue(params[i], arg));
+            if (suspends) {
+                // the program returns, and goes on in the continuation given the value
+                const resume = values.pop();
+                Promise.all([fun(...values), instantiated]).then(([result]) =>
+                    resume(ret === "string" ? writeString(rt.instance.exports, result) : result)
+                );
+                return;
+            }
+            const result = fun(...values);
+            return ret === "string" ? writeString(rt.instance.exports, result) : toWasm(ret, result);
+        };
+    }
+    const module = await WebAssembly.compile(await load(program));
+    const sites = errorSites(module);
+    const errorSite = rt.instance.exports.error_site || (() => 0);
+    // the error at the site the program recorded, if any
+    const located = (error) => {
+        const site = sites[errorSite() - 1];
+        return site ? new Error(`${site[0]} in ${site[1]}`, {cause: error}) : error;
+    };
+    const raised = () => {
+        if (rt.instance.exports.raised && rt.instance.exports.raised()) {
+            throw located(new Error("uncaught exception"));
+        }
+    };
+    const trapped = (error) => (error instanceof WebAssembly.RuntimeError ? located(error) : error);
+
+    try {
+        // the start function runs the top level of the program
+        instance = await WebAssembly.instantiate(module, imports);
+    } catch (error) {
+        throw trapped(error);
+    } finally {
+        output.flush();
+    }
+    raised();
+    ready();
+
+    const types = exportTypes(module);
+    const exports = {};
+    for (const [name, params] of EXPORTS) {
+        const fun = instance.exports[name];
+        const typed = types.get(name);
+        // the tuple parameters are taken as the arguments, which the function may take unboxed
+        const encode = (args) => {
+            const {datatypes, param} = typed;
+            const rtExports = rt.instance.exports;
+            if (params.length > 1) {
+                return param[1].map((ty, i) => toSml(rtExports, datatypes, ty, args[i]));
+            }
+            const tuple = Array.isArray(param) && param[0] === "tuple" && param[1].length > 0;
+            return [toSml(rtExports, datatypes, param, tuple ? args : args[0])];
+        };
+        exports[name] = (...args) => {
+            const frame = rt.instance.exports.enter(0);
+            let result;
+            try {
+                const values = typed ? encode(args) : params.map((ty, i) => toWasm(ty, args[i]));
+                result = fun(...values);
+            } catch (error) {
+                throw trapped(error);
+            } finally {
+                rt.instance.exports.leave(frame);
+                output.flush();
+            }
+            raised();
+            return typed ? fromSml(rt.instance.exports, typed.datatypes, typed.ret, result) : result;
+        };
+    }
+    RUNTIMES.set(exports, rt.instance.exports);
+    return exports;
+}
+
+// the number of calls and the estimated cycles of each function of the program compiled
+// with `--profile`, as `[{name, calls, cycles}]` sorted by the cycles
+export function profile(exports) {
+    const rt = RUNTIMES.get(exports);
+    const table = rt.profile_table ? rt.profile_table() : 0;
+    if (table === 0) {
+        throw new Error("the program is not compiled with --profile");
+    }
+    const view = new DataView(rt.memory.buffer, table, PROFILE.length * 16);
+    const rows = PROFILE.map((name, i) => ({
+        name,
+        calls: view.getBigUint64(i * 16, true),
+        cycles: view.getBigUint64(i * 16 + 8, true),
+    }));
+    return rows.sort((a, b) => (a.cycles < b.cycles) - (a.cycles > b.cycles));
+}
+
+// prints the profile of the functions called, to stderr
+export function printProfile(exports, {limit = 20} = {}) {
+    const rows = profile(exports).filter((row) => row.calls > 0n);
+    const total = rows.reduce((sum, row) => sum + row.cycles, 0n) || 1n;
+    console.error("  cycles      %    calls  function");
+    for (const {name, calls, cycles} of rows.slice(0, limit)) {
+        const percent = (Number(cycles * 1000n / total) / 10).toFixed(1);
+        console.error(
+            `${String(cycles).padStart(8)} ${percent.padStart(6)} ${String(calls).padStart(8)}  ${name}`
+        );
+    }
+}
+"#;
+
+/// generates an ES module which loads the module from `program_url`
+pub fn generate_glue(interface: &Interface, program_url: &str) -> String {
+    let mut js = String::new();
+    // writing to a String never fails
+    writeln!(js, "// generated by webml").unwrap();
+    writeln!(js, "const RUNTIME_URL = {};", string(RUNTIME_URL)).unwrap();
+    writeln!(js, "const PROGRAM_URL = {};", string(program_url)).unwrap();
+
+    writeln!(
+        js,
+        "// module, name, parameter types, return type and whether it returns a promise"
+    )
+    .unwrap();
+    writeln!(js, "const IMPORTS = [").unwrap();
+    for (module, name, ty, host_types) in &interface.imports {
+        let ret = match (&host_types.ret, &ty.ret) {
+            (Some(host_ty), _) => host_type(host_ty),
+            (None, Some(ret)) => value_type(ret).to_string(),
+            (None, None) => "null".to_string(),
+        };
+        writeln!(
+            js,
+            "    [{}, {}, {}, {}, {}],",
+            string(module),
+            string(name),
+            param_types(&ty.params, host_types),
+            ret,
+            host_types.suspends
+        )
+        .unwrap();
+    }
+    writeln!(js, "];").unwrap();
+
+    writeln!(js, "// name and parameter types").unwrap();
+    writeln!(js, "const EXPORTS = [").unwrap();
+    for (name, ty) in &interface.exports {
+        writeln!(js, "    [{}, {}],", string(name), value_types(&ty.params)).unwrap();
+    }
+    writeln!(js, "];").unwrap();
+
+    writeln!(js, "// functions in the rows of the profile table").unwrap();
+    writeln!(js, "export const PROFILE = [").unwrap();
+    for name in &interface.profile {
+        writeln!(js, "    {},", string(name)).unwrap();
+    }
+    writeln!(js, "];").unwrap();
+
+    js.push_str(LOADER);
+    js
+}
@@ -1,3 +1,16 @@
+pub mod js;
+pub mod module;
+pub mod peephole;
+pub mod validate;
+pub mod wasi;
 pub mod wasm;
+pub mod wat;
+pub use self::js::generate_glue;
+pub use self::module::{
+    CustomSection, ErrorSites, Interface, Metadata, Module, NameSection, TypeSection,
+};
+pub use self::peephole::Peephole;
+pub use self::validate::Validate;
 pub use self::wasm::LIR2WASM;
+pub use self::wat::print_wat;
 mod pp;
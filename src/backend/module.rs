@@ -0,0 +1,391 @@
+use crate::lir::HostTypes;
+use crate::prim::{ExportTy, ExportTypes};
+use wasm::{Dump, FuncType};
+
+/// The output of the backend.
+/// The wasm encoder doesn't support custom sections, so they are appended after the module.
+#[derive(Debug, Clone)]
+pub struct Module {
+    /// the binary of the module, without the custom sections
+    pub binary: Vec<u8>,
+    pub custom_sections: Vec<CustomSection>,
+    pub interface: Interface,
+}
+
+/// the functions the module imports from and exports to the host, besides the runtime.
+/// the JS glue is generated from this
+#[derive(Debug, Clone, Default)]
+pub struct Interface {
+    /// module name, function name, type and the values the host converts
+    /// in the order of the import section
+    pub imports: Vec<(String, String, FuncType, HostTypes)>,
+    pub exports: Vec<(String, FuncType)>,
+    /// the names of the functions in the rows of the profile table,
+    /// if compiled with `Config::profile`
+    pub profile: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct CustomSection {
+    pub name: String,
+    pub payload: Vec<u8>,
+}
+
+/// the `webml.meta` custom section, which tells tools how the module was compiled.
+/// the payload is the version followed by the vectors of the flags and the exports,
+/// all strings encoded as names of the wasm binary format
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Metadata {
+    /// the version of the compiler
+    pub version: String,
+    /// the options affecting the code, as pairs of the option and its value
+    pub flags: Vec<(String, String)>,
+    /// the names of the exported functions and their SML types
+    pub exports: Vec<(String, String)>,
+}
+
+/// the `webml.errors` custom section, the table of the sites where the program may fail.
+/// before the generated code traps or raises, it records the index of the site, counted from 1,
+/// with `error_at` of the runtime, so that the host can tell where the program failed.
+/// the IRs don't have the source spans yet, so the sites are located by the functions
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ErrorSites {
+    /// the kind of the error and the function, e.g. `("integer divide by zero", "f@12")`
+    pub sites: Vec<(String, String)>,
+}
+
+/// the `webml.types` custom section, the types of the exported functions,
+/// by which the JS glue converts the values to and from JS values.
+/// the types are encoded as the tag of the `ExportTy` variant followed by its fields
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TypeSection {
+    pub exports: Vec<(String, ExportTypes)>,
+}
+
+/// the `name` custom section.
+/// indices are of the function index space, which includes imported functions
+#[derive(Debug, Clone, Default)]
+pub struct NameSection {
+    pub functions: Vec<(u32, String)>,
+    pub locals: Vec<(u32, Vec<(u32, String)>)>,
+}
+
+pub(crate) fn write_u32(buf: &mut Vec<u8>, mut n: u32) {
+    loop {
+        let b = (n & 0x7f) as u8;
+        n >>= 7;
+        if n == 0 {
+            buf.push(b);
+            break;
+        }
+        buf.push(b | 0x80);
+    }
+}
+
+pub(crate) fn write_name(buf: &mut Vec<u8>, name: &str) {
+    write_u32(buf, name.len() as u32);
+    buf.extend_from_slice(name.as_bytes());
+}
+
+fn write_name_pairs(buf: &mut Vec<u8>, pairs: &[(String, String)]) {
+    write_u32(buf, pairs.len() as u32);
+    for (key, value) in pairs {
+        write_name(buf, key);
+        write_name(buf, value);
+    }
+}
+
+pub(crate) fn read_u32(buf: &mut &[u8]) -> Option<u32> {
+    let mut n = 0;
+    for shift in (0..35).step_by(7) {
+        let (&b, rest) = buf.split_first()?;
+        *buf = rest;
+        n |= ((b & 0x7f) as u32) << shift;
+        if b & 0x80 == 0 {
+            return Some(n);
+        }
+    }
+    None
+}
+
+pub(crate) fn read_name(buf: &mut &[u8]) -> Option<String> {
+    let len = read_u32(buf)? as usize;
+    if buf.len() < len {
+        return None;
+    }
+    let (name, rest) = buf.split_at(len);
+    *buf = rest;
+    String::from_utf8(name.to_vec()).ok()
+}
+
+fn read_name_pairs(buf: &mut &[u8]) -> Option<Vec<(String, String)>> {
+    let len = read_u32(buf)?;
+    (0..len)
+        .map(|_| Some((read_name(buf)?, read_name(buf)?)))
+        .collect()
+}
+
+fn write_name_map(buf: &mut Vec<u8>, map: &[(u32, String)]) {
+    write_u32(buf, map.len() as u32);
+    for (index, name) in map {
+        write_u32(buf, *index);
+        write_name(buf, name);
+    }
+}
+
+impl Module {
+    pub fn new(module: wasm::Module) -> Self {
+        let mut binary = Vec::new();
+        module.dump(&mut binary);
+        Module {
+            binary,
+            custom_sections: Vec::new(),
+            interface: Interface::default(),
+        }
+    }
+
+    pub fn dump(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.binary);
+        for section in &self.custom_sections {
+            section.dump(buf);
+        }
+    }
+}
+
+impl CustomSection {
+    fn dump(&self, buf: &mut Vec<u8>) {
+        let mut content = Vec::new();
+        write_name(&mut content, &self.name);
+        content.extend_from_slice(&self.payload);
+        buf.push(0);
+        write_u32(buf, content.len() as u32);
+        buf.extend(content);
+    }
+}
+
+impl NameSection {
+    pub fn encode(mut self) -> CustomSection {
+        self.functions.sort_by_key(|(index, _)| *index);
+        self.locals.sort_by_key(|(index, _)| *index);
+        let mut payload = Vec::new();
+
+        let mut subsection = Vec::new();
+        write_name_map(&mut subsection, &self.functions);
+        payload.push(1);
+        write_u32(&mut payload, subsection.len() as u32);
+        payload.extend(subsection);
+
+        let mut subsection = Vec::new();
+        write_u32(&mut subsection, self.locals.len() as u32);
+        for (index, mut locals) in self.locals {
+            locals.sort_by_key(|(index, _)| *index);
+            write_u32(&mut subsection, index);
+            write_name_map(&mut subsection, &locals);
+        }
+        payload.push(2);
+        write_u32(&mut payload, subsection.len() as u32);
+        payload.extend(subsection);
+
+        CustomSection {
+            name: "name".into(),
+            payload,
+        }
+    }
+}
+
+impl Metadata {
+    pub const SECTION_NAME: &'static str = "webml.meta";
+
+    pub fn encode(&self) -> CustomSection {
+        let mut payload = Vec::new();
+        write_name(&mut payload, &self.version);
+        write_name_pairs(&mut payload, &self.flags);
+        write_name_pairs(&mut payload, &self.exports);
+        CustomSection {
+            name: Self::SECTION_NAME.into(),
+            payload,
+        }
+    }
+
+    /// reads the metadata from the payload of the section. returns `None` if it is malformed
+    pub fn decode(mut payload: &[u8]) -> Option<Self> {
+        let buf = &mut payload;
+        let metadata = Metadata {
+            version: read_name(buf)?,
+            flags: read_name_pairs(buf)?,
+            exports: read_name_pairs(buf)?,
+        };
+        if buf.is_empty() {
+            Some(metadata)
+        } else {
+            None
+        }
+    }
+}
+
+fn write_export_ty(buf: &mut Vec<u8>, ty: &ExportTy) {
+    use crate::prim::ExportTy::*;
+    match ty {
+        Int => write_u32(buf, 0),
+        Word => write_u32(buf, 1),
+        Real => write_u32(buf, 2),
+        Char => write_u32(buf, 3),
+        String => write_u32(buf, 4),
+        Bool => write_u32(buf, 5),
+        Tuple(tys) => {
+            write_u32(buf, 6);
+            write_u32(buf, tys.len() as u32);
+            for ty in tys {
+                write_export_ty(buf, ty);
+            }
+        }
+        Datatype(index) => {
+            write_u32(buf, 7);
+            write_u32(buf, *index);
+        }
+        Fun => write_u32(buf, 8),
+    }
+}
+
+fn read_export_ty(buf: &mut &[u8]) -> Option<ExportTy> {
+    use crate::prim::ExportTy::*;
+    let ty = match read_u32(buf)? {
+        0 => Int,
+        1 => Word,
+        2 => Real,
+        3 => Char,
+        4 => String,
+        5 => Bool,
+        6 => Tuple(
+            (0..read_u32(buf)?)
+                .map(|_| read_export_ty(buf))
+                .collect::<Option<_>>()?,
+        ),
+        7 => Datatype(read_u32(buf)?),
+        8 => Fun,
+        _ => return None,
+    };
+    Some(ty)
+}
+
+fn read_datatype(buf: &mut &[u8]) -> Option<(String, Vec<(String, Option<ExportTy>)>)> {
+    let name = read_name(buf)?;
+    let constructors = (0..read_u32(buf)?)
+        .map(|_| {
+            let constructor = read_name(buf)?;
+            let arg = match read_u32(buf)? {
+                0 => None,
+                1 => Some(read_export_ty(buf)?),
+                _ => return None,
+            };
+            Some((constructor, arg))
+        })
+        .collect::<Option<_>>()?;
+    Some((name, constructors))
+}
+
+impl TypeSection {
+    pub const SECTION_NAME: &'static str = "webml.types";
+
+    pub fn encode(&self) -> CustomSection {
+        let mut payload = Vec::new();
+        write_u32(&mut payload, self.exports.len() as u32);
+        for (name, types) in &self.exports {
+            write_name(&mut payload, name);
+            write_u32(&mut payload, types.datatypes.len() as u32);
+            for (datatype, constructors) in &types.datatypes {
+                write_name(&mut payload, datatype);
+                write_u32(&mut payload, constructors.len() as u32);
+                for (constructor, arg) in constructors {
+                    write_name(&mut payload, constructor);
+                    match arg {
+                        None => write_u32(&mut payload, 0),
+                        Some(arg) => {
+                            write_u32(&mut payload, 1);
+                            write_export_ty(&mut payload, arg);
+                        }
+                    }
+                }
+            }
+            write_export_ty(&mut payload, &types.param);
+            write_export_ty(&mut payload, &types.ret);
+        }
+        CustomSection {
+            name: Self::SECTION_NAME.into(),
+            payload,
+        }
+    }
+
+    /// reads the types from the payload of the section. returns `None` if it is malformed
+    pub fn decode(mut payload: &[u8]) -> Option<Self> {
+        let buf = &mut payload;
+        let exports = (0..read_u32(buf)?)
+            .map(|_| {
+                let name = read_name(buf)?;
+                let datatypes = (0..read_u32(buf)?)
+                    .map(|_| read_datatype(buf))
+                    .collect::<Option<_>>()?;
+                let param = read_export_ty(buf)?;
+                let ret = read_export_ty(buf)?;
+                Some((
+                    name,
+                    ExportTypes {
+                        param,
+                        ret,
+                        datatypes,
+                    },
+                ))
+            })
+            .collect::<Option<_>>()?;
+        if buf.is_empty() {
+            Some(TypeSection { exports })
+        } else {
+            None
+        }
+    }
+}
+
+impl ErrorSites {
+    pub const SECTION_NAME: &'static str = "webml.errors";
+
+    /// the kind of the uncaught exceptions
+    pub const RAISE: &'static str = "uncaught exception";
+    /// the kind of the divisions and the remainders by zero
+    pub const DIVIDE_BY_ZERO: &'static str = "integer divide by zero";
+
+    /// adds the site unless it's in the table, and returns its index
+    pub fn site(&mut self, kind: &str, function: &str) -> u32 {
+        let position = self
+            .sites
+            .iter()
+            .position(|(k, f)| k == kind && f == function);
+        let index = match position {
+            Some(index) => index,
+            None => {
+                self.sites.push((kind.to_string(), function.to_string()));
+                self.sites.len() - 1
+            }
+        };
+        index as u32 + 1
+    }
+
+    pub fn encode(&self) -> CustomSection {
+        let mut payload = Vec::new();
+        write_name_pairs(&mut payload, &self.sites);
+        CustomSection {
+            name: Self::SECTION_NAME.into(),
+            payload,
+        }
+    }
+
+    /// reads the sites from the payload of the section. returns `None` if it is malformed
+    pub fn decode(mut payload: &[u8]) -> Option<Self> {
+        let buf = &mut payload;
+        let sites = read_name_pairs(buf)?;
+        if buf.is_empty() {
+            Some(ErrorSites { sites })
+        } else {
+            None
+        }
+    }
+}
@@ -0,0 +1,234 @@
+//! peephole optimization of the emitted module.
+//! the backend emits the code of each LIR operation separately, leaving sequences like
+//! `local.set 1; local.get 1` between them. this pass rewrites them in the function bodies.
+
+use crate::ast::TypeError;
+use crate::backend::module::write_u32;
+use crate::backend::wat::invalid;
+use crate::backend::Module;
+use crate::config::Config;
+use crate::pass::Pass;
+use std::io;
+use wasmparser::BinaryReader;
+
+const CODE_SECTION: u8 = 10;
+
+const DROP: u8 = 0x1a;
+const LOCAL_GET: u8 = 0x20;
+const LOCAL_SET: u8 = 0x21;
+const LOCAL_TEE: u8 = 0x22;
+const GLOBAL_GET: u8 = 0x23;
+const I32_CONST: u8 = 0x41;
+const I64_CONST: u8 = 0x42;
+const F32_CONST: u8 = 0x43;
+const F64_CONST: u8 = 0x44;
+const I32_ADD: u8 = 0x6a;
+
+/// an instruction with its encoded immediates
+#[derive(Debug, Clone, PartialEq)]
+struct Instr {
+    op: u8,
+    immediates: Vec<u8>,
+}
+
+impl Instr {
+    fn new(op: u8, immediates: Vec<u8>) -> Self {
+        Instr { op, immediates }
+    }
+
+    fn i32(&self) -> i32 {
+        // the immediates were read as valid LEB128s
+        BinaryReader::new(&self.immediates).read_var_i32().unwrap()
+    }
+
+    fn is_load(&self) -> bool {
+        0x28 <= self.op && self.op <= 0x35
+    }
+
+    fn is_store(&self) -> bool {
+        0x36 <= self.op && self.op <= 0x3e
+    }
+
+    /// the alignment and the offset of the memory instruction
+    fn memarg(&self) -> (u32, u32) {
+        let mut r = BinaryReader::new(&self.immediates);
+        (r.read_var_u32().unwrap(), r.read_var_u32().unwrap())
+    }
+
+    fn with_memarg(&self, align: u32, offset: u32) -> Self {
+        let mut immediates = Vec::new();
+        write_u32(&mut immediates, align);
+        write_u32(&mut immediates, offset);
+        Instr::new(self.op, immediates)
+    }
+
+    /// whether the instruction only pushes a value, without any other effect
+    fn is_pure_push(&self) -> bool {
+        matches!(
+            self.op,
+            LOCAL_GET | GLOBAL_GET | I32_CONST | I64_CONST | F32_CONST | F64_CONST
+        )
+    }
+
+    fn dump(&self, buf: &mut Vec<u8>) {
+        buf.push(self.op);
+        buf.extend_from_slice(&self.immediates);
+    }
+}
+
+fn read_instrs(body: &[u8], mut r: BinaryReader) -> wasmparser::Result<Vec<Instr>> {
+    let mut instrs = Vec::new();
+    while !r.eof() {
+        let start = r.current_position();
+        r.read_operator()?;
+        let instr = &body[start..r.current_position()];
+        instrs.push(Instr::new(instr[0], instr[1..].to_vec()));
+    }
+    Ok(instrs)
+}
+
+fn tail(instrs: &[Instr], len: usize) -> &[Instr] {
+    &instrs[instrs.len().saturating_sub(len)..]
+}
+
+/// rewrites the instructions at the end of `instrs`. returns whether it rewrote any
+fn rewrite_tail(instrs: &mut Vec<Instr>) -> bool {
+    let n = instrs.len();
+    // local.set x; local.get x => local.tee x
+    if let [set, get] = tail(instrs, 2) {
+        if set.op == LOCAL_SET && get.op == LOCAL_GET && set.immediates == get.immediates {
+            let tee = Instr::new(LOCAL_TEE, set.immediates.clone());
+            instrs.truncate(n - 2);
+            instrs.push(tee);
+            return true;
+        }
+    }
+    if let [instr, drop] = tail(instrs, 2) {
+        if drop.op == DROP {
+            // local.tee x; drop => local.set x
+            if instr.op == LOCAL_TEE {
+                let set = Instr::new(LOCAL_SET, instr.immediates.clone());
+                instrs.truncate(n - 2);
+                instrs.push(set);
+                return true;
+            }
+            // the value is not used
+            if instr.is_pure_push() {
+                instrs.truncate(n - 2);
+                return true;
+            }
+        }
+    }
+    // i32.const c; i32.add; load offset=o => load offset=o+c
+    // the addresses are assumed not to wrap around
+    if let [constant, add, load] = tail(instrs, 3) {
+        if constant.op == I32_CONST && add.op == I32_ADD && load.is_load() {
+            if let Some(load) = fold_offset(load, constant.i32()) {
+                instrs.truncate(n - 3);
+                instrs.push(load);
+                return true;
+            }
+        }
+    }
+    // i32.const c; i32.add; v; store offset=o => v; store offset=o+c
+    if let [constant, add, value, store] = tail(instrs, 4) {
+        if constant.op == I32_CONST && add.op == I32_ADD && value.is_pure_push() && store.is_store()
+        {
+            if let Some(store) = fold_offset(store, constant.i32()) {
+                let value = value.clone();
+                instrs.truncate(n - 4);
+                instrs.push(value);
+                instrs.push(store);
+                return true;
+            }
+        }
+    }
+    false
+}
+
+fn fold_offset(instr: &Instr, c: i32) -> Option<Instr> {
+    if c < 0 {
+        return None;
+    }
+    let (align, offset) = instr.memarg();
+    let offset = offset.checked_add(c as u32)?;
+    Some(instr.with_memarg(align, offset))
+}
+
+fn optimize_instrs(instrs: Vec<Instr>) -> Vec<Instr> {
+    let mut optimized = Vec::with_capacity(instrs.len());
+    for instr in instrs {
+        optimized.push(instr);
+        while rewrite_tail(&mut optimized) {}
+    }
+    optimized
+}
+
+fn optimize_function(body: &[u8]) -> wasmparser::Result<Vec<u8>> {
+    let mut r = BinaryReader::new(body);
+    for _ in 0..r.read_var_u32()? {
+        let _count = r.read_var_u32()?;
+        let _ty = r.read_type()?;
+    }
+    let mut function = body[..r.current_position()].to_vec();
+    for instr in optimize_instrs(read_instrs(body, r)?) {
+        instr.dump(&mut function);
+    }
+    Ok(function)
+}
+
+fn optimize_code_section(section: &[u8]) -> wasmparser::Result<Vec<u8>> {
+    let mut r = BinaryReader::new(section);
+    let count = r.read_var_u32()?;
+    let mut optimized = Vec::new();
+    write_u32(&mut optimized, count);
+    for _ in 0..count {
+        let size = r.read_var_u32()? as usize;
+        let function = optimize_function(r.read_bytes(size)?)?;
+        write_u32(&mut optimized, function.len() as u32);
+        optimized.extend(function);
+    }
+    Ok(optimized)
+}
+
+fn optimize_module(binary: &[u8]) -> wasmparser::Result<Vec<u8>> {
+    let mut r = BinaryReader::new(binary);
+    let mut optimized = r.read_bytes(8)?.to_vec();
+    while !r.eof() {
+        let id = r.read_u8()? as u8;
+        let size = r.read_var_u32()? as usize;
+        let section = r.read_bytes(size)?;
+        let section = if id == CODE_SECTION {
+            optimize_code_section(section)?
+        } else {
+            section.to_vec()
+        };
+        optimized.push(id);
+        write_u32(&mut optimized, section.len() as u32);
+        optimized.extend(section);
+    }
+    Ok(optimized)
+}
+
+/// optimizes the function bodies of the binary module. the other sections are copied as is
+pub fn optimize(binary: &[u8]) -> io::Result<Vec<u8>> {
+    optimize_module(binary).map_err(invalid)
+}
+
+pub struct Peephole;
+
+impl Peephole {
+    pub fn new() -> Self {
+        Peephole
+    }
+}
+
+impl<'a> Pass<Module, TypeError<'a>> for Peephole {
+    type Target = Module;
+
+    fn trans(&mut self, mut module: Module, _: &Config) -> Result<Self::Target, TypeError<'a>> {
+        module.binary = optimize(&module.binary)
+            .map_err(|e| TypeError::InvalidIR(format!("malformed wasm: {}", e)))?;
+        Ok(module)
+    }
+}
@@ -1,10 +1,12 @@
+use crate::backend::wat::print_wat;
+use crate::backend::Module;
 use crate::util::PP;
 use std::io;
-use wasm::Module;
 
 impl PP for Module {
     fn pp<W: io::Write>(&self, w: &mut W, _: usize) -> io::Result<()> {
-        writeln!(w, "{:#?}", self)?;
-        Ok(())
+        let mut code = Vec::new();
+        self.dump(&mut code);
+        print_wat(&code, w)
     }
 }
@@ -0,0 +1,32 @@
+//! validation of the emitted module.
+//! codegen bugs are reported as compile errors instead of failures to instantiate the module.
+
+use crate::ast::TypeError;
+use crate::backend::Module;
+use crate::config::Config;
+use crate::pass::Pass;
+
+pub struct Validate;
+
+impl Validate {
+    pub fn new() -> Self {
+        Validate
+    }
+}
+
+impl<'a> Pass<Module, TypeError<'a>> for Validate {
+    type Target = Module;
+
+    fn trans(&mut self, module: Module, _: &Config) -> Result<Self::Target, TypeError<'a>> {
+        let mut code = Vec::new();
+        module.dump(&mut code);
+        wasmparser::validate(&code, None).map_err(|e| {
+            TypeError::InvalidIR(format!(
+                "invalid wasm at offset {}: {}",
+                e.offset(),
+                e.message()
+            ))
+        })?;
+        Ok(module)
+    }
+}
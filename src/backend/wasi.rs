@@ -0,0 +1,260 @@
+//! host functions implemented with WASI imports, for `Target::Wasi`.
+//! the extern calls to these functions are linked to the functions generated here
+//! instead of being imported from the host.
+
+use crate::backend::wasm::intern_type;
+use std::collections::HashMap;
+use wasm::builder::*;
+use wasm::*;
+
+pub const WASI_MODULE: &str = "wasi_snapshot_preview1";
+const STDOUT: i32 = 1;
+
+/// the host functions implemented here
+const HOST_FUNCTIONS: &[(&str, &str)] = &[
+    ("js-ffi", "print"),
+    ("js-ffi", "printString"),
+    ("js-ffi", "clock"),
+    ("js-ffi", "random_seed"),
+];
+
+/// the WASI functions the host functions use
+pub struct WasiImports {
+    fd_write: FunctionSpaceIndex,
+    clock_time_get: FunctionSpaceIndex,
+    random_get: FunctionSpaceIndex,
+}
+
+/// the functions of the runtime the host functions call
+pub struct Runtime {
+    pub alloc: FunctionSpaceIndex,
+    pub enter: FunctionSpaceIndex,
+    pub leave: FunctionSpaceIndex,
+}
+
+/// whether the host function is implemented here
+pub fn implements(module: &str, name: &str) -> bool {
+    HOST_FUNCTIONS.contains(&(module, name))
+}
+
+/// imports the WASI functions. they must be imported before any function is defined
+pub fn import(
+    md: &mut ModuleBuilder,
+    function_type_table: &mut HashMap<FuncType, TypeIndex>,
+    function_names: &mut Vec<String>,
+) -> WasiImports {
+    let fd_write_ty = intern_type(
+        md,
+        function_type_table,
+        funtype!((i32, i32, i32, i32) -> i32),
+    );
+    let fd_write = md.import(WASI_MODULE, "fd_write", fd_write_ty);
+    let fd_write = md.function_index_of(fd_write).unwrap();
+    function_names.push(format!("{}.fd_write", WASI_MODULE));
+    let clock_time_get_ty = intern_type(md, function_type_table, funtype!((i32, i64, i32) -> i32));
+    let clock_time_get = md.import(WASI_MODULE, "clock_time_get", clock_time_get_ty);
+    let clock_time_get = md.function_index_of(clock_time_get).unwrap();
+    function_names.push(format!("{}.clock_time_get", WASI_MODULE));
+    let random_get_ty = intern_type(md, function_type_table, funtype!((i32, i32) -> i32));
+    let random_get = md.import(WASI_MODULE, "random_get", random_get_ty);
+    let random_get = md.function_index_of(random_get).unwrap();
+    function_names.push(format!("{}.random_get", WASI_MODULE));
+    WasiImports {
+        fd_write,
+        clock_time_get,
+        random_get,
+    }
+}
+
+/// generates the host function
+pub fn host_function(
+    module: &str,
+    name: &str,
+    wasi: &WasiImports,
+    runtime: &Runtime,
+) -> (FuncType, Code) {
+    match (module, name) {
+        ("js-ffi", "print") => print_int(wasi, runtime.alloc),
+        ("js-ffi", "printString") => print_string(wasi, runtime),
+        ("js-ffi", "clock") => clock(wasi, runtime),
+        ("js-ffi", "random_seed") => random_seed(wasi, runtime),
+        _ => unreachable!("no WASI implementation of {}.{}", module, name),
+    }
+}
+
+/// prints the int in decimal followed by a newline, like `console.log`
+fn print_int(wasi: &WasiImports, alloc: FunctionSpaceIndex) -> (FuncType, Code) {
+    // the buffer is laid out as the iovec {buf, len}, nwritten and the digits,
+    // which are written backward from the end
+    const SIZE: i32 = 32;
+    let mut fb = FunctionBuilder::new(funtype!((i32)));
+    let locals = fb.new_locals(vec![ValueType::I32; 3]);
+    let (buf, pos, n) = (locals[0], locals[1], locals[2]);
+    fb.code(|cb, params| {
+        let x = params[0];
+        let cb = cb
+            .constant(SIZE)
+            // no pointers
+            .constant(0)
+            .constant(0)
+            .call(alloc)
+            .tee_local(buf)
+            .constant(SIZE)
+            .i32_add()
+            .constant(1)
+            .i32_sub()
+            .tee_local(pos)
+            .constant(b'\n' as i32)
+            .i32_store8(0);
+        // the absolute value. i32::MIN is correct as unsigned
+        let cb = cb
+            .constant(0)
+            .get_local(x)
+            .i32_sub()
+            .get_local(x)
+            .get_local(x)
+            .constant(0)
+            .i32_lt_s()
+            .select()
+            .set_local(n);
+        let cb = cb
+            .loop_(BlockType(None))
+            .get_local(pos)
+            .constant(1)
+            .i32_sub()
+            .tee_local(pos)
+            .get_local(n)
+            .constant(10)
+            .i32_rem_u()
+            .constant(b'0' as i32)
+            .i32_add()
+            .i32_store8(0)
+            .get_local(n)
+            .constant(10)
+            .i32_div_u()
+            .tee_local(n)
+            .br_if(0)
+            .end();
+        let cb = cb
+            .get_local(x)
+            .constant(0)
+            .i32_lt_s()
+            .if_(BlockType(None))
+            .get_local(pos)
+            .constant(1)
+            .i32_sub()
+            .tee_local(pos)
+            .constant(b'-' as i32)
+            .i32_store8(0)
+            .end();
+        cb.get_local(buf)
+            .get_local(pos)
+            .i32_store(0)
+            .get_local(buf)
+            .get_local(buf)
+            .constant(SIZE)
+            .i32_add()
+            .get_local(pos)
+            .i32_sub()
+            .i32_store(4)
+            .constant(STDOUT)
+            .get_local(buf)
+            .constant(1)
+            .get_local(buf)
+            .constant(8)
+            .i32_add()
+            .call(wasi.fd_write)
+            .drop()
+            .return_()
+    })
+    .build()
+}
+
+/// writes the bytes of the string as they are
+fn print_string(wasi: &WasiImports, runtime: &Runtime) -> (FuncType, Code) {
+    // the iovec {buf, len} and nwritten are in a frame of the shadow stack,
+    // which nothing collects while it is written
+    let mut fb = FunctionBuilder::new(funtype!((i32)));
+    let frame = fb.new_local(ValueType::I32);
+    fb.code(|cb, params| {
+        let s = params[0];
+        cb.constant(3)
+            .call(runtime.enter)
+            .tee_local(frame)
+            .get_local(s)
+            .constant(4)
+            .i32_add()
+            .i32_store(0)
+            .get_local(frame)
+            .get_local(s)
+            .i32_load(0)
+            .i32_store(4)
+            .constant(STDOUT)
+            .get_local(frame)
+            .constant(1)
+            .get_local(frame)
+            .constant(8)
+            .i32_add()
+            .call(wasi.fd_write)
+            .drop()
+            .get_local(frame)
+            .call(runtime.leave)
+            .return_()
+    })
+    .build()
+}
+
+/// the seconds the clock of the id tells, 0 for the realtime clock and 1 for the monotonic clock
+/// as WASI numbers them
+fn clock(wasi: &WasiImports, runtime: &Runtime) -> (FuncType, Code) {
+    // the time is written to the frame of the shadow stack, aligned to 8 bytes
+    let mut fb = FunctionBuilder::new(funtype!((i32) -> f64));
+    let locals = fb.new_locals(vec![ValueType::I32; 2]);
+    let (frame, time) = (locals[0], locals[1]);
+    fb.code(|cb, params| {
+        let id = params[0];
+        cb.constant(4)
+            .call(runtime.enter)
+            .tee_local(frame)
+            .constant(7)
+            .i32_add()
+            .constant(-8)
+            .i32_and()
+            .set_local(time)
+            .get_local(id)
+            // the precision in nanoseconds
+            .constant(1000 as i64)
+            .get_local(time)
+            .call(wasi.clock_time_get)
+            .drop()
+            .get_local(time)
+            .i64_load(0)
+            .f64_convert_u_i64()
+            .constant(1.0e9)
+            .f64_div()
+            .get_local(frame)
+            .call(runtime.leave)
+            .return_()
+    })
+    .build()
+}
+
+/// the random int of the entropy of the host
+fn random_seed(wasi: &WasiImports, runtime: &Runtime) -> (FuncType, Code) {
+    let mut fb = FunctionBuilder::new(funtype!((i32) -> i32));
+    let frame = fb.new_local(ValueType::I32);
+    fb.code(|cb, _params| {
+        cb.constant(1)
+            .call(runtime.enter)
+            .tee_local(frame)
+            .constant(4)
+            .call(wasi.random_get)
+            .drop()
+            .get_local(frame)
+            .i32_load(0)
+            .get_local(frame)
+            .call(runtime.leave)
+            .return_()
+    })
+    .build()
+}
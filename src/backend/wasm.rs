@@ -1,8 +1,11 @@
-use crate::config::Config;
+use crate::backend::wasi::{self, WasiImports};
+use crate::backend::{ErrorSites, Interface, Metadata, Module, NameSection, TypeSection};
+use crate::config::{Collector, Config, Target};
 use crate::lir;
 use crate::pass::Pass;
 use crate::prim::*;
 use std::collections::{HashMap, HashSet};
+use tracing::debug_span;
 use wasm::builder::*;
 use wasm::*;
 
@@ -15,7 +18,7 @@ enum Control<'a> {
     BlockEnd(&'a lir::Label),
 }
 
-fn lty_to_valuetype_opt(t: &lir::LTy) -> Option<ValueType> {
+pub(crate) fn lty_to_valuetype_opt(t: &lir::LTy) -> Option<ValueType> {
     use crate::lir::LTy::*;
     match *t {
         Unit => None,
@@ -34,6 +37,143 @@ fn lty_to_valuetype(t: &lir::LTy) -> ValueType {
     lty_to_valuetype_opt(t).unwrap_or(ValueType::I32)
 }
 
+/// the bitmaps of the 4-byte words of the object which hold heap pointers
+/// and which may hold heap pointers.
+/// the top bit of the latter tells the collector to scan all the words of a large object
+fn pointer_maps(layout: &[(u32, lir::Trace)]) -> (u32, u32) {
+    const SCAN_ALL: u32 = 1 << 31;
+    layout
+        .iter()
+        .fold((0, 0), |(pointers, ambiguous), (offset, trace)| {
+            let word = offset / 4;
+            match trace {
+                lir::Trace::Value => (pointers, ambiguous),
+                _ if 31 <= word => (pointers, ambiguous | SCAN_ALL),
+                lir::Trace::Pointer => (pointers | (1 << word), ambiguous),
+                lir::Trace::Ambiguous => (pointers, ambiguous | (1 << word)),
+            }
+        })
+}
+
+/// the metadata of the module compiled from the LIR
+fn metadata(lir: &lir::LIR, config: &Config) -> Metadata {
+    let flag = |key: &str, value: &str| (key.to_string(), value.to_string());
+    let mut flags = Vec::new();
+    let mut passes = config.enabled_passes.iter().collect::<Vec<_>>();
+    passes.sort();
+    flags.extend(passes.into_iter().map(|pass| flag("enable", pass)));
+    let mut passes = config.disabled_passes.iter().collect::<Vec<_>>();
+    passes.sort();
+    flags.extend(passes.into_iter().map(|pass| flag("disable", pass)));
+    flags.push(flag("opt-level", config.opt_level.name()));
+    if let Some(threshold) = config.inline_threshold {
+        flags.push(flag("inline-threshold", &threshold.to_string()));
+    }
+    flags.push(flag("target", config.target.name()));
+    flags.push(flag("gc", config.collector.name()));
+    flags.push(flag("overflow", config.overflow.name()));
+    if config.profile {
+        flags.push(flag("profile", "true"));
+    }
+    Metadata {
+        version: env!("CARGO_PKG_VERSION").into(),
+        flags,
+        exports: lir
+            .0
+            .iter()
+            .filter_map(|f| f.export.as_ref())
+            .map(|export| (export.name.clone(), export.ty.clone()))
+            .collect(),
+    }
+}
+
+fn type_section(lir: &lir::LIR) -> TypeSection {
+    TypeSection {
+        exports: lir
+            .0
+            .iter()
+            .filter_map(|f| f.export.as_ref())
+            .filter_map(|export| Some((export.name.clone(), export.types.clone()?)))
+            .collect(),
+    }
+}
+
+/// the estimated cycles to run the block, one for each operation
+fn cycles(block: &lir::Block) -> u32 {
+    block.body.len() as u32
+}
+
+/// the kind of the error the operation may fail with, recorded in `ErrorSites`
+fn error_kind(op: &lir::Op) -> Option<&'static str> {
+    use crate::lir::Op::*;
+    match op {
+        DivI32(..) | ModI32(..) | DivU32(..) | ModU32(..) | DivI64(..) | ModI64(..)
+        | DivU64(..) | ModU64(..) => Some(ErrorSites::DIVIDE_BY_ZERO),
+        ExternCall(_, module, fun, _) if module == "webml-rt" && fun == "raise" => {
+            Some(ErrorSites::RAISE)
+        }
+        _ => None,
+    }
+}
+
+/// whether the operation may run the garbage collector
+fn may_collect(op: &lir::Op) -> bool {
+    use crate::lir::Op::*;
+    matches!(
+        op,
+        HeapAlloc(..) | StackAlloc(..) | FunCall(..) | ClosureCall(..) | ExternCall(..)
+    )
+}
+
+/// selects the instructions converting the value of `from` on the stack to `to`
+fn convert(cb: CodeBuilder, from: &lir::LTy, to: &lir::LTy) -> CodeBuilder {
+    use crate::lir::LTy::*;
+    match (from, to) {
+        // the same representation
+        (I32, I32) | (I32, U32) | (U32, I32) | (U32, U32) => cb,
+        (I64, I64) | (I64, U64) | (U64, I64) | (U64, U64) => cb,
+        (F32, F32) | (F64, F64) => cb,
+
+        (I64, I32) | (I64, U32) | (U64, I32) | (U64, U32) => cb.i32_wrap_i64(),
+        (I32, I64) | (I32, U64) => cb.i64_extend_s_i32(),
+        (U32, I64) | (U32, U64) => cb.i64_extend_u_i32(),
+
+        (F32, I32) => cb.i32_trunc_s_f32(),
+        (F32, U32) => cb.i32_trunc_u_f32(),
+        (F64, I32) => cb.i32_trunc_s_f64(),
+        (F64, U32) => cb.i32_trunc_u_f64(),
+        (F32, I64) => cb.i64_trunc_s_f32(),
+        (F32, U64) => cb.i64_trunc_u_f32(),
+        (F64, I64) => cb.i64_trunc_s_f64(),
+        (F64, U64) => cb.i64_trunc_u_f64(),
+
+        (I32, F32) => cb.f32_convert_s_i32(),
+        (U32, F32) => cb.f32_convert_u_i32(),
+        (I64, F32) => cb.f32_convert_s_i64(),
+        (U64, F32) => cb.f32_convert_u_i64(),
+        (I32, F64) => cb.f64_convert_s_i32(),
+        (U32, F64) => cb.f64_convert_u_i32(),
+        (I64, F64) => cb.f64_convert_s_i64(),
+        (U64, F64) => cb.f64_convert_u_i64(),
+
+        (F64, F32) => cb.f32_demote_f64(),
+        (F32, F64) => cb.f64_promote_f32(),
+        _ => panic!("internal error: cannot convert {:?} to {:?}", from, to),
+    }
+}
+
+/// selects the instruction reinterpreting the bits of `from` on the stack as `to`
+fn reinterpret(cb: CodeBuilder, from: &lir::LTy, to: &lir::LTy) -> CodeBuilder {
+    use crate::lir::LTy::*;
+    match (from, to) {
+        (F32, I32) | (F32, U32) => cb.i32_reinterpret_f32(),
+        (F64, I64) | (F64, U64) => cb.i64_reinterpret_f64(),
+        (I32, F32) | (U32, F32) => cb.f32_reinterpret_i32(),
+        (I64, F64) | (U64, F64) => cb.f64_reinterpret_i64(),
+        _ => panic!("internal error: cannot reinterpret {:?} as {:?}", from, to),
+    }
+}
+
 fn fun_type(f: &lir::Function) -> FuncType {
     let &lir::Function {
         ref nparams,
@@ -54,6 +194,31 @@ fn fun_type(f: &lir::Function) -> FuncType {
     }
 }
 
+/// the index of the function type, adding it to the module only if no equal type is there
+/// yet. all the types of the module are added through here, so that each is added once
+pub(crate) fn intern_type(
+    md: &mut ModuleBuilder,
+    function_type_table: &mut HashMap<FuncType, TypeIndex>,
+    ftype: FuncType,
+) -> TypeIndex {
+    *function_type_table
+        .entry(ftype.clone())
+        .or_insert_with(|| md.add_type(ftype))
+}
+
+/// the type of the function a closure of the arguments and the result points to
+fn closure_type(reg: &lir::Reg, args: &[lir::Reg]) -> FuncType {
+    let mut params = vec![
+        // pointer to closure
+        ValueType::I32,
+    ];
+    params.extend(args.iter().map(|r| lty_to_valuetype(&r.0)));
+    FuncType {
+        params,
+        ret: lty_to_valuetype_opt(&reg.0),
+    }
+}
+
 pub struct LIR2WASM;
 
 impl LIR2WASM {
@@ -61,11 +226,30 @@ impl LIR2WASM {
         Self
     }
 
-    fn generate_pass(&mut self, extern_types: lir::ExternTypes) -> LIR2WASMPass {
+    fn generate_pass(&mut self, extern_types: lir::ExternTypes, config: &Config) -> LIR2WASMPass {
         let mut md = ModuleBuilder::new();
         let mut extern_functions = HashMap::new();
         let mut function_type_table = HashMap::new();
-        for ((module, name), (paramtys, retty)) in extern_types {
+        let mut function_names = Vec::new();
+        let mut interface = Interface::default();
+        let mut host_functions = Vec::new();
+        let wasi = match config.target {
+            Target::Js => None,
+            Target::Wasi => Some(wasi::import(
+                &mut md,
+                &mut function_type_table,
+                &mut function_names,
+            )),
+        };
+        // import in a fixed order for reproducible output
+        let mut extern_types = extern_types.into_iter().collect::<Vec<_>>();
+        extern_types.sort_by(|(name1, _), (name2, _)| name1.cmp(name2));
+        for ((module, name), (paramtys, retty, host_types)) in extern_types {
+            if wasi.is_some() && wasi::implements(&module, &name) {
+                // defined after the functions of the program
+                host_functions.push((module, name));
+                continue;
+            }
             let ftype = FuncType {
                 params: paramtys
                     .into_iter()
@@ -73,31 +257,61 @@ impl LIR2WASM {
                     .collect(),
                 ret: lty_to_valuetype_opt(&retty),
             };
-            let tyind = if !function_type_table.contains_key(&ftype) {
-                let tyi = md.add_type(ftype.clone());
-                function_type_table.insert(ftype, tyi.clone());
-                tyi
-            } else {
-                function_type_table[&ftype].clone()
-            };
-
+            if module != "webml-rt" {
+                interface
+                    .imports
+                    .push((module.clone(), name.clone(), ftype.clone(), host_types));
+            }
+            let tyind = intern_type(&mut md, &mut function_type_table, ftype);
             let funind = md.import(module.clone(), name.clone(), tyind);
             let fun = md.function_index_of(funind).unwrap();
+            function_names.push(format!("{}.{}", module, name));
             extern_functions.insert((module, name), fun);
         }
-        LIR2WASMPass::new(md, extern_functions, function_type_table)
+        LIR2WASMPass::new(
+            md,
+            extern_functions,
+            function_type_table,
+            function_names,
+            interface,
+            wasi,
+            host_functions,
+            config,
+        )
     }
 }
 
+/// the functions of the runtime which count the calls and the cycles of the functions
+#[derive(Debug, Clone, Copy)]
+struct Profiler {
+    init: FunctionSpaceIndex,
+    call: FunctionSpaceIndex,
+    cycles: FunctionSpaceIndex,
+}
+
 struct LIR2WASMPass {
     md: ModuleBuilder,
     init_fun: FunctionSpaceIndex,
+    collector: Collector,
+    profiler: Option<Profiler>,
     alloc_fun: FunctionSpaceIndex,
+    enter_fun: FunctionSpaceIndex,
+    leave_fun: FunctionSpaceIndex,
+    error_at: FunctionSpaceIndex,
+    // the indices of the sites in `error_sites` by the function and the kind of the error
+    site_table: HashMap<(Symbol, &'static str), u32>,
+    error_sites: ErrorSites,
     extern_functions: HashMap<(String, String), FunctionSpaceIndex>,
     function_table: HashMap<Symbol, u32>,
     function_type_table: HashMap<FuncType, TypeIndex>,
     dynamic_function_table: HashMap<Symbol, u32>,
     dynamic_function_elements: Vec<FunctionSpaceIndex>,
+    // names of the functions in the order of the function index space
+    names: NameSection,
+    function_names: Vec<String>,
+    interface: Interface,
+    wasi: Option<WasiImports>,
+    host_functions: Vec<(String, String)>,
 }
 
 impl LIR2WASMPass {
@@ -105,41 +319,96 @@ impl LIR2WASMPass {
         mut md: ModuleBuilder,
         extern_functions: HashMap<(String, String), FunctionSpaceIndex>,
         mut function_type_table: HashMap<FuncType, TypeIndex>,
+        mut function_names: Vec<String>,
+        interface: Interface,
+        wasi: Option<WasiImports>,
+        host_functions: Vec<(String, String)>,
+        config: &Config,
     ) -> Self {
-        let init_fun_ty = funtype!(());
-        let alloc_fun_ty = funtype!((i32) -> i32);
-        let init_fun_ty_index = md.add_type(init_fun_ty.clone());
-        let alloc_fun_ty_index = md.add_type(alloc_fun_ty.clone());
+        // the collector to use
+        let init_fun_ty = funtype!((i32));
+        // size and pointer maps
+        let alloc_fun_ty = funtype!((i32, i32, i32) -> i32);
+        // push and pop a frame of the shadow stack
+        let enter_fun_ty = funtype!((i32) -> i32);
+        let leave_fun_ty = funtype!((i32));
+        let init_fun_ty_index = intern_type(&mut md, &mut function_type_table, init_fun_ty);
+        let alloc_fun_ty_index = intern_type(&mut md, &mut function_type_table, alloc_fun_ty);
+        let enter_fun_ty_index = intern_type(&mut md, &mut function_type_table, enter_fun_ty);
+        let leave_fun_ty_index = intern_type(&mut md, &mut function_type_table, leave_fun_ty);
         let init_fun = md.import("webml-rt", "init", init_fun_ty_index);
         let init_fun = md.function_index_of(init_fun).unwrap();
         let alloc_fun = md.import("webml-rt", "alloc", alloc_fun_ty_index);
         let alloc_fun = md.function_index_of(alloc_fun).unwrap();
+        let enter_fun = md.import("webml-rt", "enter", enter_fun_ty_index);
+        let enter_fun = md.function_index_of(enter_fun).unwrap();
+        let leave_fun = md.import("webml-rt", "leave", leave_fun_ty_index);
+        let leave_fun = md.function_index_of(leave_fun).unwrap();
+        // records the site of the error, typed as `init`
+        let error_at = md.import("webml-rt", "error_at", init_fun_ty_index);
+        let error_at = md.function_index_of(error_at).unwrap();
+        function_names.push("webml-rt.init".into());
+        function_names.push("webml-rt.alloc".into());
+        function_names.push("webml-rt.enter".into());
+        function_names.push("webml-rt.leave".into());
+        function_names.push("webml-rt.error_at".into());
 
-        function_type_table.extend(vec![
-            (init_fun_ty, init_fun_ty_index),
-            (alloc_fun_ty, alloc_fun_ty_index),
-        ]);
+        let profiler = if config.profile {
+            // `profile_init` and `profile_call` take an `i32` as `init` does
+            // and `profile_cycles` takes the index of the function and the cycles
+            let profile_cycles_ty = funtype!((i32, i32));
+            let profile_cycles_ty_index =
+                intern_type(&mut md, &mut function_type_table, profile_cycles_ty);
+            let mut import = |name: &str, ty| {
+                let index = md.import("webml-rt", name, ty);
+                function_names.push(format!("webml-rt.{}", name));
+                md.function_index_of(index).unwrap()
+            };
+            let profiler = Profiler {
+                init: import("profile_init", init_fun_ty_index),
+                call: import("profile_call", init_fun_ty_index),
+                cycles: import("profile_cycles", profile_cycles_ty_index),
+            };
+            Some(profiler)
+        } else {
+            None
+        };
 
-        md.import(
-            "webml-rt",
-            "memory",
-            MemoryType {
-                limits: ResizableLimits::new(2),
-            },
-        );
+        // the runtime grows the memory when the heap is exhausted
+        let mut limits = ResizableLimits::new(config.memory.initial_pages);
+        if let Some(max) = config.memory.maximum() {
+            limits = limits.max(max);
+        }
+        md.import("webml-rt", "memory", MemoryType { limits });
 
         Self {
             md,
             init_fun,
+            collector: config.collector,
+            profiler,
             alloc_fun,
+            enter_fun,
+            leave_fun,
+            error_at,
+            site_table: HashMap::new(),
+            error_sites: ErrorSites::default(),
             extern_functions,
             function_table: HashMap::new(),
             function_type_table,
             dynamic_function_table: HashMap::new(),
             dynamic_function_elements: vec![],
+            names: NameSection::default(),
+            function_names,
+            interface,
+            wasi,
+            host_functions,
         }
     }
 
+    fn type_index(&mut self, ftype: FuncType) -> TypeIndex {
+        intern_type(&mut self.md, &mut self.function_type_table, ftype)
+    }
+
     fn intern_fun(&mut self, fname: &Symbol) -> u32 {
         let index = self.function_index(fname);
         let &mut Self {
@@ -164,19 +433,49 @@ impl LIR2WASMPass {
                 .collect();
         {
             for f in l.0.iter() {
-                let ftype = fun_type(f);
-                if !self.function_type_table.contains_key(&ftype) {
-                    let tyi = self.md.add_type(ftype.clone());
-                    self.function_type_table.insert(ftype, tyi);
-                }
+                self.type_index(fun_type(f));
             }
         }
+        for f in l.0.iter() {
+            self.intern_indirect(f);
+            self.add_error_sites(f);
+        }
 
         let nfunctions = l.0.len();
-        for f in l.0 {
-            self.trans_function(f);
+        if self.profiler.is_some() {
+            self.interface.profile =
+                l.0.iter()
+                    .map(|f| format!("{}@{}", f.name.0, f.name.1))
+                    .collect();
+        }
+        for (i, (module, name)) in self.host_functions.iter().enumerate() {
+            let index = FunctionIndex((nfunctions + i) as u32).into();
+            self.extern_functions
+                .insert((module.clone(), name.clone()), index);
+        }
+        let bodies = self.function_bodies(&l.0);
+        for (f, body) in l.0.into_iter().zip(bodies) {
+            self.add_names(&f);
+            self.add_function(f, body);
+        }
+        if let Some(wasi) = &self.wasi {
+            let runtime = wasi::Runtime {
+                alloc: self.alloc_fun,
+                enter: self.enter_fun,
+                leave: self.leave_fun,
+            };
+            for (module, name) in &self.host_functions {
+                let (ftype, code) = wasi::host_function(module, name, wasi, &runtime);
+                let ftype = intern_type(&mut self.md, &mut self.function_type_table, ftype);
+                NewFunction::new_function(&mut self.md, ftype, code);
+                self.function_names.push(format!("{}.{}", module, name));
+            }
         }
         let fun_table = self.md.new_table(ElemType::AnyFunc, (nfunctions as u32)..);
+        if self.wasi.is_none() {
+            // the JS glue calls the closures passed to the host as callbacks through the table
+            self.md.export("table", fun_table);
+        }
         let elems = ElemSegment {
             index: fun_table,
             offset: InitExpr(CodeBuilder::new().constant(0 as i32).end().build()),
@@ -184,20 +483,74 @@ impl LIR2WASMPass {
         };
 
         self.md.add_element(elems);
-        let main_function = FunctionBuilder::new(funtype!(()))
+        let (main_type, main_code) = FunctionBuilder::new(funtype!(()))
             .code(|cb, _params| {
-                cb.call(self.init_fun)
-                    .call(self.function_index(&Symbol::new("sml-main")))
-                    .return_()
+                let collector = match self.collector {
+                    Collector::MarkSweep => 0,
+                    Collector::Generational => 1,
+                    Collector::ReferenceCounting => 2,
+                };
+                let mut cb = cb.constant(collector).call(self.init_fun);
+                if let Some(profiler) = self.profiler {
+                    cb = cb.constant(nfunctions as i32).call(profiler.init);
+                }
+                cb = cb.call(self.function_index(&Symbol::new("sml-main")));
+                // flushes the output `TextIO` buffered, even if an exception is uncaught
+                let take = ("webml-rt".to_string(), "output_take".to_string());
+                let print = ("js-ffi".to_string(), "printString".to_string());
+                if let (Some(&take), Some(&print)) = (
+                    self.extern_functions.get(&take),
+                    self.extern_functions.get(&print),
+                ) {
+                    cb = cb.constant(0).call(take).call(print);
+                }
+                cb.return_()
             })
             .build();
-        let main_function = self.md.new_function(main_function);
-        self.md.start(main_function);
+        let main_type = self.type_index(main_type);
+        let main_function = NewFunction::new_function(&mut self.md, main_type, main_code);
+        match self.wasi {
+            None => self.md.start(main_function),
+            Some(_) => {
+                self.md.export("_start", main_function);
+                // WASI reads the iovecs from the memory named `memory`
+                self.md.export("memory", MemoryIndex(0));
+            }
+        }
+        self.function_names.push("main".into());
 
         let mut ret = ModuleBuilder::new();
         // FIXME:
         ::std::mem::swap(&mut self.md, &mut ret);
-        ret.build()
+        let mut module = Module::new(ret.build());
+        let mut names = ::std::mem::replace(&mut self.names, NameSection::default());
+        names.functions = self
+            .function_names
+            .drain(..)
+            .enumerate()
+            .map(|(i, name)| (i as u32, name))
+            .collect();
+        module.custom_sections.push(names.encode());
+        let error_sites = ::std::mem::replace(&mut self.error_sites, ErrorSites::default());
+        module.custom_sections.push(error_sites.encode());
+        module.interface = ::std::mem::replace(&mut self.interface, Interface::default());
+        module
+    }
+
+    fn add_names(&mut self, f: &lir::Function) {
+        let index = self.function_names.len() as u32;
+        let name = format!("{}@{}", f.name.0, f.name.1);
+        self.function_names.push(name);
+        let locals = f
+            .reg_names
+            .iter()
+            .enumerate()
+            .filter_map(|(i, var)| {
+                var.as_ref()
+                    .map(|var| (i as u32, format!("{}@{}", var.0, var.1)))
+            })
+            .collect();
+        self.names.locals.push((index, locals));
     }
 
     fn function_index(&self, fname: &Symbol) -> FunctionSpaceIndex {
@@ -205,9 +558,85 @@ impl LIR2WASMPass {
         Into::<FunctionSpaceIndex>::into(findex)
     }
 
-    fn trans_function(&mut self, f: lir::Function) {
-        use crate::lir::Value::*;
+    /// registers the types of the indirect calls and the functions stored as values,
+    /// so that the bodies only look them up
+    fn intern_indirect(&mut self, f: &lir::Function) {
+        for op in f.body.iter().flat_map(|b| &b.body) {
+            match op {
+                lir::Op::StoreFnPtr(_, value) => {
+                    self.intern_fun(value);
+                }
+                lir::Op::ClosureCall(reg, _, args) => {
+                    self.type_index(closure_type(reg, args));
+                }
+                _ => (),
+            }
+        }
+    }
+
+    /// numbers the sites where the function may fail, so that the bodies only look them up
+    fn add_error_sites(&mut self, f: &lir::Function) {
+        let name = format!("{}@{}", f.name.0, f.name.1);
+        for op in f.body.iter().flat_map(|b| &b.body) {
+            if let Some(kind) = error_kind(op) {
+                let site = self.error_sites.site(kind, &name);
+                self.site_table.insert((f.name.clone(), kind), site);
+            }
+        }
+    }
+
+    /// traps unless the divisor is nonzero, recording the site first.
+    /// the division would trap as well, but without the site
+    fn check_divisor(
+        &self,
+        cb: CodeBuilder,
+        f: &lir::Function,
+        divisor: LocalIndex,
+        wide: bool,
+    ) -> CodeBuilder {
+        let site = self.site_table[&(f.name.clone(), ErrorSites::DIVIDE_BY_ZERO)];
+        let mut cb = cb.block(BlockType(None)).get_local(divisor);
+        if wide {
+            cb = cb.constant(0i64).i64_ne();
+        }
+        cb.br_if(0)
+            .constant(site as i32)
+            .call(self.error_at)
+            .unreachable()
+            .end()
+    }
+
+    #[cfg(feature = "parallel")]
+    fn function_bodies(&self, functions: &[lir::Function]) -> Vec<Code> {
+        use rayon::prelude::*;
+        functions
+            .par_iter()
+            .map(|f| self.function_body(f))
+            .collect()
+    }
+
+    #[cfg(not(feature = "parallel"))]
+    fn function_bodies(&self, functions: &[lir::Function]) -> Vec<Code> {
+        functions.iter().map(|f| self.function_body(f)).collect()
+    }
+
+    fn add_function(&mut self, f: lir::Function, body: Code) {
         let ftype = fun_type(&f);
+        // use calculated type index,
+        let index = NewFunction::new_function(&mut self.md, self.function_type_table[&ftype], body);
+        if let Some(export) = f.export {
+            self.interface.exports.push((export.name.clone(), ftype));
+            self.md.export(export.name, index);
+        }
+    }
+
+    /// generates the body of the function. the bodies only read the tables of the module,
+    /// so that they can be generated in parallel
+    fn function_body(&self, f: &lir::Function) -> Code {
+        use crate::lir::Value::*;
+        let span = debug_span!("codegen", function = %f.name.0, id = f.name.1);
+        let _enter = span.enter();
+        let ftype = fun_type(f);
         let lir::Function {
             nparams,
             regs,
@@ -218,10 +647,23 @@ impl LIR2WASMPass {
             .iter()
             .map(|reg| lty_to_valuetype(reg))
             .collect::<Vec<_>>();
-        let regtys = tys.split_off(nparams as usize);
+        let regtys = tys.split_off(*nparams as usize);
         let mut fb = FunctionBuilder::new(ftype.clone());
 
         let mut locals = fb.new_locals(regtys);
+        // the registers holding heap pointers are the roots of the garbage collection.
+        // they are saved to a frame of the shadow stack before the operations which may collect
+        let roots = regs
+            .iter()
+            .enumerate()
+            .filter(|(_, ty)| **ty == lir::LTy::Ptr)
+            .map(|(i, _)| i)
+            .collect::<Vec<_>>();
+        let frame = if !roots.is_empty() && body.iter().any(|b| b.body.iter().any(may_collect)) {
+            Some(fb.new_local(ValueType::I32))
+        } else {
+            None
+        };
 
         let fb = fb.code(|mut cb, params| {
             let body = self.alloc_loop_block_break(&body);
@@ -229,6 +671,17 @@ impl LIR2WASMPass {
             params.append(&mut locals);
             let mut scope = Vec::new();
 
+            if let Some(frame) = frame {
+                cb = cb
+                    .constant(roots.len() as i32)
+                    .call(self.enter_fun)
+                    .set_local(frame);
+            }
+            let index = self.function_table[&f.name] as i32;
+            if let Some(profiler) = self.profiler {
+                cb = cb.constant(index).call(profiler.call);
+            }
+
             macro_rules! reg {
                 ($reg: expr) => {
                     params[$reg.1 as usize]
@@ -268,7 +721,39 @@ impl LIR2WASMPass {
                     }
                     Control::Body(b) => {
                         use crate::lir::Op::*;
+                        if let Some(profiler) = self.profiler {
+                            cb = cb
+                                .constant(index)
+                                .constant(cycles(b) as i32)
+                                .call(profiler.cycles);
+                        }
                         for op in &b.body {
+                            match frame {
+                                Some(frame) if may_collect(op) => {
+                                    for (slot, &root) in roots.iter().enumerate() {
+                                        cb = cb
+                                            .get_local(frame)
+                                            .get_local(params[root])
+                                            .i32_store(slot as u32 * 4);
+                                    }
+                                }
+                                _ => (),
+                            }
+                            match op {
+                                DivI32(_, _, divisor)
+                                | ModI32(_, _, divisor)
+                                | DivU32(_, _, divisor)
+                                | ModU32(_, _, divisor) => {
+                                    cb = self.check_divisor(cb, f, reg!(divisor), false)
+                                }
+                                DivI64(_, _, divisor)
+                                | ModI64(_, _, divisor)
+                                | DivU64(_, _, divisor)
+                                | ModU64(_, _, divisor) => {
+                                    cb = self.check_divisor(cb, f, reg!(divisor), true)
+                                }
+                                _ => (),
+                            }
                             match op {
                                 ConstI32(reg, c) | ConstU32(reg, c) => {
                                     cb = cb.constant(*c as i32).set_local(reg!(reg))
@@ -392,6 +877,48 @@ impl LIR2WASMPass {
                                         .i32_le_u()
                                         .set_local(reg!(reg1))
                                 }
+                                ShrI32(reg1, reg2, reg3) => {
+                                    cb = cb
+                                        .get_local(reg!(reg2))
+                                        .get_local(reg!(reg3))
+                                        .i32_shr_s()
+                                        .set_local(reg!(reg1))
+                                }
+                                AndU32(reg1, reg2, reg3) => {
+                                    cb = cb
+                                        .get_local(reg!(reg2))
+                                        .get_local(reg!(reg3))
+                                        .i32_and()
+                                        .set_local(reg!(reg1))
+                                }
+                                OrU32(reg1, reg2, reg3) => {
+                                    cb = cb
+                                        .get_local(reg!(reg2))
+                                        .get_local(reg!(reg3))
+                                        .i32_or()
+                                        .set_local(reg!(reg1))
+                                }
+                                XorU32(reg1, reg2, reg3) => {
+                                    cb = cb
+                                        .get_local(reg!(reg2))
+                                        .get_local(reg!(reg3))
+                                        .i32_xor()
+                                        .set_local(reg!(reg1))
+                                }
+                                ShlU32(reg1, reg2, reg3) => {
+                                    cb = cb
+                                        .get_local(reg!(reg2))
+                                        .get_local(reg!(reg3))
+                                        .i32_shl()
+                                        .set_local(reg!(reg1))
+                                }
+                                ShrU32(reg1, reg2, reg3) => {
+                                    cb = cb
+                                        .get_local(reg!(reg2))
+                                        .get_local(reg!(reg3))
+                                        .i32_shr_u()
+                                        .set_local(reg!(reg1))
+                                }
                                 MoveI32(reg1, reg2)
                                 | MoveU32(reg1, reg2)
                                 | MoveI64(reg1, reg2)
@@ -729,29 +1256,61 @@ impl LIR2WASMPass {
                                         .f64_load(addr.1)
                                         .set_local(reg!(reg));
                                 }
+                                FloorF64(reg1, reg2) => {
+                                    cb = cb.get_local(reg!(reg2)).f64_floor().set_local(reg!(reg1));
+                                }
+                                CeilF64(reg1, reg2) => {
+                                    cb = cb.get_local(reg!(reg2)).f64_ceil().set_local(reg!(reg1));
+                                }
+                                TruncF64(reg1, reg2) => {
+                                    cb = cb.get_local(reg!(reg2)).f64_trunc().set_local(reg!(reg1));
+                                }
+                                NearestF64(reg1, reg2) => {
+                                    cb = cb
+                                        .get_local(reg!(reg2))
+                                        .f64_nearest()
+                                        .set_local(reg!(reg1));
+                                }
+                                SqrtF64(reg1, reg2) => {
+                                    cb = cb.get_local(reg!(reg2)).f64_sqrt().set_local(reg!(reg1));
+                                }
+
+                                Convert(reg1, reg2) => {
+                                    cb = convert(cb.get_local(reg!(reg2)), &reg2.0, &reg1.0)
+                                        .set_local(reg!(reg1));
+                                }
+                                Reinterpret(reg1, reg2) => {
+                                    cb = reinterpret(cb.get_local(reg!(reg2)), &reg2.0, &reg1.0)
+                                        .set_local(reg!(reg1));
+                                }
 
-                                HeapAlloc(reg, value, _tys) => {
+                                HeapAlloc(reg, value, layout) => {
                                     cb = match value {
                                         I(i) => cb.constant(*i as i32),
                                         R(r) => cb.get_local(reg!(r)),
                                     };
 
-                                    cb = cb//.constant(tys_to_ptrbits(tys) as i32)
+                                    let (pointers, ambiguous) = pointer_maps(layout);
+                                    cb = cb
+                                        .constant(pointers as i32)
+                                        .constant(ambiguous as i32)
                                         .call(self.alloc_fun)
                                         .set_local(reg!(reg))
                                 }
-                                StackAlloc(reg, size, _tys) => {
+                                StackAlloc(reg, size, layout) => {
                                     // allocating to heap, not stack
+                                    let (pointers, ambiguous) = pointer_maps(layout);
                                     cb = cb
                                         .constant(*size as i32)
-                                        //.constant(tys_to_ptrbits(tys) as i32)
+                                        .constant(pointers as i32)
+                                        .constant(ambiguous as i32)
                                         .call(self.alloc_fun)
                                         .set_local(reg!(reg))
                                 }
                                 StoreFnPtr(addr, value) => {
                                     cb = cb
                                         .get_local(reg!(addr.0))
-                                        .constant(self.intern_fun(&value) as i32)
+                                        .constant(self.dynamic_function_table[value] as i32)
                                         .i32_store(addr.1);
                                 }
 
@@ -768,23 +1327,15 @@ impl LIR2WASMPass {
                                         cb = cb.get_local(reg!(arg))
                                     }
 
-                                    let ret = lty_to_valuetype_opt(&reg.0);
-                                    let ftype = {
-                                        let mut params = vec![
-                                            // pointer to closure
-                                            ValueType::I32,
-                                        ];
-                                        params.extend(args.iter().map(|r| lty_to_valuetype(&r.0)));
-                                        FuncType {
-                                            params,
-                                            ret: ret.clone(),
-                                        }
-                                    };
+                                    let ftype = closure_type(reg, args);
+                                    let ret = ftype.ret.clone();
 
                                     cb = cb
                                         .get_local(reg!(fun))
                                         // load function
                                         .i32_load(0)
+                                        // the callee may not be defined in this module,
+                                        // e.g. a continuation never created
                                         .call_indirect(self.function_type_table[&ftype], false);
 
                                     if let Some(_) = ret {
@@ -803,6 +1354,10 @@ impl LIR2WASMPass {
                                     }
                                 }
                                 ExternCall(reg, module, fun, args) => {
+                                    if let Some(kind) = error_kind(op) {
+                                        let site = self.site_table[&(f.name.clone(), kind)];
+                                        cb = cb.constant(site as i32).call(self.error_at);
+                                    }
                                     for arg in args.iter() {
                                         cb = cb.get_local(reg!(arg))
                                     }
@@ -820,6 +1375,9 @@ impl LIR2WASMPass {
                                     cb = cb.unreachable();
                                 }
                                 Ret(reg) => {
+                                    if let Some(frame) = frame {
+                                        cb = cb.get_local(frame).call(self.leave_fun);
+                                    }
                                     cb = match reg {
                                         Some(r) => cb.get_local(reg!(r)),
                                         None => cb,
@@ -834,14 +1392,13 @@ impl LIR2WASMPass {
             cb
         });
         let (_, body) = fb.build();
-        // use calculated type index,
-        NewFunction::new_function(&mut self.md, self.function_type_table[&ftype], body);
+        body
     }
 
     /// allocate block and loop scopes for jump -> break transformation.
     /// Forward jump will be block + break,
     /// and backword jump will be loop + break in following transformation.
-    fn alloc_loop_block_break<'a>(&mut self, v: &'a [lir::Block]) -> Vec<Control<'a>> {
+    fn alloc_loop_block_break<'a>(&self, v: &'a [lir::Block]) -> Vec<Control<'a>> {
         // 1. calculate minimum coverings of loops, blocks and insert them
         // 2. adjust interleavings (lift down loopends and lift up blocks)
 
@@ -851,13 +1408,13 @@ impl LIR2WASMPass {
         ret
     }
 
-    fn insert_loop_block<'a>(&mut self, v: Vec<Control<'a>>) -> Vec<Control<'a>> {
+    fn insert_loop_block<'a>(&self, v: Vec<Control<'a>>) -> Vec<Control<'a>> {
         let v = self.insert_loop(v);
         let v = self.insert_block(v);
         v
     }
 
-    fn insert_loop<'a>(&mut self, v: Vec<Control<'a>>) -> Vec<Control<'a>> {
+    fn insert_loop<'a>(&self, v: Vec<Control<'a>>) -> Vec<Control<'a>> {
         let mut loop_targets = HashSet::new();
         let mut labels = HashMap::new();
         for (i, c) in v.iter().enumerate() {
@@ -911,7 +1468,7 @@ impl LIR2WASMPass {
         ret
     }
 
-    fn insert_block<'a>(&mut self, v: Vec<Control<'a>>) -> Vec<Control<'a>> {
+    fn insert_block<'a>(&self, v: Vec<Control<'a>>) -> Vec<Control<'a>> {
         let mut block_targets = HashSet::new();
         let mut labels = HashMap::new();
         for (i, c) in v.iter().enumerate() {
@@ -964,13 +1521,13 @@ impl LIR2WASMPass {
         ret
     }
 
-    fn adjust_loop_block<'a>(&mut self, v: Vec<Control<'a>>) -> Vec<Control<'a>> {
+    fn adjust_loop_block<'a>(&self, v: Vec<Control<'a>>) -> Vec<Control<'a>> {
         let v = self.adjust_loop(v);
         let v = self.adjust_block(v);
         v
     }
 
-    fn adjust_loop<'a>(&mut self, v: Vec<Control<'a>>) -> Vec<Control<'a>> {
+    fn adjust_loop<'a>(&self, v: Vec<Control<'a>>) -> Vec<Control<'a>> {
         let mut ret = Vec::new();
         let mut scope = Vec::new();
         let mut defers = HashMap::new();
@@ -1003,10 +1560,11 @@ impl LIR2WASMPass {
         ret
     }
 
-    fn adjust_block<'a>(&mut self, v: Vec<Control<'a>>) -> Vec<Control<'a>> {
+    fn adjust_block<'a>(&self, v: Vec<Control<'a>>) -> Vec<Control<'a>> {
         let mut tmp = Vec::new();
         let mut scope = Vec::new();
-        let mut defers = HashMap::<&lir::Label, Vec<&lir::Label>>::new();
+        // blocks which must be opened outside of the blocks ending before them
+        let mut pending = HashSet::new();
         for c in v.into_iter().rev() {
             match c {
                 Control::BlockEnd(name) | Control::LoopEnd(name) => {
@@ -1014,29 +1572,31 @@ impl LIR2WASMPass {
                     tmp.push(c);
                 }
                 Control::Block(name) | Control::Loop(name) => {
-                    let last_name = scope.pop().unwrap();
-                    if name == last_name {
-                        tmp.extend(
-                            self.resolve_defers(name, &mut defers)
-                                .into_iter()
-                                .map(Control::Block),
-                        );
+                    if scope.last() == Some(&name) {
+                        scope.pop();
+                        tmp.push(c);
+                        while let Some(top) = scope.last().cloned() {
+                            if !pending.remove(top) {
+                                break;
+                            }
+                            scope.pop();
+                            tmp.push(Control::Block(top));
+                        }
                     } else {
                         // Note: in the arm of Loop, this else clause must not occure,
                         // thus assuming safe.
-                        defers.entry(last_name).or_insert(Vec::new()).push(name);
-                        scope.push(last_name);
+                        pending.insert(name);
                     }
                 }
                 c => tmp.push(c),
             }
         }
-        assert!(defers.is_empty());
+        assert!(pending.is_empty());
         tmp.into_iter().rev().collect()
     }
 
     fn resolve_defers<'a>(
-        &mut self,
+        &self,
         name: &'a lir::Label,
         defers: &mut HashMap<&'a lir::Label, Vec<&'a lir::Label>>,
     ) -> Vec<&'a lir::Label> {
@@ -1062,9 +1622,14 @@ impl<E> Pass<(lir::ExternTypes, lir::LIR), E> for LIR2WASM {
     fn trans(
         &mut self,
         (extern_types, lir): (lir::ExternTypes, lir::LIR),
-        _: &Config,
+        config: &Config,
     ) -> ::std::result::Result<Self::Target, E> {
-        let mut pass = self.generate_pass(extern_types);
-        Ok(pass.trans_lir(lir))
+        let metadata = metadata(&lir, config);
+        let types = type_section(&lir);
+        let mut pass = self.generate_pass(extern_types, config);
+        let mut module = pass.trans_lir(lir);
+        module.custom_sections.push(metadata.encode());
+        module.custom_sections.push(types.encode());
+        Ok(module)
     }
 }
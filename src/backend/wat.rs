@@ -0,0 +1,450 @@
+//! printer of the WebAssembly text format.
+//! it reads the binary module with wasmparser so that it shows exactly what is emitted.
+
+use std::io::{self, Write};
+use wasmparser::{
+    BinaryReaderError, DataKind, ElementItem, ElementKind, ExternalKind, FuncType,
+    ImportSectionEntryType, InitExpr, ModuleReader, Operator, ResizableLimits, SectionCode, Type,
+    TypeOrFuncType,
+};
+
+pub(crate) fn invalid(e: BinaryReaderError) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("{} at offset {}", e.message(), e.offset()),
+    )
+}
+
+fn unsupported(what: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, what.into())
+}
+
+fn valtype(ty: Type) -> io::Result<&'static str> {
+    match ty {
+        Type::I32 => Ok("i32"),
+        Type::I64 => Ok("i64"),
+        Type::F32 => Ok("f32"),
+        Type::F64 => Ok("f64"),
+        Type::FuncRef => Ok("funcref"),
+        Type::ExternRef => Ok("externref"),
+        ty => Err(unsupported(format!("unsupported value type {:?}", ty))),
+    }
+}
+
+fn limits(limits: ResizableLimits) -> String {
+    match limits.maximum {
+        None => format!("{}", limits.initial),
+        Some(max) => format!("{} {}", limits.initial, max),
+    }
+}
+
+fn float(f: f64) -> String {
+    if f.is_nan() {
+        "nan".into()
+    } else if f.is_infinite() {
+        if f < 0.0 { "-inf" } else { "inf" }.into()
+    } else {
+        format!("{:?}", f)
+    }
+}
+
+fn string(bytes: &[u8]) -> String {
+    let mut s = String::new();
+    for &b in bytes {
+        match b {
+            b'"' | b'\\' => s.push_str(&format!("\\{}", b as char)),
+            0x20..=0x7e => s.push(b as char),
+            _ => s.push_str(&format!("\\{:02x}", b)),
+        }
+    }
+    s
+}
+
+fn functype(ty: &FuncType) -> io::Result<String> {
+    let mut s = String::new();
+    if !ty.params.is_empty() {
+        let params = ty
+            .params
+            .iter()
+            .map(|ty| valtype(*ty))
+            .collect::<io::Result<Vec<_>>>()?;
+        s.push_str(&format!(" (param {})", params.join(" ")));
+    }
+    if !ty.returns.is_empty() {
+        let results = ty
+            .returns
+            .iter()
+            .map(|ty| valtype(*ty))
+            .collect::<io::Result<Vec<_>>>()?;
+        s.push_str(&format!(" (result {})", results.join(" ")));
+    }
+    Ok(s)
+}
+
+fn blocktype(ty: TypeOrFuncType) -> io::Result<String> {
+    match ty {
+        TypeOrFuncType::Type(Type::EmptyBlockType) => Ok(String::new()),
+        TypeOrFuncType::Type(ty) => Ok(format!(" (result {})", valtype(ty)?)),
+        TypeOrFuncType::FuncType(index) => Ok(format!(" (type {})", index)),
+    }
+}
+
+/// the name of the instruction in the text format, derived from the name of the variant.
+/// `I32TruncF64S { .. }` is `i32.trunc_f64_s`
+fn mnemonic(op: &Operator) -> String {
+    let variant = format!("{:?}", op);
+    let variant = variant.split(' ').next().unwrap_or_default();
+    let mut words = Vec::<String>::new();
+    for c in variant.chars() {
+        match words.last_mut() {
+            Some(word) if !c.is_ascii_uppercase() => word.push(c),
+            _ => words.push(c.to_ascii_lowercase().to_string()),
+        }
+    }
+    match words.split_first() {
+        Some((first, rest)) if !rest.is_empty() => format!("{}.{}", first, rest.join("_")),
+        _ => words.concat(),
+    }
+}
+
+/// how an instruction affects the nesting of the blocks
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Nest {
+    Plain,
+    Open,
+    Else,
+    End,
+}
+
+fn instr(op: &Operator) -> io::Result<(String, Nest)> {
+    use self::Operator::*;
+    let plain = |s: String| Ok((s, Nest::Plain));
+    // the natural alignment of the memory instructions
+    let natural = match op {
+        I32Load8S { memarg }
+        | I32Load8U { memarg }
+        | I64Load8S { memarg }
+        | I64Load8U { memarg }
+        | I32Store8 { memarg }
+        | I64Store8 { memarg } => Some((memarg, 0)),
+        I32Load16S { memarg }
+        | I32Load16U { memarg }
+        | I64Load16S { memarg }
+        | I64Load16U { memarg }
+        | I32Store16 { memarg }
+        | I64Store16 { memarg } => Some((memarg, 1)),
+        I32Load { memarg }
+        | F32Load { memarg }
+        | I64Load32S { memarg }
+        | I64Load32U { memarg }
+        | I32Store { memarg }
+        | F32Store { memarg }
+        | I64Store32 { memarg } => Some((memarg, 2)),
+        I64Load { memarg } | F64Load { memarg } | I64Store { memarg } | F64Store { memarg } => {
+            Some((memarg, 3))
+        }
+        _ => None,
+    };
+    if let Some((memarg, natural)) = natural {
+        let mut s = mnemonic(op);
+        if memarg.offset != 0 {
+            s.push_str(&format!(" offset={}", memarg.offset));
+        }
+        if memarg.flags != natural {
+            s.push_str(&format!(" align={}", 1u64 << memarg.flags));
+        }
+        return plain(s);
+    }
+    match op {
+        Block { ty } => Ok((format!("block{}", blocktype(*ty)?), Nest::Open)),
+        Loop { ty } => Ok((format!("loop{}", blocktype(*ty)?), Nest::Open)),
+        If { ty } => Ok((format!("if{}", blocktype(*ty)?), Nest::Open)),
+        Else => Ok(("else".into(), Nest::Else)),
+        End => Ok(("end".into(), Nest::End)),
+        Br { relative_depth } => plain(format!("br {}", relative_depth)),
+        BrIf { relative_depth } => plain(format!("br_if {}", relative_depth)),
+        BrTable { table } => {
+            let (targets, default) = table.read_table().map_err(invalid)?;
+            let mut s = "br_table".to_string();
+            for target in targets.iter().chain(Some(&default)) {
+                s.push_str(&format!(" {}", target));
+            }
+            plain(s)
+        }
+        Call { function_index } => plain(format!("call {}", function_index)),
+        CallIndirect { index, .. } => plain(format!("call_indirect (type {})", index)),
+        ReturnCall { function_index } => plain(format!("return_call {}", function_index)),
+        ReturnCallIndirect { index, .. } => {
+            plain(format!("return_call_indirect (type {})", index))
+        }
+        LocalGet { local_index } => plain(format!("local.get {}", local_index)),
+        LocalSet { local_index } => plain(format!("local.set {}", local_index)),
+        LocalTee { local_index } => plain(format!("local.tee {}", local_index)),
+        GlobalGet { global_index } => plain(format!("global.get {}", global_index)),
+        GlobalSet { global_index } => plain(format!("global.set {}", global_index)),
+        MemorySize { .. } => plain("memory.size".into()),
+        MemoryGrow { .. } => plain("memory.grow".into()),
+        I32Const { value } => plain(format!("i32.const {}", value)),
+        I64Const { value } => plain(format!("i64.const {}", value)),
+        F32Const { value } => plain(format!(
+            "f32.const {}",
+            float(f32::from_bits(value.bits()) as f64)
+        )),
+        F64Const { value } => plain(format!("f64.const {}", float(f64::from_bits(value.bits())))),
+        // the rest of the instructions we emit have no immediates
+        op if !format!("{:?}", op).contains(' ') => plain(mnemonic(op)),
+        op => Err(unsupported(format!("unsupported instruction {:?}", op))),
+    }
+}
+
+/// constant expressions used in globals, elements and data
+fn const_expr(expr: &InitExpr) -> io::Result<String> {
+    let mut exprs = Vec::new();
+    let mut r = expr.get_operators_reader();
+    loop {
+        match instr(&r.read().map_err(invalid)?)? {
+            (_, Nest::End) => break,
+            (instr, _) => exprs.push(format!("({})", instr)),
+        }
+    }
+    Ok(exprs.join(" "))
+}
+
+struct Printer<'a> {
+    types: Vec<FuncType>,
+    // type of each function including imported ones
+    functions: Vec<u32>,
+    nimported: usize,
+    w: &'a mut dyn Write,
+}
+
+impl<'a> Printer<'a> {
+    fn module(&mut self, code: &[u8]) -> io::Result<()> {
+        let mut r = ModuleReader::new(code).map_err(invalid)?;
+        writeln!(self.w, "(module")?;
+        while !r.eof() {
+            let section = r.read().map_err(invalid)?;
+            match section.code {
+                SectionCode::Custom { name, .. } => {
+                    writeln!(
+                        self.w,
+                        "  ;; custom section \"{}\"",
+                        string(name.as_bytes())
+                    )?;
+                }
+                SectionCode::Type => {
+                    let mut r = section.get_type_section_reader().map_err(invalid)?;
+                    for i in 0..r.get_count() {
+                        let ty = r.read().map_err(invalid)?;
+                        writeln!(self.w, "  (type (;{};) (func{}))", i, functype(&ty)?)?;
+                        self.types.push(ty);
+                    }
+                }
+                SectionCode::Import => {
+                    let mut r = section.get_import_section_reader().map_err(invalid)?;
+                    for _ in 0..r.get_count() {
+                        let import = r.read().map_err(invalid)?;
+                        let desc = match import.ty {
+                            ImportSectionEntryType::Function(ty) => {
+                                self.functions.push(ty);
+                                self.nimported += 1;
+                                format!("(func (;{};) (type {}))", self.functions.len() - 1, ty)
+                            }
+                            ImportSectionEntryType::Table(table) => format!(
+                                "(table {} {})",
+                                limits(table.limits),
+                                valtype(table.element_type)?
+                            ),
+                            ImportSectionEntryType::Memory(memory) => {
+                                format!("(memory {})", limits(memory.limits))
+                            }
+                            ImportSectionEntryType::Global(global) => {
+                                let ty = valtype(global.content_type)?;
+                                if global.mutable {
+                                    format!("(global (mut {}))", ty)
+                                } else {
+                                    format!("(global {})", ty)
+                                }
+                            }
+                        };
+                        writeln!(
+                            self.w,
+                            "  (import \"{}\" \"{}\" {})",
+                            string(import.module.as_bytes()),
+                            string(import.field.as_bytes()),
+                            desc
+                        )?;
+                    }
+                }
+                SectionCode::Function => {
+                    let mut r = section.get_function_section_reader().map_err(invalid)?;
+                    for _ in 0..r.get_count() {
+                        self.functions.push(r.read().map_err(invalid)?);
+                    }
+                }
+                SectionCode::Table => {
+                    let mut r = section.get_table_section_reader().map_err(invalid)?;
+                    for _ in 0..r.get_count() {
+                        let table = r.read().map_err(invalid)?;
+                        writeln!(
+                            self.w,
+                            "  (table {} {})",
+                            limits(table.limits),
+                            valtype(table.element_type)?
+                        )?;
+                    }
+                }
+                SectionCode::Memory => {
+                    let mut r = section.get_memory_section_reader().map_err(invalid)?;
+                    for _ in 0..r.get_count() {
+                        let memory = r.read().map_err(invalid)?;
+                        writeln!(self.w, "  (memory {})", limits(memory.limits))?;
+                    }
+                }
+                SectionCode::Global => {
+                    let mut r = section.get_global_section_reader().map_err(invalid)?;
+                    for _ in 0..r.get_count() {
+                        let global = r.read().map_err(invalid)?;
+                        let ty = valtype(global.ty.content_type)?;
+                        let ty = if global.ty.mutable {
+                            format!("(mut {})", ty)
+                        } else {
+                            ty.to_string()
+                        };
+                        writeln!(
+                            self.w,
+                            "  (global {} {})",
+                            ty,
+                            const_expr(&global.init_expr)?
+                        )?;
+                    }
+                }
+                SectionCode::Export => {
+                    let mut r = section.get_export_section_reader().map_err(invalid)?;
+                    for _ in 0..r.get_count() {
+                        let export = r.read().map_err(invalid)?;
+                        let kind = match export.kind {
+                            ExternalKind::Function => "func",
+                            ExternalKind::Table => "table",
+                            ExternalKind::Memory => "memory",
+                            ExternalKind::Global => "global",
+                        };
+                        writeln!(
+                            self.w,
+                            "  (export \"{}\" ({} {}))",
+                            string(export.field.as_bytes()),
+                            kind,
+                            export.index
+                        )?;
+                    }
+                }
+                SectionCode::Start => {
+                    let start = section.get_start_section_content().map_err(invalid)?;
+                    writeln!(self.w, "  (start {})", start)?;
+                }
+                SectionCode::Element => {
+                    let mut r = section.get_element_section_reader().map_err(invalid)?;
+                    for _ in 0..r.get_count() {
+                        let element = r.read().map_err(invalid)?;
+                        let offset = match element.kind {
+                            ElementKind::Active { init_expr, .. } => const_expr(&init_expr)?,
+                            _ => return Err(unsupported("unsupported passive element")),
+                        };
+                        let mut items = element.items.get_items_reader().map_err(invalid)?;
+                        let mut funs = Vec::new();
+                        for _ in 0..items.get_count() {
+                            match items.read().map_err(invalid)? {
+                                ElementItem::Func(f) => funs.push(f.to_string()),
+                                ElementItem::Null(_) => {
+                                    return Err(unsupported("unsupported null element"))
+                                }
+                            }
+                        }
+                        writeln!(self.w, "  (elem {} func {})", offset, funs.join(" "))?;
+                    }
+                }
+                SectionCode::Code => {
+                    let mut r = section.get_code_section_reader().map_err(invalid)?;
+                    for i in 0..r.get_count() as usize {
+                        let body = r.read().map_err(invalid)?;
+                        self.function(self.nimported + i, &body)?;
+                    }
+                }
+                SectionCode::Data => {
+                    let mut r = section.get_data_section_reader().map_err(invalid)?;
+                    for _ in 0..r.get_count() {
+                        let data = r.read().map_err(invalid)?;
+                        let offset = match data.kind {
+                            DataKind::Active { init_expr, .. } => const_expr(&init_expr)?,
+                            DataKind::Passive => {
+                                return Err(unsupported("unsupported passive data"))
+                            }
+                        };
+                        writeln!(self.w, "  (data {} \"{}\")", offset, string(data.data))?;
+                    }
+                }
+                SectionCode::DataCount => (),
+            }
+        }
+        writeln!(self.w, ")")
+    }
+
+    fn function(&mut self, index: usize, body: &wasmparser::FunctionBody) -> io::Result<()> {
+        let ty = *self
+            .functions
+            .get(index)
+            .ok_or_else(|| unsupported("function without type"))?;
+        let functy = self
+            .types
+            .get(ty as usize)
+            .ok_or_else(|| unsupported("unknown type"))?;
+        writeln!(
+            self.w,
+            "  (func (;{};) (type {}){}",
+            index,
+            ty,
+            functype(functy)?
+        )?;
+        let mut locals = Vec::new();
+        let mut r = body.get_locals_reader().map_err(invalid)?;
+        for _ in 0..r.get_count() {
+            let (count, ty) = r.read().map_err(invalid)?;
+            let ty = valtype(ty)?;
+            locals.extend((0..count).map(|_| ty));
+        }
+        if !locals.is_empty() {
+            writeln!(self.w, "    (local {})", locals.join(" "))?;
+        }
+        let mut r = body.get_operators_reader().map_err(invalid)?;
+        let mut depth = 0;
+        loop {
+            let (instr, nest) = instr(&r.read().map_err(invalid)?)?;
+            let indent = match nest {
+                Nest::End if depth == 0 => break,
+                Nest::End => {
+                    depth -= 1;
+                    depth
+                }
+                Nest::Else => depth - 1,
+                Nest::Open => {
+                    depth += 1;
+                    depth - 1
+                }
+                Nest::Plain => depth,
+            };
+            writeln!(self.w, "    {}{}", "  ".repeat(indent), instr)?;
+        }
+        writeln!(self.w, "  )")
+    }
+}
+
+/// prints the binary module `code` in the text format
+pub fn print_wat<W: Write>(code: &[u8], w: &mut W) -> io::Result<()> {
+    let mut printer = Printer {
+        types: Vec::new(),
+        functions: Vec::new(),
+        nimported: 0,
+        w,
+    };
+    printer.module(code)
+}
@@ -0,0 +1,312 @@
+//! the cache of the compiled modules in `Config::cache_dir`, which lets the builds skip
+//! compiling the programs compiled before.
+//! the entries are keyed by the hash of the compiler version, the options affecting the
+//! code and the sources. the entry also records them to tell the programs with the same hash apart.
+//! the front end infers the types of the whole program, so a program is cached as a whole.
+
+use crate::backend::module::{read_name, read_u32, write_name, write_u32};
+use crate::backend::{CustomSection, Interface, Module};
+use crate::config::Config;
+use crate::lir::{HostTy, HostTypes, LTy};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+use wasm::{FuncType, ValueType};
+
+const MAGIC: &[u8] = b"webml-cache\0";
+
+/// the 64-bit FNV-1a hash, which is stable across the builds of the compiler
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &b| {
+        (hash ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// the options which change the output, in a fixed order
+fn fingerprint(config: &Config) -> String {
+    let sorted = |set: &mut dyn Iterator<Item = String>| {
+        let mut items = set.collect::<Vec<_>>();
+        items.sort();
+        items.join(",")
+    };
+    format!(
+        "enable={};disable={};opt-level={};inline-threshold={:?};target={};gc={};overflow={};pattern-guards={};memory={:?};exports={:?};async-imports={:?};profile={}",
+        sorted(&mut config.enabled_passes.iter().cloned()),
+        sorted(&mut config.disabled_passes.iter().cloned()),
+        config.opt_level.name(),
+        config.inline_threshold,
+        config.target.name(),
+        config.collector.name(),
+        config.overflow.name(),
+        config.pattern_guards,
+        config.memory,
+        config.exports,
+        config.async_imports,
+        config.profile,
+    )
+}
+
+fn value_type_code(ty: &ValueType) -> u8 {
+    match ty {
+        ValueType::I32 => 0x7f,
+        ValueType::I64 => 0x7e,
+        ValueType::F32 => 0x7d,
+        ValueType::F64 => 0x7c,
+    }
+}
+
+fn read_value_type(buf: &mut &[u8]) -> Option<ValueType> {
+    let (&code, rest) = buf.split_first()?;
+    *buf = rest;
+    match code {
+        0x7f => Some(ValueType::I32),
+        0x7e => Some(ValueType::I64),
+        0x7d => Some(ValueType::F32),
+        0x7c => Some(ValueType::F64),
+        _ => None,
+    }
+}
+
+fn write_func_type(buf: &mut Vec<u8>, ty: &FuncType) {
+    write_u32(buf, ty.params.len() as u32);
+    buf.extend(ty.params.iter().map(value_type_code));
+    write_u32(buf, ty.ret.iter().len() as u32);
+    buf.extend(ty.ret.iter().map(value_type_code));
+}
+
+fn read_func_type(buf: &mut &[u8]) -> Option<FuncType> {
+    let params = (0..read_u32(buf)?)
+        .map(|_| read_value_type(buf))
+        .collect::<Option<Vec<_>>>()?;
+    let ret = match read_u32(buf)? {
+        0 => None,
+        1 => Some(read_value_type(buf)?),
+        _ => return None,
+    };
+    Some(FuncType { params, ret })
+}
+
+const LTYS: [LTy; 9] = [
+    LTy::I32,
+    LTy::U32,
+    LTy::I64,
+    LTy::U64,
+    LTy::F32,
+    LTy::F64,
+    LTy::Unit,
+    LTy::Ptr,
+    LTy::FPtr,
+];
+
+fn write_lty(buf: &mut Vec<u8>, ty: &LTy) {
+    write_u32(buf, LTYS.iter().position(|t| t == ty).unwrap() as u32);
+}
+
+fn read_lty(buf: &mut &[u8]) -> Option<LTy> {
+    LTYS.get(read_u32(buf)? as usize).cloned()
+}
+
+/// 0 for none, 1 for a string and 2 followed by the types for a callback
+fn write_host_ty(buf: &mut Vec<u8>, ty: Option<&HostTy>) {
+    match ty {
+        None => write_u32(buf, 0),
+        Some(HostTy::String) => write_u32(buf, 1),
+        Some(HostTy::Callback(param, ret)) => {
+            write_u32(buf, 2);
+            write_lty(buf, param);
+            write_lty(buf, ret);
+        }
+    }
+}
+
+fn read_host_ty(buf: &mut &[u8]) -> Option<Option<HostTy>> {
+    match read_u32(buf)? {
+        0 => Some(None),
+        1 => Some(Some(HostTy::String)),
+        2 => Some(Some(HostTy::Callback(read_lty(buf)?, read_lty(buf)?))),
+        _ => None,
+    }
+}
+
+fn write_host_types(buf: &mut Vec<u8>, host_types: &HostTypes) {
+    write_u32(buf, host_types.params.len() as u32);
+    for (index, ty) in &host_types.params {
+        write_u32(buf, *index);
+        write_host_ty(buf, Some(ty));
+    }
+    write_host_ty(buf, host_types.ret.as_ref());
+    write_u32(buf, host_types.suspends as u32);
+}
+
+fn read_host_types(buf: &mut &[u8]) -> Option<HostTypes> {
+    let params = (0..read_u32(buf)?)
+        .map(|_| Some((read_u32(buf)?, read_host_ty(buf)??)))
+        .collect::<Option<Vec<_>>>()?;
+    let ret = read_host_ty(buf)?;
+    let suspends = read_u32(buf)? != 0;
+    Some(HostTypes {
+        params,
+        ret,
+        suspends,
+    })
+}
+
+fn write_bytes(buf: &mut Vec<u8>, bytes: &[u8]) {
+    write_u32(buf, bytes.len() as u32);
+    buf.extend_from_slice(bytes);
+}
+
+fn read_bytes(buf: &mut &[u8]) -> Option<Vec<u8>> {
+    let len = read_u32(buf)? as usize;
+    if buf.len() < len {
+        return None;
+    }
+    let (bytes, rest) = buf.split_at(len);
+    *buf = rest;
+    Some(bytes.to_vec())
+}
+
+fn encode_module(buf: &mut Vec<u8>, module: &Module) {
+    write_bytes(buf, &module.binary);
+    write_u32(buf, module.custom_sections.len() as u32);
+    for section in &module.custom_sections {
+        write_name(buf, &section.name);
+        write_bytes(buf, &section.payload);
+    }
+    write_u32(buf, module.interface.imports.len() as u32);
+    for (module, name, ty, host_types) in &module.interface.imports {
+        write_name(buf, module);
+        write_name(buf, name);
+        write_func_type(buf, ty);
+        write_host_types(buf, host_types);
+    }
+    write_u32(buf, module.interface.exports.len() as u32);
+    for (name, ty) in &module.interface.exports {
+        write_name(buf, name);
+        write_func_type(buf, ty);
+    }
+    write_u32(buf, module.interface.profile.len() as u32);
+    for name in &module.interface.profile {
+        write_name(buf, name);
+    }
+}
+
+fn decode_module(buf: &mut &[u8]) -> Option<Module> {
+    let binary = read_bytes(buf)?;
+    let custom_sections = (0..read_u32(buf)?)
+        .map(|_| {
+            Some(CustomSection {
+                name: read_name(buf)?,
+                payload: read_bytes(buf)?,
+            })
+        })
+        .collect::<Option<_>>()?;
+    let imports = (0..read_u32(buf)?)
+        .map(|_| {
+            Some((
+                read_name(buf)?,
+                read_name(buf)?,
+                read_func_type(buf)?,
+                read_host_types(buf)?,
+            ))
+        })
+        .collect::<Option<_>>()?;
+    let exports = (0..read_u32(buf)?)
+        .map(|_| Some((read_name(buf)?, read_func_type(buf)?)))
+        .collect::<Option<_>>()?;
+    let profile = (0..read_u32(buf)?)
+        .map(|_| read_name(buf))
+        .collect::<Option<_>>()?;
+    Some(Module {
+        binary,
+        custom_sections,
+        interface: Interface {
+            imports,
+            exports,
+            profile,
+        },
+    })
+}
+
+pub struct Cache {
+    dir: PathBuf,
+}
+
+impl Cache {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Cache { dir: dir.into() }
+    }
+
+    /// whether the compilation of the config can be skipped.
+    /// the options to print the IRs need the passes to run
+    pub fn applies(config: &Config) -> bool {
+        config.pretty_print_ir.is_empty() && config.emit.is_empty() && config.stop_after.is_none()
+    }
+
+    /// the identity of the program, which the entry starts with
+    fn header(sources: &[&str], config: &Config) -> Vec<u8> {
+        let mut header = MAGIC.to_vec();
+        write_name(&mut header, env!("CARGO_PKG_VERSION"));
+        write_name(&mut header, &fingerprint(config));
+        write_u32(&mut header, sources.len() as u32);
+        for source in sources {
+            write_name(&mut header, source);
+        }
+        header
+    }
+
+    fn path(&self, header: &[u8]) -> PathBuf {
+        self.dir.join(format!("{:016x}.cache", fnv1a(header)))
+    }
+
+    /// the module compiled from the sources with the config before
+    pub fn get(&self, sources: &[&str], config: &Config) -> Option<Module> {
+        let header = Self::header(sources, config);
+        let path = self.path(&header);
+        let entry = fs::read(&path).ok()?;
+        if !entry.starts_with(&header) {
+            info!("cache: {} is of another program", path.display());
+            return None;
+        }
+        let mut buf = &entry[header.len()..];
+        let module = decode_module(&mut buf).filter(|_| buf.is_empty());
+        match &module {
+            Some(_) => info!("cache: hit {}", path.display()),
+            None => warn!("cache: {} is broken", path.display()),
+        }
+        module
+    }
+
+    pub fn put(&self, sources: &[&str], config: &Config, module: &Module) -> io::Result<()> {
+        let mut entry = Self::header(sources, config);
+        let path = self.path(&entry);
+        encode_module(&mut entry, module);
+        fs::create_dir_all(&self.dir)?;
+        // the other builds never see a partially written entry
+        let tmp = path.with_extension(format!("tmp{}", std::process::id()));
+        fs::write(&tmp, entry)?;
+        fs::rename(&tmp, &path)
+    }
+
+    /// the module from the cache, or compiled and stored in the cache
+    pub fn get_or_compile<E>(
+        dir: Option<&Path>,
+        sources: &[&str],
+        config: &Config,
+        compile: impl FnOnce() -> Result<Module, E>,
+    ) -> Result<Module, E> {
+        let cache = match dir {
+            Some(dir) if Self::applies(config) => Cache::new(dir),
+            _ => return compile(),
+        };
+        if let Some(module) = cache.get(sources, config) {
+            return Ok(module);
+        }
+        let module = compile()?;
+        if let Err(e) = cache.put(sources, config, &module) {
+            warn!("cache: failed to write to {}: {}", cache.dir.display(), e);
+        }
+        Ok(module)
+    }
+}
@@ -0,0 +1,226 @@
+//! the compilation as a library call.
+//! the builder takes the sources and the options, and `Compiler::compile` returns
+//! all the artifacts asked for at once, along with the errors, instead of chaining
+//! `compile`, `Module::dump` and `generate_glue` by hand.
+
+use crate::backend::generate_glue;
+use crate::config::{Config, Emit, OptLevel};
+use crate::pass_manager::{CompileError, IR};
+use crate::project::Project;
+use std::fmt;
+use std::path::PathBuf;
+
+/// the outputs of the compilation to produce
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Artifact {
+    /// the encoded module
+    Wasm,
+    /// the JavaScript loader of the module, which loads it from the URL
+    Js { program_url: String },
+    /// the IR, dumped as `Config::emit` prints it
+    Ir(Emit),
+}
+
+/// an error of the compilation, detached from the sources
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// the pass which reported the error
+    pub pass: Option<&'static str>,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.pass {
+            Some(pass) => write!(f, "{}: {}", pass, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+impl<'a> From<CompileError<'a>> for Diagnostic {
+    fn from(error: CompileError<'a>) -> Self {
+        match error {
+            CompileError::Pass { pass, error } => Diagnostic {
+                pass: Some(pass),
+                message: error.to_string(),
+            },
+            error => Diagnostic {
+                pass: None,
+                message: error.to_string(),
+            },
+        }
+    }
+}
+
+/// the result of `Compiler::compile`.
+/// the artifacts not asked for, or not produced because of the errors, are left empty
+#[derive(Debug, Default)]
+pub struct Output {
+    pub wasm: Option<Vec<u8>>,
+    pub js: Option<String>,
+    /// the IRs asked for in the order they are complete, e.g. the HIR before the MIR
+    pub dumps: Vec<(Emit, Vec<u8>)>,
+    /// the errors of the program. empty if the compilation succeeded
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl Output {
+    pub fn is_ok(&self) -> bool {
+        self.diagnostics.is_empty()
+    }
+
+    /// the dump of the IR, if it was asked for
+    pub fn dump(&self, kind: Emit) -> Option<&[u8]> {
+        self.dumps
+            .iter()
+            .find(|(emit, _)| *emit == kind)
+            .map(|(_, dump)| dump.as_slice())
+    }
+}
+
+/// the builder of `Compiler`
+#[derive(Default)]
+pub struct CompilerBuilder {
+    basis: Option<String>,
+    files: Vec<(PathBuf, String)>,
+    config: Config,
+    opt_level: Option<OptLevel>,
+    artifacts: Vec<Artifact>,
+}
+
+impl CompilerBuilder {
+    /// the declarations which the sources may shadow, instead of `Config::prelude`
+    pub fn basis(mut self, source: impl Into<String>) -> Self {
+        self.basis = Some(source.into());
+        self
+    }
+
+    /// adds a source file. the files are compiled in order as one program like `Project`
+    pub fn source(mut self, path: impl Into<PathBuf>, source: impl Into<String>) -> Self {
+        self.files.push((path.into(), source.into()));
+        self
+    }
+
+    /// the options of the compilation.
+    /// `opt_level` takes precedence over the config whichever is set first
+    pub fn config(mut self, config: Config) -> Self {
+        self.config = config;
+        self
+    }
+
+    pub fn opt_level(mut self, opt_level: OptLevel) -> Self {
+        self.opt_level = Some(opt_level);
+        self
+    }
+
+    /// asks for the artifact. only the module is produced if none is asked for
+    pub fn artifact(mut self, artifact: Artifact) -> Self {
+        self.artifacts.push(artifact);
+        self
+    }
+
+    pub fn build(self) -> Compiler {
+        let mut config = self.config;
+        let mut errors = Vec::new();
+        let basis = match self.basis {
+            Some(basis) => basis,
+            None => config.prelude.load().unwrap_or_else(|e| {
+                errors.push(Diagnostic {
+                    pass: None,
+                    message: format!("failed to read the prelude: {}", e),
+                });
+                String::new()
+            }),
+        };
+        let mut project = Project::new(basis);
+        for (path, source) in self.files {
+            project.add_file(path, source);
+        }
+        if let Some(opt_level) = self.opt_level {
+            config.opt_level = opt_level;
+        }
+        let mut wasm = self.artifacts.is_empty();
+        let mut js = None;
+        for artifact in self.artifacts {
+            match artifact {
+                Artifact::Wasm => wasm = true,
+                Artifact::Js { program_url } => js = Some(program_url),
+                Artifact::Ir(kind) => {
+                    config.emit.insert(kind);
+                }
+            }
+        }
+        Compiler {
+            project,
+            config,
+            wasm,
+            js,
+            errors,
+        }
+    }
+}
+
+/// the compilation of a program with its options
+pub struct Compiler {
+    project: Project,
+    /// `emit` holds the IRs to dump, which are collected in `Output` rather than printed
+    config: Config,
+    wasm: bool,
+    /// the URL of the program for the JavaScript loader
+    js: Option<String>,
+    /// the errors before the compilation, e.g. of reading the prelude
+    errors: Vec<Diagnostic>,
+}
+
+impl Compiler {
+    pub fn builder() -> CompilerBuilder {
+        CompilerBuilder::default()
+    }
+
+    /// compiles the program. the compilation stopped by `Config::stop_after` is not an error,
+    /// and produces the dumps of the IRs before the stop
+    pub fn compile(&self) -> Output {
+        if !self.errors.is_empty() {
+            return Output {
+                diagnostics: self.errors.clone(),
+                ..Output::default()
+            };
+        }
+        let config = &self.config;
+        let mut dumps = Vec::new();
+        // the IRs are dumped on the way, so the cache is used only if they are not asked for
+        let result = if config.emit.is_empty() {
+            self.project.compile(config)
+        } else {
+            self.project.parse().and_then(|ast| {
+                crate::compile_ast(ast, config, &mut |kind, ir: &IR| {
+                    let mut dump = Vec::new();
+                    ir.dump(kind, &mut dump, config)
+                        .expect("writing to a Vec never fails");
+                    dumps.push((kind, dump));
+                })
+            })
+        };
+
+        let mut output = Output {
+            dumps,
+            ..Output::default()
+        };
+        match result {
+            Ok(module) => {
+                if self.wasm {
+                    let mut code = Vec::new();
+                    module.dump(&mut code);
+                    output.wasm = Some(code);
+                }
+                if let Some(program_url) = &self.js {
+                    output.js = Some(generate_glue(&module.interface, program_url));
+                }
+            }
+            Err(CompileError::Stopped { .. }) => (),
+            Err(error) => output.diagnostics.push(error.into()),
+        }
+        output
+    }
+}
@@ -1,6 +1,467 @@
 use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::str::FromStr;
+use tracing::level_filters::LevelFilter;
 
 #[derive(Clone, Debug, Default)]
 pub struct Config {
     pub pretty_print_ir: HashSet<String>,
+    /// optional passes to run, e.g. `cps_conversion`
+    pub enabled_passes: HashSet<String>,
+    /// passes to skip
+    pub disabled_passes: HashSet<String>,
+    /// stop compilation after the named pass
+    pub stop_after: Option<String>,
+    /// IRs to print to stdout once they are complete
+    pub emit: HashSet<Emit>,
+    /// format to print the `emit` IRs in
+    pub emit_format: EmitFormat,
+    /// how much of the pipeline the command logs to stderr
+    pub verbosity: Verbosity,
+    /// how much to optimize the program
+    pub opt_level: OptLevel,
+    /// the size of the largest function to inline, instead of the threshold of `opt_level`
+    pub inline_threshold: Option<usize>,
+    /// host environment the module runs in
+    pub target: Target,
+    /// garbage collector of the runtime
+    pub collector: Collector,
+    /// what `+`, `-` and `*` of `int` do when the result overflows
+    pub overflow: Overflow,
+    /// accept the guards of the `case` clauses, `pat when expr => expr`, which are not SML '97.
+    /// the clause is taken only if the guard is true, otherwise the following clauses are tried
+    pub pattern_guards: bool,
+    /// the linear memory imported from the runtime
+    pub memory: Memory,
+    /// top level functions to export from the module under their names.
+    /// they take the argument and return the result as wasm values:
+    /// `int` and `char` as `i32`, `real` as `f64` and the others as `i32` pointers into
+    /// the linear memory. `unit` is an ignored `i32` argument and no result
+    pub exports: Vec<String>,
+    /// host functions returning promises, as `(module, name)` of their `_import`s.
+    /// the calls to them pass the continuation, which the JavaScript loader calls with the
+    /// value of the promise once it is resolved, so they block the program until then.
+    /// it requires the `cps_conversion` pass and the JS target
+    pub async_imports: Vec<(String, String)>,
+    /// instrument the functions to count their calls and estimate the cycles they run.
+    /// `printProfile` of the JavaScript loader reports them
+    pub profile: bool,
+    /// directory to keep the compiled modules in, to skip compiling the same program again
+    pub cache_dir: Option<PathBuf>,
+    /// the declarations prepended to the programs of `Compiler` and the command
+    pub prelude: Prelude,
+}
+
+/// limits of the linear memory, in 64KiB pages.
+/// the runtime provides the memory, so it must be created within these limits
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Memory {
+    pub initial_pages: u32,
+    pub maximum_pages: Option<u32>,
+    /// whether the runtime may expand the heap with `memory.grow`.
+    /// if not, the maximum is pinned to the initial pages
+    pub grow: bool,
+}
+
+impl Default for Memory {
+    fn default() -> Self {
+        Memory {
+            initial_pages: 2,
+            maximum_pages: None,
+            grow: true,
+        }
+    }
+}
+
+impl Memory {
+    /// the wasm page limit of 4GiB
+    pub const MAX_PAGES: u32 = 65536;
+
+    /// the maximum pages to declare in the module
+    pub fn maximum(&self) -> Option<u32> {
+        if self.grow {
+            self.maximum_pages
+        } else {
+            Some(self.initial_pages)
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.initial_pages > Self::MAX_PAGES {
+            return Err(format!(
+                "initial memory of {} pages exceeds the limit of {} pages",
+                self.initial_pages,
+                Self::MAX_PAGES
+            ));
+        }
+        match self.maximum_pages {
+            Some(max) if max > Self::MAX_PAGES => Err(format!(
+                "maximum memory of {} pages exceeds the limit of {} pages",
+                max,
+                Self::MAX_PAGES
+            )),
+            Some(max) if max < self.initial_pages => Err(format!(
+                "maximum memory of {} pages is less than the initial {} pages",
+                max, self.initial_pages
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// optimization levels, the presets of the optimizing passes to run and their thresholds
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OptLevel {
+    /// only the passes required to generate the code
+    O0,
+    /// the optimizations which don't grow the code
+    O1,
+    /// also the inlining and the constant folding
+    O2,
+    /// the smallest code: the inlining of only the tiny functions, and no uncurrying,
+    /// which adds the curried wrappers of the functions
+    Os,
+}
+
+impl Default for OptLevel {
+    fn default() -> Self {
+        OptLevel::O1
+    }
+}
+
+impl OptLevel {
+    pub const NAMES: &'static [&'static str] = &["0", "1", "2", "s"];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            OptLevel::O0 => "0",
+            OptLevel::O1 => "1",
+            OptLevel::O2 => "2",
+            OptLevel::Os => "s",
+        }
+    }
+
+    /// the optional passes run at the level, besides `Config::enabled_passes`
+    pub fn enabled_passes(self) -> &'static [&'static str] {
+        match self {
+            OptLevel::O0 | OptLevel::O1 => &[],
+            OptLevel::O2 | OptLevel::Os => &["inlining", "constant_folding"],
+        }
+    }
+
+    /// the passes skipped at the level, besides `Config::disabled_passes`
+    pub fn disabled_passes(self) -> &'static [&'static str] {
+        match self {
+            OptLevel::O0 => &[
+                "uncurry",
+                "copy_propagation",
+                "dead_code_elimination",
+                "let_floating",
+                "unboxing",
+                "escape_analysis",
+                "cse",
+                "peephole",
+            ],
+            OptLevel::O1 | OptLevel::O2 => &[],
+            OptLevel::Os => &["uncurry"],
+        }
+    }
+
+    /// the size of the largest function body to inline, in the operations of the HIR
+    pub fn inline_threshold(self) -> usize {
+        match self {
+            OptLevel::Os => 8,
+            _ => 40,
+        }
+    }
+}
+
+impl FromStr for OptLevel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "0" => Ok(OptLevel::O0),
+            "1" => Ok(OptLevel::O1),
+            "2" => Ok(OptLevel::O2),
+            "s" => Ok(OptLevel::Os),
+            _ => Err(format!("unknown optimization level: {}", s)),
+        }
+    }
+}
+
+/// host environments
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Target {
+    /// the host functions are provided by JavaScript. the start function runs the program
+    Js,
+    /// the host functions are implemented with WASI imports.
+    /// the program runs from the exported `_start` and the memory is exported as `memory`
+    Wasi,
+}
+
+impl Default for Target {
+    fn default() -> Self {
+        Target::Js
+    }
+}
+
+impl Target {
+    pub const NAMES: &'static [&'static str] = &["js", "wasi"];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Target::Js => "js",
+            Target::Wasi => "wasi",
+        }
+    }
+}
+
+impl FromStr for Target {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "js" => Ok(Target::Js),
+            "wasi" => Ok(Target::Wasi),
+            _ => Err(format!("unknown target: {}", s)),
+        }
+    }
+}
+
+/// garbage collectors. the compiled code passes the choice to the runtime on start
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Collector {
+    /// a non-moving mark-sweep collector
+    MarkSweep,
+    /// objects are allocated in a nursery and copied to the mark-sweep heap if they survive
+    Generational,
+    /// objects are freed as soon as they are unreferenced, without pauses to trace the heap.
+    /// cycles are never freed
+    ReferenceCounting,
+}
+
+impl Default for Collector {
+    fn default() -> Self {
+        Collector::MarkSweep
+    }
+}
+
+impl Collector {
+    pub const NAMES: &'static [&'static str] = &["mark-sweep", "generational", "refcount"];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Collector::MarkSweep => "mark-sweep",
+            Collector::Generational => "generational",
+            Collector::ReferenceCounting => "refcount",
+        }
+    }
+}
+
+impl FromStr for Collector {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mark-sweep" => Ok(Collector::MarkSweep),
+            "generational" => Ok(Collector::Generational),
+            "refcount" => Ok(Collector::ReferenceCounting),
+            _ => Err(format!("unknown garbage collector: {}", s)),
+        }
+    }
+}
+
+/// behaviors of the `int` arithmetic on overflow
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Overflow {
+    /// the result wraps around as the `i32` operations of wasm do
+    Wrap,
+    /// the `Overflow` exception is raised, as the Basis Library specifies.
+    /// the operations are checked by the comparisons of the operands and the result
+    Raise,
+}
+
+impl Default for Overflow {
+    fn default() -> Self {
+        Overflow::Wrap
+    }
+}
+
+impl Overflow {
+    pub const NAMES: &'static [&'static str] = &["wrap", "raise"];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Overflow::Wrap => "wrap",
+            Overflow::Raise => "raise",
+        }
+    }
+}
+
+impl FromStr for Overflow {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "wrap" => Ok(Overflow::Wrap),
+            "raise" => Ok(Overflow::Raise),
+            _ => Err(format!("unknown overflow behavior: {}", s)),
+        }
+    }
+}
+
+/// the declarations the programs start from, which they may shadow
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Prelude {
+    /// `ml_src/prelude.sml`, built into the compiler
+    Builtin,
+    /// no declarations. the programs have only the builtin types and operators
+    None,
+    /// the declarations read from the file
+    File(PathBuf),
+}
+
+impl Default for Prelude {
+    fn default() -> Self {
+        Prelude::Builtin
+    }
+}
+
+impl Prelude {
+    /// the source of `Prelude::Builtin`
+    pub const BUILTIN: &'static str = include_str!("../ml_src/prelude.sml");
+
+    /// the source of the declarations
+    pub fn load(&self) -> io::Result<String> {
+        match self {
+            Prelude::Builtin => Ok(Self::BUILTIN.to_string()),
+            Prelude::None => Ok(String::new()),
+            Prelude::File(path) => fs::read_to_string(path),
+        }
+    }
+}
+
+/// IRs which can be dumped by `Config::emit`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Emit {
+    Ast,
+    TypedAst,
+    HIR,
+    MIR,
+    LIR,
+    Wat,
+    Wasm,
+}
+
+impl Emit {
+    pub const NAMES: &'static [&'static str] =
+        &["ast", "typed-ast", "hir", "mir", "lir", "wat", "wasm"];
+}
+
+impl FromStr for Emit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        use self::Emit::*;
+        match s {
+            "ast" => Ok(Ast),
+            "typed-ast" => Ok(TypedAst),
+            "hir" => Ok(HIR),
+            "mir" => Ok(MIR),
+            "lir" => Ok(LIR),
+            "wat" => Ok(Wat),
+            "wasm" => Ok(Wasm),
+            _ => Err(format!("unknown IR to emit: {}", s)),
+        }
+    }
+}
+
+/// formats of the IRs dumped by `Config::emit`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EmitFormat {
+    /// the pretty printed IR, the same as `Config::pretty_print_ir`
+    Text,
+    /// the AST, HIR and MIR as JSON objects, one per line, for the tools outside of Rust.
+    /// the LIR and the module are printed as text
+    #[cfg(feature = "json")]
+    Json,
+}
+
+impl Default for EmitFormat {
+    fn default() -> Self {
+        EmitFormat::Text
+    }
+}
+
+impl EmitFormat {
+    #[cfg(feature = "json")]
+    pub const NAMES: &'static [&'static str] = &["text", "json"];
+    #[cfg(not(feature = "json"))]
+    pub const NAMES: &'static [&'static str] = &["text"];
+
+    /// version of the JSON dump, incremented on incompatible changes of the IRs
+    pub const JSON_VERSION: u32 = 1;
+}
+
+impl FromStr for EmitFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(EmitFormat::Text),
+            #[cfg(feature = "json")]
+            "json" => Ok(EmitFormat::Json),
+            _ => Err(format!("unknown emit format: {}", s)),
+        }
+    }
+}
+
+/// levels of the logs of the pipeline, which are the `tracing` spans and events of the passes
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Verbosity {
+    /// only the warnings, e.g. of the broken cache
+    Quiet,
+    /// the passes and the time they take
+    Passes,
+    /// also the functions generated, the cases compiled and the passes skipped
+    Functions,
+    /// also the unifications of the type inference and the operations lowered
+    Steps,
+}
+
+impl Default for Verbosity {
+    fn default() -> Self {
+        Verbosity::Quiet
+    }
+}
+
+impl Verbosity {
+    pub const NAMES: &'static [&'static str] = &["quiet", "passes", "functions", "steps"];
+
+    /// the filter of the subscriber showing the logs of the level
+    pub fn level_filter(self) -> LevelFilter {
+        match self {
+            Verbosity::Quiet => LevelFilter::WARN,
+            Verbosity::Passes => LevelFilter::INFO,
+            Verbosity::Functions => LevelFilter::DEBUG,
+            Verbosity::Steps => LevelFilter::TRACE,
+        }
+    }
+}
+
+impl FromStr for Verbosity {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "quiet" => Ok(Verbosity::Quiet),
+            "passes" => Ok(Verbosity::Passes),
+            "functions" => Ok(Verbosity::Functions),
+            "steps" => Ok(Verbosity::Steps),
+            _ => Err(format!("unknown verbosity: {}", s)),
+        }
+    }
 }
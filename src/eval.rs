@@ -0,0 +1,641 @@
+//! the reference interpreter of the HIR.
+//! it runs the HIR right after `ast_to_hir`, before the optimizations and the closure conversion,
+//! as the executable semantics to test the backend against.
+//! the values behave as in the wasm module: `int` is a wrapping 32-bit integer
+//! and the division by zero traps.
+
+use crate::hir::{Expr, HTy, Pattern, Val, HIR};
+use crate::pass_manager::CompileError;
+use crate::prim::*;
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, Write};
+use std::rc::Rc;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+#[derive(Debug)]
+pub enum EvalError<'a> {
+    /// the program has errors
+    Compile(CompileError<'a>),
+    /// an exception was raised to the top level
+    UncaughtException,
+    /// the program trapped, e.g. divided by zero
+    Trap(&'static str),
+    /// the program calls the host function the interpreter doesn't provide
+    UnknownExtern { module: String, fun: String },
+    /// failed to write the output
+    Io(io::Error),
+}
+
+impl<'a> fmt::Display for EvalError<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use self::EvalError::*;
+        match self {
+            Compile(e) => e.fmt(f),
+            UncaughtException => write!(f, "uncaught exception"),
+            Trap(message) => write!(f, "trapped: {}", message),
+            UnknownExtern { module, fun } => write!(f, "unknown host function {}.{}", module, fun),
+            Io(e) => write!(f, "failed to write the output: {}", e),
+        }
+    }
+}
+
+impl<'a> Error for EvalError<'a> {}
+
+impl<'a> From<CompileError<'a>> for EvalError<'a> {
+    fn from(e: CompileError<'a>) -> Self {
+        EvalError::Compile(e)
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Value<'h> {
+    Int(i32),
+    Real(f64),
+    Char(u32),
+    Word(u32),
+    String(Rc<str>),
+    Tuple(Rc<Vec<Value<'h>>>),
+    Constructor {
+        descriminant: u32,
+        arg: Option<Rc<Value<'h>>>,
+    },
+    Closure(Rc<Closure<'h>>),
+}
+
+impl<'h> Value<'h> {
+    fn unit() -> Self {
+        Value::Tuple(Rc::new(Vec::new()))
+    }
+
+    fn bool(b: bool) -> Self {
+        // `datatype bool = false | true`
+        Value::Constructor {
+            descriminant: b as u32,
+            arg: None,
+        }
+    }
+}
+
+/// a function with the environment it is defined in
+#[derive(Debug)]
+struct Closure<'h> {
+    /// the name of the recursive function, which the body refers to itself by
+    name: Option<Symbol>,
+    param: &'h Symbol,
+    body: &'h Expr,
+    env: Env<'h>,
+}
+
+/// the local variables, innermost first. the top level ones are in `Interpreter::globals`
+type Env<'h> = Option<Rc<Frame<'h>>>;
+
+#[derive(Debug)]
+struct Frame<'h> {
+    name: Symbol,
+    value: Value<'h>,
+    next: Env<'h>,
+}
+
+fn bind<'h>(env: Env<'h>, name: Symbol, value: Value<'h>) -> Env<'h> {
+    Some(Rc::new(Frame {
+        name,
+        value,
+        next: env,
+    }))
+}
+
+/// the ways the evaluation stops before returning a value
+enum Unwind<'h> {
+    Raise(Value<'h>),
+    // boxed to keep the results of the evaluation small
+    Error(Box<EvalError<'static>>),
+}
+
+impl<'h> From<EvalError<'static>> for Unwind<'h> {
+    fn from(e: EvalError<'static>) -> Self {
+        Unwind::Error(Box::new(e))
+    }
+}
+
+type Result<'h, T> = ::std::result::Result<T, Unwind<'h>>;
+
+/// the size of the buffer of `TextIO`, as in the runtime
+const OUTPUT_SIZE: usize = 4096;
+/// the number of the generators of `Random` the runtime has room for
+const GENERATORS: usize = 1024;
+
+pub struct Interpreter<'h, W> {
+    globals: HashMap<Symbol, Value<'h>>,
+    /// where `print` writes to
+    out: W,
+    /// the output of `TextIO` not flushed yet
+    output: String,
+    /// the origin of the monotonic clock
+    started: Instant,
+    /// the states of the generators of `Random`
+    generators: Vec<u64>,
+}
+
+impl<'h, W: Write> Interpreter<'h, W> {
+    pub fn new(out: W) -> Self {
+        Interpreter {
+            globals: HashMap::new(),
+            out,
+            output: String::new(),
+            started: Instant::now(),
+            generators: Vec::new(),
+        }
+    }
+
+    /// runs the top level declarations in order, and flushes the output of `TextIO`
+    /// as the compiled program does when it exits
+    pub fn run(&mut self, hir: &'h HIR) -> ::std::result::Result<(), EvalError<'static>> {
+        let result = self.run_vals(hir);
+        if let Ok(()) | Err(EvalError::UncaughtException) = result {
+            self.out
+                .write_all(self.output.as_bytes())
+                .map_err(EvalError::Io)?;
+            self.output.clear();
+        }
+        result
+    }
+
+    fn run_vals(&mut self, hir: &'h HIR) -> ::std::result::Result<(), EvalError<'static>> {
+        for val in &hir.0 {
+            match self.eval_val(&None, val) {
+                Ok(value) => {
+                    self.globals.insert(val.name.clone(), value);
+                }
+                Err(Unwind::Raise(_)) => return Err(EvalError::UncaughtException),
+                Err(Unwind::Error(e)) => return Err(*e),
+            }
+        }
+        Ok(())
+    }
+
+    fn lookup(&self, env: &Env<'h>, name: &Symbol) -> Value<'h> {
+        let mut frame = env;
+        while let Some(f) = frame {
+            if f.name == *name {
+                return f.value.clone();
+            }
+            frame = &f.next;
+        }
+        match self.globals.get(name) {
+            Some(value) => value.clone(),
+            None => panic!("internal error: unbound variable {:?}", name),
+        }
+    }
+
+    fn eval_val(&mut self, env: &Env<'h>, val: &'h Val) -> Result<'h, Value<'h>> {
+        match (&val.expr, val.rec) {
+            (Expr::Fun { param, body, .. }, rec) => Ok(Value::Closure(Rc::new(Closure {
+                name: if rec { Some(val.name.clone()) } else { None },
+                param: &param.1,
+                body,
+                env: env.clone(),
+            }))),
+            (expr, false) => self.eval(env.clone(), expr),
+            (_, true) => panic!("internal error: recursive value {:?}", val.name),
+        }
+    }
+
+    /// calls the closure. the bindings of the body refer to the closure itself when recursive
+    fn enter(closure: &Rc<Closure<'h>>, arg: Value<'h>) -> (Env<'h>, &'h Expr) {
+        let mut env = closure.env.clone();
+        if let Some(name) = &closure.name {
+            env = bind(env, name.clone(), Value::Closure(closure.clone()));
+        }
+        (bind(env, closure.param.clone(), arg), closure.body)
+    }
+
+    fn apply(&mut self, fun: Value<'h>, arg: Value<'h>) -> Result<'h, Value<'h>> {
+        match fun {
+            Value::Closure(closure) => {
+                let (env, body) = Self::enter(&closure, arg);
+                self.eval(env, body)
+            }
+            fun => panic!("internal error: applying non-function {:?}", fun),
+        }
+    }
+
+    /// evaluates the expression. the calls in the tail positions are evaluated in the loop,
+    /// so that the loops written as recursive functions run in constant stack
+    fn eval(&mut self, mut env: Env<'h>, mut expr: &'h Expr) -> Result<'h, Value<'h>> {
+        use crate::hir::Expr::*;
+        loop {
+            match expr {
+                Binds { binds, ret, .. } => {
+                    for val in binds {
+                        let value = self.eval_val(&env, val)?;
+                        env = bind(env, val.name.clone(), value);
+                    }
+                    expr = ret;
+                }
+                App { fun, arg, .. } => {
+                    let fun = self.eval(env.clone(), fun)?;
+                    let arg = self.eval(env.clone(), arg)?;
+                    match fun {
+                        Value::Closure(closure) => {
+                            let (callee_env, body) = Self::enter(&closure, arg);
+                            env = callee_env;
+                            expr = body;
+                        }
+                        fun => panic!("internal error: applying non-function {:?}", fun),
+                    }
+                }
+                Case {
+                    expr: cond, arms, ..
+                } => {
+                    let value = self.eval(env.clone(), cond)?;
+                    let (arm_env, arm) = arms
+                        .iter()
+                        .find_map(|(pattern, arm)| {
+                            Self::match_pattern(&env, pattern, &value).map(|env| (env, arm))
+                        })
+                        .unwrap_or_else(|| panic!("internal error: no arm matches {:?}", value));
+                    env = arm_env;
+                    expr = arm;
+                }
+                Fun { param, body, .. } => {
+                    return Ok(Value::Closure(Rc::new(self::Closure {
+                        name: None,
+                        param: &param.1,
+                        body,
+                        env,
+                    })))
+                }
+                Closure { .. } => {
+                    panic!("internal error: the interpreter takes the HIR before the closure conversion")
+                }
+                BuiltinCall { fun, args, .. } => return self.eval_builtin(&env, *fun, args),
+                ExternCall {
+                    ty,
+                    module,
+                    fun,
+                    args,
+                } => {
+                    let args = args
+                        .iter()
+                        .map(|arg| self.eval(env.clone(), arg))
+                        .collect::<Result<Vec<_>>>()?;
+                    return self.call_extern(ty, module, fun, args);
+                }
+                Tuple { tuple, .. } => {
+                    let tuple = tuple
+                        .iter()
+                        .map(|e| self.eval(env.clone(), e))
+                        .collect::<Result<Vec<_>>>()?;
+                    return Ok(Value::Tuple(Rc::new(tuple)));
+                }
+                Proj { index, tuple, .. } => match self.eval(env, tuple)? {
+                    Value::Tuple(tuple) => return Ok(tuple[*index as usize].clone()),
+                    value => panic!("internal error: projecting non-tuple {:?}", value),
+                },
+                Constructor {
+                    arg, descriminant, ..
+                } => {
+                    let arg = match arg {
+                        Some(arg) => Some(Rc::new(self.eval(env, arg)?)),
+                        None => None,
+                    };
+                    return Ok(Value::Constructor {
+                        descriminant: *descriminant,
+                        arg,
+                    });
+                }
+                Sym { name, .. } => return Ok(self.lookup(&env, name)),
+                Lit { value, .. } => {
+                    return Ok(match value {
+                        // the literals are in the range of `int` after the type check
+                        Literal::Int(n) => Value::Int(*n as i32),
+                        Literal::Real(f) => Value::Real(*f),
+                        Literal::Char(c) => Value::Char(*c),
+                        Literal::Word(w) => Value::Word(*w),
+                        Literal::String(s) => Value::String(s.as_str().into()),
+                    });
+                }
+            }
+        }
+    }
+
+    /// the environment with the variables of the pattern, if the value matches it
+    fn match_pattern(env: &Env<'h>, pattern: &Pattern, value: &Value<'h>) -> Option<Env<'h>> {
+        match (pattern, value) {
+            (Pattern::Constant { value, .. }, Value::Int(n)) if *n == *value as i32 => {
+                Some(env.clone())
+            }
+            (Pattern::Char { value, .. }, Value::Char(c)) if c == value => Some(env.clone()),
+            (
+                Pattern::Constructor {
+                    descriminant, arg, ..
+                },
+                Value::Constructor {
+                    descriminant: d,
+                    arg: value,
+                },
+            ) if descriminant == d => match (arg, value) {
+                (Some((_, name)), Some(value)) => {
+                    Some(bind(env.clone(), name.clone(), (**value).clone()))
+                }
+                _ => Some(env.clone()),
+            },
+            (Pattern::Var { name, .. }, value) => {
+                Some(bind(env.clone(), name.clone(), value.clone()))
+            }
+            (Pattern::Tuple { tuple, .. }, Value::Tuple(values)) => Some(
+                tuple
+                    .iter()
+                    .zip(values.iter())
+                    .fold(env.clone(), |env, (name, value)| {
+                        bind(env, name.clone(), value.clone())
+                    }),
+            ),
+            _ => None,
+        }
+    }
+
+    fn eval_builtin(&mut self, env: &Env<'h>, fun: BIF, args: &'h [Expr]) -> Result<'h, Value<'h>> {
+        use crate::prim::BIF::*;
+        let mut args = args
+            .iter()
+            .map(|arg| self.eval(env.clone(), arg))
+            .collect::<Result<Vec<_>>>()?
+            .into_iter();
+        let mut arg = || args.next().expect("internal error: too few arguments");
+        let (l, r) = match fun {
+            Raise => return Err(Unwind::Raise(arg())),
+            Handle => {
+                let (body, handler) = (arg(), arg());
+                return match self.apply(body, Value::unit()) {
+                    Err(Unwind::Raise(exn)) => self.apply(handler, exn),
+                    result => result,
+                };
+            }
+            _ => (arg(), arg()),
+        };
+        let value = match (fun, l, r) {
+            (Add, Value::Int(l), Value::Int(r)) => Value::Int(l.wrapping_add(r)),
+            (Sub, Value::Int(l), Value::Int(r)) => Value::Int(l.wrapping_sub(r)),
+            (Mul, Value::Int(l), Value::Int(r)) => Value::Int(l.wrapping_mul(r)),
+            (Div, Value::Int(_), Value::Int(0)) | (Mod, Value::Int(_), Value::Int(0)) => {
+                return Err(EvalError::Trap("integer divide by zero").into())
+            }
+            // as `i32.div_s` and `i32.rem_s`
+            (Div, Value::Int(l), Value::Int(r)) => match l.checked_div(r) {
+                Some(n) => Value::Int(n),
+                None => return Err(EvalError::Trap("integer overflow").into()),
+            },
+            (Mod, Value::Int(l), Value::Int(r)) => Value::Int(l.wrapping_rem(r)),
+            (Add, Value::Real(l), Value::Real(r)) => Value::Real(l + r),
+            (Sub, Value::Real(l), Value::Real(r)) => Value::Real(l - r),
+            (Mul, Value::Real(l), Value::Real(r)) => Value::Real(l * r),
+            (Divf, Value::Real(l), Value::Real(r)) => Value::Real(l / r),
+            (Add, Value::Word(l), Value::Word(r)) => Value::Word(l.wrapping_add(r)),
+            (Sub, Value::Word(l), Value::Word(r)) => Value::Word(l.wrapping_sub(r)),
+            (Mul, Value::Word(l), Value::Word(r)) => Value::Word(l.wrapping_mul(r)),
+            (fun, Value::Int(l), Value::Int(r)) => Value::bool(compare(fun, l, r)),
+            (fun, Value::Char(l), Value::Char(r)) => Value::bool(compare(fun, l, r)),
+            (fun, Value::Word(l), Value::Word(r)) => Value::bool(compare(fun, l, r)),
+            (fun, Value::Real(l), Value::Real(r)) => Value::bool(compare(fun, l, r)),
+            (fun, l, r) => panic!("internal error: {:?} of {:?} and {:?}", fun, l, r),
+        };
+        Ok(value)
+    }
+
+    /// the host functions the loader provides by default, and the wasm instructions.
+    /// `ty` is the type of the result
+    fn call_extern(
+        &mut self,
+        ty: &HTy,
+        module: &str,
+        fun: &str,
+        args: Vec<Value<'h>>,
+    ) -> Result<'h, Value<'h>> {
+        match (module, fun, args.as_slice()) {
+            ("js-ffi", "print", [Value::Int(n)]) => {
+                writeln!(self.out, "{}", n).map_err(EvalError::Io)?;
+                Ok(Value::unit())
+            }
+            ("js-ffi", "printString", [Value::String(s)]) => {
+                write!(self.out, "{}", s).map_err(EvalError::Io)?;
+                Ok(Value::unit())
+            }
+            ("js-ffi", "clock", [Value::Int(0)]) => {
+                let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+                Ok(Value::Real(now.as_secs_f64()))
+            }
+            ("js-ffi", "clock", [Value::Int(1)]) => {
+                Ok(Value::Real(self.started.elapsed().as_secs_f64()))
+            }
+            // the hasher is seeded with the entropy of the OS
+            ("js-ffi", "random_seed", [Value::Int(_)]) => {
+                Ok(Value::Int(RandomState::new().build_hasher().finish() as i32))
+            }
+            ("js-ffi", "sin", [Value::Real(x)]) => Ok(Value::Real(x.sin())),
+            ("js-ffi", "cos", [Value::Real(x)]) => Ok(Value::Real(x.cos())),
+            ("js-ffi", "exp", [Value::Real(x)]) => Ok(Value::Real(x.exp())),
+            ("js-ffi", "ln", [Value::Real(x)]) => Ok(Value::Real(x.ln())),
+            ("js-ffi", "pow", [Value::Real(x), Value::Real(y)]) => Ok(Value::Real(x.powf(*y))),
+            ("wasm", "f64.floor", [Value::Real(x)]) => Ok(Value::Real(x.floor())),
+            ("wasm", "f64.ceil", [Value::Real(x)]) => Ok(Value::Real(x.ceil())),
+            ("wasm", "f64.trunc", [Value::Real(x)]) => Ok(Value::Real(x.trunc())),
+            ("wasm", "f64.nearest", [Value::Real(x)]) => Ok(Value::Real(x.round_ties_even())),
+            ("wasm", "f64.sqrt", [Value::Real(x)]) => Ok(Value::Real(x.sqrt())),
+            ("wasm", "f64.convert_i32_s", [Value::Int(n)]) => Ok(Value::Real(*n as f64)),
+            ("wasm", "convert", [value]) => Ok(convert(value, ty)),
+            ("wasm", "i32.and", [Value::Word(l), Value::Word(r)]) => Ok(Value::Word(l & r)),
+            ("wasm", "i32.or", [Value::Word(l), Value::Word(r)]) => Ok(Value::Word(l | r)),
+            ("wasm", "i32.xor", [Value::Word(l), Value::Word(r)]) => Ok(Value::Word(l ^ r)),
+            // the shifts take the count modulo 32
+            ("wasm", "i32.shl", [Value::Word(l), Value::Word(r)]) => {
+                Ok(Value::Word(l.wrapping_shl(*r)))
+            }
+            ("wasm", "i32.shr_u", [Value::Word(l), Value::Word(r)]) => {
+                Ok(Value::Word(l.wrapping_shr(*r)))
+            }
+            ("wasm", "i32.shr_s", [Value::Word(l), Value::Word(r)]) => {
+                Ok(Value::Word((*l as i32).wrapping_shr(*r) as u32))
+            }
+            ("wasm", "i32.div_u", [Value::Word(_), Value::Word(0)])
+            | ("wasm", "i32.rem_u", [Value::Word(_), Value::Word(0)]) => {
+                Err(EvalError::Trap("integer divide by zero").into())
+            }
+            ("wasm", "i32.div_u", [Value::Word(l), Value::Word(r)]) => Ok(Value::Word(l / r)),
+            ("wasm", "i32.rem_u", [Value::Word(l), Value::Word(r)]) => Ok(Value::Word(l % r)),
+            ("wasm", "i32.trunc_f64_s", [Value::Real(x)]) => {
+                if x.is_nan() {
+                    return Err(EvalError::Trap("invalid conversion to integer").into());
+                }
+                let n = x.trunc();
+                if n < i32::MIN as f64 || n > i32::MAX as f64 {
+                    return Err(EvalError::Trap("integer overflow").into());
+                }
+                Ok(Value::Int(n as i32))
+            }
+            ("webml-rt", "string_length", [Value::String(s)]) => Ok(Value::Int(s.len() as i32)),
+            // the prelude checks the ranges
+            ("webml-rt", "string_byte", [Value::String(s), Value::Int(i)]) => {
+                Ok(Value::Char(s.as_bytes()[*i as usize] as u32))
+            }
+            ("webml-rt", "string_sub", [Value::String(s), Value::Int(i), Value::Int(n)]) => {
+                let bytes = &s.as_bytes()[*i as usize..(*i + *n) as usize];
+                Ok(Value::String(String::from_utf8_lossy(bytes).into()))
+            }
+            ("webml-rt", "string_concat", [Value::String(s), Value::String(t)]) => {
+                Ok(Value::String(format!("{}{}", s, t).into()))
+            }
+            ("webml-rt", "string_compare", [Value::String(s), Value::String(t)]) => {
+                Ok(Value::Int(s.cmp(t) as i32))
+            }
+            ("webml-rt", "string_hash", [Value::String(s)]) => Ok(Value::Word(
+                s.bytes().fold(0x811c_9dc5, |hash: u32, byte| {
+                    (hash ^ byte as u32).wrapping_mul(0x0100_0193)
+                }),
+            )),
+            ("webml-rt", "real_hash", [Value::Real(x)]) => {
+                let bits = if *x == 0.0 { 0 } else { x.to_bits() };
+                Ok(Value::Word((bits >> 32) as u32 ^ bits as u32))
+            }
+            ("webml-rt", "char_to_string", [Value::Char(c)]) => {
+                let c = std::char::from_u32(*c).unwrap_or(std::char::REPLACEMENT_CHARACTER);
+                Ok(Value::String(c.to_string().into()))
+            }
+            ("webml-rt", "output_append", [Value::String(s)]) => {
+                if OUTPUT_SIZE - self.output.len() < s.len() {
+                    return Ok(Value::Int(1));
+                }
+                self.output.push_str(s);
+                Ok(Value::Int(0))
+            }
+            ("webml-rt", "output_take", [Value::Int(_)]) => {
+                let s = std::mem::replace(&mut self.output, String::new());
+                Ok(Value::String(s.into()))
+            }
+            ("webml-rt", "rand_new", [Value::Int(m), Value::Int(n)]) => {
+                if self.generators.len() == GENERATORS {
+                    return Err(EvalError::Trap("too many random generators").into());
+                }
+                let seed = (*m as u32 as u64) << 32 | *n as u32 as u64;
+                self.generators.push(if seed == 0 {
+                    0x9e37_79b9_7f4a_7c15
+                } else {
+                    seed
+                });
+                Ok(Value::Int(self.generators.len() as i32 - 1))
+            }
+            ("webml-rt", "rand_int", [Value::Int(g)]) => {
+                let x = rand_next(&mut self.generators[*g as usize]);
+                Ok(Value::Int((x >> 32) as i32))
+            }
+            ("webml-rt", "rand_real", [Value::Int(g)]) => {
+                let x = rand_next(&mut self.generators[*g as usize]);
+                Ok(Value::Real((x >> 11) as f64 / (1u64 << 53) as f64))
+            }
+            ("webml-rt", "int_to_string", [Value::Int(n)]) => {
+                Ok(Value::String(int_to_string(*n).into()))
+            }
+            ("webml-rt", "word_to_string", [Value::Word(w)]) => {
+                Ok(Value::String(format!("{:X}", w).into()))
+            }
+            ("webml-rt", "real_to_string", [Value::Real(x)]) => {
+                Ok(Value::String(real_to_string(*x).into()))
+            }
+            _ => Err(EvalError::UnknownExtern {
+                module: module.to_string(),
+                fun: fun.to_string(),
+            }
+            .into()),
+        }
+    }
+}
+
+/// `convert` of the value to the type of the same representation, which keeps the bits
+fn convert<'h>(value: &Value<'h>, ty: &HTy) -> Value<'h> {
+    let bits = match value {
+        Value::Int(n) => *n as u32,
+        Value::Char(c) => *c,
+        Value::Word(w) => *w,
+        value => panic!("internal error: cannot convert {:?}", value),
+    };
+    match ty {
+        HTy::Int => Value::Int(bits as i32),
+        HTy::Char => Value::Char(bits),
+        HTy::Word => Value::Word(bits),
+        ty => panic!("internal error: cannot convert to {:?}", ty),
+    }
+}
+
+/// advances the xorshift64* generator of the runtime and returns the 64 bits of the output
+fn rand_next(state: &mut u64) -> u64 {
+    let mut x = *state;
+    x ^= x >> 12;
+    x ^= x << 25;
+    x ^= x >> 27;
+    *state = x;
+    x.wrapping_mul(0x2545_f491_4f6c_dd1d)
+}
+
+/// `Int.toString` of the runtime, with `~` for the minus sign
+fn int_to_string(n: i32) -> String {
+    if n < 0 {
+        format!("~{}", n.unsigned_abs())
+    } else {
+        n.to_string()
+    }
+}
+
+/// `Real.toString` of the runtime. the 12 significant digits are shown in the fixed notation
+/// if the exponent is from -4 to 11 as `%.12g` does, and in the scientific notation otherwise
+fn real_to_string(x: f64) -> String {
+    if x.is_nan() {
+        return "nan".into();
+    }
+    let sign = if x.is_sign_negative() { "~" } else { "" };
+    let x = x.abs();
+    if x.is_infinite() {
+        return format!("{}inf", sign);
+    }
+    let sci = format!("{:.11e}", x);
+    let (mantissa, exp) = sci.split_at(sci.find('e').unwrap());
+    let exp: i32 = exp[1..].parse().unwrap();
+    let digits = mantissa.replace('.', "");
+    let digits = match digits.trim_end_matches('0') {
+        "" => "0",
+        digits => digits,
+    };
+    if exp < -4 || exp >= 12 {
+        let (first, rest) = digits.split_at(1);
+        let point = if rest.is_empty() { "" } else { "." };
+        let exp = int_to_string(exp);
+        format!("{}{}{}{}E{}", sign, first, point, rest, exp)
+    } else if exp < 0 {
+        let zeros = "0".repeat((-exp - 1) as usize);
+        format!("{}0.{}{}", sign, zeros, digits)
+    } else {
+        let int_len = exp as usize + 1;
+        if digits.len() > int_len {
+            format!("{}{}.{}", sign, &digits[..int_len], &digits[int_len..])
+        } else {
+            let zeros = "0".repeat(int_len - digits.len());
+            format!("{}{}{}.0", sign, digits, zeros)
+        }
+    }
+}
+
+fn compare<T: PartialOrd>(fun: BIF, l: T, r: T) -> bool {
+    use crate::prim::BIF::*;
+    match fun {
+        Eq => l == r,
+        Neq => l != r,
+        Gt => l > r,
+        Ge => l >= r,
+        Lt => l < r,
+        Le => l <= r,
+        fun => panic!("internal error: {:?} is not a comparison", fun),
+    }
+}
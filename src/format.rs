@@ -0,0 +1,705 @@
+//! source code formatter.
+//! the AST doesn't keep the comments nor how the operators were written,
+//! so the formatter lays out the tokens of the source instead of printing the AST.
+//! it only changes the whitespaces between the tokens, keeping the comments where they are.
+
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FormatConfig {
+    /// the maximum width of the lines, which long lines are broken to fit in
+    pub width: usize,
+    /// the number of spaces to indent the nested code with
+    pub indent: usize,
+}
+
+impl Default for FormatConfig {
+    fn default() -> Self {
+        FormatConfig {
+            width: 80,
+            indent: 4,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FormatError {
+    /// the byte offset in the source
+    pub offset: usize,
+    pub message: &'static str,
+}
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at offset {}", self.message, self.offset)
+    }
+}
+
+impl std::error::Error for FormatError {}
+
+/// formats the source code
+pub fn fmt(source: &str, config: &FormatConfig) -> Result<String, FormatError> {
+    let tokens = lex(source)?;
+    let mut layout = Layout {
+        tokens,
+        pos: 0,
+        indent: config.indent,
+    };
+    let doc = layout.program();
+    Ok(Renderer::new(config.width).render(&doc))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Word,
+    Symbol,
+    Literal,
+    Punct,
+    Eof,
+}
+
+#[derive(Debug, Clone)]
+struct Comment<'a> {
+    text: &'a str,
+    /// the number of the newlines before the comment
+    newlines: usize,
+}
+
+#[derive(Debug, Clone)]
+struct Token<'a> {
+    text: &'a str,
+    kind: Kind,
+    /// the comments before the token
+    comments: Vec<Comment<'a>>,
+    /// the comments after the token on the same line
+    trailing: Vec<&'a str>,
+    /// the number of the newlines between the last comment or token and this token
+    newlines: usize,
+}
+
+impl<'a> Token<'a> {
+    /// the number of the newlines before the token and its comments
+    fn newlines_before(&self) -> usize {
+        self.comments
+            .first()
+            .map(|c| c.newlines)
+            .unwrap_or(self.newlines)
+    }
+}
+
+fn is_symbolic(c: char) -> bool {
+    "!%&$#+-/:<=>?@\\~`^|*".contains(c)
+}
+
+fn is_alphanumeric(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '\''
+}
+
+/// the length of the word literal at the head, as `0w255` or `0wxff`
+fn word_literal(s: &str) -> Option<usize> {
+    let (prefix, is_digit): (&str, fn(&char) -> bool) = if s.starts_with("0wx") {
+        ("0wx", char::is_ascii_hexdigit)
+    } else if s.starts_with("0w") {
+        ("0w", char::is_ascii_digit)
+    } else {
+        return None;
+    };
+    match s[prefix.len()..].chars().take_while(is_digit).count() {
+        0 => None,
+        len => Some(prefix.len() + len),
+    }
+}
+
+fn lex(source: &str) -> Result<Vec<Token<'_>>, FormatError> {
+    let error = |offset, message| FormatError { offset, message };
+    let mut tokens = Vec::new();
+    let mut comments = Vec::new();
+    let mut newlines = 0;
+    let mut pos = 0;
+    loop {
+        let rest = &source[pos..];
+        let c = match rest.chars().next() {
+            Some(c) => c,
+            None => break,
+        };
+        if c.is_whitespace() {
+            if c == '\n' {
+                newlines += 1;
+            }
+            pos += c.len_utf8();
+            continue;
+        }
+
+        let start = pos;
+        let kind = if rest.starts_with("(*") {
+            let mut depth = 0;
+            loop {
+                let rest = &source[pos..];
+                if rest.starts_with("(*") {
+                    depth += 1;
+                    pos += 2;
+                } else if rest.starts_with("*)") {
+                    depth -= 1;
+                    pos += 2;
+                    if depth == 0 {
+                        break;
+                    }
+                } else {
+                    match rest.chars().next() {
+                        Some(c) => pos += c.len_utf8(),
+                        None => return Err(error(start, "unterminated comment")),
+                    }
+                }
+            }
+            let text = &source[start..pos];
+            match tokens.last_mut() {
+                Some(Token { trailing, .. }) if newlines == 0 && comments.is_empty() => {
+                    trailing.push(text)
+                }
+                _ => comments.push(Comment { text, newlines }),
+            }
+            newlines = 0;
+            continue;
+        } else if c == '"' || rest.starts_with("#\"") {
+            pos += if c == '"' { 1 } else { 2 };
+            loop {
+                let mut chars = source[pos..].chars();
+                match chars.next() {
+                    Some('"') => {
+                        pos += 1;
+                        break;
+                    }
+                    Some('\\') => {
+                        pos += 1 + chars.next().map(char::len_utf8).unwrap_or(0);
+                    }
+                    Some(c) => pos += c.len_utf8(),
+                    None => return Err(error(start, "unterminated string")),
+                }
+            }
+            Kind::Literal
+        } else if let Some(len) = word_literal(rest) {
+            pos += len;
+            Kind::Literal
+        } else if c.is_ascii_digit()
+            || (c == '~' && rest[1..].starts_with(|c: char| c.is_ascii_digit()))
+        {
+            pos += 1;
+            let digits = |pos: &mut usize| {
+                let len = source[*pos..]
+                    .find(|c: char| !c.is_ascii_digit())
+                    .unwrap_or(source.len() - *pos);
+                *pos += len;
+                len
+            };
+            digits(&mut pos);
+            if source[pos..].starts_with('.')
+                && source[pos + 1..].starts_with(|c: char| c.is_ascii_digit())
+            {
+                pos += 1;
+                digits(&mut pos);
+            }
+            if source[pos..].starts_with(['e', 'E']) {
+                let mut exponent = pos + 1;
+                if source[exponent..].starts_with('~') {
+                    exponent += 1;
+                }
+                if digits(&mut exponent) > 0 {
+                    pos = exponent;
+                }
+            }
+            Kind::Literal
+        } else if is_alphanumeric(c) {
+            pos += rest.find(|c| !is_alphanumeric(c)).unwrap_or(rest.len());
+            // the qualified names as `Int.toString`
+            while source[pos..].starts_with('.')
+                && source[pos + 1..].starts_with(|c: char| c.is_ascii_alphabetic())
+            {
+                pos += 1;
+                let rest = &source[pos..];
+                pos += rest.find(|c| !is_alphanumeric(c)).unwrap_or(rest.len());
+            }
+            Kind::Word
+        } else if is_symbolic(c) {
+            pos += rest.find(|c| !is_symbolic(c)).unwrap_or(rest.len());
+            Kind::Symbol
+        } else {
+            pos += c.len_utf8();
+            Kind::Punct
+        };
+        tokens.push(Token {
+            text: &source[start..pos],
+            kind,
+            comments: std::mem::take(&mut comments),
+            trailing: Vec::new(),
+            newlines,
+        });
+        newlines = 0;
+    }
+    tokens.push(Token {
+        text: "",
+        kind: Kind::Eof,
+        comments,
+        trailing: Vec::new(),
+        newlines,
+    });
+    Ok(tokens)
+}
+
+/// the layout of the code, in the manner of Wadler's "A prettier printer"
+#[derive(Debug, Clone)]
+enum Doc {
+    Text(String),
+    /// `flat` if the group fits in the line, otherwise a newline followed by `broken`
+    Line {
+        flat: &'static str,
+        broken: &'static str,
+    },
+    HardLine,
+    BlankLine,
+    Nest(usize, Box<Doc>),
+    Group(Box<Doc>),
+    Concat(Vec<Doc>),
+}
+
+impl Doc {
+    fn text(s: impl Into<String>) -> Self {
+        Doc::Text(s.into())
+    }
+
+    fn line() -> Self {
+        Doc::Line {
+            flat: " ",
+            broken: "",
+        }
+    }
+
+    fn nest(indent: usize, doc: Doc) -> Self {
+        Doc::Nest(indent, Box::new(doc))
+    }
+
+    fn group(doc: Doc) -> Self {
+        Doc::Group(Box::new(doc))
+    }
+}
+
+const DECLARATIONS: &[&str] = &[
+    "val",
+    "fun",
+    "datatype",
+    "exception",
+    "infix",
+    "infixr",
+    "_import",
+];
+
+/// builds the layout from the tokens.
+/// it follows the structure of the code loosely, accepting any sequence of the tokens
+struct Layout<'a> {
+    tokens: Vec<Token<'a>>,
+    pos: usize,
+    indent: usize,
+}
+
+impl<'a> Layout<'a> {
+    fn peek(&self) -> &Token<'a> {
+        &self.tokens[self.pos]
+    }
+
+    fn is(&self, text: &str) -> bool {
+        let token = self.peek();
+        token.kind != Kind::Literal && token.kind != Kind::Eof && token.text == text
+    }
+
+    /// whether the token `offset` tokens ahead is `text`
+    fn is_at(&self, offset: usize, text: &str) -> bool {
+        match self.tokens.get(self.pos + offset) {
+            Some(token) => token.kind != Kind::Literal && token.text == text,
+            None => false,
+        }
+    }
+
+    fn at_stop(&self, stops: &[&str]) -> bool {
+        let token = self.peek();
+        match token.kind {
+            Kind::Eof => true,
+            Kind::Literal => false,
+            _ => stops.contains(&token.text) || DECLARATIONS.contains(&token.text),
+        }
+    }
+
+    /// the token with the comments before it
+    fn token(&mut self) -> Doc {
+        let token = self.peek().clone();
+        if token.kind != Kind::Eof {
+            self.pos += 1;
+        }
+        let mut docs = Vec::new();
+        let mut comments = token.comments.iter().peekable();
+        while let Some(comment) = comments.next() {
+            match comment.newlines {
+                0 => (),
+                1 => docs.push(Doc::HardLine),
+                _ => docs.push(Doc::BlankLine),
+            }
+            docs.push(Doc::text(comment.text));
+            let newlines = comments
+                .peek()
+                .map(|c| c.newlines)
+                .unwrap_or(token.newlines);
+            match newlines {
+                0 if token.kind != Kind::Eof => docs.push(Doc::text(" ")),
+                0 | 1 => docs.push(Doc::HardLine),
+                _ => docs.push(Doc::BlankLine),
+            }
+        }
+        docs.push(Doc::text(token.text));
+        for comment in &token.trailing {
+            docs.push(Doc::text(" "));
+            docs.push(Doc::text(*comment));
+        }
+        Doc::Concat(docs)
+    }
+
+    fn program(&mut self) -> Doc {
+        let mut docs = vec![self.decls()];
+        // the tokens left by unbalanced `in` and `end`
+        while self.peek().kind != Kind::Eof {
+            docs.push(Doc::HardLine);
+            docs.push(self.token());
+            docs.push(self.decls());
+        }
+        docs.push(self.token());
+        Doc::Concat(docs)
+    }
+
+    fn decls(&mut self) -> Doc {
+        let mut docs = Vec::new();
+        while !self.at_stop(&["in", "end"]) || DECLARATIONS.contains(&self.peek().text) {
+            if !docs.is_empty() {
+                if 2 <= self.peek().newlines_before() {
+                    docs.push(Doc::BlankLine);
+                } else {
+                    docs.push(Doc::HardLine);
+                }
+            }
+            docs.push(self.decl());
+        }
+        Doc::Concat(docs)
+    }
+
+    fn decl(&mut self) -> Doc {
+        const STOPS: &[&str] = &["in", "end"];
+        if !DECLARATIONS.contains(&self.peek().text) {
+            return self.seq(STOPS);
+        }
+        let keyword = self.peek().text;
+        let mut docs = vec![self.token(), Doc::text(" ")];
+        match keyword {
+            // `datatype t = datatype M.s`, whose second `datatype` doesn't start a declaration
+            "datatype" if self.is_at(1, "=") && self.is_at(2, "datatype") => {
+                for _ in 0..3 {
+                    docs.push(self.token());
+                    docs.push(Doc::text(" "));
+                }
+                docs.push(self.seq(STOPS));
+            }
+            "fun" | "datatype" => docs.push(self.clauses(STOPS)),
+            _ => docs.push(self.seq(STOPS)),
+        }
+        Doc::group(Doc::Concat(docs))
+    }
+
+    /// the clauses separated by `|`, of which the first one is on the current line
+    fn clauses(&mut self, stops: &[&str]) -> Doc {
+        let stops = with(stops, &["|"]);
+        let mut docs = vec![self.seq(&stops)];
+        while self.is("|") {
+            let bar = vec![Doc::line(), self.token(), Doc::text(" "), self.seq(&stops)];
+            docs.push(Doc::nest(2, Doc::Concat(bar)));
+        }
+        Doc::Concat(docs)
+    }
+
+    /// an expression, or a part of a declaration, up to the stops
+    fn seq(&mut self, stops: &[&str]) -> Doc {
+        let start = self.pos;
+        let mut docs = Vec::new();
+        // whether no space goes before the next token
+        let mut tight = true;
+        while !self.at_stop(stops) {
+            let token = self.peek().clone();
+            let space = if tight || token.text == "." {
+                Doc::text("")
+            } else if token.kind == Kind::Symbol && !["~", "!", "#"].contains(&token.text) {
+                // the operators begin the continuation lines
+                Doc::nest(self.indent, Doc::line())
+            } else {
+                Doc::text(" ")
+            };
+            tight = (token.kind == Kind::Symbol && token.text == "#") || token.text == ".";
+            let doc = match (token.kind, token.text) {
+                (Kind::Literal, _) => self.token(),
+                (_, "=") | (_, "=>") => {
+                    let left = Doc::Concat(docs);
+                    let op = self.token();
+                    let right = self.seq(stops);
+                    return Doc::group(Doc::Concat(vec![
+                        left,
+                        Doc::text(" "),
+                        op,
+                        Doc::nest(self.indent, Doc::Concat(vec![Doc::line(), right])),
+                    ]));
+                }
+                (_, "handle") => {
+                    let left = Doc::Concat(std::mem::take(&mut docs));
+                    let handle = self.token();
+                    let clauses = self.clauses(stops);
+                    docs.push(Doc::group(Doc::Concat(vec![
+                        left,
+                        Doc::line(),
+                        handle,
+                        Doc::text(" "),
+                        clauses,
+                    ])));
+                    continue;
+                }
+                (_, "let") => self.let_(),
+                (_, "(") => self.bracket(")"),
+                (_, "[") => self.bracket("]"),
+                (_, "{") => self.bracket("}"),
+                (_, "case") => self.case(stops),
+                (_, "fn") => {
+                    let fn_ = self.token();
+                    Doc::group(Doc::Concat(vec![fn_, Doc::text(" "), self.clauses(stops)]))
+                }
+                (_, "if") => self.if_(stops),
+                _ => self.token(),
+            };
+            docs.push(space);
+            docs.push(doc);
+        }
+        if self.pos == start && self.peek().kind != Kind::Eof && !stops.contains(&self.peek().text)
+        {
+            // a declaration keyword out of place
+            docs.push(self.token());
+        }
+        Doc::group(Doc::Concat(docs))
+    }
+
+    fn bracket(&mut self, close: &str) -> Doc {
+        let open = self.token();
+        let stops = [close, ",", ";"];
+        let mut items = Vec::new();
+        while !self.is(close) && self.peek().kind != Kind::Eof {
+            let start = self.pos;
+            items.push(self.seq(&stops));
+            if self.is(",") || self.is(";") {
+                items.push(self.token());
+                items.push(Doc::nest(self.indent, Doc::line()));
+            } else if self.pos == start && !self.is(close) {
+                items.push(self.token());
+            }
+        }
+        let close = self.token();
+        items.insert(0, open);
+        items.push(close);
+        Doc::group(Doc::Concat(items))
+    }
+
+    fn let_(&mut self) -> Doc {
+        let mut docs = vec![self.token()];
+        docs.push(Doc::nest(
+            self.indent,
+            Doc::Concat(vec![Doc::HardLine, self.decls()]),
+        ));
+        if self.is("in") {
+            docs.push(Doc::HardLine);
+            docs.push(self.token());
+            let mut body = Vec::new();
+            while !self.is("end") && self.peek().kind != Kind::Eof {
+                let start = self.pos;
+                body.push(Doc::HardLine);
+                body.push(self.seq(&["end", ";"]));
+                // the separator, or a token which can't start an expression
+                if self.is(";") || self.pos == start {
+                    body.push(self.token());
+                }
+            }
+            docs.push(Doc::nest(self.indent, Doc::Concat(body)));
+        }
+        if self.is("end") {
+            docs.push(Doc::HardLine);
+            docs.push(self.token());
+        }
+        Doc::Concat(docs)
+    }
+
+    fn case(&mut self, stops: &[&str]) -> Doc {
+        let mut docs = vec![self.token(), Doc::text(" ")];
+        docs.push(self.seq(&with(stops, &["of"])));
+        if !self.is("of") {
+            return Doc::group(Doc::Concat(docs));
+        }
+        docs.push(Doc::text(" "));
+        docs.push(self.token());
+        let stops = with(stops, &["|"]);
+        let mut clauses = vec![
+            Doc::Line {
+                flat: " ",
+                broken: "  ",
+            },
+            self.seq(&stops),
+        ];
+        while self.is("|") {
+            clauses.push(Doc::line());
+            clauses.push(self.token());
+            clauses.push(Doc::text(" "));
+            clauses.push(self.seq(&stops));
+        }
+        docs.push(Doc::nest(self.indent, Doc::Concat(clauses)));
+        Doc::group(Doc::Concat(docs))
+    }
+
+    fn if_(&mut self, stops: &[&str]) -> Doc {
+        let mut docs = vec![self.token(), Doc::text(" ")];
+        docs.push(self.seq(&with(stops, &["then", "else"])));
+        if self.is("then") {
+            docs.push(Doc::line());
+            docs.push(self.token());
+            docs.push(Doc::text(" "));
+            docs.push(self.seq(&with(stops, &["else"])));
+        }
+        if self.is("else") {
+            docs.push(Doc::line());
+            docs.push(self.token());
+            docs.push(Doc::text(" "));
+            docs.push(self.seq(stops));
+        }
+        Doc::group(Doc::Concat(docs))
+    }
+}
+
+fn with<'a>(stops: &[&'a str], more: &[&'a str]) -> Vec<&'a str> {
+    stops.iter().chain(more).cloned().collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Flat,
+    Break,
+}
+
+struct Renderer {
+    width: usize,
+    out: String,
+    column: usize,
+    /// the newlines and the indentation to write before the next text
+    newlines: usize,
+    indent: usize,
+}
+
+impl Renderer {
+    fn new(width: usize) -> Self {
+        Renderer {
+            width,
+            out: String::new(),
+            column: 0,
+            newlines: 0,
+            indent: 0,
+        }
+    }
+
+    fn text(&mut self, s: &str) {
+        if s.is_empty() {
+            return;
+        }
+        if 0 < self.newlines {
+            // no newlines at the start of the file
+            if !self.out.is_empty() {
+                self.out.extend((0..self.newlines).map(|_| '\n'));
+            }
+            self.out.extend((0..self.indent).map(|_| ' '));
+            self.column = self.indent;
+            self.newlines = 0;
+        }
+        self.out.push_str(s);
+        self.column += s.chars().count();
+    }
+
+    fn newline(&mut self, newlines: usize, indent: usize) {
+        self.newlines = self.newlines.max(newlines);
+        self.indent = indent;
+    }
+
+    /// the column the next text is written at
+    fn next_column(&self) -> usize {
+        if 0 < self.newlines {
+            self.indent
+        } else {
+            self.column
+        }
+    }
+
+    /// whether the document fits in the rest of the line if laid out flat,
+    /// followed by the rest of the commands up to the next line break
+    fn fits(&self, doc: &Doc, rest: &[(usize, Mode, &Doc)]) -> bool {
+        let mut width = self.width as isize - self.next_column() as isize;
+        let mut stack = vec![(Mode::Flat, doc)];
+        let mut rest = rest.iter().rev();
+        loop {
+            let (mode, doc) = match stack.pop() {
+                Some(cmd) => cmd,
+                None => match rest.next() {
+                    Some(&(_, mode, doc)) => (mode, doc),
+                    None => return true,
+                },
+            };
+            match doc {
+                Doc::Text(s) => width -= s.chars().count() as isize,
+                Doc::Line { flat, .. } => match mode {
+                    Mode::Flat => width -= flat.len() as isize,
+                    Mode::Break => return true,
+                },
+                Doc::HardLine | Doc::BlankLine => return mode == Mode::Break,
+                Doc::Nest(_, doc) => stack.push((mode, doc)),
+                Doc::Group(doc) => stack.push((mode, doc)),
+                Doc::Concat(docs) => stack.extend(docs.iter().rev().map(|doc| (mode, doc))),
+            }
+            if width < 0 {
+                return false;
+            }
+        }
+    }
+
+    fn render(mut self, doc: &Doc) -> String {
+        let mut stack = vec![(0, Mode::Break, doc)];
+        while let Some((indent, mode, doc)) = stack.pop() {
+            match doc {
+                Doc::Text(s) => self.text(s),
+                Doc::Line { flat, broken } => match mode {
+                    Mode::Flat => self.text(flat),
+                    Mode::Break => {
+                        self.newline(1, indent);
+                        self.text(broken);
+                    }
+                },
+                Doc::HardLine => self.newline(1, indent),
+                Doc::BlankLine => self.newline(2, indent),
+                Doc::Nest(n, doc) => stack.push((indent + n, mode, doc)),
+                Doc::Group(doc) => {
+                    let mode = if mode == Mode::Flat || self.fits(doc, &stack) {
+                        Mode::Flat
+                    } else {
+                        Mode::Break
+                    };
+                    stack.push((indent, mode, doc));
+                }
+                Doc::Concat(docs) => stack.extend(docs.iter().rev().map(|doc| (indent, mode, doc))),
+            }
+        }
+        if !self.out.is_empty() {
+            self.out.push('\n');
+        }
+        self.out
+    }
+}
@@ -1,9 +1,12 @@
 use crate::ast;
-use crate::config::Config;
+use crate::ast::TypeError;
+use crate::config::{Config, Overflow};
 use crate::hir::{Expr, HTy, Pattern, SymbolTable, TypeInfo, Val, HIR};
 use crate::id::Id;
 use crate::pass::Pass;
 use crate::prim::*;
+use crate::util::ensure_stack;
+use std::collections::HashMap;
 
 pub struct AST2HIR {
     id: Id,
@@ -12,6 +15,13 @@ pub struct AST2HIR {
 struct AST2HIRPass {
     symbol_table: ast::SymbolTable,
     id: Id,
+    overflow: Overflow,
+    /// the functions comparing the values of the datatypes, by the datatype
+    equalities: HashMap<Symbol, Symbol>,
+    /// the functions hashing the values of the datatypes, by the datatype
+    hashes: HashMap<Symbol, Symbol>,
+    /// the definitions of `equalities` and `hashes`, each after the ones it calls
+    derived_funs: Vec<Val>,
 }
 
 impl AST2HIR {
@@ -19,8 +29,8 @@ impl AST2HIR {
         Self { id }
     }
 
-    fn generate_pass(&mut self, symbol_table: ast::SymbolTable) -> AST2HIRPass {
-        AST2HIRPass::new(symbol_table, self.id.clone())
+    fn generate_pass(&mut self, symbol_table: ast::SymbolTable, config: &Config) -> AST2HIRPass {
+        AST2HIRPass::new(symbol_table, self.id.clone(), config.overflow)
     }
 }
 
@@ -31,6 +41,91 @@ fn conv_symbol_table(symbol_table: ast::SymbolTable) -> SymbolTable {
             .into_iter()
             .map(|(k, v)| (k, conv_type_info(v)))
             .collect(),
+        exports: Vec::new(),
+    }
+}
+
+/// finds the top level functions to export. later definitions shadow earlier ones
+fn resolve_exports(
+    hir: &HIR,
+    symbol_table: &ast::SymbolTable,
+    names: &[String],
+) -> Result<Vec<(Symbol, String, Option<ExportTypes>)>, String> {
+    names
+        .iter()
+        .map(|export| {
+            hir.0
+                .iter()
+                .rev()
+                .find(|val| val.name.0 == export.as_str())
+                .and_then(|val| match &val.expr {
+                    Expr::Fun { param, body_ty, .. } => {
+                        let types = export_types(symbol_table, &param.0, body_ty);
+                        Some((val.name.clone(), export.clone(), Some(types)))
+                    }
+                    _ => None,
+                })
+                .ok_or_else(|| export.clone())
+        })
+        .collect()
+}
+
+fn export_types(symbol_table: &ast::SymbolTable, param: &HTy, ret: &HTy) -> ExportTypes {
+    let mut datatypes = Vec::new();
+    let param = export_ty(symbol_table, &mut datatypes, param);
+    let ret = export_ty(symbol_table, &mut datatypes, ret);
+    ExportTypes {
+        param,
+        ret,
+        datatypes: datatypes
+            .into_iter()
+            .map(|(name, constructors)| (name.0.to_string(), constructors))
+            .collect(),
+    }
+}
+
+/// the type for the JS glue, numbering the datatypes in the order they are found
+fn export_ty(
+    symbol_table: &ast::SymbolTable,
+    datatypes: &mut Vec<(Symbol, Vec<(String, Option<ExportTy>)>)>,
+    ty: &HTy,
+) -> ExportTy {
+    match ty {
+        HTy::Char => ExportTy::Char,
+        HTy::Int => ExportTy::Int,
+        HTy::Word => ExportTy::Word,
+        HTy::Real => ExportTy::Real,
+        HTy::String => ExportTy::String,
+        HTy::Tuple(tys) => ExportTy::Tuple(
+            tys.iter()
+                .map(|ty| export_ty(symbol_table, datatypes, ty))
+                .collect(),
+        ),
+        HTy::Fun(..) => ExportTy::Fun,
+        ty if *ty == bool_ty() => ExportTy::Bool,
+        HTy::Datatype(name) => {
+            if let Some(index) = datatypes.iter().position(|(n, _)| n == name) {
+                return ExportTy::Datatype(index as u32);
+            }
+            // numbered before the constructors, which may refer to the datatype itself
+            let index = datatypes.len();
+            datatypes.push((*name, Vec::new()));
+            let info = symbol_table
+                .get_type(name)
+                .expect("internal error: type not found");
+            let constructors = info
+                .constructors
+                .iter()
+                .map(|(cname, arg)| {
+                    let arg = arg
+                        .clone()
+                        .map(|arg| export_ty(symbol_table, datatypes, &conv_ty(arg)));
+                    (cname.0.to_string(), arg)
+                })
+                .collect();
+            datatypes[index].1 = constructors;
+            ExportTy::Datatype(index as u32)
+        }
     }
 }
 
@@ -50,7 +145,9 @@ fn conv_ty(ty: ast::Type) -> HTy {
     match ty {
         Char => HTy::Char,
         Int => HTy::Int,
+        Word => HTy::Word,
         Real => HTy::Real,
+        String => HTy::String,
         Tuple(tys) => HTy::Tuple(tys.into_iter().map(|ty| conv_ty(ty)).collect()),
         Fun(arg, ret) => HTy::fun(conv_ty(*arg), conv_ty(*ret)),
         Datatype(name) => HTy::Datatype(name),
@@ -58,9 +155,69 @@ fn conv_ty(ty: ast::Type) -> HTy {
     }
 }
 
+// `datatype bool = false | true`
+fn bool_ty() -> HTy {
+    HTy::Datatype(Symbol::new("bool"))
+}
+
+fn bool_lit(value: bool) -> Expr {
+    Expr::Constructor {
+        ty: bool_ty(),
+        arg: None,
+        descriminant: value as u32,
+    }
+}
+
+/// `if cond then then_ else else_`
+fn if_(ty: HTy, cond: Expr, then_: Expr, else_: Expr) -> Expr {
+    let arm = |value: bool| Pattern::Constructor {
+        descriminant: value as u32,
+        arg: None,
+        ty: bool_ty(),
+    };
+    Expr::Case {
+        ty,
+        expr: Box::new(cond),
+        arms: vec![(arm(true), then_), (arm(false), else_)],
+    }
+}
+
+/// the parameters of 32-bit FNV-1a, which the runtime hashes the strings with
+const FNV_OFFSET: u32 = 0x811c_9dc5;
+const FNV_PRIME: u32 = 0x0100_0193;
+
+fn word_lit(value: u32) -> Expr {
+    Expr::Lit {
+        ty: HTy::Word,
+        value: Literal::Word(value),
+    }
+}
+
+/// `Word.xorb (hash, value) * 0wx1000193`
+fn mix(hash: Expr, value: Expr) -> Expr {
+    let xor = Expr::ExternCall {
+        ty: HTy::Word,
+        module: "wasm".into(),
+        fun: "i32.xor".into(),
+        args: vec![hash, value],
+    };
+    Expr::BuiltinCall {
+        ty: HTy::Word,
+        fun: BIF::Mul,
+        args: vec![xor, word_lit(FNV_PRIME)],
+    }
+}
+
 impl AST2HIRPass {
-    fn new(symbol_table: ast::SymbolTable, id: Id) -> Self {
-        Self { symbol_table, id }
+    fn new(symbol_table: ast::SymbolTable, id: Id, overflow: Overflow) -> Self {
+        Self {
+            symbol_table,
+            id,
+            overflow,
+            equalities: HashMap::new(),
+            hashes: HashMap::new(),
+            derived_funs: Vec::new(),
+        }
     }
     fn symbol_table(&self) -> &ast::SymbolTable {
         &self.symbol_table
@@ -80,16 +237,19 @@ impl AST2HIRPass {
     }
 
     fn conv_ast(&mut self, ast: ast::TypedCore) -> HIR {
-        HIR(ast
+        let vals = ast
             .0
             .into_iter()
             .flat_map(|decl| self.conv_statement(decl))
-            .collect())
+            .collect::<Vec<_>>();
+        let mut derived_funs = std::mem::take(&mut self.derived_funs);
+        derived_funs.extend(vals);
+        HIR(derived_funs)
     }
 
     fn conv_statement(&mut self, decl: ast::TypedCoreDeclaration) -> Vec<Val> {
         match decl {
-            ast::Declaration::Datatype { .. } => {
+            ast::Declaration::Datatype { .. } | ast::Declaration::Exception { .. } => {
                 // ignore
                 vec![]
             }
@@ -98,7 +258,7 @@ impl AST2HIRPass {
                 match pattern.inner {
                     ast::PatternKind::Variable { name } => vec![Val {
                         ty: conv_ty(ty),
-                        rec: false,
+                        rec,
                         name: name,
                         expr: self.conv_expr(expr),
                     }],
@@ -170,6 +330,13 @@ impl AST2HIRPass {
                         // val var2 = #2 tmp
                         // val var3 = #3 tmp
                         // ```
+                        //
+                        // where the case binds fresh variables, so that each is assigned once
+                        let fresh = pattern
+                            .binds()
+                            .into_iter()
+                            .map(|(name, _)| (name.clone(), Symbol(name.0, self.id.next())))
+                            .collect::<HashMap<_, _>>();
                         let binds = pattern
                             .binds()
                             .iter()
@@ -183,14 +350,16 @@ impl AST2HIRPass {
                                     let ty = conv_ty(ty.clone());
                                     let expr = Expr::Sym {
                                         ty: ty.clone(),
-                                        name: name.clone(),
+                                        name: fresh[name],
                                     };
                                     (ty, expr)
                                 })
                                 .unzip();
                             let tuple_tys = HTy::Tuple(tys.clone());
                             let tuple = Expr::Tuple { tys, tuple };
-                            let pattern = self.conv_pat(pattern);
+                            let pattern = self
+                                .conv_pat(pattern)
+                                .rename(|name| fresh.get(&name).copied().unwrap_or(name));
                             // FIXME: this transformation should be done before case_check
                             // assert!(pattern.is_irrefutable());
 
@@ -227,6 +396,9 @@ impl AST2HIRPass {
                         }
                         ret
                     }
+                    ast::PatternKind::Or { .. } => {
+                        panic!("internal error: or-patterns must be expanded by case_simplify")
+                    }
                 }
             }
             ast::Declaration::D(d) => match d {},
@@ -234,76 +406,408 @@ impl AST2HIRPass {
     }
 
     fn conv_expr(&mut self, expr: ast::TypedCoreExpr) -> Expr {
-        use crate::ast::ExprKind as E;
-        let ty = expr.ty;
-        match expr.inner {
-            E::Binds { binds, ret } => Expr::Binds {
-                ty: conv_ty(ty),
-                binds: binds
-                    .into_iter()
-                    .flat_map(|s| self.conv_statement(s))
-                    .collect(),
-                ret: Box::new(self.conv_expr(*ret)),
-            },
-            E::BuiltinCall { fun, args } => Expr::BuiltinCall {
-                ty: conv_ty(ty),
+        ensure_stack(|| {
+            use crate::ast::ExprKind as E;
+            let ty = expr.ty;
+            match expr.inner {
+                E::Binds { binds, ret } => Expr::Binds {
+                    ty: conv_ty(ty),
+                    binds: binds
+                        .into_iter()
+                        .flat_map(|s| self.conv_statement(s))
+                        .collect(),
+                    ret: Box::new(self.conv_expr(*ret)),
+                },
+                E::BuiltinCall { fun, args } => {
+                    let args = args
+                        .into_iter()
+                        .map(|arg| self.conv_expr(arg))
+                        .collect::<Vec<_>>();
+                    self.conv_builtin_call(conv_ty(ty), fun, args)
+                }
+                E::ExternCall {
+                    module,
+                    fun,
+                    args,
+                    argty: _,
+                    retty: _,
+                } => Expr::ExternCall {
+                    ty: conv_ty(ty),
+                    module,
+                    fun,
+
+                    args: args.into_iter().map(|arg| self.conv_expr(arg)).collect(),
+                },
+                E::Fn { param, body } => {
+                    let (param_ty, body_ty) = match ty {
+                        ast::Type::Fun(param_ty, body_ty) => (*param_ty, *body_ty),
+                        _ => panic!("internal error: functon is not typed as function"),
+                    };
+                    Expr::Fun {
+                        param: (conv_ty(param_ty), param),
+                        body_ty: conv_ty(body_ty),
+                        body: Box::new(self.conv_expr(*body)),
+                        captures: Vec::new(),
+                    }
+                }
+                E::App { fun, arg } => self.conv_expr(*fun).app1(conv_ty(ty), self.conv_expr(*arg)),
+                E::Case { cond, clauses } => Expr::Case {
+                    ty: conv_ty(ty),
+                    expr: Box::new(self.conv_expr(*cond)),
+                    arms: clauses
+                        .into_iter()
+                        .map(|(pat, expr)| (self.conv_pat(pat), self.conv_expr(expr)))
+                        .collect(),
+                },
+                E::Tuple { tuple } => Expr::Tuple {
+                    tys: self.force_tuple(ty),
+                    tuple: tuple.into_iter().map(|e| self.conv_expr(e)).collect(),
+                },
+                E::Constructor { arg, name } => Expr::Constructor {
+                    ty: conv_ty(ty),
+                    arg: arg.map(|a| Box::new(self.conv_expr(*a))),
+                    descriminant: self.conv_constructor_name(&name),
+                },
+                E::Symbol { name } => Expr::Sym {
+                    ty: conv_ty(ty),
+                    name,
+                },
+                E::Literal { value } => Expr::Lit {
+                    ty: conv_ty(ty),
+                    value,
+                },
+                E::D(d) => match d {},
+            }
+        })
+    }
+    fn conv_builtin_call(&mut self, ty: HTy, fun: BIF, args: Vec<Expr>) -> Expr {
+        match args.first().map(Expr::ty) {
+            Some(arg_ty) if fun == BIF::Hash => {
+                let arg = args.into_iter().next().unwrap();
+                self.structural_hash(arg_ty, arg)
+            }
+            // the runtime compares the strings, and the result is compared with 0
+            Some(HTy::String) => Expr::BuiltinCall {
+                ty,
                 fun,
-                args: args.into_iter().map(|arg| self.conv_expr(arg)).collect(),
+                args: vec![
+                    Expr::ExternCall {
+                        ty: HTy::Int,
+                        module: "webml-rt".into(),
+                        fun: "string_compare".into(),
+                        args,
+                    },
+                    Expr::Lit {
+                        ty: HTy::Int,
+                        value: Literal::Int(0),
+                    },
+                ],
             },
-            E::ExternCall {
-                module,
-                fun,
-                args,
-                argty: _,
-                retty: _,
-            } => Expr::ExternCall {
-                ty: conv_ty(ty),
-                module,
-                fun,
+            Some(arg_ty @ HTy::Tuple(_)) | Some(arg_ty @ HTy::Datatype(_))
+                if fun == BIF::Eq || fun == BIF::Neq =>
+            {
+                let mut args = args.into_iter();
+                let (l, r) = (args.next().unwrap(), args.next().unwrap());
+                let equal = self.structural_equal(arg_ty, l, r);
+                match fun {
+                    BIF::Eq => equal,
+                    BIF::Neq => if_(ty, equal, bool_lit(false), bool_lit(true)),
+                    _ => unreachable!("internal error: {:?} on structural values", fun),
+                }
+            }
+            Some(HTy::Int) if [BIF::Div, BIF::Mod].contains(&fun) => self.checked_div(fun, args),
+            // `Config::overflow` selects whether the arithmetic of `int` is checked
+            Some(HTy::Int)
+                if self.overflow == Overflow::Raise
+                    && [BIF::Add, BIF::Sub, BIF::Mul].contains(&fun) =>
+            {
+                self.checked_arith(fun, args)
+            }
+            _ => Expr::BuiltinCall { ty, fun, args },
+        }
+    }
 
-                args: args.into_iter().map(|arg| self.conv_expr(arg)).collect(),
-            },
-            E::Fn { param, body } => {
-                let (param_ty, body_ty) = match ty {
-                    ast::Type::Fun(param_ty, body_ty) => (*param_ty, *body_ty),
-                    _ => panic!("internal error: functon is not typed as function"),
+    /// `l = r` of the tuples and the datatypes, comparing the components.
+    /// the tuples are compared in place:
+    ///
+    /// ```text
+    /// let val l = l val r = r in if #1 l = #1 r then if #2 l = #2 r then true else false else false end
+    /// ```
+    ///
+    /// and the datatypes are passed to the functions generated by `equality_fun`
+    fn structural_equal(&mut self, ty: HTy, l: Expr, r: Expr) -> Expr {
+        match ty {
+            HTy::Tuple(tys) => {
+                let (lname, rname) = (self.gensym(), self.gensym());
+                let proj = |index: usize, ty: &HTy, name: Symbol| Expr::Proj {
+                    ty: ty.clone(),
+                    index: index as u32,
+                    tuple: Box::new(Expr::Sym {
+                        ty: HTy::Tuple(tys.clone()),
+                        name,
+                    }),
                 };
-                Expr::Fun {
-                    param: (conv_ty(param_ty), param),
-                    body_ty: conv_ty(body_ty),
-                    body: Box::new(self.conv_expr(*body)),
-                    captures: Vec::new(),
+                let mut ret = bool_lit(true);
+                for (index, ty) in tys.iter().enumerate().rev() {
+                    let equal = self.conv_builtin_call(
+                        bool_ty(),
+                        BIF::Eq,
+                        vec![proj(index, ty, lname), proj(index, ty, rname)],
+                    );
+                    ret = if_(bool_ty(), equal, ret, bool_lit(false));
                 }
-            }
-            E::App { fun, arg } => self.conv_expr(*fun).app1(conv_ty(ty), self.conv_expr(*arg)),
-            E::Case { cond, clauses } => Expr::Case {
-                ty: conv_ty(ty),
-                expr: Box::new(self.conv_expr(*cond)),
-                arms: clauses
+                let binds = vec![(lname, l), (rname, r)]
                     .into_iter()
-                    .map(|(pat, expr)| (self.conv_pat(pat), self.conv_expr(expr)))
-                    .collect(),
-            },
-            E::Tuple { tuple } => Expr::Tuple {
-                tys: self.force_tuple(ty),
-                tuple: tuple.into_iter().map(|e| self.conv_expr(e)).collect(),
-            },
-            E::Constructor { arg, name } => Expr::Constructor {
-                ty: conv_ty(ty),
-                arg: arg.map(|a| Box::new(self.conv_expr(*a))),
-                descriminant: self.conv_constructor_name(&name),
-            },
-            E::Symbol { name } => Expr::Sym {
-                ty: conv_ty(ty),
-                name,
-            },
-            E::Literal { value } => Expr::Lit {
-                ty: conv_ty(ty),
-                value,
+                    .map(|(name, expr)| Val {
+                        ty: HTy::Tuple(tys.clone()),
+                        rec: false,
+                        name,
+                        expr,
+                    })
+                    .collect();
+                Expr::Binds {
+                    ty: bool_ty(),
+                    binds,
+                    ret: Box::new(ret),
+                }
+            }
+            HTy::Datatype(name) => {
+                let fun = self.equality_fun(name);
+                let param_ty = HTy::Tuple(vec![ty.clone(), ty.clone()]);
+                Expr::Sym {
+                    ty: HTy::fun(param_ty.clone(), bool_ty()),
+                    name: fun,
+                }
+                .app1(
+                    bool_ty(),
+                    Expr::Tuple {
+                        tys: vec![ty.clone(), ty],
+                        tuple: vec![l, r],
+                    },
+                )
+            }
+            _ => unreachable!("internal error: {:?} is not structural", ty),
+        }
+    }
+
+    /// the top level function comparing the values of the datatype `name`:
+    ///
+    /// ```text
+    /// fun equal (a, b) = case a of
+    ///     C1 x => (case b of C1 y => x = y | _ => false)
+    ///   | C2 => (case b of C2 => true | _ => false)
+    /// ```
+    fn equality_fun(&mut self, name: Symbol) -> Symbol {
+        if let Some(fun) = self.equalities.get(&name) {
+            return *fun;
+        }
+        let fun = self.gensym();
+        self.equalities.insert(name, fun);
+
+        let ty = HTy::Datatype(name);
+        let constructors = self
+            .symbol_table()
+            .get_type(&name)
+            .expect("internal error: type not found")
+            .constructors
+            .iter()
+            .map(|(_, arg)| arg.clone().map(conv_ty))
+            .collect::<Vec<_>>();
+        let sym = |ty: &HTy, name: Symbol| Expr::Sym {
+            ty: ty.clone(),
+            name,
+        };
+        let (a, b) = (self.gensym(), self.gensym());
+        let mut arms = Vec::new();
+        for (descriminant, arg_ty) in constructors.iter().enumerate() {
+            let descriminant = descriminant as u32;
+            let (x, y) = (self.gensym(), self.gensym());
+            let equal = match arg_ty {
+                Some(arg_ty) => {
+                    self.conv_builtin_call(bool_ty(), BIF::Eq, vec![sym(arg_ty, x), sym(arg_ty, y)])
+                }
+                None => bool_lit(true),
+            };
+            let pattern = |name: Symbol| Pattern::Constructor {
+                descriminant,
+                arg: arg_ty.clone().map(|ty| (ty, name)),
+                ty: ty.clone(),
+            };
+            let mut inner_arms = vec![(pattern(y), equal)];
+            if constructors.len() > 1 {
+                let name = self.gensym();
+                let default = Pattern::Var {
+                    name,
+                    ty: ty.clone(),
+                };
+                inner_arms.push((default, bool_lit(false)));
+            }
+            let inner = Expr::Case {
+                ty: bool_ty(),
+                expr: Box::new(sym(&ty, b)),
+                arms: inner_arms,
+            };
+            arms.push((pattern(x), inner));
+        }
+        let param_ty = HTy::Tuple(vec![ty.clone(), ty.clone()]);
+        let param = self.gensym();
+        let body = Expr::Case {
+            ty: bool_ty(),
+            expr: Box::new(sym(&param_ty, param)),
+            arms: vec![(
+                Pattern::Tuple {
+                    tys: vec![ty.clone(), ty.clone()],
+                    tuple: vec![a, b],
+                },
+                Expr::Case {
+                    ty: bool_ty(),
+                    expr: Box::new(sym(&ty, a)),
+                    arms,
+                },
+            )],
+        };
+        self.derived_funs.push(Val {
+            ty: HTy::fun(param_ty.clone(), bool_ty()),
+            rec: true,
+            name: fun,
+            expr: Expr::Fun {
+                param: (param_ty, param),
+                body_ty: bool_ty(),
+                body: Box::new(body),
+                captures: Vec::new(),
             },
-            E::D(d) => match d {},
+        });
+        fun
+    }
+
+    /// `hash x`, following the type as `structural_equal` does. the scalars are the hashes of
+    /// themselves, the strings and the reals are hashed by the runtime, and the hashes of the
+    /// components of the tuples are mixed as FNV-1a does:
+    ///
+    /// ```text
+    /// let val x = x in mix (mix (0wx811c9dc5, hash (#1 x)), hash (#2 x)) end
+    /// ```
+    ///
+    /// where `mix (h, y)` is `Word.xorb (h, y) * 0wx1000193`.
+    /// the datatypes are passed to the functions generated by `hash_fun`
+    fn structural_hash(&mut self, ty: HTy, arg: Expr) -> Expr {
+        let extern_call = |module: &str, fun: &str, arg: Expr| Expr::ExternCall {
+            ty: HTy::Word,
+            module: module.into(),
+            fun: fun.into(),
+            args: vec![arg],
+        };
+        match ty {
+            HTy::Word => arg,
+            HTy::Int | HTy::Char => extern_call("wasm", "convert", arg),
+            HTy::Real => extern_call("webml-rt", "real_hash", arg),
+            HTy::String => extern_call("webml-rt", "string_hash", arg),
+            HTy::Tuple(tys) => {
+                let name = self.gensym();
+                let mut ret = word_lit(FNV_OFFSET);
+                for (index, ty) in tys.iter().enumerate() {
+                    let proj = Expr::Proj {
+                        ty: ty.clone(),
+                        index: index as u32,
+                        tuple: Box::new(Expr::Sym {
+                            ty: HTy::Tuple(tys.clone()),
+                            name,
+                        }),
+                    };
+                    ret = mix(ret, self.structural_hash(ty.clone(), proj));
+                }
+                Expr::Binds {
+                    ty: HTy::Word,
+                    binds: vec![Val {
+                        ty: HTy::Tuple(tys),
+                        rec: false,
+                        name,
+                        expr: arg,
+                    }],
+                    ret: Box::new(ret),
+                }
+            }
+            HTy::Datatype(name) => {
+                let fun = self.hash_fun(name);
+                Expr::Sym {
+                    ty: HTy::fun(ty.clone(), HTy::Word),
+                    name: fun,
+                }
+                .app1(HTy::Word, arg)
+            }
+            HTy::Fun(..) => unreachable!("internal error: functions are not hashed"),
         }
     }
+
+    /// the top level function hashing the values of the datatype `name`, mixing the
+    /// descriminant and the hash of the argument:
+    ///
+    /// ```text
+    /// fun hash a = case a of
+    ///     C0 x => mix (mix (0wx811c9dc5, 0w0), hash x)
+    ///   | C1 => mix (0wx811c9dc5, 0w1)
+    /// ```
+    fn hash_fun(&mut self, name: Symbol) -> Symbol {
+        if let Some(fun) = self.hashes.get(&name) {
+            return *fun;
+        }
+        let fun = self.gensym();
+        self.hashes.insert(name, fun);
+
+        let ty = HTy::Datatype(name);
+        let constructors = self
+            .symbol_table()
+            .get_type(&name)
+            .expect("internal error: type not found")
+            .constructors
+            .iter()
+            .map(|(_, arg)| arg.clone().map(conv_ty))
+            .collect::<Vec<_>>();
+        let mut arms = Vec::new();
+        for (descriminant, arg_ty) in constructors.into_iter().enumerate() {
+            let descriminant = descriminant as u32;
+            let x = self.gensym();
+            let hash = mix(word_lit(FNV_OFFSET), word_lit(descriminant));
+            let hash = match &arg_ty {
+                Some(arg_ty) => {
+                    let arg = Expr::Sym {
+                        ty: arg_ty.clone(),
+                        name: x,
+                    };
+                    mix(hash, self.structural_hash(arg_ty.clone(), arg))
+                }
+                None => hash,
+            };
+            let pattern = Pattern::Constructor {
+                descriminant,
+                arg: arg_ty.map(|ty| (ty, x)),
+                ty: ty.clone(),
+            };
+            arms.push((pattern, hash));
+        }
+        let param = self.gensym();
+        let body = Expr::Case {
+            ty: HTy::Word,
+            expr: Box::new(Expr::Sym {
+                ty: ty.clone(),
+                name: param,
+            }),
+            arms,
+        };
+        self.derived_funs.push(Val {
+            ty: HTy::fun(ty.clone(), HTy::Word),
+            rec: true,
+            name: fun,
+            expr: Expr::Fun {
+                param: (ty, param),
+                body_ty: HTy::Word,
+                body: Box::new(body),
+                captures: Vec::new(),
+            },
+        });
+        fun
+    }
+
     fn conv_pat(&mut self, pat: ast::TypedPattern) -> Pattern {
         let ty = pat.ty;
         match pat.inner {
@@ -344,9 +848,208 @@ impl AST2HIRPass {
                 ty: conv_ty(ty),
             },
             ast::PatternKind::Wildcard {} => Pattern::Var {
-                name: Symbol::new("_"),
+                name: self.gensym(),
                 ty: conv_ty(ty),
             },
+            ast::PatternKind::Or { .. } => {
+                panic!("internal error: or-patterns must be expanded by case_simplify")
+            }
+        }
+    }
+
+    /// `l op r` of `int` which raises `Overflow` instead of wrapping around.
+    /// the operation wraps, and the result is compared with the operands:
+    ///
+    /// ```text
+    /// let val ret = l op r in if overflowed then raise Overflow else ret end
+    /// ```
+    ///
+    /// where `overflowed` is
+    ///
+    /// - `if r < 0 then ret > l else ret < l` for `+`
+    /// - `if r < 0 then ret < l else ret > l` for `-`
+    /// - `if l = 0 then false else if l = ~1 then r = minInt else ret div l <> r` for `*`.
+    ///   `minInt div ~1` traps, so `~1 * minInt` is checked separately
+    fn checked_arith(&mut self, fun: BIF, args: Vec<Expr>) -> Expr {
+        let int = |value: i64| Expr::Lit {
+            ty: HTy::Int,
+            value: Literal::Int(value),
+        };
+        let sym = |name: Symbol| Expr::Sym { ty: HTy::Int, name };
+        let call = |ty: HTy, fun: BIF, l: Expr, r: Expr| Expr::BuiltinCall {
+            ty,
+            fun,
+            args: vec![l, r],
+        };
+        let compare = |fun: BIF, l: Expr, r: Expr| call(bool_ty(), fun, l, r);
+
+        let (l, r, ret) = (self.gensym(), self.gensym(), self.gensym());
+        let mut args = args.into_iter();
+        let binds = vec![
+            (l, args.next().unwrap()),
+            (r, args.next().unwrap()),
+            (ret, call(HTy::Int, fun, sym(l), sym(r))),
+        ]
+        .into_iter()
+        .map(|(name, expr)| Val {
+            ty: HTy::Int,
+            rec: false,
+            name,
+            expr,
+        })
+        .collect();
+        let overflowed = match fun {
+            BIF::Add => if_(
+                bool_ty(),
+                compare(BIF::Lt, sym(r), int(0)),
+                compare(BIF::Gt, sym(ret), sym(l)),
+                compare(BIF::Lt, sym(ret), sym(l)),
+            ),
+            BIF::Sub => if_(
+                bool_ty(),
+                compare(BIF::Lt, sym(r), int(0)),
+                compare(BIF::Lt, sym(ret), sym(l)),
+                compare(BIF::Gt, sym(ret), sym(l)),
+            ),
+            BIF::Mul => if_(
+                bool_ty(),
+                compare(BIF::Eq, sym(l), int(0)),
+                bool_lit(false),
+                if_(
+                    bool_ty(),
+                    compare(BIF::Eq, sym(l), int(-1)),
+                    compare(BIF::Eq, sym(r), int(i32::MIN.into())),
+                    compare(BIF::Neq, call(HTy::Int, BIF::Div, sym(ret), sym(l)), sym(r)),
+                ),
+            ),
+            _ => unreachable!("internal error: {:?} is not checked", fun),
+        };
+        let raise = self.raise(HTy::Int, "Overflow");
+        Expr::Binds {
+            ty: HTy::Int,
+            binds,
+            ret: Box::new(if_(HTy::Int, overflowed, raise, sym(ret))),
+        }
+    }
+
+    /// `l div r` and `l mod r` of `int` which raise `Div` instead of trapping on division by
+    /// zero. `minInt div ~1` also traps, so the division by `~1` is the negation:
+    ///
+    /// ```text
+    /// let val l = l val r = r in if r = 0 then raise Div else if r = ~1 then 0 - l else l div r end
+    /// ```
+    ///
+    /// where the negation is checked as `-` is when `Config::overflow` is `Raise`,
+    /// and `l mod ~1` is `0`. The builtin division truncates as `i32.div_s` does, but `div`
+    /// rounds toward negative infinity and `mod` takes the sign of `r`, so the last branch is
+    ///
+    /// ```text
+    /// let val q = l div r val m = l mod r in if m <> 0 and the signs of m and r differ then q - 1 else q end
+    /// ```
+    ///
+    /// and `m + r` instead of `q - 1` for `mod`
+    fn checked_div(&mut self, fun: BIF, args: Vec<Expr>) -> Expr {
+        let int = |value: i64| Expr::Lit {
+            ty: HTy::Int,
+            value: Literal::Int(value),
+        };
+        let sym = |name: Symbol| Expr::Sym { ty: HTy::Int, name };
+        let call = |ty: HTy, fun: BIF, l: Expr, r: Expr| Expr::BuiltinCall {
+            ty,
+            fun,
+            args: vec![l, r],
+        };
+
+        let (l, r) = (self.gensym(), self.gensym());
+        let mut args = args.into_iter();
+        let binds = vec![(l, args.next().unwrap()), (r, args.next().unwrap())]
+            .into_iter()
+            .map(|(name, expr)| Val {
+                ty: HTy::Int,
+                rec: false,
+                name,
+                expr,
+            })
+            .collect();
+        let (q, m) = (self.gensym(), self.gensym());
+        let compare = |fun: BIF, l: Expr, r: Expr| call(bool_ty(), fun, l, r);
+        let signs_differ = if_(
+            bool_ty(),
+            compare(BIF::Lt, sym(m), int(0)),
+            compare(BIF::Gt, sym(r), int(0)),
+            if_(
+                bool_ty(),
+                compare(BIF::Gt, sym(m), int(0)),
+                compare(BIF::Lt, sym(r), int(0)),
+                bool_lit(false),
+            ),
+        );
+        let (by_minus_one, quotient, floored) = match fun {
+            BIF::Div => (
+                self.conv_builtin_call(HTy::Int, BIF::Sub, vec![int(0), sym(l)]),
+                vec![(q, call(HTy::Int, BIF::Div, sym(l), sym(r)))],
+                if_(
+                    HTy::Int,
+                    signs_differ,
+                    call(HTy::Int, BIF::Sub, sym(q), int(1)),
+                    sym(q),
+                ),
+            ),
+            BIF::Mod => (
+                int(0),
+                vec![],
+                if_(
+                    HTy::Int,
+                    signs_differ,
+                    call(HTy::Int, BIF::Add, sym(m), sym(r)),
+                    sym(m),
+                ),
+            ),
+            _ => unreachable!("internal error: {:?} is not a division", fun),
+        };
+        let floored = Expr::Binds {
+            ty: HTy::Int,
+            binds: quotient
+                .into_iter()
+                .chain(Some((m, call(HTy::Int, BIF::Mod, sym(l), sym(r)))))
+                .map(|(name, expr)| Val {
+                    ty: HTy::Int,
+                    rec: false,
+                    name,
+                    expr,
+                })
+                .collect(),
+            ret: Box::new(floored),
+        };
+        let raise = self.raise(HTy::Int, "Div");
+        let ret = if_(
+            HTy::Int,
+            call(bool_ty(), BIF::Eq, sym(r), int(0)),
+            raise,
+            if_(
+                HTy::Int,
+                call(bool_ty(), BIF::Eq, sym(r), int(-1)),
+                by_minus_one,
+                floored,
+            ),
+        );
+        Expr::Binds {
+            ty: HTy::Int,
+            binds,
+            ret: Box::new(ret),
+        }
+    }
+
+    /// `raise name` of the builtin exception `name`, typed as `ty`
+    fn raise(&mut self, ty: HTy, name: &str) -> Expr {
+        Expr::BuiltinCall {
+            ty,
+            fun: BIF::Raise,
+            args: vec![Expr::Constructor {
+                ty: HTy::Datatype(Symbol::new("exn")),
+                arg: None,
+                descriminant: self.conv_constructor_name(&Symbol::new(name)),
+            }],
         }
     }
 
@@ -355,17 +1058,20 @@ impl AST2HIRPass {
     }
 }
 
-impl<E> Pass<(ast::SymbolTable, ast::TypedCore), E> for AST2HIR {
+impl<'a> Pass<(ast::SymbolTable, ast::TypedCore), TypeError<'a>> for AST2HIR {
     type Target = (SymbolTable, HIR);
 
     fn trans(
         &mut self,
         (symbol_table, ast): (ast::SymbolTable, ast::TypedCore),
-        _: &Config,
-    ) -> ::std::result::Result<Self::Target, E> {
-        let mut pass = self.generate_pass(symbol_table);
-        let ast = pass.conv_ast(ast);
-        let symbol_table = conv_symbol_table(pass.symbol_table);
-        Ok((symbol_table, ast))
+        config: &Config,
+    ) -> ::std::result::Result<Self::Target, TypeError<'a>> {
+        let mut pass = self.generate_pass(symbol_table, config);
+        let hir = pass.conv_ast(ast);
+        let exports = resolve_exports(&hir, &pass.symbol_table, &config.exports)
+            .map_err(TypeError::UnknownExport)?;
+        let mut symbol_table = conv_symbol_table(pass.symbol_table);
+        symbol_table.exports = exports;
+        Ok((symbol_table, hir))
     }
 }
@@ -0,0 +1,219 @@
+//! The call graph of the program in Graphviz DOT.
+//! Runs after the closure conversion, where all the functions are at the top level
+//! and the closures list the variables they capture.
+use crate::config::Config;
+use crate::hir::util::Traverse;
+use crate::hir::*;
+use crate::pass::Pass;
+use crate::util::PP;
+use std::collections::{HashMap, HashSet};
+use std::io;
+
+/// Writes the call graph to stdout, leaving the program unchanged.
+/// Solid edges are calls and dashed edges allocate the closures of the functions.
+pub struct CallGraph;
+
+/// the edges from a top level binding
+struct Edges<'a> {
+    functions: &'a HashSet<Symbol>,
+    /// local variables bound to the closures of the functions
+    locals: HashMap<Symbol, Symbol>,
+    calls: Vec<(Symbol, usize)>,
+    closures: Vec<(Symbol, usize)>,
+    externs: Vec<(String, usize)>,
+    /// calls of the closures not known statically
+    indirect_calls: usize,
+}
+
+fn count<T: PartialEq>(counts: &mut Vec<(T, usize)>, t: T) {
+    match counts.iter_mut().find(|(u, _)| *u == t) {
+        Some((_, n)) => *n += 1,
+        None => counts.push((t, 1)),
+    }
+}
+
+impl<'a> Edges<'a> {
+    fn new(functions: &'a HashSet<Symbol>) -> Self {
+        Edges {
+            functions,
+            locals: HashMap::new(),
+            calls: Vec::new(),
+            closures: Vec::new(),
+            externs: Vec::new(),
+            indirect_calls: 0,
+        }
+    }
+
+    fn function(&self, name: &Symbol) -> Option<Symbol> {
+        if self.functions.contains(name) {
+            Some(name.clone())
+        } else {
+            self.locals.get(name).cloned()
+        }
+    }
+}
+
+impl<'a> Traverse for Edges<'a> {
+    fn traverse_val(&mut self, val: &mut Val) {
+        let function = match &val.expr {
+            Expr::Closure { fname, .. } => Some(fname.clone()),
+            Expr::Sym { name, .. } => self.function(name),
+            _ => None,
+        };
+        if let Some(function) = function {
+            self.locals.insert(val.name.clone(), function);
+        }
+        self.traverse_expr(&mut val.expr)
+    }
+
+    fn traverse_closure(
+        &mut self,
+        _envs: &mut Vec<(HTy, Symbol)>,
+        _param_ty: &mut HTy,
+        _body_ty: &mut HTy,
+        fname: &mut Symbol,
+    ) {
+        count(&mut self.closures, fname.clone());
+    }
+
+    fn traverse_extern_call(
+        &mut self,
+        _ty: &mut HTy,
+        module: &mut String,
+        fun: &mut String,
+        args: &mut Vec<Expr>,
+    ) {
+        count(&mut self.externs, format!("{}.{}", module, fun));
+        for arg in args {
+            self.traverse_expr(arg)
+        }
+    }
+
+    fn traverse_app(&mut self, _ty: &mut HTy, fun: &mut Box<Expr>, arg: &mut Box<Expr>) {
+        let function = match &**fun {
+            Expr::Sym { name, .. } => self.function(name),
+            _ => None,
+        };
+        match function {
+            Some(function) => count(&mut self.calls, function),
+            None => self.indirect_calls += 1,
+        }
+        self.traverse_expr(fun);
+        self.traverse_expr(arg);
+    }
+}
+
+fn show<T: PP>(t: &T) -> String {
+    let mut buf = Vec::new();
+    t.pp(&mut buf, 0).unwrap();
+    String::from_utf8(buf).unwrap()
+}
+
+/// a double quoted DOT string, with `\n`s in `lines` kept as line breaks
+fn quote<S: AsRef<str>>(lines: &[S]) -> String {
+    let lines: Vec<String> = lines
+        .iter()
+        .map(|line| line.as_ref().replace('\\', "\\\\").replace('"', "\\\""))
+        .collect();
+    format!("\"{}\"", lines.join("\\n"))
+}
+
+/// the calls are labeled only if they occur more than once
+fn write_edge<W: io::Write>(
+    w: &mut W,
+    from: &str,
+    to: &str,
+    n: usize,
+    closure: bool,
+) -> io::Result<()> {
+    let mut attrs = Vec::new();
+    if closure {
+        attrs.push("style=dashed".to_string());
+    }
+    let what = if closure { "closure" } else { "call" };
+    if n > 1 {
+        attrs.push(format!("label={}", quote(&[format!("{} x{}", what, n)])));
+    } else if closure {
+        attrs.push(format!("label={}", quote(&[what])));
+    }
+    write!(w, "    {} -> {}", quote(&[from]), quote(&[to]))?;
+    if !attrs.is_empty() {
+        write!(w, " [{}]", attrs.join(", "))?;
+    }
+    writeln!(w, ";")
+}
+
+impl CallGraph {
+    pub fn new() -> Self {
+        CallGraph
+    }
+
+    pub fn write<W: io::Write>(&self, hir: &mut HIR, w: &mut W) -> io::Result<()> {
+        let functions: HashSet<Symbol> = hir
+            .0
+            .iter()
+            .filter(|val| matches!(val.expr, Expr::Fun { .. }))
+            .map(|val| val.name.clone())
+            .collect();
+        let mut externs = Vec::new();
+        writeln!(w, "digraph program {{")?;
+        writeln!(w, "    node [shape=box];")?;
+        for val in hir.0.iter_mut() {
+            let node = show(&val.name);
+            let mut label = vec![node.clone()];
+            if let Expr::Fun { captures, .. } = &val.expr {
+                for (ty, name) in captures {
+                    label.push(format!("captures {}: {}", show(name), show(ty)));
+                }
+            }
+            let mut edges = Edges::new(&functions);
+            edges.traverse_val(val);
+            match edges.indirect_calls {
+                0 => (),
+                1 => label.push("1 indirect call".to_string()),
+                n => label.push(format!("{} indirect calls", n)),
+            }
+            let shape = if functions.contains(&val.name) {
+                ""
+            } else {
+                ", shape=ellipse"
+            };
+            writeln!(
+                w,
+                "    {} [label={}{}];",
+                quote(&[&node]),
+                quote(&label),
+                shape
+            )?;
+            for (callee, n) in edges.calls {
+                write_edge(w, &node, &show(&callee), n, false)?;
+            }
+            for (function, n) in edges.closures {
+                write_edge(w, &node, &show(&function), n, true)?;
+            }
+            for (fun, n) in edges.externs {
+                write_edge(w, &node, &fun, n, false)?;
+                if !externs.contains(&fun) {
+                    externs.push(fun);
+                }
+            }
+        }
+        for fun in externs {
+            writeln!(w, "    {} [shape=plaintext];", quote(&[fun]))?;
+        }
+        writeln!(w, "}}")
+    }
+}
+
+impl<E> Pass<(SymbolTable, HIR), E> for CallGraph {
+    type Target = (SymbolTable, HIR);
+
+    fn trans(
+        &mut self,
+        (symbol_table, mut hir): (SymbolTable, HIR),
+        _: &Config,
+    ) -> ::std::result::Result<Self::Target, E> {
+        self.write(&mut hir, &mut io::stdout()).unwrap();
+        Ok((symbol_table, hir))
+    }
+}
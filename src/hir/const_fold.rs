@@ -0,0 +1,247 @@
+use crate::config::Config;
+use crate::hir::util::Transform;
+use crate::hir::*;
+use crate::pass::Pass;
+use std::collections::HashMap;
+
+/// Constant folding.
+/// Computes the arithmetic and the comparisons of the constants, and takes the arm of the case
+/// of a constant or of a tuple. The variables bound to the constants are known to be the
+/// constants, but left to `CopyProp` and `DCE`. The divisions are left to trap at runtime.
+pub struct ConstFold;
+
+/// the values known at compile time
+#[derive(Debug, Clone)]
+enum Constant {
+    Lit(Literal),
+    /// the constructor without the argument
+    Constructor(u32),
+    /// the tuple, with the elements which are known
+    Tuple(Vec<Option<Constant>>),
+}
+
+struct Folder {
+    constants: HashMap<Symbol, Constant>,
+}
+
+// `int` is `i32` in the generated code
+fn int(n: i64) -> i32 {
+    n as i32
+}
+
+fn compare<T: PartialOrd>(fun: BIF, l: T, r: T) -> Option<bool> {
+    use crate::prim::BIF::*;
+    match fun {
+        Eq => Some(l == r),
+        Neq => Some(l != r),
+        Gt => Some(l > r),
+        Ge => Some(l >= r),
+        Lt => Some(l < r),
+        Le => Some(l <= r),
+        _ => None,
+    }
+}
+
+/// the value of the builtin call of the constants, if it is known
+fn fold(fun: BIF, l: &Literal, r: &Literal) -> Option<Constant> {
+    use crate::prim::Literal::*;
+    use crate::prim::BIF::*;
+    let lit = match (fun, l, r) {
+        (Add, Int(l), Int(r)) => Int(int(*l).wrapping_add(int(*r)).into()),
+        (Sub, Int(l), Int(r)) => Int(int(*l).wrapping_sub(int(*r)).into()),
+        (Mul, Int(l), Int(r)) => Int(int(*l).wrapping_mul(int(*r)).into()),
+        (Add, Word(l), Word(r)) => Word(l.wrapping_add(*r)),
+        (Sub, Word(l), Word(r)) => Word(l.wrapping_sub(*r)),
+        (Mul, Word(l), Word(r)) => Word(l.wrapping_mul(*r)),
+        (Add, Real(l), Real(r)) => Real(l + r),
+        (Sub, Real(l), Real(r)) => Real(l - r),
+        (Mul, Real(l), Real(r)) => Real(l * r),
+        (Divf, Real(l), Real(r)) => Real(l / r),
+        // `datatype bool = false | true`
+        (fun, Int(l), Int(r)) => {
+            return compare(fun, int(*l), int(*r)).map(|b| Constant::Constructor(b as u32))
+        }
+        (fun, Char(l), Char(r)) | (fun, Word(l), Word(r)) => {
+            return compare(fun, l, r).map(|b| Constant::Constructor(b as u32))
+        }
+        (fun, Real(l), Real(r)) => {
+            return compare(fun, l, r).map(|b| Constant::Constructor(b as u32))
+        }
+        _ => return None,
+    };
+    Some(Constant::Lit(lit))
+}
+
+impl Constant {
+    fn into_expr(self, ty: HTy) -> Expr {
+        match self {
+            Constant::Lit(value) => Expr::Lit { ty, value },
+            Constant::Constructor(descriminant) => Expr::Constructor {
+                ty,
+                arg: None,
+                descriminant,
+            },
+            Constant::Tuple(_) => unreachable!("internal error: tuples are not folded"),
+        }
+    }
+
+    fn matches(&self, pat: &Pattern) -> bool {
+        match (self, pat) {
+            (_, Pattern::Var { .. }) => true,
+            (Constant::Lit(Literal::Int(n)), Pattern::Constant { value, .. }) => n == value,
+            (Constant::Lit(Literal::Char(c)), Pattern::Char { value, .. }) => c == value,
+            (Constant::Constructor(d), Pattern::Constructor { descriminant, .. }) => {
+                d == descriminant
+            }
+            (Constant::Tuple(_), Pattern::Tuple { .. }) => true,
+            _ => false,
+        }
+    }
+}
+
+impl Folder {
+    fn constant(&self, expr: &Expr) -> Option<Constant> {
+        match expr {
+            Expr::Lit { value, .. } => Some(Constant::Lit(value.clone())),
+            Expr::Constructor {
+                arg: None,
+                descriminant,
+                ..
+            } => Some(Constant::Constructor(*descriminant)),
+            Expr::Tuple { tuple, .. } => Some(Constant::Tuple(
+                tuple.iter().map(|elem| self.constant(elem)).collect(),
+            )),
+            Expr::Proj { index, tuple, .. } => match self.constant(tuple) {
+                Some(Constant::Tuple(elems)) => elems[*index as usize].clone(),
+                _ => None,
+            },
+            Expr::Sym { name, .. } => self.constants.get(name).cloned(),
+            _ => None,
+        }
+    }
+
+    /// records the value of the folded binding if it is known
+    fn bind(&mut self, val: &Val) {
+        if val.rec {
+            return;
+        }
+        // the bindings are kept, so the variable is the value of the body even if they have
+        // the side effects
+        let mut value = &val.expr;
+        while let Expr::Binds { ret, .. } = value {
+            value = ret;
+        }
+        if let Some(constant) = self.constant(value) {
+            self.constants.insert(val.name.clone(), constant);
+        }
+    }
+}
+
+impl Transform for Folder {
+    fn transform_val(&mut self, mut val: Val) -> Val {
+        val.expr = self.transform_expr(val.expr);
+        self.bind(&val);
+        val
+    }
+
+    fn transform_builtin_call(&mut self, ty: HTy, fun: BIF, args: Vec<Expr>) -> Expr {
+        let args = args
+            .into_iter()
+            .map(|arg| self.transform_expr(arg))
+            .collect::<Vec<_>>();
+        let folded = match args.as_slice() {
+            [l, r] => match (self.constant(l), self.constant(r)) {
+                (Some(Constant::Lit(l)), Some(Constant::Lit(r))) => fold(fun, &l, &r),
+                _ => None,
+            },
+            _ => None,
+        };
+        match folded {
+            Some(constant) => constant.into_expr(ty),
+            None => Expr::BuiltinCall { ty, fun, args },
+        }
+    }
+
+    fn transform_case(&mut self, ty: HTy, cond: Box<Expr>, arms: Vec<(Pattern, Expr)>) -> Expr {
+        let cond = self.transform_expr(*cond);
+        let arm = self
+            .constant(&cond)
+            // the tuple is projected for each of the variables
+            .filter(|constant| {
+                !matches!(constant, Constant::Tuple(_)) || matches!(cond, Expr::Sym { .. })
+            })
+            .and_then(|constant| arms.iter().position(|(pat, _)| constant.matches(pat)));
+        let index = match arm {
+            Some(index) => index,
+            None => {
+                return Expr::Case {
+                    ty,
+                    expr: Box::new(cond),
+                    arms: arms
+                        .into_iter()
+                        .map(|(pat, arm)| (pat, self.transform_expr(arm)))
+                        .collect(),
+                }
+            }
+        };
+        let (pat, arm) = arms.into_iter().nth(index).unwrap();
+        // the variables of the pattern are bound before the arm, so that it sees their values
+        let binds = match pat {
+            Pattern::Var { name, ty: var_ty } => vec![Val {
+                ty: var_ty,
+                rec: false,
+                name,
+                expr: cond,
+            }],
+            Pattern::Tuple { tys, tuple } => tys
+                .into_iter()
+                .zip(tuple)
+                .enumerate()
+                .map(|(index, (ty, name))| Val {
+                    ty: ty.clone(),
+                    rec: false,
+                    name,
+                    expr: Expr::Proj {
+                        ty,
+                        index: index as u32,
+                        tuple: Box::new(cond.clone()),
+                    },
+                })
+                .collect(),
+            _ => vec![],
+        };
+        for val in &binds {
+            self.bind(val);
+        }
+        let arm = self.transform_expr(arm);
+        if binds.is_empty() {
+            return arm;
+        }
+        Expr::Binds {
+            ty,
+            binds,
+            ret: Box::new(arm),
+        }
+    }
+}
+
+impl ConstFold {
+    pub fn new() -> Self {
+        ConstFold
+    }
+}
+
+impl<E> Pass<(SymbolTable, HIR), E> for ConstFold {
+    type Target = (SymbolTable, HIR);
+
+    fn trans(
+        &mut self,
+        (symbol_table, hir): (SymbolTable, HIR),
+        _: &Config,
+    ) -> ::std::result::Result<Self::Target, E> {
+        let mut folder = Folder {
+            constants: HashMap::new(),
+        };
+        Ok((symbol_table, folder.transform_hir(hir)))
+    }
+}
@@ -0,0 +1,154 @@
+use crate::config::Config;
+use crate::hir::util::{take, walk_expr, Traverse};
+use crate::hir::*;
+use crate::pass::Pass;
+use std::collections::HashMap;
+
+/// Copy propagation.
+/// Removes `val a = b` and replaces the uses of `a` with `b`.
+pub struct CopyProp {
+    alias: HashMap<Symbol, Symbol>,
+    // previous entries of `alias` to restore when leaving the scope
+    undo: Vec<(Symbol, Option<Symbol>)>,
+}
+
+impl CopyProp {
+    pub fn new() -> Self {
+        CopyProp {
+            alias: HashMap::new(),
+            undo: Vec::new(),
+        }
+    }
+
+    fn copy_prop_vals(&mut self, vals: &mut Vec<Val>) {
+        *vals = std::mem::take(vals)
+            .into_iter()
+            .filter_map(|mut val| match val.expr {
+                Expr::Sym { name, .. } if !val.rec => {
+                    let orig = self.resolv_alias(name);
+                    self.bind(val.name, Some(orig));
+                    None
+                }
+                _ if val.rec => {
+                    // the name may shadow an alias
+                    self.bind(val.name, None);
+                    self.traverse_val(&mut val);
+                    Some(val)
+                }
+                _ => {
+                    self.traverse_val(&mut val);
+                    self.bind(val.name, None);
+                    Some(val)
+                }
+            })
+            .collect()
+    }
+
+    fn bind(&mut self, name: Symbol, orig: Option<Symbol>) {
+        // aliases to the shadowed variable are no longer valid
+        let invalidated = self
+            .alias
+            .iter()
+            .filter(|(_, target)| *target == &name)
+            .map(|(alias, _)| *alias)
+            .collect::<Vec<_>>();
+        for alias in invalidated {
+            let prev = self.alias.remove(&alias);
+            self.undo.push((alias, prev));
+        }
+        let prev = match orig {
+            Some(orig) => self.alias.insert(name, orig),
+            None => self.alias.remove(&name),
+        };
+        self.undo.push((name, prev));
+    }
+
+    fn scope<T>(&mut self, f: impl FnOnce(&mut Self) -> T) -> T {
+        let depth = self.undo.len();
+        let ret = f(self);
+        for (name, prev) in self.undo.split_off(depth).into_iter().rev() {
+            match prev {
+                Some(prev) => self.alias.insert(name, prev),
+                None => self.alias.remove(&name),
+            };
+        }
+        ret
+    }
+
+    fn resolv_alias(&self, name: Symbol) -> Symbol {
+        match self.alias.get(&name) {
+            None => name,
+            Some(orig) => *orig,
+        }
+    }
+}
+
+impl Traverse for CopyProp {
+    fn traverse_hir(&mut self, hir: &mut HIR) {
+        self.copy_prop_vals(&mut hir.0)
+    }
+
+    fn traverse_expr(&mut self, expr: &mut Expr) {
+        walk_expr(self, expr);
+        // all the bindings were aliases
+        if let Expr::Binds { binds, ret, .. } = expr {
+            if binds.is_empty() {
+                *expr = take(ret);
+            }
+        }
+    }
+
+    fn traverse_binds(&mut self, _ty: &mut HTy, binds: &mut Vec<Val>, ret: &mut Box<Expr>) {
+        self.scope(|this| {
+            this.copy_prop_vals(binds);
+            this.traverse_expr(ret)
+        })
+    }
+
+    fn traverse_fun(
+        &mut self,
+        param: &mut (HTy, Symbol),
+        _body_ty: &mut HTy,
+        body: &mut Box<Expr>,
+        _captures: &mut Vec<(HTy, Symbol)>,
+    ) {
+        self.scope(|this| {
+            this.bind(param.1, None);
+            this.traverse_expr(body)
+        })
+    }
+
+    fn traverse_case(
+        &mut self,
+        _ty: &mut HTy,
+        cond: &mut Box<Expr>,
+        arms: &mut Vec<(Pattern, Expr)>,
+    ) {
+        self.traverse_expr(cond);
+        for (pat, arm) in arms.iter_mut() {
+            self.scope(|this| {
+                for name in pat.variables() {
+                    this.bind(name, None);
+                }
+                this.traverse_expr(arm)
+            })
+        }
+    }
+
+    fn traverse_sym(&mut self, _ty: &mut HTy, name: &mut Symbol) {
+        *name = self.resolv_alias(*name);
+    }
+}
+
+impl<E> Pass<(SymbolTable, HIR), E> for CopyProp {
+    type Target = (SymbolTable, HIR);
+
+    fn trans(
+        &mut self,
+        (symbol_table, mut hir): (SymbolTable, HIR),
+        _: &Config,
+    ) -> ::std::result::Result<Self::Target, E> {
+        self.traverse_hir(&mut hir);
+        Ok((symbol_table, hir))
+    }
+}
@@ -0,0 +1,698 @@
+use crate::ast::TypeError;
+use crate::config::Config;
+use crate::hir::free_vars::FreeVars;
+use crate::hir::*;
+use crate::id::Id;
+use crate::pass::Pass;
+use std::collections::HashSet;
+use std::mem;
+use std::vec;
+
+/// Conversion to continuation-passing style.
+/// This is an optional pass enabled by `Config::enabled_passes`.
+///
+/// A function `fn x => e` of type `a -> b` becomes `fn (x, k, h) => [e] k h` of type
+/// `(a * (b -> unit) * (exn -> unit)) -> unit`, so every call is a tail call and
+/// every function returns unit. `raise` applies the handler continuation `h`
+/// and `handle` passes the body a new one resuming with `k`.
+/// Top level values are evaluated inside a single `val` in the converted program, in the order
+/// of the declarations. The top level functions reading the values are bound there too,
+/// and the others stay at the top level.
+///
+/// The calls to the imports of `Config::async_imports` pass the continuation as the last
+/// argument instead of applying it to the result, so that the host resumes the program
+/// once the value is ready.
+pub struct CPS {
+    id: Id,
+    async_imports: Vec<(String, String)>,
+    /// the handler continuation of the function being converted
+    handler: Symbol,
+    /// whether the program raises, so that the handler of the top level must report it
+    raises: bool,
+}
+
+/// continuation of the expression being converted
+enum Cont {
+    /// continuation at compile time. receives an atomic expression and builds the rest of the code.
+    Meta(Box<dyn FnOnce(&mut CPS, Expr) -> Expr>),
+    /// continuation at run time, bound to the symbol
+    Var(HTy, Symbol),
+}
+
+impl Cont {
+    fn meta(f: impl FnOnce(&mut CPS, Expr) -> Expr + 'static) -> Self {
+        Cont::Meta(Box::new(f))
+    }
+
+    fn apply(self, t: &mut CPS, value: Expr) -> Expr {
+        match self {
+            Cont::Meta(f) => f(t, value),
+            Cont::Var(ty, name) => Expr::App {
+                ty: answer_ty(),
+                fun: Box::new(Expr::Sym { ty, name }),
+                arg: Box::new(value),
+            },
+        }
+    }
+}
+
+/// the type returned by all the converted functions
+fn answer_ty() -> HTy {
+    HTy::Tuple(vec![])
+}
+
+fn exn_ty() -> HTy {
+    HTy::Datatype(Symbol::new("exn"))
+}
+
+fn handler_ty() -> HTy {
+    HTy::fun(exn_ty(), answer_ty())
+}
+
+fn conv_ty(ty: HTy) -> HTy {
+    use crate::hir::HTy::*;
+    match ty {
+        Char => Char,
+        Int => Int,
+        Word => Word,
+        Real => Real,
+        String => String,
+        Tuple(tys) => Tuple(tys.into_iter().map(conv_ty).collect()),
+        Datatype(name) => Datatype(name),
+        Fun(arg, ret) => HTy::fun(
+            HTy::Tuple(vec![
+                conv_ty(*arg),
+                HTy::fun(conv_ty(*ret), answer_ty()),
+                handler_ty(),
+            ]),
+            answer_ty(),
+        ),
+    }
+}
+
+fn conv_symbol_table(symbol_table: SymbolTable) -> SymbolTable {
+    SymbolTable {
+        types: symbol_table
+            .types
+            .into_iter()
+            .map(|(name, info)| {
+                let constructors = info
+                    .constructors
+                    .into_iter()
+                    .map(|(des, arg)| (des, arg.map(conv_ty)))
+                    .collect();
+                (name, TypeInfo { constructors })
+            })
+            .collect(),
+        // the exported functions take the continuations, which the JS glue doesn't convert
+        exports: symbol_table
+            .exports
+            .into_iter()
+            .map(|(name, export, _)| (name, export, None))
+            .collect(),
+    }
+}
+
+fn conv_pattern(pattern: Pattern) -> Pattern {
+    use crate::hir::Pattern::*;
+    match pattern {
+        Constant { value, ty } => Constant {
+            value,
+            ty: conv_ty(ty),
+        },
+        Char { value, ty } => Char {
+            value,
+            ty: conv_ty(ty),
+        },
+        Constructor {
+            descriminant,
+            arg,
+            ty,
+        } => Constructor {
+            descriminant,
+            arg: arg.map(|(ty, name)| (conv_ty(ty), name)),
+            ty: conv_ty(ty),
+        },
+        Var { name, ty } => Var {
+            name,
+            ty: conv_ty(ty),
+        },
+        Tuple { tys, tuple } => Tuple {
+            tys: tys.into_iter().map(conv_ty).collect(),
+            tuple,
+        },
+    }
+}
+
+impl CPS {
+    pub fn new(id: Id) -> Self {
+        CPS {
+            id,
+            async_imports: Vec::new(),
+            // the ids are only taken when the pass runs, so that disabling it doesn't change them
+            handler: Symbol("#g".into(), 0),
+            raises: false,
+        }
+    }
+
+    fn is_async(&self, module: &str, fun: &str) -> bool {
+        self.async_imports
+            .iter()
+            .any(|(m, f)| m == module && f == fun)
+    }
+
+    fn gensym(&mut self) -> Symbol {
+        let id = self.id.next();
+        Symbol("#g".into(), id)
+    }
+
+    fn conv_hir<'a>(
+        &mut self,
+        hir: HIR,
+        exports: &[(Symbol, String, Option<ExportTypes>)],
+    ) -> Result<HIR, TypeError<'a>> {
+        self.handler = self.gensym();
+        // the values only exist in main, so the functions reading them are bound there too
+        let free_vars = FreeVars::analyze(&hir);
+        let mut locals = HashSet::new();
+        let (funs, vals): (Vec<_>, Vec<_>) = hir.0.into_iter().partition(|val| {
+            let top = match &val.expr {
+                Expr::Fun { param, .. } => free_vars
+                    .of_function(&param.1)
+                    .iter()
+                    .all(|(_, name)| !locals.contains(name)),
+                _ => false,
+            };
+            if !top {
+                locals.insert(val.name.clone());
+            }
+            top
+        });
+        if let Some((_, export, _)) = exports.iter().find(|(name, _, _)| locals.contains(name)) {
+            return Err(TypeError::LocalExport(export.clone()));
+        }
+        let mut ret = funs
+            .into_iter()
+            .map(|val| Val {
+                ty: conv_ty(val.ty),
+                rec: val.rec,
+                name: val.name,
+                expr: self.conv_fun(val.expr),
+            })
+            .collect::<Vec<_>>();
+        let unit = Expr::Tuple {
+            tys: vec![],
+            tuple: vec![],
+        };
+        let main = self.conv_binds(vals.into_iter(), unit, Cont::meta(|_, value| value));
+        let main = Expr::Binds {
+            ty: answer_ty(),
+            binds: vec![self.top_handler()],
+            ret: Box::new(main),
+        };
+        let name = self.gensym();
+        ret.push(Val {
+            ty: answer_ty(),
+            rec: false,
+            name,
+            expr: main,
+        });
+        Ok(HIR(ret))
+    }
+
+    /// the handler of the top level, which raises the uncaught exceptions to the runtime
+    fn top_handler(&mut self) -> Val {
+        let exn = self.gensym();
+        let body = if self.raises {
+            Expr::BuiltinCall {
+                ty: answer_ty(),
+                fun: BIF::Raise,
+                args: vec![Expr::Sym {
+                    ty: exn_ty(),
+                    name: exn.clone(),
+                }],
+            }
+        } else {
+            Expr::Tuple {
+                tys: vec![],
+                tuple: vec![],
+            }
+        };
+        Val {
+            ty: handler_ty(),
+            rec: false,
+            name: self.handler.clone(),
+            expr: Expr::Fun {
+                param: (exn_ty(), exn),
+                body_ty: answer_ty(),
+                body: Box::new(body),
+                captures: vec![],
+            },
+        }
+    }
+
+    /// `fn x => e` to `fn p => let val x = #0 p val k = #1 p val h = #2 p in [e] k h end`
+    fn conv_fun(&mut self, fun: Expr) -> Expr {
+        let (param_ty, param, body_ty, body) = match fun {
+            Expr::Fun {
+                param: (param_ty, param),
+                body_ty,
+                body,
+                ..
+            } => (conv_ty(param_ty), param, conv_ty(body_ty), *body),
+            _ => unreachable!("function expected"),
+        };
+        let args = self.gensym();
+        let k = self.gensym();
+        let h = self.gensym();
+        let cont_ty = HTy::fun(body_ty, answer_ty());
+        let args_ty = HTy::Tuple(vec![param_ty.clone(), cont_ty.clone(), handler_ty()]);
+        let proj = |index, ty: &HTy, args: &Symbol| Expr::Proj {
+            ty: ty.clone(),
+            index,
+            tuple: Box::new(Expr::Sym {
+                ty: args_ty.clone(),
+                name: args.clone(),
+            }),
+        };
+        let binds = vec![
+            Val {
+                ty: param_ty.clone(),
+                rec: false,
+                name: param,
+                expr: proj(0, &param_ty, &args),
+            },
+            Val {
+                ty: cont_ty.clone(),
+                rec: false,
+                name: k.clone(),
+                expr: proj(1, &cont_ty, &args),
+            },
+            Val {
+                ty: handler_ty(),
+                rec: false,
+                name: h.clone(),
+                expr: proj(2, &handler_ty(), &args),
+            },
+        ];
+        let outer = mem::replace(&mut self.handler, h);
+        let body = self.conv_expr(body, Cont::Var(cont_ty, k));
+        self.handler = outer;
+        Expr::Fun {
+            param: (args_ty, args),
+            body_ty: answer_ty(),
+            body: Box::new(Expr::Binds {
+                ty: answer_ty(),
+                binds,
+                ret: Box::new(body),
+            }),
+            captures: vec![],
+        }
+    }
+
+    fn conv_expr(&mut self, expr: Expr, k: Cont) -> Expr {
+        use crate::hir::Expr::*;
+        match expr {
+            Binds { binds, ret, .. } => self.conv_binds(binds.into_iter(), *ret, k),
+            BuiltinCall {
+                fun: BIF::Raise,
+                mut args,
+                ..
+            } => {
+                // the rest of the computation is dropped
+                self.raises = true;
+                let handler = self.handler.clone();
+                self.conv_expr(
+                    args.remove(0),
+                    Cont::meta(move |_, exn| App {
+                        ty: answer_ty(),
+                        fun: Box::new(Sym {
+                            ty: handler_ty(),
+                            name: handler,
+                        }),
+                        arg: Box::new(exn),
+                    }),
+                )
+            }
+            BuiltinCall {
+                ty,
+                fun: BIF::Handle,
+                args,
+            } => {
+                // `handle (body, handler)` calls `body ((), k, fn exn => handler (exn, k, h))`
+                let outer = self.handler.clone();
+                self.conv_exprs(args, move |t, mut args| {
+                    let handler = args.pop().expect("internal error");
+                    let body = args.pop().expect("internal error");
+                    t.reify(conv_ty(ty), k, move |t, k_ty, k| {
+                        let exn = t.gensym();
+                        let catch = Fun {
+                            param: (exn_ty(), exn.clone()),
+                            body_ty: answer_ty(),
+                            body: Box::new(App {
+                                ty: answer_ty(),
+                                fun: Box::new(handler),
+                                arg: Box::new(Tuple {
+                                    tys: vec![exn_ty(), k_ty.clone(), handler_ty()],
+                                    tuple: vec![
+                                        Sym {
+                                            ty: exn_ty(),
+                                            name: exn,
+                                        },
+                                        Sym {
+                                            ty: k_ty.clone(),
+                                            name: k.clone(),
+                                        },
+                                        Sym {
+                                            ty: handler_ty(),
+                                            name: outer,
+                                        },
+                                    ],
+                                }),
+                            }),
+                            captures: vec![],
+                        };
+                        t.bind(
+                            catch,
+                            Cont::meta(move |_, catch| {
+                                let unit = Tuple {
+                                    tys: vec![],
+                                    tuple: vec![],
+                                };
+                                App {
+                                    ty: answer_ty(),
+                                    fun: Box::new(body),
+                                    arg: Box::new(Tuple {
+                                        tys: vec![unit.ty(), k_ty.clone(), handler_ty()],
+                                        tuple: vec![unit, Sym { ty: k_ty, name: k }, catch],
+                                    }),
+                                }
+                            }),
+                        )
+                    })
+                })
+            }
+            BuiltinCall { ty, fun, args } => self.conv_exprs(args, move |t, args| {
+                let ty = conv_ty(ty);
+                t.bind(BuiltinCall { ty, fun, args }, k)
+            }),
+            ExternCall {
+                ty,
+                module,
+                fun,
+                args,
+            } if self.is_async(&module, &fun) => self.conv_exprs(args, move |t, mut args| {
+                t.reify(conv_ty(ty), k, move |_, k_ty, k| {
+                    args.push(Sym { ty: k_ty, name: k });
+                    ExternCall {
+                        ty: answer_ty(),
+                        module,
+                        fun,
+                        args,
+                    }
+                })
+            }),
+            ExternCall {
+                ty,
+                module,
+                fun,
+                args,
+            } => self.conv_exprs(args, move |t, args| {
+                let ty = conv_ty(ty);
+                t.bind(
+                    ExternCall {
+                        ty,
+                        module,
+                        fun,
+                        args,
+                    },
+                    k,
+                )
+            }),
+            fun @ Fun { .. } => {
+                let fun = self.conv_fun(fun);
+                self.bind(fun, k)
+            }
+            Closure { .. } => {
+                panic!("internal error: closures must not appear before CPS conversion")
+            }
+            App { ty, fun, arg } => {
+                let ty = conv_ty(ty);
+                self.conv_expr(
+                    *fun,
+                    Cont::meta(move |t, fun| {
+                        t.conv_expr(
+                            *arg,
+                            Cont::meta(move |t, arg| {
+                                let h = Sym {
+                                    ty: handler_ty(),
+                                    name: t.handler.clone(),
+                                };
+                                t.reify(ty, k, move |_, k_ty, k| App {
+                                    ty: answer_ty(),
+                                    fun: Box::new(fun),
+                                    arg: Box::new(Tuple {
+                                        tys: vec![arg.ty(), k_ty.clone(), handler_ty()],
+                                        tuple: vec![arg, Sym { ty: k_ty, name: k }, h],
+                                    }),
+                                })
+                            }),
+                        )
+                    }),
+                )
+            }
+            Case { ty, expr, arms } => {
+                let ty = conv_ty(ty);
+                self.conv_expr(
+                    *expr,
+                    Cont::meta(move |t, expr| {
+                        // share the continuation among the arms
+                        t.reify(ty, k, move |t, k_ty, k| {
+                            let arms = arms
+                                .into_iter()
+                                .map(|(pat, arm)| {
+                                    let k = Cont::Var(k_ty.clone(), k.clone());
+                                    (conv_pattern(pat), t.conv_expr(arm, k))
+                                })
+                                .collect();
+                            Case {
+                                ty: answer_ty(),
+                                expr: Box::new(expr),
+                                arms,
+                            }
+                        })
+                    }),
+                )
+            }
+            Tuple { tuple, .. } => self.conv_exprs(tuple, move |t, tuple| {
+                let tys = tuple.iter().map(Expr::ty).collect();
+                t.bind(Tuple { tys, tuple }, k)
+            }),
+            Proj { ty, index, tuple } => self.conv_expr(
+                *tuple,
+                Cont::meta(move |t, tuple| {
+                    let ty = conv_ty(ty);
+                    t.bind(
+                        Proj {
+                            ty,
+                            index,
+                            tuple: Box::new(tuple),
+                        },
+                        k,
+                    )
+                }),
+            ),
+            Constructor {
+                ty,
+                arg: None,
+                descriminant,
+            } => self.bind(
+                Constructor {
+                    ty: conv_ty(ty),
+                    arg: None,
+                    descriminant,
+                },
+                k,
+            ),
+            Constructor {
+                ty,
+                arg: Some(arg),
+                descriminant,
+            } => self.conv_expr(
+                *arg,
+                Cont::meta(move |t, arg| {
+                    let ty = conv_ty(ty);
+                    t.bind(
+                        Constructor {
+                            ty,
+                            arg: Some(Box::new(arg)),
+                            descriminant,
+                        },
+                        k,
+                    )
+                }),
+            ),
+            Sym { ty, name } => k.apply(
+                self,
+                Sym {
+                    ty: conv_ty(ty),
+                    name,
+                },
+            ),
+            Lit { ty, value } => k.apply(
+                self,
+                Lit {
+                    ty: conv_ty(ty),
+                    value,
+                },
+            ),
+        }
+    }
+
+    fn conv_binds(&mut self, mut binds: vec::IntoIter<Val>, ret: Expr, k: Cont) -> Expr {
+        let val = match binds.next() {
+            None => return self.conv_expr(ret, k),
+            Some(val) => val,
+        };
+        let Val {
+            ty,
+            rec,
+            name,
+            expr,
+        } = val;
+        let ty = conv_ty(ty);
+        match expr {
+            fun @ Expr::Fun { .. } => {
+                let fun = self.conv_fun(fun);
+                let rest = self.conv_binds(binds, ret, k);
+                Expr::Binds {
+                    ty: answer_ty(),
+                    binds: vec![Val {
+                        ty,
+                        rec,
+                        name,
+                        expr: fun,
+                    }],
+                    ret: Box::new(rest),
+                }
+            }
+            expr => self.conv_expr(
+                expr,
+                Cont::meta(move |t, value| {
+                    let rest = t.conv_binds(binds, ret, k);
+                    Expr::Binds {
+                        ty: answer_ty(),
+                        binds: vec![Val {
+                            ty,
+                            rec: false,
+                            name,
+                            expr: value,
+                        }],
+                        ret: Box::new(rest),
+                    }
+                }),
+            ),
+        }
+    }
+
+    /// convert expressions from left to right and pass the values to `k`
+    fn conv_exprs(
+        &mut self,
+        exprs: Vec<Expr>,
+        k: impl FnOnce(&mut CPS, Vec<Expr>) -> Expr + 'static,
+    ) -> Expr {
+        self.conv_exprs_rec(exprs.into_iter(), Vec::new(), Box::new(k))
+    }
+
+    fn conv_exprs_rec(
+        &mut self,
+        mut exprs: vec::IntoIter<Expr>,
+        mut values: Vec<Expr>,
+        k: Box<dyn FnOnce(&mut CPS, Vec<Expr>) -> Expr>,
+    ) -> Expr {
+        match exprs.next() {
+            None => k(self, values),
+            Some(expr) => self.conv_expr(
+                expr,
+                Cont::meta(move |t, value| {
+                    values.push(value);
+                    t.conv_exprs_rec(exprs, values, k)
+                }),
+            ),
+        }
+    }
+
+    /// bind a non-atomic expression to a fresh variable and pass it to `k`
+    fn bind(&mut self, expr: Expr, k: Cont) -> Expr {
+        let name = self.gensym();
+        let ty = expr.ty();
+        let rest = k.apply(
+            self,
+            Expr::Sym {
+                ty: ty.clone(),
+                name: name.clone(),
+            },
+        );
+        Expr::Binds {
+            ty: answer_ty(),
+            binds: vec![Val {
+                ty,
+                rec: false,
+                name,
+                expr,
+            }],
+            ret: Box::new(rest),
+        }
+    }
+
+    /// make `k` a run-time continuation and pass it to `f`.
+    /// a compile time continuation is bound to a fresh function
+    fn reify(&mut self, ty: HTy, k: Cont, f: impl FnOnce(&mut CPS, HTy, Symbol) -> Expr) -> Expr {
+        match k {
+            Cont::Var(k_ty, k) => f(self, k_ty, k),
+            Cont::Meta(k) => {
+                let param = self.gensym();
+                let body = k(
+                    self,
+                    Expr::Sym {
+                        ty: ty.clone(),
+                        name: param.clone(),
+                    },
+                );
+                let fun = Expr::Fun {
+                    param: (ty, param),
+                    body_ty: answer_ty(),
+                    body: Box::new(body),
+                    captures: vec![],
+                };
+                let name = self.gensym();
+                let fun_ty = fun.ty();
+                let rest = f(self, fun_ty.clone(), name.clone());
+                Expr::Binds {
+                    ty: answer_ty(),
+                    binds: vec![Val {
+                        ty: fun_ty,
+                        rec: false,
+                        name,
+                        expr: fun,
+                    }],
+                    ret: Box::new(rest),
+                }
+            }
+        }
+    }
+}
+
+impl<'a> Pass<(SymbolTable, HIR), TypeError<'a>> for CPS {
+    type Target = (SymbolTable, HIR);
+
+    fn trans(
+        &mut self,
+        (symbol_table, hir): (SymbolTable, HIR),
+        config: &Config,
+    ) -> ::std::result::Result<Self::Target, TypeError<'a>> {
+        self.async_imports = config.async_imports.clone();
+        let hir = self.conv_hir(hir, &symbol_table.exports)?;
+        Ok((conv_symbol_table(symbol_table), hir))
+    }
+}
@@ -0,0 +1,131 @@
+use crate::config::Config;
+use crate::hir::effect::Effects;
+use crate::hir::util::{take, walk_expr, Traverse};
+use crate::hir::*;
+use crate::pass::Pass;
+use std::collections::HashSet;
+
+/// Dead code elimination.
+/// Removes local bindings that are never referenced and whose expressions are pure,
+/// and the top level functions that are never referenced nor exported,
+/// e.g. the functions of the prelude the program doesn't use.
+pub struct DCE;
+
+/// collects the symbols referred
+pub(crate) struct Usage {
+    pub(crate) used: HashSet<Symbol>,
+}
+
+impl Traverse for Usage {
+    fn traverse_closure(
+        &mut self,
+        envs: &mut Vec<(HTy, Symbol)>,
+        _param_ty: &mut HTy,
+        _body_ty: &mut HTy,
+        fname: &mut Symbol,
+    ) {
+        self.used.insert(*fname);
+        self.used.extend(envs.iter().map(|(_, name)| *name));
+    }
+
+    fn traverse_sym(&mut self, _ty: &mut HTy, name: &mut Symbol) {
+        self.used.insert(*name);
+    }
+}
+
+struct Remover {
+    effects: Effects,
+    used: HashSet<Symbol>,
+    exports: HashSet<Symbol>,
+    removed: bool,
+}
+
+impl Traverse for Remover {
+    fn traverse_hir(&mut self, hir: &mut HIR) {
+        hir.0 = std::mem::take(&mut hir.0)
+            .into_iter()
+            .filter_map(|mut val| {
+                let dead = matches!(val.expr, Expr::Fun { .. })
+                    && !self.used.contains(&val.name)
+                    && !self.exports.contains(&val.name);
+                if dead {
+                    self.removed = true;
+                    None
+                } else {
+                    self.traverse_val(&mut val);
+                    Some(val)
+                }
+            })
+            .collect();
+    }
+
+    fn traverse_expr(&mut self, expr: &mut Expr) {
+        walk_expr(self, expr);
+        // all the bindings were dead
+        if let Expr::Binds { binds, ret, .. } = expr {
+            if binds.is_empty() {
+                *expr = take(ret);
+            }
+        }
+    }
+
+    fn traverse_binds(&mut self, _ty: &mut HTy, binds: &mut Vec<Val>, ret: &mut Box<Expr>) {
+        *binds = std::mem::take(binds)
+            .into_iter()
+            .filter_map(|mut val| {
+                if self.used.contains(&val.name) || !self.effects.is_pure(&val.expr) {
+                    self.traverse_val(&mut val);
+                    Some(val)
+                } else {
+                    self.removed = true;
+                    None
+                }
+            })
+            .collect();
+        self.traverse_expr(ret)
+    }
+}
+
+impl DCE {
+    pub fn new() -> Self {
+        DCE
+    }
+
+    fn conv_hir(&mut self, mut hir: HIR, exports: HashSet<Symbol>) -> HIR {
+        // removing a binding may make others dead
+        loop {
+            let mut usage = Usage {
+                used: HashSet::new(),
+            };
+            usage.traverse_hir(&mut hir);
+            let mut remover = Remover {
+                effects: Effects::analyze(&hir),
+                used: usage.used,
+                exports: exports.clone(),
+                removed: false,
+            };
+            remover.traverse_hir(&mut hir);
+            if !remover.removed {
+                return hir;
+            }
+        }
+    }
+}
+
+impl<E> Pass<(SymbolTable, HIR), E> for DCE {
+    type Target = (SymbolTable, HIR);
+
+    fn trans(
+        &mut self,
+        (symbol_table, hir): (SymbolTable, HIR),
+        _: &Config,
+    ) -> ::std::result::Result<Self::Target, E> {
+        let exports = symbol_table
+            .exports
+            .iter()
+            .map(|(name, _, _)| *name)
+            .collect();
+        let hir = self.conv_hir(hir, exports);
+        Ok((symbol_table, hir))
+    }
+}
@@ -0,0 +1,114 @@
+use crate::hir::*;
+use std::collections::HashSet;
+
+/// Effect analysis.
+/// An expression is pure if evaluating it has no side effects, never traps and always terminates.
+/// Pure expressions can be removed, duplicated or moved freely by optimizations.
+pub struct Effects {
+    // non-recursive functions whose application is pure
+    pure_functions: HashSet<Symbol>,
+}
+
+impl Effects {
+    pub fn analyze(hir: &HIR) -> Self {
+        let mut effects = Effects {
+            pure_functions: HashSet::new(),
+        };
+        for val in hir.0.iter() {
+            effects.analyze_val(val)
+        }
+        effects
+    }
+
+    fn analyze_val(&mut self, val: &Val) {
+        self.analyze_expr(&val.expr);
+        match &val.expr {
+            // recursive functions may not terminate
+            Expr::Fun { body, .. } if !val.rec => {
+                if self.is_pure(body) {
+                    self.pure_functions.insert(val.name.clone());
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn analyze_expr(&mut self, expr: &Expr) {
+        use crate::hir::Expr::*;
+        match expr {
+            Binds { binds, ret, .. } => {
+                for val in binds {
+                    self.analyze_val(val)
+                }
+                self.analyze_expr(ret)
+            }
+            BuiltinCall { args, .. } | ExternCall { args, .. } => {
+                for arg in args {
+                    self.analyze_expr(arg)
+                }
+            }
+            Fun { body, .. } => self.analyze_expr(body),
+            App { fun, arg, .. } => {
+                self.analyze_expr(fun);
+                self.analyze_expr(arg)
+            }
+            Case { expr, arms, .. } => {
+                self.analyze_expr(expr);
+                for (_, arm) in arms {
+                    self.analyze_expr(arm)
+                }
+            }
+            Tuple { tuple, .. } => {
+                for t in tuple {
+                    self.analyze_expr(t)
+                }
+            }
+            Proj { tuple, .. } => self.analyze_expr(tuple),
+            Constructor { arg, .. } => {
+                if let Some(arg) = arg {
+                    self.analyze_expr(arg)
+                }
+            }
+            Closure { .. } | Sym { .. } | Lit { .. } => (),
+        }
+    }
+
+    /// whether applying the function named `name` is pure
+    pub fn is_pure_function(&self, name: &Symbol) -> bool {
+        self.pure_functions.contains(name)
+    }
+
+    pub fn is_pure(&self, expr: &Expr) -> bool {
+        use crate::hir::Expr::*;
+        match expr {
+            Binds { binds, ret, .. } => {
+                binds.iter().all(|val| self.is_pure(&val.expr)) && self.is_pure(ret)
+            }
+            BuiltinCall { fun, args, .. } => {
+                // division by zero traps
+                let traps = match fun {
+                    BIF::Div | BIF::Mod => true,
+                    // handlers may have side effects
+                    BIF::Raise | BIF::Handle => true,
+                    _ => false,
+                };
+                !traps && args.iter().all(|arg| self.is_pure(arg))
+            }
+            ExternCall { .. } => false,
+            // creating a function does nothing
+            Fun { .. } | Closure { .. } => true,
+            App { fun, arg, .. } => match &**fun {
+                Sym { name, .. } => self.is_pure_function(name) && self.is_pure(arg),
+                Closure { fname, .. } => self.is_pure_function(fname) && self.is_pure(arg),
+                _ => false,
+            },
+            Case { expr, arms, .. } => {
+                self.is_pure(expr) && arms.iter().all(|(_, arm)| self.is_pure(arm))
+            }
+            Tuple { tuple, .. } => tuple.iter().all(|t| self.is_pure(t)),
+            Proj { tuple, .. } => self.is_pure(tuple),
+            Constructor { arg, .. } => arg.iter().all(|arg| self.is_pure(arg)),
+            Sym { .. } | Lit { .. } => true,
+        }
+    }
+}
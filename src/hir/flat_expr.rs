@@ -113,6 +113,35 @@ impl Transform for FlatExpr {
         }
     }
 
+    fn transform_extern_call(
+        &mut self,
+        ty: HTy,
+        module: String,
+        fun: String,
+        args: Vec<Expr>,
+    ) -> Expr {
+        let (args, mut vals): (Vec<_>, Vec<_>) = args
+            .into_iter()
+            .map(|arg| {
+                let (arg, argval) = self.flat_make_val(arg);
+                (*arg, argval)
+            })
+            .unzip();
+        let (ret, retval) = self.make_val(ExternCall {
+            module,
+            fun,
+            args,
+            ty: ty.clone(),
+        });
+
+        vals.push(retval);
+        Binds {
+            ty,
+            binds: vals,
+            ret,
+        }
+    }
+
     fn transform_app(&mut self, ty: HTy, fun: Box<Expr>, arg: Box<Expr>) -> Expr {
         let (fun, funval) = self.flat_make_val(*fun);
         let (arg, argval) = self.flat_make_val(*arg);
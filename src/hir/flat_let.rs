@@ -2,115 +2,110 @@ use crate::config::Config;
 use crate::hir::util::Transform;
 use crate::hir::*;
 use crate::pass::Pass;
+use crate::util::ensure_stack;
 
 pub struct FlatLet;
 
-fn take_binds(mut expr: Expr) -> (Expr, Vec<Val>) {
-    use crate::hir::Expr::*;
-    match expr {
-        Binds { binds, ret, .. } => {
-            expr = *ret;
-            (expr, binds)
-        }
-        BuiltinCall { args, ty, fun } => {
-            let (args, bindss): (_, Vec<_>) = args.into_iter().map(take_binds).unzip();
-            let expr = BuiltinCall { fun, args, ty };
-            (expr, bindss.into_iter().flat_map(Vec::into_iter).collect())
-        }
-        ExternCall {
-            args,
-            ty,
-            module,
-            fun,
-        } => {
-            let (args, bindss): (_, Vec<_>) = args.into_iter().map(take_binds).unzip();
-            let expr = ExternCall {
-                module,
-                fun,
+/// flattens the lets in `expr` into `binds`, in the order they are evaluated.
+/// all the lets of an expression share the one accumulator,
+/// so a long chain of lets is flattened in linear time
+fn take_binds(expr: Expr, binds: &mut Vec<Val>) -> Expr {
+    ensure_stack(|| {
+        use crate::hir::Expr::*;
+        match expr {
+            Binds {
+                binds: vals, ret, ..
+            } => {
+                for mut val in vals {
+                    val.expr = take_binds(val.expr, binds);
+                    binds.push(val);
+                }
+                take_binds(*ret, binds)
+            }
+            BuiltinCall { args, ty, fun } => {
+                let args = args.into_iter().map(|arg| take_binds(arg, binds)).collect();
+                BuiltinCall { fun, args, ty }
+            }
+            ExternCall {
                 args,
                 ty,
-            };
-            (expr, bindss.into_iter().flat_map(Vec::into_iter).collect())
-        }
-        App {
-            mut fun,
-            mut arg,
-            ty,
-        } => {
-            let (f, mut fbinds) = take_binds(*fun);
-            let (a, mut abinds) = take_binds(*arg);
-            fun = Box::new(f);
-            arg = Box::new(a);
-            fbinds.append(&mut abinds);
-            let expr = App { fun, arg, ty };
-            (expr, fbinds)
-        }
-        Case { mut expr, arms, ty } => {
-            let (e, ebinds) = take_binds(*expr);
-            expr = Box::new(e);
-            let expr = Case { expr, arms, ty };
-            (expr, ebinds)
-        }
-        Tuple { tys, tuple } => {
-            let (tuple, bindss): (_, Vec<_>) = tuple.into_iter().map(take_binds).unzip();
-            let expr = Tuple { tys, tuple };
-            (expr, bindss.into_iter().flat_map(Vec::into_iter).collect())
-        }
-        Proj { ty, tuple, index } => {
-            let (t, binds) = take_binds(*tuple);
-            let proj = Proj {
+                module,
+                fun,
+            } => {
+                let args = args.into_iter().map(|arg| take_binds(arg, binds)).collect();
+                ExternCall {
+                    module,
+                    fun,
+                    args,
+                    ty,
+                }
+            }
+            App { fun, arg, ty } => {
+                let fun = Box::new(take_binds(*fun, binds));
+                let arg = Box::new(take_binds(*arg, binds));
+                App { fun, arg, ty }
+            }
+            Case { expr, arms, ty } => {
+                let expr = Box::new(take_binds(*expr, binds));
+                // binds in arms cannot be lifted over the branch
+                let arms = arms
+                    .into_iter()
+                    .map(|(pat, arm)| (pat, wrap_binds(arm)))
+                    .collect();
+                Case { expr, arms, ty }
+            }
+            Tuple { tys, tuple } => {
+                let tuple = tuple.into_iter().map(|t| take_binds(t, binds)).collect();
+                Tuple { tys, tuple }
+            }
+            Proj { ty, tuple, index } => Proj {
                 ty,
                 index,
-                tuple: Box::new(t),
-            };
-            (proj, binds)
+                tuple: Box::new(take_binds(*tuple, binds)),
+            },
+            Constructor {
+                ty,
+                descriminant,
+                arg,
+            } => Constructor {
+                ty,
+                descriminant,
+                arg: arg.map(|arg| Box::new(take_binds(*arg, binds))),
+            },
+            Fun {
+                param,
+                body_ty,
+                body,
+                captures,
+            } => Fun {
+                param,
+                body_ty,
+                body: Box::new(wrap_binds(*body)),
+                captures,
+            },
+            x @ Closure { .. } | x @ Sym { .. } | x @ Lit { .. } => x,
         }
-        Constructor {
+    })
+}
+
+fn wrap_binds(expr: Expr) -> Expr {
+    let ty = expr.ty();
+    let mut binds = Vec::new();
+    let expr = take_binds(expr, &mut binds);
+    if binds.is_empty() {
+        expr
+    } else {
+        Expr::Binds {
             ty,
-            descriminant,
-            arg,
-        } => {
-            if let Some(arg) = arg {
-                let (arg, binds) = take_binds(*arg);
-                (
-                    Constructor {
-                        ty,
-                        descriminant,
-                        arg: Some(Box::new(arg)),
-                    },
-                    binds,
-                )
-            } else {
-                (
-                    Constructor {
-                        ty,
-                        descriminant,
-                        arg,
-                    },
-                    Vec::new(),
-                )
-            }
+            binds,
+            ret: Box::new(expr),
         }
-        x @ Fun { .. } | x @ Closure { .. } | x @ Sym { .. } | x @ Lit { .. } => (x, Vec::new()),
     }
 }
 
 impl Transform for FlatLet {
-    fn transform_binds(&mut self, ty: HTy, mut binds: Vec<Val>, mut ret: Box<Expr>) -> Expr {
-        let mut vec = Vec::new();
-        for mut val in binds.into_iter() {
-            val.expr = self.transform_expr(val.expr);
-            let (expr, mut binds) = take_binds(val.expr);
-            val.expr = expr;
-            vec.append(&mut binds);
-            vec.push(val)
-        }
-        let ret_ = self.transform_expr(*ret);
-        let (expr, mut binds_) = take_binds(ret_);
-        ret = Box::new(expr);
-        vec.append(&mut binds_);
-        binds = vec;
-        Expr::Binds { binds, ret, ty }
+    fn transform_expr(&mut self, expr: Expr) -> Expr {
+        wrap_binds(expr)
     }
 }
 
@@ -0,0 +1,105 @@
+use crate::hir::*;
+use crate::util::ensure_stack;
+use std::collections::HashMap;
+
+/// Free variable analysis.
+/// The free variables of every function are computed once, bottom up, reusing those of the
+/// functions nested in it. The functions are identified by their parameters, which are unique.
+/// Names bound at the top level are free too; the passes filter them out as they need.
+#[derive(Default)]
+pub struct FreeVars {
+    functions: HashMap<Symbol, Vec<(HTy, Symbol)>>,
+}
+
+/// adds the variables not in `frees` yet, keeping the order of their first occurrences
+fn union(frees: &mut Vec<(HTy, Symbol)>, more: Vec<(HTy, Symbol)>) {
+    for (ty, name) in more {
+        if !frees.iter().any(|(_, free)| *free == name) {
+            frees.push((ty, name))
+        }
+    }
+}
+
+fn remove(frees: &mut Vec<(HTy, Symbol)>, bound: &Symbol) {
+    frees.retain(|(_, free)| free != bound)
+}
+
+impl FreeVars {
+    pub fn analyze(hir: &HIR) -> Self {
+        let mut free_vars = FreeVars {
+            functions: HashMap::new(),
+        };
+        for val in hir.0.iter() {
+            free_vars.analyze_expr(&val.expr);
+        }
+        free_vars
+    }
+
+    fn analyze_expr(&mut self, expr: &Expr) -> Vec<(HTy, Symbol)> {
+        ensure_stack(|| {
+            use crate::hir::Expr::*;
+            let mut frees = Vec::new();
+            match expr {
+                Binds { binds, ret, .. } => {
+                    for val in binds {
+                        let more = self.analyze_expr(&val.expr);
+                        union(&mut frees, more);
+                    }
+                    let more = self.analyze_expr(ret);
+                    union(&mut frees, more);
+                    for val in binds {
+                        remove(&mut frees, &val.name);
+                    }
+                }
+                Fun { param, body, .. } => {
+                    frees = self.analyze_expr(body);
+                    remove(&mut frees, &param.1);
+                    self.functions.insert(param.1, frees.clone());
+                }
+                BuiltinCall { args, .. } | ExternCall { args, .. } => {
+                    for arg in args {
+                        let more = self.analyze_expr(arg);
+                        union(&mut frees, more);
+                    }
+                }
+                App { fun, arg, .. } => {
+                    frees = self.analyze_expr(fun);
+                    let more = self.analyze_expr(arg);
+                    union(&mut frees, more);
+                }
+                Case { expr, arms, .. } => {
+                    frees = self.analyze_expr(expr);
+                    for (pat, arm) in arms {
+                        let mut more = self.analyze_expr(arm);
+                        for name in pat.variables() {
+                            remove(&mut more, &name);
+                        }
+                        union(&mut frees, more);
+                    }
+                }
+                Tuple { tuple, .. } => {
+                    for t in tuple {
+                        let more = self.analyze_expr(t);
+                        union(&mut frees, more);
+                    }
+                }
+                Proj { tuple, .. } => frees = self.analyze_expr(tuple),
+                Constructor { arg, .. } => {
+                    if let Some(arg) = arg {
+                        frees = self.analyze_expr(arg)
+                    }
+                }
+                Closure { envs, .. } => frees = envs.clone(),
+                Sym { ty, name } => frees.push((ty.clone(), *name)),
+                Lit { .. } => (),
+            }
+            frees
+        })
+    }
+
+    /// the free variables of the function whose parameter is `param`,
+    /// in the order of their first occurrences
+    pub fn of_function(&self, param: &Symbol) -> &[(HTy, Symbol)] {
+        self.functions.get(param).map(Vec::as_slice).unwrap_or(&[])
+    }
+}
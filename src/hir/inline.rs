@@ -0,0 +1,245 @@
+use crate::config::Config;
+use crate::hir::dce::Usage;
+use crate::hir::util::{Transform, Traverse};
+use crate::hir::*;
+use crate::id::Id;
+use crate::pass::Pass;
+use std::collections::{HashMap, HashSet};
+
+/// Inlining.
+/// Replaces the calls of the small functions with `let val param = arg in body end`,
+/// where the variables bound in the body are renamed.
+/// A function is inlined after its definition, if it doesn't refer to itself and its body
+/// is not larger than the threshold of `Config`. Runs before `FlatLet`, which flattens the
+/// inlined bodies.
+pub struct Inline {
+    id: Id,
+}
+
+struct Inliner {
+    id: Id,
+    threshold: usize,
+    functions: HashMap<Symbol, ((HTy, Symbol), Expr)>,
+}
+
+/// the number of the operations of the expression.
+/// the bindings and the variables are not counted, which `FlatExpr` adds many of
+fn size(expr: &Expr) -> usize {
+    use crate::hir::Expr::*;
+    match expr {
+        Binds { binds, ret, .. } => {
+            binds.iter().map(|val| size(&val.expr)).sum::<usize>() + size(ret)
+        }
+        BuiltinCall { args, .. } | ExternCall { args, .. } => {
+            1 + args.iter().map(size).sum::<usize>()
+        }
+        Fun { body, .. } => 1 + size(body),
+        App { fun, arg, .. } => 1 + size(fun) + size(arg),
+        Case { expr, arms, .. } => {
+            1 + size(expr) + arms.iter().map(|(_, arm)| size(arm)).sum::<usize>()
+        }
+        Tuple { tuple, .. } => 1 + tuple.iter().map(size).sum::<usize>(),
+        Proj { tuple, .. } => 1 + size(tuple),
+        Constructor { arg, .. } => 1 + arg.iter().map(|arg| size(arg)).sum::<usize>(),
+        Closure { .. } => 1,
+        Sym { .. } | Lit { .. } => 0,
+    }
+}
+
+/// collects the variables bound in the expression
+struct Binders {
+    names: HashSet<Symbol>,
+}
+
+impl Traverse for Binders {
+    fn traverse_val(&mut self, val: &mut Val) {
+        self.names.insert(val.name.clone());
+        self.traverse_expr(&mut val.expr)
+    }
+
+    fn traverse_fun(
+        &mut self,
+        param: &mut (HTy, Symbol),
+        _body_ty: &mut HTy,
+        body: &mut Box<Expr>,
+        _captures: &mut Vec<(HTy, Symbol)>,
+    ) {
+        self.names.insert(param.1.clone());
+        self.traverse_expr(body)
+    }
+
+    fn traverse_case(
+        &mut self,
+        _ty: &mut HTy,
+        expr: &mut Box<Expr>,
+        arms: &mut Vec<(Pattern, Expr)>,
+    ) {
+        self.traverse_expr(expr);
+        for (pat, arm) in arms {
+            self.names.extend(pat.variables());
+            self.traverse_expr(arm);
+        }
+    }
+}
+
+/// renames the variables to the fresh ones
+struct Renamer {
+    names: HashMap<Symbol, Symbol>,
+}
+
+impl Renamer {
+    fn rename(&self, name: Symbol) -> Symbol {
+        self.names.get(&name).cloned().unwrap_or(name)
+    }
+}
+
+impl Transform for Renamer {
+    fn transform_val(&mut self, mut val: Val) -> Val {
+        val.name = self.rename(val.name);
+        val.expr = self.transform_expr(val.expr);
+        val
+    }
+
+    fn transform_fun(
+        &mut self,
+        param: (HTy, Symbol),
+        body_ty: HTy,
+        body: Box<Expr>,
+        captures: Vec<(HTy, Symbol)>,
+    ) -> Expr {
+        Expr::Fun {
+            param: (param.0, self.rename(param.1)),
+            body_ty,
+            body: Box::new(self.transform_expr(*body)),
+            captures,
+        }
+    }
+
+    fn transform_case(&mut self, ty: HTy, cond: Box<Expr>, arms: Vec<(Pattern, Expr)>) -> Expr {
+        Expr::Case {
+            ty,
+            expr: Box::new(self.transform_expr(*cond)),
+            arms: arms
+                .into_iter()
+                .map(|(pat, arm)| {
+                    (
+                        pat.rename(|name| self.rename(name)),
+                        self.transform_expr(arm),
+                    )
+                })
+                .collect(),
+        }
+    }
+
+    fn transform_sym(&mut self, ty: HTy, name: Symbol) -> Expr {
+        Expr::Sym {
+            ty,
+            name: self.rename(name),
+        }
+    }
+}
+
+impl Inliner {
+    fn is_inlinable(&self, val: &mut Val) -> bool {
+        match &mut val.expr {
+            Expr::Fun { body, captures, .. } if captures.is_empty() => {
+                let mut usage = Usage {
+                    used: HashSet::new(),
+                };
+                usage.traverse_expr(body);
+                size(body) <= self.threshold && !usage.used.contains(&val.name)
+            }
+            _ => false,
+        }
+    }
+
+    /// `let val param = arg in body end` with the variables of the body renamed
+    fn inline(&mut self, ty: HTy, param: (HTy, Symbol), mut body: Expr, arg: Expr) -> Expr {
+        let mut binders = Binders {
+            names: HashSet::new(),
+        };
+        binders.traverse_expr(&mut body);
+        binders.names.insert(param.1.clone());
+        let names = binders
+            .names
+            .into_iter()
+            .map(|name| {
+                let fresh = Symbol(name.0, self.id.next());
+                (name, fresh)
+            })
+            .collect();
+        let mut renamer = Renamer { names };
+        let body = renamer.transform_expr(body);
+        Expr::Binds {
+            ty,
+            binds: vec![Val {
+                ty: param.0,
+                rec: false,
+                name: renamer.rename(param.1),
+                expr: arg,
+            }],
+            ret: Box::new(body),
+        }
+    }
+}
+
+impl Transform for Inliner {
+    fn transform_val(&mut self, mut val: Val) -> Val {
+        val.expr = self.transform_expr(val.expr);
+        if self.is_inlinable(&mut val) {
+            if let Expr::Fun { param, body, .. } = &val.expr {
+                self.functions
+                    .insert(val.name.clone(), (param.clone(), (**body).clone()));
+            }
+        }
+        // the functions are called through the aliases after `FlatExpr`
+        if let Expr::Sym { name, .. } = &val.expr {
+            if let Some(function) = self.functions.get(name).cloned() {
+                self.functions.insert(val.name.clone(), function);
+            }
+        }
+        val
+    }
+
+    fn transform_app(&mut self, ty: HTy, fun: Box<Expr>, arg: Box<Expr>) -> Expr {
+        let fun = self.transform_expr(*fun);
+        let arg = self.transform_expr(*arg);
+        let inlined = match &fun {
+            Expr::Sym { name, .. } => self.functions.get(name).cloned(),
+            _ => None,
+        };
+        match inlined {
+            Some((param, body)) => self.inline(ty, param, body, arg),
+            None => Expr::App {
+                ty,
+                fun: Box::new(fun),
+                arg: Box::new(arg),
+            },
+        }
+    }
+}
+
+impl Inline {
+    pub fn new(id: Id) -> Self {
+        Inline { id }
+    }
+}
+
+impl<E> Pass<(SymbolTable, HIR), E> for Inline {
+    type Target = (SymbolTable, HIR);
+
+    fn trans(
+        &mut self,
+        (symbol_table, hir): (SymbolTable, HIR),
+        config: &Config,
+    ) -> ::std::result::Result<Self::Target, E> {
+        let mut inliner = Inliner {
+            id: self.id.clone(),
+            threshold: config
+                .inline_threshold
+                .unwrap_or_else(|| config.opt_level.inline_threshold()),
+            functions: HashMap::new(),
+        };
+        Ok((symbol_table, inliner.transform_hir(hir)))
+    }
+}
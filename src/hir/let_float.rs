@@ -0,0 +1,207 @@
+use crate::config::Config;
+use crate::hir::dce::Usage;
+use crate::hir::effect::Effects;
+use crate::hir::util::{Transform, Traverse};
+use crate::hir::*;
+use crate::pass::Pass;
+use std::collections::HashSet;
+
+/// Let floating.
+/// Pure bindings at the top of a function body which do not depend on
+/// the parameter or other local variables of the function are moved out of the function
+/// so that they are evaluated once when the function is created rather than on every call.
+/// Top level functions are left as is because functions cannot refer to top level values.
+pub struct LetFloat;
+
+/// counts how many times each symbol is bound
+struct Binders {
+    bound: HashMap<Symbol, usize>,
+}
+
+impl Binders {
+    fn bind(&mut self, name: &Symbol) {
+        *self.bound.entry(name.clone()).or_insert(0) += 1;
+    }
+}
+
+impl Traverse for Binders {
+    fn traverse_val(&mut self, val: &mut Val) {
+        self.bind(&val.name);
+        self.traverse_expr(&mut val.expr)
+    }
+
+    fn traverse_fun(
+        &mut self,
+        param: &mut (HTy, Symbol),
+        _body_ty: &mut HTy,
+        body: &mut Box<Expr>,
+        _captures: &mut Vec<(HTy, Symbol)>,
+    ) {
+        self.bind(&param.1);
+        self.traverse_expr(body)
+    }
+
+    fn traverse_case(
+        &mut self,
+        _ty: &mut HTy,
+        expr: &mut Box<Expr>,
+        arms: &mut Vec<(Pattern, Expr)>,
+    ) {
+        self.traverse_expr(expr);
+        for (pat, arm) in arms.iter_mut() {
+            for name in pat.variables() {
+                self.bind(&name);
+            }
+            self.traverse_expr(arm);
+        }
+    }
+}
+
+fn mentions(expr: &mut Expr) -> HashSet<Symbol> {
+    let mut usage = Usage {
+        used: HashSet::new(),
+    };
+    usage.traverse_expr(expr);
+    usage.used
+}
+
+struct Floater {
+    effects: Effects,
+    // symbols bound only once in the program can move without being captured by other bindings
+    unique: HashSet<Symbol>,
+}
+
+impl Floater {
+    /// splits the bindings floatable out of the function from the function
+    fn float_fun(&mut self, val: Val) -> (Vec<Val>, Val) {
+        let Val {
+            ty,
+            rec,
+            name,
+            expr,
+        } = val;
+        let (param, body_ty, body, captures) = match expr {
+            Expr::Fun {
+                param,
+                body_ty,
+                body,
+                captures,
+            } => (param, body_ty, body, captures),
+            expr => {
+                return (
+                    vec![],
+                    Val {
+                        ty,
+                        rec,
+                        name,
+                        expr,
+                    },
+                )
+            }
+        };
+        let (binds, ret) = match *body {
+            Expr::Binds { binds, ret, .. } => (binds, ret),
+            body => (vec![], Box::new(body)),
+        };
+
+        // variables which are not available outside of the function
+        let mut locals = captures
+            .iter()
+            .map(|(_, var)| var.clone())
+            .chain(Some(param.1.clone()))
+            .collect::<HashSet<_>>();
+        if rec {
+            locals.insert(name.clone());
+        }
+
+        let mut floated = Vec::new();
+        let mut kept = Vec::new();
+        for mut val in binds {
+            let floatable = !val.rec
+                && self.unique.contains(&val.name)
+                && self.effects.is_pure(&val.expr)
+                && mentions(&mut val.expr).is_disjoint(&locals);
+            if floatable {
+                floated.push(val);
+            } else {
+                locals.insert(val.name.clone());
+                kept.push(val);
+            }
+        }
+
+        let body = if kept.is_empty() {
+            *ret
+        } else {
+            Expr::Binds {
+                ty: body_ty.clone(),
+                binds: kept,
+                ret,
+            }
+        };
+        let fun = Val {
+            ty,
+            rec,
+            name,
+            expr: Expr::Fun {
+                param,
+                body_ty,
+                body: Box::new(body),
+                captures,
+            },
+        };
+        (floated, fun)
+    }
+}
+
+impl Transform for Floater {
+    fn transform_binds(&mut self, ty: HTy, binds: Vec<Val>, ret: Box<Expr>) -> Expr {
+        let mut ret_binds = Vec::new();
+        for val in binds {
+            // float the inner functions first so that their bindings can float further
+            let val = self.transform_val(val);
+            let (mut floated, val) = self.float_fun(val);
+            ret_binds.append(&mut floated);
+            ret_binds.push(val);
+        }
+        Expr::Binds {
+            ty,
+            binds: ret_binds,
+            ret: Box::new(self.transform_expr(*ret)),
+        }
+    }
+}
+
+impl LetFloat {
+    pub fn new() -> Self {
+        LetFloat
+    }
+
+    fn conv_hir(&mut self, mut hir: HIR) -> HIR {
+        let mut binders = Binders {
+            bound: HashMap::new(),
+        };
+        binders.traverse_hir(&mut hir);
+        let mut floater = Floater {
+            effects: Effects::analyze(&hir),
+            unique: binders
+                .bound
+                .into_iter()
+                .filter(|(_, count)| *count == 1)
+                .map(|(name, _)| name)
+                .collect(),
+        };
+        floater.transform_hir(hir)
+    }
+}
+
+impl<E> Pass<(SymbolTable, HIR), E> for LetFloat {
+    type Target = (SymbolTable, HIR);
+
+    fn trans(
+        &mut self,
+        (symbol_table, hir): (SymbolTable, HIR),
+        _: &Config,
+    ) -> ::std::result::Result<Self::Target, E> {
+        Ok((symbol_table, self.conv_hir(hir)))
+    }
+}
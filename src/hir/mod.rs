@@ -1,24 +1,50 @@
 pub mod ast2hir;
+pub mod call_graph;
+pub mod const_fold;
+pub mod copy_prop;
+pub mod cps;
+pub mod dce;
+pub mod effect;
 pub mod flat_expr;
 pub mod flat_let;
 pub mod force_closure;
+pub mod free_vars;
+pub mod inline;
+pub mod let_float;
 pub mod pp;
+pub mod uncurry;
 pub mod unnest_func;
 pub mod util;
+pub mod verify;
 
 pub use self::ast2hir::AST2HIR;
+pub use self::call_graph::CallGraph;
+pub use self::const_fold::ConstFold;
+pub use self::copy_prop::CopyProp;
+pub use self::cps::CPS;
+pub use self::dce::DCE;
+pub use self::effect::Effects;
 pub use self::flat_expr::FlatExpr;
 pub use self::flat_let::FlatLet;
 pub use self::force_closure::ForceClosure;
+pub use self::free_vars::FreeVars;
+pub use self::inline::Inline;
+pub use self::let_float::LetFloat;
+pub use self::uncurry::Uncurry;
 pub use self::unnest_func::UnnestFunc;
+pub use self::verify::Verify;
 use std::collections::HashMap;
 
 use crate::prim::*;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct HIR(pub Vec<Val>);
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Val {
     pub ty: HTy,
     pub rec: bool,
@@ -27,6 +53,7 @@ pub struct Val {
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Expr {
     Binds {
         ty: HTy,
@@ -92,6 +119,7 @@ pub enum Expr {
 }
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Pattern {
     Constant {
         value: i64,
@@ -119,13 +147,19 @@ pub enum Pattern {
 #[derive(Debug, Clone, PartialEq)]
 pub struct SymbolTable {
     pub types: HashMap<Symbol, TypeInfo>,
+    /// top level functions exported from the module, with the names to export as
+    /// and the types the JS glue converts the values by
+    pub exports: Vec<(Symbol, String, Option<ExportTypes>)>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum HTy {
     Char,
     Int,
+    Word,
     Real,
+    String,
     Fun(Box<HTy>, Box<HTy>),
     Tuple(Vec<HTy>),
     Datatype(Symbol),
@@ -133,6 +167,7 @@ pub enum HTy {
 }
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct TypeInfo {
     pub constructors: Vec<(u32, Option<HTy>)>,
 }
@@ -198,6 +233,17 @@ impl Pattern {
         }
     }
 
+    /// all the variables bound by the pattern
+    pub fn variables(&self) -> Vec<Symbol> {
+        use self::Pattern::*;
+        match self {
+            Constant { .. } | Char { .. } => vec![],
+            Constructor { arg, .. } => arg.iter().map(|(_, name)| name.clone()).collect(),
+            Var { name, .. } => vec![name.clone()],
+            Tuple { tuple, .. } => tuple.clone(),
+        }
+    }
+
     pub fn is_irrefutable(&self) -> bool {
         use self::Pattern::*;
         match *self {
@@ -205,10 +251,68 @@ impl Pattern {
             Tuple { .. } | Var { .. } => true,
         }
     }
+
+    /// the pattern binding the variables renamed by `rename`
+    pub fn rename(self, mut rename: impl FnMut(Symbol) -> Symbol) -> Pattern {
+        use self::Pattern::*;
+        match self {
+            Constructor {
+                descriminant,
+                arg,
+                ty,
+            } => Constructor {
+                descriminant,
+                arg: arg.map(|(ty, name)| (ty, rename(name))),
+                ty,
+            },
+            Var { name, ty } => Var {
+                name: rename(name),
+                ty,
+            },
+            Tuple { tys, tuple } => Tuple {
+                tys,
+                tuple: tuple.into_iter().map(rename).collect(),
+            },
+            pat @ Constant { .. } | pat @ Char { .. } => pat,
+        }
+    }
 }
 
 impl HTy {
     pub fn fun(arg: HTy, ret: HTy) -> HTy {
         HTy::Fun(Box::new(arg), Box::new(ret))
     }
+
+    /// the type in the SML syntax, as shown to the users of the module
+    pub fn to_sml(&self) -> String {
+        use crate::hir::HTy::*;
+        match self {
+            Char => "char".into(),
+            Int => "int".into(),
+            Word => "word".into(),
+            Real => "real".into(),
+            String => "string".into(),
+            Tuple(tys) if tys.is_empty() => "unit".into(),
+            Tuple(tys) => tys
+                .iter()
+                .map(|ty| match ty {
+                    Fun(..) | Tuple(..) if !ty.is_unit() => format!("({})", ty.to_sml()),
+                    _ => ty.to_sml(),
+                })
+                .collect::<Vec<_>>()
+                .join(" * "),
+            Fun(arg, ret) => match **arg {
+                Fun(..) => format!("({}) -> {}", arg.to_sml(), ret.to_sml()),
+                _ => format!("{} -> {}", arg.to_sml(), ret.to_sml()),
+            },
+            Datatype(name) => name.0.to_string(),
+        }
+    }
+
+    fn is_unit(&self) -> bool {
+        match self {
+            HTy::Tuple(tys) => tys.is_empty(),
+            _ => false,
+        }
+    }
 }
@@ -186,7 +186,9 @@ impl PP for HTy {
         match self {
             Char => write!(w, "char")?,
             Int => write!(w, "int")?,
+            Word => write!(w, "word")?,
             Real => write!(w, "real")?,
+            String => write!(w, "string")?,
             Tuple(tys) => {
                 write!(w, "(")?;
                 inter_iter! {
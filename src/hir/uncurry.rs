@@ -0,0 +1,297 @@
+use crate::config::Config;
+use crate::hir::util::{Transform, Traverse};
+use crate::hir::*;
+use crate::id::Id;
+use crate::pass::Pass;
+use std::collections::{HashMap, HashSet};
+
+/// multi-argument worker of a curried function.
+/// the arguments are passed as a tuple.
+#[derive(Debug, Clone)]
+struct Worker {
+    name: Symbol,
+    params: Vec<HTy>,
+    ret: HTy,
+}
+
+impl Worker {
+    fn ty(&self) -> HTy {
+        HTy::fun(HTy::Tuple(self.params.clone()), self.ret.clone())
+    }
+
+    fn arity(&self) -> usize {
+        self.params.len()
+    }
+}
+
+pub struct Uncurry {
+    id: Id,
+    workers: HashMap<Symbol, Worker>,
+    // functions whose currying wrapper is not defined yet at the current position
+    pending: HashSet<Symbol>,
+}
+
+struct Arity<'a> {
+    t: &'a mut Uncurry,
+}
+
+/// count directly nested `fn`s
+fn arity(mut expr: &Expr) -> (Vec<HTy>, HTy) {
+    let mut params = Vec::new();
+    let mut ret = expr.ty();
+    while let Expr::Fun {
+        param: (ty, _),
+        body_ty,
+        body,
+        ..
+    } = expr
+    {
+        params.push(ty.clone());
+        ret = body_ty.clone();
+        expr = body;
+    }
+    (params, ret)
+}
+
+/// returns the head and the number of arguments of a chain of applications
+fn spine(mut expr: &Expr) -> (&Expr, usize) {
+    let mut n = 0;
+    while let Expr::App { fun, .. } = expr {
+        n += 1;
+        expr = fun;
+    }
+    (expr, n)
+}
+
+impl<'a> Traverse for Arity<'a> {
+    fn traverse_val(&mut self, val: &mut Val) {
+        let (params, ret) = arity(&val.expr);
+        if 2 <= params.len() {
+            let name = self.t.worker_name(&val.name);
+            self.t
+                .workers
+                .insert(val.name.clone(), Worker { name, params, ret });
+        }
+        self.traverse_expr(&mut val.expr)
+    }
+}
+
+impl Uncurry {
+    pub fn new(id: Id) -> Self {
+        Self {
+            id,
+            workers: HashMap::new(),
+            pending: HashSet::new(),
+        }
+    }
+
+    fn gensym(&mut self) -> Symbol {
+        let id = self.id.next();
+        Symbol("#g".into(), id)
+    }
+
+    fn worker_name(&mut self, name: &Symbol) -> Symbol {
+        let id = self.id.next();
+        Symbol(format!("{}_uncurried", name.0).into(), id)
+    }
+
+    /// `fn x1 => ... => fn xn => worker (x1, ..., xn)`
+    fn wrapper(&mut self, worker: &Worker) -> Expr {
+        let params = worker
+            .params
+            .iter()
+            .map(|ty| (ty.clone(), self.gensym()))
+            .collect::<Vec<_>>();
+        let call = Expr::App {
+            ty: worker.ret.clone(),
+            fun: Box::new(Expr::Sym {
+                ty: worker.ty(),
+                name: worker.name.clone(),
+            }),
+            arg: Box::new(Expr::Tuple {
+                tys: worker.params.clone(),
+                tuple: params
+                    .iter()
+                    .map(|(ty, name)| Expr::Sym {
+                        ty: ty.clone(),
+                        name: name.clone(),
+                    })
+                    .collect(),
+            }),
+        };
+        let mut expr = call;
+        let mut body_ty = worker.ret.clone();
+        for param in params.into_iter().rev() {
+            let ty = HTy::fun(param.0.clone(), body_ty.clone());
+            expr = Expr::Fun {
+                param,
+                body_ty,
+                body: Box::new(expr),
+                captures: vec![],
+            };
+            body_ty = ty;
+        }
+        expr
+    }
+
+    fn uncurry_vals(&mut self, vals: Vec<Val>) -> Vec<Val> {
+        let mut ret = Vec::new();
+        for val in vals {
+            match self.workers.get(&val.name).cloned() {
+                None => ret.push(self.transform_val(val)),
+                Some(worker) => {
+                    let Val {
+                        ty,
+                        rec,
+                        name,
+                        expr,
+                    } = val;
+                    let mut params = Vec::new();
+                    let mut body = expr;
+                    while params.len() < worker.arity() {
+                        match body {
+                            Expr::Fun { param, body: b, .. } => {
+                                params.push(param);
+                                body = *b;
+                            }
+                            _ => unreachable!("arity analysis is inconsistent"),
+                        }
+                    }
+
+                    self.pending.insert(name.clone());
+                    let body = self.transform_expr(body);
+                    self.pending.remove(&name);
+
+                    let tuple = self.gensym();
+                    let tuple_ty = HTy::Tuple(worker.params.clone());
+                    let binds = params
+                        .into_iter()
+                        .enumerate()
+                        .map(|(index, (ty, name))| Val {
+                            ty: ty.clone(),
+                            rec: false,
+                            name,
+                            expr: Expr::Proj {
+                                ty,
+                                index: index as u32,
+                                tuple: Box::new(Expr::Sym {
+                                    ty: tuple_ty.clone(),
+                                    name: tuple.clone(),
+                                }),
+                            },
+                        })
+                        .collect();
+                    ret.push(Val {
+                        ty: worker.ty(),
+                        rec,
+                        name: worker.name.clone(),
+                        expr: Expr::Fun {
+                            param: (tuple_ty, tuple),
+                            body_ty: worker.ret.clone(),
+                            body: Box::new(Expr::Binds {
+                                ty: worker.ret.clone(),
+                                binds,
+                                ret: Box::new(body),
+                            }),
+                            captures: vec![],
+                        },
+                    });
+                    ret.push(Val {
+                        ty,
+                        rec: false,
+                        name,
+                        expr: self.wrapper(&worker),
+                    });
+                }
+            }
+        }
+        ret
+    }
+}
+
+impl Transform for Uncurry {
+    fn transform_hir(&mut self, mut hir: HIR) -> HIR {
+        hir.0 = self.uncurry_vals(hir.0);
+        hir
+    }
+
+    fn transform_binds(&mut self, ty: HTy, binds: Vec<Val>, ret: Box<Expr>) -> Expr {
+        Expr::Binds {
+            ty,
+            binds: self.uncurry_vals(binds),
+            ret: Box::new(self.transform_expr(*ret)),
+        }
+    }
+
+    fn transform_app(&mut self, ty: HTy, fun: Box<Expr>, arg: Box<Expr>) -> Expr {
+        let worker = match spine(&fun) {
+            (Expr::Sym { name, .. }, n) => self
+                .workers
+                .get(name)
+                .filter(|worker| worker.arity() <= n + 1)
+                .cloned(),
+            _ => None,
+        };
+        let worker = match worker {
+            Some(worker) => worker,
+            None => {
+                return Expr::App {
+                    ty,
+                    fun: Box::new(self.transform_expr(*fun)),
+                    arg: Box::new(self.transform_expr(*arg)),
+                }
+            }
+        };
+
+        // saturated call. collect arguments in the order of application
+        let mut args = vec![(ty, *arg)];
+        let mut fun = *fun;
+        while let Expr::App { ty, fun: f, arg } = fun {
+            args.push((ty, *arg));
+            fun = *f;
+        }
+        args.reverse();
+        let rest = args.split_off(worker.arity());
+        let call = Expr::App {
+            ty: worker.ret.clone(),
+            fun: Box::new(Expr::Sym {
+                ty: worker.ty(),
+                name: worker.name.clone(),
+            }),
+            arg: Box::new(Expr::Tuple {
+                tys: worker.params.clone(),
+                tuple: args
+                    .into_iter()
+                    .map(|(_, arg)| self.transform_expr(arg))
+                    .collect(),
+            }),
+        };
+        // over-saturated call
+        rest.into_iter().fold(call, |fun, (ty, arg)| Expr::App {
+            ty,
+            fun: Box::new(fun),
+            arg: Box::new(self.transform_expr(arg)),
+        })
+    }
+
+    fn transform_sym(&mut self, ty: HTy, name: Symbol) -> Expr {
+        if self.pending.contains(&name) {
+            let worker = self.workers[&name].clone();
+            return self.wrapper(&worker);
+        }
+        Expr::Sym { ty, name }
+    }
+}
+
+impl<E> Pass<(SymbolTable, HIR), E> for Uncurry {
+    type Target = (SymbolTable, HIR);
+
+    fn trans(
+        &mut self,
+        (symbol_table, mut hir): (SymbolTable, HIR),
+        _: &Config,
+    ) -> ::std::result::Result<Self::Target, E> {
+        Arity { t: self }.traverse_hir(&mut hir);
+        Ok((symbol_table, self.transform_hir(hir)))
+    }
+}
@@ -1,4 +1,5 @@
 use crate::config::Config;
+use crate::hir::free_vars::FreeVars;
 use crate::hir::*;
 use crate::id::Id;
 use crate::pass::Pass;
@@ -10,6 +11,7 @@ pub struct UnnestFunc {
     tables: Vec<HashSet<Symbol>>,
     tops: Vec<Val>,
     pos: usize,
+    free_vars: FreeVars,
     id: Id,
 }
 
@@ -53,7 +55,7 @@ impl<'a> Scope<'a> {
             Some(name) => format!("<{}>", name.0),
         };
         let id = self.id.next();
-        Symbol(new_name, id)
+        Symbol(new_name.into(), id)
     }
 
     fn add_scope(&mut self, symbol: Symbol) {
@@ -61,6 +63,11 @@ impl<'a> Scope<'a> {
         self.tables[pos].insert(symbol);
     }
 
+    fn remove_scope(&mut self, symbol: &Symbol) {
+        let pos = self.pos - 1;
+        self.tables[pos].remove(symbol);
+    }
+
     fn new_closure(&mut self, val: Val) {
         self.add_scope(val.name.clone());
         self.0.tops.push(val);
@@ -84,12 +91,15 @@ impl<'a> Scope<'a> {
             .map(|val| {
                 if val.rec {
                     self.add_scope(val.name.clone());
-                    self.conv_top_val(val)
-                } else {
-                    let val = self.conv_top_val(val);
-                    self.add_scope(val.name.clone());
-                    val
                 }
+                let val = self.conv_top_val(val);
+                // the other values are only bound in main, and the functions reading them
+                // capture them
+                match val.expr {
+                    Expr::Fun { .. } => self.add_scope(val.name.clone()),
+                    _ => self.remove_scope(&val.name),
+                }
+                val
             })
             .collect();
         let mut closures = self.0.tops.drain(..).collect::<Vec<_>>();
@@ -142,9 +152,10 @@ impl<'a> Scope<'a> {
                 assert_eq!(captures.len(), 0);
                 body = Box::new(self.conv_expr(*body, None, false));
                 let (param_ty, param) = param;
-                let mut frees = Vec::new();
-                self.analyze_free_expr(&mut frees, &param, &body);
-                frees.dedup();
+                let mut frees = self.free_vars.of_function(&param).to_vec();
+                frees.retain(|(_, name)| {
+                    !(self.is_in_scope(name) || Some(name) == bind_name.as_ref())
+                });
                 captures.extend(frees.clone());
                 let is_closure = !captures.is_empty();
                 if !is_closure && is_top {
@@ -158,7 +169,35 @@ impl<'a> Scope<'a> {
                 }
 
                 let fname = self.new_fname(bind_name.clone());
-                self.rename(&mut body, &bind_name, &fname);
+                match &bind_name {
+                    // the closure calls itself through a new closure of its environment
+                    Some(name) if is_closure => {
+                        let this = Closure {
+                            envs: frees.clone(),
+                            param_ty: param_ty.clone(),
+                            body_ty: body_ty.clone(),
+                            fname: fname.clone(),
+                        };
+                        let this = Val {
+                            ty: this.ty(),
+                            rec: false,
+                            name: name.clone(),
+                            expr: this,
+                        };
+                        body = match *body {
+                            Binds { ty, mut binds, ret } => {
+                                binds.insert(0, this);
+                                Box::new(Binds { ty, binds, ret })
+                            }
+                            body => Box::new(Binds {
+                                ty: body.ty(),
+                                binds: vec![this],
+                                ret: Box::new(body),
+                            }),
+                        }
+                    }
+                    _ => self.rename(&mut body, &bind_name, &fname),
+                }
                 let anonfun = Fun {
                     param: (param_ty.clone(), param),
                     body_ty: body_ty.clone(),
@@ -263,101 +302,6 @@ impl<'a> Scope<'a> {
         }
     }
 
-    fn analyze_free_val<'b, 'c>(
-        &'b mut self,
-        frees: &mut Vec<(HTy, Symbol)>,
-        bound: &Symbol,
-        val: &'c Val,
-    ) {
-        self.analyze_free_expr(frees, bound, &val.expr);
-    }
-
-    fn analyze_free_expr<'b, 'c>(
-        &'b mut self,
-        frees: &mut Vec<(HTy, Symbol)>,
-        bound: &Symbol,
-        expr: &'c Expr,
-    ) {
-        use crate::hir::Expr::*;
-        match expr {
-            Binds { binds, ret, .. } => {
-                let scope = self;
-                for bind in binds.iter() {
-                    if bind.rec {
-                        scope.add_scope(bind.name.clone());
-                        scope.analyze_free_val(frees, bound, bind);
-                    } else {
-                        scope.analyze_free_val(frees, bound, bind);
-                        scope.add_scope(bind.name.clone());
-                    }
-                }
-                scope.analyze_free_expr(frees, bound, ret);
-            }
-            Fun { .. } => panic!("internal bug"),
-            BuiltinCall { args, .. } => {
-                for arg in args {
-                    self.analyze_free_expr(frees, bound, arg);
-                }
-            }
-            ExternCall { args, .. } => {
-                for arg in args {
-                    self.analyze_free_expr(frees, bound, arg);
-                }
-            }
-            App { fun, arg, .. } => {
-                self.analyze_free_expr(frees, bound, fun);
-                self.analyze_free_expr(frees, bound, arg);
-            }
-            Case { expr, arms, .. } => {
-                self.analyze_free_expr(frees, bound, expr);
-                let scope = self;
-                for (pat, arm) in arms.iter() {
-                    use self::Pattern::*;
-                    match pat {
-                        Constructor { arg, .. } => {
-                            if let Some((_, arg)) = arg {
-                                scope.add_scope(arg.clone())
-                            }
-                        }
-                        Constant { .. } => (),
-                        Char { .. } => (),
-                        Tuple { tuple, .. } => {
-                            for name in tuple {
-                                scope.add_scope(name.clone())
-                            }
-                        }
-                        Var { name, .. } => scope.add_scope(name.clone()),
-                    }
-                    scope.analyze_free_expr(frees, bound, arm);
-                }
-            }
-            Tuple { tuple, .. } => {
-                for t in tuple.iter() {
-                    self.analyze_free_expr(frees, bound, t);
-                }
-            }
-            Proj { tuple, .. } => self.analyze_free_expr(frees, bound, tuple),
-            Sym { name, ty } => {
-                if !(self.is_in_scope(name) || bound == name) {
-                    frees.push((ty.clone(), name.clone()))
-                }
-            }
-            Closure { envs, .. } => {
-                for (ty, name) in envs {
-                    if !(self.is_in_scope(name) || bound == name) {
-                        frees.push((ty.clone(), name.clone()))
-                    }
-                }
-            }
-            Constructor { arg, .. } => {
-                if let Some(arg) = arg {
-                    self.analyze_free_expr(frees, bound, arg)
-                }
-            }
-            Lit { .. } => (),
-        }
-    }
-
     fn rename(&mut self, expr: &mut Expr, from: &Option<Symbol>, to: &Symbol) {
         use crate::hir::Expr::*;
         match expr {
@@ -415,6 +359,7 @@ impl UnnestFunc {
             tables: Vec::new(),
             tops: Vec::new(),
             pos: 0,
+            free_vars: FreeVars::default(),
             id,
         }
     }
@@ -432,6 +377,7 @@ impl<E> Pass<(SymbolTable, HIR), E> for UnnestFunc {
         (symbol_table, hir): (SymbolTable, HIR),
         _: &Config,
     ) -> ::std::result::Result<Self::Target, E> {
+        self.free_vars = FreeVars::analyze(&hir);
         Ok((symbol_table, self.scope().conv_hir(hir)))
     }
 }
@@ -1,52 +1,74 @@
 use crate::hir::*;
+use crate::util::ensure_stack;
 
-pub trait Traverse {
-    fn traverse_hir(&mut self, hir: &mut HIR) {
-        for val in hir.0.iter_mut() {
-            self.traverse_val(val)
-        }
-    }
-
-    fn traverse_val(&mut self, val: &mut Val) {
-        self.traverse_expr(&mut val.expr)
-    }
+/// takes the expression out of the tree, leaving the unit in its place,
+/// so that the passes rewriting the tree in place can move the parts of it
+pub fn take(expr: &mut Expr) -> Expr {
+    std::mem::replace(
+        expr,
+        Expr::Tuple {
+            tys: Vec::new(),
+            tuple: Vec::new(),
+        },
+    )
+}
 
-    fn traverse_expr(&mut self, expr: &mut Expr) {
+/// traverses the children of the expression. the passes overriding `traverse_expr` call it
+/// to rewrite the expression after, or before, its children
+pub fn walk_expr<T: Traverse + ?Sized>(this: &mut T, expr: &mut Expr) {
+    ensure_stack(|| {
         use crate::hir::Expr::*;
         match expr {
-            Binds { ty, binds, ret } => self.traverse_binds(ty, binds, ret),
+            Binds { ty, binds, ret } => this.traverse_binds(ty, binds, ret),
             Fun {
                 param,
                 body_ty,
                 body,
                 captures,
-            } => self.traverse_fun(param, body_ty, body, captures),
+            } => this.traverse_fun(param, body_ty, body, captures),
             Closure {
                 envs,
                 param_ty,
                 body_ty,
                 fname,
-            } => self.traverse_closure(envs, param_ty, body_ty, fname),
-            BuiltinCall { ty, fun, args } => self.traverse_builtin_call(ty, fun, args),
+            } => this.traverse_closure(envs, param_ty, body_ty, fname),
+            BuiltinCall { ty, fun, args } => this.traverse_builtin_call(ty, fun, args),
             ExternCall {
                 ty,
                 module,
                 fun,
                 args,
-            } => self.traverse_extern_call(ty, module, fun, args),
-            App { ty, fun, arg } => self.traverse_app(ty, fun, arg),
-            Case { ty, expr, arms } => self.traverse_case(ty, expr, arms),
-            Tuple { tys, tuple } => self.traverse_tuple(tys, tuple),
-            Proj { ty, index, tuple } => self.traverse_proj(ty, index, tuple),
+            } => this.traverse_extern_call(ty, module, fun, args),
+            App { ty, fun, arg } => this.traverse_app(ty, fun, arg),
+            Case { ty, expr, arms } => this.traverse_case(ty, expr, arms),
+            Tuple { tys, tuple } => this.traverse_tuple(tys, tuple),
+            Proj { ty, index, tuple } => this.traverse_proj(ty, index, tuple),
             Constructor {
                 ty,
                 arg,
                 descriminant,
-            } => self.traverse_constructor(ty, arg, descriminant),
-            Sym { ty, name } => self.traverse_sym(ty, name),
-            Lit { ty, value } => self.traverse_lit(ty, value),
+            } => this.traverse_constructor(ty, arg, descriminant),
+            Sym { ty, name } => this.traverse_sym(ty, name),
+            Lit { ty, value } => this.traverse_lit(ty, value),
         }
+    })
+}
+
+pub trait Traverse {
+    fn traverse_hir(&mut self, hir: &mut HIR) {
+        for val in hir.0.iter_mut() {
+            self.traverse_val(val)
+        }
+    }
+
+    fn traverse_val(&mut self, val: &mut Val) {
+        self.traverse_expr(&mut val.expr)
+    }
+
+    fn traverse_expr(&mut self, expr: &mut Expr) {
+        walk_expr(self, expr)
     }
+
     fn traverse_binds(&mut self, _ty: &mut HTy, binds: &mut Vec<Val>, ret: &mut Box<Expr>) {
         for val in binds.iter_mut() {
             self.traverse_val(val)
@@ -150,40 +172,42 @@ pub trait Transform {
     }
 
     fn transform_expr(&mut self, expr: Expr) -> Expr {
-        use crate::hir::Expr::*;
-        match expr {
-            Binds { ty, binds, ret } => self.transform_binds(ty, binds, ret),
-            Fun {
-                param,
-                body_ty,
-                body,
-                captures,
-            } => self.transform_fun(param, body_ty, body, captures),
-            App { fun, arg, ty } => self.transform_app(ty, fun, arg),
-            Case { ty, expr, arms } => self.transform_case(ty, expr, arms),
-            Tuple { tys, tuple } => self.transform_tuple(tys, tuple),
-            Proj { ty, index, tuple } => self.transform_proj(ty, index, tuple),
-            BuiltinCall { ty, fun, args } => self.transform_builtin_call(ty, fun, args),
-            ExternCall {
-                ty,
-                module,
-                fun,
-                args,
-            } => self.transform_extern_call(ty, module, fun, args),
-            Closure {
-                envs,
-                param_ty,
-                body_ty,
-                fname,
-            } => self.transform_closure(envs, param_ty, body_ty, fname),
-            Constructor {
-                ty,
-                arg,
-                descriminant,
-            } => self.transform_constructor(ty, arg, descriminant),
-            Sym { ty, name } => self.transform_sym(ty, name),
-            Lit { ty, value } => self.transform_lit(ty, value),
-        }
+        ensure_stack(|| {
+            use crate::hir::Expr::*;
+            match expr {
+                Binds { ty, binds, ret } => self.transform_binds(ty, binds, ret),
+                Fun {
+                    param,
+                    body_ty,
+                    body,
+                    captures,
+                } => self.transform_fun(param, body_ty, body, captures),
+                App { fun, arg, ty } => self.transform_app(ty, fun, arg),
+                Case { ty, expr, arms } => self.transform_case(ty, expr, arms),
+                Tuple { tys, tuple } => self.transform_tuple(tys, tuple),
+                Proj { ty, index, tuple } => self.transform_proj(ty, index, tuple),
+                BuiltinCall { ty, fun, args } => self.transform_builtin_call(ty, fun, args),
+                ExternCall {
+                    ty,
+                    module,
+                    fun,
+                    args,
+                } => self.transform_extern_call(ty, module, fun, args),
+                Closure {
+                    envs,
+                    param_ty,
+                    body_ty,
+                    fname,
+                } => self.transform_closure(envs, param_ty, body_ty, fname),
+                Constructor {
+                    ty,
+                    arg,
+                    descriminant,
+                } => self.transform_constructor(ty, arg, descriminant),
+                Sym { ty, name } => self.transform_sym(ty, name),
+                Lit { ty, value } => self.transform_lit(ty, value),
+            }
+        })
     }
 
     fn transform_binds(&mut self, ty: HTy, binds: Vec<Val>, ret: Box<Expr>) -> Expr {
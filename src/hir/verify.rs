@@ -0,0 +1,196 @@
+use crate::ast::TypeError;
+use crate::config::Config;
+use crate::hir::*;
+use crate::pass::Pass;
+use std::collections::HashSet;
+
+/// Checks the invariants of HIR.
+///
+/// - all the symbols are bound
+/// - the type of an application agrees with the type of the function
+/// - (flat mode) `Binds` appear only at the top of vals, function bodies and case arms,
+///   which is the form `FlatLet` produces.
+pub struct Verify {
+    flat: bool,
+    functions: HashSet<Symbol>,
+    bound: HashMap<Symbol, usize>,
+    // bound variables in the order of binding, to unbind when leaving the scope
+    scope: Vec<Symbol>,
+}
+
+type Result<T> = ::std::result::Result<T, String>;
+
+impl Verify {
+    pub fn new() -> Self {
+        Verify {
+            flat: false,
+            functions: HashSet::new(),
+            bound: HashMap::new(),
+            scope: Vec::new(),
+        }
+    }
+
+    /// verifier for HIR after `FlatLet`
+    pub fn flat() -> Self {
+        Verify {
+            flat: true,
+            ..Self::new()
+        }
+    }
+
+    fn bind(&mut self, name: &Symbol) {
+        *self.bound.entry(name.clone()).or_insert(0) += 1;
+        self.scope.push(name.clone());
+    }
+
+    fn scope<T>(&mut self, f: impl FnOnce(&mut Self) -> T) -> T {
+        let depth = self.scope.len();
+        let ret = f(self);
+        for name in self.scope.split_off(depth) {
+            let count = self.bound.get_mut(&name).expect("internal error");
+            *count -= 1;
+            if *count == 0 {
+                self.bound.remove(&name);
+            }
+        }
+        ret
+    }
+
+    fn is_bound(&self, name: &Symbol) -> bool {
+        self.bound.contains_key(name) || self.functions.contains(name)
+    }
+
+    fn verify_hir(&mut self, hir: &HIR) -> Result<()> {
+        // top level functions can be referred before their definitions
+        self.functions = hir
+            .0
+            .iter()
+            .filter(|val| match val.expr {
+                Expr::Fun { .. } => true,
+                _ => false,
+            })
+            .map(|val| val.name.clone())
+            .collect();
+        self.scope(|this| {
+            for val in hir.0.iter() {
+                this.verify_val(val, true)?;
+            }
+            Ok(())
+        })
+    }
+
+    fn verify_val(&mut self, val: &Val, top: bool) -> Result<()> {
+        if val.rec {
+            self.bind(&val.name);
+            self.verify_expr(&val.expr, top)?;
+        } else {
+            self.verify_expr(&val.expr, top)?;
+            self.bind(&val.name);
+        }
+        Ok(())
+    }
+
+    /// `top` is true if the expr may be `Binds` in the flat form
+    fn verify_expr(&mut self, expr: &Expr, top: bool) -> Result<()> {
+        use crate::hir::Expr::*;
+        match expr {
+            Binds { binds, ret, .. } => {
+                if self.flat && !top {
+                    return Err(format!("nested binds are left: {:?}", expr));
+                }
+                self.scope(|this| {
+                    for val in binds {
+                        this.verify_val(val, false)?;
+                    }
+                    this.verify_expr(ret, false)
+                })
+            }
+            BuiltinCall { args, .. } | ExternCall { args, .. } => {
+                for arg in args {
+                    self.verify_expr(arg, false)?;
+                }
+                Ok(())
+            }
+            Fun {
+                param,
+                body,
+                captures,
+                ..
+            } => self.scope(|this| {
+                this.bind(&param.1);
+                for (_, var) in captures {
+                    this.bind(var);
+                }
+                this.verify_expr(body, true)
+            }),
+            Closure { envs, fname, .. } => {
+                if !self.functions.contains(fname) {
+                    return Err(format!("closure of unknown function {:?}", fname));
+                }
+                for (_, var) in envs {
+                    self.verify_sym(var)?;
+                }
+                Ok(())
+            }
+            App { ty, fun, arg } => {
+                self.verify_expr(fun, false)?;
+                self.verify_expr(arg, false)?;
+                let expected = HTy::fun(arg.ty(), ty.clone());
+                if fun.ty() != expected {
+                    return Err(format!(
+                        "function of type {:?} is applied as {:?}",
+                        fun.ty(),
+                        expected
+                    ));
+                }
+                Ok(())
+            }
+            Case { expr, arms, .. } => {
+                self.verify_expr(expr, false)?;
+                for (pat, arm) in arms {
+                    self.scope(|this| {
+                        for name in pat.variables() {
+                            this.bind(&name);
+                        }
+                        this.verify_expr(arm, true)
+                    })?;
+                }
+                Ok(())
+            }
+            Tuple { tuple, .. } => {
+                for e in tuple {
+                    self.verify_expr(e, false)?;
+                }
+                Ok(())
+            }
+            Proj { tuple, .. } => self.verify_expr(tuple, false),
+            Constructor { arg, .. } => match arg {
+                Some(arg) => self.verify_expr(arg, false),
+                None => Ok(()),
+            },
+            Sym { name, .. } => self.verify_sym(name),
+            Lit { .. } => Ok(()),
+        }
+    }
+
+    fn verify_sym(&self, name: &Symbol) -> Result<()> {
+        if self.is_bound(name) {
+            Ok(())
+        } else {
+            Err(format!("unbound symbol {:?}", name))
+        }
+    }
+}
+
+impl<'a> Pass<(SymbolTable, HIR), TypeError<'a>> for Verify {
+    type Target = (SymbolTable, HIR);
+
+    fn trans(
+        &mut self,
+        (symbol_table, hir): (SymbolTable, HIR),
+        _: &Config,
+    ) -> ::std::result::Result<Self::Target, TypeError<'a>> {
+        self.verify_hir(&hir).map_err(TypeError::InvalidIR)?;
+        Ok((symbol_table, hir))
+    }
+}
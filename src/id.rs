@@ -9,6 +9,11 @@ impl Id {
         Id(Rc::new(Cell::new(1)))
     }
 
+    /// a new generator which starts from where this one is, and goes on separately
+    pub fn fork(&self) -> Self {
+        Id(Rc::new(Cell::new(self.0.get())))
+    }
+
     pub fn next(&mut self) -> u64 {
         let ret = self.0.get();
         self.0.set(ret + 1);
@@ -0,0 +1,105 @@
+//! compilation of the programs which change a little between the compilations,
+//! e.g. in the watch mode.
+//! the top level declarations are parsed only when their source changed, and the
+//! front end runs only when the parsed program changed, e.g. not for edits of comments.
+//! the inference is monomorphic and a declaration may fix the types of the earlier
+//! declarations, so the front end runs on the whole program when any declaration changed.
+
+use crate::ast::{SymbolTable, TypedCore, UntypedAst};
+use crate::config::Config;
+use crate::id::Id;
+use crate::parser::ParseCache;
+use crate::pass_manager::{CompileError, PassManager, IR};
+use crate::{add_backend_passes, add_elaboration_passes, backend, into_module, validate_config};
+
+/// the output of the front end
+struct Elaborated {
+    ast: UntypedAst,
+    typed: (SymbolTable, TypedCore),
+    /// the ids after the front end, for the back end to go on with
+    id: Id,
+}
+
+/// compiles the programs keeping the results for the next compilation
+#[derive(Default)]
+pub struct Incremental {
+    parse_cache: ParseCache,
+    elaborated: Option<Elaborated>,
+    elaborations: usize,
+}
+
+impl Incremental {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// the number of the declarations parsed anew by the last compilation
+    pub fn reparsed(&self) -> usize {
+        self.parse_cache.reparsed()
+    }
+
+    /// the number of the times the front end ran
+    pub fn elaborations(&self) -> usize {
+        self.elaborations
+    }
+
+    fn elaborate<'a>(
+        &mut self,
+        input: &'a str,
+        config: &Config,
+    ) -> Result<(Id, (SymbolTable, TypedCore)), CompileError<'a>> {
+        let ast = self
+            .parse_cache
+            .parse(input)
+            .map_err(|e| CompileError::Pass {
+                pass: "parse",
+                error: e.into(),
+            })?;
+        if let Some(elaborated) = &self.elaborated {
+            if elaborated.ast == ast {
+                return Ok((elaborated.id.fork(), elaborated.typed.clone()));
+            }
+        }
+
+        let id = Id::new();
+        let mut passes = PassManager::new();
+        add_elaboration_passes(&mut passes, &id);
+        self.elaborations += 1;
+        let typed = match passes.run(ast.clone(), config)? {
+            IR::TypedCore(typed) => typed,
+            ir => {
+                return Err(CompileError::IRMismatch {
+                    pass: "output",
+                    ir: ir.kind(),
+                })
+            }
+        };
+        self.elaborated = Some(Elaborated {
+            ast,
+            typed: typed.clone(),
+            id: id.fork(),
+        });
+        Ok((id, typed))
+    }
+
+    /// reports the errors of the program. there are none if the parsed program is the same as
+    /// the last one passing the front end, which is not run again then
+    pub fn check<'a>(&mut self, input: &'a str, config: &Config) -> Result<(), CompileError<'a>> {
+        self.elaborate(input, config)?;
+        Ok(())
+    }
+
+    /// compiles the program, starting the back end from the typed program of the last one
+    /// passing the front end if the parsed program is the same
+    pub fn compile<'a>(
+        &mut self,
+        input: &'a str,
+        config: &Config,
+    ) -> Result<backend::Module, CompileError<'a>> {
+        validate_config(config)?;
+        let (id, typed) = self.elaborate(input, config)?;
+        let mut passes = PassManager::new();
+        add_backend_passes(&mut passes, id);
+        into_module(passes.run(typed, config)?)
+    }
+}
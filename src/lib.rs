@@ -2,49 +2,273 @@
 pub mod util;
 pub mod ast;
 pub mod backend;
+mod cache;
+pub mod compiler;
 mod config;
+pub mod eval;
+pub mod format;
 pub mod hir;
 pub mod id;
+pub mod incremental;
 pub mod lir;
 pub mod mir;
 mod parser;
 pub mod pass;
+pub mod pass_manager;
 pub mod prim;
+pub mod project;
 mod unification_pool;
 
 pub use crate::ast::TypeError;
-pub use crate::config::Config;
-pub use crate::parser::parse;
+pub use crate::compiler::{Artifact, Compiler, CompilerBuilder, Diagnostic, Output};
+pub use crate::config::{
+    Collector, Config, Emit, EmitFormat, Memory, OptLevel, Overflow, Prelude, Target, Verbosity,
+};
+pub use crate::eval::{EvalError, Interpreter};
+pub use crate::format::{fmt, FormatConfig, FormatError};
+pub use crate::incremental::Incremental;
+pub use crate::parser::{parse, ParseCache, StreamParser};
 pub use crate::pass::{Chain, Pass};
+pub use crate::pass_manager::{CompileError, PassManager, IR};
+pub use crate::project::{Project, SourceFile};
+use std::io;
 
-pub fn compile_str<'a>(input: &'a str, config: &Config) -> Result<Vec<u8>, TypeError<'a>> {
-    use crate::pass::{ConvError, PrintablePass};
-    use wasm::Dump;
+/// inserts the verifier after each of the passes in the debug builds, to catch their bugs early.
+/// the verifier after `pass` is named `verify_pass`
+macro_rules! verify_after {
+    ($passes: expr, $verifier: expr, [$($pass: literal),* $(,)?]) => {
+        if cfg!(debug_assertions) {
+            $($passes.insert_after($pass, concat!("verify_", $pass), $verifier);)*
+        }
+    };
+}
+
+/// the source of the prelude selected by `Config::prelude`
+fn load_prelude(config: &Config) -> Result<String, CompileError<'static>> {
+    config
+        .prelude
+        .load()
+        .map_err(|e| CompileError::InvalidConfig {
+            message: format!("failed to read the prelude: {}", e),
+        })
+}
+
+/// adds the passes from the parser to the pattern match compilation,
+/// which find all the errors of the program. the program is parsed after the prelude
+fn add_frontend_passes<'a>(
+    passes: &mut PassManager<'a>,
+    id: &id::Id,
+    prelude: &str,
+) -> Result<(), CompileError<'static>> {
+    use crate::pass::ConvError;
+
+    let parser = parser::BasisParser::new(prelude).map_err(|e| CompileError::InvalidConfig {
+        message: format!("failed to parse the prelude: {}", e),
+    })?;
+    passes.add(
+        "parse",
+        ConvError::new(move |input: &'a str| parser.parse(input)),
+    );
+    add_elaboration_passes(passes, id);
+    Ok(())
+}
+
+/// adds the passes of the front end after the parser
+fn add_elaboration_passes<'a>(passes: &mut PassManager<'a>, id: &id::Id) {
+    passes
+        .add("desugar", ast::Desugar::new(id.clone()))
+        .add("rename", ast::Rename::new(id.clone()))
+        .add("var_to_constructor", ast::VarToConstructor::new(id.clone()))
+        .add("typing", ast::Typer::new(id.clone()))
+        .add("case_simplify", ast::CaseSimplify::new(id.clone()));
+
+    verify_after!(passes, ast::Verify::new(), ["typing", "case_simplify"]);
+}
+
+/// reports the errors of the program without generating the code.
+/// it type checks the program and checks the exhaustiveness of the patterns
+pub fn check<'a>(input: &'a str, config: &Config) -> Result<(), CompileError<'a>> {
+    let id = id::Id::new();
+    let mut passes = PassManager::new();
+    add_frontend_passes(&mut passes, &id, &load_prelude(config)?)?;
+    passes.run(input, config)?;
+    Ok(())
+}
+
+/// type checks the program and returns the typed AST after the pattern match compilation,
+/// for the tools analyzing the programs. it's serializable with the `serde` feature
+pub fn typed_ast<'a>(input: &'a str, config: &Config) -> Result<ast::TypedCore, CompileError<'a>> {
+    let id = id::Id::new();
+    let mut passes = PassManager::new();
+    add_frontend_passes(&mut passes, &id, &load_prelude(config)?)?;
+    match passes.run(input, config)? {
+        IR::TypedCore((_, ast)) => Ok(ast),
+        ir => Err(CompileError::IRMismatch {
+            pass: "typed_ast",
+            ir: ir.kind(),
+        }),
+    }
+}
 
+/// `check` for the parsed program
+fn check_ast<'a>(ast: ast::UntypedAst, config: &Config) -> Result<(), CompileError<'a>> {
     let id = id::Id::new();
+    let mut passes = PassManager::new();
+    add_elaboration_passes(&mut passes, &id);
+    passes.run(ast, config)?;
+    Ok(())
+}
 
-    let mut passes = compile_pass![
-       parse: ConvError::new(parse),
-       desugar: ast::Desugar::new(id.clone()),
-       rename: ast::Rename::new(id.clone()),
-       var_to_constructor: ast::VarToConstructor::new(id.clone()),
-       typing: ast::Typer::new(),
-       case_simplify: ast::CaseSimplify::new(id.clone()),
-       ast_to_hir: hir::AST2HIR::new(id.clone()),
-       flattening_expression: hir::FlatExpr::new(id.clone()),
-       flattening_let: hir::FlatLet::new(),
-       unnest_functions: hir::UnnestFunc::new(id.clone()),
-       closure_conversion: hir::ForceClosure::new(),
-       hir_to_mir: mir::HIR2MIR::new(id),
-       unalias: mir::UnAlias::new(),
-       block_arrange: mir::BlockArrange::new(),
-       mir_to_lir: lir::MIR2LIR::new(),
-       backend: backend::LIR2WASM::new(),
-    ];
-
-    let module: wasm::Module = passes.trans(input, config)?;
+/// runs the program with the reference interpreter, writing the output of `print` to `out`
+pub fn eval<'a>(input: &'a str, config: &Config, out: impl io::Write) -> Result<(), EvalError<'a>> {
+    let id = id::Id::new();
+    let mut passes = PassManager::new();
+    add_frontend_passes(&mut passes, &id, &load_prelude(config)?)?;
+    passes.add("ast_to_hir", hir::AST2HIR::new(id));
+    verify_after!(passes, hir::Verify::new(), ["ast_to_hir"]);
+    match passes.run(input, config)? {
+        IR::HIR((_, hir)) => Interpreter::new(out).run(&hir)?,
+        ir => {
+            return Err(CompileError::IRMismatch {
+                pass: "eval",
+                ir: ir.kind(),
+            }
+            .into())
+        }
+    }
+    Ok(())
+}
 
+pub fn compile_str<'a>(input: &'a str, config: &Config) -> Result<Vec<u8>, CompileError<'a>> {
+    let module = compile(input, config)?;
     let mut code = Vec::new();
     module.dump(&mut code);
     Ok(code)
 }
+
+/// adds the passes from the lowering of the typed AST to the encoding of the module
+fn add_backend_passes<'a>(passes: &mut PassManager<'a>, id: id::Id) {
+    passes
+        .add("ast_to_hir", hir::AST2HIR::new(id.clone()))
+        .add("uncurry", hir::Uncurry::new(id.clone()))
+        .add_optional("cps_conversion", hir::CPS::new(id.clone()))
+        .add("flattening_expression", hir::FlatExpr::new(id.clone()))
+        .add_optional("inlining", hir::Inline::new(id.clone()))
+        .add_optional("constant_folding", hir::ConstFold::new())
+        .add("flattening_let", hir::FlatLet::new())
+        .add("copy_propagation", hir::CopyProp::new())
+        .add("dead_code_elimination", hir::DCE::new())
+        .add("let_floating", hir::LetFloat::new())
+        .add("unnest_functions", hir::UnnestFunc::new(id.clone()))
+        .add("closure_conversion", hir::ForceClosure::new())
+        .add_optional("call_graph", hir::CallGraph::new())
+        .add("hir_to_mir", mir::HIR2MIR::new(id.clone()))
+        .add("unboxing", mir::Unbox::new(id))
+        .add("escape_analysis", mir::EscapeAnalysis::new())
+        .add("cse", mir::CSE::new())
+        .add("unalias", mir::UnAlias::new())
+        .add("block_arrange", mir::BlockArrange::new())
+        .add("mir_to_lir", lir::MIR2LIR::new())
+        .add("local_allocation", lir::LocalAlloc::new())
+        .add("backend", backend::LIR2WASM::new())
+        .add("peephole", backend::Peephole::new())
+        .add("validate", backend::Validate::new());
+
+    verify_after!(
+        passes,
+        hir::Verify::new(),
+        [
+            "ast_to_hir",
+            "uncurry",
+            "cps_conversion",
+            "flattening_expression",
+            "inlining",
+            "constant_folding",
+        ]
+    );
+    verify_after!(
+        passes,
+        hir::Verify::flat(),
+        [
+            "flattening_let",
+            "copy_propagation",
+            "dead_code_elimination",
+            "let_floating",
+            "unnest_functions",
+            "closure_conversion",
+        ]
+    );
+    verify_after!(
+        passes,
+        mir::Verify::new(),
+        [
+            "hir_to_mir",
+            "unboxing",
+            "escape_analysis",
+            "cse",
+            "unalias",
+            "block_arrange",
+        ]
+    );
+}
+
+/// fails if the config can't be compiled with
+fn validate_config(config: &Config) -> Result<(), CompileError<'static>> {
+    config
+        .memory
+        .validate()
+        .map_err(|message| CompileError::InvalidConfig { message })?;
+    // the async imports resume the program with its continuation, which the loader calls
+    if let Some((module, name)) = config.async_imports.first() {
+        let cps = config.enabled_passes.contains("cps_conversion")
+            && !config.disabled_passes.contains("cps_conversion");
+        if !cps || config.target != Target::Js {
+            return Err(CompileError::InvalidConfig {
+                message: format!(
+                    "async import {}.{} requires the cps_conversion pass and the JS target",
+                    module, name
+                ),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// takes the module out of the output of the passes
+fn into_module(ir: IR) -> Result<backend::Module, CompileError> {
+    match ir {
+        IR::Wasm(module) => Ok(module),
+        ir => Err(CompileError::IRMismatch {
+            pass: "output",
+            ir: ir.kind(),
+        }),
+    }
+}
+
+/// compiles to the module before encoding, along with the interface to the host
+pub fn compile<'a>(input: &'a str, config: &Config) -> Result<backend::Module, CompileError<'a>> {
+    validate_config(config)?;
+    let prelude = load_prelude(config)?;
+    let sources = [prelude.as_str(), input];
+    cache::Cache::get_or_compile(config.cache_dir.as_deref(), &sources, config, || {
+        let id = id::Id::new();
+        let mut passes = PassManager::new();
+        add_frontend_passes(&mut passes, &id, &prelude)?;
+        add_backend_passes(&mut passes, id);
+        into_module(passes.run(input, config)?)
+    })
+}
+
+/// `compile` for the parsed program, passing the IRs selected by `Config::emit` to `emit`
+fn compile_ast<'a>(
+    ast: ast::UntypedAst,
+    config: &Config,
+    emit: &mut dyn FnMut(Emit, &IR<'a>),
+) -> Result<backend::Module, CompileError<'a>> {
+    validate_config(config)?;
+    let id = id::Id::new();
+    let mut passes = PassManager::new();
+    add_elaboration_passes(&mut passes, &id);
+    add_backend_passes(&mut passes, id);
+    into_module(passes.run_emitting(ast, config, emit)?)
+}
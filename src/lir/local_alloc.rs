@@ -0,0 +1,182 @@
+//! allocation of the registers to the wasm locals.
+//! MIR2LIR gives every temporary a register of its own, each of which becomes a local.
+//! this pass lets the registers of the same type whose live ranges don't overlap
+//! share a local, so that the functions declare a few locals.
+
+use crate::config::Config;
+use crate::lir::*;
+use crate::pass::Pass;
+use std::collections::{HashMap, HashSet};
+
+pub struct LocalAlloc;
+
+impl LocalAlloc {
+    pub fn new() -> Self {
+        LocalAlloc
+    }
+
+    fn trans_lir(&mut self, lir: LIR) -> LIR {
+        LIR(lir.0.into_iter().map(|f| self.trans_function(f)).collect())
+    }
+
+    fn trans_function(&mut self, mut f: Function) -> Function {
+        let interference = Liveness::new(&f.body).interference(f.nparams);
+        let nparams = f.nparams as usize;
+
+        // the parameters keep their registers
+        let mut regs = f.regs[..nparams].to_vec();
+        let mut reg_names = f.reg_names[..nparams].to_vec();
+        let mut assigned = (0..nparams as u32)
+            .map(|reg| (reg, reg))
+            .collect::<HashMap<_, _>>();
+        let mut used = f
+            .body
+            .iter()
+            .flat_map(|b| &b.body)
+            .flat_map(|op| {
+                let (def, uses) = op.def_uses();
+                def.into_iter().chain(uses).map(|reg| reg.1)
+            })
+            .filter(|&reg| reg as usize >= nparams)
+            .collect::<Vec<_>>();
+        used.sort();
+        used.dedup();
+
+        for reg in used {
+            let ty = &f.regs[reg as usize];
+            let name = &f.reg_names[reg as usize];
+            let neighbors = interference
+                .get(&reg)
+                .into_iter()
+                .flatten()
+                .filter_map(|n| assigned.get(n))
+                .collect::<HashSet<_>>();
+            let local = (0..regs.len() as u32)
+                .find(|local| regs[*local as usize] == *ty && !neighbors.contains(local));
+            let local = match local {
+                Some(local) => {
+                    // the name is only kept when all the registers of the local are of it
+                    if reg_names[local as usize] != *name {
+                        reg_names[local as usize] = None;
+                    }
+                    local
+                }
+                None => {
+                    regs.push(ty.clone());
+                    reg_names.push(name.clone());
+                    regs.len() as u32 - 1
+                }
+            };
+            assigned.insert(reg, local);
+        }
+
+        for op in f.body.iter_mut().flat_map(|b| &mut b.body) {
+            let (def, uses) = op.def_uses_mut();
+            for reg in def.into_iter().chain(uses) {
+                reg.1 = assigned[&reg.1];
+            }
+        }
+        f.regs = regs;
+        f.reg_names = reg_names;
+        f
+    }
+}
+
+/// the registers live at the start of the blocks
+struct Liveness<'a> {
+    body: &'a [Block],
+    labels: HashMap<&'a Label, usize>,
+    live_in: Vec<HashSet<u32>>,
+}
+
+impl<'a> Liveness<'a> {
+    fn new(body: &'a [Block]) -> Self {
+        let labels = body.iter().enumerate().map(|(i, b)| (&b.name, i)).collect();
+        let mut liveness = Liveness {
+            body,
+            labels,
+            live_in: vec![HashSet::new(); body.len()],
+        };
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for i in (0..body.len()).rev() {
+                let live_in = liveness.scan(i, |_, _| ());
+                if live_in != liveness.live_in[i] {
+                    liveness.live_in[i] = live_in;
+                    changed = true;
+                }
+            }
+        }
+        liveness
+    }
+
+    /// walks the block backward calling `on_def` with each defined register and
+    /// the registers live after the definition. returns the registers live at the start
+    fn scan(&self, i: usize, mut on_def: impl FnMut(u32, &HashSet<u32>)) -> HashSet<u32> {
+        use crate::lir::Op::*;
+        // the blocks fall through to the next one unless they jump
+        let mut live = self.live_in.get(i + 1).cloned().unwrap_or_default();
+        for op in self.body[i].body.iter().rev() {
+            match op {
+                Jump(_) | Ret(_) | Unreachable => live.clear(),
+                _ => (),
+            }
+            for label in op.branches() {
+                live.extend(&self.live_in[self.labels[label]]);
+            }
+            let (def, uses) = op.def_uses();
+            if let Some(def) = def {
+                on_def(def.1, &live);
+                live.remove(&def.1);
+            }
+            live.extend(uses.into_iter().map(|reg| reg.1));
+        }
+        live
+    }
+
+    /// the pairs of the registers which can't share a local, as adjacency lists
+    fn interference(&self, nparams: u32) -> HashMap<u32, HashSet<u32>> {
+        let mut interference = HashMap::new();
+        let mut add = |r1: u32, r2: u32| {
+            if r1 != r2 {
+                interference
+                    .entry(r1)
+                    .or_insert_with(HashSet::new)
+                    .insert(r2);
+                interference
+                    .entry(r2)
+                    .or_insert_with(HashSet::new)
+                    .insert(r1);
+            }
+        };
+        for i in 0..self.body.len() {
+            self.scan(i, |def, live| {
+                for &reg in live {
+                    add(def, reg)
+                }
+            });
+        }
+        // the parameters are defined at the entry, and the other registers
+        // live there are read before written, holding zero
+        let entry = self.live_in.first().cloned().unwrap_or_default();
+        for param in 0..nparams {
+            for &reg in &entry {
+                add(param, reg);
+            }
+        }
+        interference
+    }
+}
+
+impl<E> Pass<(ExternTypes, LIR), E> for LocalAlloc {
+    type Target = (ExternTypes, LIR);
+
+    fn trans(
+        &mut self,
+        (extern_types, lir): (ExternTypes, LIR),
+        _: &Config,
+    ) -> ::std::result::Result<Self::Target, E> {
+        Ok((extern_types, self.trans_lir(lir)))
+    }
+}
@@ -3,14 +3,18 @@ use crate::lir::*;
 use crate::mir;
 use crate::pass::Pass;
 use crate::prim::*;
-use log::debug;
 use std::collections::HashMap;
+use tracing::trace;
 
 pub struct MIR2LIR {}
 
 pub struct MIR2LIRPass {
     extern_types: ExternTypes,
+    // the imports taking the continuation, of `Config::async_imports`
+    async_imports: Vec<(String, String)>,
     symbol_table: mir::SymbolTable,
+    // the types of the variants the union variables hold
+    variants: HashMap<Symbol, mir::EbbTy>,
 }
 
 impl MIR2LIR {
@@ -18,16 +22,48 @@ impl MIR2LIR {
         MIR2LIR {}
     }
 
-    fn generate_pass(&mut self, symbol_table: mir::SymbolTable) -> MIR2LIRPass {
-        MIR2LIRPass::new(symbol_table)
+    fn generate_pass(
+        &mut self,
+        symbol_table: mir::SymbolTable,
+        async_imports: Vec<(String, String)>,
+    ) -> MIR2LIRPass {
+        MIR2LIRPass::new(symbol_table, async_imports)
     }
 }
 
 impl MIR2LIRPass {
-    fn new(symbol_table: mir::SymbolTable) -> Self {
+    fn new(symbol_table: mir::SymbolTable, async_imports: Vec<(String, String)>) -> Self {
         Self {
             extern_types: HashMap::new(),
+            async_imports,
             symbol_table,
+            variants: HashMap::new(),
+        }
+    }
+
+    fn trace(&self, ty: &mir::EbbTy) -> Trace {
+        use crate::mir::EbbTy::*;
+        match ty {
+            Unit | Char | Int | Word | Float | Bool | Ebb { .. } => Trace::Value,
+            String | Tuple(_) | Cls { .. } => Trace::Pointer,
+            Union(_) => Trace::Ambiguous,
+            Variable(name) => self.trace(self.symbol_table.canonical_value(name).unwrap()),
+        }
+    }
+
+    /// the trace of the field holding the variable
+    fn field_trace(&self, var: &Symbol, ty: &mir::EbbTy) -> Trace {
+        match self.trace(ty) {
+            // the variable made by a constructor holds the known variant, or its box
+            Trace::Ambiguous => self
+                .variants
+                .get(var)
+                .map(|ty| match self.ebbty_to_lty(ty) {
+                    LTy::F32 | LTy::F64 | LTy::I64 | LTy::U64 => Trace::Pointer,
+                    _ => self.trace(ty),
+                })
+                .unwrap_or(Trace::Ambiguous),
+            trace => trace,
         }
     }
 
@@ -37,9 +73,10 @@ impl MIR2LIRPass {
             Unit => LTy::Unit,
             Char => LTy::U32,
             Int => LTy::I32,
+            Word => LTy::U32,
             Float => LTy::F64,
             Bool => LTy::I32,
-            Tuple(_) => LTy::Ptr,
+            String | Tuple(_) => LTy::Ptr,
             //FIXME
             Union(_) => LTy::Ptr,
             Cls { .. } => LTy::Ptr,
@@ -48,6 +85,29 @@ impl MIR2LIRPass {
         }
     }
 
+    fn host_ty(&self, ty: &mir::EbbTy) -> Option<HostTy> {
+        use crate::mir::EbbTy::*;
+        match ty {
+            String => Some(HostTy::String),
+            Cls { param, ret, .. } => Some(HostTy::Callback(
+                self.ebbty_to_lty(param),
+                self.ebbty_to_lty(ret),
+            )),
+            Variable(name) => self.host_ty(self.symbol_table.canonical_value(name).unwrap()),
+            _ => None,
+        }
+    }
+
+    /// the host type of the parameter of the closure type
+    fn param_host_ty(&self, ty: &mir::EbbTy) -> Option<HostTy> {
+        use crate::mir::EbbTy::*;
+        match ty {
+            Cls { param, .. } => self.host_ty(param),
+            Variable(name) => self.param_host_ty(self.symbol_table.canonical_value(name).unwrap()),
+            _ => None,
+        }
+    }
+
     pub fn trans_mir(&mut self, mir: mir::MIR) -> LIR {
         LIR(mir.0.into_iter().map(|f| self.trans_function(f)).collect())
     }
@@ -60,12 +120,14 @@ impl MIR2LIRPass {
             name,
             body,
             body_ty,
+            export,
         } = f;
         let nparams = body[0].params.len() as u32;
         let ret_ty = self.ebbty_to_lty(&body_ty);
         let mut regs = Vec::new();
         let mut id = 0;
         let mut blocks = Vec::new();
+        let mut names = Vec::new();
         {
             // limit the scope of new_reg
 
@@ -76,8 +138,14 @@ impl MIR2LIRPass {
                 reg
             };
 
-            let symbol_table = self.make_symbol_table(body.as_ref(), &mut new_reg);
+            let (symbol_table, host_tys, param_tys) =
+                self.make_symbol_table(body.as_ref(), &mut new_reg);
             let target_table = self.make_target_table(body.as_ref(), &symbol_table);
+            names.extend(
+                symbol_table
+                    .iter()
+                    .map(|(&var, reg)| (reg.1 as usize, var.clone())),
+            );
             macro_rules! reg {
                 ($var: expr) => {
                     symbol_table
@@ -90,14 +158,43 @@ impl MIR2LIRPass {
             for ebb in body.iter() {
                 let mut ops = Vec::new();
                 for op in ebb.body.iter() {
-                    debug!(target: "mir_to_lir", "op: {:?}", op);
+                    trace!("op: {:?}", op);
                     match op {
                         &m::Lit {
                             ref var, ref value, ..
                         } => match value {
                             &Literal::Char(c) => ops.push(ConstI32(reg!(var), c as u32)),
                             &Literal::Int(i) => ops.push(ConstI32(reg!(var), i as u32)),
+                            &Literal::Word(w) => ops.push(ConstU32(reg!(var), w)),
                             &Literal::Real(f) => ops.push(ConstF64(reg!(var), f as f64)),
+                            &Literal::String(ref s) => {
+                                let reg = reg!(var);
+                                let len = new_reg(LTy::I32);
+                                let word = new_reg(LTy::I32);
+                                let (module, fun) = ("webml-rt".to_string(), "string_alloc");
+                                self.extern_types.insert(
+                                    (module.clone(), fun.to_string()),
+                                    (vec![LTy::I32], LTy::Ptr, HostTypes::default()),
+                                );
+                                ops.push(ConstI32(len.clone(), s.len() as u32));
+                                ops.push(ExternCall(
+                                    reg.clone(),
+                                    module,
+                                    fun.to_string(),
+                                    vec![len],
+                                ));
+                                // the bytes follow the length. the payload is padded to 8 bytes,
+                                // so the last word may be written whole
+                                for (i, bytes) in s.as_bytes().chunks(4).enumerate() {
+                                    let mut le = [0; 4];
+                                    le[..bytes.len()].copy_from_slice(bytes);
+                                    ops.push(ConstI32(word.clone(), u32::from_le_bytes(le)));
+                                    ops.push(StoreI32(
+                                        Addr(reg.clone(), 4 + i as u32 * 4),
+                                        word.clone(),
+                                    ));
+                                }
+                            }
                         },
                         &m::Alias {
                             ref var,
@@ -128,6 +225,8 @@ impl MIR2LIRPass {
                         } => {
                             if ty == &mir::EbbTy::Int {
                                 ops.push(AddI32(reg!(var), reg!(l), reg!(r)));
+                            } else if ty == &mir::EbbTy::Word {
+                                ops.push(AddU32(reg!(var), reg!(l), reg!(r)));
                             } else {
                                 assert_eq!(ty, &mir::EbbTy::Float);
                                 ops.push(AddF64(reg!(var), reg!(l), reg!(r)));
@@ -141,6 +240,8 @@ impl MIR2LIRPass {
                         } => {
                             if ty == &mir::EbbTy::Int {
                                 ops.push(SubI32(reg!(var), reg!(l), reg!(r)));
+                            } else if ty == &mir::EbbTy::Word {
+                                ops.push(SubU32(reg!(var), reg!(l), reg!(r)));
                             } else {
                                 assert_eq!(ty, &mir::EbbTy::Float);
                                 ops.push(SubF64(reg!(var), reg!(l), reg!(r)));
@@ -154,6 +255,8 @@ impl MIR2LIRPass {
                         } => {
                             if ty == &mir::EbbTy::Int {
                                 ops.push(MulI32(reg!(var), reg!(l), reg!(r)));
+                            } else if ty == &mir::EbbTy::Word {
+                                ops.push(MulU32(reg!(var), reg!(l), reg!(r)));
                             } else {
                                 assert_eq!(ty, &mir::EbbTy::Float);
                                 ops.push(MulF64(reg!(var), reg!(l), reg!(r)));
@@ -274,12 +377,18 @@ impl MIR2LIRPass {
                         } => {
                             let reg = reg!(var);
 
+                            let layout = tuple
+                                .iter()
+                                .zip(tys)
+                                .enumerate()
+                                .map(|(i, (var, ty))| (i as u32 * 8, self.field_trace(var, ty)))
+                                .collect();
                             let tys: Vec<_> = tys.iter().map(|ty| self.ebbty_to_lty(ty)).collect();
                             // currently all the items are aligned to 8
                             let size: u32 = tys.iter().map(|_| 8).sum();
                             // let size: u32 = tys.iter().map(|ty| ty.size()).sum();
 
-                            ops.push(HeapAlloc(reg.clone(), I(size as i32), tys.clone()));
+                            ops.push(HeapAlloc(reg.clone(), I(size as i32), layout));
 
                             let mut acc = 0;
                             for (var, ty) in tuple.iter().zip(tys) {
@@ -350,20 +459,22 @@ impl MIR2LIRPass {
                             ref index,
                         } => {
                             let ty = &tys[*index as usize];
+                            self.variants.insert(var.clone(), ty.clone());
+                            // a union is a word, so the other values are boxed
                             #[allow(clippy::never_loop)]
                             loop {
                                 let ctor = match self.ebbty_to_lty(ty) {
-                                    LTy::F32 => MoveF32,
-                                    LTy::F64 => MoveF64,
-                                    LTy::I32 => MoveI32,
-                                    LTy::U32 => MoveU32,
-                                    LTy::I64 => MoveI64,
-                                    LTy::U64 => MoveU64,
-                                    LTy::Ptr => MoveI32,
-                                    LTy::FPtr => MoveI32,
-                                    LTy::Unit => MoveI32,
+                                    LTy::F32 => StoreF32,
+                                    LTy::F64 => StoreF64,
+                                    LTy::I64 => StoreI64,
+                                    LTy::U64 => StoreU64,
+                                    LTy::I32 | LTy::U32 | LTy::Ptr | LTy::FPtr | LTy::Unit => {
+                                        ops.push(MoveI32(reg!(var), reg!(variant)));
+                                        break;
+                                    }
                                 };
-                                ops.push(ctor(reg!(var), reg!(variant)));
+                                ops.push(HeapAlloc(reg!(var), I(8), vec![(0, Trace::Value)]));
+                                ops.push(ctor(Addr(reg!(var), 0), reg!(variant)));
                                 break;
                             }
                         }
@@ -373,25 +484,16 @@ impl MIR2LIRPass {
                             ref union,
                             ..
                         } => {
-                            #[allow(clippy::never_loop)]
-                            loop {
-                                let ctor = match self.ebbty_to_lty(ty) {
-                                    LTy::F32 => MoveF32,
-                                    LTy::F64 => MoveF64,
-                                    LTy::I32 => MoveI32,
-                                    LTy::U32 => MoveU32,
-                                    LTy::I64 => MoveI64,
-                                    LTy::U64 => MoveU64,
-                                    LTy::Ptr => MoveI32,
-                                    LTy::FPtr => MoveI32,
-                                    LTy::Unit =>
-                                    // do nothing
-                                    {
-                                        break
-                                    }
-                                };
-                                ops.push(ctor(reg!(var), reg!(union)));
-                                break;
+                            match self.ebbty_to_lty(ty) {
+                                LTy::F32 => ops.push(LoadF32(reg!(var), Addr(reg!(union), 0))),
+                                LTy::F64 => ops.push(LoadF64(reg!(var), Addr(reg!(union), 0))),
+                                LTy::I64 => ops.push(LoadI64(reg!(var), Addr(reg!(union), 0))),
+                                LTy::U64 => ops.push(LoadU64(reg!(var), Addr(reg!(union), 0))),
+                                LTy::I32 => ops.push(MoveI32(reg!(var), reg!(union))),
+                                LTy::U32 => ops.push(MoveU32(reg!(var), reg!(union))),
+                                LTy::Ptr | LTy::FPtr => ops.push(MoveI32(reg!(var), reg!(union))),
+                                // do nothing
+                                LTy::Unit => (),
                             }
                         }
 
@@ -413,11 +515,13 @@ impl MIR2LIRPass {
                                 .iter()
                                 .map(|&(ref ty, _)| self.ebbty_to_lty(ty).size())
                                 .sum::<u32>();
-                            let mut tys = vec![LTy::FPtr];
-                            for &(ref ty, _) in env.iter() {
-                                tys.push(self.ebbty_to_lty(ty));
+                            let mut layout = vec![(0, Trace::Value)];
+                            let mut offset = LTy::FPtr.size();
+                            for &(ref ty, ref var) in env.iter() {
+                                layout.push((offset, self.field_trace(var, ty)));
+                                offset += self.ebbty_to_lty(ty).size();
                             }
-                            ops.push(HeapAlloc(reg.clone(), I(size as i32), tys));
+                            ops.push(HeapAlloc(reg.clone(), I(size as i32), layout));
                             // FIXME: explicitly take fun pointer
                             ops.push(StoreFnPtr(Addr(reg.clone(), 0), fun.clone()));
                             let mut acc = LTy::FPtr.size();
@@ -462,11 +566,68 @@ impl MIR2LIRPass {
                             ref fun,
                             ref args,
                             ..
+                        } if module == "wasm" => {
+                            // the instructions of wasm, which are not imported
+                            let (dst, arg) = (reg!(var), reg!(args[0]));
+                            let arg2 = || reg!(args[1]);
+                            match fun.as_str() {
+                                "i32.and" => ops.push(AndU32(dst, arg, arg2())),
+                                "i32.or" => ops.push(OrU32(dst, arg, arg2())),
+                                "i32.xor" => ops.push(XorU32(dst, arg, arg2())),
+                                "i32.shl" => ops.push(ShlU32(dst, arg, arg2())),
+                                "i32.shr_u" => ops.push(ShrU32(dst, arg, arg2())),
+                                "i32.shr_s" => ops.push(ShrI32(dst, arg, arg2())),
+                                "i32.div_u" => ops.push(DivU32(dst, arg, arg2())),
+                                "i32.rem_u" => ops.push(ModU32(dst, arg, arg2())),
+                                "f64.floor" => ops.push(FloorF64(dst, arg)),
+                                "f64.ceil" => ops.push(CeilF64(dst, arg)),
+                                "f64.trunc" => ops.push(TruncF64(dst, arg)),
+                                "f64.nearest" => ops.push(NearestF64(dst, arg)),
+                                "f64.sqrt" => ops.push(SqrtF64(dst, arg)),
+                                // `convert` is the conversion between the types of the argument
+                                // and the result, e.g. of `char` to `int`, which may be no instruction
+                                "convert" | "f64.convert_i32_s" | "i32.trunc_f64_s" => {
+                                    ops.push(Convert(dst, arg))
+                                }
+                                _ => panic!("unknown wasm instruction: {}", fun),
+                            }
+                        }
+                        &m::ExternCall {
+                            ref var,
+                            ref ty,
+                            ref module,
+                            ref fun,
+                            ref args,
                         } => {
-                            let args = args.iter().map(|a| reg!(a)).collect();
+                            let suspends = self
+                                .async_imports
+                                .iter()
+                                .any(|(m, f)| m == module && f == fun);
+                            // the host passes the value to the continuation, the last argument
+                            let ret = if suspends {
+                                args.last().and_then(|k| param_tys.get(k)).cloned()
+                            } else {
+                                self.host_ty(ty)
+                            };
+                            let host_types = HostTypes {
+                                params: args
+                                    .iter()
+                                    .enumerate()
+                                    .filter_map(|(i, arg)| {
+                                        Some((i as u32, host_tys.get(arg)?.clone()))
+                                    })
+                                    .collect(),
+                                ret: ret.filter(|ty| *ty == HostTy::String),
+                                suspends,
+                            };
+                            let args = args.iter().map(|a| reg!(a)).collect::<Vec<_>>();
                             self.extern_types.insert(
                                 (module.to_string(), fun.to_string()),
-                                (vec![LTy::I32], LTy::Unit),
+                                (
+                                    args.iter().map(|arg| arg.0.clone()).collect(),
+                                    self.ebbty_to_lty(ty),
+                                    host_types,
+                                ),
                             );
                             ops.push(ExternCall(
                                 reg!(var),
@@ -487,118 +648,120 @@ impl MIR2LIRPass {
                                 None => ops.push(FunCall(reg!(var), fun.clone(), args)),
                             }
                         }
-                        &m::Branch {
-                            ref cond,
-                            ref clauses,
-                            ref default,
-                            ..
-                        } => {
-                            let mut clauses = clauses.clone();
-                            clauses.sort_by_key(|&(ref key, _, _)| *key);
-                            let default_label = match default.clone() {
-                                None => None,
-                                Some((label, _)) => {
-                                    let params = &target_table[&label];
-                                    assert_eq!(params.len(), 1);
-                                    let p = &params[0];
-                                    match p.0 {
-                                        LTy::Unit => {
-                                            // do nothing
-                                        }
-                                        LTy::I32 => ops.push(MoveI32(p.clone(), reg!(cond))),
-                                        LTy::U32 => ops.push(MoveU32(p.clone(), reg!(cond))),
-                                        LTy::I64 => ops.push(MoveI64(p.clone(), reg!(cond))),
-                                        LTy::U64 => ops.push(MoveU64(p.clone(), reg!(cond))),
-                                        LTy::F32 => ops.push(MoveF32(p.clone(), reg!(cond))),
-                                        LTy::F64 => ops.push(MoveF64(p.clone(), reg!(cond))),
-                                        LTy::Ptr => ops.push(MoveI32(p.clone(), reg!(cond))),
-                                        LTy::FPtr => ops.push(MoveI32(p.clone(), reg!(cond))),
-                                    };
-                                    Some(Label(label))
-                                }
-                            };
+                    }
+                }
+                match &ebb.term {
+                    &mir::Terminator::Branch {
+                        ref cond,
+                        ref clauses,
+                        ref default,
+                        ..
+                    } => {
+                        let mut clauses = clauses.clone();
+                        clauses.sort_by_key(|&(ref key, _, _)| *key);
+                        let default_label = match default.clone() {
+                            None => None,
+                            Some((label, _)) => {
+                                let params = &target_table[&label];
+                                assert_eq!(params.len(), 1);
+                                let p = &params[0];
+                                match p.0 {
+                                    LTy::Unit => {
+                                        // do nothing
+                                    }
+                                    LTy::I32 => ops.push(MoveI32(p.clone(), reg!(cond))),
+                                    LTy::U32 => ops.push(MoveU32(p.clone(), reg!(cond))),
+                                    LTy::I64 => ops.push(MoveI64(p.clone(), reg!(cond))),
+                                    LTy::U64 => ops.push(MoveU64(p.clone(), reg!(cond))),
+                                    LTy::F32 => ops.push(MoveF32(p.clone(), reg!(cond))),
+                                    LTy::F64 => ops.push(MoveF64(p.clone(), reg!(cond))),
+                                    LTy::Ptr => ops.push(MoveI32(p.clone(), reg!(cond))),
+                                    LTy::FPtr => ops.push(MoveI32(p.clone(), reg!(cond))),
+                                };
+                                Some(Label(label))
+                            }
+                        };
 
-                            if !clauses.is_empty()
-                                && clauses[0].0 == 0
-                                && clauses
-                                    .iter()
-                                    .enumerate()
-                                    .all(|(n, &(ref key, _, _))| n == (*key as usize))
-                            {
-                                // use jump table
-                                ops.push(JumpTableI32(
-                                    reg!(cond),
-                                    clauses
-                                        .into_iter()
-                                        .map(|(_, label, _)| Label(label))
-                                        .collect(),
-                                    default_label,
-                                ))
-                            } else {
-                                let cond = reg!(cond);
+                        if !clauses.is_empty()
+                            && clauses[0].0 == 0
+                            && clauses
+                                .iter()
+                                .enumerate()
+                                .all(|(n, &(ref key, _, _))| n == (*key as usize))
+                        {
+                            // use jump table
+                            ops.push(JumpTableI32(
+                                reg!(cond),
+                                clauses
+                                    .into_iter()
+                                    .map(|(_, label, _)| Label(label))
+                                    .collect(),
+                                default_label,
+                            ))
+                        } else {
+                            let cond = reg!(cond);
 
-                                match cond.0 {
-                                    LTy::I32 => {
-                                        let boolean = new_reg(LTy::I32);
-                                        let constant = new_reg(LTy::I32);
-                                        for (key, label, _) in clauses {
-                                            ops.push(ConstI32(constant.clone(), key as u32));
-                                            ops.push(EqI32(
-                                                boolean.clone(),
-                                                cond.clone(),
-                                                constant.clone(),
-                                            ));
-                                            ops.push(JumpIfI32(boolean.clone(), Label(label)))
-                                        }
+                            match cond.0 {
+                                LTy::I32 => {
+                                    let boolean = new_reg(LTy::I32);
+                                    let constant = new_reg(LTy::I32);
+                                    for (key, label, _) in clauses {
+                                        ops.push(ConstI32(constant.clone(), key as u32));
+                                        ops.push(EqI32(
+                                            boolean.clone(),
+                                            cond.clone(),
+                                            constant.clone(),
+                                        ));
+                                        ops.push(JumpIfI32(boolean.clone(), Label(label)))
                                     }
-                                    LTy::U32 => {
-                                        let boolean = new_reg(LTy::U32);
-                                        let constant = new_reg(LTy::U32);
-                                        for (key, label, _) in clauses {
-                                            ops.push(ConstU32(constant.clone(), key as u32));
-                                            ops.push(EqU32(
-                                                boolean.clone(),
-                                                cond.clone(),
-                                                constant.clone(),
-                                            ));
-                                            ops.push(JumpIfI32(boolean.clone(), Label(label)))
-                                        }
-                                    }
-                                    _ => panic!("internal error: branching currently supports only 32 bit types"),
                                 }
-                                if let Some(label) = default_label {
-                                    ops.push(Jump(label))
+                                LTy::U32 => {
+                                    let boolean = new_reg(LTy::U32);
+                                    let constant = new_reg(LTy::U32);
+                                    for (key, label, _) in clauses {
+                                        ops.push(ConstU32(constant.clone(), key as u32));
+                                        ops.push(EqU32(
+                                            boolean.clone(),
+                                            cond.clone(),
+                                            constant.clone(),
+                                        ));
+                                        ops.push(JumpIfI32(boolean.clone(), Label(label)))
+                                    }
                                 }
+                                _ => panic!("internal error: branching currently supports only 32 bit types"),
+                            }
+                            if let Some(label) = default_label {
+                                ops.push(Jump(label))
                             }
                         }
-                        &m::Jump {
-                            ref target,
-                            ref args,
-                            ..
-                        } => {
-                            let params = &target_table[target];
-                            for (p, a) in params.iter().zip(args) {
-                                match p.0 {
-                                    LTy::Unit => {
-                                        // do nothing
-                                    }
-                                    LTy::I32 => ops.push(MoveI32(p.clone(), reg!(a))),
-                                    LTy::U32 => ops.push(MoveU32(p.clone(), reg!(a))),
-                                    LTy::I64 => ops.push(MoveI64(p.clone(), reg!(a))),
-                                    LTy::U64 => ops.push(MoveU64(p.clone(), reg!(a))),
-                                    LTy::F32 => ops.push(MoveF32(p.clone(), reg!(a))),
-                                    LTy::F64 => ops.push(MoveF64(p.clone(), reg!(a))),
-                                    LTy::Ptr => ops.push(MoveI32(p.clone(), reg!(a))),
-                                    LTy::FPtr => ops.push(MoveI32(p.clone(), reg!(a))),
+                    }
+                    &mir::Terminator::Jump {
+                        ref target,
+                        ref args,
+                        ..
+                    } => {
+                        let params = &target_table[target];
+                        for (p, a) in params.iter().zip(args) {
+                            match p.0 {
+                                LTy::Unit => {
+                                    // do nothing
                                 }
+                                LTy::I32 => ops.push(MoveI32(p.clone(), reg!(a))),
+                                LTy::U32 => ops.push(MoveU32(p.clone(), reg!(a))),
+                                LTy::I64 => ops.push(MoveI64(p.clone(), reg!(a))),
+                                LTy::U64 => ops.push(MoveU64(p.clone(), reg!(a))),
+                                LTy::F32 => ops.push(MoveF32(p.clone(), reg!(a))),
+                                LTy::F64 => ops.push(MoveF64(p.clone(), reg!(a))),
+                                LTy::Ptr => ops.push(MoveI32(p.clone(), reg!(a))),
+                                LTy::FPtr => ops.push(MoveI32(p.clone(), reg!(a))),
                             }
-                            ops.push(Jump(Label(target.clone())))
                         }
-                        &m::Ret { ref value, ref ty } => match ty {
-                            mir::EbbTy::Unit => ops.push(Ret(None)),
-                            _ => ops.push(Ret(value.as_ref().map(|v| reg!(v)))),
-                        },
+                        ops.push(Jump(Label(target.clone())))
                     }
+                    &mir::Terminator::Ret { ref value, ref ty } => match ty {
+                        mir::EbbTy::Unit => ops.push(Ret(None)),
+                        _ => ops.push(Ret(value.as_ref().map(|v| reg!(v)))),
+                    },
                 }
                 blocks.push(Block {
                     name: Label(ebb.name.clone()),
@@ -608,13 +771,19 @@ impl MIR2LIRPass {
         }
 
         let regs = regs.into_iter().map(|r| r.0).collect::<Vec<_>>();
+        let mut reg_names = vec![None; regs.len()];
+        for (reg, var) in names {
+            reg_names[reg] = Some(var);
+        }
 
         Function {
             name,
             nparams,
             regs,
+            reg_names,
             ret_ty,
             body: blocks,
+            export,
         }
     }
 
@@ -622,11 +791,19 @@ impl MIR2LIRPass {
         &self,
         body: &'a [mir::EBB],
         mut new_reg: F,
-    ) -> HashMap<&'a Symbol, Reg>
+    ) -> (
+        HashMap<&'a Symbol, Reg>,
+        HashMap<&'a Symbol, HostTy>,
+        HashMap<&'a Symbol, HostTy>,
+    )
     where
         F: FnMut(LTy) -> Reg,
     {
         let mut table = HashMap::new();
+        // the variables of the values the host converts, which are plain pointers in the LIR
+        let mut host_tys = HashMap::new();
+        // the host types of the parameters of the closures, which the host passes to them
+        let mut param_tys = HashMap::new();
         macro_rules! intern {
             ($ty: expr, $var: expr) => {{
                 if table.get(&$var).is_none() {
@@ -634,15 +811,26 @@ impl MIR2LIRPass {
                 };
             }};
         }
+        macro_rules! intern_typed {
+            ($ty: expr, $var: expr) => {{
+                if let Some(host_ty) = self.host_ty($ty) {
+                    host_tys.insert($var, host_ty);
+                }
+                if let Some(host_ty) = self.param_host_ty($ty) {
+                    param_tys.insert($var, host_ty);
+                }
+                intern!(self.ebbty_to_lty($ty), $var);
+            }};
+        }
 
         // allocate function params first
         for &(ref ty, ref param) in &body[0].params {
-            intern!(self.ebbty_to_lty(ty), param);
+            intern_typed!(ty, param);
         }
 
         for ebb in body {
             for &(ref ty, ref param) in &ebb.params {
-                intern!(self.ebbty_to_lty(ty), param);
+                intern_typed!(ty, param);
             }
 
             for op in ebb.body.iter() {
@@ -701,18 +889,31 @@ impl MIR2LIRPass {
                     | &mir::Op::Call {
                         ref var, ref ty, ..
                     } => {
-                        intern!(self.ebbty_to_lty(ty), var);
+                        intern_typed!(ty, var);
                     }
-                    &mir::Op::Tuple { ref var, .. } | &mir::Op::Closure { ref var, .. } => {
+                    &mir::Op::Tuple { ref var, .. } => intern!(LTy::Ptr, var),
+                    &mir::Op::Closure {
+                        ref var,
+                        ref param_ty,
+                        ref ret_ty,
+                        ..
+                    } => {
+                        let callback = HostTy::Callback(
+                            self.ebbty_to_lty(param_ty),
+                            self.ebbty_to_lty(ret_ty),
+                        );
+                        host_tys.insert(var, callback);
+                        if let Some(host_ty) = self.host_ty(param_ty) {
+                            param_tys.insert(var, host_ty);
+                        }
                         intern!(LTy::Ptr, var);
                     }
                     &mir::Op::Union { ref var, .. } => intern!(LTy::Ptr, var),
-                    _ => (),
                 }
             }
         }
 
-        table
+        (table, host_tys, param_tys)
     }
 
     fn make_target_table<'a>(
@@ -739,9 +940,9 @@ impl<E> Pass<(mir::SymbolTable, mir::MIR), E> for MIR2LIR {
     fn trans(
         &mut self,
         (symbol_table, mir): (mir::SymbolTable, mir::MIR),
-        _: &Config,
+        config: &Config,
     ) -> ::std::result::Result<Self::Target, E> {
-        let mut pass = self.generate_pass(symbol_table);
+        let mut pass = self.generate_pass(symbol_table, config.async_imports.clone());
         let lir = pass.trans_mir(mir);
         let types = pass.extern_types.drain().collect();
         Ok((types, lir))
@@ -1,6 +1,8 @@
+pub mod local_alloc;
 pub mod mir2lir;
 pub mod pp;
 
+pub use self::local_alloc::LocalAlloc;
 pub use self::mir2lir::MIR2LIR;
 use crate::prim::*;
 use std::collections::HashMap;
@@ -39,7 +41,40 @@ impl LTy {
     }
 }
 
-pub type ExternTypes = HashMap<(String, String), (Vec<LTy>, LTy)>;
+/// a value the LIR holds as a pointer, which the host converts to its own
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HostTy {
+    String,
+    /// a closure, which the host calls as a function of the parameter to the result
+    Callback(LTy, LTy),
+}
+
+/// the parameters and the result of an imported function which the host converts
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HostTypes {
+    /// the indices of the parameters with their types
+    pub params: Vec<(u32, HostTy)>,
+    /// only strings are returned from the host
+    pub ret: Option<HostTy>,
+    /// whether the host function returns a promise and takes the continuation as the last
+    /// parameter, which it calls with the value of the promise typed `ret`
+    pub suspends: bool,
+}
+
+pub type ExternTypes = HashMap<(String, String), (Vec<LTy>, LTy, HostTypes)>;
+
+/// the fields of a heap object with their offsets in bytes
+pub type Layout = Vec<(u32, Trace)>;
+
+/// what the garbage collector finds in a field of a heap object
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trace {
+    Value,
+    Pointer,
+    /// a union whose variant is unknown, holding either a pointer or a value.
+    /// the collector must not move the object it may point to
+    Ambiguous,
+}
 
 #[derive(Debug, Clone)]
 pub struct Reg(pub LTy, pub u32);
@@ -71,8 +106,11 @@ pub struct Function {
     pub name: Symbol,
     pub nparams: u32,
     pub regs: Vec<LTy>,
+    /// the variables the registers hold, for debug info
+    pub reg_names: Vec<Option<Symbol>>,
     pub ret_ty: LTy,
     pub body: Vec<Block>,
+    pub export: Option<Export>,
 }
 
 #[derive(Debug, Clone)]
@@ -96,6 +134,8 @@ pub enum Op {
     GeI32(Reg, Reg, Reg),
     LtI32(Reg, Reg, Reg),
     LeI32(Reg, Reg, Reg),
+    /// shifts right with the sign
+    ShrI32(Reg, Reg, Reg),
     StoreI32(Addr, Reg),
     LoadI32(Reg, Addr),
 
@@ -115,6 +155,12 @@ pub enum Op {
     GeU32(Reg, Reg, Reg),
     LtU32(Reg, Reg, Reg),
     LeU32(Reg, Reg, Reg),
+    AndU32(Reg, Reg, Reg),
+    OrU32(Reg, Reg, Reg),
+    XorU32(Reg, Reg, Reg),
+    ShlU32(Reg, Reg, Reg),
+    /// shifts right with zeros
+    ShrU32(Reg, Reg, Reg),
     StoreU32(Addr, Reg),
     LoadU32(Reg, Addr),
 
@@ -179,9 +225,21 @@ pub enum Op {
     LeF64(Reg, Reg, Reg),
     StoreF64(Addr, Reg),
     LoadF64(Reg, Addr),
+    FloorF64(Reg, Reg),
+    CeilF64(Reg, Reg),
+    TruncF64(Reg, Reg),
+    /// rounds to the nearest integer, to the even one on ties
+    NearestF64(Reg, Reg),
+    SqrtF64(Reg, Reg),
+
+    /// converts the value of the numeric type of the second register to the type of the first.
+    /// floats are truncated toward zero when converted to integers
+    Convert(Reg, Reg),
+    /// reinterprets the bits of the value as the type of the same width
+    Reinterpret(Reg, Reg),
 
-    HeapAlloc(Reg, Value, Vec<LTy>),
-    StackAlloc(Reg, u32, Vec<LTy>),
+    HeapAlloc(Reg, Value, Layout),
+    StackAlloc(Reg, u32, Layout),
 
     StoreFnPtr(Addr, Symbol),
     ExternCall(Reg, String, String, Vec<Reg>),
@@ -192,19 +250,155 @@ pub enum Op {
     Ret(Option<Reg>),
 }
 
-impl Block {
+// the defined register and the used registers of the operation,
+// shared by `Op::def_uses` and `Op::def_uses_mut`
+macro_rules! def_uses {
+    ($op: expr $(, $mut: tt)?) => {{
+        use self::Op::*;
+        match $op {
+            ConstI32(def, ..)
+            | ConstU32(def, ..)
+            | ConstI64(def, ..)
+            | ConstU64(def, ..)
+            | ConstF32(def, ..)
+            | ConstF64(def, ..)
+            | StackAlloc(def, ..) => (Some(def), vec![]),
+            MoveI32(def, reg)
+            | MoveU32(def, reg)
+            | MoveI64(def, reg)
+            | MoveU64(def, reg)
+            | MoveF32(def, reg)
+            | MoveF64(def, reg)
+            | FloorF64(def, reg)
+            | CeilF64(def, reg)
+            | TruncF64(def, reg)
+            | NearestF64(def, reg)
+            | SqrtF64(def, reg)
+            | Convert(def, reg)
+            | Reinterpret(def, reg) => (Some(def), vec![reg]),
+            AddI32(def, reg1, reg2)
+            | SubI32(def, reg1, reg2)
+            | MulI32(def, reg1, reg2)
+            | DivI32(def, reg1, reg2)
+            | ModI32(def, reg1, reg2)
+            | EqI32(def, reg1, reg2)
+            | NeqI32(def, reg1, reg2)
+            | GtI32(def, reg1, reg2)
+            | GeI32(def, reg1, reg2)
+            | LtI32(def, reg1, reg2)
+            | LeI32(def, reg1, reg2)
+            | ShrI32(def, reg1, reg2)
+            | AddU32(def, reg1, reg2)
+            | SubU32(def, reg1, reg2)
+            | MulU32(def, reg1, reg2)
+            | DivU32(def, reg1, reg2)
+            | ModU32(def, reg1, reg2)
+            | EqU32(def, reg1, reg2)
+            | NeqU32(def, reg1, reg2)
+            | GtU32(def, reg1, reg2)
+            | GeU32(def, reg1, reg2)
+            | LtU32(def, reg1, reg2)
+            | LeU32(def, reg1, reg2)
+            | AndU32(def, reg1, reg2)
+            | OrU32(def, reg1, reg2)
+            | XorU32(def, reg1, reg2)
+            | ShlU32(def, reg1, reg2)
+            | ShrU32(def, reg1, reg2)
+            | AddI64(def, reg1, reg2)
+            | SubI64(def, reg1, reg2)
+            | MulI64(def, reg1, reg2)
+            | DivI64(def, reg1, reg2)
+            | ModI64(def, reg1, reg2)
+            | EqI64(def, reg1, reg2)
+            | NeqI64(def, reg1, reg2)
+            | GtI64(def, reg1, reg2)
+            | GeI64(def, reg1, reg2)
+            | LtI64(def, reg1, reg2)
+            | LeI64(def, reg1, reg2)
+            | AddU64(def, reg1, reg2)
+            | SubU64(def, reg1, reg2)
+            | MulU64(def, reg1, reg2)
+            | DivU64(def, reg1, reg2)
+            | ModU64(def, reg1, reg2)
+            | EqU64(def, reg1, reg2)
+            | NeqU64(def, reg1, reg2)
+            | GtU64(def, reg1, reg2)
+            | GeU64(def, reg1, reg2)
+            | LtU64(def, reg1, reg2)
+            | LeU64(def, reg1, reg2)
+            | AddF32(def, reg1, reg2)
+            | SubF32(def, reg1, reg2)
+            | MulF32(def, reg1, reg2)
+            | DivF32(def, reg1, reg2)
+            | EqF32(def, reg1, reg2)
+            | NeqF32(def, reg1, reg2)
+            | GtF32(def, reg1, reg2)
+            | GeF32(def, reg1, reg2)
+            | LtF32(def, reg1, reg2)
+            | LeF32(def, reg1, reg2)
+            | AddF64(def, reg1, reg2)
+            | SubF64(def, reg1, reg2)
+            | MulF64(def, reg1, reg2)
+            | DivF64(def, reg1, reg2)
+            | EqF64(def, reg1, reg2)
+            | NeqF64(def, reg1, reg2)
+            | GtF64(def, reg1, reg2)
+            | GeF64(def, reg1, reg2)
+            | LtF64(def, reg1, reg2)
+            | LeF64(def, reg1, reg2) => (Some(def), vec![reg1, reg2]),
+            StoreI32(addr, reg)
+            | StoreU32(addr, reg)
+            | StoreI64(addr, reg)
+            | StoreU64(addr, reg)
+            | StoreF32(addr, reg)
+            | StoreF64(addr, reg) => (None, vec![&$($mut)? addr.0, reg]),
+            LoadI32(def, addr)
+            | LoadU32(def, addr)
+            | LoadI64(def, addr)
+            | LoadU64(def, addr)
+            | LoadF32(def, addr)
+            | LoadF64(def, addr) => (Some(def), vec![&$($mut)? addr.0]),
+            HeapAlloc(def, Value::R(reg), _) => (Some(def), vec![reg]),
+            HeapAlloc(def, Value::I(_), _) => (Some(def), vec![]),
+            StoreFnPtr(addr, _) => (None, vec![&$($mut)? addr.0]),
+            JumpIfI32(reg, _) | JumpTableI32(reg, ..) => (None, vec![reg]),
+            ExternCall(def, _, _, args) | FunCall(def, _, args) => {
+                (Some(def), args.into_iter().collect())
+            }
+            ClosureCall(def, fun, args) => {
+                (Some(def), ::std::iter::once(fun).chain(args).collect())
+            }
+            Ret(reg) => (None, reg.into_iter().collect()),
+            Jump(_) | Unreachable => (None, vec![]),
+        }
+    }};
+}
+
+impl Op {
     pub fn branches(&self) -> Vec<&Label> {
         use self::Op::*;
-        self.body
-            .iter()
-            .flat_map(|op| match *op {
-                Jump(ref label) => vec![label],
-                JumpIfI32(_, ref label) => vec![label],
-                JumpTableI32(_, ref labels, ref default) => {
-                    labels.iter().chain(default.iter()).collect()
-                }
-                _ => vec![],
-            })
-            .collect()
+        match *self {
+            Jump(ref label) => vec![label],
+            JumpIfI32(_, ref label) => vec![label],
+            JumpTableI32(_, ref labels, ref default) => {
+                labels.iter().chain(default.iter()).collect()
+            }
+            _ => vec![],
+        }
+    }
+
+    /// the register the operation writes to and the registers it reads
+    pub fn def_uses(&self) -> (Option<&Reg>, Vec<&Reg>) {
+        def_uses!(self)
+    }
+
+    pub fn def_uses_mut(&mut self) -> (Option<&mut Reg>, Vec<&mut Reg>) {
+        def_uses!(self, mut)
+    }
+}
+
+impl Block {
+    pub fn branches(&self) -> Vec<&Label> {
+        self.body.iter().flat_map(Op::branches).collect()
     }
 }
@@ -20,6 +20,9 @@ impl PP for LIR {
 impl PP for Function {
     fn pp<W: io::Write>(&self, w: &mut W, indent: usize) -> io::Result<()> {
         let indent = indent + 4;
+        if let Some(export) = &self.export {
+            write!(w, "export \"{}\" ", export.name)?;
+        }
         write!(w, "fun ")?;
         self.name.pp(w, 0)?;
         write!(w, ": (")?;
@@ -327,6 +330,27 @@ impl PP for Op {
                 write!(w, " <= ")?;
                 r3.pp(w, indent)?;
             }
+            AndU32(r1, r2, r3)
+            | OrU32(r1, r2, r3)
+            | XorU32(r1, r2, r3)
+            | ShlU32(r1, r2, r3)
+            | ShrU32(r1, r2, r3)
+            | ShrI32(r1, r2, r3) => {
+                r1.pp(w, indent)?;
+                write!(w, ": ")?;
+                r1.0.pp(w, indent)?;
+                write!(w, " <- ")?;
+                r2.pp(w, indent)?;
+                match self {
+                    AndU32(..) => write!(w, " andb ")?,
+                    OrU32(..) => write!(w, " orb ")?,
+                    XorU32(..) => write!(w, " xorb ")?,
+                    ShlU32(..) => write!(w, " << ")?,
+                    ShrU32(..) => write!(w, " >> ")?,
+                    _ => write!(w, " ~>> ")?,
+                }
+                r3.pp(w, indent)?;
+            }
             ConstF32(reg, i) => {
                 reg.pp(w, indent)?;
                 write!(w, ": ")?;
@@ -339,6 +363,27 @@ impl PP for Op {
                 reg.0.pp(w, indent)?;
                 write!(w, " <- {}", i)?;
             }
+            Convert(r1, r2)
+            | Reinterpret(r1, r2)
+            | FloorF64(r1, r2)
+            | CeilF64(r1, r2)
+            | TruncF64(r1, r2)
+            | NearestF64(r1, r2)
+            | SqrtF64(r1, r2) => {
+                r1.pp(w, indent)?;
+                write!(w, ": ")?;
+                r1.0.pp(w, indent)?;
+                match self {
+                    Convert(..) => write!(w, " <- convert ")?,
+                    Reinterpret(..) => write!(w, " <- reinterpret ")?,
+                    FloorF64(..) => write!(w, " <- floor ")?,
+                    CeilF64(..) => write!(w, " <- ceil ")?,
+                    TruncF64(..) => write!(w, " <- trunc ")?,
+                    NearestF64(..) => write!(w, " <- nearest ")?,
+                    _ => write!(w, " <- sqrt ")?,
+                }
+                r2.pp(w, indent)?;
+            }
             HeapAlloc(reg, value, tys) => {
                 reg.pp(w, indent)?;
                 write!(w, ": ")?;
@@ -1,9 +1,58 @@
-use clap::{app_from_crate, crate_authors, crate_description, crate_name, crate_version, Arg};
+use clap::{
+    app_from_crate, crate_authors, crate_description, crate_name, crate_version, App, AppSettings,
+    Arg, ArgMatches, SubCommand,
+};
 use std::collections::HashSet;
+use std::env;
 use std::fs;
 use std::io::{self, prelude::*};
-use std::path::Path;
-use webml::{compile_str, Config};
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+use std::thread;
+use std::time::Duration;
+use tracing_subscriber::fmt::format::FmtSpan;
+use webml::{
+    backend, compile, eval, fmt, Collector, CompileError, Config, Emit, EmitFormat, FormatConfig,
+    Incremental, Memory, OptLevel, Overflow, Prelude, Project, Target, Verbosity,
+};
+
+/// how often `watch` looks for the modification of the file
+const WATCH_INTERVAL: Duration = Duration::from_millis(300);
+
+/// runs the program and its JavaScript loader on Node.js
+const RUN_JS: &str = r#"
+import {readFileSync} from "fs";
+import {pathToFileURL} from "url";
+const [glue, runtime, program] = process.argv.slice(1);
+const {instantiate, printProfile, PROFILE} = await import(pathToFileURL(glue));
+let exports;
+try {
+    exports = await instantiate({}, {runtime: readFileSync(runtime), program: readFileSync(program)});
+} catch (error) {
+    // uncaught exceptions and traps, at the site the program recorded
+    console.error(`error: ${error.message}`);
+    process.exit(1);
+}
+if (PROFILE.length !== 0) {
+    printProfile(exports);
+}
+"#;
+
+/// runs the program with the WASI implementation of Node.js
+const RUN_WASI: &str = r#"
+import {readFileSync} from "fs";
+import {WASI} from "wasi";
+const [runtime, program] = process.argv.slice(1);
+const wasi = new WASI({version: "preview1"});
+const rt = await WebAssembly.instantiate(readFileSync(runtime), {
+    imports: {print: (x) => console.log(x)},
+});
+const {instance} = await WebAssembly.instantiate(readFileSync(program), {
+    "webml-rt": rt.instance.exports,
+    wasi_snapshot_preview1: wasi.wasiImport,
+});
+wasi.start(instance);
+"#;
 
 fn read_and_append_to_string(path: impl AsRef<Path>, buf: &mut String) -> io::Result<usize> {
     let file = fs::File::open(path)?;
@@ -11,27 +60,260 @@ fn read_and_append_to_string(path: impl AsRef<Path>, buf: &mut String) -> io::Re
     input.read_to_string(buf)
 }
 
-fn main() {
-    env_logger::init();
-    let matches = app_from_crate!()
-        .arg(
-            Arg::with_name("PRINT_IR")
-                .long("print-ir")
-                .help("print the output of IR")
-                .value_name("IR")
-                .takes_value(true)
-                .multiple(true),
-        )
-        .arg(
-            Arg::with_name("INPUT")
-                .help("file to compile")
-                .required(true),
-        )
-        .get_matches();
+/// the source of `Config::prelude`
+fn load_prelude(config: &Config) -> String {
+    config
+        .prelude
+        .load()
+        .unwrap_or_else(|e| exit_with(format!("failed to read the prelude: {}", e)))
+}
+
+/// reads the files of the program, after the prelude
+fn load_project(matches: &ArgMatches, config: &Config) -> Project {
+    let mut project = Project::new(load_prelude(config));
+    for path in matches.values_of("INPUT").into_iter().flatten() {
+        if let Err(e) = project.load(path) {
+            exit_with(format!("{}: {}", path, e))
+        }
+    }
+    project
+}
+
+/// reads the program with the prelude prepended
+fn read_program(path: impl AsRef<Path>, config: &Config) -> String {
+    let mut input = load_prelude(config);
+    read_and_append_to_string(path, &mut input).expect("failed to load file");
+    input
+}
+
+fn exit_with(e: impl std::fmt::Display) -> ! {
+    eprintln!("error: {}", e);
+    process::exit(1)
+}
+
+/// compiles the program. returns `None` when the compilation is stopped by `--stop-after`
+fn compile_program(project: &Project, config: &Config) -> Option<backend::Module> {
+    match project.compile(config) {
+        Ok(module) => Some(module),
+        Err(CompileError::Stopped { .. }) => None,
+        Err(e) => exit_with(e),
+    }
+}
+
+/// the options of the compilation, shared by the subcommands which compile the program
+fn compile_options<'a, 'b>(app: App<'a, 'b>) -> App<'a, 'b> {
+    let app = prelude_options(app);
+    app.arg(
+        Arg::with_name("PRINT_IR")
+            .long("print-ir")
+            .help("print the output of IR")
+            .value_name("IR")
+            .takes_value(true)
+            .multiple(true),
+    )
+    .arg(
+        Arg::with_name("ENABLE_PASS")
+            .long("enable-pass")
+            .help("run the optional pass")
+            .value_name("PASS")
+            .takes_value(true)
+            .multiple(true),
+    )
+    .arg(
+        Arg::with_name("DISABLE_PASS")
+            .long("disable-pass")
+            .help("skip the pass")
+            .value_name("PASS")
+            .takes_value(true)
+            .multiple(true),
+    )
+    .arg(
+        Arg::with_name("STOP_AFTER")
+            .long("stop-after")
+            .help("stop compilation after the pass")
+            .value_name("PASS")
+            .takes_value(true),
+    )
+    .arg(
+        Arg::with_name("EMIT")
+            .long("emit")
+            .help("print the IR to stdout")
+            .value_name("IR")
+            .takes_value(true)
+            .multiple(true)
+            .possible_values(Emit::NAMES),
+    )
+    .arg(
+        Arg::with_name("EMIT_FORMAT")
+            .long("emit-format")
+            .help("format to print the IR in")
+            .value_name("FORMAT")
+            .takes_value(true)
+            .possible_values(EmitFormat::NAMES),
+    )
+    .arg(
+        Arg::with_name("VERBOSITY")
+            .long("verbosity")
+            .help("log the passes to stderr, or more of the pipeline")
+            .value_name("LEVEL")
+            .takes_value(true)
+            .possible_values(Verbosity::NAMES),
+    )
+    .arg(
+        Arg::with_name("OPT_LEVEL")
+            .short("O")
+            .help("optimization level. `s` optimizes for the size of the module")
+            .value_name("LEVEL")
+            .takes_value(true)
+            .possible_values(OptLevel::NAMES),
+    )
+    .arg(
+        Arg::with_name("INLINE_THRESHOLD")
+            .long("inline-threshold")
+            .help("inline the functions up to the size, instead of the threshold of the level")
+            .value_name("SIZE")
+            .takes_value(true),
+    )
+    .arg(
+        Arg::with_name("TARGET")
+            .long("target")
+            .help("host environment to run the module in")
+            .value_name("TARGET")
+            .takes_value(true)
+            .possible_values(Target::NAMES),
+    )
+    .arg(
+        Arg::with_name("GC")
+            .long("gc")
+            .help("garbage collector of the runtime")
+            .value_name("GC")
+            .takes_value(true)
+            .possible_values(Collector::NAMES),
+    )
+    .arg(
+        Arg::with_name("OVERFLOW")
+            .long("overflow")
+            .help("whether the overflowing int arithmetic wraps or raises Overflow")
+            .value_name("OVERFLOW")
+            .takes_value(true)
+            .possible_values(Overflow::NAMES),
+    )
+    .arg(
+        Arg::with_name("PATTERN_GUARDS")
+            .long("pattern-guards")
+            .help("accept the guards of the case clauses, `pat when expr => expr`"),
+    )
+    .arg(
+        Arg::with_name("INITIAL_MEMORY")
+            .long("initial-memory")
+            .help("initial size of the linear memory in 64KiB pages")
+            .value_name("PAGES")
+            .takes_value(true),
+    )
+    .arg(
+        Arg::with_name("MAXIMUM_MEMORY")
+            .long("maximum-memory")
+            .help("maximum size of the linear memory in 64KiB pages")
+            .value_name("PAGES")
+            .takes_value(true),
+    )
+    .arg(
+        Arg::with_name("NO_MEMORY_GROW")
+            .long("no-memory-grow")
+            .help("do not expand the heap beyond the initial memory"),
+    )
+    .arg(
+        Arg::with_name("EXPORT")
+            .long("export")
+            .help("export the top level function from the module")
+            .value_name("FUNCTION")
+            .takes_value(true)
+            .multiple(true),
+    )
+    .arg(
+        Arg::with_name("ASYNC_IMPORT")
+            .long("async-import")
+            .help("wait for the promise the imported function returns, with CPS conversion")
+            .value_name("MODULE.NAME")
+            .takes_value(true)
+            .multiple(true),
+    )
+    .arg(
+        Arg::with_name("PROFILE")
+            .long("profile")
+            .help("count the calls and the cycles of the functions. `run` prints them"),
+    )
+    .arg(
+        Arg::with_name("CACHE_DIR")
+            .long("cache-dir")
+            .help("reuse the modules compiled before, kept in the directory")
+            .value_name("DIR")
+            .takes_value(true),
+    )
+}
+
+/// the declarations the program starts from, shared by the subcommands which read the program
+fn prelude_options<'a, 'b>(app: App<'a, 'b>) -> App<'a, 'b> {
+    app.arg(
+        Arg::with_name("PRELUDE")
+            .long("prelude")
+            .help("file to read the prelude from, instead of the builtin one")
+            .value_name("FILE")
+            .takes_value(true),
+    )
+    .arg(
+        Arg::with_name("NO_PRELUDE")
+            .long("no-prelude")
+            .help("compile the program without the prelude")
+            .conflicts_with("PRELUDE"),
+    )
+}
+
+/// where to write the compiled module, shared by the subcommands which write it
+fn output_options<'a, 'b>(app: App<'a, 'b>) -> App<'a, 'b> {
+    app.arg(
+        Arg::with_name("OUTPUT")
+            .short("o")
+            .long("output")
+            .help("file to write the module to")
+            .value_name("FILE")
+            .takes_value(true)
+            .default_value("out.wasm"),
+    )
+    .arg(
+        Arg::with_name("JS")
+            .long("js")
+            .help("also write the JavaScript loader next to the module"),
+    )
+}
+
+fn runtime_option<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("RUNTIME")
+        .long("runtime")
+        .help("the built webml-rt module")
+        .value_name("PATH")
+        .takes_value(true)
+        .default_value(backend::js::RUNTIME_URL)
+}
+
+fn input_argument<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("INPUT")
+        .help("files to compile in order, or .mlb files listing them")
+        .required(true)
+        .multiple(true)
+}
+
+/// prints the logs of the pipeline to stderr, with the time each pass takes
+fn init_tracing(verbosity: Verbosity) {
+    // the subcommands running the compilation repeatedly initialize it only once
+    let _ = tracing_subscriber::fmt()
+        .with_max_level(verbosity.level_filter())
+        .with_span_events(FmtSpan::CLOSE)
+        .with_writer(io::stderr)
+        .try_init();
+}
 
-    let filename = matches
-        .value_of("INPUT")
-        .unwrap_or("ml_example/example1.sml");
+fn config(matches: &ArgMatches) -> Config {
     let pretty_print_ir = matches
         .values_of("PRINT_IR")
         .into_iter()
@@ -39,14 +321,421 @@ fn main() {
         .map(|s| s.to_string())
         .collect::<HashSet<String>>();
 
-    let config = Config {
+    let passes = |name| {
+        matches
+            .values_of(name)
+            .into_iter()
+            .flatten()
+            .map(|s| s.to_string())
+            .collect::<HashSet<String>>()
+    };
+
+    let pages = |name| {
+        matches
+            .value_of(name)
+            .map(|s| s.parse::<u32>().expect("number of pages expected"))
+    };
+    let verbosity = matches
+        .value_of("VERBOSITY")
+        .map(|s| s.parse().unwrap())
+        .unwrap_or_default();
+    init_tracing(verbosity);
+
+    let default_memory = Memory::default();
+    let memory = Memory {
+        initial_pages: pages("INITIAL_MEMORY").unwrap_or(default_memory.initial_pages),
+        maximum_pages: pages("MAXIMUM_MEMORY"),
+        grow: !matches.is_present("NO_MEMORY_GROW"),
+    };
+
+    Config {
         pretty_print_ir,
+        enabled_passes: passes("ENABLE_PASS"),
+        disabled_passes: passes("DISABLE_PASS"),
+        stop_after: matches.value_of("STOP_AFTER").map(|s| s.to_string()),
+        emit: matches
+            .values_of("EMIT")
+            .into_iter()
+            .flatten()
+            .map(|s| s.parse().unwrap())
+            .collect(),
+        emit_format: matches
+            .value_of("EMIT_FORMAT")
+            .map(|s| s.parse().unwrap())
+            .unwrap_or_default(),
+        verbosity,
+        opt_level: matches
+            .value_of("OPT_LEVEL")
+            .map(|s| s.parse().unwrap())
+            .unwrap_or_default(),
+        inline_threshold: matches
+            .value_of("INLINE_THRESHOLD")
+            .map(|s| s.parse().expect("size of the function expected")),
+        target: matches
+            .value_of("TARGET")
+            .map(|s| s.parse().unwrap())
+            .unwrap_or_default(),
+        collector: matches
+            .value_of("GC")
+            .map(|s| s.parse().unwrap())
+            .unwrap_or_default(),
+        overflow: matches
+            .value_of("OVERFLOW")
+            .map(|s| s.parse().unwrap())
+            .unwrap_or_default(),
+        pattern_guards: matches.is_present("PATTERN_GUARDS"),
+        memory,
+        exports: matches
+            .values_of("EXPORT")
+            .into_iter()
+            .flatten()
+            .map(|s| s.to_string())
+            .collect(),
+        async_imports: matches
+            .values_of("ASYNC_IMPORT")
+            .into_iter()
+            .flatten()
+            .map(|s| {
+                let mut names = s.rsplitn(2, '.');
+                let name = names.next().unwrap().to_string();
+                let module = names.next().expect("MODULE.NAME expected").to_string();
+                (module, name)
+            })
+            .collect(),
+        profile: matches.is_present("PROFILE"),
+        cache_dir: matches.value_of("CACHE_DIR").map(PathBuf::from),
+        prelude: prelude(matches),
         ..Default::default()
+    }
+}
+
+/// the prelude of `--prelude` and `--no-prelude`
+fn prelude(matches: &ArgMatches) -> Prelude {
+    if matches.is_present("NO_PRELUDE") {
+        Prelude::None
+    } else {
+        matches
+            .value_of("PRELUDE")
+            .map(|path| Prelude::File(PathBuf::from(path)))
+            .unwrap_or_default()
+    }
+}
+
+/// a compiled program written to a temporary directory to be run on Node.js
+struct Program {
+    dir: PathBuf,
+    target: Target,
+}
+
+impl Program {
+    fn write(module: &backend::Module, config: &Config) -> io::Result<Self> {
+        let dir = env::temp_dir().join(format!("webml-{}", process::id()));
+        fs::create_dir_all(&dir)?;
+        let mut code = Vec::new();
+        module.dump(&mut code);
+        fs::write(dir.join("program.wasm"), code)?;
+        fs::write(
+            dir.join("program.mjs"),
+            backend::generate_glue(&module.interface, "program.wasm"),
+        )?;
+        Ok(Program {
+            dir,
+            target: config.target,
+        })
+    }
+
+    fn command(&self, runtime: &str) -> Command {
+        if !Path::new(runtime).exists() {
+            exit_with(format!(
+                "runtime {} not found. build webml-rt or pass --runtime",
+                runtime
+            ));
+        }
+        let program = self.dir.join("program.wasm");
+        let mut command = Command::new("node");
+        command.args(["--no-warnings", "--input-type=module", "-e"]);
+        match self.target {
+            Target::Js => command
+                .arg(RUN_JS)
+                .arg(self.dir.join("program.mjs"))
+                .arg(runtime)
+                .arg(program),
+            Target::Wasi => command.arg(RUN_WASI).arg(runtime).arg(program),
+        };
+        command
+    }
+}
+
+impl Drop for Program {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// compiles the file to the wasm module, and the JavaScript loader with `--js`
+fn compile_file(matches: &ArgMatches) {
+    let config = config(matches);
+    let project = load_project(matches, &config);
+    let module = match compile_program(&project, &config) {
+        Some(module) => module,
+        None => return,
+    };
+    write_module(&module, matches);
+}
+
+/// writes the module, and the JavaScript loader with `--js`
+fn write_module(module: &backend::Module, matches: &ArgMatches) {
+    let output = Path::new(matches.value_of("OUTPUT").unwrap());
+    let mut code = Vec::new();
+    module.dump(&mut code);
+    fs::write(output, &code).expect("failed to write file");
+    if matches.is_present("JS") {
+        let url = output.file_name().unwrap().to_string_lossy();
+        let glue = backend::generate_glue(&module.interface, &url);
+        fs::write(output.with_extension("js"), glue).expect("failed to write file");
+    }
+}
+
+/// compiles the file each time it is modified, reusing the results for the unchanged
+/// declarations. runs until interrupted
+fn watch_file(matches: &ArgMatches) {
+    let config = config(matches);
+    let path = matches.value_of("INPUT").unwrap();
+    let mut incremental = Incremental::new();
+    let mut last_modified = None;
+    loop {
+        let modified = fs::metadata(path).and_then(|m| m.modified()).ok();
+        if modified.is_some() && modified != last_modified {
+            last_modified = modified;
+            let input = read_program(path, &config);
+            let result = if matches.is_present("CHECK") {
+                incremental.check(&input, &config)
+            } else {
+                incremental
+                    .compile(&input, &config)
+                    .map(|module| write_module(&module, matches))
+            };
+            match result {
+                Ok(()) => eprintln!("compiled {}", path),
+                Err(CompileError::Stopped { .. }) => (),
+                Err(e) => eprintln!("error: {}", e),
+            }
+        }
+        thread::sleep(WATCH_INTERVAL);
+    }
+}
+
+/// reports the errors of the program without generating the code
+fn check_file(matches: &ArgMatches) {
+    let config = Config {
+        prelude: prelude(matches),
+        ..Config::default()
+    };
+    if let Err(e) = load_project(matches, &config).check(&config) {
+        exit_with(e)
+    }
+}
+
+/// compiles the file and runs it on Node.js
+fn run_file(matches: &ArgMatches) {
+    let config = config(matches);
+    let project = load_project(matches, &config);
+    let module = match compile_program(&project, &config) {
+        Some(module) => module,
+        None => return,
     };
+    let program = Program::write(&module, &config).expect("failed to write the program");
+    let status = program
+        .command(matches.value_of("RUNTIME").unwrap())
+        .status()
+        .expect("failed to run node");
+    if !status.success() {
+        process::exit(status.code().unwrap_or(1));
+    }
+}
+
+/// runs the program with the interpreter, or on Node.js with `--wasm`.
+/// returns the output, and whether the program succeeded
+fn run_session(program: &str, config: &Config, matches: &ArgMatches) -> (Vec<u8>, bool) {
+    if !matches.is_present("WASM") {
+        let mut stdout = Vec::new();
+        let result = eval(program, config, &mut stdout);
+        if let Err(e) = &result {
+            eprintln!("error: {}", e);
+        }
+        return (stdout, result.is_ok());
+    }
+    let module = match compile(program, config) {
+        Ok(module) => module,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            return (Vec::new(), false);
+        }
+    };
+    let output = Program::write(&module, config)
+        .and_then(|program| {
+            program
+                .command(matches.value_of("RUNTIME").unwrap())
+                .output()
+        })
+        .expect("failed to run the program");
+    io::stderr().write_all(&output.stderr).unwrap();
+    (output.stdout, output.status.success())
+}
+
+/// reads the declarations from stdin and runs them.
+/// the declarations given so far are run again as a program each time,
+/// and only the output of the new declarations is shown
+fn repl(matches: &ArgMatches) {
+    let config = config(matches);
+    let stdin = io::stdin();
+    let mut session = String::new();
+    let mut shown = 0;
+    let mut input = String::new();
+    loop {
+        print!("{} ", if input.is_empty() { "-" } else { "=" });
+        io::stdout().flush().unwrap();
+        let mut line = String::new();
+        if stdin
+            .lock()
+            .read_line(&mut line)
+            .expect("failed to read stdin")
+            == 0
+        {
+            println!();
+            return;
+        }
+        input.push_str(&line);
+        // as the other SML REPLs, the input ends with `;`
+        if !input.trim_end().ends_with(';') {
+            continue;
+        }
+        let program = format!("{}\n{}", session, input.trim_end().trim_end_matches(';'));
+        input.clear();
+        let (stdout, success) = run_session(&program, &config, matches);
+        io::stdout()
+            .write_all(stdout.get(shown..).unwrap_or(&[]))
+            .unwrap();
+        if success {
+            shown = stdout.len();
+            session = program;
+        }
+    }
+}
+
+/// formats the files in place, or reports the files not formatted with `--check`
+fn format_files(matches: &ArgMatches) {
+    let default = FormatConfig::default();
+    let number = |name, default| {
+        matches
+            .value_of(name)
+            .map(|s| s.parse::<usize>().expect("number expected"))
+            .unwrap_or(default)
+    };
+    let config = FormatConfig {
+        width: number("WIDTH", default.width),
+        indent: number("INDENT", default.indent),
+    };
+    let mut unformatted = false;
+    for path in matches.values_of("FILES").into_iter().flatten() {
+        let source = fs::read_to_string(path).expect("failed to load file");
+        let formatted = fmt(&source, &config).unwrap_or_else(|e| panic!("{}: {}", path, e));
+        if formatted == source {
+            continue;
+        }
+        if matches.is_present("CHECK") {
+            println!("{}", path);
+            unformatted = true;
+        } else {
+            fs::write(path, formatted).expect("failed to write file");
+        }
+    }
+    if unformatted {
+        std::process::exit(1);
+    }
+}
+
+fn main() {
+    let matches = app_from_crate!()
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(
+            output_options(compile_options(SubCommand::with_name("compile")))
+                .about("compile the file to out.wasm")
+                .arg(input_argument()),
+        )
+        .subcommand(
+            output_options(compile_options(SubCommand::with_name("watch")))
+                .about("compile the file again each time it changes")
+                .arg(
+                    Arg::with_name("CHECK")
+                        .long("check")
+                        .help("only report the errors of the file"),
+                )
+                .arg(
+                    Arg::with_name("INPUT")
+                        .help("file to compile")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            prelude_options(SubCommand::with_name("check"))
+                .about("report the errors of the file without compiling it")
+                .arg(input_argument()),
+        )
+        .subcommand(
+            compile_options(SubCommand::with_name("run"))
+                .about("compile the file and run it on Node.js")
+                .arg(runtime_option())
+                .arg(input_argument()),
+        )
+        .subcommand(
+            SubCommand::with_name("fmt")
+                .about("format the source files in place")
+                .arg(
+                    Arg::with_name("CHECK")
+                        .long("check")
+                        .help("print the files not formatted instead of formatting them"),
+                )
+                .arg(
+                    Arg::with_name("WIDTH")
+                        .long("width")
+                        .help("maximum width of the lines")
+                        .value_name("COLUMNS")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("INDENT")
+                        .long("indent")
+                        .help("number of spaces to indent with")
+                        .value_name("SPACES")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("FILES")
+                        .help("files to format")
+                        .required(true)
+                        .multiple(true),
+                ),
+        )
+        .subcommand(
+            compile_options(SubCommand::with_name("repl"))
+                .about("run the declarations read from stdin")
+                .arg(
+                    Arg::with_name("WASM")
+                        .long("wasm")
+                        .help("compile the declarations and run them on Node.js"),
+                )
+                .arg(runtime_option()),
+        )
+        .get_matches();
 
-    let prelude = include_str!("../ml_src/prelude.sml").to_string();
-    let mut input = prelude;
-    read_and_append_to_string(filename, &mut input).expect("failed to load file");
-    let code = compile_str(&input, &config).unwrap();
-    fs::write("out.wasm", &code).unwrap()
+    match matches.subcommand() {
+        ("compile", Some(matches)) => compile_file(matches),
+        ("watch", Some(matches)) => watch_file(matches),
+        ("check", Some(matches)) => check_file(matches),
+        ("run", Some(matches)) => run_file(matches),
+        ("fmt", Some(matches)) => format_files(matches),
+        ("repl", Some(matches)) => repl(matches),
+        _ => unreachable!("a subcommand is required"),
+    }
 }
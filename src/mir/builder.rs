@@ -9,9 +9,14 @@ impl FunctionBuilder {
             name,
             body_ty,
             body: Vec::new(),
+            export: None,
         })
     }
 
+    pub fn export(&mut self, name: String, ty: String, types: Option<ExportTypes>) {
+        self.0.export = Some(Export { name, ty, types });
+    }
+
     pub fn add_ebb(&mut self, ebb: EBB) {
         self.0.body.push(ebb);
     }
@@ -21,19 +26,32 @@ impl FunctionBuilder {
     }
 }
 
-pub struct EBBBuilder(EBB);
+pub struct EBBBuilder {
+    name: Symbol,
+    params: Vec<(EbbTy, Symbol)>,
+    body: Vec<Op>,
+}
 
 impl EBBBuilder {
     pub fn new(name: Symbol, params: Vec<(EbbTy, Symbol)>) -> Self {
-        EBBBuilder(EBB {
+        EBBBuilder {
             name,
             params,
             body: Vec::new(),
-        })
+        }
     }
 
     fn push(&mut self, op: Op) {
-        self.0.body.push(op);
+        self.body.push(op);
+    }
+
+    fn terminate(self, term: Terminator) -> EBB {
+        EBB {
+            name: self.name,
+            params: self.params,
+            body: self.body,
+            term,
+        }
     }
 
     pub fn lit(&mut self, var: Symbol, ty: EbbTy, value: Literal) -> &mut Self {
@@ -189,33 +207,30 @@ impl EBBBuilder {
     }
 
     pub fn branch(
-        mut self,
+        self,
         cond: Symbol,
         clauses: Vec<(u32, Symbol, bool)>,
         default: Option<(Symbol, bool)>,
     ) -> EBB {
-        self.push(Op::Branch {
+        self.terminate(Terminator::Branch {
             cond,
             clauses,
             default,
-        });
-        self.0
+        })
     }
 
-    pub fn jump(mut self, target: Symbol, forward: bool, args: Vec<Symbol>) -> EBB {
-        self.push(Op::Jump {
+    pub fn jump(self, target: Symbol, forward: bool, args: Vec<Symbol>) -> EBB {
+        self.terminate(Terminator::Jump {
             target,
             forward,
             args,
-        });
-        self.0
+        })
     }
 
-    pub fn ret<V: Into<Option<Symbol>>>(mut self, value: V, ty: EbbTy) -> EBB {
-        self.push(Op::Ret {
+    pub fn ret<V: Into<Option<Symbol>>>(self, value: V, ty: EbbTy) -> EBB {
+        self.terminate(Terminator::Ret {
             value: value.into(),
             ty,
-        });
-        self.0
+        })
     }
 }
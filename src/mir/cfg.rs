@@ -1,39 +1,49 @@
 use crate::prim::*;
-use std::collections::{HashSet, VecDeque};
+use std::collections::HashMap;
 
 use super::{Function, EBB};
-use petgraph::graph::Graph;
+use petgraph::graph::{Graph, NodeIndex};
 
 impl Function {
+    /// control flow graph of the function.
+    /// each node holds the index of the block in `body`.
     pub fn cfg(&self) -> Graph<usize, ()> {
         let mut graph = Graph::new();
-        let mut queue = VecDeque::new();
-        let mut done = HashSet::new();
-        queue.push_back(0);
-        while !queue.is_empty() {
-            let ebb_idx = queue
-                .pop_front()
-                // this is safe because queue is non_empty
-                .expect("internal error");
-            done.insert(ebb_idx);
-            let node = graph.add_node(ebb_idx);
-            for &(next, _) in self.body[ebb_idx].next_ebbs().iter() {
+        let nodes = (0..self.body.len())
+            .map(|ebb_idx| graph.add_node(ebb_idx))
+            .collect::<Vec<NodeIndex>>();
+        for (ebb_idx, ebb) in self.body.iter().enumerate() {
+            for (next, _) in ebb.next_ebbs() {
                 let next_idx = self
                     .find_ebb(next)
                     // this is safe because jump target must be in the function
                     .expect("internal error");
-                if done.contains(&next_idx) {
-                    continue;
-                }
-                let next_node = graph.add_node(next_idx);
-                graph.add_edge(node, next_node, ());
-                queue.push_back(next_idx);
+                graph.add_edge(nodes[ebb_idx], nodes[next_idx], ());
             }
         }
 
         graph
     }
 
+    /// blocks that may jump into each block
+    pub fn predecessors(&self) -> HashMap<&Symbol, Vec<&Symbol>> {
+        let mut preds = self
+            .body
+            .iter()
+            .map(|ebb| (&ebb.name, Vec::new()))
+            .collect::<HashMap<_, _>>();
+        for ebb in self.body.iter() {
+            for (next, _) in ebb.next_ebbs() {
+                preds
+                    .get_mut(next)
+                    // this is safe because jump target must be in the function
+                    .expect("internal error")
+                    .push(&ebb.name);
+            }
+        }
+        preds
+    }
+
     pub fn find_ebb(&self, name: &Symbol) -> Option<usize> {
         self.body.iter().position(|ebb| &ebb.name == name)
     }
@@ -41,9 +51,8 @@ impl Function {
 
 impl EBB {
     pub fn next_ebbs<'a>(&'a self) -> Vec<(&'a Symbol, bool)> {
-        use crate::mir::Op::*;
-        let last = self.body.len() - 1;
-        match &self.body[last] {
+        use crate::mir::Terminator::*;
+        match &self.term {
             &Branch {
                 ref clauses,
                 ref default,
@@ -59,7 +68,6 @@ impl EBB {
                 ..
             } => vec![(target, forward)],
             &Ret { .. } => vec![],
-            _ => unreachable!(),
         }
     }
 }
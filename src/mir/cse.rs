@@ -0,0 +1,157 @@
+use crate::config::Config;
+use crate::mir::*;
+use crate::pass::Pass;
+use crate::prim::*;
+use std::collections::HashMap;
+
+/// Common subexpression elimination.
+/// Pure operations computing the same value as a preceding operation in the same EBB
+/// are replaced with an alias to the former one.
+/// Aliases are removed later by `UnAlias`.
+/// The expressions and the aliases are forgotten at the start of each EBB and when a variable
+/// they read is assigned, so that the pass doesn't rely on `Verify` keeping MIR SSA.
+pub struct CSE {
+    exprs: HashMap<Key, Symbol>,
+    alias: HashMap<Symbol, Symbol>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Key {
+    BinOp(BIF, Symbol, Symbol),
+    Proj(u32, Symbol),
+    Select(u32, Symbol),
+}
+
+impl Key {
+    fn reads(&self, var: &Symbol) -> bool {
+        match self {
+            Key::BinOp(_, l, r) => l == var || r == var,
+            Key::Proj(_, sym) | Key::Select(_, sym) => sym == var,
+        }
+    }
+}
+
+impl CSE {
+    pub fn new() -> Self {
+        CSE {
+            exprs: HashMap::new(),
+            alias: HashMap::new(),
+        }
+    }
+
+    fn conv_mir(&mut self, mir: MIR) -> MIR {
+        MIR(mir.0.into_iter().map(|f| self.conv_fun(f)).collect())
+    }
+
+    fn conv_fun(&mut self, mut fun: Function) -> Function {
+        fun.body = fun.body.into_iter().map(|ebb| self.conv_ebb(ebb)).collect();
+        fun
+    }
+
+    fn conv_ebb(&mut self, mut ebb: EBB) -> EBB {
+        self.exprs.clear();
+        self.alias.clear();
+        ebb.body = ebb.body.into_iter().map(|op| self.conv_op(op)).collect();
+        ebb
+    }
+
+    fn conv_op(&mut self, op: Op) -> Op {
+        use crate::mir::Op::*;
+        let (var, ty, key) = match &op {
+            Alias { var, sym, .. } => {
+                let sym = self.resolv_alias(sym);
+                self.kill(var);
+                if &sym != var {
+                    self.alias.insert(var.clone(), sym);
+                }
+                return op;
+            }
+            Add { var, ty, l, r } => (var, ty, self.commutative(BIF::Add, l, r)),
+            Mul { var, ty, l, r } => (var, ty, self.commutative(BIF::Mul, l, r)),
+            Eq { var, ty, l, r } => (var, ty, self.commutative(BIF::Eq, l, r)),
+            Neq { var, ty, l, r } => (var, ty, self.commutative(BIF::Neq, l, r)),
+            Sub { var, ty, l, r } => (var, ty, self.binop(BIF::Sub, l, r)),
+            DivInt { var, ty, l, r } => (var, ty, self.binop(BIF::Div, l, r)),
+            DivFloat { var, ty, l, r } => (var, ty, self.binop(BIF::Divf, l, r)),
+            Mod { var, ty, l, r } => (var, ty, self.binop(BIF::Mod, l, r)),
+            Gt { var, ty, l, r } => (var, ty, self.binop(BIF::Gt, l, r)),
+            Ge { var, ty, l, r } => (var, ty, self.binop(BIF::Ge, l, r)),
+            Lt { var, ty, l, r } => (var, ty, self.binop(BIF::Lt, l, r)),
+            Le { var, ty, l, r } => (var, ty, self.binop(BIF::Le, l, r)),
+            Proj {
+                var,
+                ty,
+                index,
+                tuple,
+            } => (var, ty, Key::Proj(*index, self.resolv_alias(tuple))),
+            Select {
+                var,
+                ty,
+                index,
+                union,
+            } => (var, ty, Key::Select(*index, self.resolv_alias(union))),
+            _ => {
+                self.kill(op.var());
+                return op;
+            }
+        };
+        self.kill(var);
+        // `x = x + 1` computes the value of the previous `x`
+        if key.reads(var) {
+            return op;
+        }
+        match self.exprs.get(&key) {
+            Some(sym) => {
+                self.alias.insert(var.clone(), sym.clone());
+                Alias {
+                    var: var.clone(),
+                    ty: ty.clone(),
+                    sym: sym.clone(),
+                }
+            }
+            None => {
+                self.exprs.insert(key, var.clone());
+                op
+            }
+        }
+    }
+
+    /// forgets the expressions and the aliases which read `var`, which is being assigned
+    fn kill(&mut self, var: &Symbol) {
+        self.exprs.retain(|key, sym| sym != var && !key.reads(var));
+        self.alias.retain(|alias, orig| alias != var && orig != var);
+    }
+
+    fn binop(&self, op: BIF, l: &Symbol, r: &Symbol) -> Key {
+        Key::BinOp(op, self.resolv_alias(l), self.resolv_alias(r))
+    }
+
+    fn commutative(&self, op: BIF, l: &Symbol, r: &Symbol) -> Key {
+        let l = self.resolv_alias(l);
+        let r = self.resolv_alias(r);
+        if (l.1, &l.0) <= (r.1, &r.0) {
+            Key::BinOp(op, l, r)
+        } else {
+            Key::BinOp(op, r, l)
+        }
+    }
+
+    fn resolv_alias(&self, sym: &Symbol) -> Symbol {
+        match self.alias.get(sym) {
+            None => sym.clone(),
+            Some(orig) => orig.clone(),
+        }
+    }
+}
+
+impl<E> Pass<(SymbolTable, MIR), E> for CSE {
+    type Target = (SymbolTable, MIR);
+
+    fn trans(
+        &mut self,
+        (symbol_table, mir): (SymbolTable, MIR),
+        _: &Config,
+    ) -> ::std::result::Result<Self::Target, E> {
+        Ok((symbol_table, self.conv_mir(mir)))
+    }
+}
@@ -0,0 +1,216 @@
+use crate::config::Config;
+use crate::mir::*;
+use crate::pass::Pass;
+use crate::prim::*;
+use std::collections::{HashMap, HashSet};
+
+/// Escape analysis.
+/// Tuples and unions that are only projected in the function creating them
+/// never escape, so they are not allocated at all.
+/// Their projections are replaced with aliases to the components,
+/// which are kept in locals.
+pub struct EscapeAnalysis;
+
+#[derive(Debug, Clone)]
+enum Alloc {
+    Tuple(Vec<Symbol>),
+    Union(u32, Symbol),
+}
+
+impl EscapeAnalysis {
+    pub fn new() -> Self {
+        EscapeAnalysis
+    }
+
+    fn conv_mir(&mut self, mir: MIR) -> MIR {
+        MIR(mir.0.into_iter().map(|f| self.conv_fun(f)).collect())
+    }
+
+    fn conv_fun(&mut self, mut fun: Function) -> Function {
+        self.forward_params(&mut fun);
+        // removing a tuple may make its components unescaping
+        while self.scalar_replace(&mut fun) {}
+        fun
+    }
+
+    /// a block with the only predecessor jumping into it
+    /// receives its arguments by aliases instead of parameters
+    /// so that values passed to it can be tracked.
+    fn forward_params(&mut self, fun: &mut Function) {
+        let forwarded = {
+            let preds = fun.predecessors();
+            fun.body
+                .iter()
+                .skip(1)
+                .filter(|ebb| !ebb.params.is_empty())
+                .filter_map(|ebb| match preds[&ebb.name].as_slice() {
+                    [pred] => Some(((*pred).clone(), ebb.name.clone())),
+                    _ => None,
+                })
+                .collect::<HashMap<_, _>>()
+        };
+        let mut args = HashMap::new();
+        for ebb in fun.body.iter_mut() {
+            if let Terminator::Jump {
+                target,
+                args: jump_args,
+                ..
+            } = &mut ebb.term
+            {
+                if forwarded.get(&ebb.name) == Some(target) {
+                    args.insert(target.clone(), ::std::mem::replace(jump_args, Vec::new()));
+                }
+            }
+        }
+        for ebb in fun.body.iter_mut() {
+            if let Some(args) = args.remove(&ebb.name) {
+                let params = ::std::mem::replace(&mut ebb.params, Vec::new());
+                let mut body = params
+                    .into_iter()
+                    .zip(args)
+                    .map(|((ty, var), sym)| Op::Alias { var, ty, sym })
+                    .collect::<Vec<_>>();
+                body.append(&mut ebb.body);
+                ebb.body = body;
+            }
+        }
+    }
+
+    /// returns true if any allocation is removed
+    fn scalar_replace(&mut self, fun: &mut Function) -> bool {
+        let mut allocs = HashMap::new();
+        let mut aliases = HashMap::new();
+        for op in fun.body.iter().flat_map(|ebb| ebb.body.iter()) {
+            match op {
+                Op::Tuple { var, tuple, .. } => {
+                    allocs.insert(var.clone(), Alloc::Tuple(tuple.clone()));
+                }
+                Op::Union {
+                    var,
+                    index,
+                    variant,
+                    ..
+                } => {
+                    allocs.insert(var.clone(), Alloc::Union(*index, variant.clone()));
+                }
+                Op::Alias { var, sym, .. } => {
+                    aliases.insert(var.clone(), sym.clone());
+                }
+                _ => (),
+            }
+        }
+        // aliases of allocations are allocations, too
+        loop {
+            let found = aliases
+                .iter()
+                .filter(|(var, sym)| !allocs.contains_key(*var) && allocs.contains_key(*sym))
+                .map(|(var, sym)| (var.clone(), allocs[sym].clone()))
+                .collect::<Vec<_>>();
+            if found.is_empty() {
+                break;
+            }
+            allocs.extend(found);
+        }
+
+        let mut escaped = HashSet::new();
+        for ebb in fun.body.iter() {
+            for op in ebb.body.iter() {
+                match op {
+                    Op::Alias { sym, .. } if allocs.contains_key(sym) => (),
+                    Op::Proj { tuple, .. } => match allocs.get(tuple) {
+                        Some(Alloc::Tuple(_)) => (),
+                        _ => escaped.extend(op.uses().into_iter().cloned()),
+                    },
+                    Op::Select { index, union, .. } => match allocs.get(union) {
+                        Some(Alloc::Union(i, _)) if i == index => (),
+                        _ => escaped.extend(op.uses().into_iter().cloned()),
+                    },
+                    op => escaped.extend(op.uses().into_iter().cloned()),
+                }
+            }
+            escaped.extend(ebb.term.uses().into_iter().cloned());
+        }
+        // the original escapes if an alias escapes
+        loop {
+            let found = aliases
+                .iter()
+                .filter(|(var, sym)| escaped.contains(*var) && !escaped.contains(*sym))
+                .map(|(_, sym)| sym.clone())
+                .collect::<Vec<_>>();
+            if found.is_empty() {
+                break;
+            }
+            escaped.extend(found);
+        }
+        allocs.retain(|var, _| !escaped.contains(var));
+        if allocs.is_empty() {
+            return false;
+        }
+
+        for ebb in fun.body.iter_mut() {
+            let body = ::std::mem::replace(&mut ebb.body, Vec::new());
+            ebb.body = body
+                .into_iter()
+                .filter_map(|op| match op {
+                    Op::Tuple { ref var, .. }
+                    | Op::Union { ref var, .. }
+                    | Op::Alias { ref var, .. }
+                        if allocs.contains_key(var) =>
+                    {
+                        None
+                    }
+                    Op::Proj {
+                        var,
+                        ty,
+                        index,
+                        tuple,
+                    } => match allocs.get(&tuple) {
+                        Some(Alloc::Tuple(components)) => Some(Op::Alias {
+                            var,
+                            ty,
+                            sym: components[index as usize].clone(),
+                        }),
+                        _ => Some(Op::Proj {
+                            var,
+                            ty,
+                            index,
+                            tuple,
+                        }),
+                    },
+                    Op::Select {
+                        var,
+                        ty,
+                        index,
+                        union,
+                    } => match allocs.get(&union) {
+                        Some(Alloc::Union(_, variant)) => Some(Op::Alias {
+                            var,
+                            ty,
+                            sym: variant.clone(),
+                        }),
+                        _ => Some(Op::Select {
+                            var,
+                            ty,
+                            index,
+                            union,
+                        }),
+                    },
+                    op => Some(op),
+                })
+                .collect();
+        }
+        true
+    }
+}
+
+impl<E> Pass<(SymbolTable, MIR), E> for EscapeAnalysis {
+    type Target = (SymbolTable, MIR);
+
+    fn trans(
+        &mut self,
+        (symbol_table, mir): (SymbolTable, MIR),
+        _: &Config,
+    ) -> ::std::result::Result<Self::Target, E> {
+        Ok((symbol_table, self.conv_mir(mir)))
+    }
+}
@@ -1,6 +1,7 @@
 use super::builder::*;
 use crate::config::Config;
 use crate::hir;
+use crate::hir::util::Traverse;
 use crate::id::Id;
 use crate::mir::*;
 use crate::pass::Pass;
@@ -21,34 +22,55 @@ impl HIR2MIR {
     }
 }
 
+/// finds `raise`s in the program
+struct Raises(bool);
+
+impl Traverse for Raises {
+    fn traverse_builtin_call(
+        &mut self,
+        _ty: &mut hir::HTy,
+        fun: &mut BIF,
+        args: &mut Vec<hir::Expr>,
+    ) {
+        if let BIF::Raise = fun {
+            self.0 = true;
+        }
+        for arg in args {
+            self.traverse_expr(arg)
+        }
+    }
+}
+
 struct HIR2MIRPass {
-    label: u64,
     id: Id,
     closure_wrapper: HashMap<Symbol, (Symbol, EbbTy, EbbTy)>,
     symbol_table: hir::SymbolTable,
+    // exceptions are lowered to checks of the flag of the runtime after calls.
+    // unless the program raises, no checks are emitted.
+    exceptions: bool,
+    // the block which returns from the current function when an exception is raised
+    propagate: Symbol,
 }
 
 impl HIR2MIRPass {
     pub fn new(id: Id, symbol_table: hir::SymbolTable) -> Self {
         HIR2MIRPass {
             id,
-            label: 0,
             closure_wrapper: HashMap::new(),
             symbol_table,
+            exceptions: false,
+            propagate: Symbol::new("propagate"),
         }
     }
 
     fn genlabel(&mut self, name: &str) -> Symbol {
-        let name = name.to_string();
-        let label = self.label;
-        self.label += 1;
-        Symbol(name, label)
+        let label = self.id.next();
+        Symbol(name.into(), label)
     }
 
     fn gensym(&mut self, name: &str) -> Symbol {
-        let name = name.to_string();
         let id = self.id.next();
-        Symbol(name, id)
+        Symbol(name.into(), id)
     }
 
     fn generate_symbol_table(&self) -> SymbolTable {
@@ -78,7 +100,9 @@ impl HIR2MIRPass {
         match ty {
             Char => EbbTy::Char,
             Int => EbbTy::Int,
+            Word => EbbTy::Word,
             Real => EbbTy::Float,
+            String => EbbTy::String,
             Tuple(tys) => match tys.len() {
                 0 => EbbTy::Unit,
                 // TODO: treat 1-tuple as inner type
@@ -104,7 +128,7 @@ impl HIR2MIRPass {
 
     fn wrapper_name(&mut self, mut name: Symbol) -> Symbol {
         name.1 = self.id.next();
-        name.0.push_str("_closure_wrapper");
+        name.0 = format!("{}_closure_wrapper", name.0).into();
         name
     }
 
@@ -123,16 +147,25 @@ impl HIR2MIRPass {
         wrapper_name.0.clone()
     }
 
-    fn trans_hir(&mut self, hir: hir::HIR) -> MIR {
+    fn trans_hir(&mut self, mut hir: hir::HIR) -> MIR {
+        let mut raises = Raises(false);
+        raises.traverse_hir(&mut hir);
+        self.exceptions = raises.0;
+
         // TODO: make anonymous
         let mut mainbuilder = FunctionBuilder::new(Symbol::new("sml-main"), EbbTy::Unit);
         let mut mainebuilder = EBBBuilder::new(self.genlabel("entry"), Vec::new());
         let mut funs = Vec::new();
+        self.propagate = self.genlabel("propagate");
+        let main_propagate = self.propagate.clone();
 
         for val in hir.0.into_iter() {
             mainebuilder = self.trans_val(&mut funs, &mut mainbuilder, mainebuilder, val);
         }
-        for (fname, (wrapper_name, param_ty, ret_ty)) in self.closure_wrapper.clone().into_iter() {
+        // generate the wrappers in the order of creation for reproducible output
+        let mut wrappers = self.closure_wrapper.clone().into_iter().collect::<Vec<_>>();
+        wrappers.sort_by_key(|(_, (wrapper_name, _, _))| wrapper_name.1);
+        for (fname, (wrapper_name, param_ty, ret_ty)) in wrappers {
             self.make_wrapper(
                 &mut funs,
                 fname.clone(),
@@ -144,6 +177,10 @@ impl HIR2MIRPass {
 
         let ebb = mainebuilder.ret(None, EbbTy::Unit);
         mainbuilder.add_ebb(ebb);
+        if self.exceptions {
+            let ebb = self.propagate_ebb(main_propagate, EbbTy::Unit);
+            mainbuilder.add_ebb(ebb);
+        }
         let main = mainbuilder.build();
         funs.push(main);
         MIR(funs)
@@ -157,16 +194,16 @@ impl HIR2MIRPass {
         param_ty: EbbTy,
         ret_ty: EbbTy,
     ) {
-        let param = Symbol::new("param");
+        let param = self.gensym("param");
         let mut fb = FunctionBuilder::new(wrapper_name, ret_ty.clone());
         let mut eb = EBBBuilder::new(
             Symbol::new("entry"),
             vec![
-                (EbbTy::Tuple(vec![]), Symbol::new("_")),
+                (EbbTy::Tuple(vec![]), self.gensym("_")),
                 (param_ty, param.clone()),
             ],
         );
-        let ret = Symbol::new("ret");
+        let ret = self.gensym("ret");
         eb.call(ret.clone(), ret_ty.clone(), fname, vec![param]);
         let ebb = eb.ret(ret, ret_ty);
         fb.add_ebb(ebb);
@@ -174,6 +211,42 @@ impl HIR2MIRPass {
         funs.push(f);
     }
 
+    /// the block to return from the function with a dummy value when an exception is raised.
+    /// the callers will see the flag and propagate the exception in turn.
+    fn propagate_ebb(&mut self, label: Symbol, ret_ty: EbbTy) -> EBB {
+        let mut eb = EBBBuilder::new(label, vec![(EbbTy::Int, self.gensym("raised"))]);
+        let dummy = self.gensym("dummy");
+        match ret_ty {
+            EbbTy::Unit => return eb.ret(None, ret_ty),
+            EbbTy::Float => eb.lit(dummy.clone(), ret_ty.clone(), Literal::Real(0.0)),
+            _ => eb.lit(dummy.clone(), ret_ty.clone(), Literal::Int(0)),
+        };
+        eb.ret(dummy, ret_ty)
+    }
+
+    /// continues in a new block if no exception is raised
+    fn check_raised(&mut self, fb: &mut FunctionBuilder, mut eb: EBBBuilder) -> EBBBuilder {
+        if !self.exceptions {
+            return eb;
+        }
+        let raised = self.gensym("raised");
+        let cont = self.genlabel("cont");
+        eb.extern_call(
+            raised.clone(),
+            EbbTy::Int,
+            "webml-rt".into(),
+            "raised".into(),
+            vec![],
+        );
+        let ebb = eb.branch(
+            raised,
+            vec![(0, cont.clone(), true)],
+            Some((self.propagate.clone(), true)),
+        );
+        fb.add_ebb(ebb);
+        EBBBuilder::new(cont, Vec::new())
+    }
+
     fn trans_val(
         &mut self,
         funs: &mut Vec<Function>,
@@ -196,6 +269,7 @@ impl HIR2MIRPass {
                 captures,
             } => {
                 //                assert_eq!(body_ty, ty_);
+                let sml_ty = hir::HTy::fun(param.0.clone(), body_ty.clone()).to_sml();
                 let param = (self.trans_ty(&param.0), param.1);
                 let mut eb_;
                 if !captures.is_empty() {
@@ -204,7 +278,7 @@ impl HIR2MIRPass {
                         .into_iter()
                         .map(|(ty, var)| (self.trans_ty(&ty), var))
                         .unzip();
-                    let closure = Symbol::new("env");
+                    let closure = self.gensym("env");
                     eb_ = EBBBuilder::new(
                         Symbol::new("entry"),
                         vec![(EbbTy::Tuple(tuples.clone()), closure.clone()), param],
@@ -216,9 +290,26 @@ impl HIR2MIRPass {
                     // make pure function
                     eb_ = EBBBuilder::new(Symbol::new("entry"), vec![param]);
                 }
-                let mut fb = FunctionBuilder::new(name, self.trans_ty(&body_ty));
+                let ret_ty = self.trans_ty(&body_ty);
+                let export = self
+                    .symbol_table
+                    .exports
+                    .iter()
+                    .find(|(f, _, _)| f == &name)
+                    .map(|(_, export, types)| (export.clone(), types.clone()));
+                let mut fb = FunctionBuilder::new(name, ret_ty.clone());
+                if let Some((export, types)) = export {
+                    fb.export(export, sml_ty, types);
+                }
+                let propagate = self.genlabel("propagate");
+                let outer = ::std::mem::replace(&mut self.propagate, propagate.clone());
                 let ebb = self.trans_expr(&mut fb, eb_, body_ty, *body);
                 fb.add_ebb(ebb);
+                if self.exceptions {
+                    let ebb = self.propagate_ebb(propagate, ret_ty);
+                    fb.add_ebb(ebb);
+                }
+                self.propagate = outer;
                 let function = fb.build();
                 funs.push(function);
                 eb
@@ -240,6 +331,28 @@ impl HIR2MIRPass {
                         args.remove(0)
                     };
                 }
+                match fun {
+                    Raise => {
+                        let unit = self.gensym("unit");
+                        eb.extern_call(
+                            unit,
+                            EbbTy::Unit,
+                            "webml-rt".into(),
+                            "raise".into(),
+                            vec![pop!()],
+                        );
+                        let mut eb = self.check_raised(fb, eb);
+                        // never reached
+                        match self.trans_ty(&ty) {
+                            EbbTy::Unit => eb.tuple(name, vec![], vec![]),
+                            EbbTy::Float => eb.lit(name, EbbTy::Float, Literal::Real(0.0)),
+                            ty => eb.lit(name, ty, Literal::Int(0)),
+                        };
+                        return eb;
+                    }
+                    Handle => return self.trans_handle(fb, eb, name, ty, pop!(), pop!()),
+                    _ => (),
+                }
                 match fun {
                     Add => eb.add(name, self.trans_ty(&ty), pop!(), pop!()),
                     Sub => eb.sub(name, self.trans_ty(&ty), pop!(), pop!()),
@@ -253,6 +366,8 @@ impl HIR2MIRPass {
                     Ge => eb.ge(name, self.trans_ty(&ty), pop!(), pop!()),
                     Lt => eb.lt(name, self.trans_ty(&ty), pop!(), pop!()),
                     Le => eb.le(name, self.trans_ty(&ty), pop!(), pop!()),
+                    Raise | Handle => unreachable!(),
+                    Hash => unreachable!("internal error: hash is lowered in ast2hir"),
                 };
                 eb
             }
@@ -272,7 +387,7 @@ impl HIR2MIRPass {
                 let arg = force_symbol(*arg);
                 let fun = force_symbol(*fun);
                 eb.call(name, self.trans_ty(&ty), fun, vec![arg]);
-                eb
+                self.check_raised(fb, eb)
             }
             Case { ty, expr, arms } => {
                 let joinlabel = self.genlabel("join");
@@ -493,6 +608,66 @@ impl HIR2MIRPass {
         }
     }
 
+    // r = body ()
+    // if raised then r = handler(take_exception())
+    fn trans_handle(
+        &mut self,
+        fb: &mut FunctionBuilder,
+        mut eb: EBBBuilder,
+        name: Symbol,
+        ty: hir::HTy,
+        body: Symbol,
+        handler: Symbol,
+    ) -> EBBBuilder {
+        let ty = self.trans_ty(&ty);
+        let unit = self.gensym("unit");
+        eb.tuple(unit.clone(), vec![], vec![]);
+        if !self.exceptions {
+            // nothing is raised
+            eb.call(name, ty, body, vec![unit]);
+            return eb;
+        }
+        let joinlabel = self.genlabel("join");
+        let catchlabel = self.genlabel("catch");
+        let donelabel = self.genlabel("done");
+
+        let ret = self.gensym("ret");
+        let raised = self.gensym("raised");
+        eb.call(ret.clone(), ty.clone(), body, vec![unit]);
+        eb.extern_call(
+            raised.clone(),
+            EbbTy::Int,
+            "webml-rt".into(),
+            "raised".into(),
+            vec![],
+        );
+        let ebb = eb.branch(
+            raised,
+            vec![(0, donelabel.clone(), true)],
+            Some((catchlabel.clone(), true)),
+        );
+        fb.add_ebb(ebb);
+
+        let eb = EBBBuilder::new(donelabel, Vec::new());
+        fb.add_ebb(eb.jump(joinlabel.clone(), true, vec![ret]));
+
+        let mut eb = EBBBuilder::new(catchlabel, vec![(EbbTy::Int, self.gensym("raised"))]);
+        let exn = self.gensym("exn");
+        let ret = self.gensym("ret");
+        eb.extern_call(
+            exn.clone(),
+            self.trans_ty(&hir::HTy::Datatype(Symbol::new("exn"))),
+            "webml-rt".into(),
+            "take_exception".into(),
+            vec![],
+        );
+        eb.call(ret.clone(), ty.clone(), handler, vec![exn]);
+        let eb = self.check_raised(fb, eb);
+        fb.add_ebb(eb.jump(joinlabel.clone(), true, vec![ret]));
+
+        EBBBuilder::new(joinlabel, vec![(ty, name)])
+    }
+
     fn trans_expr(
         &mut self,
         fb: &mut FunctionBuilder,
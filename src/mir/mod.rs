@@ -1,36 +1,56 @@
 mod block_arrange;
 mod builder;
 pub mod cfg;
+mod cse;
+mod escape;
 mod hir2mir;
 pub mod pp;
 mod unalias;
+mod unbox;
+mod verify;
 
 pub use self::block_arrange::BlockArrange;
+pub use self::cse::CSE;
+pub use self::escape::EscapeAnalysis;
 pub use self::hir2mir::HIR2MIR;
 pub use self::unalias::UnAlias;
+pub use self::unbox::Unbox;
+pub use self::verify::Verify;
 use crate::prim::*;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct MIR(pub Vec<Function>);
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Function {
     pub name: Symbol,
     // pub params: Vec<Symbol>,
     // pub params_ty: Vec<EbbTy>,
     pub body: Vec<EBB>,
     pub body_ty: EbbTy,
+    pub export: Option<Export>,
 }
 
+/// A basic block.
+/// Every variable is assigned exactly once in a function (SSA).
+/// The parameters of a block play the role of phi nodes
+/// and the edges of the control flow graph are explicit in the terminator.
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct EBB {
     pub name: Symbol,
     pub params: Vec<(EbbTy, Symbol)>,
     pub body: Vec<Op>,
+    pub term: Terminator,
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Op {
     Lit {
         var: Symbol,
@@ -160,7 +180,13 @@ pub enum Op {
         index: u32,
         union: Symbol,
     },
+}
 
+/// the last instruction of a block, which transfers the control to other blocks
+/// or returns from the function.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Terminator {
     Branch {
         cond: Symbol,
         clauses: Vec<(u32, Symbol, bool)>,
@@ -177,18 +203,23 @@ pub enum Op {
     },
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Default)]
 pub struct SymbolTable {
     table: HashMap<Symbol, EbbTy>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum EbbTy {
     Unit,
     Char,
     Int,
+    /// the unsigned 32-bit integer
+    Word,
     Float,
     Bool,
+    /// the pointer to the length and the UTF-8 bytes
+    String,
     Tuple(Vec<EbbTy>),
     Union(Vec<EbbTy>),
     Cls {
@@ -209,6 +240,89 @@ impl MIR {
     }
 }
 
+impl EBB {
+    /// the variables defined by the block, the parameters and those of the ops
+    pub fn defs(&self) -> impl Iterator<Item = &Symbol> {
+        self.params
+            .iter()
+            .map(|(_, var)| var)
+            .chain(self.body.iter().map(Op::var))
+    }
+}
+
+impl Op {
+    /// the variable defined by the op
+    pub fn var(&self) -> &Symbol {
+        use crate::mir::Op::*;
+        match self {
+            Lit { var, .. }
+            | Alias { var, .. }
+            | Add { var, .. }
+            | Sub { var, .. }
+            | Mul { var, .. }
+            | DivInt { var, .. }
+            | DivFloat { var, .. }
+            | Mod { var, .. }
+            | Eq { var, .. }
+            | Neq { var, .. }
+            | Gt { var, .. }
+            | Ge { var, .. }
+            | Lt { var, .. }
+            | Le { var, .. }
+            | Closure { var, .. }
+            | ExternCall { var, .. }
+            | Call { var, .. }
+            | Tuple { var, .. }
+            | Proj { var, .. }
+            | Union { var, .. }
+            | Select { var, .. } => var,
+        }
+    }
+
+    /// the variables used by the op
+    pub fn uses(&self) -> Vec<&Symbol> {
+        use crate::mir::Op::*;
+        match self {
+            Lit { .. } => vec![],
+            Alias { sym, .. } => vec![sym],
+            Add { l, r, .. }
+            | Sub { l, r, .. }
+            | Mul { l, r, .. }
+            | DivInt { l, r, .. }
+            | DivFloat { l, r, .. }
+            | Mod { l, r, .. }
+            | Eq { l, r, .. }
+            | Neq { l, r, .. }
+            | Gt { l, r, .. }
+            | Ge { l, r, .. }
+            | Lt { l, r, .. }
+            | Le { l, r, .. } => vec![l, r],
+            Closure { fun, env, .. } => Some(fun)
+                .into_iter()
+                .chain(env.iter().map(|(_, var)| var))
+                .collect(),
+            ExternCall { args, .. } => args.iter().collect(),
+            Call { fun, args, .. } => Some(fun).into_iter().chain(args.iter()).collect(),
+            Tuple { tuple, .. } => tuple.iter().collect(),
+            Proj { tuple, .. } => vec![tuple],
+            Union { variant, .. } => vec![variant],
+            Select { union, .. } => vec![union],
+        }
+    }
+}
+
+impl Terminator {
+    /// the variables used by the terminator
+    pub fn uses(&self) -> Vec<&Symbol> {
+        use crate::mir::Terminator::*;
+        match self {
+            Branch { cond, .. } => vec![cond],
+            Jump { args, .. } => args.iter().collect(),
+            Ret { value, .. } => value.iter().collect(),
+        }
+    }
+}
+
 impl SymbolTable {
     pub fn canonical_value(&self, name: &Symbol) -> Option<&EbbTy> {
         match self.table.get(name) {
@@ -20,6 +20,9 @@ impl PP for MIR {
 impl PP for Function {
     fn pp<W: io::Write>(&self, w: &mut W, indent: usize) -> io::Result<()> {
         let indent = indent + 4;
+        if let Some(export) = &self.export {
+            write!(w, "export \"{}\" ", export.name)?;
+        }
         write!(w, "fun ")?;
         self.name.pp(w, indent)?;
         write!(w, ": (")?;
@@ -52,7 +55,9 @@ impl PP for EbbTy {
             Bool => write!(w, "bool")?,
             Char => write!(w, "char")?,
             Int => write!(w, "int")?,
+            Word => write!(w, "word")?,
             Float => write!(w, "float")?,
+            String => write!(w, "string")?,
             Tuple(tys) => {
                 write!(w, "(")?;
                 inter_iter! {
@@ -126,6 +131,8 @@ impl PP for EBB {
             op.pp(w, indent)?;
             write!(w, "\n")?;
         }
+        self.term.pp(w, indent)?;
+        write!(w, "\n")?;
         Ok(())
     }
 }
@@ -347,6 +354,17 @@ impl PP for Op {
                 union.pp(w, indent)?;
                 write!(w, ")")?;
             }
+        };
+        Ok(())
+    }
+}
+
+impl PP for Terminator {
+    fn pp<W: io::Write>(&self, w: &mut W, indent: usize) -> io::Result<()> {
+        use crate::mir::Terminator::*;
+        let space = Self::nspaces(indent);
+        let indent = indent + 4;
+        match self {
             Branch {
                 cond,
                 clauses,
@@ -147,22 +147,24 @@ impl UnAlias {
                         self.resolv_alias(arg);
                     }
                 }
-                &mut Jump { ref mut args, .. } => {
-                    for arg in args.iter_mut() {
-                        self.resolv_alias(arg);
-                    }
-                }
-                &mut Ret { ref mut value, .. } => {
-                    if let Some(v) = value.as_mut() {
-                        self.resolv_alias(v)
-                    }
-                }
                 &mut Lit { .. } => (),
-                &mut Branch { ref mut cond, .. } => self.resolv_alias(cond),
             }
             body.push(op)
         }
         ebb.body = body;
+        match &mut ebb.term {
+            &mut Terminator::Jump { ref mut args, .. } => {
+                for arg in args.iter_mut() {
+                    self.resolv_alias(arg);
+                }
+            }
+            &mut Terminator::Ret { ref mut value, .. } => {
+                if let Some(v) = value.as_mut() {
+                    self.resolv_alias(v)
+                }
+            }
+            &mut Terminator::Branch { ref mut cond, .. } => self.resolv_alias(cond),
+        }
         ebb
     }
 
@@ -177,7 +179,7 @@ impl UnAlias {
         match self.alias.get(sym) {
             None => (),
             Some(orig) => {
-                sym.0 = orig.0.clone();
+                sym.0 = orig.0;
                 sym.1 = orig.1;
             }
         }
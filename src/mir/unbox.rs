@@ -0,0 +1,127 @@
+use crate::config::Config;
+use crate::id::Id;
+use crate::mir::*;
+use crate::pass::Pass;
+use crate::prim::*;
+use std::collections::HashMap;
+
+/// Unboxing of small tuples across calls.
+/// A function taking a tuple of 2 or 3 scalars receives the elements
+/// as separate parameters. The callers pass projections of the tuple.
+/// The tuple is rebuilt at the entry of the function
+/// and `EscapeAnalysis` removes it if it is only projected.
+pub struct Unbox {
+    id: Id,
+}
+
+fn is_scalar(ty: &EbbTy) -> bool {
+    use crate::mir::EbbTy::*;
+    match ty {
+        Char | Int | Word | Float | Bool => true,
+        _ => false,
+    }
+}
+
+/// element types of the parameter if the function is subject to unboxing
+fn unboxed_params(fun: &Function) -> Option<Vec<EbbTy>> {
+    let entry = &fun.body[0];
+    let tys = match entry.params.as_slice() {
+        [(EbbTy::Tuple(tys), _)] => tys,
+        _ => return None,
+    };
+    if !(2 <= tys.len() && tys.len() <= 3 && tys.iter().all(is_scalar)) {
+        return None;
+    }
+    // the entry block must not be a jump target to change its parameters
+    if fun
+        .body
+        .iter()
+        .any(|ebb| ebb.next_ebbs().iter().any(|(next, _)| *next == &entry.name))
+    {
+        return None;
+    }
+    Some(tys.clone())
+}
+
+impl Unbox {
+    pub fn new(id: Id) -> Self {
+        Unbox { id }
+    }
+
+    fn gensym(&mut self) -> Symbol {
+        let id = self.id.next();
+        Symbol("#g".into(), id)
+    }
+
+    fn conv_mir(&mut self, mir: MIR) -> MIR {
+        let unboxed = mir
+            .0
+            .iter()
+            .filter_map(|fun| unboxed_params(fun).map(|tys| (fun.name.clone(), tys)))
+            .collect::<HashMap<_, _>>();
+        MIR(mir
+            .0
+            .into_iter()
+            .map(|fun| self.conv_fun(&unboxed, fun))
+            .collect())
+    }
+
+    fn conv_fun(&mut self, unboxed: &HashMap<Symbol, Vec<EbbTy>>, mut fun: Function) -> Function {
+        if let Some(tys) = unboxed.get(&fun.name) {
+            let entry = &mut fun.body[0];
+            let (ty, tuple) = entry.params.remove(0);
+            entry.params = tys.iter().map(|ty| (ty.clone(), self.gensym())).collect();
+            let rebox = Op::Tuple {
+                var: tuple,
+                tys: match ty {
+                    EbbTy::Tuple(tys) => tys,
+                    _ => unreachable!(),
+                },
+                tuple: entry.params.iter().map(|(_, var)| var.clone()).collect(),
+            };
+            entry.body.insert(0, rebox);
+        }
+        for ebb in fun.body.iter_mut() {
+            let body = ::std::mem::replace(&mut ebb.body, Vec::new());
+            for op in body {
+                match op {
+                    Op::Call {
+                        var,
+                        ty,
+                        fun,
+                        mut args,
+                    } => {
+                        if let (Some(tys), 1) = (unboxed.get(&fun), args.len()) {
+                            let tuple = args.remove(0);
+                            for (index, ty) in tys.iter().enumerate() {
+                                let elem = self.gensym();
+                                ebb.body.push(Op::Proj {
+                                    var: elem.clone(),
+                                    ty: ty.clone(),
+                                    index: index as u32,
+                                    tuple: tuple.clone(),
+                                });
+                                args.push(elem);
+                            }
+                        }
+                        ebb.body.push(Op::Call { var, ty, fun, args })
+                    }
+                    op => ebb.body.push(op),
+                }
+            }
+        }
+        fun
+    }
+}
+
+impl<E> Pass<(SymbolTable, MIR), E> for Unbox {
+    type Target = (SymbolTable, MIR);
+
+    fn trans(
+        &mut self,
+        (symbol_table, mir): (SymbolTable, MIR),
+        _: &Config,
+    ) -> ::std::result::Result<Self::Target, E> {
+        Ok((symbol_table, self.conv_mir(mir)))
+    }
+}
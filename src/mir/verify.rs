@@ -0,0 +1,153 @@
+use crate::ast::TypeError;
+use crate::config::Config;
+use crate::mir::*;
+use crate::pass::Pass;
+use crate::prim::*;
+use petgraph::algo::dominators;
+use petgraph::graph::NodeIndex;
+use std::collections::{HashMap, HashSet};
+
+/// Checks the invariants of MIR.
+///
+/// - all the used variables are defined in the function or are functions
+/// - each variable is assigned once, before its uses in the same block or in a block
+///   dominating them, which makes MIR SSA
+/// - jump targets exist and receive as many arguments as their parameters.
+///   the default target of a branch receives the condition
+pub struct Verify;
+
+type Result<T> = ::std::result::Result<T, String>;
+
+impl Verify {
+    pub fn new() -> Self {
+        Verify
+    }
+
+    fn verify_mir(&mut self, mir: &MIR) -> Result<()> {
+        let mut functions = HashSet::new();
+        for fun in mir.0.iter() {
+            if !functions.insert(&fun.name) {
+                return Err(format!("function {:?} is defined twice", fun.name));
+            }
+        }
+        for fun in mir.0.iter() {
+            self.verify_fun(&functions, fun)
+                .map_err(|e| format!("in {:?}: {}", fun.name, e))?;
+        }
+        Ok(())
+    }
+
+    fn verify_fun(&mut self, functions: &HashSet<&Symbol>, fun: &Function) -> Result<()> {
+        if fun.body.is_empty() {
+            return Err("function has no blocks".into());
+        }
+
+        let mut ebbs = HashMap::new();
+        let mut vars: HashSet<&Symbol> = HashSet::new();
+        for ebb in fun.body.iter() {
+            if ebbs.insert(&ebb.name, ebb.params.len()).is_some() {
+                return Err(format!("block {:?} is defined twice", ebb.name));
+            }
+            for var in ebb.defs() {
+                if !vars.insert(var) {
+                    return Err(format!("variable {:?} is assigned twice", var));
+                }
+            }
+        }
+
+        for ebb in fun.body.iter() {
+            let uses = ebb.body.iter().flat_map(Op::uses).chain(ebb.term.uses());
+            for var in uses {
+                if !vars.contains(var) && !functions.contains(var) {
+                    return Err(format!("undefined variable {:?} in {:?}", var, ebb.name));
+                }
+            }
+
+            for (target, _) in ebb.next_ebbs() {
+                if !ebbs.contains_key(target) {
+                    return Err(format!(
+                        "{:?} jumps to unknown block {:?}",
+                        ebb.name, target
+                    ));
+                }
+            }
+            match &ebb.term {
+                Terminator::Jump { target, args, .. } if ebbs[target] != args.len() => {
+                    return Err(format!(
+                        "{:?} passes {} arguments to {:?} which takes {}",
+                        ebb.name,
+                        args.len(),
+                        target,
+                        ebbs[target]
+                    ));
+                }
+                Terminator::Branch {
+                    clauses, default, ..
+                } => {
+                    let targets = clauses
+                        .iter()
+                        .map(|(_, target, _)| (target, 0))
+                        .chain(default.iter().map(|(target, _)| (target, 1)));
+                    for (target, nargs) in targets {
+                        if ebbs[target] != nargs {
+                            return Err(format!(
+                                "{:?} passes {} arguments to {:?} which takes {}",
+                                ebb.name, nargs, target, ebbs[target]
+                            ));
+                        }
+                    }
+                }
+                _ => (),
+            }
+        }
+        self.verify_dominance(functions, fun)
+    }
+
+    fn verify_dominance(&mut self, functions: &HashSet<&Symbol>, fun: &Function) -> Result<()> {
+        let cfg = fun.cfg();
+        let dominators = dominators::simple_fast(&cfg, NodeIndex::new(0));
+        for (index, ebb) in fun.body.iter().enumerate() {
+            let mut defined = match dominators.strict_dominators(NodeIndex::new(index)) {
+                Some(doms) => doms
+                    .flat_map(|dom| fun.body[cfg[dom]].defs())
+                    .collect::<HashSet<_>>(),
+                // the block is unreachable
+                None => continue,
+            };
+            defined.extend(ebb.params.iter().map(|(_, var)| var));
+            let check = |defined: &HashSet<&Symbol>, var: &Symbol| {
+                if defined.contains(var) || functions.contains(var) {
+                    Ok(())
+                } else {
+                    Err(format!(
+                        "variable {:?} is used in {:?} where it may not be defined",
+                        var, ebb.name
+                    ))
+                }
+            };
+            for op in ebb.body.iter() {
+                for var in op.uses() {
+                    check(&defined, var)?;
+                }
+                defined.insert(op.var());
+            }
+            for var in ebb.term.uses() {
+                check(&defined, var)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'a> Pass<(SymbolTable, MIR), TypeError<'a>> for Verify {
+    type Target = (SymbolTable, MIR);
+
+    fn trans(
+        &mut self,
+        (symbol_table, mir): (SymbolTable, MIR),
+        _: &Config,
+    ) -> ::std::result::Result<Self::Target, TypeError<'a>> {
+        self.verify_mir(&mir).map_err(TypeError::InvalidIR)?;
+        Ok((symbol_table, mir))
+    }
+}
@@ -1,23 +1,159 @@
 use crate::ast::*;
 use crate::prim::*;
+use crate::util::ensure_stack;
 use nom::branch::alt;
 use nom::bytes::complete::tag;
-use nom::character::complete::{alphanumeric1, digit1, multispace0, multispace1};
-use nom::combinator::{all_consuming, complete, map, map_res, opt, recognize, value, verify};
+use nom::character::complete::{alphanumeric1, digit1, hex_digit1, one_of};
+use nom::combinator::{all_consuming, complete, map, map_res, not, opt, recognize, value, verify};
 use nom::multi::{many1, separated_list, separated_nonempty_list};
 use nom::number::complete::recognize_float;
-use nom::sequence::{preceded, terminated, tuple};
+use nom::sequence::{delimited, preceded, terminated, tuple};
 use nom::IResult;
 use std::cell::RefCell;
 use std::collections::{BTreeMap, HashMap};
+use std::str::FromStr;
+use tracing::info;
 
 static KEYWORDS: &[&str] = &[
-    "val", "fun", "fn", "let", "in", "end", "if", "then", "else", "case", "of", "_", "datatype",
-    "op", "=>", "infix", "infixr",
+    "val",
+    "fun",
+    "fn",
+    "let",
+    "in",
+    "end",
+    "if",
+    "then",
+    "else",
+    "case",
+    "of",
+    "_",
+    "datatype",
+    "op",
+    "=>",
+    "infix",
+    "infixr",
+    "exception",
+    "raise",
+    "handle",
+    "withtype",
 ];
 
 static RESERVED: &[&str] = &["|", "=", "#"];
 
+/// a comment, which may nest
+fn comment(i: &str) -> IResult<&str, &str> {
+    let (mut rest, _) = tag("(*")(i)?;
+    let mut depth = 1;
+    while depth > 0 {
+        if rest.starts_with("*)") {
+            depth -= 1;
+            rest = &rest[2..];
+        } else if rest.starts_with("(*") {
+            depth += 1;
+            rest = &rest[2..];
+        } else {
+            let c = rest
+                .chars()
+                .next()
+                .ok_or_else(|| nom::Err::Error((rest, nom::error::ErrorKind::Eof)))?;
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+    Ok((rest, &i[..i.len() - rest.len()]))
+}
+
+/// a character which may follow the first letter of an alphanumeric identifier
+fn name_char(i: &str) -> IResult<&str, char> {
+    match i.chars().next() {
+        Some(c) if c.is_ascii_alphanumeric() || c == '_' || c == '\'' => {
+            Ok((&i[c.len_utf8()..], c))
+        }
+        _ => Err(nom::Err::Error((i, nom::error::ErrorKind::AlphaNumeric))),
+    }
+}
+
+/// the keyword, which is not a prefix of an identifier like `valx` or `trueish`
+fn keyword<'a>(keyword: &'static str) -> impl Fn(&'a str) -> IResult<&'a str, &'a str> {
+    move |i| terminated(tag(keyword), not(name_char))(i)
+}
+
+/// the digits of a literal of `T`. too large literals fail at the literal, without trying the
+/// other alternatives
+fn integer<T: FromStr>(i: &str) -> IResult<&str, T> {
+    let (rest, digits) = digit1(i)?;
+    match digits.parse() {
+        Ok(n) => Ok((rest, n)),
+        Err(_) => Err(nom::Err::Failure((digits, nom::error::ErrorKind::TooLarge))),
+    }
+}
+
+/// the word literal, `0w` followed by the decimal digits or `0wx` by the hexadecimal digits
+fn word(i: &str) -> IResult<&str, u32> {
+    let (i, _) = tag("0w")(i)?;
+    let (rest, (digits, radix)) = alt((
+        map(preceded(tag("x"), hex_digit1), |digits| (digits, 16)),
+        map(digit1, |digits| (digits, 10)),
+    ))(i)?;
+    match u32::from_str_radix(digits, radix) {
+        Ok(n) => Ok((rest, n)),
+        Err(_) => Err(nom::Err::Failure((digits, nom::error::ErrorKind::TooLarge))),
+    }
+}
+
+/// `(` which doesn't start a comment
+fn open_paren(i: &str) -> IResult<&str, &str> {
+    terminated(tag("("), not(tag("*")))(i)
+}
+
+/// the characters of the symbolic identifiers
+const SYMBOLIC_CHARS: &str = "!%&$#+-/:<=>?@\\~`^|*";
+
+/// `=` as an identifier, which is not the head of a longer symbol like `=>`
+fn equal(i: &str) -> IResult<&str, &str> {
+    terminated(tag("="), not(one_of(SYMBOLIC_CHARS)))(i)
+}
+
+/// an alphanumeric identifier, which starts with a letter.
+/// it may be qualified by the structures, as `Int.toString`, and then may be symbolic, as `Word.<<`
+fn alphanumeric_name(i: &str) -> IResult<&str, &str> {
+    let name_len = |s: &str| match s.chars().next() {
+        Some(c) if c.is_ascii_alphabetic() => Some(
+            s.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '\''))
+                .unwrap_or(s.len()),
+        ),
+        _ => None,
+    };
+    let mut len = match name_len(i) {
+        Some(len) => len,
+        None => return Err(nom::Err::Error((i, nom::error::ErrorKind::AlphaNumeric))),
+    };
+    while i[len..].starts_with('.') {
+        let rest = &i[len + 1..];
+        match name_len(rest) {
+            Some(l) => len += 1 + l,
+            None => {
+                let l = rest
+                    .find(|c| !SYMBOLIC_CHARS.contains(c))
+                    .unwrap_or(rest.len());
+                if l != 0 {
+                    len += 1 + l;
+                }
+                break;
+            }
+        }
+    }
+    Ok((&i[len..], &i[..len]))
+}
+
+/// whitespaces and comments
+fn multispace1(i: &str) -> IResult<&str, &str> {
+    recognize(many1(alt((nom::character::complete::multispace1, comment))))(i)
+}
+
+fn multispace0(i: &str) -> IResult<&str, &str> {
+    recognize(opt(multispace1))(i)
+}
+
 struct Parser {
     infixes: RefCell<Vec<BTreeMap<u8, Vec<Symbol>>>>,
 }
@@ -80,32 +216,80 @@ impl Parser {
         move |i| {
             alt((
                 self.decl_datatype(),
+                self.decl_exception(),
                 self.decl_val(),
                 self.decl_fun(),
                 self.decl_infix(),
+                self.decl_import(),
             ))(i)
         }
     }
 
     fn decl_datatype(&self) -> impl Fn(&str) -> IResult<&str, Declaration<()>> + '_ {
         move |i| {
-            let (i, _) = tag("datatype")(i)?;
+            let (i, _) = keyword("datatype")(i)?;
             let (i, _) = multispace1(i)?;
             let (i, name) = self.symbol()(i)?;
             let (i, _) = multispace0(i)?;
             let (i, _) = tag("=")(i)?;
             let (i, _) = multispace0(i)?;
+            let replication = preceded(tuple((keyword("datatype"), multispace1)), self.symbol());
+            if let Ok((i, datatype)) = replication(i) {
+                let decl = DerivedDeclaration::Replication { name, datatype };
+                return Ok((i, Declaration::D(decl)));
+            }
             let (i, constructors) = separated_nonempty_list(
                 tuple((multispace0, tag("|"), multispace0)),
                 self.constructor_def(),
             )(i)?;
-            Ok((i, Declaration::Datatype { name, constructors }))
+            let (i, types) = opt(complete(map(
+                tuple((
+                    multispace1,
+                    keyword("withtype"),
+                    multispace1,
+                    separated_nonempty_list(
+                        tuple((multispace1, keyword("and"), multispace1)),
+                        self.type_def(),
+                    ),
+                )),
+                |(_, _, _, types)| types,
+            )))(i)?;
+            let decl = match types {
+                None => Declaration::Datatype { name, constructors },
+                Some(types) => Declaration::D(DerivedDeclaration::Withtype {
+                    name,
+                    constructors,
+                    types,
+                }),
+            };
+            Ok((i, decl))
+        }
+    }
+
+    /// `name = ty` of `withtype`
+    fn type_def(&self) -> impl Fn(&str) -> IResult<&str, (Symbol, Type)> + '_ {
+        move |i| {
+            let (i, name) = self.symbol()(i)?;
+            let (i, _) = multispace0(i)?;
+            let (i, _) = tag("=")(i)?;
+            let (i, _) = multispace0(i)?;
+            let (i, ty) = self.typename()(i)?;
+            Ok((i, (name, ty)))
+        }
+    }
+
+    fn decl_exception(&self) -> impl Fn(&str) -> IResult<&str, Declaration<()>> + '_ {
+        move |i| {
+            let (i, _) = keyword("exception")(i)?;
+            let (i, _) = multispace1(i)?;
+            let (i, (name, arg)) = self.constructor_def()(i)?;
+            Ok((i, Declaration::Exception { name, arg }))
         }
     }
 
     fn decl_val(&self) -> impl Fn(&str) -> IResult<&str, Declaration<()>> + '_ {
         move |i| {
-            let (i, _) = tag("val")(i)?;
+            let (i, _) = keyword("val")(i)?;
             let (i, _) = multispace1(i)?;
             let (i, pattern) = self.pattern()(i)?;
             let (i, _) = multispace0(i)?;
@@ -125,7 +309,7 @@ impl Parser {
 
     fn decl_fun(&self) -> impl Fn(&str) -> IResult<&str, Declaration<()>> + '_ {
         move |i| {
-            let (i, _) = tag("fun")(i)?;
+            let (i, _) = keyword("fun")(i)?;
             let (i, _) = multispace1(i)?;
             let (i, cs) = separated_nonempty_list(
                 tuple((multispace0, tag("|"), multispace0)),
@@ -170,7 +354,7 @@ impl Parser {
         move |i| {
             let (i, name) = self.symbol()(i)?;
             let (i, param) = opt(complete(map(
-                tuple((multispace1, tag("of"), multispace1, self.typename())),
+                tuple((multispace1, keyword("of"), multispace1, self.typename())),
                 |(_, _, _, ty)| ty,
             )))(i)?;
 
@@ -180,15 +364,10 @@ impl Parser {
 
     fn decl_infix(&self) -> impl Fn(&str) -> IResult<&str, Declaration<()>> + '_ {
         move |i| {
-            let (i, _) = tag("infix")(i)?;
-            let (i, _) = multispace1(i)?;
-            let (i, priority) = opt(digit1)(i)?;
+            let (i, _) = keyword("infix")(i)?;
             let (i, _) = multispace1(i)?;
+            let (i, priority) = opt(terminated(integer::<u8>, multispace1))(i)?;
             let (i, names) = separated_nonempty_list(multispace1, self.symbol_eq())(i)?;
-            let priority = priority.map(|s| {
-                s.parse()
-                    .expect("internal error: falied to parse digits as integer")
-            });
             self.new_infix_op(priority, names.clone());
             Ok((
                 i,
@@ -197,27 +376,74 @@ impl Parser {
         }
     }
 
+    fn decl_import(&self) -> impl Fn(&str) -> IResult<&str, Declaration<()>> + '_ {
+        move |i| {
+            let (i, _) = keyword("_import")(i)?;
+            let (i, _) = multispace1(i)?;
+            let (i, module) = map(self.quoted_name(), String::from)(i)?;
+            let (i, _) = multispace1(i)?;
+            let (i, name) = delimited(tag("\""), self.symbol_alphanumeric(), tag("\""))(i)?;
+            let (i, _) = multispace0(i)?;
+            let (i, _) = tag(":")(i)?;
+            let (i, _) = multispace0(i)?;
+            let (i, ty) = self.typename()(i)?;
+            let (argty, retty) = match ty {
+                Type::Fun(argty, retty) => (*argty, *retty),
+                _ => return Err(nom::Err::Error((i, nom::error::ErrorKind::Verify))),
+            };
+            Ok((
+                i,
+                Declaration::D(DerivedDeclaration::Import {
+                    module,
+                    name,
+                    argty,
+                    retty,
+                }),
+            ))
+        }
+    }
+
     fn expr(&self) -> impl Fn(&str) -> IResult<&str, Expr<()>> + '_ {
         move |i| {
-            alt((
-                self.expr_bind(),
-                self.expr_fun(),
-                self.expr_if(),
-                self.expr_case(),
-                self.expr_infix_and_app(),
-            ))(i)
+            ensure_stack(|| {
+                let (i, expr) = alt((
+                    self.expr_fun(),
+                    self.expr_if(),
+                    self.expr_case(),
+                    self.expr_raise(),
+                    self.expr_infix_and_app(),
+                ))(i)?;
+                let (i, handler) = opt(complete(map(
+                    tuple((multispace1, keyword("handle"), multispace1, self.clauses())),
+                    |(_, _, _, clauses)| clauses,
+                )))(i)?;
+                let expr = match handler {
+                    None => expr,
+                    Some(clauses) => Expr {
+                        ty: (),
+                        inner: ExprKind::D(DerivedExprKind::Handle {
+                            expr: expr.boxed(),
+                            clauses,
+                        }),
+                    },
+                };
+                Ok((i, expr))
+            })
         }
     }
 
     fn expr1(&self) -> impl Fn(&str) -> IResult<&str, Expr<()>> + '_ {
         move |i| {
             alt((
+                self.expr_bind(),
                 self.expr1_tuple(),
                 self.expr1_unit(),
                 self.expr1_paren(),
                 self.expr1_float(),
+                self.expr1_word(),
                 self.expr1_int(),
                 self.expr1_char(),
+                self.expr1_string(),
                 self.expr1_bool(),
                 self.expr1_sym(),
                 self.expr1_builtincall(),
@@ -229,15 +455,15 @@ impl Parser {
     fn expr_bind(&self) -> impl Fn(&str) -> IResult<&str, Expr<()>> + '_ {
         move |i| {
             self.with_scope(|| {
-                let (i, _) = tag("let")(i)?;
+                let (i, _) = keyword("let")(i)?;
                 let (i, _) = multispace1(i)?;
                 let (i, binds) = separated_list(multispace1, self.decl())(i)?;
                 let (i, _) = multispace1(i)?;
-                let (i, _) = tag("in")(i)?;
+                let (i, _) = keyword("in")(i)?;
                 let (i, _) = multispace1(i)?;
                 let (i, ret) = self.expr()(i)?;
                 let (i, _) = multispace1(i)?;
-                let (i, _) = tag("end")(i)?;
+                let (i, _) = keyword("end")(i)?;
                 Ok((
                     i,
                     Expr {
@@ -254,7 +480,7 @@ impl Parser {
 
     fn expr_fun(&self) -> impl Fn(&str) -> IResult<&str, Expr<()>> + '_ {
         move |i| {
-            let (i, _) = tag("fn")(i)?;
+            let (i, _) = keyword("fn")(i)?;
             let (i, _) = multispace1(i)?;
             let (i, param) = self.symbol()(i)?;
             let (i, _) = multispace0(i)?;
@@ -276,15 +502,15 @@ impl Parser {
 
     fn expr_if(&self) -> impl Fn(&str) -> IResult<&str, Expr<()>> + '_ {
         move |i| {
-            let (i, _) = tag("if")(i)?;
+            let (i, _) = keyword("if")(i)?;
             let (i, _) = multispace1(i)?;
             let (i, cond) = self.expr()(i)?;
             let (i, _) = multispace1(i)?;
-            let (i, _) = tag("then")(i)?;
+            let (i, _) = keyword("then")(i)?;
             let (i, _) = multispace1(i)?;
             let (i, then) = self.expr()(i)?;
             let (i, _) = multispace1(i)?;
-            let (i, _) = tag("else")(i)?;
+            let (i, _) = keyword("else")(i)?;
             let (i, _) = multispace1(i)?;
             let (i, else_) = self.expr()(i)?;
             Ok((
@@ -303,32 +529,83 @@ impl Parser {
 
     fn expr_case(&self) -> impl Fn(&str) -> IResult<&str, Expr<()>> + '_ {
         move |i| {
-            let (i, _) = tag("case")(i)?;
+            let (i, _) = keyword("case")(i)?;
             let (i, _) = multispace1(i)?;
             let (i, cond) = self.expr()(i)?;
             let (i, _) = multispace1(i)?;
-            let (i, _) = tag("of")(i)?;
+            let (i, _) = keyword("of")(i)?;
             let (i, _) = multispace1(i)?;
-            let (i, clauses) = separated_nonempty_list(
+            let (i, clauses) = self.guarded_clauses()(i)?;
+            let inner = if clauses.iter().any(|(_, guard, _)| guard.is_some()) {
+                ExprKind::D(DerivedExprKind::GuardedCase {
+                    cond: cond.boxed(),
+                    clauses,
+                })
+            } else {
+                ExprKind::Case {
+                    cond: cond.boxed(),
+                    clauses: clauses
+                        .into_iter()
+                        .map(|(pat, _, expr)| (pat, expr))
+                        .collect(),
+                }
+            };
+            Ok((i, Expr { ty: (), inner }))
+        }
+    }
+
+    /// `pat => expr | pat => expr ...` of `handle`
+    fn clauses(&self) -> impl Fn(&str) -> IResult<&str, Vec<(Pattern<()>, Expr<()>)>> + '_ {
+        move |i| {
+            let (rest, clauses) = self.guarded_clauses()(i)?;
+            if clauses.iter().any(|(_, guard, _)| guard.is_some()) {
+                return Err(nom::Err::Error((i, nom::error::ErrorKind::Verify)));
+            }
+            let clauses = clauses
+                .into_iter()
+                .map(|(pat, _, expr)| (pat, expr))
+                .collect();
+            Ok((rest, clauses))
+        }
+    }
+
+    /// `pat when expr => expr | pat => expr ...` of `case`, where the guards are optional
+    fn guarded_clauses(
+        &self,
+    ) -> impl Fn(&str) -> IResult<&str, Vec<(Pattern<()>, Option<Expr<()>>, Expr<()>)>> + '_ {
+        move |i| {
+            separated_nonempty_list(
                 tuple((multispace0, tag("|"), multispace0)),
                 map(
                     tuple((
-                        self.pattern(),
+                        self.pattern_or(),
+                        opt(map(
+                            tuple((multispace1, keyword("when"), multispace1, self.expr())),
+                            |(_, _, _, guard)| guard,
+                        )),
                         multispace0,
                         tag("=>"),
                         multispace0,
                         self.expr(),
                     )),
-                    |(pat, _, _, _, expr)| (pat, expr),
+                    |(pat, guard, _, _, _, expr)| (pat, guard, expr),
                 ),
-            )(i)?;
+            )(i)
+        }
+    }
+
+    fn expr_raise(&self) -> impl Fn(&str) -> IResult<&str, Expr<()>> + '_ {
+        move |i| {
+            let (i, _) = keyword("raise")(i)?;
+            let (i, _) = multispace1(i)?;
+            let (i, exn) = self.expr()(i)?;
             Ok((
                 i,
                 Expr {
                     ty: (),
-                    inner: ExprKind::Case {
-                        cond: cond.boxed(),
-                        clauses: clauses,
+                    inner: ExprKind::BuiltinCall {
+                        fun: BIF::Raise,
+                        args: vec![exn],
                     },
                 },
             ))
@@ -339,7 +616,16 @@ impl Parser {
     fn expr_infix_and_app(&self) -> impl Fn(&str) -> IResult<&str, Expr<()>> + '_ {
         move |i| {
             // TODO: support 1+1
-            let (i, mixed) = many1(map(tuple((multispace0, self.expr1())), |(_, e)| e))(i)?;
+            let (i, mixed) = many1(preceded(
+                multispace0,
+                alt((
+                    map(self.op_symbol(), |name| {
+                        let inner = ExprKind::Symbol { name };
+                        (true, Expr { ty: (), inner })
+                    }),
+                    map(self.expr1(), |e| (false, e)),
+                )),
+            ))(i)?;
             #[derive(Debug)]
             enum Mixed {
                 E(Expr<()>),
@@ -349,8 +635,9 @@ impl Parser {
             // find infixes
             let mixed = mixed
                 .into_iter()
-                .map(|mut e| match e.inner {
-                    ExprKind::Symbol { name } => {
+                .map(|(op, mut e)| match e.inner {
+                    // `op sym` is not an infix operator
+                    ExprKind::Symbol { name } if !op => {
                         for (f, table) in self.get_table() {
                             if table.contains(&name) {
                                 return Fix(f, name);
@@ -405,35 +692,43 @@ impl Parser {
                     (m1, m2, m3) => (m1, Some((m2, m3))),
                 })
             }
-            let mut rest = (1u8..=9)
+            let mut rest = (0u8..=9)
                 .rev()
                 .fold(rest, |rest, n| reduce_infixl_n(n, rest));
-            assert_eq!(rest.len(), 1);
-            let e = match rest.remove(0) {
-                E(e) => e,
-                Fix(..) => unreachable!("infix alone"),
-            };
-            Ok((i, e))
+            // operators without the operands are left
+            match (rest.pop(), rest.is_empty()) {
+                (Some(E(e)), true) => Ok((i, e)),
+                _ => Err(nom::Err::Error((i, nom::error::ErrorKind::Verify))),
+            }
         }
     }
     fn expr1_sym(&self) -> impl Fn(&str) -> IResult<&str, Expr<()>> + '_ {
         move |i| {
             // = is allowed to be used in expression exceptionally
-            map(alt((self.symbol(), map(tag("="), Symbol::new))), |name| {
-                Expr {
-                    ty: (),
-                    inner: ExprKind::Symbol { name },
-                }
+            map(alt((self.symbol(), map(equal, Symbol::new))), |name| Expr {
+                ty: (),
+                inner: ExprKind::Symbol { name },
             })(i)
         }
     }
 
     fn expr1_int(&self) -> impl Fn(&str) -> IResult<&str, Expr<()>> + '_ {
         move |i| {
-            map(digit1, |s: &str| Expr {
+            map(integer, |n| Expr {
                 ty: (),
                 inner: ExprKind::Literal {
-                    value: Literal::Int(s.parse().unwrap()),
+                    value: Literal::Int(n),
+                },
+            })(i)
+        }
+    }
+
+    fn expr1_word(&self) -> impl Fn(&str) -> IResult<&str, Expr<()>> + '_ {
+        move |i| {
+            map(word, |w| Expr {
+                ty: (),
+                inner: ExprKind::Literal {
+                    value: Literal::Word(w),
                 },
             })(i)
         }
@@ -443,10 +738,10 @@ impl Parser {
         move |i| {
             let not_int = verify(recognize_float, |s: &&str| s.contains('.'));
 
-            map(not_int, |s: &str| Expr {
+            map(map_res(not_int, |s: &str| s.parse()), |f| Expr {
                 ty: (),
                 inner: ExprKind::Literal {
-                    value: Literal::Real(s.parse().unwrap()),
+                    value: Literal::Real(f),
                 },
             })(i)
         }
@@ -455,9 +750,7 @@ impl Parser {
     fn expr1_char(&self) -> impl Fn(&str) -> IResult<&str, Expr<()>> + '_ {
         move |i| {
             let (i, _) = tag("#")(i)?;
-            let (i, s) = self.string_literal()(i)?;
-            assert_eq!(s.iter().count(), 1);
-            let c = s.into_iter().next().unwrap();
+            let (i, c) = self.char_literal()(i)?;
             Ok((
                 i,
                 Expr {
@@ -475,17 +768,49 @@ impl Parser {
             let (i, _) = tag("\"")(i)?;
             let mut s = vec![];
             let mut chars = i.chars();
-            while let Some(c) = chars.next() {
-                if c == '"' {
-                    break;
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') => match chars.next() {
+                        Some('n') => s.push('\n' as u32),
+                        Some('t') => s.push('\t' as u32),
+                        Some(c @ '\\') | Some(c @ '"') => s.push(c as u32),
+                        _ => return Err(nom::Err::Error((i, nom::error::ErrorKind::Escaped))),
+                    },
+                    Some(c) => s.push(c as u32),
+                    None => return Err(nom::Err::Error((i, nom::error::ErrorKind::Eof))),
                 }
-                s.push(c as u32)
             }
             let i = chars.as_str();
             Ok((i, s))
         }
     }
 
+    fn expr1_string(&self) -> impl Fn(&str) -> IResult<&str, Expr<()>> + '_ {
+        move |i| {
+            let (i, s) = self.string_literal()(i)?;
+            // the characters are decoded from the `str`, so they are valid
+            let value = s.into_iter().filter_map(std::char::from_u32).collect();
+            Ok((
+                i,
+                Expr {
+                    ty: (),
+                    inner: ExprKind::Literal {
+                        value: Literal::String(value),
+                    },
+                },
+            ))
+        }
+    }
+
+    /// the string literal of one character
+    fn char_literal(&self) -> impl Fn(&str) -> IResult<&str, u32> + '_ {
+        move |i| match self.string_literal()(i)? {
+            (rest, s) if s.len() == 1 => Ok((rest, s[0])),
+            _ => Err(nom::Err::Error((i, nom::error::ErrorKind::Verify))),
+        }
+    }
+
     fn expr1_bool(&self) -> impl Fn(&str) -> IResult<&str, Expr<()>> + '_ {
         move |i| {
             alt((
@@ -497,7 +822,7 @@ impl Parser {
                             arg: None,
                         },
                     },
-                    tag("true"),
+                    keyword("true"),
                 ),
                 value(
                     Expr {
@@ -507,7 +832,7 @@ impl Parser {
                             arg: None,
                         },
                     },
-                    tag("false"),
+                    keyword("false"),
                 ),
             ))(i)
         }
@@ -515,7 +840,7 @@ impl Parser {
 
     fn expr1_paren(&self) -> impl Fn(&str) -> IResult<&str, Expr<()>> + '_ {
         move |i| {
-            let (i, _) = tag("(")(i)?;
+            let (i, _) = open_paren(i)?;
             let (i, _) = multispace0(i)?;
             let (i, e) = self.expr()(i)?;
             let (i, _) = multispace0(i)?;
@@ -526,7 +851,7 @@ impl Parser {
 
     fn expr1_tuple(&self) -> impl Fn(&str) -> IResult<&str, Expr<()>> + '_ {
         move |i| {
-            let (i, _) = tag("(")(i)?;
+            let (i, _) = open_paren(i)?;
             let (i, _) = multispace0(i)?;
             let sep = tuple((multispace0, tag(","), multispace0));
             let (i, es) = many1(map(tuple((self.expr(), sep)), |(e, _)| e))(i)?;
@@ -553,14 +878,14 @@ impl Parser {
                     ty: (),
                     inner: ExprKind::Tuple { tuple: vec![] },
                 },
-                tuple((tag("("), multispace0, tag(")"))),
+                tuple((open_paren, multispace0, tag(")"))),
             )(i)
         }
     }
 
     fn expr1_builtincall(&self) -> impl Fn(&str) -> IResult<&str, Expr<()>> + '_ {
         move |i| {
-            let (i, _) = tag("_builtincall")(i)?;
+            let (i, _) = keyword("_builtincall")(i)?;
             let (i, _) = multispace0(i)?;
             let (i, _) = tag("\"")(i)?;
             let (i, fun) = map_res(alphanumeric1, |name| match name {
@@ -576,11 +901,14 @@ impl Parser {
                 "ge" => Ok(BIF::Ge),
                 "lt" => Ok(BIF::Lt),
                 "le" => Ok(BIF::Le),
+                "raise" => Ok(BIF::Raise),
+                "handle" => Ok(BIF::Handle),
+                "hash" => Ok(BIF::Hash),
                 _ => Err(nom::Err::Error(nom::error::ErrorKind::Tag)),
             })(i)?;
             let (i, _) = tag("\"")(i)?;
             let (i, _) = multispace0(i)?;
-            let (i, _) = tag("(")(i)?;
+            let (i, _) = open_paren(i)?;
             let (i, args) = separated_nonempty_list(
                 tuple((multispace0, tag(","), multispace0)),
                 self.expr(),
@@ -596,26 +924,29 @@ impl Parser {
         }
     }
 
+    fn quoted_name(&self) -> impl Fn(&str) -> IResult<&str, &str> + '_ {
+        move |i| {
+            let allowed = recognize(many1(nom::character::complete::none_of("\"")));
+            preceded(tag("\""), terminated(allowed, tag("\"")))(i)
+        }
+    }
+
     /// `_externcall ("module"."fun": (arg, ty) -> retty) (arg, s)`
     fn expr1_externcall(&self) -> impl Fn(&str) -> IResult<&str, Expr<()>> + '_ {
         move |i| {
-            fn name_parser(i: &str) -> IResult<&str, &str> {
-                let allowed = recognize(many1(nom::character::complete::none_of("\"")));
-                preceded(tag("\""), terminated(allowed, tag("\"")))(i)
-            }
-            let (i, _) = tag("_externcall")(i)?;
+            let (i, _) = keyword("_externcall")(i)?;
             let (i, _) = multispace0(i)?;
-            let (i, _) = tag("(")(i)?;
+            let (i, _) = open_paren(i)?;
             let (i, _) = multispace0(i)?;
-            let (i, module) = map(name_parser, String::from)(i)?;
+            let (i, module) = map(self.quoted_name(), String::from)(i)?;
             let (i, _) = multispace0(i)?;
             let (i, _) = tag(".")(i)?;
             let (i, _) = multispace0(i)?;
-            let (i, fun) = map(name_parser, String::from)(i)?;
+            let (i, fun) = map(self.quoted_name(), String::from)(i)?;
             let (i, _) = multispace0(i)?;
             let (i, _) = tag(":")(i)?;
             let (i, _) = multispace0(i)?;
-            let (i, _) = tag("(")(i)?;
+            let (i, _) = open_paren(i)?;
             let (i, argty) = separated_nonempty_list(
                 tuple((multispace0, tag(","), multispace0)),
                 self.typename(),
@@ -628,7 +959,7 @@ impl Parser {
             let (i, _) = multispace0(i)?;
             let (i, _) = tag(")")(i)?;
             let (i, _) = multispace0(i)?;
-            let (i, _) = tag("(")(i)?;
+            let (i, _) = open_paren(i)?;
             let (i, args) = separated_nonempty_list(
                 tuple((multispace0, tag(","), multispace0)),
                 self.expr(),
@@ -692,7 +1023,7 @@ impl Parser {
 
     fn typename2_paren(&self) -> impl Fn(&str) -> IResult<&str, Type> + '_ {
         move |i| {
-            let (i, _) = tag("(")(i)?;
+            let (i, _) = open_paren(i)?;
             let (i, _) = multispace0(i)?;
             let (i, ty) = self.typename()(i)?;
             let (i, _) = multispace0(i)?;
@@ -707,6 +1038,9 @@ impl Parser {
                 "unit" => Type::Tuple(vec![]),
                 "real" => Type::Real,
                 "int" => Type::Int,
+                "char" => Type::Char,
+                "word" => Type::Word,
+                "string" => Type::String,
                 _ => Type::Datatype(name),
             })(i)
         }
@@ -724,42 +1058,55 @@ impl Parser {
         move |i| alt((self.op_symbol_alphanumeric(), self.op_symbol_symbolic_eq()))(i)
     }
 
+    /// `op sym`, which takes the infix operator as a value
+    fn op_symbol(&self) -> impl Fn(&str) -> IResult<&str, Symbol> + '_ {
+        move |i| {
+            let (i, _) = keyword("op")(i)?;
+            alt((
+                preceded(multispace1, self.symbol_alphanumeric()),
+                preceded(multispace0, self.symbol_symbolic_eq()),
+            ))(i)
+        }
+    }
+
     fn op_symbol_alphanumeric(&self) -> impl Fn(&str) -> IResult<&str, Symbol> + '_ {
         move |i| {
-            let (i, _) = opt(tuple((tag("op"), multispace1)))(i)?;
+            let (i, _) = opt(tuple((keyword("op"), multispace1)))(i)?;
             self.symbol_alphanumeric()(i)
         }
     }
 
     fn op_symbol_symbolic_eq(&self) -> impl Fn(&str) -> IResult<&str, Symbol> + '_ {
         move |i| {
-            let (i, _) = opt(tuple((tag("op"), multispace0)))(i)?;
-            alt((self.symbol_symbolic(), value(Symbol::new("="), tag("="))))(i)
+            let (i, _) = opt(tuple((keyword("op"), multispace0)))(i)?;
+            alt((self.symbol_symbolic(), value(Symbol::new("="), equal)))(i)
         }
     }
 
+    /// the name in the patterns, which is not `when` starting the guard of the clause
+    fn symbol_pattern(&self) -> impl Fn(&str) -> IResult<&str, Symbol> + '_ {
+        move |i| verify(self.symbol(), |name: &Symbol| name.0 != "when")(i)
+    }
+
     fn symbol_alphanumeric(&self) -> impl Fn(&str) -> IResult<&str, Symbol> + '_ {
         move |i| {
-            // FIXME: collect syntax is [a-zA-Z'_][a-zA-Z'_0-9]*
-            let (i, sym) = verify(alphanumeric1, |s: &str| !KEYWORDS.contains(&s))(i)?;
-            Ok((i, Symbol::new(sym.to_string())))
+            let (i, sym) = verify(alphanumeric_name, |s: &str| !KEYWORDS.contains(&s))(i)?;
+            Ok((i, Symbol::new(sym)))
         }
     }
 
     fn symbol_symbolic_eq(&self) -> impl Fn(&str) -> IResult<&str, Symbol> + '_ {
-        move |i| alt((self.symbol_symbolic(), value(Symbol::new("="), tag("="))))(i)
+        move |i| alt((self.symbol_symbolic(), value(Symbol::new("="), equal)))(i)
     }
 
     fn symbol_symbolic(&self) -> impl Fn(&str) -> IResult<&str, Symbol> + '_ {
         move |i| {
-            let symbolic1 = recognize(many1(nom::character::complete::one_of(
-                "!%&$#+-/:<=>?@\\~'^|*",
-            )));
+            let symbolic1 = recognize(many1(nom::character::complete::one_of(SYMBOLIC_CHARS)));
 
             let (i, sym) = verify(symbolic1, |s: &str| {
                 !KEYWORDS.contains(&s) && !RESERVED.contains(&s)
             })(i)?;
-            Ok((i, Symbol::new(sym.to_string())))
+            Ok((i, Symbol::new(sym)))
         }
     }
 
@@ -767,6 +1114,23 @@ impl Parser {
         move |i| alt((self.pattern_constructor(), self.pattern_atmic()))(i)
     }
 
+    /// `p1 | p2 | ...` of the clauses and the parenthesized patterns
+    fn pattern_or(&self) -> impl Fn(&str) -> IResult<&str, Pattern<()>> + '_ {
+        move |i| {
+            let sep = tuple((multispace0, tag("|"), multispace0));
+            let (i, mut patterns) = separated_nonempty_list(sep, self.pattern())(i)?;
+            let pattern = if patterns.len() == 1 {
+                patterns.remove(0)
+            } else {
+                Pattern {
+                    ty: (),
+                    inner: PatternKind::Or { patterns },
+                }
+            };
+            Ok((i, pattern))
+        }
+    }
+
     fn pattern_atmic(&self) -> impl Fn(&str) -> IResult<&str, Pattern<()>> + '_ {
         move |i| {
             alt((
@@ -785,14 +1149,14 @@ impl Parser {
     fn pattern_bool(&self) -> impl Fn(&str) -> IResult<&str, Pattern<()>> + '_ {
         move |i| {
             alt((
-                map(tag("true"), |_| Pattern {
+                map(keyword("true"), |_| Pattern {
                     ty: (),
                     inner: PatternKind::Constructor {
                         name: Symbol::new("true"),
                         arg: None,
                     },
                 }),
-                map(tag("false"), |_| Pattern {
+                map(keyword("false"), |_| Pattern {
                     ty: (),
                     inner: PatternKind::Constructor {
                         name: Symbol::new("false"),
@@ -805,11 +1169,9 @@ impl Parser {
 
     fn pattern_int(&self) -> impl Fn(&str) -> IResult<&str, Pattern<()>> + '_ {
         move |i| {
-            map(digit1, |s: &str| Pattern {
+            map(integer, |value| Pattern {
                 ty: (),
-                inner: PatternKind::Constant {
-                    value: s.parse().unwrap(),
-                },
+                inner: PatternKind::Constant { value },
             })(i)
         }
     }
@@ -817,9 +1179,7 @@ impl Parser {
     fn pattern_char(&self) -> impl Fn(&str) -> IResult<&str, Pattern<()>> + '_ {
         move |i| {
             let (i, _) = tag("#")(i)?;
-            let (i, s) = self.string_literal()(i)?;
-            assert_eq!(s.iter().count(), 1);
-            let c = s.into_iter().next().unwrap();
+            let (i, c) = self.char_literal()(i)?;
             Ok((
                 i,
                 Pattern {
@@ -832,11 +1192,11 @@ impl Parser {
 
     fn pattern_tuple(&self) -> impl Fn(&str) -> IResult<&str, Pattern<()>> + '_ {
         move |i| {
-            let (i, _) = tag("(")(i)?;
+            let (i, _) = open_paren(i)?;
             let (i, _) = multispace0(i)?;
             let sep = tuple((multispace0, tag(","), multispace0));
-            let (i, es) = many1(map(tuple((self.pattern(), sep)), |(e, _)| e))(i)?;
-            let (i, e) = self.pattern()(i)?;
+            let (i, es) = many1(map(tuple((self.pattern_or(), sep)), |(e, _)| e))(i)?;
+            let (i, e) = self.pattern_or()(i)?;
             let (i, _) = multispace0(i)?;
             let (i, _) = tag(")")(i)?;
 
@@ -859,7 +1219,7 @@ impl Parser {
                     ty: (),
                     inner: PatternKind::Tuple { tuple: vec![] },
                 },
-                tuple((tag("("), multispace0, tag(")"))),
+                tuple((open_paren, multispace0, tag(")"))),
             )(i)
         }
     }
@@ -869,7 +1229,7 @@ impl Parser {
     //  will be converted in later phases
     fn pattern_constructor(&self) -> impl Fn(&str) -> IResult<&str, Pattern<()>> + '_ {
         move |i| {
-            let (i, name) = self.symbol()(i)?;
+            let (i, name) = self.symbol_pattern()(i)?;
             let (i, _) = multispace0(i)?;
             let (i, arg) = self.pattern_atmic()(i)?;
             Ok((
@@ -887,7 +1247,7 @@ impl Parser {
 
     fn pattern_var(&self) -> impl Fn(&str) -> IResult<&str, Pattern<()>> + '_ {
         move |i| {
-            map(self.symbol(), |name| Pattern {
+            map(self.symbol_pattern(), |name| Pattern {
                 ty: (),
                 inner: PatternKind::Variable { name: name },
             })(i)
@@ -908,9 +1268,9 @@ impl Parser {
 
     fn pattern_paren(&self) -> impl Fn(&str) -> IResult<&str, Pattern<()>> + '_ {
         move |i| {
-            let (i, _) = tag("(")(i)?;
+            let (i, _) = open_paren(i)?;
             let (i, _) = multispace0(i)?;
-            let (i, e) = self.pattern()(i)?;
+            let (i, e) = self.pattern_or()(i)?;
             let (i, _) = multispace0(i)?;
             let (i, _) = tag(")")(i)?;
 
@@ -1045,6 +1405,13 @@ fn test_expr_infix_and_app2() {
     )
 }
 
+#[test]
+fn test_comments() {
+    let input = "val (* a (* nested *) comment *) x = 1 (* trailing *)";
+    let ret = parse(input).unwrap();
+    assert_eq!(ret, parse("val x = 1").unwrap());
+}
+
 pub fn parse(
     input: &str,
 ) -> ::std::result::Result<UntypedAst, nom::Err<(&str, nom::error::ErrorKind)>> {
@@ -1052,3 +1419,235 @@ pub fn parse(
     let (_, iresult) = all_consuming(parser.top())(input)?;
     Ok(iresult)
 }
+
+/// parses the sources as the parts of one program, in order.
+/// the infix operators declared in a source are in effect in the following sources
+pub fn parse_sources<'a>(
+    inputs: &[&'a str],
+) -> ::std::result::Result<Vec<UntypedAst>, nom::Err<(&'a str, nom::error::ErrorKind)>> {
+    let parser = Parser::new();
+    inputs
+        .iter()
+        .map(|input| {
+            let (_, ast) = all_consuming(parser.top())(input)?;
+            Ok(ast)
+        })
+        .collect()
+}
+
+/// parses the programs after the basis, in which the infix operators declared in the basis are
+/// in effect. the declarations of the basis come first in the parsed programs
+pub(crate) struct BasisParser {
+    parser: Parser,
+    basis: UntypedAst,
+}
+
+impl BasisParser {
+    /// parses the basis. the error is formatted, as the basis need not outlive the parser
+    pub(crate) fn new(basis: &str) -> ::std::result::Result<Self, String> {
+        let parser = Parser::new();
+        let basis = match all_consuming(parser.top())(basis) {
+            Ok((_, ast)) => ast,
+            Err(e) => return Err(format!("{:?}", e)),
+        };
+        Ok(Self { parser, basis })
+    }
+
+    pub(crate) fn parse<'a>(
+        &self,
+        input: &'a str,
+    ) -> ::std::result::Result<UntypedAst, nom::Err<(&'a str, nom::error::ErrorKind)>> {
+        // the infix operators of the program are not in effect in the next one
+        let infixes = self.parser.infixes.borrow().clone();
+        let parsed = all_consuming(self.parser.top())(input);
+        self.parser.infixes.replace(infixes);
+        let (_, AST(decls)) = parsed?;
+        let mut ast = self.basis.clone();
+        ast.0.extend(decls);
+        Ok(ast)
+    }
+}
+
+/// the keywords which start the top level declarations
+static DECLARATION_KEYWORDS: &[&str] = &[
+    "val",
+    "fun",
+    "datatype",
+    "exception",
+    "infix",
+    "infixr",
+    "_import",
+];
+
+/// whether the input is at the end of a top level declaration, i.e. followed by
+/// another declaration or the end of the input
+fn at_declaration_end(i: &str) -> bool {
+    let i = match multispace0(i) {
+        Ok((i, _)) => i,
+        Err(_) => return false,
+    };
+    i.is_empty()
+        || DECLARATION_KEYWORDS.iter().any(|keyword| {
+            i.starts_with(keyword)
+                && !i[keyword.len()..].starts_with(|c: char| c.is_alphanumeric() || c == '_')
+        })
+}
+
+/// whether the input starts another top level declaration after spaces. unlike
+/// `at_declaration_end`, the keyword must be followed by a character ending it,
+/// because more input may extend it to a name
+fn starts_declaration(i: &str) -> bool {
+    let i = match multispace1(i) {
+        Ok((i, _)) => i,
+        Err(_) => return false,
+    };
+    DECLARATION_KEYWORDS.iter().any(|keyword| {
+        i.starts_with(keyword)
+            && i[keyword.len()..]
+                .starts_with(|c: char| !(c.is_alphanumeric() || c == '_' || c == '\''))
+    })
+}
+
+/// parses a program given in chunks, e.g. read from stdin or a network stream, yielding the
+/// top level declarations as soon as they are complete. a declaration is complete when the
+/// next one has started, so the last one is yielded by `finish`.
+/// the input that does not parse yet is kept for the next chunks, and `finish` reports its error
+pub struct StreamParser {
+    parser: Parser,
+    buffer: String,
+}
+
+impl StreamParser {
+    pub fn new() -> Self {
+        Self {
+            parser: Parser::new(),
+            buffer: String::new(),
+        }
+    }
+
+    /// adds the chunk to the input and returns the declarations completed by it
+    pub fn feed(&mut self, chunk: &str) -> Vec<UntypedDeclaration> {
+        self.buffer.push_str(chunk);
+        let mut decls = Vec::new();
+        let mut consumed = 0;
+        loop {
+            let i = match multispace0(&self.buffer[consumed..]) {
+                Ok((i, _)) => i,
+                Err(_) => break,
+            };
+            // an infix declaration cut by the chunk declares its prefix, as `+` of `++`
+            let infixes = self.parser.infixes.borrow().clone();
+            let (rest, decl) = match self.parser.decl()(i) {
+                Ok(parsed) if starts_declaration(parsed.0) => parsed,
+                _ => {
+                    self.parser.infixes.replace(infixes);
+                    break;
+                }
+            };
+            decls.push(decl);
+            consumed = self.buffer.len() - rest.len();
+        }
+        self.buffer.drain(..consumed);
+        decls
+    }
+
+    /// parses the rest of the input at the end of the stream
+    pub fn finish(
+        &self,
+    ) -> ::std::result::Result<Vec<UntypedDeclaration>, nom::Err<(&str, nom::error::ErrorKind)>>
+    {
+        let (_, AST(decls)) = all_consuming(self.parser.top())(&self.buffer)?;
+        Ok(decls)
+    }
+}
+
+impl Default for StreamParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// a top level declaration parsed before
+#[derive(Debug, Clone)]
+struct ParsedDeclaration {
+    source: String,
+    /// the infix operators in effect at the declaration, which change how it parses
+    infixes: BTreeMap<u8, Vec<Symbol>>,
+    decl: UntypedDeclaration,
+}
+
+/// parses the programs reusing the top level declarations parsed from the same source
+/// in the previous program. a declaration is parsed again if its source changed, or
+/// if the infix operators declared before it changed
+#[derive(Debug, Default)]
+pub struct ParseCache {
+    decls: Vec<ParsedDeclaration>,
+    reparsed: usize,
+}
+
+impl ParseCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// the number of the declarations parsed anew by the last `parse`
+    pub fn reparsed(&self) -> usize {
+        self.reparsed
+    }
+
+    pub fn parse<'a>(
+        &mut self,
+        input: &'a str,
+    ) -> ::std::result::Result<UntypedAst, nom::Err<(&'a str, nom::error::ErrorKind)>> {
+        let parser = Parser::new();
+        let mut cached = &self.decls[..];
+        let mut decls = Vec::new();
+        self.reparsed = 0;
+        let (mut i, _) = multispace0(input)?;
+        while !i.is_empty() {
+            let mut infixes = parser.get_table();
+            // the table is built from a HashMap
+            for names in infixes.values_mut() {
+                names.sort_by(|s1, s2| s1.0.cmp(&s2.0));
+            }
+            let reused = cached.iter().position(|decl| {
+                decl.infixes == infixes
+                    && i.starts_with(&decl.source)
+                    && at_declaration_end(&i[decl.source.len()..])
+            });
+            let decl = match reused {
+                Some(pos) => {
+                    let decl = cached[pos].clone();
+                    cached = &cached[pos + 1..];
+                    if let Declaration::D(DerivedDeclaration::Infix { priority, names }) =
+                        &decl.decl
+                    {
+                        parser.new_infix_op(*priority, names.clone());
+                    }
+                    decl
+                }
+                None => {
+                    let (rest, decl) = parser.decl()(i)?;
+                    self.reparsed += 1;
+                    ParsedDeclaration {
+                        source: i[..i.len() - rest.len()].to_string(),
+                        infixes,
+                        decl,
+                    }
+                }
+            };
+            let rest = &i[decl.source.len()..];
+            decls.push(decl);
+            // the declarations are separated by spaces
+            let (rest, space) = multispace0(rest)?;
+            if !rest.is_empty() && space.is_empty() {
+                return Err(nom::Err::Error((rest, nom::error::ErrorKind::Eof)));
+            }
+            i = rest;
+        }
+        info!("parsed {} of {} declarations", self.reparsed, decls.len());
+        let ast = AST(decls.iter().map(|decl| decl.decl.clone()).collect());
+        self.decls = decls;
+        Ok(ast)
+    }
+}
@@ -1,8 +1,8 @@
 use crate::config::Config;
 use crate::util::PP;
-use log::info;
 use std::fmt::Debug;
 use std::marker::PhantomData;
+use tracing::{debug, info};
 
 pub trait Pass<T, E> {
     type Target;
@@ -31,7 +31,7 @@ where
 
     fn trans(&mut self, i: In, config: &Config) -> Result<Self::Target, Err> {
         let o = self.0.trans(i, config)?;
-        println!("{:#?}", o);
+        debug!("{:#?}", o);
         Ok(o)
     }
 }
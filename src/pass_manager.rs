@@ -0,0 +1,425 @@
+use crate::ast::{self, TypeError};
+use crate::backend;
+use crate::config::{Config, Emit, EmitFormat};
+use crate::hir;
+use crate::lir;
+use crate::mir;
+use crate::pass::Pass;
+use crate::util::PP;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::marker::PhantomData;
+use std::path::PathBuf;
+use tracing::{debug, info_span};
+
+/// The IR passed between passes in `PassManager`.
+pub enum IR<'a> {
+    Source(&'a str),
+    Ast(ast::UntypedAst),
+    Core(ast::UntypedCore),
+    ScopedCore((ast::SymbolTable, ast::UntypedCore)),
+    TypedCore((ast::SymbolTable, ast::TypedCore)),
+    HIR((hir::SymbolTable, hir::HIR)),
+    MIR((mir::SymbolTable, mir::MIR)),
+    LIR((lir::ExternTypes, lir::LIR)),
+    Wasm(backend::Module),
+}
+
+/// conversion from the `IR` to the input of a pass.
+/// returns the IR back if it is not of the type.
+pub trait FromIR<'a>: Sized {
+    fn from_ir(ir: IR<'a>) -> Result<Self, IR<'a>>;
+    /// whether `from_ir` succeeds
+    fn is(ir: &IR<'a>) -> bool;
+}
+
+macro_rules! ir_variant {
+    ($variant: ident, $ty: ty) => {
+        impl<'a> From<$ty> for IR<'a> {
+            fn from(t: $ty) -> Self {
+                IR::$variant(t)
+            }
+        }
+
+        impl<'a> FromIR<'a> for $ty {
+            fn from_ir(ir: IR<'a>) -> Result<Self, IR<'a>> {
+                match ir {
+                    IR::$variant(t) => Ok(t),
+                    ir => Err(ir),
+                }
+            }
+
+            fn is(ir: &IR<'a>) -> bool {
+                match ir {
+                    IR::$variant(_) => true,
+                    _ => false,
+                }
+            }
+        }
+    };
+}
+
+ir_variant!(Source, &'a str);
+ir_variant!(Ast, ast::UntypedAst);
+ir_variant!(Core, ast::UntypedCore);
+ir_variant!(ScopedCore, (ast::SymbolTable, ast::UntypedCore));
+ir_variant!(TypedCore, (ast::SymbolTable, ast::TypedCore));
+ir_variant!(HIR, (hir::SymbolTable, hir::HIR));
+ir_variant!(MIR, (mir::SymbolTable, mir::MIR));
+ir_variant!(LIR, (lir::ExternTypes, lir::LIR));
+ir_variant!(Wasm, backend::Module);
+
+impl<'a> IR<'a> {
+    /// name of the IR, used in error messages
+    pub fn kind(&self) -> &'static str {
+        use self::IR::*;
+        match self {
+            Source(_) => "source",
+            Ast(_) => "ast",
+            Core(_) => "core",
+            ScopedCore(_) => "scoped core",
+            TypedCore(_) => "typed core",
+            HIR(_) => "hir",
+            MIR(_) => "mir",
+            LIR(_) => "lir",
+            Wasm(_) => "wasm",
+        }
+    }
+
+    /// the `Emit`s which select the IR
+    fn emit_kinds(&self) -> &'static [Emit] {
+        use self::IR::*;
+        match self {
+            Source(_) | Core(_) | ScopedCore(_) => &[],
+            Ast(_) => &[Emit::Ast],
+            TypedCore(_) => &[Emit::TypedAst],
+            HIR(_) => &[Emit::HIR],
+            MIR(_) => &[Emit::MIR],
+            LIR(_) => &[Emit::LIR],
+            Wasm(_) => &[Emit::Wat, Emit::Wasm],
+        }
+    }
+
+    /// writes the IR as selected by `kind`, in the `Config::emit_format`.
+    /// `Emit::Wasm` writes the encoded module
+    pub fn dump<W: io::Write>(&self, kind: Emit, w: &mut W, config: &Config) -> io::Result<()> {
+        match self {
+            IR::Wasm(module) if kind == Emit::Wasm => {
+                let mut code = Vec::new();
+                module.dump(&mut code);
+                w.write_all(&code)
+            }
+            _ => match config.emit_format {
+                EmitFormat::Text => self.pp(w, 0),
+                #[cfg(feature = "json")]
+                EmitFormat::Json => self.dump_json(w),
+            },
+        }
+    }
+}
+
+#[cfg(feature = "json")]
+impl<'a> IR<'a> {
+    /// writes the program as a line of JSON, `{"version": _, "ir": _, "program": _}`.
+    /// the symbol tables are left out; the HIR and MIR annotate the nodes with their types.
+    /// the IRs without the JSON form are pretty printed
+    fn dump_json<W: io::Write>(&self, w: &mut W) -> io::Result<()> {
+        use serde::Serialize;
+
+        #[derive(Serialize)]
+        struct Dump<'p, T> {
+            version: u32,
+            ir: &'static str,
+            program: &'p T,
+        }
+
+        fn write<W: io::Write, T: Serialize>(
+            w: &mut W,
+            ir: &'static str,
+            program: &T,
+        ) -> io::Result<()> {
+            let dump = Dump {
+                version: EmitFormat::JSON_VERSION,
+                ir,
+                program,
+            };
+            serde_json::to_writer(&mut *w, &dump)?;
+            writeln!(w)
+        }
+
+        match self {
+            IR::Ast(ast) => write(w, "ast", ast),
+            IR::TypedCore((_, core)) => write(w, "typed-ast", core),
+            IR::HIR((_, hir)) => write(w, "hir", hir),
+            IR::MIR((_, mir)) => write(w, "mir", mir),
+            ir => ir.pp(w, 0),
+        }
+    }
+}
+
+impl<'a> PP for IR<'a> {
+    fn pp<W: io::Write>(&self, w: &mut W, indent: usize) -> io::Result<()> {
+        use self::IR::*;
+        match self {
+            Source(s) => write!(w, "{}", s),
+            Ast(ast) => ast.pp(w, indent),
+            Core(core) => core.pp(w, indent),
+            ScopedCore(core) => core.pp(w, indent),
+            TypedCore(core) => core.pp(w, indent),
+            HIR(hir) => hir.pp(w, indent),
+            MIR(mir) => mir.pp(w, indent),
+            LIR(lir) => lir.pp(w, indent),
+            Wasm(module) => module.pp(w, indent),
+        }
+    }
+}
+
+/// the `emit` of `PassManager::run_emitting` which prints the IRs to stdout
+pub(crate) fn print_emitted(config: &Config) -> impl FnMut(Emit, &IR) + '_ {
+    move |kind, ir| ir.dump(kind, &mut io::stdout(), config).unwrap()
+}
+
+#[derive(Debug)]
+pub enum CompileError<'a> {
+    /// the pass failed
+    Pass {
+        pass: &'static str,
+        error: TypeError<'a>,
+    },
+    /// the pass received an IR which it does not accept.
+    /// happens when required passes are disabled
+    IRMismatch {
+        pass: &'static str,
+        ir: &'static str,
+    },
+    /// compilation stopped after the pass as requested by `Config::stop_after`
+    Stopped { pass: &'static str },
+    /// the config has contradicting options
+    InvalidConfig { message: String },
+    /// the top level name is defined in more than one file of the program
+    DuplicateDefinition {
+        name: String,
+        first: PathBuf,
+        second: PathBuf,
+    },
+}
+
+impl<'a> fmt::Display for CompileError<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use self::CompileError::*;
+        match self {
+            Pass { pass, error } => write!(f, "{}: {}", pass, error),
+            IRMismatch { pass, ir } => write!(f, "{}: cannot take {} as input", pass, ir),
+            Stopped { pass } => write!(f, "stopped after {}", pass),
+            InvalidConfig { message } => write!(f, "invalid config: {}", message),
+            DuplicateDefinition {
+                name,
+                first,
+                second,
+            } => write!(
+                f,
+                "{} is defined in both {} and {}",
+                name,
+                first.display(),
+                second.display()
+            ),
+        }
+    }
+}
+
+impl<'a> Error for CompileError<'a> {}
+
+enum StageError<'a> {
+    Pass(TypeError<'a>),
+    IRMismatch(IR<'a>),
+}
+
+/// a pass wrapped to take and return `IR`
+trait Stage<'a> {
+    /// whether the stage transforms the IR to the same kind of IR
+    fn preserves(&self, ir: &IR<'a>) -> bool;
+    fn trans_ir(&mut self, ir: IR<'a>, config: &Config) -> Result<IR<'a>, StageError<'a>>;
+}
+
+struct Wrap<P, In> {
+    pass: P,
+    phantom: PhantomData<fn(In)>,
+}
+
+impl<'a, P, In> Stage<'a> for Wrap<P, In>
+where
+    In: FromIR<'a>,
+    P: Pass<In, TypeError<'a>>,
+    P::Target: Into<IR<'a>> + FromIR<'a>,
+{
+    fn preserves(&self, ir: &IR<'a>) -> bool {
+        In::is(ir) && P::Target::is(ir)
+    }
+
+    fn trans_ir(&mut self, ir: IR<'a>, config: &Config) -> Result<IR<'a>, StageError<'a>> {
+        let input = In::from_ir(ir).map_err(StageError::IRMismatch)?;
+        let output = self.pass.trans(input, config).map_err(StageError::Pass)?;
+        Ok(output.into())
+    }
+}
+
+struct Entry<'a> {
+    name: &'static str,
+    // optional passes run only when enabled by `Config::enabled_passes`
+    optional: bool,
+    stage: Box<dyn Stage<'a> + 'a>,
+}
+
+/// An ordered pipeline of named passes.
+pub struct PassManager<'a> {
+    passes: Vec<Entry<'a>>,
+}
+
+impl<'a> PassManager<'a> {
+    pub fn new() -> Self {
+        PassManager { passes: Vec::new() }
+    }
+
+    fn entry<P, In>(name: &'static str, optional: bool, pass: P) -> Entry<'a>
+    where
+        In: FromIR<'a> + 'a,
+        P: Pass<In, TypeError<'a>> + 'a,
+        P::Target: Into<IR<'a>> + FromIR<'a>,
+    {
+        Entry {
+            name,
+            optional,
+            stage: Box::new(Wrap {
+                pass,
+                phantom: PhantomData,
+            }),
+        }
+    }
+
+    /// append a pass to the pipeline
+    pub fn add<P, In>(&mut self, name: &'static str, pass: P) -> &mut Self
+    where
+        In: FromIR<'a> + 'a,
+        P: Pass<In, TypeError<'a>> + 'a,
+        P::Target: Into<IR<'a>> + FromIR<'a>,
+    {
+        self.passes.push(Self::entry(name, false, pass));
+        self
+    }
+
+    /// append a pass which runs only when enabled by `Config::enabled_passes`
+    pub fn add_optional<P, In>(&mut self, name: &'static str, pass: P) -> &mut Self
+    where
+        In: FromIR<'a> + 'a,
+        P: Pass<In, TypeError<'a>> + 'a,
+        P::Target: Into<IR<'a>> + FromIR<'a>,
+    {
+        self.passes.push(Self::entry(name, true, pass));
+        self
+    }
+
+    /// insert a pass after the pass named `after`.
+    /// returns false if no such pass exists
+    pub fn insert_after<P, In>(&mut self, after: &str, name: &'static str, pass: P) -> bool
+    where
+        In: FromIR<'a> + 'a,
+        P: Pass<In, TypeError<'a>> + 'a,
+        P::Target: Into<IR<'a>> + FromIR<'a>,
+    {
+        match self.passes.iter().position(|entry| entry.name == after) {
+            Some(index) => {
+                self.passes
+                    .insert(index + 1, Self::entry(name, false, pass));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// remove the pass named `name`.
+    /// returns false if no such pass exists
+    pub fn remove(&mut self, name: &str) -> bool {
+        let len = self.passes.len();
+        self.passes.retain(|entry| entry.name != name);
+        len != self.passes.len()
+    }
+
+    /// names of the passes in the order of execution
+    pub fn names(&self) -> Vec<&'static str> {
+        self.passes.iter().map(|entry| entry.name).collect()
+    }
+
+    fn is_enabled(entry: &Entry, config: &Config) -> bool {
+        if entry.optional {
+            (config.enabled_passes.contains(entry.name)
+                || config.opt_level.enabled_passes().contains(&entry.name))
+                && !config.disabled_passes.contains(entry.name)
+        } else {
+            !config.disabled_passes.contains(entry.name)
+                && !config.opt_level.disabled_passes().contains(&entry.name)
+        }
+    }
+
+    pub fn run(
+        &mut self,
+        input: impl Into<IR<'a>>,
+        config: &Config,
+    ) -> Result<IR<'a>, CompileError<'a>> {
+        self.run_emitting(input, config, &mut print_emitted(config))
+    }
+
+    /// `run`, passing the IRs selected by `Config::emit` to `emit` instead of printing them
+    pub fn run_emitting(
+        &mut self,
+        input: impl Into<IR<'a>>,
+        config: &Config,
+        emit: &mut dyn FnMut(Emit, &IR<'a>),
+    ) -> Result<IR<'a>, CompileError<'a>> {
+        let mut ir = input.into();
+        let enabled = self
+            .passes
+            .iter()
+            .map(|entry| Self::is_enabled(entry, config))
+            .collect::<Vec<_>>();
+        for i in 0..self.passes.len() {
+            let entry = &mut self.passes[i];
+            if !enabled[i] {
+                debug!(pass = entry.name, "skipped");
+                continue;
+            }
+            let name = entry.name;
+            let span = info_span!("pass", name);
+            let _enter = span.enter();
+            ir = entry.stage.trans_ir(ir, config).map_err(|e| match e {
+                StageError::Pass(error) => CompileError::Pass { pass: name, error },
+                StageError::IRMismatch(ir) => CompileError::IRMismatch {
+                    pass: name,
+                    ir: ir.kind(),
+                },
+            })?;
+            if config.pretty_print_ir.contains(name) {
+                ir.pp(&mut io::stdout(), 0).unwrap();
+            }
+            let stop = config.stop_after.as_ref().map(String::as_str) == Some(name);
+            // the IR is complete if the next pass converts it to another IR
+            let complete = stop
+                || self.passes[i + 1..]
+                    .iter()
+                    .zip(&enabled[i + 1..])
+                    .find(|(_, enabled)| **enabled)
+                    .map_or(true, |(next, _)| !next.stage.preserves(&ir));
+            if complete {
+                for &kind in ir.emit_kinds() {
+                    if config.emit.contains(&kind) {
+                        emit(kind, &ir);
+                    }
+                }
+            }
+            if stop {
+                return Err(CompileError::Stopped { pass: name });
+            }
+        }
+        Ok(ir)
+    }
+}
@@ -1,27 +1,208 @@
 use crate::util::PP;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
 use std::io;
+use std::ops::Deref;
+use std::sync::{OnceLock, RwLock};
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
-pub struct Symbol(pub String, pub u64);
+/// a string interned in the table shared by all the compilations.
+/// compared and hashed by its index, and ordered by the string
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Name(u32);
+
+/// the strings are leaked so that the names can borrow them without the lock
+#[derive(Default)]
+struct Interner {
+    indices: HashMap<&'static str, u32>,
+    strings: Vec<&'static str>,
+}
+
+fn interner() -> &'static RwLock<Interner> {
+    static INTERNER: OnceLock<RwLock<Interner>> = OnceLock::new();
+    INTERNER.get_or_init(Default::default)
+}
+
+impl Name {
+    pub fn new(s: &str) -> Self {
+        if let Some(&index) = interner().read().unwrap().indices.get(s) {
+            return Name(index);
+        }
+        let mut interner = interner().write().unwrap();
+        // another thread may have interned it after the read lock is released
+        if let Some(&index) = interner.indices.get(s) {
+            return Name(index);
+        }
+        let string: &'static str = Box::leak(s.to_string().into_boxed_str());
+        let index = interner.strings.len() as u32;
+        interner.strings.push(string);
+        interner.indices.insert(string, index);
+        Name(index)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        interner().read().unwrap().strings[self.0 as usize]
+    }
+}
+
+impl Deref for Name {
+    type Target = str;
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl From<&str> for Name {
+    fn from(s: &str) -> Self {
+        Name::new(s)
+    }
+}
+
+impl From<&String> for Name {
+    fn from(s: &String) -> Self {
+        Name::new(s)
+    }
+}
+
+impl From<String> for Name {
+    fn from(s: String) -> Self {
+        Name::new(&s)
+    }
+}
+
+impl PartialEq<str> for Name {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<&str> for Name {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl PartialOrd for Name {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Name {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.as_str().cmp(other.as_str())
+    }
+}
+
+impl fmt::Debug for Name {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl fmt::Display for Name {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl Serialize for Name {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for Name {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(Name::from)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Symbol(pub Name, pub u64);
 
 impl Symbol {
-    pub fn new<S: Into<String>>(s: S) -> Self {
+    pub fn new<S: Into<Name>>(s: S) -> Self {
         Symbol(s.into(), 0)
     }
 }
 
 impl PP for Symbol {
     fn pp<W: io::Write>(&self, w: &mut W, _indent: usize) -> io::Result<()> {
-        write!(w, "{}@{}", self.0, self.1)?;
+        // the symbols not renamed yet have id 0
+        if self.1 == 0 {
+            write!(w, "{}", self.0)?;
+        } else {
+            write!(w, "{}@{}", self.0, self.1)?;
+        }
         Ok(())
     }
 }
 
+/// a top level function exported to the host
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Export {
+    /// the name to export the function as
+    pub name: String,
+    /// the SML type of the function
+    pub ty: String,
+    /// the types the JS glue converts the argument and the result by,
+    /// unless the function takes a continuation
+    pub types: Option<ExportTypes>,
+}
+
+/// the parameter and the result types of an exported function, as `webml.types` records them
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ExportTypes {
+    pub param: ExportTy,
+    pub ret: ExportTy,
+    /// the datatypes the types refer to, with the names and the arguments of their constructors
+    pub datatypes: Vec<(String, Vec<(String, Option<ExportTy>)>)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum ExportTy {
+    Int,
+    Word,
+    Real,
+    Char,
+    String,
+    Bool,
+    /// unit is the empty tuple
+    Tuple(Vec<ExportTy>),
+    /// the index of the datatype in `ExportTypes::datatypes`
+    Datatype(u32),
+    /// functions are passed as the addresses of the closures
+    Fun,
+}
+
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Literal {
     Int(i64),
     Real(f64),
     Char(u32),
+    Word(u32),
+    String(String),
+}
+
+/// the character in a string or character literal
+fn write_escaped<W: io::Write>(w: &mut W, c: char) -> io::Result<()> {
+    match c {
+        '"' => write!(w, "\\\""),
+        '\\' => write!(w, "\\\\"),
+        '\n' => write!(w, "\\n"),
+        '\t' => write!(w, "\\t"),
+        c => write!(w, "{}", c),
+    }
 }
 
 impl PP for Literal {
@@ -32,17 +213,37 @@ impl PP for Literal {
                 write!(w, "{}", v)?;
             }
             Real(v) => {
-                write!(w, "{}", v)?;
+                // keep the decimal point so that it reads as a real
+                let s = format!("{:?}", v);
+                match s.find('e') {
+                    Some(_) if s.contains('.') => write!(w, "{}", s)?,
+                    Some(e) => write!(w, "{}.0{}", &s[..e], &s[e..])?,
+                    None => write!(w, "{}", s)?,
+                }
             }
-            Char(c) => {
-                write!(w, r##"#"{}""##, c)?;
+            Char(c) => match std::char::from_u32(*c) {
+                Some(c) => {
+                    write!(w, "#\"")?;
+                    write_escaped(w, c)?;
+                    write!(w, "\"")?;
+                }
+                None => write!(w, r##"#"\u{{{:x}}}""##, c)?,
+            },
+            Word(v) => write!(w, "0w{}", v)?,
+            String(s) => {
+                write!(w, "\"")?;
+                for c in s.chars() {
+                    write_escaped(w, c)?;
+                }
+                write!(w, "\"")?;
             }
         }
         Ok(())
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum BIF {
     Add,
     Sub,
@@ -56,6 +257,12 @@ pub enum BIF {
     Ge,
     Lt,
     Le,
+    /// `raise exn`
+    Raise,
+    /// `handle (fn () => body, fn exn => handler)`
+    Handle,
+    /// `hash x`, the `word` hashing the value so that the equal values have the same hash
+    Hash,
 }
 
 impl PP for BIF {
@@ -98,6 +305,15 @@ impl PP for BIF {
             Le => {
                 write!(w, "le")?;
             }
+            Raise => {
+                write!(w, "raise")?;
+            }
+            Handle => {
+                write!(w, "handle")?;
+            }
+            Hash => {
+                write!(w, "hash")?;
+            }
         }
         Ok(())
     }
@@ -0,0 +1,210 @@
+//! programs made of several source files.
+//! the files are compiled in order as one program: a file sees the declarations of
+//! the files before it. unlike the declarations in a file, which may shadow the earlier
+//! ones, a top level name may be defined in only one of the files.
+//!
+//! the project files, with the `.mlb` extension, list the files of the program, one per line.
+//! the paths are relative to the project file and the lines in `(* *)` are comments.
+//!
+//! a source file includes another file with the line `use "path";`, relative to itself.
+//! the included file is compiled before the including file, and only once.
+
+use crate::ast::{Declaration, DerivedDeclaration, UntypedAst, UntypedDeclaration, AST};
+use crate::backend;
+use crate::cache::Cache;
+use crate::config::Config;
+use crate::parser::parse_sources;
+use crate::pass_manager::{print_emitted, CompileError};
+use crate::prim::Symbol;
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsStr;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+pub struct SourceFile {
+    pub path: PathBuf,
+    pub source: String,
+}
+
+pub struct Project {
+    /// the declarations which the files may shadow, e.g. the prelude
+    pub basis: String,
+    pub files: Vec<SourceFile>,
+}
+
+/// the names the declaration defines, and whether each of them is a constructor
+fn defined_names<'d>(
+    decl: &'d UntypedDeclaration,
+    constructors: &HashSet<&str>,
+) -> Vec<(&'d Symbol, bool)> {
+    use Declaration::*;
+    match decl {
+        Datatype { name, constructors } => std::iter::once((name, false))
+            .chain(constructors.iter().map(|(name, _)| (name, true)))
+            .collect(),
+        Exception { name, .. } => vec![(name, true)],
+        // a variable pattern may be a constructor, which is not defined here
+        Val { pattern, .. } => pattern
+            .binds()
+            .into_iter()
+            .filter(|(name, _)| !constructors.contains(name.0.as_str()))
+            .map(|(name, _)| (name, false))
+            .collect(),
+        D(DerivedDeclaration::Fun { name, .. }) | D(DerivedDeclaration::Import { name, .. }) => {
+            vec![(name, false)]
+        }
+        // the constructors replicated are not known before the desugaring
+        D(DerivedDeclaration::Replication { name, .. }) => vec![(name, false)],
+        D(DerivedDeclaration::Withtype {
+            name,
+            constructors,
+            types,
+        }) => std::iter::once((name, false))
+            .chain(constructors.iter().map(|(name, _)| (name, true)))
+            .chain(types.iter().map(|(name, _)| (name, false)))
+            .collect(),
+        D(DerivedDeclaration::Infix { .. }) => vec![],
+    }
+}
+
+/// the path in the `use "path";` line
+fn used_file(line: &str) -> Option<&str> {
+    let line = line.trim();
+    let line = line.strip_suffix(';').unwrap_or(line).trim_end();
+    let path = line.strip_prefix("use")?.trim_start();
+    path.strip_prefix('"')?.strip_suffix('"')
+}
+
+impl Project {
+    pub fn new(basis: impl Into<String>) -> Self {
+        Project {
+            basis: basis.into(),
+            files: Vec::new(),
+        }
+    }
+
+    pub fn add_file(&mut self, path: impl Into<PathBuf>, source: impl Into<String>) {
+        self.files.push(SourceFile {
+            path: path.into(),
+            source: source.into(),
+        })
+    }
+
+    /// reads the source file and the files it uses, or the files the project file lists.
+    /// the files already in the project are not added again
+    pub fn load(&mut self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut loading = Vec::new();
+        self.load_file(path.as_ref(), &mut loading)
+    }
+
+    fn load_file(&mut self, path: &Path, loading: &mut Vec<PathBuf>) -> io::Result<()> {
+        let canonical = path.canonicalize()?;
+        if loading.contains(&canonical) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} includes itself", path.display()),
+            ));
+        }
+        let loaded = self
+            .files
+            .iter()
+            .any(|file| file.path.canonicalize().ok().as_ref() == Some(&canonical));
+        if loaded {
+            return Ok(());
+        }
+        let source = fs::read_to_string(path)?;
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+        loading.push(canonical);
+        if path.extension() == Some(OsStr::new("mlb")) {
+            for line in source.lines().map(str::trim) {
+                if line.is_empty() || (line.starts_with("(*") && line.ends_with("*)")) {
+                    continue;
+                }
+                self.load_file(&dir.join(line), loading)?;
+            }
+        } else {
+            let mut lines = Vec::new();
+            for line in source.lines() {
+                match used_file(line) {
+                    Some(used) => {
+                        self.load_file(&dir.join(used), loading)?;
+                        // keeps the lines where they are
+                        lines.push("");
+                    }
+                    None => lines.push(line),
+                }
+            }
+            self.add_file(path, lines.join("\n"));
+        }
+        loading.pop();
+        Ok(())
+    }
+
+    /// parses the files into one program
+    pub(crate) fn parse(&self) -> Result<UntypedAst, CompileError<'_>> {
+        let sources = std::iter::once(self.basis.as_str())
+            .chain(self.files.iter().map(|file| file.source.as_str()))
+            .collect::<Vec<_>>();
+        let mut asts = parse_sources(&sources).map_err(|e| CompileError::Pass {
+            pass: "parse",
+            error: e.into(),
+        })?;
+
+        let constructors = asts
+            .iter()
+            .flat_map(|ast| &ast.0)
+            .flat_map(|decl| defined_names(decl, &HashSet::new()))
+            .filter(|(_, constructor)| *constructor)
+            .map(|(name, _)| name.0.as_str())
+            .collect::<HashSet<_>>();
+        let mut defined = HashMap::new();
+        for (file, ast) in self.files.iter().zip(&asts[1..]) {
+            let names = ast
+                .0
+                .iter()
+                .flat_map(|decl| defined_names(decl, &constructors))
+                .map(|(name, _)| name.0.as_str())
+                .collect::<HashSet<_>>();
+            // sorted for the errors to be reproducible
+            let mut names = names.into_iter().collect::<Vec<_>>();
+            names.sort();
+            for name in names {
+                if let Some(first) = defined.insert(name, &file.path) {
+                    return Err(CompileError::DuplicateDefinition {
+                        name: name.to_string(),
+                        first: first.clone(),
+                        second: file.path.clone(),
+                    });
+                }
+            }
+        }
+
+        let decls = asts.iter_mut().flat_map(|ast| ast.0.drain(..)).collect();
+        Ok(AST(decls))
+    }
+
+    /// reports the errors of the files after the basis, starting with the names
+    /// defined in two files
+    pub fn check(&self, config: &Config) -> Result<(), CompileError<'_>> {
+        crate::check_ast(self.parse()?, config)
+    }
+
+    /// compiles the files after the basis into one module,
+    /// cached by the sources along with their paths
+    pub fn compile(&self, config: &Config) -> Result<backend::Module, CompileError<'_>> {
+        let paths = self
+            .files
+            .iter()
+            .map(|file| file.path.to_string_lossy())
+            .collect::<Vec<_>>();
+        let mut sources = vec![self.basis.as_str()];
+        for (path, file) in paths.iter().zip(&self.files) {
+            sources.push(path);
+            sources.push(&file.source);
+        }
+        Cache::get_or_compile(config.cache_dir.as_deref(), &sources, config, || {
+            crate::compile_ast(self.parse()?, config, &mut print_emitted(config))
+        })
+    }
+}
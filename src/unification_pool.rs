@@ -48,7 +48,7 @@ impl<T> UnificationPool<T> {
         &mut self.pool[node_id.0]
     }
 
-    fn value_id(&self, mut id: NodeId) -> NodeId {
+    pub fn value_id(&self, mut id: NodeId) -> NodeId {
         loop {
             match self.at(id) {
                 Node::Value(_) => return id,
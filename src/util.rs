@@ -1,5 +1,16 @@
 use std::io;
 
+/// the stack left below which `ensure_stack` grows it
+const RED_ZONE: usize = 128 * 1024;
+/// the size of the stack segments added
+const STACK_SIZE: usize = 4 * 1024 * 1024;
+
+/// runs `f`, first growing the stack if little of it is left, so that the passes
+/// recursing on the nesting of the expressions survive machine generated programs
+pub fn ensure_stack<R>(f: impl FnOnce() -> R) -> R {
+    stacker::maybe_grow(RED_ZONE, STACK_SIZE, f)
+}
+
 pub trait PP {
     fn pp<W: io::Write>(&self, w: &mut W, indent: usize) -> io::Result<()>;
     fn nspaces(n: usize) -> String {
@@ -0,0 +1,135 @@
+use std::fs;
+use std::path::PathBuf;
+use webml::{compile, Config, Prelude, Project};
+
+fn double() -> String {
+    "fun double x = x * 2\nval x = double 21".to_string()
+}
+
+fn cache_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("webml-cache-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    dir
+}
+
+fn entries(dir: &PathBuf) -> Vec<PathBuf> {
+    let mut entries = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect::<Vec<_>>();
+    entries.sort();
+    entries
+}
+
+fn encode(module: &webml::backend::Module) -> (Vec<u8>, String) {
+    let mut code = Vec::new();
+    module.dump(&mut code);
+    (code, format!("{:?}", module.interface))
+}
+
+#[test]
+fn cached_module_is_reused() {
+    let dir = cache_dir("reuse");
+    let config = Config {
+        cache_dir: Some(dir.clone()),
+        exports: vec!["double".into()],
+        ..Config::default()
+    };
+    let compiled = compile(&double(), &config).unwrap();
+    let cached = entries(&dir);
+    assert_eq!(cached.len(), 1);
+
+    let reused = compile(&double(), &config).unwrap();
+    assert_eq!(encode(&compiled), encode(&reused));
+    assert_eq!(entries(&dir), cached);
+
+    let uncached = Config {
+        cache_dir: None,
+        ..config.clone()
+    };
+    assert_eq!(
+        encode(&compiled),
+        encode(&compile(&double(), &uncached).unwrap())
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn programs_and_options_have_their_own_entries() {
+    let dir = cache_dir("entries");
+    let config = Config {
+        cache_dir: Some(dir.clone()),
+        ..Config::default()
+    };
+    compile(&double(), &config).unwrap();
+    compile("val x = 1", &config).unwrap();
+    assert_eq!(entries(&dir).len(), 2);
+
+    let config = Config {
+        disabled_passes: vec!["peephole".to_string()].into_iter().collect(),
+        ..config
+    };
+    compile(&double(), &config).unwrap();
+    assert_eq!(entries(&dir).len(), 3);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn broken_entries_are_compiled_again() {
+    let dir = cache_dir("broken");
+    let config = Config {
+        cache_dir: Some(dir.clone()),
+        ..Config::default()
+    };
+    let compiled = compile(&double(), &config).unwrap();
+    let entry = entries(&dir).remove(0);
+    let mut bytes = fs::read(&entry).unwrap();
+    bytes.truncate(bytes.len() - 1);
+    fs::write(&entry, bytes).unwrap();
+
+    let recompiled = compile(&double(), &config).unwrap();
+    assert_eq!(encode(&compiled), encode(&recompiled));
+    // the broken entry is replaced
+    assert_eq!(
+        encode(&compile(&double(), &config).unwrap()),
+        encode(&compiled)
+    );
+    assert_eq!(entries(&dir), vec![entry]);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn printing_ir_skips_cache() {
+    let dir = cache_dir("print");
+    let config = Config {
+        cache_dir: Some(dir.clone()),
+        pretty_print_ir: vec!["parse".to_string()].into_iter().collect(),
+        ..Config::default()
+    };
+    compile("val x = 1", &config).unwrap();
+    assert!(!dir.exists());
+}
+
+#[test]
+fn projects_are_cached() {
+    let dir = cache_dir("project");
+    let config = Config {
+        cache_dir: Some(dir.clone()),
+        ..Config::default()
+    };
+    let mut project = Project::new(Prelude::BUILTIN);
+    project.add_file("lib.sml", "fun double x = x * 2");
+    project.add_file("main.sml", "val x = double 21");
+    let compiled = project.compile(&config).unwrap();
+    assert_eq!(entries(&dir).len(), 1);
+    assert_eq!(
+        encode(&compiled),
+        encode(&project.compile(&config).unwrap())
+    );
+    assert_eq!(entries(&dir).len(), 1);
+
+    project.add_file("extra.sml", "val y = x");
+    project.compile(&config).unwrap();
+    assert_eq!(entries(&dir).len(), 2);
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,72 @@
+use std::fs;
+use std::process::Command;
+
+/// the call graph of the program printed by `webml compile`, with the ids of the symbols removed
+fn call_graph(name: &str, source: &str) -> String {
+    let dir = std::env::temp_dir().join(format!("webml-call-graph-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let input = dir.join(format!("{}.sml", name));
+    fs::write(&input, source).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_webml"))
+        .arg("compile")
+        .arg(&input)
+        .arg("-o")
+        .arg(dir.join(format!("{}.wasm", name)))
+        .args(["--enable-pass", "call_graph"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let graph = String::from_utf8(output.stdout).unwrap();
+    let mut stripped = String::new();
+    let mut chars = graph.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '@' {
+            while let Some('0'..='9') = chars.peek() {
+                chars.next();
+            }
+        } else {
+            stripped.push(c);
+        }
+    }
+    stripped
+}
+
+#[test]
+fn closures_and_their_captures_are_drawn() {
+    let graph = call_graph(
+        "closures",
+        "fun add x = fn y => x + y
+fun twice f x = f (f x)
+val n = twice (add 2) 1
+val addFour = twice (add 2)
+val _ = printInt (addFour n)
+",
+    );
+    assert!(graph.starts_with("digraph program {\n"), "{}", graph);
+    assert!(graph.ends_with("}\n"), "{}", graph);
+    for line in &[
+        r#""add" -> "<anonfun>" [style=dashed, label="closure"];"#,
+        r#""twice" -> "<anonfun>" [style=dashed, label="closure"];"#,
+        r#""n" -> "twice_uncurried";"#,
+        r#""twice_uncurried" [label="twice_uncurried\n2 indirect calls"];"#,
+        r#""printInt" -> "js-ffi.print";"#,
+        r#""js-ffi.print" [shape=plaintext];"#,
+    ] {
+        assert!(graph.contains(line), "{} not in\n{}", line, graph);
+    }
+    assert!(
+        graph.contains(r#"\ncaptures #g: int -> int"#),
+        "the function returned by twice captures f\n{}",
+        graph
+    );
+}
+
+#[test]
+fn repeated_calls_are_counted() {
+    let graph = call_graph("calls", "fun f x = x\nval y = f (f 1)\n");
+    assert!(
+        graph.contains(r#""y" -> "f" [label="call x2"];"#),
+        "{}",
+        graph
+    );
+}
@@ -1,8 +1,8 @@
 use std::fs;
 use std::io::{self, prelude::*};
 use std::path::{Path, PathBuf};
-use webml::TypeError;
-use webml::{compile_str, Config};
+use webml::CompileError;
+use webml::{check, compile_str, Config};
 
 fn read_and_append_to_string(path: impl AsRef<Path>, buf: &mut String) -> io::Result<usize> {
     let file = fs::File::open(path)?;
@@ -12,20 +12,24 @@ fn read_and_append_to_string(path: impl AsRef<Path>, buf: &mut String) -> io::Re
 
 fn with_compile_result(
     path: impl AsRef<Path>,
-    callback: impl for<'a> FnOnce(Result<Vec<u8>, TypeError<'a>>),
+    config: &Config,
+    callback: impl for<'a> FnOnce(Result<Vec<u8>, CompileError<'a>>),
 ) {
     let path = path.as_ref();
-    let mut input = include_str!("../../ml_src/prelude.sml").to_string();
-    let config = Config::default();
+    let mut input = String::new();
     read_and_append_to_string(&path, &mut input).expect("failed to load file");
-    let result = compile_str(&input, &config);
+    let result = compile_str(&input, config);
     println!("{}", path.to_str().unwrap());
     callback(result)
 }
 
 fn assert_compile_pass(path: impl AsRef<Path>) {
+    assert_compile_pass_with(path, &Config::default())
+}
+
+fn assert_compile_pass_with(path: impl AsRef<Path>, config: &Config) {
     let path = path.as_ref();
-    with_compile_result(path, |res| match res {
+    with_compile_result(path, config, |res| match res {
         Ok(_) => (),
         Err(e) => panic!("failed to compile {}: {}", path.display(), e),
     })
@@ -33,12 +37,23 @@ fn assert_compile_pass(path: impl AsRef<Path>) {
 
 fn assert_compile_fail(path: impl AsRef<Path>) {
     let path = path.as_ref();
-    with_compile_result(path, |res| match res {
+    with_compile_result(path, &Config::default(), |res| match res {
         Ok(_) => panic!("succeded to compile {}, which should fail", path.display(),),
         Err(_) => (),
     })
 }
 
+fn assert_check(path: impl AsRef<Path>, pass: bool) {
+    let path = path.as_ref();
+    let mut input = String::new();
+    read_and_append_to_string(path, &mut input).expect("failed to load file");
+    match check(&input, &Config::default()) {
+        Ok(()) if !pass => panic!("{} passed the check, which should fail", path.display()),
+        Err(e) if pass => panic!("failed to check {}: {}", path.display(), e),
+        _ => (),
+    }
+}
+
 fn walk_dir(name: impl AsRef<Path>, mut callback: impl for<'a> FnMut(PathBuf)) {
     use walkdir::WalkDir;
 
@@ -56,6 +71,29 @@ fn examples_compile_pass() {
     walk_dir("ml_example", assert_compile_pass)
 }
 
+#[test]
+fn examples_compile_pass_cps() {
+    let mut config = Config::default();
+    config.enabled_passes.insert("cps_conversion".into());
+    walk_dir("ml_example", |path| assert_compile_pass_with(path, &config));
+    walk_dir("tests/compile_pass", |path| {
+        assert_compile_pass_with(path, &config)
+    })
+}
+
+#[test]
+fn cps_functions_read_top_level_values() {
+    let mut config = Config::default();
+    config.enabled_passes.insert("cps_conversion".into());
+    let input = "val two = 2 fun addTwo x = x + two val _ = printInt (addTwo 1)";
+    if let Err(e) = compile_str(input, &config) {
+        panic!("failed to compile the function reading the value: {}", e)
+    }
+    // the function is bound in main, so it cannot be exported
+    config.exports = vec!["addTwo".into()];
+    assert!(compile_str(input, &config).is_err());
+}
+
 #[test]
 fn test_compile_pass() {
     walk_dir("tests/compile_pass", assert_compile_pass)
@@ -65,3 +103,43 @@ fn test_compile_pass() {
 fn test_compile_fail() {
     walk_dir("tests/compile_fail", assert_compile_fail)
 }
+
+#[test]
+fn examples_check_pass() {
+    walk_dir("ml_example", |path| assert_check(path, true))
+}
+
+#[test]
+fn test_check_fail() {
+    walk_dir("tests/compile_fail", |path| assert_check(path, false))
+}
+
+#[test]
+fn examples_compile_reproducibly() {
+    walk_dir("ml_example", |path| {
+        let mut outputs = Vec::new();
+        for _ in 0..3 {
+            with_compile_result(&path, &Config::default(), |res| {
+                outputs.push(res.expect("failed to compile"))
+            });
+        }
+        assert!(
+            outputs.windows(2).all(|w| w[0] == w[1]),
+            "output of {} differs between compilations",
+            path.display()
+        );
+    })
+}
+
+#[test]
+fn deeply_nested_expressions_check() {
+    let depth = 3000;
+    let input = format!(
+        "fun f x = x + 1 val y = {}1{}",
+        "f (".repeat(depth),
+        ")".repeat(depth)
+    );
+    if let Err(e) = check(&input, &Config::default()) {
+        panic!("failed to check nested applications: {}", e)
+    }
+}
@@ -0,0 +1,158 @@
+use std::fs;
+use webml::backend::Metadata;
+use webml::{compile_str, Artifact, Compiler, Config, Emit, OptLevel, Prelude};
+
+const PRELUDE: &str = Prelude::BUILTIN;
+
+#[test]
+fn builder_compiles_like_compile_str() {
+    let main = "fun double x = x * 2 val () = printInt (double 21)";
+    let output = Compiler::builder()
+        .basis(PRELUDE)
+        .source("main.sml", main)
+        .build()
+        .compile();
+    assert!(output.is_ok(), "{:?}", output.diagnostics);
+    assert_eq!(
+        output.wasm,
+        Some(compile_str(main, &Config::default()).unwrap())
+    );
+    assert_eq!(output.js, None);
+    assert!(output.dumps.is_empty());
+}
+
+#[test]
+fn errors_are_diagnostics() {
+    let output = Compiler::builder()
+        .basis(PRELUDE)
+        .source("main.sml", "val x = 1 + true")
+        .artifact(Artifact::Js {
+            program_url: "main.wasm".into(),
+        })
+        .build()
+        .compile();
+    assert_eq!(output.wasm, None);
+    assert_eq!(output.js, None);
+    assert_eq!(output.diagnostics.len(), 1);
+    assert_eq!(output.diagnostics[0].pass, Some("typing"));
+}
+
+#[test]
+fn prelude_is_prepended_by_default() {
+    let main = "val () = printInt (Int.abs (0 - 3))";
+    let output = Compiler::builder()
+        .source("main.sml", main)
+        .build()
+        .compile();
+    assert!(output.is_ok(), "{:?}", output.diagnostics);
+
+    let config = Config {
+        prelude: Prelude::None,
+        ..Config::default()
+    };
+    let output = Compiler::builder()
+        .source("main.sml", main)
+        .config(config)
+        .build()
+        .compile();
+    assert_eq!(output.diagnostics.len(), 1);
+
+    let config = Config {
+        prelude: Prelude::File("no_such_prelude.sml".into()),
+        ..Config::default()
+    };
+    let output = Compiler::builder()
+        .source("main.sml", main)
+        .config(config)
+        .build()
+        .compile();
+    assert_eq!(output.diagnostics.len(), 1);
+    assert!(output.diagnostics[0]
+        .message
+        .starts_with("failed to read the prelude"));
+}
+
+#[test]
+fn artifacts_are_collected() {
+    let output = Compiler::builder()
+        .basis(PRELUDE)
+        .source("lib.sml", "fun double x = x * 2")
+        .source("main.sml", "val () = printInt (double 21)")
+        .artifact(Artifact::Wasm)
+        .artifact(Artifact::Js {
+            program_url: "main.wasm".into(),
+        })
+        .artifact(Artifact::Ir(Emit::HIR))
+        .artifact(Artifact::Ir(Emit::Wat))
+        .build()
+        .compile();
+    assert!(output.is_ok(), "{:?}", output.diagnostics);
+    assert!(output.wasm.is_some());
+    assert!(output.js.as_ref().unwrap().contains("main.wasm"));
+    let kinds = output
+        .dumps
+        .iter()
+        .map(|(kind, _)| *kind)
+        .collect::<Vec<_>>();
+    assert_eq!(kinds, vec![Emit::HIR, Emit::Wat]);
+    let hir = String::from_utf8(output.dump(Emit::HIR).unwrap().to_vec()).unwrap();
+    assert!(hir.contains("double"), "{}", hir);
+}
+
+#[test]
+fn stopped_compilation_keeps_the_dumps() {
+    let config = Config {
+        stop_after: Some("flattening_let".into()),
+        ..Config::default()
+    };
+    let output = Compiler::builder()
+        .basis(PRELUDE)
+        .source("main.sml", "val () = printInt 1")
+        .config(config)
+        .artifact(Artifact::Ir(Emit::TypedAst))
+        .artifact(Artifact::Ir(Emit::MIR))
+        .build()
+        .compile();
+    assert!(output.is_ok(), "{:?}", output.diagnostics);
+    assert_eq!(output.wasm, None);
+    assert!(output.dump(Emit::TypedAst).is_some());
+    assert!(output.dump(Emit::MIR).is_none());
+}
+
+#[test]
+fn unoptimized_programs_compile() {
+    for entry in fs::read_dir("tests/tests/golden").unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().and_then(|e| e.to_str()) != Some("sml") {
+            continue;
+        }
+        let output = Compiler::builder()
+            .basis(PRELUDE)
+            .source(&path, fs::read_to_string(&path).unwrap())
+            .opt_level(OptLevel::O0)
+            .build()
+            .compile();
+        assert!(
+            output.is_ok(),
+            "{}: {:?}",
+            path.display(),
+            output.diagnostics
+        );
+    }
+}
+
+#[test]
+fn opt_level_is_recorded() {
+    let config = Config {
+        opt_level: OptLevel::O0,
+        ..Config::default()
+    };
+    let module = webml::compile("val () = printInt 1", &config).unwrap();
+    let section = module
+        .custom_sections
+        .iter()
+        .find(|section| section.name == Metadata::SECTION_NAME)
+        .unwrap();
+    let metadata = Metadata::decode(&section.payload).unwrap();
+    assert!(metadata.flags.contains(&("opt-level".into(), "0".into())));
+}
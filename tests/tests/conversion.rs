@@ -0,0 +1,60 @@
+use webml::backend::{Module, LIR2WASM};
+use webml::lir::{Block, ExternTypes, Function, LTy, Label, Op, Reg, LIR};
+use webml::prim::Symbol;
+use webml::{Config, Pass};
+
+const NUMERIC_TYPES: &[LTy] = &[LTy::I32, LTy::U32, LTy::I64, LTy::U64, LTy::F32, LTy::F64];
+
+// a program whose main function runs the operation on the registers of the types
+fn program(tys: Vec<LTy>, op: Op) -> LIR {
+    let main = Function {
+        name: Symbol::new("sml-main"),
+        nparams: 0,
+        reg_names: vec![None; tys.len()],
+        regs: tys,
+        ret_ty: LTy::Unit,
+        body: vec![Block {
+            name: Label(Symbol::new("entry")),
+            body: vec![op, Op::Ret(None)],
+        }],
+        export: None,
+    };
+    LIR(vec![main])
+}
+
+fn generate(lir: LIR) -> Module {
+    let result: Result<Module, ()> =
+        LIR2WASM::new().trans((ExternTypes::new(), lir), &Config::default());
+    result.unwrap()
+}
+
+#[test]
+fn convert_between_numeric_types() {
+    for from in NUMERIC_TYPES {
+        for to in NUMERIC_TYPES {
+            let op = Op::Convert(Reg(to.clone(), 1), Reg(from.clone(), 0));
+            generate(program(vec![from.clone(), to.clone()], op));
+        }
+    }
+}
+
+#[test]
+fn reinterpret_between_types_of_same_width() {
+    for from in NUMERIC_TYPES {
+        for to in NUMERIC_TYPES {
+            let float = |ty: &LTy| *ty == LTy::F32 || *ty == LTy::F64;
+            if from.size() != to.size() || float(from) == float(to) {
+                continue;
+            }
+            let op = Op::Reinterpret(Reg(to.clone(), 1), Reg(from.clone(), 0));
+            generate(program(vec![from.clone(), to.clone()], op));
+        }
+    }
+}
+
+#[test]
+#[should_panic]
+fn reinterpret_between_types_of_different_width() {
+    let op = Op::Reinterpret(Reg(LTy::F64, 1), Reg(LTy::I32, 0));
+    generate(program(vec![LTy::I32, LTy::F64], op));
+}
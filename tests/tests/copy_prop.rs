@@ -0,0 +1,54 @@
+use super::util::{binds, int, run_pass, sym, val};
+use webml::hir::{CopyProp, Expr, HTy, HIR};
+use webml::prim::*;
+
+#[test]
+fn copy_prop_chain() {
+    // let val x = 1 val y = x val z = y in z + y end
+    let expr = binds(
+        vec![
+            val("x", 1, int(1)),
+            val("y", 2, sym("x", 1)),
+            val("z", 3, sym("y", 2)),
+        ],
+        Expr::BuiltinCall {
+            ty: HTy::Int,
+            fun: BIF::Add,
+            args: vec![sym("z", 3), sym("y", 2)],
+        },
+    );
+    let hir = run_pass(CopyProp::new(), HIR(vec![val("a", 0, expr)]));
+    match &hir.0[0].expr {
+        Expr::Binds { binds, ret, .. } => {
+            assert_eq!(binds.len(), 1);
+            assert_eq!(binds[0].name, Symbol("x".into(), 1));
+            match &**ret {
+                Expr::BuiltinCall { args, .. } => {
+                    for arg in args {
+                        match arg {
+                            Expr::Sym { name, .. } => assert_eq!(name, &Symbol("x".into(), 1)),
+                            _ => panic!("symbol expected"),
+                        }
+                    }
+                }
+                _ => panic!("builtin call expected"),
+            }
+        }
+        expr => panic!("binds expected: {:?}", expr),
+    }
+}
+
+#[test]
+fn copy_prop_removes_empty_binds() {
+    // val a = 1 val b = a val c = let val d = b in d end
+    let hir = run_pass(CopyProp::new(), HIR(vec![
+        val("a", 1, int(1)),
+        val("b", 2, sym("a", 1)),
+        val("c", 3, binds(vec![val("d", 4, sym("b", 2))], sym("d", 4))),
+    ]));
+    assert_eq!(hir.0.len(), 2);
+    match &hir.0[1].expr {
+        Expr::Sym { name, .. } => assert_eq!(name, &Symbol("a".into(), 1)),
+        expr => panic!("symbol expected: {:?}", expr),
+    }
+}
@@ -0,0 +1,135 @@
+use webml::mir::{EbbTy, Function, Op, SymbolTable, Terminator, CSE, EBB, MIR};
+use webml::prim::Symbol;
+use webml::{Config, Pass};
+
+fn var(name: &str, id: u64) -> Symbol {
+    Symbol(name.into(), id)
+}
+
+fn add(var: Symbol, l: Symbol, r: Symbol) -> Op {
+    Op::Add {
+        var,
+        ty: EbbTy::Int,
+        l,
+        r,
+    }
+}
+
+fn mul(var: Symbol, l: Symbol, r: Symbol) -> Op {
+    Op::Mul {
+        var,
+        ty: EbbTy::Int,
+        l,
+        r,
+    }
+}
+
+fn alias(var: Symbol, sym: Symbol) -> Op {
+    Op::Alias {
+        var,
+        ty: EbbTy::Int,
+        sym,
+    }
+}
+
+fn ret(value: Symbol) -> Terminator {
+    Terminator::Ret {
+        value: Some(value),
+        ty: EbbTy::Int,
+    }
+}
+
+/// runs CSE on the function `f(a, b)` made of the blocks
+fn cse(body: Vec<EBB>) -> Vec<EBB> {
+    let f = Function {
+        name: var("f", 0),
+        body,
+        body_ty: EbbTy::Int,
+        export: None,
+    };
+    let result: Result<_, ()> =
+        CSE::new().trans((SymbolTable::default(), MIR(vec![f])), &Config::default());
+    let (_, mut mir) = result.unwrap();
+    mir.0.pop().unwrap().body
+}
+
+fn params() -> Vec<(EbbTy, Symbol)> {
+    vec![(EbbTy::Int, var("a", 1)), (EbbTy::Int, var("b", 2))]
+}
+
+#[test]
+fn same_expressions_are_aliased() {
+    let body = cse(vec![EBB {
+        name: var("entry", 3),
+        params: params(),
+        body: vec![
+            add(var("x", 4), var("a", 1), var("b", 2)),
+            add(var("y", 5), var("b", 2), var("a", 1)),
+        ],
+        term: ret(var("y", 5)),
+    }]);
+    match &body[0].body[1] {
+        Op::Alias { sym, .. } => assert_eq!(sym, &var("x", 4)),
+        op => panic!("alias expected: {:?}", op),
+    }
+}
+
+#[test]
+fn reassigned_variables_are_forgotten() {
+    // entry: t = a + b; y = t; jump next
+    // next:  t = a * b; v = y + b; w = t + b
+    // `y` is the first `t`, so `w` is not `v`
+    let body = cse(vec![
+        EBB {
+            name: var("entry", 3),
+            params: params(),
+            body: vec![
+                add(var("t", 4), var("a", 1), var("b", 2)),
+                alias(var("y", 5), var("t", 4)),
+            ],
+            term: Terminator::Jump {
+                target: var("next", 6),
+                forward: true,
+                args: vec![],
+            },
+        },
+        EBB {
+            name: var("next", 6),
+            params: vec![],
+            body: vec![
+                mul(var("t", 4), var("a", 1), var("b", 2)),
+                add(var("v", 7), var("y", 5), var("b", 2)),
+                add(var("w", 8), var("t", 4), var("b", 2)),
+                add(var("z", 9), var("t", 4), var("b", 2)),
+            ],
+            term: ret(var("z", 9)),
+        },
+    ]);
+    let next = &body[1].body;
+    assert!(matches!(next[2], Op::Add { .. }), "{:?}", next[2]);
+    // the second `t` is still known in its block
+    match &next[3] {
+        Op::Alias { sym, .. } => assert_eq!(sym, &var("w", 8)),
+        op => panic!("alias expected: {:?}", op),
+    }
+}
+
+#[test]
+fn expressions_reading_the_assigned_variable_are_forgotten() {
+    // x = a + b; a = x * b; y = a + b
+    let body = cse(vec![EBB {
+        name: var("entry", 3),
+        params: params(),
+        body: vec![
+            add(var("x", 4), var("a", 1), var("b", 2)),
+            mul(var("a", 1), var("x", 4), var("b", 2)),
+            add(var("y", 5), var("a", 1), var("b", 2)),
+        ],
+        term: ret(var("y", 5)),
+    }]);
+    assert!(
+        matches!(body[0].body[2], Op::Add { .. }),
+        "{:?}",
+        body[0].body[2]
+    );
+}
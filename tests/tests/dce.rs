@@ -0,0 +1,83 @@
+use super::util::{binop, int, run_pass, sym, symbol_table, val};
+use webml::hir::{Effects, Expr, HTy, DCE, HIR};
+use webml::prim::*;
+use webml::{Config, Pass};
+
+fn print(arg: Expr) -> Expr {
+    Expr::ExternCall {
+        ty: HTy::Tuple(vec![]),
+        module: "js-ffi".into(),
+        fun: "print".into(),
+        args: vec![arg],
+    }
+}
+
+#[test]
+fn effects_of_primitives() {
+    let effects = Effects::analyze(&HIR(vec![]));
+    assert!(effects.is_pure(&binop(BIF::Add, int(1), int(2))));
+    assert!(!effects.is_pure(&binop(BIF::Div, int(1), int(0))));
+    assert!(!effects.is_pure(&print(int(1))));
+}
+
+#[test]
+fn dce_removes_unused_pure_binds() {
+    // let val x = 1 val y = x + 1 val z = x div 0 val w = print x in x end
+    let expr = Expr::Binds {
+        ty: HTy::Int,
+        binds: vec![
+            val("x", 1, int(1)),
+            val("y", 2, binop(BIF::Add, sym("x", 1), int(1))),
+            val("z", 3, binop(BIF::Div, sym("x", 1), int(0))),
+            val("w", 4, print(sym("x", 1))),
+        ],
+        ret: Box::new(sym("x", 1)),
+    };
+    let hir = run_pass(DCE::new(), HIR(vec![val("a", 0, expr)]));
+    match &hir.0[0].expr {
+        Expr::Binds { binds, .. } => {
+            let names = binds
+                .iter()
+                .map(|val| val.name.0.as_str())
+                .collect::<Vec<_>>();
+            assert_eq!(names, vec!["x", "z", "w"]);
+        }
+        expr => panic!("binds expected: {:?}", expr),
+    }
+}
+
+#[test]
+fn dce_removes_unused_top_level_functions() {
+    let fun = |body: Expr| Expr::Fun {
+        param: (HTy::Int, Symbol("x".into(), 10)),
+        body_ty: HTy::Int,
+        body: Box::new(body),
+        captures: Vec::new(),
+    };
+    let app = |name: &str, id: u64| Expr::App {
+        ty: HTy::Int,
+        fun: Box::new(Expr::Sym {
+            ty: HTy::fun(HTy::Int, HTy::Int),
+            name: Symbol(name.into(), id),
+        }),
+        arg: Box::new(int(1)),
+    };
+    // fun f x = x  fun g x = f x  fun h x = x  fun e x = x  val y = print (h 1)
+    let hir = HIR(vec![
+        val("f", 1, fun(sym("x", 10))),
+        val("g", 2, fun(app("f", 1))),
+        val("h", 3, fun(sym("x", 10))),
+        val("e", 4, fun(sym("x", 10))),
+        val("y", 5, print(app("h", 3))),
+    ]);
+    let mut table = symbol_table();
+    table.exports = vec![(Symbol("e".into(), 4), "e".into(), None)];
+    let result: Result<_, ()> = DCE::new().trans((table, hir), &Config::default());
+    let (_, hir) = result.unwrap();
+    let names = hir
+        .0
+        .iter()
+        .map(|val| val.name.0.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(names, vec!["h", "e", "y"]);
+}
@@ -0,0 +1,64 @@
+use webml::backend::ErrorSites;
+use webml::{compile, Config};
+
+fn error_sites(program: &str) -> ErrorSites {
+    let module = compile(program, &Config::default()).unwrap();
+    let section = module
+        .custom_sections
+        .iter()
+        .find(|section| section.name == ErrorSites::SECTION_NAME)
+        .expect("no error sites section");
+    ErrorSites::decode(&section.payload).unwrap()
+}
+
+/// the sites with the ids of the functions stripped
+fn sites(error_sites: &ErrorSites) -> Vec<(&str, &str)> {
+    error_sites
+        .sites
+        .iter()
+        .map(|(kind, function)| (kind.as_str(), function.split('@').next().unwrap()))
+        .collect()
+}
+
+#[test]
+fn divisions_and_raises_are_sites() {
+    let error_sites = error_sites(
+        "exception Negative
+         fun divide (x, y) = x div y + x mod y
+         fun check n = if n < 0 then raise Negative else n
+         val _ = printInt (divide (check 7, 2))",
+    );
+    let sites = sites(&error_sites);
+    assert!(
+        sites.contains(&(ErrorSites::DIVIDE_BY_ZERO, "divide")),
+        "{:?}",
+        sites
+    );
+    assert!(sites.contains(&(ErrorSites::RAISE, "check")), "{:?}", sites);
+    // both of the divisions of `divide` are the same site
+    assert_eq!(
+        sites
+            .iter()
+            .filter(|site| *site == &(ErrorSites::DIVIDE_BY_ZERO, "divide"))
+            .count(),
+        1
+    );
+}
+
+#[test]
+fn sites_are_counted_from_one() {
+    let mut error_sites = ErrorSites::default();
+    assert_eq!(error_sites.site(ErrorSites::RAISE, "f@1"), 1);
+    assert_eq!(error_sites.site(ErrorSites::DIVIDE_BY_ZERO, "f@1"), 2);
+    assert_eq!(error_sites.site(ErrorSites::RAISE, "f@1"), 1);
+    assert_eq!(error_sites.sites.len(), 2);
+}
+
+#[test]
+fn malformed_error_sites_are_rejected() {
+    let mut error_sites = ErrorSites::default();
+    error_sites.site(ErrorSites::DIVIDE_BY_ZERO, "divide@12");
+    let payload = error_sites.encode().payload;
+    assert_eq!(ErrorSites::decode(&payload), Some(error_sites));
+    assert_eq!(ErrorSites::decode(&payload[..payload.len() - 1]), None);
+}
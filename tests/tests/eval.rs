@@ -0,0 +1,196 @@
+use std::fs;
+use walkdir::WalkDir;
+use webml::{compile_str, eval, Config, EvalError, Overflow};
+
+fn run(source: &str) -> Result<String, String> {
+    let mut out = Vec::new();
+    match eval(source, &Config::default(), &mut out) {
+        Ok(()) => Ok(String::from_utf8(out).unwrap()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+#[test]
+fn examples_eval() {
+    for entry in WalkDir::new("ml_example") {
+        let path = entry.unwrap().into_path();
+        if !path.is_file() {
+            continue;
+        }
+        let source = fs::read_to_string(&path).unwrap();
+        if let Err(e) = run(&source) {
+            panic!("failed to run {}: {}", path.display(), e)
+        }
+    }
+}
+
+#[test]
+fn print_writes_lines() {
+    assert_eq!(
+        run("val _ = printInt (1 + 2)\nval _ = printInt 4").unwrap(),
+        "3\n4\n"
+    );
+}
+
+#[test]
+fn closures_capture_their_environment() {
+    let source = "fun adder x = fn y => x + y
+val add1 = adder 1
+val _ = printInt (add1 2)
+fun fib n = if n < 2 then n else fib (n - 1) + fib (n - 2)
+val _ = printInt (fib 10)";
+    assert_eq!(run(source).unwrap(), "3\n55\n");
+}
+
+#[test]
+fn datatypes_and_tuples() {
+    let source = "datatype shape = Circle of int | Rect of int * int
+fun area s = case s of Circle r => 3 * r * r | Rect (w, h) => w * h
+val (a, b) = (area (Circle 2), area (Rect (3, 4)))
+val _ = printInt (a + b)";
+    assert_eq!(run(source).unwrap(), "24\n");
+}
+
+#[test]
+fn tail_calls_run_in_constant_stack() {
+    let source = "fun loop (n, acc) = if n = 0 then acc else loop (n - 1, acc + 1)
+val _ = printInt (loop (100000, 0))";
+    assert_eq!(run(source).unwrap(), "100000\n");
+}
+
+#[test]
+fn exceptions_are_handled() {
+    let source = "exception Negative of int
+fun check n = if n < 0 then raise Negative n else n
+fun safeCheck n = check n handle Negative m => 0 - m
+val _ = printInt (safeCheck 1)
+val _ = printInt (safeCheck (0 - 2))
+val x = (raise Match) handle Match => 3
+val _ = printInt x";
+    assert_eq!(run(source).unwrap(), "1\n2\n3\n");
+
+    let program = "val _ = printInt 1\nval _ = printInt (raise Match)";
+    let mut out = Vec::new();
+    match eval(program, &Config::default(), &mut out) {
+        Err(EvalError::UncaughtException) => (),
+        result => panic!("expected an uncaught exception, got {:?}", result),
+    }
+    assert_eq!(out, b"1\n");
+}
+
+#[test]
+fn int_behaves_as_in_wasm() {
+    let source = "val _ = printInt (2147483647 + 1)
+val _ = printInt ((0 - 7) div 2)
+val _ = printInt ((0 - 7) mod 2)
+val _ = printInt (7 div (0 - 2))
+val _ = printInt (7 mod (0 - 2))
+val _ = printInt ((0 - 7) div (0 - 2))
+val _ = printInt ((0 - 6) mod 2)";
+    assert_eq!(run(source).unwrap(), "-2147483648\n-4\n1\n-4\n-1\n3\n0\n");
+    assert_eq!(
+        run("val _ = printInt (1 div 0) handle Div => print \"Div\\n\"").unwrap(),
+        "Div\n"
+    );
+}
+
+#[test]
+fn checked_int_raises_overflow() {
+    let source = "fun check n = printInt n
+val _ = check (2147483647 + 1) handle Overflow => print \"Overflow\\n\"
+val _ = check (0 - 2147483647 - 2) handle Overflow => print \"Overflow\\n\"
+val _ = check (65536 * 32768) handle Overflow => print \"Overflow\\n\"
+val _ = check ((0 - 1) * (0 - 2147483647 - 1)) handle Overflow => print \"Overflow\\n\"
+val _ = check (Int.abs (0 - 2147483647 - 1)) handle Overflow => print \"Overflow\\n\"
+val _ = check (46341 * 46340)
+val _ = check ((0 - 3) * 5 + 1)";
+    let config = Config {
+        overflow: Overflow::Raise,
+        ..Config::default()
+    };
+    compile_str(source, &config).unwrap();
+    let mut out = Vec::new();
+    eval(source, &config, &mut out).unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "Overflow\nOverflow\nOverflow\nOverflow\nOverflow\n2147441940\n-14\n"
+    );
+}
+
+#[test]
+fn errors_of_the_program_are_reported() {
+    assert!(run("val x = 1 + #\"a\"").unwrap_err().starts_with("typing"));
+}
+
+#[test]
+fn or_patterns_share_the_arm() {
+    let source = "datatype t = A of int | B of int * int | C
+fun f x = case x of A n | B (n, _) | B (_, n) => n | C => 0
+fun small n = case n of 1 | 2 | 3 => 1 | _ => 0
+val _ = printInt (f (A 1) + f (B (2, 3)) + f C)
+val _ = printInt (small 2 + small 4)
+val _ = case (C, 5) of ((A n | B (n, _)), _) | (C, n) => printInt n";
+    assert_eq!(run(source).unwrap(), "3\n1\n5\n");
+}
+
+#[test]
+fn guards_fall_through_to_the_next_clause() {
+    let source = "datatype opt = SOME of int | NONE
+fun sign n = case n of 0 => 0 | n when n > 0 => 1 | _ => 0 - 1
+fun first x = case x of SOME n when n > 9 => n | SOME n when n > 0 => n * 10 | _ => 0
+val _ = printInt (sign 0 + sign 5 * 10 + sign (0 - 5) * 100)
+val _ = printInt (first (SOME 12) + first (SOME 3) + first (SOME 0) + first NONE)";
+    let config = Config {
+        pattern_guards: true,
+        ..Config::default()
+    };
+    let mut out = Vec::new();
+    eval(source, &config, &mut out).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(), "-90\n42\n");
+
+    assert!(run("val _ = case 1 of n when n > 0 => n | _ => 0")
+        .unwrap_err()
+        .starts_with("desugar"));
+}
+
+#[test]
+fn withtype_abbreviates_the_types_of_the_constructors() {
+    let source = "datatype tree = Leaf of int | Node of branches withtype branches = tree * tree
+fun sum (Leaf n) = n
+  | sum (Node (l, r)) = sum l + sum r
+exception Empty of branches
+val _ = printInt (sum (Node (Leaf 1, Node (Leaf 2, Leaf 3))))
+val _ = let
+    datatype branches = Branches
+    exception Other of branches
+  in
+    (raise Empty (Leaf 4, Leaf 5)) handle Empty b => printInt (sum (Node b))
+  end";
+    assert_eq!(run(source).unwrap(), "6\n9\n");
+}
+
+#[test]
+fn replicated_datatypes_share_the_constructors() {
+    let source = "datatype Shape.shape = Shape.Circle of int | Shape.Square of int
+datatype shape = datatype Shape.shape
+fun area (Circle r) = 3 * r * r | area (Square a) = a * a
+val _ = printInt (area (Circle 2) + area (Shape.Square 3))
+datatype Figure.shape = datatype shape
+exception Bad of Figure.shape
+val _ = (raise Bad (Figure.Square 1)) handle Bad s => printInt (area s)";
+    assert_eq!(run(source).unwrap(), "21\n1\n");
+    assert!(run("datatype num = datatype int")
+        .unwrap_err()
+        .starts_with("desugar"));
+}
+
+#[test]
+fn shadowed_names_are_restored_after_the_scope() {
+    let source = "val x = 1
+                  val y = let val x = 2 in (fn x => x) 3 + x end
+                  datatype t = A of int
+                  val z = let datatype t = B | A in case A of A => 10 | B => 20 end
+                  val _ = printInt (y + x + z)
+                  val _ = case A 4 of A n => printInt n";
+    assert_eq!(run(source).unwrap(), "16\n4\n");
+}
@@ -0,0 +1,33 @@
+use super::util::contains;
+use webml::{compile_str, Config};
+
+#[test]
+fn raise_checks_the_flag() {
+    let input = include_str!("compile_pass/exception.sml");
+    let code = compile_str(input, &Config::default()).unwrap();
+    assert!(contains(&code, "webml-rt.raised"));
+    assert!(contains(&code, "webml-rt.take_exception"));
+}
+
+#[test]
+fn no_checks_without_exceptions() {
+    let input = "fun f x = x + 1 val y = f 1";
+    let code = compile_str(input, &Config::default()).unwrap();
+    assert!(!contains(&code, "webml-rt.raised"));
+}
+
+#[test]
+fn nested_handlers_place_the_blocks() {
+    // the blocks joining the handlers overlap those propagating the exceptions
+    let input = "exception E of int
+         fun f n = if n = 0 then raise E n else n
+         fun g n =
+           (case f n of
+                1 => (f (n - 1) handle E m => m + 1)
+              | m => m)
+           handle E m => (f m handle E k => k)
+         val _ = printInt (g 1)";
+    if let Err(e) = compile_str(input, &Config::default()) {
+        panic!("failed to compile nested handlers: {}", e)
+    }
+}
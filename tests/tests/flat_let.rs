@@ -0,0 +1,154 @@
+use super::util::{add, binds, int, run_pass, sym, val};
+use webml::hir::{Expr, FlatLet, HTy, Pattern, HIR};
+use webml::prim::*;
+
+// let val x = 1 in let val y = let val z = 2 in z end in x + y end end
+fn nested(n: u64) -> Expr {
+    binds(
+        vec![val("x", n, int(1))],
+        binds(
+            vec![val(
+                "y",
+                n + 1,
+                binds(vec![val("z", n + 2, int(2))], sym("z", n + 2)),
+            )],
+            add(sym("x", n), sym("y", n + 1)),
+        ),
+    )
+}
+
+/// `Binds` may only appear at the top of a val, a function body or a case arm,
+/// and it must not contain another `Binds` directly.
+fn assert_flat(expr: &Expr, allow_binds: bool) {
+    use webml::hir::Expr::*;
+    match expr {
+        Binds { binds, ret, .. } => {
+            assert!(allow_binds, "nested let remains: {:?}", expr);
+            for val in binds {
+                assert_flat(&val.expr, false);
+            }
+            assert_flat(ret, false);
+        }
+        Fun { body, .. } => assert_flat(body, true),
+        Case { expr, arms, .. } => {
+            assert_flat(expr, false);
+            for (_, arm) in arms {
+                assert_flat(arm, true);
+            }
+        }
+        BuiltinCall { args, .. } | ExternCall { args, .. } => {
+            for arg in args {
+                assert_flat(arg, false);
+            }
+        }
+        App { fun, arg, .. } => {
+            assert_flat(fun, false);
+            assert_flat(arg, false);
+        }
+        Tuple { tuple, .. } => {
+            for t in tuple {
+                assert_flat(t, false);
+            }
+        }
+        Proj { tuple, .. } => assert_flat(tuple, false),
+        Constructor { arg, .. } => {
+            if let Some(arg) = arg {
+                assert_flat(arg, false)
+            }
+        }
+        Closure { .. } | Sym { .. } | Lit { .. } => (),
+    }
+}
+
+fn assert_flat_hir(hir: &HIR) {
+    for val in &hir.0 {
+        assert_flat(&val.expr, true);
+    }
+}
+
+fn bound_names(expr: &Expr) -> Vec<&str> {
+    match expr {
+        Expr::Binds { binds, .. } => binds.iter().map(|val| val.name.0.as_str()).collect(),
+        _ => Vec::new(),
+    }
+}
+
+#[test]
+fn flat_let_toplevel() {
+    let hir = run_pass(FlatLet::new(), HIR(vec![val("a", 1, nested(10))]));
+    assert_flat_hir(&hir);
+    assert_eq!(bound_names(&hir.0[0].expr), vec!["x", "z", "y"]);
+}
+
+#[test]
+fn flat_let_tuple_and_binop() {
+    let tuple = Expr::Tuple {
+        tys: vec![HTy::Int, HTy::Int],
+        tuple: vec![nested(10), add(nested(20), nested(30))],
+    };
+    let hir = run_pass(FlatLet::new(), HIR(vec![val("a", 1, tuple)]));
+    assert_flat_hir(&hir);
+    assert_eq!(bound_names(&hir.0[0].expr).len(), 9);
+}
+
+#[test]
+fn flat_let_case() {
+    let case = Expr::Case {
+        ty: HTy::Int,
+        expr: Box::new(nested(10)),
+        arms: vec![
+            (
+                Pattern::Constant {
+                    value: 0,
+                    ty: HTy::Int,
+                },
+                add(nested(20), int(1)),
+            ),
+            (
+                Pattern::Var {
+                    name: Symbol("w".into(), 30),
+                    ty: HTy::Int,
+                },
+                nested(40),
+            ),
+        ],
+    };
+    let hir = run_pass(FlatLet::new(), HIR(vec![val("a", 1, case)]));
+    assert_flat_hir(&hir);
+    // only the binds in the scrutinee are lifted
+    assert_eq!(bound_names(&hir.0[0].expr), vec!["x", "z", "y"]);
+}
+
+#[test]
+fn flat_let_fun_body() {
+    let fun = Expr::Fun {
+        param: (HTy::Int, Symbol("p".into(), 2)),
+        body_ty: HTy::Int,
+        body: Box::new(add(sym("p", 2), nested(10))),
+        captures: vec![],
+    };
+    let hir = run_pass(FlatLet::new(), HIR(vec![val("f", 1, fun)]));
+    assert_flat_hir(&hir);
+    match &hir.0[0].expr {
+        Expr::Fun { body, .. } => assert_eq!(bound_names(body), vec!["x", "z", "y"]),
+        _ => panic!("function expected"),
+    }
+}
+
+#[test]
+fn flat_let_long_chain() {
+    let depth = 10_000;
+    let mut expr = sym("x", depth);
+    for id in (1..=depth).rev() {
+        expr = binds(vec![val("x", id, int(id as i64))], expr);
+    }
+    let hir = run_pass(FlatLet::new(), HIR(vec![val("a", 0, expr)]));
+    assert_flat_hir(&hir);
+    match &hir.0[0].expr {
+        Expr::Binds { binds, .. } => {
+            let ids = binds.iter().map(|val| val.name.1).collect::<Vec<_>>();
+            assert_eq!(ids, (1..=depth).collect::<Vec<_>>());
+        }
+        _ => panic!("binds expected"),
+    }
+}
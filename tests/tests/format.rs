@@ -0,0 +1,90 @@
+use webml::{fmt, parse, FormatConfig};
+
+fn format(source: &str) -> String {
+    fmt(source, &FormatConfig::default()).unwrap()
+}
+
+fn assert_same_program(source: &str, formatted: &str) {
+    let prelude = include_str!("../../ml_src/prelude.sml");
+    let parse = |source: &str| parse(&format!("{}\n{}", prelude, source)).unwrap();
+    assert_eq!(parse(source), parse(formatted), "{}", formatted);
+}
+
+#[test]
+fn formatting_keeps_the_programs() {
+    let mut sources = vec![include_str!("../../ml_src/prelude.sml").to_string()];
+    for entry in std::fs::read_dir("ml_example").unwrap() {
+        sources.push(std::fs::read_to_string(entry.unwrap().path()).unwrap());
+    }
+    for source in sources {
+        let formatted = format(&source);
+        assert_same_program(&source, &formatted);
+        assert_eq!(format(&formatted), formatted);
+    }
+}
+
+#[test]
+fn format_layout() {
+    let source = "val  x=1+2\n\n\n\nfun f 0=1|f n=n*f(n-1)\nval i = let val two = 2 in two end";
+    assert_eq!(
+        format(source),
+        "val x = 1 + 2
+
+fun f 0 = 1 | f n = n * f (n - 1)
+val i =
+    let
+        val two = 2
+    in
+        two
+    end
+"
+    );
+}
+
+#[test]
+fn datatype_replication_is_one_declaration() {
+    let source = "datatype   time =\n  datatype Time.time\nval x = 1";
+    assert_eq!(
+        format(source),
+        "datatype time = datatype Time.time\nval x = 1\n"
+    );
+}
+
+#[test]
+fn comments_are_kept() {
+    let source = "(* header *)
+
+val x = 1 (* trailing *)
+(* about y *)
+val y = (* inside *) x
+(* end *)
+";
+    let formatted = format(source);
+    assert_eq!(formatted, source);
+    assert_same_program(source, &formatted);
+}
+
+#[test]
+fn long_lines_are_broken() {
+    let source = "fun f x = case x of 0 => 1 | 1 => 2 | _ => 3";
+    let config = FormatConfig {
+        width: 30,
+        indent: 2,
+    };
+    assert_eq!(
+        fmt(source, &config).unwrap(),
+        "fun f x =
+  case x of
+      0 => 1
+    | 1 => 2
+    | _ => 3
+"
+    );
+    assert_eq!(format(source), format!("{}\n", source));
+}
+
+#[test]
+fn unterminated_comment() {
+    let e = fmt("val x = 1 (* unterminated", &FormatConfig::default()).unwrap_err();
+    assert_eq!(e.offset, 10);
+}
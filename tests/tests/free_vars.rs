@@ -0,0 +1,49 @@
+use super::util::{add, fun, sym};
+use webml::hir::{Expr, FreeVars, HTy, Val, HIR};
+use webml::prim::*;
+
+fn names(frees: &[(HTy, Symbol)]) -> Vec<u64> {
+    frees.iter().map(|(_, name)| name.1).collect()
+}
+
+// val f = fn x => let val y = x + a in fn z => x + y + z + b + a end
+#[test]
+fn free_vars_of_nested_functions() {
+    let inner = fun(
+        "z",
+        4,
+        add(
+            add(add(add(sym("x", 2), sym("y", 3)), sym("z", 4)), sym("b", 5)),
+            sym("a", 1),
+        ),
+    );
+    let outer = fun(
+        "x",
+        2,
+        Expr::Binds {
+            ty: inner.ty(),
+            binds: vec![Val {
+                ty: HTy::Int,
+                rec: false,
+                name: Symbol("y".into(), 3),
+                expr: add(sym("x", 2), sym("a", 1)),
+            }],
+            ret: Box::new(inner),
+        },
+    );
+    let hir = HIR(vec![Val {
+        ty: outer.ty(),
+        rec: false,
+        name: Symbol("f".into(), 0),
+        expr: outer,
+    }]);
+    let free_vars = FreeVars::analyze(&hir);
+    assert_eq!(
+        names(free_vars.of_function(&Symbol("z".into(), 4))),
+        vec![2, 3, 5, 1]
+    );
+    assert_eq!(
+        names(free_vars.of_function(&Symbol("x".into(), 2))),
+        vec![1, 5]
+    );
+}
@@ -0,0 +1,105 @@
+//! the end-to-end tests comparing the output of the programs in `golden` with the expectations.
+//! `golden/NAME.sml` runs with the interpreter, and its output followed by the error if any is
+//! compared with `golden/NAME.out`. run with `WEBML_BLESS=1` to write the outputs as the expectations.
+//! the module compiled from the program is validated, and run on Node.js if it and the built
+//! runtime are found, which must print the output of the interpreter and fail as it does
+
+use std::env;
+use std::ffi::OsStr;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use webml::backend::js::RUNTIME_URL;
+use webml::{compile_str, eval, Config};
+
+const GOLDEN_DIR: &str = "tests/tests/golden";
+
+/// the output of the program, and the error if it failed
+fn run(source: &str) -> (String, Option<String>) {
+    let mut out = Vec::new();
+    let result = eval(source, &Config::default(), &mut out);
+    let output = String::from_utf8(out).expect("the output is not UTF-8");
+    (output, result.err().map(|e| e.to_string()))
+}
+
+/// the output of the module run on Node.js and whether it succeeded,
+/// or `None` if Node.js or the built runtime is not found
+fn run_wasm(path: &Path) -> Option<(String, bool)> {
+    let node = Command::new("node").arg("--version").output();
+    if node.is_err() || !Path::new(RUNTIME_URL).exists() {
+        return None;
+    }
+    let output = Command::new(env!("CARGO_BIN_EXE_webml"))
+        .arg("run")
+        .arg(path)
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(output.stdout).expect("the output is not UTF-8");
+    Some((stdout, output.status.success()))
+}
+
+fn check(path: &Path, bless: bool) -> Result<(), String> {
+    let source = fs::read_to_string(path).unwrap();
+    // the `validate` pass checks the module
+    if let Err(e) = compile_str(&source, &Config::default()) {
+        return Err(format!("failed to compile: {}", e));
+    }
+
+    let (output, error) = run(&source);
+    // the loader reports the errors in its own words, so only whether it failed is compared
+    if let Some((wasm_output, success)) = run_wasm(path) {
+        if wasm_output != output || success != error.is_none() {
+            return Err(format!(
+                "the module runs differently\n--- interpreter\n{}--- wasm\n{}{}",
+                output,
+                wasm_output,
+                if success { "" } else { "(failed)\n" }
+            ));
+        }
+    }
+
+    let mut actual = output;
+    if let Some(error) = error {
+        actual.push_str(&format!("error: {}\n", error));
+    }
+    let expected_path = path.with_extension("out");
+    if bless {
+        fs::write(&expected_path, &actual).unwrap();
+        return Ok(());
+    }
+    match fs::read_to_string(&expected_path) {
+        Ok(expected) if expected == actual => Ok(()),
+        Ok(expected) => Err(format!(
+            "output differs\n--- expected\n{}--- actual\n{}",
+            expected, actual
+        )),
+        Err(_) => Err(format!("{} not found", expected_path.display())),
+    }
+}
+
+#[test]
+fn golden() {
+    let bless = env::var_os("WEBML_BLESS").is_some();
+    let mut paths = fs::read_dir(GOLDEN_DIR)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension() == Some(OsStr::new("sml")))
+        .collect::<Vec<_>>();
+    paths.sort();
+    assert!(!paths.is_empty(), "no programs in {}", GOLDEN_DIR);
+
+    let failures = paths
+        .iter()
+        .filter_map(|path| {
+            check(path, bless)
+                .err()
+                .map(|e| format!("{}: {}", path.display(), e))
+        })
+        .collect::<Vec<_>>();
+    if !failures.is_empty() {
+        panic!(
+            "{}\nrun with WEBML_BLESS=1 to update the expectations",
+            failures.join("\n")
+        );
+    }
+}
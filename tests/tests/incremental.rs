@@ -0,0 +1,100 @@
+use webml::{compile_str, parse, Config, Incremental, ParseCache, Prelude};
+
+const PRELUDE: &str = Prelude::BUILTIN;
+
+fn with_prelude(source: &str) -> String {
+    format!("{}\n{}", PRELUDE, source)
+}
+
+#[test]
+fn only_changed_declarations_are_parsed() {
+    let mut cache = ParseCache::new();
+    let source = "val x = 1\nval y = x + 1\nval z = y * 2";
+    assert_eq!(cache.parse(source).unwrap(), parse(source).unwrap());
+    assert_eq!(cache.reparsed(), 3);
+
+    let source = "val x = 1\nval y = x + 2\nval z = y * 2";
+    assert_eq!(cache.parse(source).unwrap(), parse(source).unwrap());
+    assert_eq!(cache.reparsed(), 1);
+
+    let source = "(* one *)\nval x = 1\n\nval y = x + 2 (* two *) val z = y * 2";
+    assert_eq!(cache.parse(source).unwrap(), parse(source).unwrap());
+    assert_eq!(cache.reparsed(), 0);
+
+    // the declaration grows into the next one
+    let source = "val x = 1\nval y = x + 2\nval z = y * 2 + x";
+    assert_eq!(cache.parse(source).unwrap(), parse(source).unwrap());
+    assert_eq!(cache.reparsed(), 1);
+}
+
+#[test]
+fn declarations_after_changed_infixes_are_parsed() {
+    let mut cache = ParseCache::new();
+    let program = |priority| {
+        format!(
+            "infix 6 +\ninfix 7 *\ninfix {} ++\nval x = 1 ++ 2 * 3\nval y = 4",
+            priority
+        )
+    };
+    let source = program(5);
+    assert_eq!(cache.parse(&source).unwrap(), parse(&source).unwrap());
+    assert_eq!(cache.reparsed(), 5);
+
+    let source = program(8);
+    assert_eq!(cache.parse(&source).unwrap(), parse(&source).unwrap());
+    assert_eq!(cache.reparsed(), 3);
+}
+
+#[test]
+fn parse_errors_keep_the_cache() {
+    let mut cache = ParseCache::new();
+    cache.parse("val x = 1\nval y = 2").unwrap();
+    assert!(cache.parse("val x = 1\nval y =").is_err());
+    cache.parse("val x = 1\nval y = 3").unwrap();
+    assert_eq!(cache.reparsed(), 1);
+}
+
+#[test]
+fn incremental_compilation_matches_compilation() {
+    let config = Config::default();
+    for entry in std::fs::read_dir("ml_example").unwrap() {
+        let path = entry.unwrap().path();
+        let program = std::fs::read_to_string(&path).unwrap();
+        // `compile_str` prepends the prelude itself
+        let expected = compile_str(&program, &config).unwrap();
+        let source = with_prelude(&program);
+
+        let mut incremental = Incremental::new();
+        for source in &[source.clone(), format!("{}\n(* edited *)", source)] {
+            let mut code = Vec::new();
+            incremental
+                .compile(source, &config)
+                .unwrap()
+                .dump(&mut code);
+            assert_eq!(code, expected, "{}", path.display());
+        }
+        assert_eq!(incremental.reparsed(), 0);
+        // the comment doesn't change the program
+        assert_eq!(incremental.elaborations(), 1);
+    }
+}
+
+#[test]
+fn incremental_compilation_recovers_from_errors() {
+    let config = Config::default();
+    let mut incremental = Incremental::new();
+    let source = with_prelude("val x = 1\nval y = x + 1");
+    incremental.compile(&source, &config).unwrap();
+
+    let source = with_prelude("val x = 1\nval y = x + 1.0");
+    assert!(incremental.check(&source, &config).is_err());
+
+    let program = "val x = 1.0\nval y = x + 1.0";
+    let mut code = Vec::new();
+    incremental
+        .compile(&with_prelude(program), &config)
+        .unwrap()
+        .dump(&mut code);
+    assert_eq!(code, compile_str(program, &config).unwrap());
+    assert_eq!(incremental.reparsed(), 1);
+}
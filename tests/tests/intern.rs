@@ -0,0 +1,32 @@
+use std::collections::HashMap;
+use webml::prim::{Name, Symbol};
+
+#[test]
+fn same_strings_are_the_same_name() {
+    let name = Name::new("interned");
+    assert_eq!(name, Name::from(String::from("interned")));
+    assert_ne!(name, Name::new("interned2"));
+    assert_eq!(name, "interned");
+    assert_eq!(name.to_string(), "interned");
+    assert_eq!(format!("{:?}", Symbol(name, 3)), r#"Symbol("interned", 3)"#);
+}
+
+#[test]
+fn names_are_ordered_by_the_strings() {
+    // interned in the reverse order
+    let b = Name::new("ordered_b");
+    let a = Name::new("ordered_a");
+    let mut names = vec![b, a];
+    names.sort();
+    assert_eq!(names, vec![a, b]);
+}
+
+#[test]
+fn symbols_are_looked_up_by_the_name_and_the_id() {
+    let x = Symbol::new("x");
+    let mut table = HashMap::new();
+    table.insert(x, 0);
+    table.insert(Symbol(x.0, 1), 1);
+    assert_eq!(table[&Symbol::new("x")], 0);
+    assert_eq!(table[&Symbol(Name::new("x"), 1)], 1);
+}
@@ -0,0 +1,92 @@
+use webml::backend::generate_glue;
+use webml::{compile, Config};
+
+#[test]
+fn glue_lists_imports_and_exports() {
+    let input = r#"_import "js" "now" : unit -> real
+           fun double x = x * 2
+           val t = now ()
+           val () = printInt (double 2)"#;
+    let config = Config {
+        exports: vec!["double".into()],
+        ..Config::default()
+    };
+    let module = compile(input, &config).unwrap();
+    let glue = generate_glue(&module.interface, "double.wasm");
+    assert!(glue.contains(r#"const PROGRAM_URL = "double.wasm";"#));
+    assert!(glue.contains(r#"["js", "now", [], "f64", false],"#));
+    assert!(glue.contains(r#"["js-ffi", "print", ["i32"], null, false],"#));
+    assert!(glue.contains(r#"["double", ["i32"]],"#));
+    assert!(!glue.contains(r#""webml-rt", "#));
+}
+
+#[test]
+fn glue_exposes_string_helpers() {
+    let input = "val () = printInt 1";
+    let module = compile(input, &Config::default()).unwrap();
+    let glue = generate_glue(&module.interface, "out.wasm");
+    assert!(glue.contains("export function encodeString(exports, string)"));
+    assert!(glue.contains("export function decodeString(exports, address)"));
+}
+
+#[test]
+fn glue_converts_strings_of_imports() {
+    let input = r#"_import "js" "greet" : string -> string
+           val () = print (greet "world")"#;
+    let module = compile(input, &Config::default()).unwrap();
+    let glue = generate_glue(&module.interface, "out.wasm");
+    assert!(glue.contains(r#"["js", "greet", ["string"], "string", false],"#));
+    assert!(glue.contains(r#"["js-ffi", "printString", ["string"], null, false],"#));
+    assert!(glue.contains("function writeString(rt, string)"));
+}
+
+#[test]
+fn glue_wraps_closures_passed_to_imports() {
+    let input = r#"_import "js" "onTick" : (int -> unit) -> unit
+           val () = onTick (fn n => printInt (n + 1))"#;
+    let module = compile(input, &Config::default()).unwrap();
+    let glue = generate_glue(&module.interface, "out.wasm");
+    assert!(glue.contains(r#"["js", "onTick", [[["i32"], null]], null, false],"#));
+    assert!(glue.contains("exports.callback_register(closure)"));
+    assert!(glue.contains("instance.exports.table.get(index)"));
+}
+
+#[test]
+fn glue_converts_each_parameter_of_imports_taking_closures() {
+    // the closure is the second parameter, after the string
+    let input = r#"_import "js" "every" : string * (real -> real) -> unit
+           val () = every ("tick", fn x => x * 2.0)"#;
+    let module = compile(input, &Config::default()).unwrap();
+    let glue = generate_glue(&module.interface, "out.wasm");
+    assert!(glue.contains(r#"["js", "every", ["string", [["f64"], "f64"]], null, false],"#));
+}
+
+#[test]
+fn glue_resumes_async_imports() {
+    let input = r#"_import "js" "fetch" : string -> string
+           val () = print (fetch "data.txt")"#;
+    let mut config = Config {
+        async_imports: vec![("js".into(), "fetch".into())],
+        ..Config::default()
+    };
+    config.enabled_passes.insert("cps_conversion".into());
+    let module = compile(input, &config).unwrap();
+    let glue = generate_glue(&module.interface, "out.wasm");
+    // the continuation is passed as the callback taking the string
+    assert!(glue.contains(r#"["js", "fetch", ["string", [["i32"], null]], "string", true],"#));
+    assert!(glue.contains("Promise.all([fun(...values), instantiated])"));
+}
+
+#[test]
+fn glue_converts_values_by_type_section() {
+    let input = "fun pair x = (x, Int.toString x)";
+    let config = Config {
+        exports: vec!["pair".into()],
+        ..Config::default()
+    };
+    let module = compile(input, &config).unwrap();
+    let glue = generate_glue(&module.interface, "out.wasm");
+    assert!(glue.contains(r#"sectionReader(module, "webml.types")"#));
+    assert!(glue.contains("function fromSml(rt, datatypes, ty, value)"));
+    assert!(glue.contains("function toSml(rt, datatypes, ty, value)"));
+}
@@ -0,0 +1,62 @@
+use super::util::{binds, binop, fun, int, run_pass, sym, val};
+use webml::hir::{Expr, LetFloat, Val, HIR};
+use webml::prim::*;
+
+fn names(binds: &[Val]) -> Vec<&str> {
+    binds.iter().map(|val| val.name.0.as_str()).collect()
+}
+
+#[test]
+fn let_float_hoists_invariant_binds() {
+    // fun g k = let val f = fn x => let val a = k + 1 val b = a + x val c = k div 2 in b end in f end
+    let inner = fun(
+        "x",
+        3,
+        binds(
+            vec![
+                val("a", 4, binop(BIF::Add, sym("k", 1), int(1))),
+                val("b", 5, binop(BIF::Add, sym("a", 4), sym("x", 3))),
+                val("c", 6, binop(BIF::Div, sym("k", 1), int(2))),
+            ],
+            sym("b", 5),
+        ),
+    );
+    let outer = fun("k", 1, binds(vec![val("f", 2, inner)], sym("f", 2)));
+    let hir = run_pass(LetFloat::new(), HIR(vec![val("g", 0, outer)]));
+
+    let body = match &hir.0[0].expr {
+        Expr::Fun { body, .. } => body,
+        expr => panic!("function expected: {:?}", expr),
+    };
+    let binds = match &**body {
+        Expr::Binds { binds, .. } => binds,
+        expr => panic!("binds expected: {:?}", expr),
+    };
+    // `b` depends on the parameter and `c` may trap
+    assert_eq!(names(binds), vec!["a", "f"]);
+    match &binds[1].expr {
+        Expr::Fun { body, .. } => match &**body {
+            Expr::Binds { binds, .. } => assert_eq!(names(binds), vec!["b", "c"]),
+            expr => panic!("binds expected: {:?}", expr),
+        },
+        expr => panic!("function expected: {:?}", expr),
+    }
+}
+
+#[test]
+fn let_float_keeps_top_level_functions() {
+    // fun g x = let val a = 1 + 2 in a + x end
+    let body = binds(
+        vec![val("a", 2, binop(BIF::Add, int(1), int(2)))],
+        binop(BIF::Add, sym("a", 2), sym("x", 1)),
+    );
+    let hir = run_pass(LetFloat::new(), HIR(vec![val("g", 0, fun("x", 1, body))]));
+    assert_eq!(hir.0.len(), 1);
+    match &hir.0[0].expr {
+        Expr::Fun { body, .. } => match &**body {
+            Expr::Binds { binds, .. } => assert_eq!(names(binds), vec!["a"]),
+            expr => panic!("binds expected: {:?}", expr),
+        },
+        expr => panic!("function expected: {:?}", expr),
+    }
+}
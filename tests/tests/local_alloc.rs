@@ -0,0 +1,109 @@
+use webml::lir::{Block, ExternTypes, Function, LTy, Label, LocalAlloc, Op, Reg, LIR};
+use webml::prim::Symbol;
+use webml::{Config, Pass};
+
+fn reg(index: u32) -> Reg {
+    Reg(LTy::I32, index)
+}
+
+fn block(name: &str, body: Vec<Op>) -> Block {
+    Block {
+        name: Label(Symbol::new(name)),
+        body,
+    }
+}
+
+fn alloc(nparams: u32, regs: Vec<LTy>, body: Vec<Block>) -> Function {
+    let f = Function {
+        name: Symbol::new("f"),
+        nparams,
+        reg_names: vec![None; regs.len()],
+        regs,
+        ret_ty: LTy::I32,
+        body,
+        export: None,
+    };
+    let result: Result<_, ()> =
+        LocalAlloc::new().trans((ExternTypes::new(), LIR(vec![f])), &Config::default());
+    let (_, mut lir) = result.unwrap();
+    lir.0.pop().unwrap()
+}
+
+// the registers of the operations of the block
+fn regs(block: &Block) -> Vec<Vec<u32>> {
+    block
+        .body
+        .iter()
+        .map(|op| {
+            let (def, uses) = op.def_uses();
+            def.into_iter().chain(uses).map(|reg| reg.1).collect()
+        })
+        .collect()
+}
+
+#[test]
+fn temporaries_share_locals() {
+    let f = alloc(
+        1,
+        vec![LTy::I32; 4],
+        vec![block(
+            "entry",
+            vec![
+                Op::AddI32(reg(1), reg(0), reg(0)),
+                Op::MulI32(reg(2), reg(1), reg(1)),
+                Op::SubI32(reg(3), reg(2), reg(1)),
+                Op::Ret(Some(reg(3))),
+            ],
+        )],
+    );
+    assert_eq!(f.regs, vec![LTy::I32, LTy::I32]);
+    assert_eq!(
+        regs(&f.body[0]),
+        vec![vec![0, 0, 0], vec![1, 0, 0], vec![0, 1, 0], vec![0]]
+    );
+}
+
+#[test]
+fn registers_of_different_types_do_not_share_locals() {
+    let f = alloc(
+        0,
+        vec![LTy::I32, LTy::F64, LTy::F64],
+        vec![block(
+            "entry",
+            vec![
+                Op::ConstI32(reg(0), 1),
+                Op::ConstF64(Reg(LTy::F64, 1), 1.0),
+                Op::Convert(Reg(LTy::F64, 2), reg(0)),
+                Op::Ret(Some(Reg(LTy::F64, 2))),
+            ],
+        )],
+    );
+    assert_eq!(f.regs, vec![LTy::I32, LTy::F64]);
+}
+
+#[test]
+fn registers_live_around_loops_keep_their_locals() {
+    // r1 and the parameter r0 are live throughout the loop
+    let f = alloc(
+        1,
+        vec![LTy::I32; 4],
+        vec![
+            block("entry", vec![Op::ConstI32(reg(1), 0)]),
+            block(
+                "loop",
+                vec![
+                    Op::AddI32(reg(2), reg(1), reg(0)),
+                    Op::MoveI32(reg(1), reg(2)),
+                    Op::LtI32(reg(3), reg(1), reg(0)),
+                    Op::JumpIfI32(reg(3), Label(Symbol::new("loop"))),
+                    Op::Ret(Some(reg(1))),
+                ],
+            ),
+        ],
+    );
+    assert_eq!(f.regs.len(), 3);
+    assert_eq!(
+        regs(&f.body[1]),
+        vec![vec![1, 1, 0], vec![1, 1], vec![2, 1, 0], vec![2], vec![1]]
+    );
+}
@@ -0,0 +1,83 @@
+use webml::backend::{Metadata, TypeSection};
+use webml::prim::{ExportTy, ExportTypes};
+use webml::{compile, Collector, Config};
+
+#[test]
+fn metadata_lists_flags_and_exports() {
+    let input = "fun double x = x * 2 fun swap (x, y) = (y + 0, x * 1.0) val () = printInt (double 2)";
+    let mut config = Config {
+        exports: vec!["double".into(), "swap".into()],
+        collector: Collector::Generational,
+        ..Config::default()
+    };
+    config.disabled_passes.insert("cse".into());
+    let module = compile(input, &config).unwrap();
+    let section = module
+        .custom_sections
+        .iter()
+        .find(|section| section.name == Metadata::SECTION_NAME)
+        .expect("no metadata section");
+    let metadata = Metadata::decode(&section.payload).unwrap();
+    assert_eq!(metadata.version, env!("CARGO_PKG_VERSION"));
+    assert!(metadata.flags.contains(&("disable".into(), "cse".into())));
+    assert!(metadata
+        .flags
+        .contains(&("gc".into(), "generational".into())));
+    assert_eq!(
+        metadata.exports,
+        vec![
+            ("double".into(), "int -> int".into()),
+            ("swap".into(), "real * int -> int * real".into()),
+        ]
+    );
+}
+
+#[test]
+fn malformed_metadata_is_rejected() {
+    let metadata = Metadata {
+        version: "0.1.0".into(),
+        flags: vec![("target".into(), "js".into())],
+        exports: vec![],
+    };
+    let payload = metadata.encode().payload;
+    assert_eq!(Metadata::decode(&payload), Some(metadata));
+    assert_eq!(Metadata::decode(&payload[..payload.len() - 1]), None);
+}
+
+#[test]
+fn type_section_lists_types_of_exports() {
+    let input = "datatype shape = Square of int | Empty
+         fun area s = case s of Square n => (n * n, true) | Empty => (0, false)
+         val () = printInt 1";
+    let config = Config {
+        exports: vec!["area".into()],
+        ..Config::default()
+    };
+    let module = compile(input, &config).unwrap();
+    let section = module
+        .custom_sections
+        .iter()
+        .find(|section| section.name == TypeSection::SECTION_NAME)
+        .expect("no type section");
+    let types = TypeSection::decode(&section.payload).unwrap();
+    assert_eq!(
+        types.exports,
+        vec![(
+            "area".into(),
+            ExportTypes {
+                param: ExportTy::Datatype(0),
+                ret: ExportTy::Tuple(vec![ExportTy::Int, ExportTy::Bool]),
+                datatypes: vec![(
+                    "shape".into(),
+                    vec![
+                        ("Square".into(), Some(ExportTy::Int)),
+                        ("Empty".into(), None)
+                    ]
+                )],
+            }
+        )]
+    );
+    let payload = types.encode().payload;
+    assert_eq!(TypeSection::decode(&payload), Some(types));
+    assert_eq!(TypeSection::decode(&payload[..payload.len() - 1]), None);
+}
@@ -1,2 +1,34 @@
+pub mod cache;
+pub mod call_graph;
 pub mod compile;
+pub mod compiler;
+pub mod conversion;
+pub mod copy_prop;
+pub mod cse;
+pub mod dce;
+pub mod error_sites;
+pub mod eval;
+pub mod exception;
+pub mod flat_let;
+pub mod format;
+pub mod free_vars;
+pub mod golden;
+pub mod incremental;
+pub mod intern;
+pub mod js_glue;
+pub mod let_float;
+pub mod local_alloc;
+pub mod metadata;
+pub mod names;
+pub mod opt_level;
 pub mod parser;
+pub mod pass_manager;
+pub mod peephole;
+pub mod pretty_print;
+pub mod profile;
+pub mod project;
+pub mod serialize;
+pub mod uncurry;
+pub mod util;
+pub mod verify;
+pub mod wat;
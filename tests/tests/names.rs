@@ -0,0 +1,70 @@
+use super::util::contains;
+use webml::ast::TypeError;
+use webml::{compile, compile_str, CompileError, Config, Target};
+
+#[test]
+fn name_section_has_sml_names() {
+    let input = "fun double x = x * 2 val y = double 3";
+    let code = compile_str(input, &Config::default()).unwrap();
+    assert!(contains(&code, "\x04name"));
+    assert!(contains(&code, "double@"));
+    assert!(contains(&code, "webml-rt.alloc"));
+}
+
+#[test]
+fn exported_functions() {
+    let input = "fun double x = x * 2 fun triple x = x * 3 val y = 1";
+    let config = Config {
+        exports: vec!["double".into()],
+        ..Config::default()
+    };
+    assert!(compile_str(input, &config).is_ok());
+
+    let config = Config {
+        exports: vec!["y".into()],
+        ..Config::default()
+    };
+    match compile_str(input, &config) {
+        Err(CompileError::Pass {
+            error: TypeError::UnknownExport(name),
+            ..
+        }) => assert_eq!(name, "y"),
+        _ => panic!("exporting a value should fail"),
+    }
+}
+
+#[test]
+fn host_imports() {
+    let input = r#"_import "js" "log" : int -> unit
+           _import "js" "now" : unit -> real
+           _import "js" "max" : int * int -> int
+           val () = log (max (1, 2))
+           val t = now ()"#;
+    let code = compile_str(input, &Config::default()).unwrap();
+    assert!(contains(&code, "js.log"));
+    assert!(contains(&code, "js.now"));
+    assert!(contains(&code, "js.max"));
+}
+
+#[test]
+fn wasi_target_implements_print() {
+    let input = "val () = printInt 1";
+    let config = Config {
+        target: Target::Wasi,
+        ..Config::default()
+    };
+    let module = compile(input, &config).unwrap();
+    assert!(module.interface.imports.is_empty());
+    let mut code = Vec::new();
+    module.dump(&mut code);
+    assert!(contains(&code, "wasi_snapshot_preview1.fd_write"));
+    assert!(contains(&code, "js-ffi.print"));
+}
+
+#[test]
+fn shadow_stack_is_imported() {
+    let input = "fun pair x = (x, x) val p = pair 1 val q = pair 2";
+    let code = compile_str(input, &Config::default()).unwrap();
+    assert!(contains(&code, "webml-rt.enter"));
+    assert!(contains(&code, "webml-rt.leave"));
+}
@@ -0,0 +1,128 @@
+use std::fs;
+use std::path::PathBuf;
+use webml::ast::{CaseSimplify, Desugar, Rename, Typer, VarToConstructor};
+use webml::hir::{ConstFold, FlatExpr, FlatLet, Inline, Uncurry, AST2HIR};
+use webml::id::Id;
+use webml::pass::ConvError;
+use webml::{
+    eval, parse, Artifact, Compiler, Config, Emit, Interpreter, OptLevel, PassManager, Prelude, IR,
+};
+
+const PRELUDE: &str = Prelude::BUILTIN;
+
+fn golden_programs() -> Vec<PathBuf> {
+    let mut paths = fs::read_dir("tests/tests/golden")
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().and_then(|e| e.to_str()) == Some("sml"))
+        .collect::<Vec<_>>();
+    paths.sort();
+    paths
+}
+
+/// runs the program with the interpreter after the inlining and the constant folding
+fn run_optimized(program: &str, config: &Config) -> String {
+    let id = Id::new();
+    let mut passes = PassManager::new();
+    passes
+        .add("parse", ConvError::new(parse))
+        .add("desugar", Desugar::new(id.clone()))
+        .add("rename", Rename::new(id.clone()))
+        .add("var_to_constructor", VarToConstructor::new(id.clone()))
+        .add("typing", Typer::new(id.clone()))
+        .add("case_simplify", CaseSimplify::new(id.clone()))
+        .add("ast_to_hir", AST2HIR::new(id.clone()))
+        .add("uncurry", Uncurry::new(id.clone()))
+        .add("flattening_expression", FlatExpr::new(id.clone()))
+        .add("inlining", Inline::new(id))
+        .add("constant_folding", ConstFold::new())
+        .add("flattening_let", FlatLet::new());
+    let hir = match passes.run(program, config).unwrap() {
+        IR::HIR((_, hir)) => hir,
+        ir => panic!("unexpected IR: {}", ir.kind()),
+    };
+    let mut out = Vec::new();
+    let result = Interpreter::new(&mut out).run(&hir);
+    let mut output = String::from_utf8(out).unwrap();
+    if let Err(e) = result {
+        output.push_str(&format!("error: {}\n", e));
+    }
+    output
+}
+
+fn hir(program: &str, opt_level: OptLevel) -> String {
+    let output = Compiler::builder()
+        .basis(PRELUDE)
+        .source("main.sml", program)
+        .opt_level(opt_level)
+        .artifact(Artifact::Ir(Emit::HIR))
+        .build()
+        .compile();
+    assert!(output.is_ok(), "{:?}", output.diagnostics);
+    String::from_utf8(output.dump(Emit::HIR).unwrap().to_vec()).unwrap()
+}
+
+#[test]
+fn presets_compile_the_golden_programs() {
+    for path in golden_programs() {
+        for &opt_level in &[OptLevel::O0, OptLevel::O1, OptLevel::O2, OptLevel::Os] {
+            let output = Compiler::builder()
+                .basis(PRELUDE)
+                .source(&path, fs::read_to_string(&path).unwrap())
+                .opt_level(opt_level)
+                .build()
+                .compile();
+            assert!(
+                output.is_ok(),
+                "{} at {:?}: {:?}",
+                path.display(),
+                opt_level,
+                output.diagnostics
+            );
+        }
+    }
+}
+
+#[test]
+fn inlining_and_folding_keep_the_output() {
+    for path in golden_programs() {
+        let source = fs::read_to_string(&path).unwrap();
+        let mut expected = Vec::new();
+        let result = eval(&source, &Config::default(), &mut expected);
+        let mut expected = String::from_utf8(expected).unwrap();
+        if let Err(e) = result {
+            expected.push_str(&format!("error: {}\n", e));
+        }
+        for &inline_threshold in &[Some(0), Some(8), Some(1000)] {
+            let config = Config {
+                inline_threshold,
+                ..Config::default()
+            };
+            assert_eq!(
+                run_optimized(&format!("{}\n{}", PRELUDE, source), &config),
+                expected,
+                "{} inlining up to {:?}",
+                path.display(),
+                inline_threshold
+            );
+        }
+    }
+}
+
+#[test]
+fn inlined_calls_of_constants_are_folded() {
+    let program = "fun double x = x * 2 val () = printInt (double 21)";
+    assert!(hir(program, OptLevel::O2).contains(": int = 42\n"));
+    assert!(!hir(program, OptLevel::O1).contains(": int = 42\n"));
+}
+
+#[test]
+fn size_level_inlines_only_tiny_functions() {
+    let program = "fun poly x = x * x * x + x * x * 2 + x * 3 + 4
+                   val () = printInt (poly 1)";
+    let o2 = hir(program, OptLevel::O2);
+    let os = hir(program, OptLevel::Os);
+    // the ids of the symbols may contain the digits of the result too
+    assert!(o2.contains(": int = 10\n"), "{}", o2);
+    assert!(!os.contains(": int = 10\n"), "{}", os);
+}
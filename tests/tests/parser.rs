@@ -2,8 +2,8 @@ use webml::ast::{
     Declaration, DerivedDeclaration, DerivedExprKind, Expr, ExprKind, Pattern, PatternKind, Type,
     AST,
 };
-use webml::parse;
 use webml::prim::*;
+use webml::{parse, StreamParser};
 
 #[test]
 fn parse_char() {
@@ -29,6 +29,104 @@ fn parse_char() {
     )
 }
 
+#[test]
+fn parse_string() {
+    let input = r#"val x = "a\tb\"c\\d\n""#;
+    let ast = parse(input).unwrap();
+    assert_eq!(
+        ast,
+        AST(vec![Declaration::Val {
+            rec: false,
+            pattern: Pattern {
+                ty: (),
+                inner: PatternKind::Variable {
+                    name: Symbol::new("x"),
+                }
+            },
+            expr: Expr {
+                ty: (),
+                inner: ExprKind::Literal {
+                    value: Literal::String("a\tb\"c\\d\n".into()),
+                }
+            },
+        },])
+    )
+}
+
+#[test]
+fn parse_qualified_name() {
+    let input = r#"val x = Int.toString y"#;
+    let ast = parse(input).unwrap();
+    assert_eq!(
+        ast,
+        AST(vec![Declaration::Val {
+            rec: false,
+            pattern: Pattern {
+                ty: (),
+                inner: PatternKind::Variable {
+                    name: Symbol::new("x"),
+                }
+            },
+            expr: Expr {
+                ty: (),
+                inner: ExprKind::App {
+                    fun: Expr {
+                        ty: (),
+                        inner: ExprKind::Symbol {
+                            name: Symbol::new("Int.toString")
+                        }
+                    }
+                    .boxed(),
+                    arg: Expr {
+                        ty: (),
+                        inner: ExprKind::Symbol {
+                            name: Symbol::new("y")
+                        }
+                    }
+                    .boxed()
+                }
+            }
+        }])
+    )
+}
+
+#[test]
+fn parse_qualified_symbolic_name() {
+    let input = r#"val x = Word.<< y"#;
+    let ast = parse(input).unwrap();
+    assert_eq!(
+        ast,
+        AST(vec![Declaration::Val {
+            rec: false,
+            pattern: Pattern {
+                ty: (),
+                inner: PatternKind::Variable {
+                    name: Symbol::new("x"),
+                }
+            },
+            expr: Expr {
+                ty: (),
+                inner: ExprKind::App {
+                    fun: Expr {
+                        ty: (),
+                        inner: ExprKind::Symbol {
+                            name: Symbol::new("Word.<<")
+                        }
+                    }
+                    .boxed(),
+                    arg: Expr {
+                        ty: (),
+                        inner: ExprKind::Symbol {
+                            name: Symbol::new("y")
+                        }
+                    }
+                    .boxed()
+                }
+            }
+        }])
+    )
+}
+
 #[test]
 fn parse_int() {
     let input = r#"val x = 1"#;
@@ -53,6 +151,43 @@ fn parse_int() {
     )
 }
 
+#[test]
+fn parse_word() {
+    let input = r#"val x = (0w10, 0wxfF)"#;
+    let ast = parse(input).unwrap();
+    assert_eq!(
+        ast,
+        AST(vec![Declaration::Val {
+            rec: false,
+            pattern: Pattern {
+                ty: (),
+                inner: PatternKind::Variable {
+                    name: Symbol::new("x"),
+                }
+            },
+            expr: Expr {
+                ty: (),
+                inner: ExprKind::Tuple {
+                    tuple: vec![
+                        Expr {
+                            ty: (),
+                            inner: ExprKind::Literal {
+                                value: Literal::Word(10),
+                            }
+                        },
+                        Expr {
+                            ty: (),
+                            inner: ExprKind::Literal {
+                                value: Literal::Word(255),
+                            }
+                        },
+                    ]
+                }
+            },
+        },])
+    )
+}
+
 #[test]
 fn parse_float() {
     let input = r#"val x = 1.0"#;
@@ -508,6 +643,21 @@ fn parse_externcall() {
     )
 }
 
+#[test]
+fn parse_import() {
+    let input = r#"_import "js" "log" : int * real -> unit"#;
+    let ast = parse(input).unwrap();
+    assert_eq!(
+        ast,
+        AST(vec![Declaration::D(DerivedDeclaration::Import {
+            module: "js".into(),
+            name: Symbol::new("log"),
+            argty: Type::Tuple(vec![Type::Int, Type::Real]),
+            retty: Type::Tuple(vec![]),
+        })])
+    )
+}
+
 #[test]
 fn parse_binop_pref() {
     let input = r#"infix 6 + infix 7 * val x = 1 + 2 * 3"#;
@@ -762,6 +912,44 @@ fn parse_datatype_primlike() {
     )
 }
 
+#[test]
+fn parse_datatype_withtype() {
+    let input =
+        r#"datatype tree = Leaf | Node of forest withtype forest = tree * tree and size = int"#;
+    let ast = parse(input).unwrap();
+    let tree = || Type::Datatype(Symbol::new("tree"));
+    assert_eq!(
+        ast,
+        AST(vec![Declaration::D(DerivedDeclaration::Withtype {
+            name: Symbol::new("tree"),
+            constructors: vec![
+                (Symbol::new("Leaf"), None),
+                (
+                    Symbol::new("Node"),
+                    Some(Type::Datatype(Symbol::new("forest")))
+                ),
+            ],
+            types: vec![
+                (Symbol::new("forest"), Type::Tuple(vec![tree(), tree()])),
+                (Symbol::new("size"), Type::Int),
+            ]
+        })])
+    )
+}
+
+#[test]
+fn parse_datatype_replication() {
+    let input = r#"datatype time = datatype Time.time"#;
+    let ast = parse(input).unwrap();
+    assert_eq!(
+        ast,
+        AST(vec![Declaration::D(DerivedDeclaration::Replication {
+            name: Symbol::new("time"),
+            datatype: Symbol::new("Time.time"),
+        })])
+    )
+}
+
 #[test]
 fn parse_fun_unary() {
     let input = r#"fun f x = x"#;
@@ -1640,3 +1828,329 @@ fn parse_multistatement_val_datatype() {
         ])
     )
 }
+
+#[test]
+fn parse_exception_raise_handle() {
+    let input = r#"exception Fail of int val x = raise Fail 1 handle Fail n => n"#;
+    let ast = parse(input).unwrap();
+    assert_eq!(
+        ast,
+        AST(vec![
+            Declaration::Exception {
+                name: Symbol::new("Fail"),
+                arg: Some(Type::Int),
+            },
+            Declaration::Val {
+                rec: false,
+                pattern: Pattern {
+                    ty: (),
+                    inner: PatternKind::Variable {
+                        name: Symbol::new("x")
+                    }
+                },
+                expr: Expr {
+                    ty: (),
+                    inner: ExprKind::BuiltinCall {
+                        fun: BIF::Raise,
+                        args: vec![Expr {
+                            ty: (),
+                            inner: ExprKind::D(DerivedExprKind::Handle {
+                                expr: Expr {
+                                    ty: (),
+                                    inner: ExprKind::App {
+                                        fun: Expr {
+                                            ty: (),
+                                            inner: ExprKind::Symbol {
+                                                name: Symbol::new("Fail")
+                                            }
+                                        }
+                                        .boxed(),
+                                        arg: Expr {
+                                            ty: (),
+                                            inner: ExprKind::Literal {
+                                                value: Literal::Int(1)
+                                            }
+                                        }
+                                        .boxed()
+                                    }
+                                }
+                                .boxed(),
+                                clauses: vec![(
+                                    Pattern {
+                                        ty: (),
+                                        inner: PatternKind::Constructor {
+                                            name: Symbol::new("Fail"),
+                                            arg: Some(
+                                                Pattern {
+                                                    ty: (),
+                                                    inner: PatternKind::Variable {
+                                                        name: Symbol::new("n")
+                                                    }
+                                                }
+                                                .boxed()
+                                            )
+                                        }
+                                    },
+                                    Expr {
+                                        ty: (),
+                                        inner: ExprKind::Symbol {
+                                            name: Symbol::new("n")
+                                        }
+                                    }
+                                )]
+                            })
+                        }]
+                    }
+                }
+            }
+        ])
+    )
+}
+
+#[test]
+fn malformed_literals_are_errors() {
+    for input in &[
+        "val x = 99999999999999999999",
+        "val x = case 1 of 99999999999999999999 => 1",
+        r##"val x = #"ab""##,
+        r##"val x = #"""##,
+        r##"fun f #"ab" = 1"##,
+        r##"val x = #"a"##,
+        "infix 999 ++",
+    ] {
+        assert!(parse(input).is_err(), "{}", input);
+    }
+}
+
+#[test]
+fn comments_start_at_parens() {
+    assert!(parse("val x = (*y*) 1").is_ok());
+    assert!(parse("val x = (*y 1)").is_err());
+}
+
+#[test]
+fn names_may_contain_primes() {
+    let ast = parse("val x' = 1").unwrap();
+    match &ast.0[0] {
+        Declaration::Val {
+            pattern:
+                Pattern {
+                    inner: PatternKind::Variable { name },
+                    ..
+                },
+            ..
+        } => assert_eq!(name, &Symbol::new("x'")),
+        decl => panic!("unexpected declaration {:?}", decl),
+    }
+}
+
+#[test]
+fn operators_without_operands_are_errors() {
+    for input in &[
+        "infix 6 +\nval x = 1 +",
+        "infix 6 +\nval x = + 1",
+        "infix 6 +\nval x = +",
+    ] {
+        assert!(parse(input).is_err(), "{}", input);
+    }
+}
+
+#[test]
+fn parse_infix_priority_zero() {
+    let ast = parse("infix 0 ++\nval x = a ++ b").unwrap();
+    match &ast.0[1] {
+        Declaration::Val {
+            expr:
+                Expr {
+                    inner: ExprKind::App { fun, .. },
+                    ..
+                },
+            ..
+        } => assert_eq!(
+            fun.inner,
+            ExprKind::Symbol {
+                name: Symbol::new("++")
+            }
+        ),
+        decl => panic!("unexpected declaration {:?}", decl),
+    }
+}
+
+#[test]
+fn keywords_are_not_prefixes_of_names() {
+    let ast = parse("val x = trueish").unwrap();
+    match &ast.0[0] {
+        Declaration::Val { expr, .. } => assert_eq!(
+            expr.inner,
+            ExprKind::Symbol {
+                name: Symbol::new("trueish")
+            }
+        ),
+        decl => panic!("unexpected declaration {:?}", decl),
+    }
+    assert!(parse("fun f falsey = falsey").is_ok());
+    assert!(parse("val x = let val y = 1 in y endx").is_err());
+    assert!(parse("valx = 1").is_err());
+}
+
+#[test]
+fn too_large_literals_are_errors_at_the_literal() {
+    for input in &[
+        "val x = 99999999999999999999",
+        "val x = case 1 of 99999999999999999999 => 1 | _ => 0",
+        "infix 999 ++",
+    ] {
+        match parse(input) {
+            Err(nom::Err::Failure((rest, nom::error::ErrorKind::TooLarge))) => {
+                assert!(rest.starts_with("999"), "{}", rest)
+            }
+            result => panic!("unexpected result of {}: {:?}", input, result),
+        }
+    }
+}
+
+#[test]
+fn parse_case_or_pattern() {
+    let input = r#"val x = case 1 of 1 | 2 => true | _ => false"#;
+    let ast = parse(input).unwrap();
+    let constant = |value| Pattern {
+        ty: (),
+        inner: PatternKind::Constant { value },
+    };
+    let constructor = |name| Expr {
+        ty: (),
+        inner: ExprKind::Constructor {
+            arg: None,
+            name: Symbol::new(name),
+        },
+    };
+    assert_eq!(
+        ast,
+        AST(vec![Declaration::Val {
+            rec: false,
+            pattern: Pattern {
+                ty: (),
+                inner: PatternKind::Variable {
+                    name: Symbol::new("x"),
+                }
+            },
+            expr: Expr {
+                ty: (),
+                inner: ExprKind::Case {
+                    cond: Expr {
+                        ty: (),
+                        inner: ExprKind::Literal {
+                            value: Literal::Int(1)
+                        }
+                    }
+                    .boxed(),
+                    clauses: vec![
+                        (
+                            Pattern {
+                                ty: (),
+                                inner: PatternKind::Or {
+                                    patterns: vec![constant(1), constant(2)]
+                                }
+                            },
+                            constructor("true"),
+                        ),
+                        (
+                            Pattern {
+                                ty: (),
+                                inner: PatternKind::Wildcard {}
+                            },
+                            constructor("false"),
+                        ),
+                    ]
+                }
+            },
+        }]),
+    );
+}
+
+#[test]
+fn parse_case_guard() {
+    let input = r#"val y = case x of n when pos n => n | _ => 0"#;
+    let ast = parse(input).unwrap();
+    let symbol = |name| Expr {
+        ty: (),
+        inner: ExprKind::Symbol {
+            name: Symbol::new(name),
+        },
+    };
+    assert_eq!(
+        ast,
+        AST(vec![Declaration::Val {
+            rec: false,
+            pattern: Pattern {
+                ty: (),
+                inner: PatternKind::Variable {
+                    name: Symbol::new("y"),
+                }
+            },
+            expr: Expr {
+                ty: (),
+                inner: ExprKind::D(DerivedExprKind::GuardedCase {
+                    cond: symbol("x").boxed(),
+                    clauses: vec![
+                        (
+                            Pattern {
+                                ty: (),
+                                inner: PatternKind::Variable {
+                                    name: Symbol::new("n"),
+                                }
+                            },
+                            Some(Expr {
+                                ty: (),
+                                inner: ExprKind::App {
+                                    fun: symbol("pos").boxed(),
+                                    arg: symbol("n").boxed(),
+                                }
+                            }),
+                            symbol("n"),
+                        ),
+                        (
+                            Pattern {
+                                ty: (),
+                                inner: PatternKind::Wildcard {}
+                            },
+                            None,
+                            Expr {
+                                ty: (),
+                                inner: ExprKind::Literal {
+                                    value: Literal::Int(0)
+                                }
+                            },
+                        ),
+                    ]
+                }),
+            },
+        }]),
+    );
+}
+
+#[test]
+fn stream_parser_yields_complete_declarations() {
+    let input = concat!(
+        "infix 5 ++\n",
+        "fun x ++ y = x + y\n",
+        "val value = 1 ++ 2 (* comment *)\n",
+        "val y = let val z = value in z end",
+    );
+    let mut parser = StreamParser::new();
+    let mut decls = Vec::new();
+    for c in input.chars() {
+        decls.extend(parser.feed(&c.to_string()));
+    }
+    // the last declaration may continue
+    assert_eq!(decls.len(), 3);
+    decls.extend(parser.finish().unwrap());
+    assert_eq!(decls, parse(input).unwrap().0);
+}
+
+#[test]
+fn stream_parser_reports_errors_at_the_end() {
+    let mut parser = StreamParser::new();
+    assert_eq!(parser.feed("val x = 1\nval y = ").len(), 1);
+    assert!(parser.finish().is_err());
+}
@@ -0,0 +1,146 @@
+use webml::{compile_str, Collector, CompileError, Config, Emit, EmitFormat, Memory};
+
+fn source() -> String {
+    "val x = 1 + 2\n".to_string()
+}
+
+#[test]
+fn stop_after_named_pass() {
+    let config = Config {
+        stop_after: Some("typing".into()),
+        ..Config::default()
+    };
+    match compile_str(&source(), &config) {
+        Err(CompileError::Stopped { pass }) => assert_eq!(pass, "typing"),
+        _ => panic!("compilation should stop after typing"),
+    }
+}
+
+#[test]
+fn disabled_passes_are_skipped() {
+    let mut config = Config::default();
+    config.disabled_passes.insert("copy_propagation".into());
+    config
+        .disabled_passes
+        .insert("dead_code_elimination".into());
+    assert!(compile_str(&source(), &config).is_ok());
+}
+
+#[test]
+fn emitted_module_is_validated() {
+    let config = Config {
+        stop_after: Some("validate".into()),
+        ..Config::default()
+    };
+    match compile_str(&source(), &config) {
+        Err(CompileError::Stopped { pass }) => assert_eq!(pass, "validate"),
+        _ => panic!("the module should be validated"),
+    }
+}
+
+#[test]
+fn disabled_required_pass_reports_mismatch() {
+    let mut config = Config::default();
+    config.disabled_passes.insert("ast_to_hir".into());
+    config.disabled_passes.insert("verify_ast_to_hir".into());
+    match compile_str(&source(), &config) {
+        Err(CompileError::IRMismatch { pass, ir }) => {
+            assert_eq!(pass, "uncurry");
+            assert_eq!(ir, "typed core");
+        }
+        _ => panic!("uncurry should reject typed core"),
+    }
+}
+
+#[test]
+fn emit_names_parse() {
+    for name in Emit::NAMES {
+        assert!(name.parse::<Emit>().is_ok(), "{}", name);
+    }
+    assert_eq!("typed-ast".parse::<Emit>(), Ok(Emit::TypedAst));
+    assert!("core".parse::<Emit>().is_err());
+}
+
+#[test]
+fn emit_format_names_parse() {
+    for name in EmitFormat::NAMES {
+        assert!(name.parse::<EmitFormat>().is_ok(), "{}", name);
+    }
+    assert_eq!("text".parse::<EmitFormat>(), Ok(EmitFormat::Text));
+    assert!("yaml".parse::<EmitFormat>().is_err());
+}
+
+#[test]
+fn collector_names_parse() {
+    for name in Collector::NAMES {
+        assert!(name.parse::<Collector>().is_ok(), "{}", name);
+    }
+    assert_eq!(
+        "generational".parse::<Collector>(),
+        Ok(Collector::Generational)
+    );
+    assert_eq!(
+        "refcount".parse::<Collector>(),
+        Ok(Collector::ReferenceCounting)
+    );
+    assert!("copying".parse::<Collector>().is_err());
+}
+
+#[test]
+fn generational_collector_compiles() {
+    let input = "datatype t = A | B of int * t fun f y = (B (y, B (y + 1, A)), fn z => y + z) val x = f 1";
+    let config = Config {
+        collector: Collector::Generational,
+        ..Config::default()
+    };
+    compile_str(input, &config).unwrap();
+}
+
+#[test]
+fn emit_does_not_change_output() {
+    let mut config = Config::default();
+    config.emit.insert(Emit::HIR);
+    config.emit.insert(Emit::MIR);
+    let emitted = compile_str(&source(), &config).unwrap();
+    let plain = compile_str(&source(), &Config::default()).unwrap();
+    assert_eq!(emitted, plain);
+}
+
+#[test]
+fn memory_limits_are_checked() {
+    let config = Config {
+        memory: Memory {
+            initial_pages: 4,
+            maximum_pages: Some(2),
+            grow: true,
+        },
+        ..Config::default()
+    };
+    match compile_str(&source(), &config) {
+        Err(CompileError::InvalidConfig { .. }) => (),
+        _ => panic!("maximum less than initial memory should be rejected"),
+    }
+
+    let config = Config {
+        memory: Memory {
+            initial_pages: 4,
+            maximum_pages: None,
+            grow: false,
+        },
+        ..Config::default()
+    };
+    assert_eq!(config.memory.maximum(), Some(4));
+    assert!(compile_str(&source(), &config).is_ok());
+}
+
+#[test]
+fn async_imports_need_cps() {
+    let config = Config {
+        async_imports: vec![("js".into(), "fetch".into())],
+        ..Config::default()
+    };
+    match compile_str(&source(), &config) {
+        Err(CompileError::InvalidConfig { .. }) => (),
+        _ => panic!("async imports without the CPS conversion should be rejected"),
+    }
+}
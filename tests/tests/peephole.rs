@@ -0,0 +1,97 @@
+use webml::backend::peephole::optimize;
+
+// a module of a function `(param i32) (result i32)` with an i32 local and the body
+fn module(body: &[u8]) -> Vec<u8> {
+    #[rustfmt::skip]
+    let mut code = vec![
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00,
+        // type: (i32) -> i32
+        0x01, 0x06, 0x01, 0x60, 0x01, 0x7f, 0x01, 0x7f,
+        // function (type 0)
+        0x03, 0x02, 0x01, 0x00,
+        // memory 1
+        0x05, 0x03, 0x01, 0x00, 0x01,
+    ];
+    let size = body.len() as u8 + 4;
+    code.extend_from_slice(&[0x0a, size + 2, 0x01, size, 0x01, 0x01, 0x7f]);
+    code.extend_from_slice(body);
+    code.push(0x0b);
+    code
+}
+
+fn assert_optimized(before: &[u8], after: &[u8]) {
+    assert_eq!(optimize(&module(before)).unwrap(), module(after));
+}
+
+#[test]
+fn set_and_get_become_tee() {
+    assert_optimized(
+        &[
+            0x20, 0x00, // local.get 0
+            0x21, 0x01, // local.set 1
+            0x20, 0x01, // local.get 1
+        ],
+        &[
+            0x20, 0x00, // local.get 0
+            0x22, 0x01, // local.tee 1
+        ],
+    );
+}
+
+#[test]
+fn constant_address_offsets_are_folded() {
+    assert_optimized(
+        &[
+            0x20, 0x00, // local.get 0
+            0x41, 0x08, // i32.const 8
+            0x6a, // i32.add
+            0x28, 0x02, 0x04, // i32.load offset=4
+            0x21, 0x01, // local.set 1
+            0x20, 0x00, // local.get 0
+            0x41, 0x04, // i32.const 4
+            0x6a, // i32.add
+            0x20, 0x01, // local.get 1
+            0x36, 0x02, 0x00, // i32.store
+            0x20, 0x01, // local.get 1
+        ],
+        &[
+            0x20, 0x00, // local.get 0
+            0x28, 0x02, 0x0c, // i32.load offset=12
+            0x21, 0x01, // local.set 1
+            0x20, 0x00, // local.get 0
+            0x20, 0x01, // local.get 1
+            0x36, 0x02, 0x04, // i32.store offset=4
+            0x20, 0x01, // local.get 1
+        ],
+    );
+}
+
+#[test]
+fn negative_offsets_are_not_folded() {
+    let body = &[
+        0x20, 0x00, // local.get 0
+        0x41, 0x7c, // i32.const -4
+        0x6a, // i32.add
+        0x28, 0x02, 0x00, // i32.load
+    ];
+    assert_optimized(body, body);
+}
+
+#[test]
+fn unused_values_are_not_computed() {
+    assert_optimized(
+        &[
+            0x41, 0x01, // i32.const 1
+            0x1a, // drop
+            0x20, 0x00, // local.get 0
+            0x21, 0x01, // local.set 1
+            0x20, 0x01, // local.get 1
+            0x1a, // drop
+            0x20, 0x01, // local.get 1
+        ],
+        &[
+            0x20, 0x00, // local.get 0
+            0x22, 0x01, // local.tee 1
+        ],
+    );
+}
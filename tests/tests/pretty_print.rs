@@ -0,0 +1,295 @@
+use proptest::prelude::*;
+use webml::ast::{
+    Declaration, DerivedDeclaration, DerivedExprKind, ExprKind, PatternKind, Type,
+    UntypedDeclaration, UntypedExpr, UntypedPattern, AST,
+};
+use webml::parse;
+use webml::prim::*;
+use webml::util::PP;
+
+fn print<T: PP>(t: &T) -> String {
+    let mut buf = Vec::new();
+    t.pp(&mut buf, 0).unwrap();
+    String::from_utf8(buf).unwrap()
+}
+
+fn expr(inner: ExprKind<()>) -> UntypedExpr {
+    UntypedExpr { ty: (), inner }
+}
+
+fn pattern(inner: PatternKind<()>) -> UntypedPattern {
+    UntypedPattern { ty: (), inner }
+}
+
+// the names include the ones starting with keywords
+fn name() -> impl Strategy<Value = Symbol> {
+    prop::sample::select(vec!["x", "y'", "f_1", "inner", "option", "iffy"]).prop_map(Symbol::new)
+}
+
+fn operator() -> impl Strategy<Value = Symbol> {
+    prop::sample::select(vec!["+", "*", "++", "<=>", "::", "="]).prop_map(Symbol::new)
+}
+
+fn constructor_name() -> impl Strategy<Value = Symbol> {
+    prop::sample::select(vec!["Nil", "Cons", "Some"]).prop_map(Symbol::new)
+}
+
+fn type_name() -> impl Strategy<Value = Symbol> {
+    prop::sample::select(vec!["t", "tree", "shape"]).prop_map(Symbol::new)
+}
+
+fn string() -> impl Strategy<Value = String> {
+    prop::sample::select(vec!["js-ffi", "print", "Math.sqrt"]).prop_map(String::from)
+}
+
+fn character() -> impl Strategy<Value = u32> {
+    prop::sample::select(vec!['a', 'Z', '0', ' ', '(', '*', '\'', '\\', '#', 'λ'])
+        .prop_map(|c| c as u32)
+}
+
+fn boolean() -> impl Strategy<Value = Symbol> {
+    prop::sample::select(vec!["true", "false"]).prop_map(Symbol::new)
+}
+
+fn literal() -> impl Strategy<Value = Literal> {
+    prop_oneof![
+        (0i64..1_000_000).prop_map(Literal::Int),
+        (0u32..100_000, 0u32..1000).prop_map(|(i, f)| Literal::Real(i as f64 + f as f64 / 1000.0)),
+        // printed with exponents
+        (1u32..1000, -30i32..30).prop_map(|(m, e)| Literal::Real(m as f64 * 10f64.powi(e))),
+        character().prop_map(Literal::Char),
+        prop::collection::vec(character(), 0..4)
+            .prop_map(|cs| Literal::String(cs.into_iter().filter_map(char::from_u32).collect())),
+    ]
+}
+
+fn ty() -> impl Strategy<Value = Type> {
+    let leaf = prop_oneof![
+        Just(Type::Int),
+        Just(Type::Real),
+        Just(Type::Char),
+        Just(Type::String),
+        Just(Type::unit()),
+        type_name().prop_map(Type::Datatype),
+    ];
+    leaf.prop_recursive(3, 16, 3, |inner| {
+        prop_oneof![
+            (inner.clone(), inner.clone()).prop_map(|(arg, ret)| Type::fun(arg, ret)),
+            prop::collection::vec(inner, 2..4).prop_map(Type::Tuple),
+        ]
+    })
+}
+
+fn pat() -> impl Strategy<Value = UntypedPattern> {
+    let leaf = prop_oneof![
+        (0i64..1000).prop_map(|value| PatternKind::Constant { value }),
+        character().prop_map(|value| PatternKind::Char { value }),
+        boolean().prop_map(|name| PatternKind::Constructor { name, arg: None }),
+        name().prop_map(|name| PatternKind::Variable { name }),
+        Just(PatternKind::Tuple { tuple: vec![] }),
+        Just(PatternKind::Wildcard {}),
+    ]
+    .prop_map(pattern);
+    leaf.prop_recursive(3, 16, 3, |inner| {
+        prop_oneof![
+            (constructor_name(), inner.clone()).prop_map(|(name, arg)| {
+                pattern(PatternKind::Constructor {
+                    name,
+                    arg: Some(Box::new(arg)),
+                })
+            }),
+            prop::collection::vec(inner, 2..4)
+                .prop_map(|tuple| pattern(PatternKind::Tuple { tuple })),
+        ]
+    })
+}
+
+fn clauses(
+    expr: BoxedStrategy<UntypedExpr>,
+) -> impl Strategy<Value = Vec<(UntypedPattern, UntypedExpr)>> {
+    prop::collection::vec((pat(), expr), 1..4)
+}
+
+fn constructor() -> impl Strategy<Value = (Symbol, Option<Type>)> {
+    (constructor_name(), prop::option::of(ty()))
+}
+
+fn decl(expr: BoxedStrategy<UntypedExpr>) -> impl Strategy<Value = UntypedDeclaration> {
+    let fun_clause = (prop::collection::vec(pat(), 1..3), expr.clone());
+    prop_oneof![
+        1 => (type_name(), prop::collection::vec(constructor(), 1..4))
+            .prop_map(|(name, constructors)| Declaration::Datatype { name, constructors }),
+        1 => (
+            type_name(),
+            prop::collection::vec(constructor(), 1..4),
+            prop::collection::vec((type_name(), ty()), 1..3)
+        )
+            .prop_map(|(name, constructors, types)| {
+                Declaration::D(DerivedDeclaration::Withtype {
+                    name,
+                    constructors,
+                    types,
+                })
+            }),
+        1 => (type_name(), type_name()).prop_map(|(name, datatype)| {
+            Declaration::D(DerivedDeclaration::Replication { name, datatype })
+        }),
+        1 => constructor().prop_map(|(name, arg)| Declaration::Exception { name, arg }),
+        4 => (pat(), expr).prop_map(|(pattern, expr)| Declaration::Val {
+            rec: false,
+            pattern,
+            expr,
+        }),
+        4 => (
+            prop_oneof![name(), operator()],
+            prop::collection::vec(fun_clause, 1..3)
+        )
+            .prop_map(|(name, clauses)| Declaration::D(DerivedDeclaration::Fun { name, clauses })),
+        1 => (
+            prop::option::of(0u8..10),
+            prop::collection::vec(operator(), 1..3)
+        )
+            .prop_map(
+                |(priority, names)| Declaration::D(DerivedDeclaration::Infix { priority, names })
+            ),
+        1 => (string(), name(), ty(), ty()).prop_map(|(module, name, argty, retty)| {
+            Declaration::D(DerivedDeclaration::Import {
+                module,
+                name,
+                argty,
+                retty,
+            })
+        }),
+    ]
+}
+
+fn exp() -> impl Strategy<Value = UntypedExpr> {
+    let leaf = prop_oneof![
+        literal().prop_map(|value| ExprKind::Literal { value }),
+        prop_oneof![name(), operator()].prop_map(|name| ExprKind::Symbol { name }),
+        boolean().prop_map(|name| ExprKind::Constructor { name, arg: None }),
+        Just(ExprKind::Tuple { tuple: vec![] }),
+    ]
+    .prop_map(expr);
+    leaf.prop_recursive(6, 256, 4, |inner| {
+        let bif = prop::sample::select(vec![BIF::Add, BIF::Eq, BIF::Raise, BIF::Handle]);
+        let inner_kind = prop_oneof![
+            (inner.clone(), inner.clone()).prop_map(|(fun, arg)| ExprKind::App {
+                fun: fun.boxed(),
+                arg: arg.boxed(),
+            }),
+            prop::collection::vec(inner.clone(), 2..4).prop_map(|tuple| ExprKind::Tuple { tuple }),
+            (name(), inner.clone()).prop_map(|(param, body)| ExprKind::Fn {
+                param,
+                body: body.boxed(),
+            }),
+            (inner.clone(), clauses(inner.clone())).prop_map(|(cond, clauses)| ExprKind::Case {
+                cond: cond.boxed(),
+                clauses,
+            }),
+            (
+                prop::collection::vec(decl(inner.clone()), 1..3),
+                inner.clone()
+            )
+                .prop_map(|(binds, ret)| ExprKind::Binds {
+                    binds,
+                    ret: ret.boxed(),
+                }),
+            (bif, prop::collection::vec(inner.clone(), 1..3))
+                .prop_map(|(fun, args)| ExprKind::BuiltinCall { fun, args }),
+            (
+                (string(), string()),
+                prop::collection::vec(inner.clone(), 1..3),
+                prop::collection::vec(ty(), 1..3),
+                ty()
+            )
+                .prop_map(|((module, fun), args, argty, retty)| ExprKind::ExternCall {
+                    module,
+                    fun,
+                    args,
+                    argty,
+                    retty,
+                }),
+        ];
+        let derived = prop_oneof![
+            (inner.clone(), inner.clone(), inner.clone()).prop_map(|(cond, then, else_)| {
+                DerivedExprKind::If {
+                    cond: cond.boxed(),
+                    then: then.boxed(),
+                    else_: else_.boxed(),
+                }
+            }),
+            (inner.clone(), clauses(inner.clone())).prop_map(|(expr, clauses)| {
+                DerivedExprKind::Handle {
+                    expr: expr.boxed(),
+                    clauses,
+                }
+            }),
+            // the first clause is guarded, or it's parsed as a plain case
+            (
+                inner.clone(),
+                (pat(), inner.clone(), inner.clone()),
+                prop::collection::vec((pat(), prop::option::of(inner.clone()), inner), 0..3)
+            )
+                .prop_map(|(cond, (pat, guard, arm), rest)| {
+                    let mut clauses = vec![(pat, Some(guard), arm)];
+                    clauses.extend(rest);
+                    DerivedExprKind::GuardedCase {
+                        cond: cond.boxed(),
+                        clauses,
+                    }
+                }),
+        ];
+        prop_oneof![inner_kind, derived.prop_map(ExprKind::D)].prop_map(expr)
+    })
+}
+
+fn program() -> impl Strategy<Value = AST<()>> {
+    prop::collection::vec(decl(exp().boxed()), 1..5).prop_map(AST)
+}
+
+proptest! {
+    #[test]
+    fn printed_programs_parse_back(ast in program()) {
+        let printed = print(&ast);
+        let parsed = parse(&printed).map_err(|e| format!("{:?}", e));
+        prop_assert_eq!(parsed, Ok(ast), "{}", printed);
+    }
+}
+
+#[test]
+fn print_layout() {
+    let source = "infix 7 *
+infix 6 -
+fun fact 0 = 1 | fact n = n * fact (n - 1)
+val x = case fact 3 of 6 => fn y => y | _ => raise Fail";
+    assert_eq!(
+        print(&parse(source).unwrap()),
+        "infix 7 *
+infix 6 -
+fun fact 0 = 1
+  | fact n = op * (n, fact (op - (n, 1)))
+val x = case fact 3 of
+        6 => (fn y => y)
+      | _ => raise Fail
+"
+    );
+}
+
+#[test]
+fn nested_clauses_are_parenthesized() {
+    let source = "val x = case a of
+    0 => (case b of 1 => c | _ => d)
+  | _ => (e handle Fail => f)";
+    let ast = parse(source).unwrap();
+    let printed = print(&ast);
+    assert!(printed.contains("(case b of"), "{}", printed);
+    assert_eq!(parse(&printed).unwrap(), ast);
+}
+
+#[test]
+fn op_takes_operators_as_values() {
+    let ast = parse("infix 6 +\nval x = op + (1, 2)\nval y = f op +").unwrap();
+    let prefix = parse("val x = + (1, 2)\nval y = f +").unwrap();
+    assert_eq!(ast.0[1..], prefix.0[..]);
+}
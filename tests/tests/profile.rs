@@ -0,0 +1,91 @@
+use super::util;
+use webml::backend::{generate_glue, Metadata, Module};
+use webml::{compile, Config};
+
+fn double() -> String {
+    "fun double x = x * 2\nval () = printInt (double 21)".to_string()
+}
+
+fn profiled() -> Config {
+    Config {
+        profile: true,
+        ..Config::default()
+    }
+}
+
+fn contains(module: &Module, name: &str) -> bool {
+    let mut code = Vec::new();
+    module.dump(&mut code);
+    util::contains(&code, name)
+}
+
+#[test]
+fn profiled_module_counts_with_the_runtime() {
+    let module = compile(&double(), &profiled()).unwrap();
+    for name in &[
+        "webml-rt.profile_init",
+        "webml-rt.profile_call",
+        "webml-rt.profile_cycles",
+    ] {
+        assert!(contains(&module, name), "{} is not imported", name);
+    }
+    let module = compile(&double(), &Config::default()).unwrap();
+    assert!(!contains(&module, "webml-rt.profile_call"));
+    assert!(module.interface.profile.is_empty());
+}
+
+#[test]
+fn profile_rows_are_the_functions() {
+    let module = compile(&double(), &profiled()).unwrap();
+    let rows = &module.interface.profile;
+    assert!(
+        rows.iter().any(|name| name.starts_with("double@")),
+        "{:?}",
+        rows
+    );
+    assert!(
+        rows.iter().any(|name| name.starts_with("sml-main@")),
+        "{:?}",
+        rows
+    );
+    let glue = generate_glue(&module.interface, "double.wasm");
+    for row in rows {
+        assert!(glue.contains(&format!("    {:?},\n", row)), "{}", row);
+    }
+    assert!(glue.contains("export function printProfile(exports"));
+}
+
+#[test]
+fn profile_is_recorded_in_metadata() {
+    let module = compile(&double(), &profiled()).unwrap();
+    let section = module
+        .custom_sections
+        .iter()
+        .find(|section| section.name == Metadata::SECTION_NAME)
+        .unwrap();
+    let metadata = Metadata::decode(&section.payload).unwrap();
+    assert!(metadata.flags.contains(&("profile".into(), "true".into())));
+}
+
+#[test]
+fn cached_module_keeps_the_profile() {
+    let dir = std::env::temp_dir().join(format!("webml-cache-profile-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let config = Config {
+        cache_dir: Some(dir.clone()),
+        ..profiled()
+    };
+    let compiled = compile(&double(), &config).unwrap();
+    let cached = compile(&double(), &config).unwrap();
+    assert_eq!(cached.interface.profile, compiled.interface.profile);
+    let plain = Config {
+        cache_dir: Some(dir.clone()),
+        ..Config::default()
+    };
+    assert!(compile(&double(), &plain)
+        .unwrap()
+        .interface
+        .profile
+        .is_empty());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,108 @@
+use std::path::PathBuf;
+use webml::{compile_str, CompileError, Config, Prelude, Project};
+
+const PRELUDE: &str = Prelude::BUILTIN;
+
+fn project(files: &[(&str, &str)]) -> Project {
+    let mut project = Project::new(PRELUDE);
+    for (path, source) in files {
+        project.add_file(*path, *source);
+    }
+    project
+}
+
+#[test]
+fn files_see_the_earlier_files() {
+    let lib = "infix 6 ++\nfun op ++ (x, y) = x + y + 1\nfun double x = x * 2";
+    let main = "val x = double (1 ++ 2)";
+    let project = project(&[("lib.sml", lib), ("main.sml", main)]);
+    let mut code = Vec::new();
+    project.compile(&Config::default()).unwrap().dump(&mut code);
+    let source = format!("{}\n{}", lib, main);
+    assert_eq!(code, compile_str(&source, &Config::default()).unwrap());
+
+    let project = self::project(&[("main.sml", main), ("lib.sml", lib)]);
+    assert!(project.check(&Config::default()).is_err());
+}
+
+#[test]
+fn top_level_names_are_defined_once() {
+    let project = project(&[
+        ("a.sml", "val x = 1\ndatatype t = A | B"),
+        ("b.sml", "fun y z = z\nfun x z = z"),
+    ]);
+    match project.check(&Config::default()) {
+        Err(CompileError::DuplicateDefinition {
+            name,
+            first,
+            second,
+        }) => {
+            assert_eq!(name, "x");
+            assert_eq!(first, PathBuf::from("a.sml"));
+            assert_eq!(second, PathBuf::from("b.sml"));
+        }
+        res => panic!("unexpected result: {:?}", res),
+    }
+
+    let project = self::project(&[("a.sml", "datatype t = A | B"), ("b.sml", "exception A")]);
+    assert!(project.check(&Config::default()).is_err());
+}
+
+#[test]
+fn names_may_be_shadowed_in_a_file() {
+    let project = project(&[
+        ("a.sml", "datatype t = A\nval x = 1\nval x = 2"),
+        // the pattern matches the constructor, defining nothing
+        ("b.sml", "val A = A\nfun version x = x + 1"),
+    ]);
+    project.check(&Config::default()).unwrap();
+}
+
+#[test]
+fn project_files_list_the_files() {
+    let mut project = Project::new(PRELUDE);
+    project.load("tests/project/app.mlb").unwrap();
+    project.load("tests/project/lib.sml").unwrap();
+    let paths = project
+        .files
+        .iter()
+        .map(|file| file.path.clone())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        paths,
+        vec![
+            PathBuf::from("tests/project/lib.sml"),
+            PathBuf::from("tests/project/main.sml"),
+        ]
+    );
+    project.compile(&Config::default()).unwrap();
+
+    let mut project = Project::new(PRELUDE);
+    assert!(project.load("tests/project/cycle.mlb").is_err());
+}
+
+#[test]
+fn used_files_go_first() {
+    let mut project = Project::new(PRELUDE);
+    project.load("tests/project/uses.sml").unwrap();
+    project.load("tests/project/app.mlb").unwrap();
+    let paths = project
+        .files
+        .iter()
+        .map(|file| file.path.clone())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        paths,
+        vec![
+            PathBuf::from("tests/project/lib.sml"),
+            PathBuf::from("tests/project/uses.sml"),
+            PathBuf::from("tests/project/main.sml"),
+        ]
+    );
+    assert!(!project.files[1].source.contains("use"));
+    project.compile(&Config::default()).unwrap();
+
+    let mut project = Project::new(PRELUDE);
+    let error = project.load("tests/project/cycle_a.sml").unwrap_err();
+    assert!(error.to_string().contains("cycle_a.sml"), "{}", error);
+}
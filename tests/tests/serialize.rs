@@ -0,0 +1,67 @@
+#![cfg(feature = "serde")]
+use webml::ast::TypedCore;
+use webml::{typed_ast, Config};
+
+#[test]
+fn typed_ast_round_trips_through_json() {
+    for entry in std::fs::read_dir("ml_example").unwrap() {
+        let path = entry.unwrap().path();
+        let source = std::fs::read_to_string(&path).unwrap();
+        let ast = typed_ast(&source, &Config::default()).unwrap();
+        let json = serde_json::to_string(&ast).unwrap();
+        let back: TypedCore = serde_json::from_str(&json).unwrap();
+        assert_eq!(back, ast, "{}", path.display());
+    }
+}
+
+#[test]
+fn json_has_the_types() {
+    let ast = typed_ast("val x = 1", &Config::default()).unwrap();
+    let json = serde_json::to_string(ast.0.last().unwrap()).unwrap();
+    let expected = r#"{"Val":{"rec":false,"pattern":{"ty":"Int","inner":{"Variable":{"name":["x","#;
+    assert!(json.starts_with(expected), "{}", json);
+}
+
+/// runs `webml compile` on the example and parses the JSON lines it prints
+#[cfg(feature = "json")]
+fn emit_json(example: &str, args: &[&str]) -> Vec<serde_json::Value> {
+    let output = std::env::temp_dir().join(format!("webml-emit-{}.wasm", example));
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_webml"))
+        .arg("compile")
+        .arg(format!("ml_example/{}.sml", example))
+        .args(["--emit-format", "json", "-o"])
+        .arg(&output)
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    String::from_utf8(output.stdout)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect()
+}
+
+#[test]
+#[cfg(feature = "json")]
+fn hir_and_mir_are_emitted_as_json() {
+    let dumps = emit_json("closures", &["--emit", "hir", "--emit", "mir"]);
+    let irs: Vec<_> = dumps.iter().map(|dump| dump["ir"].as_str()).collect();
+    assert_eq!(irs, vec![Some("hir"), Some("mir")]);
+    for dump in &dumps {
+        assert_eq!(dump["version"].as_u64(), Some(1));
+    }
+    let _: webml::hir::HIR = serde_json::from_value(dumps[0]["program"].clone()).unwrap();
+    let _: webml::mir::MIR = serde_json::from_value(dumps[1]["program"].clone()).unwrap();
+}
+
+#[test]
+#[cfg(feature = "json")]
+fn hir_is_emitted_where_the_compilation_stops() {
+    let dumps = emit_json(
+        "closures",
+        &["--emit", "hir", "--stop-after", "flattening_let"],
+    );
+    assert_eq!(dumps.len(), 1);
+    assert_eq!(dumps[0]["ir"].as_str(), Some("hir"));
+}
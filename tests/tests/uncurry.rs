@@ -0,0 +1,262 @@
+use super::util::{add, binds, fun, int, run_pass, sym};
+use webml::ast::{CaseSimplify, Desugar, Rename, Typer, VarToConstructor};
+use webml::hir::{Expr, HTy, Uncurry, Val, AST2HIR, HIR};
+use webml::id::Id;
+use webml::pass::ConvError;
+use webml::prim::*;
+use webml::{eval, parse, Config, Interpreter, PassManager, Prelude, IR};
+
+/// `int -> ... -> int` taking `arity` ints
+fn curried_ty(arity: usize) -> HTy {
+    (0..arity).fold(HTy::Int, |ty, _| HTy::fun(HTy::Int, ty))
+}
+
+fn curried(name: &str, id: u64, arity: usize) -> Expr {
+    Expr::Sym {
+        ty: curried_ty(arity),
+        name: Symbol(name.into(), id),
+    }
+}
+
+/// applies the function to the arguments one by one
+fn app(fun: Expr, args: Vec<Expr>) -> Expr {
+    args.into_iter().fold(fun, |fun, arg| {
+        let ty = match fun.ty() {
+            HTy::Fun(_, ret) => *ret,
+            ty => panic!("not a function: {:?}", ty),
+        };
+        Expr::App {
+            ty,
+            fun: Box::new(fun),
+            arg: Box::new(arg),
+        }
+    })
+}
+
+fn rec_val(name: &str, id: u64, expr: Expr) -> Val {
+    Val {
+        ty: expr.ty(),
+        rec: true,
+        name: Symbol(name.into(), id),
+        expr,
+    }
+}
+
+fn uncurry(vals: Vec<Val>) -> Vec<Val> {
+    run_pass(Uncurry::new(Id::new()), HIR(vals)).0
+}
+
+fn find<'a>(vals: &'a [Val], name: &str) -> &'a Val {
+    vals.iter()
+        .find(|val| val.name.0 == name)
+        .unwrap_or_else(|| panic!("{} not found in {:?}", name, vals))
+}
+
+/// the function and the tuple of the arguments of the call to the worker
+fn worker_call(expr: &Expr) -> (&Symbol, &[Expr]) {
+    match expr {
+        Expr::App { fun, arg, .. } => match (&**fun, &**arg) {
+            (Expr::Sym { name, .. }, Expr::Tuple { tuple, .. }) => (name, tuple),
+            _ => panic!("call to a worker expected: {:?}", expr),
+        },
+        _ => panic!("call to a worker expected: {:?}", expr),
+    }
+}
+
+fn literal(expr: &Expr) -> i64 {
+    match expr {
+        Expr::Lit {
+            value: Literal::Int(value),
+            ..
+        } => *value,
+        _ => panic!("int expected: {:?}", expr),
+    }
+}
+
+/// the body of `fn x1 => ... => fn xn => body`
+fn body(mut expr: &Expr, arity: usize) -> &Expr {
+    for _ in 0..arity {
+        match expr {
+            Expr::Fun { body, .. } => expr = body,
+            _ => panic!("function expected: {:?}", expr),
+        }
+    }
+    expr
+}
+
+#[test]
+fn workers_take_all_the_parameters() {
+    // val add3 = fn x => fn y => fn z => x + y + z
+    // val id = fn x => x
+    let vals = uncurry(vec![
+        rec_val(
+            "add3",
+            1,
+            fun(
+                "x",
+                2,
+                fun(
+                    "y",
+                    3,
+                    fun("z", 4, add(add(sym("x", 2), sym("y", 3)), sym("z", 4))),
+                ),
+            ),
+        ),
+        rec_val("id", 5, fun("x", 6, sym("x", 6))),
+    ]);
+    let names = vals
+        .iter()
+        .map(|val| val.name.0.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(names, ["add3_uncurried", "add3", "id"]);
+
+    let worker = find(&vals, "add3_uncurried");
+    assert_eq!(
+        worker.ty,
+        HTy::fun(HTy::Tuple(vec![HTy::Int, HTy::Int, HTy::Int]), HTy::Int)
+    );
+    // the wrapper keeps the curried type, and calls the worker with all of its parameters
+    let wrapper = find(&vals, "add3");
+    assert_eq!(wrapper.ty, curried_ty(3));
+    let (name, args) = worker_call(body(&wrapper.expr, 3));
+    assert_eq!(name, &worker.name);
+    assert_eq!(args.len(), 3);
+}
+
+#[test]
+fn saturated_calls_go_to_the_worker() {
+    // val f = fn x => fn y => x + y
+    // val a = f 1 2
+    // val p = f 1
+    let vals = uncurry(vec![
+        rec_val(
+            "f",
+            1,
+            fun("x", 2, fun("y", 3, add(sym("x", 2), sym("y", 3)))),
+        ),
+        rec_val("a", 4, app(curried("f", 1, 2), vec![int(1), int(2)])),
+        rec_val("p", 5, app(curried("f", 1, 2), vec![int(1)])),
+    ]);
+    let worker = &find(&vals, "f_uncurried").name;
+
+    let (name, args) = worker_call(&find(&vals, "a").expr);
+    assert_eq!(name, worker);
+    assert_eq!(args.iter().map(literal).collect::<Vec<_>>(), [1, 2]);
+
+    // the partial application calls the wrapper, which waits for the rest
+    match &find(&vals, "p").expr {
+        Expr::App { fun, arg, .. } => {
+            match &**fun {
+                Expr::Sym { name, .. } => assert_eq!(name, &Symbol("f".into(), 1)),
+                expr => panic!("f expected: {:?}", expr),
+            }
+            assert_eq!(literal(arg), 1);
+        }
+        expr => panic!("application expected: {:?}", expr),
+    }
+}
+
+#[test]
+fn mutually_recursive_functions_call_the_workers() {
+    // let val even = fn n => fn acc => odd n acc
+    //     val odd = fn n => fn acc => even n (acc + 1)
+    // in even 1 2 end
+    let expr = binds(
+        vec![
+            rec_val(
+                "even",
+                1,
+                fun(
+                    "n",
+                    3,
+                    fun(
+                        "acc",
+                        4,
+                        app(curried("odd", 2, 2), vec![sym("n", 3), sym("acc", 4)]),
+                    ),
+                ),
+            ),
+            rec_val(
+                "odd",
+                2,
+                fun(
+                    "n",
+                    5,
+                    fun(
+                        "acc",
+                        6,
+                        app(
+                            curried("even", 1, 2),
+                            vec![sym("n", 5), add(sym("acc", 6), int(1))],
+                        ),
+                    ),
+                ),
+            ),
+        ],
+        app(curried("even", 1, 2), vec![int(1), int(2)]),
+    );
+    let vals = uncurry(vec![rec_val("main", 7, expr)]);
+    let (vals, ret) = match &vals[0].expr {
+        Expr::Binds { binds, ret, .. } => (binds, ret),
+        expr => panic!("let expected: {:?}", expr),
+    };
+    let even = &find(vals, "even_uncurried").name;
+    let odd = &find(vals, "odd_uncurried").name;
+
+    // the workers bind the parameters out of the tuple before the body
+    let body = |val: &Val| match body(&val.expr, 1) {
+        Expr::Binds { ret, .. } => ret.clone(),
+        expr => panic!("let expected: {:?}", expr),
+    };
+    let even_body = body(find(vals, "even_uncurried"));
+    assert_eq!(worker_call(&even_body).0, odd);
+    let odd_body = body(find(vals, "odd_uncurried"));
+    let (name, args) = worker_call(&odd_body);
+    assert_eq!(name, even);
+    assert!(
+        matches!(args[1], Expr::BuiltinCall { fun: BIF::Add, .. }),
+        "{:?}",
+        args[1]
+    );
+    assert_eq!(worker_call(ret).0, even);
+}
+
+/// runs the program with the interpreter after the uncurrying
+fn run_uncurried(source: &str) -> String {
+    let program = format!("{}\n{}", Prelude::BUILTIN, source);
+    let id = Id::new();
+    let mut passes = PassManager::new();
+    passes
+        .add("parse", ConvError::new(parse))
+        .add("desugar", Desugar::new(id.clone()))
+        .add("rename", Rename::new(id.clone()))
+        .add("var_to_constructor", VarToConstructor::new(id.clone()))
+        .add("typing", Typer::new(id.clone()))
+        .add("case_simplify", CaseSimplify::new(id.clone()))
+        .add("ast_to_hir", AST2HIR::new(id.clone()))
+        .add("uncurry", Uncurry::new(id));
+    let hir = match passes.run(program.as_str(), &Config::default()).unwrap() {
+        IR::HIR((_, hir)) => hir,
+        ir => panic!("unexpected IR: {}", ir.kind()),
+    };
+    let mut out = Vec::new();
+    Interpreter::new(&mut out).run(&hir).unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+#[test]
+fn uncurried_programs_keep_the_output() {
+    let source = "fun add3 x y z = x + y * 10 + z * 100
+fun twice f x = f (f x)
+val add12 = add3 1 2
+fun count n acc = if n = 0 then acc else count (n - 1) (acc + n)
+val _ = printInt (add3 1 2 3)
+val _ = printInt (add12 4)
+val _ = printInt (twice (add3 0 0) 1)
+val _ = printInt (count 10 0)";
+    let mut expected = Vec::new();
+    eval(source, &Config::default(), &mut expected).unwrap();
+    let expected = String::from_utf8(expected).unwrap();
+    assert_eq!(expected, "321\n421\n10000\n55\n");
+    assert_eq!(run_uncurried(source), expected);
+}
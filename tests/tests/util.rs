@@ -0,0 +1,82 @@
+//! helpers shared by the tests, to build HIR by hand, to run the passes on it
+//! and to search the compiled modules
+
+use webml::hir::{Expr, HTy, SymbolTable, Val, HIR};
+use webml::prim::*;
+use webml::{Config, Pass};
+
+pub fn sym(name: &str, id: u64) -> Expr {
+    Expr::Sym {
+        ty: HTy::Int,
+        name: Symbol(name.into(), id),
+    }
+}
+
+pub fn int(value: i64) -> Expr {
+    Expr::Lit {
+        ty: HTy::Int,
+        value: Literal::Int(value),
+    }
+}
+
+pub fn val(name: &str, id: u64, expr: Expr) -> Val {
+    Val {
+        ty: expr.ty(),
+        rec: false,
+        name: Symbol(name.into(), id),
+        expr,
+    }
+}
+
+pub fn binds(binds: Vec<Val>, ret: Expr) -> Expr {
+    Expr::Binds {
+        ty: ret.ty(),
+        binds,
+        ret: Box::new(ret),
+    }
+}
+
+pub fn binop(fun: BIF, l: Expr, r: Expr) -> Expr {
+    Expr::BuiltinCall {
+        ty: HTy::Int,
+        fun,
+        args: vec![l, r],
+    }
+}
+
+pub fn add(l: Expr, r: Expr) -> Expr {
+    binop(BIF::Add, l, r)
+}
+
+/// `fn param => body` taking an int
+pub fn fun(param: &str, id: u64, body: Expr) -> Expr {
+    Expr::Fun {
+        param: (HTy::Int, Symbol(param.into(), id)),
+        body_ty: body.ty(),
+        body: Box::new(body),
+        captures: vec![],
+    }
+}
+
+/// the symbol table of the HIR built by hand, without datatypes and exports
+pub fn symbol_table() -> SymbolTable {
+    SymbolTable {
+        types: Default::default(),
+        exports: Vec::new(),
+    }
+}
+
+/// runs the pass on the HIR with the default config
+pub fn run_pass<P>(mut pass: P, hir: HIR) -> HIR
+where
+    P: Pass<(SymbolTable, HIR), (), Target = (SymbolTable, HIR)>,
+{
+    pass.trans((symbol_table(), hir), &Config::default())
+        .unwrap()
+        .1
+}
+
+/// whether the bytes of the module contain the string, e.g. the name of an import
+pub fn contains(code: &[u8], s: &str) -> bool {
+    code.windows(s.len()).any(|w| w == s.as_bytes())
+}
@@ -0,0 +1,178 @@
+use super::util::{binds, int, sym, symbol_table, val};
+use webml::hir::{Expr, HTy, Verify, HIR};
+use webml::mir::{self, EbbTy, Op, Terminator};
+use webml::prim::*;
+use webml::{Config, Pass, TypeError};
+
+fn verify(mut verifier: Verify, hir: HIR) -> Result<(), String> {
+    match verifier.trans((symbol_table(), hir), &Config::default()) {
+        Ok(_) => Ok(()),
+        Err(TypeError::InvalidIR(message)) => Err(message),
+        Err(e) => panic!("unexpected error: {:?}", e),
+    }
+}
+
+#[test]
+fn verify_accepts_bound_symbols() {
+    // val x = 1 val y = let val z = x in z end
+    let hir = HIR(vec![
+        val("x", 1, int(1)),
+        val("y", 2, binds(vec![val("z", 3, sym("x", 1))], sym("z", 3))),
+    ]);
+    assert_eq!(verify(Verify::flat(), hir), Ok(()));
+}
+
+#[test]
+fn verify_rejects_unbound_symbols() {
+    // val y = let val z = 1 in z end val w = z
+    let hir = HIR(vec![
+        val("y", 2, binds(vec![val("z", 3, int(1))], sym("z", 3))),
+        val("w", 4, sym("z", 3)),
+    ]);
+    assert!(verify(Verify::new(), hir).is_err());
+}
+
+#[test]
+fn verify_rejects_mistyped_application() {
+    // val f = fn x => x val y = f (1, 2)
+    let fun = Expr::Fun {
+        param: (HTy::Int, Symbol("x".into(), 2)),
+        body_ty: HTy::Int,
+        body: Box::new(sym("x", 2)),
+        captures: vec![],
+    };
+    let app = Expr::App {
+        ty: HTy::Int,
+        fun: Box::new(Expr::Sym {
+            ty: fun.ty(),
+            name: Symbol("f".into(), 1),
+        }),
+        arg: Box::new(Expr::Tuple {
+            tys: vec![HTy::Int, HTy::Int],
+            tuple: vec![int(1), int(2)],
+        }),
+    };
+    let hir = HIR(vec![val("f", 1, fun), val("y", 3, app)]);
+    assert!(verify(Verify::new(), hir).is_err());
+}
+
+#[test]
+fn verify_rejects_nested_binds_in_flat_mode() {
+    // val x = let val y = let val z = 1 in z end in y end
+    let inner = binds(vec![val("z", 3, int(1))], sym("z", 3));
+    let hir = HIR(vec![val(
+        "x",
+        1,
+        binds(vec![val("y", 2, inner)], sym("y", 2)),
+    )]);
+    let nested = HIR(hir.0.clone());
+    assert_eq!(verify(Verify::new(), hir), Ok(()));
+    assert!(verify(Verify::flat(), nested).is_err());
+}
+
+fn verify_mir(body: Vec<mir::EBB>) -> Result<(), String> {
+    let f = mir::Function {
+        name: Symbol("f".into(), 0),
+        body,
+        body_ty: EbbTy::Int,
+        export: None,
+    };
+    let mir = mir::MIR(vec![f]);
+    match mir::Verify::new().trans((mir::SymbolTable::default(), mir), &Config::default()) {
+        Ok(_) => Ok(()),
+        Err(TypeError::InvalidIR(message)) => Err(message),
+        Err(e) => panic!("unexpected error: {:?}", e),
+    }
+}
+
+fn var(name: &str, id: u64) -> Symbol {
+    Symbol(name.into(), id)
+}
+
+fn mir_add(var: Symbol, l: Symbol, r: Symbol) -> Op {
+    Op::Add {
+        var,
+        ty: EbbTy::Int,
+        l,
+        r,
+    }
+}
+
+fn ebb(name: Symbol, params: Vec<Symbol>, body: Vec<Op>, term: Terminator) -> mir::EBB {
+    mir::EBB {
+        name,
+        params: params.into_iter().map(|var| (EbbTy::Int, var)).collect(),
+        body,
+        term,
+    }
+}
+
+fn jump(target: Symbol, args: Vec<Symbol>) -> Terminator {
+    Terminator::Jump {
+        target,
+        forward: true,
+        args,
+    }
+}
+
+fn ret(value: Symbol) -> Terminator {
+    Terminator::Ret {
+        value: Some(value),
+        ty: EbbTy::Int,
+    }
+}
+
+/// `entry(a)` branching on `a` to `then` and `else`, which jump to `join`
+fn diamond(then_: Vec<Op>, then_args: Vec<Symbol>, join: mir::EBB) -> Vec<mir::EBB> {
+    let a = var("a", 1);
+    let nargs = then_args.len();
+    vec![
+        ebb(
+            var("entry", 2),
+            vec![a],
+            vec![],
+            Terminator::Branch {
+                cond: a,
+                clauses: vec![(1, var("then", 3), true), (0, var("else", 4), true)],
+                default: None,
+            },
+        ),
+        ebb(var("then", 3), vec![], then_, jump(join.name, then_args)),
+        ebb(
+            var("else", 4),
+            vec![],
+            vec![],
+            jump(join.name, vec![a; nargs]),
+        ),
+        join,
+    ]
+}
+
+#[test]
+fn verify_accepts_joins_through_parameters() {
+    let (a, x, z) = (var("a", 1), var("x", 6), var("z", 7));
+    let join = ebb(var("join", 5), vec![z], vec![], ret(z));
+    let body = diamond(vec![mir_add(x, a, a)], vec![x], join);
+    assert_eq!(verify_mir(body), Ok(()));
+}
+
+#[test]
+fn verify_rejects_variables_assigned_twice() {
+    let (a, x) = (var("a", 1), var("x", 6));
+    let body = vec![ebb(
+        var("entry", 2),
+        vec![a],
+        vec![mir_add(x, a, a), mir_add(x, x, a)],
+        ret(x),
+    )];
+    assert!(verify_mir(body).is_err());
+}
+
+#[test]
+fn verify_rejects_variables_not_defined_on_every_path() {
+    // `x` is only defined when `then` is taken
+    let (a, x) = (var("a", 1), var("x", 6));
+    let join = ebb(var("join", 5), vec![], vec![], ret(x));
+    let body = diamond(vec![mir_add(x, a, a)], vec![], join);
+    assert!(verify_mir(body).is_err());
+}
@@ -0,0 +1,105 @@
+use webml::backend::print_wat;
+use webml::{compile_str, Config};
+
+fn wat(code: &[u8]) -> String {
+    let mut out = Vec::new();
+    print_wat(code, &mut out).unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+#[test]
+fn print_empty_module() {
+    assert_eq!(wat(b"\0asm\x01\0\0\0"), "(module\n)\n");
+}
+
+#[test]
+fn print_functions() {
+    #[rustfmt::skip]
+    let code: &[u8] = &[
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00,
+        // type: (i32) -> i32, () -> ()
+        0x01, 0x09, 0x02, 0x60, 0x01, 0x7f, 0x01, 0x7f, 0x60, 0x00, 0x00,
+        // import "rt" "init" (func (type 1))
+        0x02, 0x0b, 0x01, 0x02, b'r', b't', 0x04, b'i', b'n', b'i', b't', 0x00, 0x01,
+        // function (type 0)
+        0x03, 0x02, 0x01, 0x00,
+        // memory 1
+        0x05, 0x03, 0x01, 0x00, 0x01,
+        // export "f" (func 1)
+        0x07, 0x05, 0x01, 0x01, b'f', 0x00, 0x01,
+        // code with a local i32
+        0x0a, 0x17, 0x01, 0x15, 0x01, 0x01, 0x7f,
+        0x20, 0x00, // local.get 0
+        0x04, 0x7f, // if (result i32)
+        0x41, 0x7f, // i32.const -1
+        0x05, // else
+        0x20, 0x00, // local.get 0
+        0x28, 0x02, 0x04, // i32.load offset=4
+        0x0b, // end
+        0x22, 0x01, // local.tee 1
+        0x10, 0x00, // call 0
+        0x0b,
+    ];
+    assert_eq!(
+        wat(code),
+        r#"(module
+  (type (;0;) (func (param i32) (result i32)))
+  (type (;1;) (func))
+  (import "rt" "init" (func (;0;) (type 1)))
+  (memory 1)
+  (export "f" (func 1))
+  (func (;1;) (type 0) (param i32) (result i32)
+    (local i32)
+    local.get 0
+    if (result i32)
+      i32.const -1
+    else
+      local.get 0
+      i32.load offset=4
+    end
+    local.tee 1
+    call 0
+  )
+)
+"#
+    );
+}
+
+#[test]
+fn print_compiled_module() {
+    let input = "val x = 1 + 2";
+    let code = compile_str(input, &Config::default()).unwrap();
+    assert!(wat(&code).starts_with("(module"));
+}
+
+#[test]
+fn compiled_module_has_no_duplicate_types() {
+    let input = "fun f x = x + 1\nval y = f 2\nval _ = printInt y";
+    let code = compile_str(input, &Config::default()).unwrap();
+    let wat = wat(&code);
+    let mut types = wat
+        .lines()
+        .filter(|line| line.starts_with("  (type "))
+        .map(|line| &line[line.find("(func").unwrap()..])
+        .collect::<Vec<_>>();
+    let n = types.len();
+    types.sort();
+    types.dedup();
+    assert_eq!(types.len(), n, "{}", wat);
+}
+
+#[test]
+fn real_functions_use_the_instructions() {
+    let input = "val () = printInt (Real.floor (Math.sqrt (Real.fromInt 10)))";
+    let code = compile_str(input, &Config::default()).unwrap();
+    let wat = wat(&code);
+    for op in &[
+        "f64.convert_i32_s",
+        "f64.sqrt",
+        "f64.floor",
+        "i32.trunc_f64_s",
+    ] {
+        assert!(wat.contains(op), "{} not in\n{}", op, wat);
+    }
+    assert!(!wat.contains("(import \"wasm\""), "{}", wat);
+}
@@ -0,0 +1,30 @@
+// the loader of the compiler built from webml-playground.
+// `loadCompiler(source)` takes the URL or the bytes of the built module,
+// and resolves to the function compiling the SML program to
+// `{wasm: Uint8Array}` or `{diagnostics: [string]}`.
+
+async function load(source) {
+    if (typeof source === "string" || source instanceof URL) {
+        const response = await fetch(source);
+        return response.arrayBuffer();
+    }
+    return source;
+}
+
+export async function loadCompiler(source) {
+    const {instance} = await WebAssembly.instantiate(await load(source), {});
+    const webml = instance.exports;
+    return function compile(program) {
+        const bytes = new TextEncoder().encode(program);
+        const ptr = webml.alloc(bytes.length);
+        new Uint8Array(webml.memory.buffer, ptr, bytes.length).set(bytes);
+        const succeeded = webml.compile(ptr, bytes.length);
+        webml.dealloc(ptr, bytes.length);
+        // the memory may have grown, so the buffer is taken after compiling
+        const output = new Uint8Array(webml.memory.buffer, webml.output_ptr(), webml.output_len()).slice();
+        if (succeeded) {
+            return {wasm: output};
+        }
+        return {diagnostics: new TextDecoder().decode(output).split("\n")};
+    };
+}
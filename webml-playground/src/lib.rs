@@ -0,0 +1,84 @@
+//! the compiler as a wasm module, for the playgrounds compiling the programs in the browsers.
+//! build it with `cargo build --release --target wasm32-unknown-unknown`.
+//!
+//! the host writes the source to the memory allocated with `alloc` and calls `compile`.
+//! the output, the module or the diagnostics one per line, is then at `output_ptr`
+//! for `output_len` bytes until the next `compile`. `playground.js` wraps them.
+
+use std::cell::RefCell;
+use std::mem;
+use std::slice;
+use std::str;
+use webml::{Compiler, Diagnostic};
+
+/// compiles the program after the builtin prelude to the module
+pub fn compile_str(source: &str) -> Result<Vec<u8>, Vec<Diagnostic>> {
+    let output = Compiler::builder()
+        .source("playground.sml", source)
+        .build()
+        .compile();
+    match output.wasm {
+        Some(wasm) if output.is_ok() => Ok(wasm),
+        _ => Err(output.diagnostics),
+    }
+}
+
+thread_local! {
+    static OUTPUT: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+}
+
+/// allocates the buffer for the source
+#[no_mangle]
+pub extern "C" fn alloc(len: usize) -> *mut u8 {
+    let mut buf = Vec::<u8>::with_capacity(len);
+    let ptr = buf.as_mut_ptr();
+    mem::forget(buf);
+    ptr
+}
+
+/// frees the buffer allocated with `alloc`
+///
+/// # Safety
+/// `ptr` and `len` must be the ones passed to and returned from `alloc`
+#[no_mangle]
+pub unsafe extern "C" fn dealloc(ptr: *mut u8, len: usize) {
+    drop(Vec::from_raw_parts(ptr, 0, len));
+}
+
+/// compiles the UTF-8 source. returns 1 and leaves the module in the output if it succeeded,
+/// or returns 0 and leaves the diagnostics
+///
+/// # Safety
+/// `ptr` must point to `len` bytes
+#[no_mangle]
+pub unsafe extern "C" fn compile(ptr: *const u8, len: usize) -> u32 {
+    let result = match str::from_utf8(slice::from_raw_parts(ptr, len)) {
+        Ok(source) => compile_str(source),
+        Err(e) => Err(vec![Diagnostic {
+            pass: None,
+            message: format!("the source is not UTF-8: {}", e),
+        }]),
+    };
+    let (succeeded, output) = match result {
+        Ok(wasm) => (1, wasm),
+        Err(diagnostics) => {
+            let lines = diagnostics
+                .iter()
+                .map(|diagnostic| diagnostic.to_string())
+                .collect::<Vec<_>>();
+            (0, lines.join("\n").into_bytes())
+        }
+    };
+    OUTPUT.with(|buf| *buf.borrow_mut() = output);
+    succeeded
+}
+
+#[no_mangle]
+pub extern "C" fn output_ptr() -> *const u8 {
+    OUTPUT.with(|buf| buf.borrow().as_ptr())
+}
+
+#[no_mangle]
+pub extern "C" fn output_len() -> usize {
+    OUTPUT.with(|buf| buf.borrow().len())
+}
@@ -1,65 +1,1281 @@
 #![no_std]
 #![cfg(target_arch = "wasm32")]
 use core::arch::wasm32::{memory_grow, memory_size};
+use core::fmt::{self, Write};
 use core::mem;
 use core::panic::PanicInfo;
+use core::ptr;
+
+// The garbage collector, either mark-sweep, generational or reference counting as chosen by `init`.
+//
+// The heap is a set of regions of wasm pages. Objects are allocated from the free lists,
+// or else by bumping the top of the last region, and are collected by marking and sweeping.
+// Each object has a header before it:
+//
+//   32                  32            32              32
+// +---------------------+-------------+---------------+---------+-----------
+// | size | MARK | FREE | pointer map | ambiguous map | forward | payload ...
+// +---------------------+-------------+---------------+---------+-----------
+//
+// `size` is the size of the payload, a multiple of 8. The maps tell which of the first 31
+// 4-byte words of the payload hold heap pointers, and which hold either pointers or integers.
+// If the SCAN_ALL bit of the ambiguous map is set, the rest of the words are ambiguous too.
+// Every candidate pointer is checked against the bitmap of object starts of the region,
+// so that non-pointer values are never followed.
+//
+// The roots are the shadow stack, to which the compiled code saves the registers holding
+// pointers before it calls functions which may collect, the exception being raised
+// and the closures the host holds as callbacks.
+//
+// The generational collector allocates small objects by bumping the regions of the nursery.
+// When they are full, the live objects of the nursery are copied to the heap, leaving their
+// new address in `forward`, and the nursery is reused. The objects referenced from the shadow
+// stack or by ambiguous words can't be moved because the references can't be updated.
+// They pin their regions, which are moved to the heap as they are.
+// Objects are never written after they are initialized, so the only references from the heap
+// to the nursery are from the large objects, which are allocated in the heap directly
+// and remembered until the next collection of the nursery.
+//
+// The reference counting collector keeps the count of the references from the heap
+// in `forward`. The references from the stack aren't counted, but the objects whose count is
+// zero are put in the zero count table, and freed when the table is collected unless
+// the shadow stack references them. Since objects are never written after they are
+// initialized, the references from the new objects are counted when the table is collected.
+// Then the ambiguous words which don't reference objects are removed from the map, so that
+// they are never taken as references to the objects allocated later. The objects referenced
+// by the words after the first 31 are never freed, nor are cycles.
 
 #[repr(C)]
-struct Page {
-    next: *mut Page,
-    size: usize,
-    top: usize,
-    data: *mut u8,
+struct Region {
+    next: *mut Region,
+    // the start of the objects
+    data: u32,
+    // the bytes available for the objects
+    size: u32,
+    // the bytes used by the objects
+    top: u32,
+    // one bit for each 8 bytes of the data, set at the headers of the live objects
+    starts: u32,
+    // the nursery region holds an object which can't be moved
+    pinned: bool,
+}
+
+#[repr(C)]
+struct Header {
+    size: u32,
+    pointers: u32,
+    ambiguous: u32,
+    forward: u32,
 }
 
 const MEMORY: u32 = 0;
-const WASM_PAGE_SIZE: usize = 64 * 1024;
-// GC page size including meta data
-const GC_PAGE_SIZE: usize = 1 * WASM_PAGE_SIZE;
-static mut GC: *mut Page = 0 as *mut _;
-static mut HEAD: *mut Page = 0 as *mut _;
-
-unsafe fn new_page() -> *mut Page {
-    let ret = memory_grow(MEMORY, 1);
+const WASM_PAGE_SIZE: u32 = 64 * 1024;
+const ALIGN: u32 = 8;
+const HEADER_SIZE: u32 = mem::size_of::<Header>() as u32;
+const MARK: u32 = 1 << 31;
+const FREE: u32 = 1 << 30;
+// the object in the nursery has been moved. objects in the nursery are never free
+const FORWARDED: u32 = FREE;
+// the object is in the zero count table
+const IN_ZCT: u32 = 1 << 29;
+// the references from the object have been counted
+const COUNTED: u32 = 1 << 28;
+const SIZE_MASK: u32 = COUNTED - 1;
+const SCAN_ALL: u32 = 1 << 31;
+
+// the collectors `init` takes
+const MARK_SWEEP: u32 = 0;
+const GENERATIONAL: u32 = 1;
+const REFERENCE_COUNTING: u32 = 2;
+static mut COLLECTOR: u32 = MARK_SWEEP;
+
+static mut REGIONS: *mut Region = 0 as *mut _;
+static mut LAST: *mut Region = 0 as *mut _;
+
+// the regions of the nursery, and the one being allocated from
+const NURSERY_REGIONS: usize = 4;
+static mut NURSERY: *mut Region = 0 as *mut _;
+static mut NURSERY_TOP: *mut Region = 0 as *mut _;
+
+// the objects larger than this are allocated in the heap even by the generational collector
+const LARGE_OBJECT_SIZE: u32 = 1024;
+const REMEMBERED_SIZE: usize = 1024;
+static mut REMEMBERED: [u32; REMEMBERED_SIZE] = [0; REMEMBERED_SIZE];
+static mut REMEMBERED_LEN: usize = 0;
+
+// free lists of the blocks of 8, 16, ..., 8 * SMALL_CLASSES bytes, and of the larger blocks.
+// the pointer map of a free block is the address of the next block
+const SMALL_CLASSES: usize = 16;
+static mut FREE_LISTS: [u32; SMALL_CLASSES + 1] = [0; SMALL_CLASSES + 1];
+
+// the bytes of the heap and allocated since the last collection
+static mut HEAP_SIZE: u32 = 0;
+static mut ALLOCATED: u32 = 0;
+
+const SHADOW_STACK_SIZE: usize = 64 * 1024;
+static mut SHADOW_STACK: [u32; SHADOW_STACK_SIZE] = [0; SHADOW_STACK_SIZE];
+static mut SP: usize = 0;
+
+// the zero count table. it is collected while it has room for the objects the stack may keep,
+// so that it never overflows
+const ZCT_SIZE: usize = 2 * SHADOW_STACK_SIZE + 16 * 1024;
+const ZCT_LIMIT: usize = ZCT_SIZE - SHADOW_STACK_SIZE - 1;
+static mut ZCT: [u32; ZCT_SIZE] = [0; ZCT_SIZE];
+static mut ZCT_LEN: usize = 0;
+// the objects to free, linked by their counts
+static mut DYING: u32 = 0;
+// the count of the objects never freed
+const STICKY: u32 = u32::max_value();
+
+const MARK_STACK_SIZE: usize = 16 * 1024;
+static mut MARK_STACK: [u32; MARK_STACK_SIZE] = [0; MARK_STACK_SIZE];
+static mut MARK_SP: usize = 0;
+// the mark stack has overflowed and the heap needs to be rescanned
+static mut OVERFLOW: bool = false;
+
+fn at<T>(addr: u32) -> *mut T {
+    addr as usize as *mut T
+}
+
+unsafe fn slot_addr(index: usize) -> u32 {
+    ptr::addr_of!(SHADOW_STACK[index]) as u32
+}
+
+fn align(size: u32) -> u32 {
+    (size + ALIGN - 1) & !(ALIGN - 1)
+}
+
+/// allocates a region which can hold an object of `min_size` bytes
+unsafe fn new_region(min_size: u32) -> *mut Region {
+    let meta = align(mem::size_of::<Region>() as u32);
+    // the bitmap takes 1/64 of the data
+    let bytes = meta + min_size + min_size / 64 + ALIGN * 2;
+    let pages = (bytes + WASM_PAGE_SIZE - 1) / WASM_PAGE_SIZE;
+    let ret = memory_grow(MEMORY, pages as usize);
     // if we failed to allocate a page then panic
     if ret == usize::max_value() {
-        // TODO: collect garbage
         panic!("memory exhausted")
+    }
+    let start = ret as u32 * WASM_PAGE_SIZE;
+    let total = pages * WASM_PAGE_SIZE - meta;
+    let starts_size = align(total / (ALIGN * 8 + 1) + 1);
+    // top: relying wasm's page is 0 initialized
+    let region = at::<Region>(start);
+    (*region).starts = start + meta;
+    (*region).data = start + meta + starts_size;
+    (*region).size = total - starts_size;
+    region
+}
+
+/// adds the region to the end of the heap
+unsafe fn add_region(region: *mut Region) {
+    HEAP_SIZE += (*region).size;
+    if LAST.is_null() {
+        REGIONS = region;
+    } else {
+        (*LAST).next = region;
+    }
+    LAST = region;
+}
+
+unsafe fn region_of(mut region: *mut Region, addr: u32) -> *mut Region {
+    while !region.is_null() {
+        if (*region).data <= addr && addr < (*region).data + (*region).top {
+            return region;
+        }
+        region = (*region).next;
+    }
+    ptr::null_mut()
+}
+
+unsafe fn start_bit(region: *mut Region, header: u32) -> (*mut u8, u8) {
+    let index = (header - (*region).data) / ALIGN;
+    (at((*region).starts + index / 8), 1 << (index % 8))
+}
+
+unsafe fn set_start(region: *mut Region, header: u32, start: bool) {
+    let (byte, bit) = start_bit(region, header);
+    if start {
+        *byte |= bit;
+    } else {
+        *byte &= !bit;
+    }
+}
+
+/// the region and the header of the object if `addr` points to a live object in the regions
+unsafe fn object_in(regions: *mut Region, addr: u32) -> Option<(*mut Region, *mut Header)> {
+    if addr % ALIGN != 0 || addr < HEADER_SIZE {
+        return None;
+    }
+    let header = addr - HEADER_SIZE;
+    let region = region_of(regions, header);
+    if region.is_null() {
+        return None;
+    }
+    let (byte, bit) = start_bit(region, header);
+    if *byte & bit == 0 {
+        return None;
+    }
+    Some((region, at(header)))
+}
+
+/// the header of the object if `addr` points to a live object in the heap
+unsafe fn header_of(addr: u32) -> Option<*mut Header> {
+    object_in(REGIONS, addr).map(|(_, header)| header)
+}
+
+unsafe fn size_of(header: *mut Header) -> u32 {
+    (*header).size & SIZE_MASK
+}
+
+unsafe fn size_class(size: u32) -> usize {
+    let class = (size / ALIGN) as usize - 1;
+    if class < SMALL_CLASSES {
+        class
     } else {
-        let page = (ret * WASM_PAGE_SIZE) as *mut u8 as *mut Page;
-        // next, top: relying wasm's page is 0 initialized
-        (*page).size = GC_PAGE_SIZE - mem::size_of::<Page>();
-        (*page).data = (page as *mut u8).offset(mem::size_of::<Page>() as isize);
-        page
+        SMALL_CLASSES
+    }
+}
+
+unsafe fn push_free(header: u32, size: u32) {
+    let class = size_class(size);
+    let block = at::<Header>(header);
+    (*block).size = size | FREE;
+    (*block).pointers = FREE_LISTS[class];
+    FREE_LISTS[class] = header;
+}
+
+/// takes a block of the size from the free lists
+unsafe fn pop_free(size: u32) -> Option<u32> {
+    let class = size_class(size);
+    if class < SMALL_CLASSES {
+        let header = FREE_LISTS[class];
+        if header != 0 {
+            FREE_LISTS[class] = (*at::<Header>(header)).pointers;
+            return Some(header);
+        }
+    }
+    // first fit from the large blocks
+    let mut prev: *mut u32 = ptr::addr_of_mut!(FREE_LISTS[SMALL_CLASSES]);
+    while *prev != 0 {
+        let header = *prev;
+        let block = at::<Header>(header);
+        let block_size = size_of(block);
+        if size <= block_size {
+            *prev = (*block).pointers;
+            // split the rest off if it can hold an object
+            if size + HEADER_SIZE + ALIGN <= block_size {
+                push_free(header + HEADER_SIZE + size, block_size - size - HEADER_SIZE);
+                (*block).size = size;
+            }
+            return Some(header);
+        }
+        prev = ptr::addr_of_mut!((*block).pointers);
+    }
+    None
+}
+
+/// takes a block of the size from the top of the last region
+unsafe fn bump(size: u32) -> Option<u32> {
+    if LAST.is_null() || (*LAST).size < (*LAST).top + HEADER_SIZE + size {
+        return None;
+    }
+    let header = (*LAST).data + (*LAST).top;
+    (*LAST).top += HEADER_SIZE + size;
+    (*at::<Header>(header)).size = size;
+    Some(header)
+}
+
+/// takes a block for an object of the size from the heap, growing it if needed
+unsafe fn take_block(size: u32) -> u32 {
+    pop_free(size).or_else(|| bump(size)).unwrap_or_else(|| {
+        add_region(new_region(size + HEADER_SIZE));
+        bump(size).unwrap()
+    })
+}
+
+/// initializes the object in the block of the region and returns its address
+unsafe fn init_object(region: *mut Region, header: u32, pointers: u32, ambiguous: u32) -> u32 {
+    let object = at::<Header>(header);
+    // the block from the free lists may be larger than the size
+    let size = size_of(object);
+    (*object).size = size;
+    (*object).pointers = pointers;
+    (*object).ambiguous = ambiguous;
+    (*object).forward = 0;
+    set_start(region, header, true);
+    let addr = header + HEADER_SIZE;
+    ptr::write_bytes(at::<u8>(addr), 0, size as usize);
+    addr
+}
+
+/// calls `f` with the addresses of the words of the object which may hold pointers,
+/// and whether they surely hold pointers
+unsafe fn for_each_field(addr: u32, mut f: impl FnMut(u32, bool)) {
+    let header = at::<Header>(addr - HEADER_SIZE);
+    let (pointers, ambiguous) = ((*header).pointers, (*header).ambiguous);
+    if pointers == 0 && ambiguous == 0 {
+        return;
+    }
+    for i in 0..size_of(header) / 4 {
+        let field = addr + i * 4;
+        if 31 <= i {
+            if ambiguous & SCAN_ALL != 0 {
+                f(field, false);
+            }
+        } else if pointers & (1 << i) != 0 {
+            f(field, true);
+        } else if ambiguous & (1 << i) != 0 {
+            f(field, false);
+        }
+    }
+}
+
+/// calls `f` with the addresses of the objects of the region
+unsafe fn for_each_object(region: *mut Region, mut f: impl FnMut(u32)) {
+    let mut header = (*region).data;
+    while header < (*region).data + (*region).top {
+        // `f` may change the flags but not the size
+        let size = size_of(at(header));
+        f(header + HEADER_SIZE);
+        header += HEADER_SIZE + size;
+    }
+}
+
+unsafe fn push_mark_stack(addr: u32) {
+    let header = at::<Header>(addr - HEADER_SIZE);
+    (*header).size |= MARK;
+    if MARK_SP < MARK_STACK_SIZE {
+        MARK_STACK[MARK_SP] = addr;
+        MARK_SP += 1;
+    } else {
+        OVERFLOW = true;
+    }
+}
+
+/// pops the objects from the mark stack and scans them until it is empty,
+/// then scans the marked objects of the regions if the stack has overflowed
+unsafe fn drain_mark_stack(regions: *mut Region, scan: unsafe fn(u32)) {
+    loop {
+        while MARK_SP > 0 {
+            MARK_SP -= 1;
+            scan(MARK_STACK[MARK_SP]);
+        }
+        if !OVERFLOW {
+            break;
+        }
+        // the objects whose children could not be pushed are marked. scan them again
+        OVERFLOW = false;
+        let mut region = regions;
+        while !region.is_null() {
+            for_each_object(region, |addr| {
+                if is_marked(addr) {
+                    scan(addr);
+                }
+            });
+            region = (*region).next;
+        }
+    }
+}
+
+unsafe fn is_marked(addr: u32) -> bool {
+    (*at::<Header>(addr - HEADER_SIZE)).size & MARK != 0
+}
+
+unsafe fn push_mark(addr: u32) {
+    if let Some(header) = header_of(addr) {
+        if (*header).size & MARK == 0 {
+            push_mark_stack(addr);
+        }
+    }
+}
+
+unsafe fn scan(addr: u32) {
+    for_each_field(addr, |field, _| push_mark(*at::<u32>(field)));
+}
+
+unsafe fn mark() {
+    for i in 0..SP {
+        push_mark(SHADOW_STACK[i]);
+    }
+    push_mark(EXCEPTION);
+    for i in 0..CALLBACKS {
+        push_mark(CALLBACK_CLOSURES[i]);
+    }
+    drain_mark_stack(REGIONS, scan);
+}
+
+unsafe fn sweep_region(region: *mut Region) {
+    let end = (*region).data + (*region).top;
+    let mut header = (*region).data;
+    // the start and the size of the run of the free blocks to coalesce
+    let mut run: Option<(u32, u32)> = None;
+    while header < end {
+        let object = at::<Header>(header);
+        let size = size_of(object);
+        if (*object).size & MARK != 0 {
+            (*object).size &= !MARK;
+            if let Some((start, size)) = run.take() {
+                push_free(start, size);
+            }
+        } else {
+            set_start(region, header, false);
+            run = match run {
+                Some((start, run_size)) => Some((start, run_size + HEADER_SIZE + size)),
+                None => Some((header, size)),
+            };
+        }
+        header += HEADER_SIZE + size;
+    }
+    match run {
+        // give the run at the end back to the top
+        Some((start, _)) if region == LAST => (*region).top = start - (*region).data,
+        Some((start, size)) => push_free(start, size),
+        None => (),
+    }
+}
+
+unsafe fn sweep() {
+    for class in 0..FREE_LISTS.len() {
+        FREE_LISTS[class] = 0;
+    }
+    let mut region = REGIONS;
+    while !region.is_null() {
+        sweep_region(region);
+        region = (*region).next;
+    }
+}
+
+unsafe fn collect_heap() {
+    mark();
+    sweep();
+    ALLOCATED = 0;
+}
+
+unsafe fn new_nursery_region() -> *mut Region {
+    new_region(WASM_PAGE_SIZE / 2)
+}
+
+/// marks the object if `addr` points to one in the nursery.
+/// the object is referenced by an ambiguous word unless `precise`
+unsafe fn push_young(addr: u32, precise: bool) {
+    if let Some((region, header)) = object_in(NURSERY, addr) {
+        if !precise {
+            (*region).pinned = true;
+        }
+        if (*header).size & MARK == 0 {
+            push_mark_stack(addr);
+        }
+    }
+}
+
+unsafe fn scan_young(addr: u32) {
+    for_each_field(addr, |field, precise| {
+        push_young(*at::<u32>(field), precise)
+    });
+}
+
+/// updates the pointer to the moved object in the nursery
+unsafe fn forward(field: *mut u32, precise: bool) {
+    if !precise {
+        // the objects referenced by ambiguous words are pinned
+        return;
+    }
+    if let Some((_, header)) = object_in(NURSERY, *field) {
+        if (*header).size & FORWARDED != 0 {
+            *field = (*header).forward;
+        }
+    }
+}
+
+/// moves the object in the nursery to the heap
+unsafe fn promote(addr: u32) {
+    let object = at::<Header>(addr - HEADER_SIZE);
+    let size = size_of(object);
+    let header = take_block(size);
+    let copy = at::<Header>(header);
+    // the block from the free lists may be larger than the size
+    let block_size = size_of(copy);
+    ptr::copy_nonoverlapping(
+        object as *const u8,
+        copy as *mut u8,
+        (HEADER_SIZE + size) as usize,
+    );
+    ptr::write_bytes(
+        at::<u8>(header + HEADER_SIZE + size),
+        0,
+        (block_size - size) as usize,
+    );
+    (*copy).size = block_size;
+    set_start(region_of(REGIONS, header), header, true);
+    ALLOCATED += HEADER_SIZE + block_size;
+    (*object).size |= FORWARDED;
+    (*object).forward = header + HEADER_SIZE;
+}
+
+/// moves the pinned region to the start of the heap, freeing the dead objects and the space
+/// left at the end
+unsafe fn promote_region(region: *mut Region) {
+    let rest = (*region).size - (*region).top;
+    if HEADER_SIZE + ALIGN <= rest {
+        // a dead object sweeping frees
+        let header = (*region).data + (*region).top;
+        (*at::<Header>(header)).size = rest - HEADER_SIZE;
+        (*region).top = (*region).size;
+    }
+    (*region).pinned = false;
+    sweep_region(region);
+    (*region).next = REGIONS;
+    REGIONS = region;
+    HEAP_SIZE += (*region).size;
+    ALLOCATED += (*region).size;
+}
+
+/// collects the nursery
+unsafe fn collect_nursery() {
+    for i in 0..SP {
+        push_young(SHADOW_STACK[i], false);
+    }
+    push_young(EXCEPTION, true);
+    for i in 0..CALLBACKS {
+        push_young(CALLBACK_CLOSURES[i], true);
+    }
+    for i in 0..REMEMBERED_LEN {
+        scan_young(REMEMBERED[i]);
+    }
+    drain_mark_stack(NURSERY, scan_young);
+
+    // the regions are pinned by now. move the marked objects of the other regions
+    let mut region = NURSERY;
+    while !region.is_null() {
+        if !(*region).pinned {
+            for_each_object(region, |addr| {
+                if is_marked(addr) {
+                    promote(addr);
+                }
+            });
+        }
+        region = (*region).next;
     }
+
+    // update the references to the moved objects
+    let mut region = NURSERY;
+    while !region.is_null() {
+        let pinned = (*region).pinned;
+        for_each_object(region, |addr| {
+            let object = at::<Header>(addr - HEADER_SIZE);
+            if pinned && (*object).size & MARK != 0 {
+                for_each_field(addr, |field, precise| forward(at(field), precise));
+            } else if !pinned && (*object).size & FORWARDED != 0 {
+                for_each_field((*object).forward, |field, precise| {
+                    forward(at(field), precise)
+                });
+            }
+        });
+        region = (*region).next;
+    }
+    for i in 0..REMEMBERED_LEN {
+        for_each_field(REMEMBERED[i], |field, precise| forward(at(field), precise));
+    }
+    forward(ptr::addr_of_mut!(EXCEPTION), true);
+    for i in 0..CALLBACKS {
+        forward(ptr::addr_of_mut!(CALLBACK_CLOSURES[i]), true);
+    }
+    REMEMBERED_LEN = 0;
+
+    // replace the pinned regions with new ones and reuse the others
+    let mut link: *mut *mut Region = ptr::addr_of_mut!(NURSERY);
+    while !(*link).is_null() {
+        let region = *link;
+        if (*region).pinned {
+            let fresh = new_nursery_region();
+            (*fresh).next = (*region).next;
+            *link = fresh;
+            promote_region(region);
+            link = ptr::addr_of_mut!((*fresh).next);
+        } else {
+            let bytes = (*region).top / ALIGN / 8 + 1;
+            ptr::write_bytes(at::<u8>((*region).starts), 0, bytes as usize);
+            (*region).top = 0;
+            link = ptr::addr_of_mut!((*region).next);
+        }
+    }
+    NURSERY_TOP = NURSERY;
+}
+
+/// collects the nursery, and the heap too if it has been used enough since its last collection
+unsafe fn collect_minor() {
+    collect_nursery();
+    if HEAP_SIZE / 2 <= ALLOCATED {
+        collect_heap();
+    }
+}
+
+unsafe fn alloc_young(size: u32, pointers: u32, ambiguous: u32) -> u32 {
+    loop {
+        let region = NURSERY_TOP;
+        if (*region).top + HEADER_SIZE + size <= (*region).size {
+            let header = (*region).data + (*region).top;
+            (*region).top += HEADER_SIZE + size;
+            (*at::<Header>(header)).size = size;
+            return init_object(region, header, pointers, ambiguous);
+        }
+        NURSERY_TOP = (*region).next;
+        if NURSERY_TOP.is_null() {
+            collect_minor();
+        }
+    }
+}
+
+unsafe fn push_zct(addr: u32) {
+    (*at::<Header>(addr - HEADER_SIZE)).size |= IN_ZCT;
+    ZCT[ZCT_LEN] = addr;
+    ZCT_LEN += 1;
 }
 
-unsafe fn add_new_page() {
-    let page = new_page();
-    (*HEAD).next = page;
-    HEAD = page;
+/// counts the references from the object,
+/// removing the words which don't reference objects from the maps
+unsafe fn count_fields(addr: u32) {
+    let header = at::<Header>(addr - HEADER_SIZE);
+    (*header).size |= COUNTED;
+    let (mut pointers, mut ambiguous) = ((*header).pointers, (*header).ambiguous);
+    for_each_field(addr, |field, _| {
+        let word = (field - addr) / 4;
+        match header_of(*at::<u32>(field)) {
+            Some(child) if 31 <= word => (*child).forward = STICKY,
+            Some(child) if (*child).forward != STICKY => (*child).forward += 1,
+            Some(_) => (),
+            None if word < 31 => {
+                pointers &= !(1 << word);
+                ambiguous &= !(1 << word);
+            }
+            None => (),
+        }
+    });
+    (*header).pointers = pointers;
+    (*header).ambiguous = ambiguous;
+}
+
+/// frees the object, releasing the objects it references
+unsafe fn release(addr: u32) {
+    for_each_field(addr, |field, _| {
+        let word = (field - addr) / 4;
+        let child = *at::<u32>(field);
+        let header = match header_of(child) {
+            Some(header) if word < 31 && (*header).forward != STICKY => header,
+            _ => return,
+        };
+        (*header).forward -= 1;
+        if (*header).forward == 0 && (*header).size & IN_ZCT == 0 {
+            // the table frees it later unless the stack references it
+            if (*header).size & MARK != 0 {
+                push_zct(child);
+            } else {
+                (*header).forward = DYING;
+                DYING = child;
+            }
+        }
+    });
+    let header = addr - HEADER_SIZE;
+    set_start(region_of(REGIONS, header), header, false);
+    push_free(header, size_of(at(header)));
+}
+
+/// marks or unmarks the objects referenced from the shadow stack, the exception
+/// and the callbacks
+unsafe fn mark_roots(mark: bool) {
+    let set_mark = |root| {
+        if let Some(header) = header_of(root) {
+            if mark {
+                (*header).size |= MARK;
+            } else {
+                (*header).size &= !MARK;
+            }
+        }
+    };
+    for i in 0..SP {
+        set_mark(SHADOW_STACK[i]);
+    }
+    set_mark(EXCEPTION);
+    for i in 0..CALLBACKS {
+        set_mark(CALLBACK_CLOSURES[i]);
+    }
+}
+
+/// frees the objects of the zero count table which the stack doesn't reference
+unsafe fn collect_zct() {
+    for i in 0..ZCT_LEN {
+        if (*at::<Header>(ZCT[i] - HEADER_SIZE)).size & COUNTED == 0 {
+            count_fields(ZCT[i]);
+        }
+    }
+    mark_roots(true);
+    let mut i = 0;
+    while i < ZCT_LEN {
+        let addr = ZCT[i];
+        let header = at::<Header>(addr - HEADER_SIZE);
+        if (*header).forward == 0 && (*header).size & MARK != 0 {
+            i += 1;
+            continue;
+        }
+        ZCT_LEN -= 1;
+        ZCT[i] = ZCT[ZCT_LEN];
+        (*header).size &= !IN_ZCT;
+        if (*header).forward == 0 {
+            release(addr);
+            while DYING != 0 {
+                let addr = DYING;
+                DYING = (*at::<Header>(addr - HEADER_SIZE)).forward;
+                release(addr);
+            }
+        }
+    }
+    mark_roots(false);
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn collect() {
+    match COLLECTOR {
+        GENERATIONAL => {
+            collect_nursery();
+            collect_heap();
+        }
+        REFERENCE_COUNTING => collect_zct(),
+        _ => collect_heap(),
+    }
+}
+
+/// initializes the heap. `collector` is 0 for the mark-sweep collector,
+/// 1 for the generational one and 2 for reference counting
+#[no_mangle]
+pub unsafe extern "C" fn init(collector: u32) {
+    COLLECTOR = collector;
+    add_region(new_region(WASM_PAGE_SIZE));
+    if collector == GENERATIONAL {
+        for _ in 0..NURSERY_REGIONS {
+            let region = new_nursery_region();
+            (*region).next = NURSERY;
+            NURSERY = region;
+        }
+        NURSERY_TOP = NURSERY;
+    }
 }
 
+/// allocates an object of `size` bytes.
+/// `pointers` and `ambiguous` are the maps of the words holding pointers
 #[no_mangle]
-pub unsafe extern "C" fn init() {
-    let page_ptr = new_page();
-    GC = page_ptr;
-    HEAD = GC;
+pub unsafe extern "C" fn alloc(size: u32, pointers: u32, ambiguous: u32) -> u32 {
+    let size = align(size.max(ALIGN));
+    let generational = COLLECTOR == GENERATIONAL;
+    if generational && size <= LARGE_OBJECT_SIZE {
+        return alloc_young(size, pointers, ambiguous);
+    }
+    let counting = COLLECTOR == REFERENCE_COUNTING;
+    if generational && REMEMBERED_LEN == REMEMBERED_SIZE {
+        collect_minor();
+    }
+    if counting && ZCT_LIMIT <= ZCT_LEN {
+        collect_zct();
+    }
+    let header = match pop_free(size).or_else(|| bump(size)) {
+        Some(header) => header,
+        None => {
+            // collect if the heap has been used enough since the last collection.
+            // the zero count table is cheap to collect, and may hold objects to free
+            if counting || HEAP_SIZE / 2 <= ALLOCATED {
+                collect();
+            }
+            take_block(size)
+        }
+    };
+    ALLOCATED += HEADER_SIZE + size_of(at(header));
+    let addr = init_object(region_of(REGIONS, header), header, pointers, ambiguous);
+    if generational {
+        REMEMBERED[REMEMBERED_LEN] = addr;
+        REMEMBERED_LEN += 1;
+    }
+    if counting {
+        push_zct(addr);
+    }
+    addr
 }
 
+/// pushes a frame of `slots` roots to the shadow stack
 #[no_mangle]
-pub unsafe extern "C" fn alloc(size: usize) -> *mut u8 {
-    if (*HEAD).size <= (*HEAD).top + size {
-        add_new_page();
+pub unsafe extern "C" fn enter(slots: u32) -> u32 {
+    let frame = SP;
+    SP += slots as usize;
+    if SHADOW_STACK_SIZE < SP {
+        panic!("shadow stack overflow")
     }
-    let ret = (*HEAD).data.offset((*HEAD).top as isize);
-    (*GC).top += size;
-    ret
+    for i in frame..SP {
+        SHADOW_STACK[i] = 0;
+    }
+    slot_addr(frame)
+}
+
+/// pops the frames down to `frame`
+#[no_mangle]
+pub unsafe extern "C" fn leave(frame: u32) {
+    SP = (frame - slot_addr(0)) as usize / 4;
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn memory_used() -> usize {
-    WASM_PAGE_SIZE * memory_size(MEMORY)
+    WASM_PAGE_SIZE as usize * memory_size(MEMORY)
+}
+
+// The profile of the programs compiled with `--profile`. The table has a row for each
+// function of the program, in the pages allocated outside of the heap:
+//
+//   64      64
+// +-------+--------+-----
+// | calls | cycles | ...
+// +-------+--------+-----
+//
+// The cycles are estimated by the compiler from the operations of the blocks run.
+
+static mut PROFILE: *mut u64 = 0 as *mut _;
+
+/// allocates the profile table of `functions` rows
+#[no_mangle]
+pub unsafe extern "C" fn profile_init(functions: u32) {
+    let bytes = functions * 16;
+    let pages = (bytes + WASM_PAGE_SIZE - 1) / WASM_PAGE_SIZE;
+    let ret = memory_grow(MEMORY, pages as usize);
+    if ret == usize::max_value() {
+        panic!("memory exhausted")
+    }
+    // relying wasm's page is 0 initialized
+    PROFILE = at(ret as u32 * WASM_PAGE_SIZE);
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn profile_call(function: u32) {
+    *PROFILE.add(function as usize * 2) += 1;
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn profile_cycles(function: u32, cycles: u32) {
+    *PROFILE.add(function as usize * 2 + 1) += cycles as u64;
+}
+
+/// the address of the profile table, or 0 if the program is not profiled
+#[no_mangle]
+pub unsafe extern "C" fn profile_table() -> u32 {
+    PROFILE as u32
+}
+
+// Strings are objects holding the length in bytes and the UTF-8 encoded bytes:
+//
+//   32
+// +--------+-----------
+// | length | bytes ...
+// +--------+-----------
+//
+// The helpers taking strings keep them on the shadow stack while they allocate.
+
+unsafe fn string_bytes(s: u32) -> *mut u8 {
+    at(s + 4)
+}
+
+/// allocates a string of `len` bytes for the caller to fill
+#[no_mangle]
+pub unsafe extern "C" fn string_alloc(len: u32) -> u32 {
+    let s = alloc(4 + len, 0, 0);
+    *at::<u32>(s) = len;
+    s
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn string_length(s: u32) -> u32 {
+    *at::<u32>(s)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn string_concat(a: u32, b: u32) -> u32 {
+    let frame = enter(2);
+    SHADOW_STACK[SP - 2] = a;
+    SHADOW_STACK[SP - 1] = b;
+    let (a_len, b_len) = (string_length(a), string_length(b));
+    let s = string_alloc(a_len + b_len);
+    ptr::copy_nonoverlapping(string_bytes(a), string_bytes(s), a_len as usize);
+    ptr::copy_nonoverlapping(
+        string_bytes(b),
+        string_bytes(s).add(a_len as usize),
+        b_len as usize,
+    );
+    leave(frame);
+    s
+}
+
+/// compares the strings by their bytes. returns -1, 0 or 1 if `a` is less than, equal to
+/// or greater than `b`
+#[no_mangle]
+pub unsafe extern "C" fn string_compare(a: u32, b: u32) -> i32 {
+    let (a_len, b_len) = (string_length(a), string_length(b));
+    for i in 0..a_len.min(b_len) as usize {
+        let (x, y) = (*string_bytes(a).add(i), *string_bytes(b).add(i));
+        if x != y {
+            return if x < y { -1 } else { 1 };
+        }
+    }
+    if a_len < b_len {
+        -1
+    } else if a_len > b_len {
+        1
+    } else {
+        0
+    }
+}
+
+/// hashes the bytes of the string with 32-bit FNV-1a
+#[no_mangle]
+pub unsafe extern "C" fn string_hash(s: u32) -> u32 {
+    (0..string_length(s) as usize).fold(0x811c_9dc5, |hash: u32, i| {
+        (hash ^ *string_bytes(s).add(i) as u32).wrapping_mul(0x0100_0193)
+    })
+}
+
+/// hashes the bits of the real. `0.0` and `-0.0` are equal and have the same hash
+#[no_mangle]
+pub extern "C" fn real_hash(x: f64) -> u32 {
+    let bits = if x == 0.0 { 0 } else { x.to_bits() };
+    (bits >> 32) as u32 ^ bits as u32
+}
+
+/// the `len` bytes of `s` from `start`
+#[no_mangle]
+pub unsafe extern "C" fn string_sub(s: u32, start: u32, len: u32) -> u32 {
+    if string_length(s) < start || string_length(s) - start < len {
+        panic!("substring out of range")
+    }
+    let frame = enter(1);
+    SHADOW_STACK[SP - 1] = s;
+    let sub = string_alloc(len);
+    ptr::copy_nonoverlapping(
+        string_bytes(s).add(start as usize),
+        string_bytes(sub),
+        len as usize,
+    );
+    leave(frame);
+    sub
+}
+
+/// the byte of `s` at `i`, which the caller checked to be in range
+#[no_mangle]
+pub unsafe extern "C" fn string_byte(s: u32, i: u32) -> u32 {
+    *string_bytes(s).add(i as usize) as u32
+}
+
+/// the string of the character, encoded in UTF-8
+#[no_mangle]
+pub unsafe extern "C" fn char_to_string(c: u32) -> u32 {
+    let mut bytes = [0; 4];
+    let c = core::char::from_u32(c).unwrap_or(core::char::REPLACEMENT_CHARACTER);
+    string_from(c.encode_utf8(&mut bytes).as_bytes())
+}
+
+/// copies the bytes to a new string
+unsafe fn string_from(bytes: &[u8]) -> u32 {
+    let s = string_alloc(bytes.len() as u32);
+    ptr::copy_nonoverlapping(bytes.as_ptr(), string_bytes(s), bytes.len());
+    s
+}
+
+/// the buffer the numbers are formatted in
+struct Buffer {
+    bytes: [u8; 32],
+    len: usize,
+}
+
+impl Buffer {
+    fn new() -> Self {
+        Buffer {
+            bytes: [0; 32],
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, byte: u8) {
+        self.bytes[self.len] = byte;
+        self.len += 1;
+    }
+
+    fn extend(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.push(byte);
+        }
+    }
+
+    /// the decimal digits of `n`, with `~` for the minus sign
+    fn push_int(&mut self, n: i32) {
+        if n < 0 {
+            self.push(b'~');
+        }
+        let mut digits = [0; 10];
+        let (mut n, mut len) = (n.unsigned_abs(), 0);
+        loop {
+            digits[len] = b'0' + (n % 10) as u8;
+            len += 1;
+            n /= 10;
+            if n == 0 {
+                break;
+            }
+        }
+        for i in (0..len).rev() {
+            self.push(digits[i]);
+        }
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.len]
+    }
+}
+
+impl fmt::Write for Buffer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if self.bytes.len() - self.len < s.len() {
+            return Err(fmt::Error);
+        }
+        self.extend(s.as_bytes());
+        Ok(())
+    }
+}
+
+/// `Int.toString`
+#[no_mangle]
+pub unsafe extern "C" fn int_to_string(n: i32) -> u32 {
+    let mut buffer = Buffer::new();
+    buffer.push_int(n);
+    string_from(buffer.as_bytes())
+}
+
+/// `Word.toString`, the hexadecimal digits in upper case
+#[no_mangle]
+pub unsafe extern "C" fn word_to_string(w: u32) -> u32 {
+    let mut buffer = Buffer::new();
+    let _ = write!(buffer, "{:X}", w);
+    string_from(buffer.as_bytes())
+}
+
+/// `Real.toString`. the 12 significant digits are shown in the fixed notation if the exponent
+/// is from -4 to 11 as `%.12g` does, and in the scientific notation otherwise
+#[no_mangle]
+pub unsafe extern "C" fn real_to_string(x: f64) -> u32 {
+    let mut out = Buffer::new();
+    if x.is_nan() {
+        return string_from(b"nan");
+    }
+    let x = if x.is_sign_negative() {
+        out.push(b'~');
+        -x
+    } else {
+        x
+    };
+    if x.is_infinite() {
+        out.extend(b"inf");
+        return string_from(out.as_bytes());
+    }
+    // the digits are rounded by the formatter of core, as `d.ddddddddddde[-]n`
+    let mut sci = Buffer::new();
+    let _ = write!(sci, "{:.11e}", x);
+    let sci = sci.as_bytes();
+    let e = sci.iter().position(|&b| b == b'e').unwrap();
+    let mut digits = Buffer::new();
+    for &b in &sci[..e] {
+        if b != b'.' {
+            digits.push(b);
+        }
+    }
+    while digits.len > 1 && digits.bytes[digits.len - 1] == b'0' {
+        digits.len -= 1;
+    }
+    let digits = digits.as_bytes();
+    let mut exp = 0;
+    for &b in &sci[e + 1..] {
+        if b != b'-' {
+            exp = exp * 10 + (b - b'0') as i32;
+        }
+    }
+    if sci[e + 1] == b'-' {
+        exp = -exp;
+    }
+
+    if exp < -4 || exp >= 12 {
+        out.push(digits[0]);
+        if digits.len() > 1 {
+            out.push(b'.');
+            out.extend(&digits[1..]);
+        }
+        out.push(b'E');
+        out.push_int(exp);
+    } else if exp < 0 {
+        out.extend(b"0.");
+        for _ in 0..-exp - 1 {
+            out.push(b'0');
+        }
+        out.extend(digits);
+    } else {
+        let int_len = exp as usize + 1;
+        for i in 0..int_len {
+            out.push(digits.get(i).copied().unwrap_or(b'0'));
+        }
+        out.push(b'.');
+        if digits.len() > int_len {
+            out.extend(&digits[int_len..]);
+        } else {
+            out.push(b'0');
+        }
+    }
+    string_from(out.as_bytes())
+}
+
+// the output of `TextIO` not flushed yet. the compiler writes it through one `printString`
+// when the program exits, and the interpreter keeps a buffer of the same size
+const OUTPUT_SIZE: usize = 4096;
+static mut OUTPUT: [u8; OUTPUT_SIZE] = [0; OUTPUT_SIZE];
+static mut OUTPUT_LEN: usize = 0;
+
+/// appends `s` to the output buffer, or returns 1 without appending if it does not fit
+#[no_mangle]
+pub unsafe extern "C" fn output_append(s: u32) -> i32 {
+    let len = string_length(s) as usize;
+    if OUTPUT_SIZE - OUTPUT_LEN < len {
+        return 1;
+    }
+    ptr::copy_nonoverlapping(string_bytes(s), OUTPUT.as_mut_ptr().add(OUTPUT_LEN), len);
+    OUTPUT_LEN += len;
+    0
+}
+
+/// the string of the buffered output, emptying the buffer
+#[no_mangle]
+pub unsafe extern "C" fn output_take(_: i32) -> u32 {
+    let s = string_from(&OUTPUT[..OUTPUT_LEN]);
+    OUTPUT_LEN = 0;
+    s
+}
+
+// the states of the xorshift64* generators of `Random`, which the programs refer to by the index.
+// the generators are never freed
+const GENERATORS: usize = 1024;
+static mut GENERATOR_STATES: [u64; GENERATORS] = [0; GENERATORS];
+static mut GENERATORS_LEN: usize = 0;
+
+/// a new generator seeded with the two ints, returning its index
+#[no_mangle]
+pub unsafe extern "C" fn rand_new(m: i32, n: i32) -> u32 {
+    if GENERATORS_LEN == GENERATORS {
+        panic!("too many random generators")
+    }
+    let seed = (m as u32 as u64) << 32 | n as u32 as u64;
+    // the state must not be 0
+    GENERATOR_STATES[GENERATORS_LEN] = if seed == 0 {
+        0x9e37_79b9_7f4a_7c15
+    } else {
+        seed
+    };
+    GENERATORS_LEN += 1;
+    (GENERATORS_LEN - 1) as u32
+}
+
+/// advances the generator and returns the 64 bits of the output
+unsafe fn rand_next(g: u32) -> u64 {
+    let mut x = GENERATOR_STATES[g as usize];
+    x ^= x >> 12;
+    x ^= x << 25;
+    x ^= x >> 27;
+    GENERATOR_STATES[g as usize] = x;
+    x.wrapping_mul(0x2545_f491_4f6c_dd1d)
+}
+
+/// `Random.randInt`, the higher 32 bits of the output
+#[no_mangle]
+pub unsafe extern "C" fn rand_int(g: u32) -> i32 {
+    (rand_next(g) >> 32) as i32
+}
+
+/// `Random.randReal`, from 0 to 1 exclusive with the higher 53 bits of the output
+#[no_mangle]
+pub unsafe extern "C" fn rand_real(g: u32) -> f64 {
+    (rand_next(g) >> 11) as f64 / (1u64 << 53) as f64
+}
+
+// the closures passed to the host as callbacks, which the host refers to by the slot.
+// they are roots until the host releases the slot, and the collector updates them
+// when it moves the closures. a free slot is null
+const CALLBACKS: usize = 1024;
+static mut CALLBACK_CLOSURES: [u32; CALLBACKS] = [0; CALLBACKS];
+
+/// keeps the closure alive for the host, returning its slot
+#[no_mangle]
+pub unsafe extern "C" fn callback_register(closure: u32) -> u32 {
+    match CALLBACK_CLOSURES.iter().position(|&c| c == 0) {
+        Some(slot) => {
+            CALLBACK_CLOSURES[slot] = closure;
+            slot as u32
+        }
+        None => panic!("too many callbacks"),
+    }
+}
+
+/// the current address of the closure in the slot
+#[no_mangle]
+pub unsafe extern "C" fn callback_closure(slot: u32) -> u32 {
+    CALLBACK_CLOSURES[slot as usize]
+}
+
+/// frees the slot, letting the closure be collected
+#[no_mangle]
+pub unsafe extern "C" fn callback_release(slot: u32) {
+    CALLBACK_CLOSURES[slot as usize] = 0;
+}
+
+// the exception being raised, or null.
+// the compiled code checks `raised` after calls and returns early while it is set.
+static mut EXCEPTION: u32 = 0;
+
+// the site of the error in the `webml.errors` section of the program, counted from 1, or 0.
+// the compiled code records the site before it raises or traps, and the host reads it
+// to tell where an uncaught exception was raised or the program trapped.
+static mut ERROR_SITE: u32 = 0;
+// the exception handled last and the site it was raised at, so that raising it again in
+// the handler keeps the site
+static mut HANDLED: u32 = 0;
+static mut HANDLED_SITE: u32 = 0;
+
+#[no_mangle]
+pub unsafe extern "C" fn error_at(site: u32) {
+    ERROR_SITE = site;
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn error_site() -> u32 {
+    ERROR_SITE
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn raise(exn: u32) {
+    if exn == HANDLED {
+        ERROR_SITE = HANDLED_SITE;
+    }
+    EXCEPTION = exn;
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn raised() -> i32 {
+    (EXCEPTION != 0) as i32
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn take_exception() -> u32 {
+    let exn = EXCEPTION;
+    EXCEPTION = 0;
+    HANDLED = exn;
+    HANDLED_SITE = ERROR_SITE;
+    ERROR_SITE = 0;
+    exn
 }
 
 #[panic_handler]